@@ -1,7 +1,8 @@
 use lib::*;
 
 use clap::{Parser, Subcommand};
-use model::{read_dataset, TrainParams};
+use model::{dataset::Augmentation, read_dataset, ClassWeights, OutputHead, TrainParams};
+use quant::QuantConfig;
 use std::{
   error::Error,
   path::{Path, PathBuf},
@@ -11,6 +12,9 @@ use std::{
 struct Cli {
   #[command(subcommand)]
   command: Command,
+  /// compact or json. Filter the levels with RUST_LOG
+  #[arg(long, global = true, default_value = "compact")]
+  log_format: utils::LogFormat,
 }
 
 #[derive(Subcommand)]
@@ -36,13 +40,120 @@ enum Command {
     data: PathBuf,
     #[arg(short, long, value_name = "INT", default_value_t = 20)]
     epochs: usize,
+    /// Cross-validate on this many stratified folds and report the accuracy of every one
+    #[arg(long, value_name = "INT")]
+    folds: Option<usize>,
+    /// Train on a copy of every sample with Gaussian noise of this standard deviation too (with --folds)
+    #[arg(long, value_name = "FLOAT")]
+    noise: Option<f32>,
+  },
+  /// Train the model on a dataset and save the weights
+  Train {
+    #[arg(short, long, value_name = "PATH")]
+    data: PathBuf,
+    #[arg(short, long, value_name = "INT", default_value_t = 20)]
+    epochs: usize,
+    /// Where to save the trained model
+    #[arg(short, long, value_name = "PATH")]
+    output: PathBuf,
+    /// Output the thresholded decision (threshold calibrated on the validation data) instead of the score
+    #[arg(long)]
+    decision: bool,
+    /// Seed of the weight initialization and the order of the samples
+    #[arg(long, value_name = "INT", default_value_t = 0)]
+    seed: u64,
+    /// Train on the weights and inputs rounded to this many fractional bits, as the circuit computes
+    #[arg(long, value_name = "INT")]
+    quantize_bits: Option<u32>,
+    /// Weigh the loss of every sample inversely to how common its class is
+    #[arg(long)]
+    balance_classes: bool,
+  },
+  /// Scalarize a trained model and dump the scalar graph (graphviz, or the JSON IR to a .json output)
+  Scalarize {
+    #[arg(short, long, value_name = "PATH")]
+    model: PathBuf,
+    #[arg(short, long, value_name = "PATH")]
+    output: PathBuf,
+    /// Group the little nodes by the tensor they come from
+    #[arg(long)]
+    cluster: bool,
+    /// Render only the neighborhood of this scalar node
+    #[arg(long, value_name = "NODE")]
+    around: Option<usize>,
+    /// Radius of the rendered neighborhood
+    #[arg(long, value_name = "INT", default_value_t = 3)]
+    radius: usize,
+    /// Bound on the magnitude of the input, for the reported value ranges
+    #[arg(long, value_name = "FLOAT", default_value_t = 1.0)]
+    input_bound: f64,
+  },
+  /// Make the keys of a trained model's circuit, run by the verifier: publish the verifier, hand the proving key out
+  Setup {
+    #[arg(short, long, value_name = "PATH")]
+    model: PathBuf,
+    /// Directory to save the verifier to, the verifying key and the public inputs schema
+    #[arg(short, long, value_name = "PATH")]
+    verifier: PathBuf,
+    /// Where to save the proving key
+    #[arg(short, long, value_name = "PATH")]
+    proving_key: PathBuf,
+  },
+  /// Prove the evaluation of a trained model on a private input
+  Prove {
+    #[arg(short, long, value_name = "PATH")]
+    model: PathBuf,
+    /// File with the input, whitespace separated floats
+    #[arg(short, long, value_name = "PATH")]
+    input: PathBuf,
+    /// The proving key made by setup for the model
+    #[arg(short, long, value_name = "PATH")]
+    proving_key: PathBuf,
+    /// Where to save the proof
+    #[arg(short, long, value_name = "PATH")]
+    output: PathBuf,
+  },
+  /// Verify a proof of the evaluation of a trained model
+  Verify {
+    /// Directory with the verifier saved by setup
+    #[arg(short, long, value_name = "PATH")]
+    verifier: PathBuf,
+    #[arg(short, long, value_name = "PATH")]
+    proof: PathBuf,
+  },
+  /// Evaluate a trained model on an input
+  Eval {
+    #[arg(short, long, value_name = "PATH")]
+    model: PathBuf,
+    #[arg(short, long, value_name = "PATH")]
+    input: PathBuf,
+  },
+  /// Prove evaluations of a trained model for clients over HTTP
+  #[cfg(feature = "service")]
+  Serve {
+    #[arg(short, long, value_name = "PATH")]
+    model: PathBuf,
+    #[arg(short, long, default_value_t = 4545)]
+    port: u16,
+    /// Proofs made at once
+    #[arg(long, value_name = "INT", default_value_t = 1)]
+    workers: usize,
+    /// Jobs waiting for a worker at most, more are refused
+    #[arg(long, value_name = "INT", default_value_t = 64)]
+    max_queued: usize,
+    /// Seconds a finished job is kept for its client, unless its bundle is fetched sooner
+    #[arg(long, value_name = "SECS", default_value_t = 600)]
+    retain_secs: u64,
+    /// Finished jobs kept at most, the oldest are dropped first
+    #[arg(long, value_name = "INT", default_value_t = 1024)]
+    max_retained: usize,
   },
 }
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn Error>> {
-  utils::init_logging()?;
   let args = Cli::parse();
+  utils::init_logging_with(args.log_format)?;
 
   match args.command {
     Command::Client {
@@ -58,9 +169,115 @@ async fn main() -> Result<(), Box<dyn Error>> {
       let app = subcommands::Server::new(port);
       app.run().await;
     }
-    Command::Model { data, epochs } => {
+    Command::Model {
+      data,
+      epochs,
+      folds,
+      noise,
+    } => {
       let ds = read_dataset(Path::new(&data)).unwrap();
-      lib::model::run_model(TrainParams { data: ds, epochs });
+      let train = |data| {
+        lib::model::run_model(TrainParams {
+          data,
+          epochs,
+          ..Default::default()
+        })
+      };
+      match folds {
+        Some(k) => {
+          let augmentation = noise.map(|sigma| Augmentation { copies: 1, sigma });
+          println!(
+            "{}",
+            accuracy::cross_validate(&ds, k, true, augmentation, 0, train)
+          );
+        }
+        None => {
+          train(ds);
+        }
+      }
+    }
+    Command::Train {
+      data,
+      epochs,
+      output,
+      decision,
+      seed,
+      quantize_bits,
+      balance_classes,
+    } => {
+      let head = if decision {
+        OutputHead::Decision
+      } else {
+        OutputHead::Score
+      };
+      let quantization = quantize_bits.map(|scale_bits| QuantConfig {
+        scale_bits,
+        ..Default::default()
+      });
+      let class_weights = balance_classes.then_some(ClassWeights::Balanced);
+      subcommands::Train::new(
+        &data,
+        &output,
+        epochs,
+        head,
+        seed,
+        quantization,
+        class_weights,
+      )
+      .run();
+    }
+    Command::Scalarize {
+      model,
+      output,
+      cluster,
+      around,
+      radius,
+      input_bound,
+    } => {
+      let neighborhood = around.map(|x| (x, radius));
+      subcommands::Scalarize::new(&model, &output, cluster, neighborhood, input_bound).run();
+    }
+    Command::Setup {
+      model,
+      verifier,
+      proving_key,
+    } => {
+      subcommands::Setup::new(&model, &verifier, &proving_key).run();
+    }
+    Command::Prove {
+      model,
+      input,
+      proving_key,
+      output,
+    } => {
+      subcommands::Prove::new(&model, &input, &proving_key, &output).run();
+    }
+    Command::Verify { verifier, proof } => {
+      if !subcommands::Verify::new(&verifier, &proof).run() {
+        return Err("Proof verification failed".into());
+      }
+    }
+    Command::Eval { model, input } => {
+      subcommands::Eval::new(&model, &input).run();
+    }
+    #[cfg(feature = "service")]
+    Command::Serve {
+      model,
+      port,
+      workers,
+      max_queued,
+      retain_secs,
+      max_retained,
+    } => {
+      let saved = model::SavedModel::load(&model)?;
+      let config = snark::pool::PoolConfig {
+        workers,
+        max_queued,
+        retain: std::time::Duration::from_secs(retain_secs),
+        max_retained,
+      };
+      let pool = snark::pool::ProverPool::start(move || model::load_model(saved.clone()), config)?;
+      service::serve(pool, port).await?;
     }
   }
   Ok(())
@@ -0,0 +1,119 @@
+//!
+//! Benchmarks of the pipeline on dense nets of growing size, see [lib::scalar::testing::mlp_expr].
+//! Run with `cargo bench`, or `cargo bench -- scalarize` for one group.
+//!
+
+use std::collections::HashMap;
+
+use ark_relations::r1cs::{ConstraintSynthesizer, ConstraintSystem};
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use lib::{
+  export::circom,
+  quant::QuantConfig,
+  scalar::{
+    random_inputs, scalar,
+    testing::{build_graph, mlp_expr},
+  },
+  snark::{CircuitField, MLSnark, SourceType},
+  SCALE,
+};
+use luminal::graph::Graph;
+use rand::{rngs::StdRng, SeedableRng};
+
+/// (name, layer widths)
+const NETS: &[(&str, &[usize])] = &[
+  ("narrow-shallow", &[8, 8, 1]),
+  ("wide-shallow", &[32, 32, 1]),
+  ("narrow-deep", &[8, 8, 8, 8, 8, 1]),
+  ("wide-deep", &[32, 32, 32, 32, 1]),
+];
+
+fn nets() -> impl Iterator<
+  Item = (
+    &'static str,
+    Graph,
+    HashMap<luminal::prelude::NodeIndex, Vec<f32>>,
+  ),
+> {
+  NETS.iter().map(|(name, widths)| {
+    let (cx, _) = build_graph(&mlp_expr(widths));
+    let inputs = random_inputs(&cx, &mut StdRng::seed_from_u64(0));
+    (*name, cx, inputs)
+  })
+}
+
+fn bench_scalarize(c: &mut Criterion) {
+  let mut group = c.benchmark_group("scalarize");
+  for (name, cx, _) in nets() {
+    group.bench_with_input(BenchmarkId::from_parameter(name), &cx, |b, cx| {
+      b.iter(|| scalar(cx))
+    });
+  }
+  group.finish();
+}
+
+fn bench_evaluate(c: &mut Criterion) {
+  let mut group = c.benchmark_group("evaluate");
+  for (name, cx, inputs) in nets() {
+    let (sc, _) = scalar(&cx);
+    group.bench_with_input(BenchmarkId::from_parameter(name), &inputs, |b, inputs| {
+      b.iter(|| sc.evaluate(inputs))
+    });
+  }
+  group.finish();
+}
+
+fn bench_quantize(c: &mut Criterion) {
+  let mut group = c.benchmark_group("quantize");
+  let quant = QuantConfig::default();
+  for (name, cx, _) in nets() {
+    let (sc, _) = scalar(&cx);
+    group.bench_function(BenchmarkId::from_parameter(name), |b| {
+      b.iter(|| circom::render(&sc, &quant).unwrap())
+    });
+  }
+  group.finish();
+}
+
+fn bench_synthesize(c: &mut Criterion) {
+  let mut group = c.benchmark_group("synthesize");
+  group.sample_size(10);
+  for (name, cx, inputs) in nets() {
+    let (sc, _) = scalar(&cx);
+    let source_map = sc
+      .inputs_tracker
+      .assign_all(&inputs)
+      .unwrap()
+      .into_iter()
+      .map(|(x, v)| (x, SourceType::Private(Some(v))))
+      .collect();
+    let input_id = *inputs.keys().next().unwrap();
+    let mut snark = MLSnark {
+      graph: sc.freeze_ops(),
+      scale: SCALE,
+      source_map,
+      og_input_id: input_id,
+      input_hash: false,
+      commitments: vec![],
+      recorded_public_inputs: vec![],
+      recorded_public_nodes: vec![],
+      trace: Default::default(),
+    };
+    group.bench_function(BenchmarkId::from_parameter(name), |b| {
+      b.iter(|| {
+        let cs = ConstraintSystem::<CircuitField>::new_ref();
+        (&mut snark).generate_constraints(cs).unwrap()
+      })
+    });
+  }
+  group.finish();
+}
+
+criterion_group!(
+  benches,
+  bench_scalarize,
+  bench_evaluate,
+  bench_quantize,
+  bench_synthesize
+);
+criterion_main!(benches);
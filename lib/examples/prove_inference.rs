@@ -0,0 +1,126 @@
+//!
+//! Proves one inference of the medium model on the bundled dataset, end to end and through the public API only:
+//! train (or load a saved model), scalarize, quantize, prove with the default backend, verify.
+//! Prints the sizes and timings of every step.
+//!
+//! cargo run --release --example prove_inference [saved model, see `zkml train`]
+//!
+
+use std::{collections::HashMap, path::Path, time::Instant};
+
+use ark_serialize::CanonicalSerialize;
+use lib::{
+  compile,
+  model::{load_model, parse_dataset, run_model, SavedModel, TrainParams},
+  quant::{calibrate, NodeScales, QuantConfig, QuantizedGraph},
+  scalar::scalar,
+  snark::{
+    backend::{Groth16Backend, ProvingBackend, VerifyingBackend},
+    scaling_helpers::unscaled_f,
+  },
+  SCALE,
+};
+use luminal::prelude::NodeIndex;
+
+fn main() {
+  let data = parse_dataset(include_str!("../../data/rp.data").to_string());
+
+  let start = Instant::now();
+  let mut trained = match std::env::args().nth(1) {
+    Some(path) => load_model(
+      SavedModel::load(Path::new(&path))
+        .unwrap_or_else(|e| panic!("Failed to load the model: {}", e)),
+    ),
+    None => run_model(TrainParams {
+      data: data.clone(),
+      ..Default::default()
+    }),
+  };
+  println!("model ready in {:?}", start.elapsed());
+
+  let samples: Vec<Vec<f32>> = data
+    .0
+    .iter()
+    .take(32)
+    .map(|x| match &trained.scaler {
+      Some(scaler) => scaler.transform_row(x),
+      None => x.to_vec(),
+    })
+    .collect();
+  let input = samples[0].clone();
+  let output = trained.graph.outputs[0];
+
+  let start = Instant::now();
+  let (sc, _) = scalar(&trained.graph.graph);
+  println!(
+    "scalarized in {:?}: {} nodes, {} edges",
+    start.elapsed(),
+    sc.graph.node_count(),
+    sc.graph.edge_count()
+  );
+
+  let start = Instant::now();
+  let quant = QuantConfig::default();
+  let tensor_scales = calibrate(&trained, &samples).tensor_scales(&quant, 4);
+  let scales = NodeScales::from_tensor_scales(&sc, &tensor_scales);
+  let quantized = QuantizedGraph::new(sc, quant, scales);
+  let mut inputs: HashMap<NodeIndex, Vec<f32>> = trained.graph.weights.iter().cloned().collect();
+  inputs.insert(trained.graph.input_id, input.clone());
+  let overflows = quantized.overflow_report(&inputs);
+  let int_result = quantized.dequantize_outputs(&quantized.evaluate_int(&inputs))[&output][0];
+  println!(
+    "quantized in {:?}: {}, result {}",
+    start.elapsed(),
+    if overflows.is_empty() {
+      "fits the value range".to_string()
+    } else {
+      format!("out of the value range:\n{}", overflows)
+    },
+    int_result
+  );
+
+  let backend = Groth16Backend;
+  let mut snark = compile(&trained);
+  let constraints = backend
+    .estimate_constraints(&mut compile(&trained))
+    .unwrap_or_else(|e| panic!("Failed to synthesize the circuit: {:?}", e));
+  println!("circuit: {} constraints", constraints);
+
+  let start = Instant::now();
+  let (pk, vk) = backend
+    .setup(&mut snark)
+    .unwrap_or_else(|e| panic!("Failed to make keys: {:?}", e));
+  println!(
+    "setup in {:?}: proving key {} bytes, verifying key {} bytes",
+    start.elapsed(),
+    pk.serialized_size(),
+    vk.serialized_size()
+  );
+
+  let start = Instant::now();
+  snark.set_input(input.clone());
+  let proof = backend
+    .prove(&mut snark, &pk)
+    .unwrap_or_else(|e| panic!("Failed to make proof: {:?}", e));
+  let public_inputs = snark.recorded_public_inputs.clone();
+  println!(
+    "proved in {:?}: proof {} bytes, {} public inputs",
+    start.elapsed(),
+    proof.serialized_size(),
+    public_inputs.len()
+  );
+
+  let start = Instant::now();
+  let verified = backend
+    .verify(&vk, &public_inputs, &proof)
+    .unwrap_or_else(|e| panic!("Failed to verify: {:?}", e));
+  println!("verified in {:?}: {}", start.elapsed(), verified);
+  assert!(verified, "The proof doesn't verify");
+
+  let float_result = trained.evaluate(input)[&output][0];
+  let proven_result = unscaled_f(snark.get_evaluation_results()[&output][0], &SCALE);
+  println!(
+    "result: {} (f32 model), {} (quantized graph), {:?} (proven)",
+    float_result, int_result, proven_result
+  );
+}
@@ -0,0 +1,467 @@
+//!
+//! How much accuracy the model loses on the way to the circuit, for choosing the quantization parameters.
+//!
+//! The test split of the dataset is run through every path:
+//!  - the f32 model ([TrainedGraph::evaluate]), the reference
+//!  - the integer evaluation of the quantized graph ([QuantizedGraph::evaluate_int]), what the exported circuits compute
+//!  - optionally the witness of a proving backend ([ProvingBackend::witness_outputs]), what the proof attests to
+//!
+//! The model is a binary classifier: the first element of the first output above 0.5 predicts the label 1.
+//! That works for both heads, see [crate::model::OutputHead].
+//!
+//! The accuracy itself is proven by the circuit of [compile_accuracy]: the model run on many samples at once, with the
+//! number of them classified correctly as the result (see [crate::scalar::batch]).
+//!
+//! The accuracy on one test split depends on which samples landed in it. [cross_validate] trains the model on every
+//! fold of a k-fold split (see [crate::model::dataset]) and reports the accuracy of the f32 model on every held-out
+//! fold.
+//!
+//! [assert_pipeline_consistent] is the same comparison as a check, on every output and with the scalar graph too: it
+//! panics as soon as two of the paths disagree.
+//!
+
+use std::{collections::HashMap, fmt};
+
+use luminal::prelude::NodeIndex;
+use rand::{rngs::StdRng, SeedableRng};
+
+use crate::{
+  compile,
+  model::{
+    dataset::{augment, split, Augmentation, Split},
+    split_dataset, InputsVec, OutputsVec, TrainedGraph,
+  },
+  quant::{NodeScales, QuantConfig, QuantizedGraph},
+  scalar::{accuracy_circuit, scalar, Prediction},
+  snark::{
+    backend::{Groth16Backend, ProvingBackend},
+    scaling_helpers::unscaled_f,
+    CircuitField, MLSnark, SourceType,
+  },
+  SCALE,
+};
+
+/// Statistics of one evaluation path over the test split.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct PathStats {
+  /// Fraction of the samples classified correctly.
+  pub accuracy: f32,
+  /// Fraction of the samples classified as by the f32 model.
+  pub agreement: f32,
+  /// Largest difference of the output from the f32 model, over all samples.
+  pub max_abs_error: f32,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct AccuracyReport {
+  pub samples: usize,
+  pub float: PathStats,
+  pub quantized: PathStats,
+  /// Not computed without a backend, see [report_accuracy_with].
+  pub circuit: Option<PathStats>,
+}
+
+/// Collects the statistics of a path, from its output (first element of the first output) on every sample.
+pub(crate) fn path_stats(outputs: &[f32], reference: &[f32], labels: &[f32]) -> PathStats {
+  let n = labels.len().max(1) as f32;
+  let predict = |v: f32| v > 0.5;
+  let count = |f: &dyn Fn(usize) -> bool| (0..labels.len()).filter(|i| f(*i)).count() as f32 / n;
+  PathStats {
+    accuracy: count(&|i| predict(outputs[i]) == predict(labels[i])),
+    agreement: count(&|i| predict(outputs[i]) == predict(reference[i])),
+    max_abs_error: outputs
+      .iter()
+      .zip(reference)
+      .map(|(a, b)| (a - b).abs())
+      .fold(0.0, f32::max),
+  }
+}
+
+/// Accuracy of the f32 model and of the quantized graph on the test split, see the module docs.
+/// The dataset is split as for training.
+pub fn report_accuracy(
+  trained: &TrainedGraph,
+  dataset: &(InputsVec, OutputsVec),
+  quant: &QuantConfig,
+) -> AccuracyReport {
+  report(trained, dataset, quant, None::<fn(&[f32]) -> f32>)
+}
+
+/// [report_accuracy] together with the results in the witness of the backend.
+/// Panics if the backend can't compute the witness of a sample.
+pub fn report_accuracy_with<B: ProvingBackend>(
+  trained: &TrainedGraph,
+  dataset: &(InputsVec, OutputsVec),
+  quant: &QuantConfig,
+  backend: &B,
+) -> AccuracyReport {
+  let mut snark = compile(trained);
+  let output = trained.graph.outputs[0];
+  let in_circuit = move |x: &[f32]| {
+    snark.set_input(x.to_vec());
+    let results = backend
+      .witness_outputs(&mut snark)
+      .unwrap_or_else(|e| panic!("Failed to compute the witness: {:?}", e));
+    unscaled_f(results[&output][0], &SCALE).unwrap_or(f32::NAN)
+  };
+  report(trained, dataset, quant, Some(in_circuit))
+}
+
+/// The sample with the scaler of the model applied, if it has one.
+fn scaled(trained: &TrainedGraph, x: &[f32]) -> Vec<f32> {
+  match &trained.scaler {
+    Some(scaler) => scaler.transform_row(x),
+    None => x.to_vec(),
+  }
+}
+
+fn report(
+  trained: &TrainedGraph,
+  dataset: &(InputsVec, OutputsVec),
+  quant: &QuantConfig,
+  mut in_circuit: Option<impl FnMut(&[f32]) -> f32>,
+) -> AccuracyReport {
+  let (x, y) = dataset.clone();
+  let (_, x_test, _, y_test) = split_dataset(x, y, 0.8);
+  let x_test: Vec<Vec<f32>> = x_test.iter().map(|x| scaled(trained, x)).collect();
+
+  let output = trained.graph.outputs[0];
+  let quantized = QuantizedGraph::new(
+    scalar(&trained.graph.graph).0,
+    *quant,
+    NodeScales::default(),
+  );
+  let weights: HashMap<NodeIndex, Vec<f32>> = trained.graph.weights.iter().cloned().collect();
+
+  let (mut float, mut int, mut circuit) = (vec![], vec![], vec![]);
+  for x in x_test.iter() {
+    float.push(trained.evaluate(x.clone())[&output][0]);
+    let mut inputs = weights.clone();
+    inputs.insert(trained.graph.input_id, x.clone());
+    let outputs = quantized.evaluate_int(&inputs);
+    int.push(quantized.dequantize_outputs(&outputs)[&output][0]);
+    if let Some(f) = in_circuit.as_mut() {
+      circuit.push(f(x));
+    }
+  }
+
+  AccuracyReport {
+    samples: y_test.len(),
+    float: path_stats(&float, &float, &y_test),
+    quantized: path_stats(&int, &float, &y_test),
+    circuit: in_circuit.map(|_| path_stats(&circuit, &float, &y_test)),
+  }
+}
+
+/// Runs the samples through the tensor graph ([TrainedGraph::evaluate]), the scalar graph on floats, the quantized
+/// graph at the default [QuantConfig] and the witness of [Groth16Backend], and panics unless every two of them agree
+/// within `tol` on every element of every output. The samples are as in the dataset, the scaler of the model is
+/// applied. A smoke check of the whole pipeline, also for the crates using this one.
+pub fn assert_pipeline_consistent(trained: &TrainedGraph, samples: &[Vec<f32>], tol: f32) {
+  assert_pipeline_consistent_with(
+    trained,
+    samples,
+    tol,
+    &QuantConfig::default(),
+    &Groth16Backend,
+  )
+}
+
+/// [assert_pipeline_consistent] at the quantization and with the backend given.
+pub fn assert_pipeline_consistent_with<B: ProvingBackend>(
+  trained: &TrainedGraph,
+  samples: &[Vec<f32>],
+  tol: f32,
+  quant: &QuantConfig,
+  backend: &B,
+) {
+  let graph = &trained.graph;
+  let (sc, _) = scalar(&graph.graph);
+  let frozen = sc.freeze_ops();
+  let quantized = QuantizedGraph::new(sc, *quant, NodeScales::default());
+  let mut snark = compile(trained);
+  let weights: HashMap<NodeIndex, Vec<f32>> = graph.weights.iter().cloned().collect();
+
+  for (i, sample) in samples.iter().enumerate() {
+    let x = scaled(trained, sample);
+    let mut inputs = weights.clone();
+    inputs.insert(graph.input_id, x.clone());
+    snark.set_input(x.clone());
+    let witness = backend
+      .witness_outputs(&mut snark)
+      .unwrap_or_else(|e| panic!("Failed to compute the witness of sample {}: {:?}", i, e));
+    let paths: [(&str, HashMap<NodeIndex, Vec<f32>>); 4] = [
+      ("tensor", trained.evaluate(x)),
+      ("scalar", frozen.evaluate_outputs(&inputs)),
+      (
+        "quantized",
+        quantized.dequantize_outputs(&quantized.evaluate_int(&inputs)),
+      ),
+      (
+        "witness",
+        witness
+          .into_iter()
+          .map(|(id, v)| {
+            let v = v.iter().map(|f| unscaled_f(*f, &SCALE).unwrap_or(f32::NAN));
+            (id, v.collect())
+          })
+          .collect(),
+      ),
+    ];
+    for output in graph.outputs.iter() {
+      for (k, (name_a, a)) in paths.iter().enumerate() {
+        for (name_b, b) in paths[k + 1..].iter() {
+          let (a, b) = (&a[output], &b[output]);
+          assert_eq!(
+            a.len(),
+            b.len(),
+            "Sample {}, output {:?}: {} elements in the {} path, {} in the {} path",
+            i,
+            output,
+            a.len(),
+            name_a,
+            b.len(),
+            name_b
+          );
+          for (j, (u, v)) in a.iter().zip(b).enumerate() {
+            // NaN fails too
+            assert!(
+              (u - v).abs() <= tol,
+              "Sample {}, output {:?}[{}]: {} in the {} path, {} in the {} path",
+              i,
+              output,
+              j,
+              u,
+              name_a,
+              v,
+              name_b
+            );
+          }
+        }
+      }
+    }
+  }
+}
+
+/// The circuit counting the samples out of `samples` the model classifies correctly, see
+/// [crate::scalar::accuracy_circuit]. The weights enter as in [compile]. The input is set from [accuracy_input], the
+/// count is the result under the first output of the model.
+pub fn compile_accuracy(
+  trained: &TrainedGraph,
+  samples: usize,
+  prediction: Prediction,
+) -> MLSnark<CircuitField> {
+  let mut snark = compile(trained);
+  let (input, output) = (trained.graph.input_id, trained.graph.outputs[0]);
+  snark.graph = accuracy_circuit(&snark.graph, input, output, samples, prediction);
+  // the sources of the input are new, the weights keep theirs
+  snark
+    .source_map
+    .retain(|_, v| !matches!(v, SourceType::Private(_)));
+  for x in snark.graph.inputs_tracker.new_inputs[&input].iter() {
+    snark.source_map.insert(*x, SourceType::Private(None));
+  }
+  snark
+}
+
+/// The input of the circuit of [compile_accuracy]: the samples one after another, with the scaler of the model
+/// applied, then their labels.
+pub fn accuracy_input(trained: &TrainedGraph, x: &[Vec<f32>], labels: &[f32]) -> Vec<f32> {
+  assert_eq!(x.len(), labels.len(), "A label for every sample");
+  x.iter()
+    .flat_map(|x| scaled(trained, x))
+    .chain(labels.iter().copied())
+    .collect()
+}
+
+/// The f32 model trained on all the folds but one, tested on that one.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FoldReport {
+  /// With the noisy copies of the augmentation.
+  pub train_samples: usize,
+  pub test_samples: usize,
+  pub accuracy: f32,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CrossValidation {
+  pub folds: Vec<FoldReport>,
+}
+
+impl CrossValidation {
+  pub fn mean_accuracy(&self) -> f32 {
+    self.folds.iter().map(|f| f.accuracy).sum::<f32>() / self.folds.len() as f32
+  }
+
+  /// The standard deviation of the accuracy over the folds.
+  pub fn std_accuracy(&self) -> f32 {
+    let mean = self.mean_accuracy();
+    let var = self
+      .folds
+      .iter()
+      .map(|f| (f.accuracy - mean).powi(2))
+      .sum::<f32>()
+      / self.folds.len() as f32;
+    var.sqrt()
+  }
+}
+
+impl fmt::Display for CrossValidation {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    for (i, fold) in self.folds.iter().enumerate() {
+      writeln!(
+        f,
+        "Fold {}: accuracy {:.3} on {} samples, trained on {}",
+        i, fold.accuracy, fold.test_samples, fold.train_samples
+      )?;
+    }
+    write!(
+      f,
+      "Accuracy {:.3} ± {:.3} over {} folds",
+      self.mean_accuracy(),
+      self.std_accuracy(),
+      self.folds.len()
+    )
+  }
+}
+
+/// Trains a model with `train` on every fold of the `k`-fold split of the dataset and tests it on the rest, see the
+/// module docs. The training samples of a fold are augmented if asked, the tested ones never are. `train` gets the
+/// training samples as [crate::model::TrainParams::data], the models hold out a part of them in turn (e.g. for the
+/// threshold of the decision head). The seed picks the folds and the noise.
+pub fn cross_validate(
+  dataset: &(InputsVec, OutputsVec),
+  k: usize,
+  stratified: bool,
+  augmentation: Option<Augmentation>,
+  seed: u64,
+  mut train: impl FnMut((InputsVec, OutputsVec)) -> TrainedGraph,
+) -> CrossValidation {
+  let mut rng = StdRng::seed_from_u64(seed);
+  let folds = split(&dataset.1, Split::KFold { k }, stratified, &mut rng)
+    .iter()
+    .map(|fold| {
+      let (train_data, (x_test, y_test)) = fold.select(dataset);
+      let train_data = match augmentation {
+        Some(augmentation) => augment(&train_data, augmentation, &mut rng),
+        None => train_data,
+      };
+      let train_samples = train_data.0.len();
+      let trained = train(train_data);
+      let output = trained.graph.outputs[0];
+      let float: Vec<f32> = x_test
+        .iter()
+        .map(|x| trained.evaluate(scaled(&trained, x))[&output][0])
+        .collect();
+      FoldReport {
+        train_samples,
+        test_samples: y_test.len(),
+        accuracy: path_stats(&float, &float, &y_test).accuracy,
+      }
+    })
+    .collect();
+  CrossValidation { folds }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{
+    accuracy_input, assert_pipeline_consistent, compile_accuracy, cross_validate,
+    report_accuracy_with,
+  };
+  use crate::{
+    model::{dataset::Augmentation, parse_dataset, split_dataset, TrainParams},
+    quant::QuantConfig,
+    scalar::Prediction,
+    snark::{
+      backend::{Groth16Backend, ProvingBackend},
+      scaling_helpers::unscaled_f,
+    },
+    SCALE,
+  };
+
+  #[test]
+  fn test_report_accuracy() {
+    let data = parse_dataset(include_str!("../../data/rp.data").to_string());
+    let trained = crate::model::tiny_model::run_model(TrainParams {
+      data: data.clone(),
+      ..Default::default()
+    });
+    let report = report_accuracy_with(&trained, &data, &QuantConfig::default(), &Groth16Backend);
+    assert!(report.samples > 0);
+    assert_eq!(report.float.agreement, 1.0);
+    assert_eq!(report.float.max_abs_error, 0.0);
+    assert!(report.quantized.max_abs_error < 1e-2);
+    assert!(report.circuit.unwrap().max_abs_error < 1e-2);
+  }
+
+  #[test]
+  fn test_prove_accuracy() {
+    let data = parse_dataset(include_str!("../../data/rp.data").to_string());
+    let trained = crate::model::tiny_model::run_model(TrainParams {
+      data: data.clone(),
+      ..Default::default()
+    });
+    let (x, y) = data;
+    let (_, x_test, _, y_test) = split_dataset(x, y, 0.8);
+    let x: Vec<Vec<f32>> = x_test.iter().take(4).map(|x| x.to_vec()).collect();
+    let labels: Vec<f32> = y_test
+      .iter()
+      .take(4)
+      .map(|y| (*y > 0.5) as u8 as f32)
+      .collect();
+    let input = accuracy_input(&trained, &x, &labels);
+    let output = trained.graph.outputs[0];
+    let expected = (0..4)
+      .filter(|i| {
+        let sample = input[i * x[0].len()..(i + 1) * x[0].len()].to_vec();
+        let predicted = trained.evaluate(sample)[&output][0] > 0.5;
+        predicted == (labels[*i] == 1.0)
+      })
+      .count();
+
+    let mut snark = compile_accuracy(&trained, 4, Prediction::Threshold(0.5)).with_input_hash();
+    snark.set_input(input);
+    let results = Groth16Backend.witness_outputs(&mut snark).unwrap();
+    assert_eq!(
+      unscaled_f(results[&output][0], &SCALE).map(f32::round),
+      Some(expected as f32)
+    );
+    assert!(snark.get_input_hash().is_some());
+  }
+
+  #[test]
+  fn test_cross_validate() {
+    let data = parse_dataset(include_str!("../../data/rp.data").to_string());
+    let augmentation = Augmentation {
+      copies: 1,
+      sigma: 0.1,
+    };
+    let report = cross_validate(&data, 3, true, Some(augmentation), 0, |data| {
+      crate::model::tiny_model::run_model(TrainParams {
+        data,
+        ..Default::default()
+      })
+    });
+    assert_eq!(report.folds.len(), 3);
+    let tested: usize = report.folds.iter().map(|f| f.test_samples).sum();
+    assert_eq!(tested, data.1.len());
+    for fold in report.folds.iter() {
+      assert_eq!(fold.train_samples, 2 * (data.1.len() - fold.test_samples));
+      assert!((0.0..=1.0).contains(&fold.accuracy));
+    }
+    assert!(report.std_accuracy() >= 0.0);
+    assert!(report.to_string().contains("over 3 folds"));
+  }
+
+  #[test]
+  fn test_pipeline_consistent() {
+    let data = parse_dataset(include_str!("../../data/rp.data").to_string());
+    let trained = crate::model::medium_model::run_model(TrainParams {
+      data: data.clone(),
+      ..Default::default()
+    });
+    let samples: Vec<Vec<f32>> = data.0.iter().take(3).map(|x| x.to_vec()).collect();
+    assert_pipeline_consistent(&trained, &samples, 1e-2);
+  }
+}
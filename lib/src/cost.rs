@@ -0,0 +1,141 @@
+//!
+//! Circuit cost of a scalar graph before proving it, to compare architectures (or their quantizations) cheaply.
+//!
+//! A backend declares the constraints of every scalar op in its [CostModel], [estimate_cost] sums them over the graph
+//! and attributes them to the nodes of the original tensor graph (the layers, see [crate::scalar::InputsTracker::origin]).
+//!
+
+use std::collections::HashMap;
+
+use itertools::Itertools;
+use luminal::prelude::{petgraph::Direction::Outgoing, NodeIndex};
+
+use crate::scalar::{ScalarGraph, ScalarOp};
+
+/// Constraints (or rows, in the measure of the backend) per scalar op.
+pub trait CostModel {
+  /// Of one node of the op, `None` if the backend can't prove the op.
+  fn op_cost(&self, op: &ScalarOp) -> Option<usize>;
+
+  /// Of binding one result (a sink or a retrieved node) to its public input.
+  fn output_cost(&self) -> usize;
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CostBreakdown {
+  pub total: usize,
+  /// By the node of the original graph the scalar nodes implement a part of.
+  pub by_tensor: HashMap<NodeIndex, usize>,
+  /// Of the nodes without an origin, made up by the scalarization or the rewrites.
+  pub unattributed: usize,
+  /// By [ScalarOp::name], the public outputs under `Output`.
+  pub by_op: HashMap<&'static str, usize>,
+  /// Nodes the backend can't prove, not in the total.
+  pub unsupported: Vec<(NodeIndex, ScalarOp)>,
+}
+
+impl CostBreakdown {
+  /// The nodes of the original graph by cost, the most expensive first.
+  pub fn layers(&self) -> Vec<(NodeIndex, usize)> {
+    self
+      .by_tensor
+      .iter()
+      .map(|(t, c)| (*t, *c))
+      .sorted_by_key(|(t, c)| (std::cmp::Reverse(*c), *t))
+      .collect()
+  }
+}
+
+impl std::fmt::Display for CostBreakdown {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    writeln!(f, "total: {}", self.total)?;
+    for (op, c) in self.by_op.iter().sorted() {
+      writeln!(f, "  {}: {}", op, c)?;
+    }
+    for (t, c) in self.layers() {
+      writeln!(f, "tensor node {:?}: {}", t, c)?;
+    }
+    if self.unattributed > 0 {
+      writeln!(f, "unattributed: {}", self.unattributed)?;
+    }
+    for (x, op) in self.unsupported.iter() {
+      writeln!(f, "unsupported {} at {:?}", op.name(), x)?;
+    }
+    Ok(())
+  }
+}
+
+/// The cost of proving the scalar graph with the backend of the cost model, see the module docs.
+pub fn estimate_cost(scalar: &ScalarGraph, model: &dyn CostModel) -> CostBreakdown {
+  let graph = &scalar.graph;
+  let mut breakdown = CostBreakdown::default();
+  for x in graph.node_indices().sorted() {
+    let op = scalar.scalar_op(x);
+    let mut cost = match model.op_cost(&op) {
+      Some(c) => {
+        *breakdown.by_op.entry(op.name()).or_default() += c;
+        c
+      }
+      None => {
+        breakdown.unsupported.push((x, op));
+        0
+      }
+    };
+    if graph.edges_directed(x, Outgoing).next().is_none() || graph.to_retrieve.contains_key(&x) {
+      *breakdown.by_op.entry("Output").or_default() += model.output_cost();
+      cost += model.output_cost();
+    }
+    breakdown.total += cost;
+    match scalar.inputs_tracker.origin.get(&x) {
+      Some((t, _)) => *breakdown.by_tensor.entry(*t).or_default() += cost,
+      None => breakdown.unattributed += cost,
+    }
+  }
+  breakdown
+}
+
+#[cfg(test)]
+mod tests {
+  use luminal::{graph::Graph, shape::R1};
+
+  use super::estimate_cost;
+  use crate::{
+    compile,
+    scalar::{scalar, ScalarOp},
+    snark::backend::{Groth16Backend, ProvingBackend},
+  };
+
+  #[test]
+  fn test_groth16_cost_matches_the_circuit() {
+    let trained = crate::model::fixed_weights::run_model();
+    let mut snark = compile(&trained);
+    let cost = estimate_cost(&snark.graph.thaw(), &Groth16Backend);
+    assert!(cost.unsupported.is_empty());
+    assert_eq!(
+      cost.total,
+      Groth16Backend.estimate_constraints(&mut snark).unwrap()
+    );
+    let attributed: usize = cost.by_tensor.values().sum();
+    assert_eq!(attributed + cost.unattributed, cost.total);
+    assert_eq!(cost.by_op.values().sum::<usize>(), cost.total);
+    // the relus, range checked, dominate
+    assert_eq!(
+      cost.by_op.keys().max_by_key(|op| cost.by_op[*op]),
+      Some(&"Relu")
+    );
+  }
+
+  #[test]
+  fn test_unsupported_ops() {
+    let mut cx = Graph::new();
+    let a = cx.tensor::<R1<3>>();
+    let _b = a.sigmoid().retrieve();
+    let (sc, _) = scalar(&cx);
+    let cost = estimate_cost(&sc, &Groth16Backend);
+    assert_eq!(cost.unsupported.len(), 3);
+    assert!(cost
+      .unsupported
+      .iter()
+      .all(|(_, op)| matches!(op, ScalarOp::Lookup(_))));
+  }
+}
@@ -0,0 +1,157 @@
+//!
+//! Element types of the tensors a model comes with.
+//!
+//! Everything here computes in f32 up to the scalar graph (and in the field after it), luminal's CPU ops too.
+//! Quantization-aware trained or half precision models store their weights and constants as f16, bf16 or i8 though.
+//! They're converted to f32 where they come in: [DType::decode] for raw data as in safetensors files, [tensor_f32] for
+//! the data of a graph tensor, in place of downcasting it to `Vec<f32>`.
+//! Every f16, bf16 and i8 is an f32 exactly, nothing is lost. An i8 is its integer value, the scale of a quantized
+//! weight is for the graph to apply.
+//!
+
+use half::{bf16, f16};
+use luminal::prelude::Tensor;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DType {
+  F32,
+  F16,
+  BF16,
+  I8,
+}
+
+impl DType {
+  /// The dtype of its name in safetensors headers, e.g. `"BF16"`.
+  pub fn from_name(name: &str) -> Option<DType> {
+    match name {
+      "F32" => Some(DType::F32),
+      "F16" => Some(DType::F16),
+      "BF16" => Some(DType::BF16),
+      "I8" => Some(DType::I8),
+      _ => None,
+    }
+  }
+
+  pub fn name(&self) -> &'static str {
+    match self {
+      DType::F32 => "F32",
+      DType::F16 => "F16",
+      DType::BF16 => "BF16",
+      DType::I8 => "I8",
+    }
+  }
+
+  /// Bytes per element.
+  pub fn size(&self) -> usize {
+    match self {
+      DType::F32 => 4,
+      DType::F16 | DType::BF16 => 2,
+      DType::I8 => 1,
+    }
+  }
+
+  /// The little-endian elements of the bytes as f32s. A trailing partial element is ignored.
+  pub fn decode(&self, bytes: &[u8]) -> Vec<f32> {
+    let chunks = bytes.chunks_exact(self.size());
+    match self {
+      DType::F32 => chunks
+        .map(|b| f32::from_le_bytes(b.try_into().unwrap()))
+        .collect(),
+      DType::F16 => chunks
+        .map(|b| f16::from_le_bytes(b.try_into().unwrap()).to_f32())
+        .collect(),
+      DType::BF16 => chunks
+        .map(|b| bf16::from_le_bytes(b.try_into().unwrap()).to_f32())
+        .collect(),
+      DType::I8 => chunks.map(|b| b[0] as i8 as f32).collect(),
+    }
+  }
+
+  /// The f32s as little-endian elements of the dtype, rounded to the nearest. i8s saturate.
+  pub fn encode(&self, data: &[f32]) -> Vec<u8> {
+    match self {
+      DType::F32 => data.iter().flat_map(|v| v.to_le_bytes()).collect(),
+      DType::F16 => data
+        .iter()
+        .flat_map(|v| f16::from_f32(*v).to_le_bytes())
+        .collect(),
+      DType::BF16 => data
+        .iter()
+        .flat_map(|v| bf16::from_f32(*v).to_le_bytes())
+        .collect(),
+      DType::I8 => data.iter().map(|v| v.round() as i8 as u8).collect(),
+    }
+  }
+
+  /// The dtype of the data of the tensor, `None` for other data (e.g. on a GPU).
+  pub fn of_tensor(tensor: &Tensor) -> Option<DType> {
+    if tensor.downcast_ref::<Vec<f32>>().is_some() {
+      Some(DType::F32)
+    } else if tensor.downcast_ref::<Vec<f16>>().is_some() {
+      Some(DType::F16)
+    } else if tensor.downcast_ref::<Vec<bf16>>().is_some() {
+      Some(DType::BF16)
+    } else if tensor.downcast_ref::<Vec<i8>>().is_some() {
+      Some(DType::I8)
+    } else {
+      None
+    }
+  }
+}
+
+/// The data of a tensor of any [DType] as f32s, `None` for other data.
+pub fn tensor_f32(tensor: &Tensor) -> Option<Vec<f32>> {
+  match DType::of_tensor(tensor)? {
+    DType::F32 => tensor.downcast_ref::<Vec<f32>>().cloned(),
+    DType::F16 => tensor
+      .downcast_ref::<Vec<f16>>()
+      .map(|d| d.iter().map(|v| v.to_f32()).collect()),
+    DType::BF16 => tensor
+      .downcast_ref::<Vec<bf16>>()
+      .map(|d| d.iter().map(|v| v.to_f32()).collect()),
+    DType::I8 => tensor
+      .downcast_ref::<Vec<i8>>()
+      .map(|d| d.iter().map(|v| *v as f32).collect()),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use half::{bf16, f16};
+  use luminal::prelude::Tensor;
+
+  use super::{tensor_f32, DType};
+
+  #[test]
+  fn test_dtypes() {
+    let values = vec![1.0, -2.0, 0.5, 0.0, 96.0, -128.0];
+    for dtype in [DType::F32, DType::F16, DType::BF16, DType::I8] {
+      assert_eq!(DType::from_name(dtype.name()), Some(dtype));
+      let bytes = dtype.encode(&values);
+      assert_eq!(bytes.len(), values.len() * dtype.size());
+      // exact in every dtype but the half of i8, rounded half away from zero
+      let expected = match dtype {
+        DType::I8 => vec![1.0, -2.0, 1.0, 0.0, 96.0, -128.0],
+        _ => values.clone(),
+      };
+      assert_eq!(dtype.decode(&bytes), expected);
+    }
+    assert_eq!(DType::I8.encode(&[300.0, -300.0]), vec![127, 128]);
+    assert_eq!(
+      DType::F16.decode(&DType::F16.encode(&[0.1])),
+      vec![0.099975586]
+    );
+    assert_eq!(DType::from_name("F64"), None);
+
+    let half = Tensor::new(vec![f16::from_f32(1.5), f16::from_f32(-0.25)]);
+    assert_eq!(DType::of_tensor(&half), Some(DType::F16));
+    assert_eq!(tensor_f32(&half), Some(vec![1.5, -0.25]));
+    let brain = Tensor::new(vec![bf16::from_f32(3.0)]);
+    assert_eq!(tensor_f32(&brain), Some(vec![3.0]));
+    assert_eq!(
+      tensor_f32(&Tensor::new(vec![-3i8, 7])),
+      Some(vec![-3.0, 7.0])
+    );
+    assert_eq!(tensor_f32(&Tensor::new(vec![1u32])), None);
+  }
+}
@@ -0,0 +1,543 @@
+//!
+//! Export of the scalar graph to a Circom 2 template, for the snarkjs toolchain.
+//!
+//! Values are fixed-point integers as defined by [QuantConfig]. Negative values are field elements `p - n`.
+//! Every scalar node becomes one signal `s<node index>`. Supported ops:
+//!  - Add: `a + b`
+//!  - Sub, Neg: `a - b`, `-a`
+//!  - Mul: `a * b` rescaled by `2^scale_bits` with a range-checked floor division (the `Rescale` template)
+//!  - LessThan: signed comparison via circomlib's `LessThan` on values moved by `2^value_bits`, the 0/1 result scaled to fixed-point
+//!  - SignedLessThan: the same, with the bits of the node (see [crate::quant::SignedLessThan])
+//!  - LessThanConst, GreaterThanConst: the same against the constant at the scale of the argument
+//!    (see [crate::quant::const_threshold]), sized for the argument alone
+//!  - RangeCheck: the bit decomposition of the value moved by `2^bits`, by the `RangeCheck` template
+//!  - RecipHint: the hinted reciprocal checked by `in * out + r == 2^k` (see [crate::quant::RecipHint]), by the `RecipHint` template
+//!  - DivConst: by a power of two, the `Rescale` of a product (see [crate::quant::QuantizedGraph::explicit_rescales])
+//!  - Max: `lt * (b - a) + a` with the same comparison
+//!  - ReluOp: `(0 < a) * a` with the same comparison
+//!
+//! All inputs (also weights) are private inputs of the template, in the order of [crate::scalar::InputsTracker::ordered_inputs].
+//! Outputs are in the order of [crate::scalar::InputsTracker::ordered_outputs].
+//! Compile with the circomlib circuits on the include path, i.e. `circom model.circom -l node_modules`.
+//!
+
+use std::{cmp::Ordering, collections::HashMap, convert::Infallible, error::Error, fs, path::Path};
+
+use itertools::Itertools;
+use luminal::prelude::NodeIndex;
+use num_bigint::BigInt;
+use serde_json::json;
+use tracing::instrument;
+
+use crate::{
+  quant::{const_threshold, divisor_shift, NodeScales, QuantConfig, RangeCheckGadget},
+  scalar::{ScalarGraph, ScalarOp},
+};
+
+use super::{field_repr, ordered_input_values};
+
+const TEMPLATES: &str = r#"pragma circom 2.0.0;
+
+include "circomlib/circuits/comparators.circom";
+
+// out = floor(in / 2^k), for |in| < 2^n
+template Rescale(k, n) {
+  signal input in;
+  signal output out;
+  signal r;
+  out <-- ((in + (1 << n)) \ (1 << k)) - (1 << (n - k));
+  r <-- (in + (1 << n)) % (1 << k);
+  in === out * (1 << k) + r;
+  component r_bits = Num2Bits(k);
+  r_bits.in <== r;
+  component out_bits = Num2Bits(n - k + 1);
+  out_bits.in <== out + (1 << (n - k));
+}
+
+// out = a < b, for |a|, |b| < 2^n
+template SignedLessThan(n) {
+  signal input a;
+  signal input b;
+  signal output out;
+  component lt = LessThan(n + 1);
+  lt.in[0] <== a + (1 << n);
+  lt.in[1] <== b + (1 << n);
+  out <== lt.out;
+}
+
+// -2^n <= in < 2^n
+template RangeCheck(n) {
+  signal input in;
+  component bits = Num2Bits(n + 1);
+  bits.in <== in + (1 << n);
+}
+
+// out = trunc(2^k / in) and 0 for in == 0, for |in| < 2^n. The quotient is a hint, checked by
+// in * out + r == 2^k * (in != 0) with 0 <= r < |in| and |out| <= 2^k
+template RecipHint(k, n) {
+  signal input in;
+  signal output out;
+  signal r;
+  component zero = IsZero();
+  zero.in <== in;
+  component neg = SignedLessThan(n);
+  neg.a <== in;
+  neg.b <== 0;
+  signal abs;
+  abs <== in * (1 - 2 * neg.out);
+  var q = (1 << k) \ (abs + zero.out);
+  out <-- neg.out == 1 ? -q : q;
+  r <-- (1 << k) * (1 - zero.out) - in * out;
+  in * out + r === (1 << k) * (1 - zero.out);
+  out * zero.out === 0;
+  component r_lt = LessThan(n + 1);
+  r_lt.in[0] <== r;
+  r_lt.in[1] <== abs + zero.out;
+  r_lt.out === 1;
+  component out_bits = Num2Bits(k + 2);
+  out_bits.in <== out + (1 << k);
+}
+"#;
+
+/// The range checks of the circuit, by the `RangeCheck` template on the signals.
+struct BitDecomposition<'a> {
+  body: &'a mut Vec<String>,
+}
+
+impl RangeCheckGadget for BitDecomposition<'_> {
+  type Var = String;
+  type Error = Infallible;
+
+  fn range_check(&mut self, x: &String, bits: u32) -> Result<(), Infallible> {
+    self
+      .body
+      .push(format!("  component rc_{} = RangeCheck({});", x, bits));
+    self.body.push(format!("  rc_{}.in <== {};", x, x));
+    Ok(())
+  }
+}
+
+/// Renders the whole circom file, with the main component named `Model`.
+pub fn render(scalar: &ScalarGraph, quant: &QuantConfig) -> Result<String, Box<dyn Error>> {
+  render_with(scalar, quant, &NodeScales::default())
+}
+
+/// [render] with every node at its own scale.
+/// A value moving to a larger scale is multiplied by a power of two, to a smaller one it goes through a `Rescale`.
+/// Inputs are expected at the scales of their nodes (see [input_json_with]) and the outputs are at theirs.
+#[instrument(
+  level = "info",
+  name = "quantize",
+  skip_all,
+  fields(backend = "circom", nodes = scalar.graph.graph.node_count())
+)]
+pub fn render_with(
+  scalar: &ScalarGraph,
+  quant: &QuantConfig,
+  scales: &NodeScales,
+) -> Result<String, Box<dyn Error>> {
+  let n = quant.value_bits;
+  // values moved to a larger scale grow past value_bits by at most this many bits
+  let spread = scales.spread(quant);
+  let k = |y: NodeIndex| scales.get(quant, y);
+  let input_index: HashMap<NodeIndex, usize> = scalar
+    .inputs_tracker
+    .ordered_inputs()
+    .into_iter()
+    .enumerate()
+    .map(|(i, x)| (x, i))
+    .collect();
+  let outputs = scalar.inputs_tracker.ordered_outputs();
+
+  // `value` at the scale `from` as an expression at the scale `to`, the rescaling component (if needed) is named `name`
+  let convert = |body: &mut Vec<String>,
+                 name: String,
+                 value: String,
+                 from: u32,
+                 to: u32,
+                 bound: u32| {
+    match from.cmp(&to) {
+      Ordering::Equal => value,
+      Ordering::Less => format!("{} * {}", value, BigInt::from(1) << (to - from)),
+      Ordering::Greater => {
+        body.push(format!(
+          "  component {} = Rescale({}, {});",
+          name,
+          from - to,
+          bound
+        ));
+        body.push(format!("  {}.in <== {};", name, value));
+        format!("{}.out", name)
+      }
+    }
+  };
+
+  let mut body: Vec<String> = vec![];
+  for node in scalar.freeze_ops().nodes {
+    let (x, args) = (node.id, node.args);
+    let s = |y: NodeIndex| format!("s{}", y.index());
+    let i = x.index();
+    let kx = k(x);
+    body.push(format!("  signal {};", s(x)));
+    match node.op {
+      ScalarOp::Input => {
+        let j = input_index
+          .get(&x)
+          .ok_or_else(|| format!("Input node {:?} is not tracked", x))?;
+        body.push(format!("  {} <== in[{}];", s(x), j));
+      }
+      ScalarOp::Constant(val) => {
+        let c = scales.config(quant, x).quantize(val);
+        body.push(format!("  {} <== {};", s(x), c));
+      }
+      op @ (ScalarOp::Add | ScalarOp::Sub) => {
+        let (name, sign) = if op == ScalarOp::Add {
+          ("add", "+")
+        } else {
+          ("sub", "-")
+        };
+        let a = convert(
+          &mut body,
+          format!("{}{}_0", name, i),
+          s(args[0]),
+          k(args[0]),
+          kx,
+          n,
+        );
+        let b = convert(
+          &mut body,
+          format!("{}{}_1", name, i),
+          s(args[1]),
+          k(args[1]),
+          kx,
+          n,
+        );
+        body.push(format!("  {} <== {} {} {};", s(x), a, sign, b));
+      }
+      ScalarOp::Neg => {
+        let a = convert(
+          &mut body,
+          format!("neg{}", i),
+          s(args[0]),
+          k(args[0]),
+          kx,
+          n,
+        );
+        body.push(format!("  {} <== -{};", s(x), a));
+      }
+      ScalarOp::Mul => {
+        let product = format!("{} * {}", s(args[0]), s(args[1]));
+        let (ka, kb) = (k(args[0]), k(args[1]));
+        let result = convert(&mut body, format!("mul{}", i), product, ka + kb, kx, 2 * n);
+        body.push(format!("  {} <== {};", s(x), result));
+      }
+      ScalarOp::Fma => {
+        let product = format!("{} * {}", s(args[0]), s(args[1]));
+        let (ka, kb) = (k(args[0]), k(args[1]));
+        let result = convert(&mut body, format!("fma{}", i), product, ka + kb, kx, 2 * n);
+        let c = convert(
+          &mut body,
+          format!("fma{}_2", i),
+          s(args[2]),
+          k(args[2]),
+          kx,
+          n,
+        );
+        body.push(format!("  {} <== {} + {};", s(x), result, c));
+      }
+      ScalarOp::LessThan | ScalarOp::SignedLessThan(_) => {
+        // compared at the larger of the scales, moving there is exact
+        let (ka, kb) = (k(args[0]), k(args[1]));
+        let c = ka.max(kb);
+        let a = convert(&mut body, String::new(), s(args[0]), ka, c, n);
+        let b = convert(&mut body, String::new(), s(args[1]), kb, c, n);
+        let bits = match node.op {
+          ScalarOp::SignedLessThan(bits) => bits,
+          _ => n + ka.abs_diff(kb),
+        };
+        body.push(format!("  component lt{} = SignedLessThan({});", i, bits));
+        body.push(format!("  lt{}.a <== {};", i, a));
+        body.push(format!("  lt{}.b <== {};", i, b));
+        body.push(format!(
+          "  {} <== lt{}.out * {};",
+          s(x),
+          i,
+          BigInt::from(1) << kx
+        ));
+      }
+      ScalarOp::LessThanConst(c) | ScalarOp::GreaterThanConst(c) => {
+        // a constant past the range of the argument is moved to its edge, a bit more keeps the edge in range
+        let less_than = matches!(node.op, ScalarOp::LessThanConst(_));
+        let bound = 1i128 << n;
+        let t = const_threshold(c, k(args[0]), less_than).clamp(-bound, bound);
+        let (a, b) = if less_than {
+          (s(args[0]), t.to_string())
+        } else {
+          (t.to_string(), s(args[0]))
+        };
+        body.push(format!("  component lt{} = SignedLessThan({});", i, n + 1));
+        body.push(format!("  lt{}.a <== {};", i, a));
+        body.push(format!("  lt{}.b <== {};", i, b));
+        body.push(format!(
+          "  {} <== lt{}.out * {};",
+          s(x),
+          i,
+          BigInt::from(1) << kx
+        ));
+      }
+      ScalarOp::RangeCheck(bits) => {
+        BitDecomposition { body: &mut body }.range_check(&s(args[0]), bits)?;
+        body.push(format!("  {} <== {};", s(x), s(args[0])));
+      }
+      ScalarOp::RecipHint => {
+        body.push(format!(
+          "  component recip{} = RecipHint({}, {});",
+          i,
+          k(args[0]) + kx,
+          n
+        ));
+        body.push(format!("  recip{}.in <== {};", i, s(args[0])));
+        body.push(format!("  {} <== recip{}.out;", s(x), i));
+      }
+      ScalarOp::DivConst(divisor) => {
+        let shift = divisor_shift(divisor).ok_or_else(|| {
+          format!(
+            "Circom export: DivConst by {} at {:?}, not a power of two",
+            divisor, x
+          )
+        })?;
+        let result = convert(&mut body, format!("div{}", i), s(args[0]), shift, 0, 2 * n);
+        body.push(format!("  {} <== {};", s(x), result));
+      }
+      ScalarOp::Max => {
+        let a = convert(
+          &mut body,
+          format!("max{}_0", i),
+          s(args[0]),
+          k(args[0]),
+          kx,
+          n,
+        );
+        let b = convert(
+          &mut body,
+          format!("max{}_1", i),
+          s(args[1]),
+          k(args[1]),
+          kx,
+          n,
+        );
+        body.push(format!(
+          "  component max{} = SignedLessThan({});",
+          i,
+          n + spread
+        ));
+        body.push(format!("  max{}.a <== {};", i, a));
+        body.push(format!("  max{}.b <== {};", i, b));
+        body.push(format!(
+          "  {} <== max{}.out * ({} - {}) + {};",
+          s(x),
+          i,
+          b,
+          a,
+          a
+        ));
+      }
+      ScalarOp::Relu => {
+        let a = convert(
+          &mut body,
+          format!("relu{}_0", i),
+          s(args[0]),
+          k(args[0]),
+          kx,
+          n,
+        );
+        body.push(format!(
+          "  component relu{} = SignedLessThan({});",
+          i,
+          n + spread
+        ));
+        body.push(format!("  relu{}.a <== 0;", i));
+        body.push(format!("  relu{}.b <== {};", i, a));
+        body.push(format!("  {} <== relu{}.out * {};", s(x), i, a));
+      }
+      op => {
+        return Err(
+          format!(
+            "Circom export: unsupported scalar op {} at {:?}",
+            op.name(),
+            x
+          )
+          .into(),
+        );
+      }
+    }
+  }
+  for (j, y) in outputs.iter().enumerate() {
+    body.push(format!("  out[{}] <== s{};", j, y.index()));
+  }
+
+  Ok(format!(
+    "{}\ntemplate Model() {{\n  signal input in[{}];\n  signal output out[{}];\n\n{}\n}}\n\ncomponent main = Model();\n",
+    TEMPLATES,
+    input_index.len(),
+    outputs.len(),
+    body.join("\n")
+  ))
+}
+
+/// Writes the circom file, see [render].
+pub fn write(scalar: &ScalarGraph, quant: &QuantConfig, path: &Path) -> Result<(), Box<dyn Error>> {
+  fs::write(path, render(scalar, quant)?)?;
+  Ok(())
+}
+
+/// The input JSON for snarkjs witness generation.
+/// Inputs are given per original tensor graph input, as for [ScalarGraph::evaluate].
+pub fn input_json(
+  scalar: &ScalarGraph,
+  quant: &QuantConfig,
+  inputs: &HashMap<NodeIndex, Vec<f32>>,
+) -> Result<serde_json::Value, Box<dyn Error>> {
+  input_json_with(scalar, quant, &NodeScales::default(), inputs)
+}
+
+/// [input_json] for a circuit made by [render_with], every input quantized at the scale of its node.
+pub fn input_json_with(
+  scalar: &ScalarGraph,
+  quant: &QuantConfig,
+  scales: &NodeScales,
+  inputs: &HashMap<NodeIndex, Vec<f32>>,
+) -> Result<serde_json::Value, Box<dyn Error>> {
+  let values = ordered_input_values(scalar, inputs)?
+    .into_iter()
+    .zip(scalar.inputs_tracker.ordered_inputs())
+    .map(|(v, x)| field_repr(scales.config(quant, x).quantize(v)))
+    .collect_vec();
+  Ok(json!({ "in": values }))
+}
+
+#[cfg(test)]
+mod tests {
+  use luminal::{graph::Graph, shape::R1};
+
+  use crate::{
+    quant::{NodeScales, QuantConfig, QuantizedGraph},
+    scalar::scalar,
+  };
+
+  use super::{render, render_with};
+
+  #[test]
+  fn test_render_relu_layer() {
+    let mut cx = Graph::new();
+    let a = cx.tensor::<R1<3>>();
+    let b = cx.tensor::<R1<3>>();
+    let _c = (a * b)
+      .relu()
+      .sum_reduce::<_, luminal::shape::Axis<0>>()
+      .retrieve();
+    let (sc, _) = scalar(&cx);
+    let circom = render(&sc, &QuantConfig::default()).unwrap();
+    assert!(circom.contains("signal input in[6];"));
+    assert!(circom.contains("signal output out[3];"));
+    assert!(circom.contains("component main = Model();"));
+  }
+
+  #[test]
+  fn test_render_subtraction() {
+    let mut cx = Graph::new();
+    let a = cx.tensor::<R1<2>>();
+    let b = cx.tensor::<R1<2>>();
+    let _c = (a - b).retrieve();
+    let _d = (-a).retrieve();
+    let (sc, _) = scalar(&cx);
+    let circom = render(&sc, &QuantConfig::default()).unwrap();
+    let lines = |f: fn(&str) -> bool| circom.lines().filter(|l| f(l)).count();
+    assert_eq!(lines(|l| l.contains("<== s") && l.contains(" - s")), 2);
+    assert_eq!(lines(|l| l.contains("<== -s")), 2);
+    // no multiplications by -1 left, nothing to rescale
+    assert!(!circom.contains("= Rescale("));
+  }
+
+  #[test]
+  fn test_render_with_layer_scales() {
+    let mut cx = Graph::new();
+    let a = cx.tensor::<R1<3>>();
+    let b = cx.tensor::<R1<3>>();
+    let c = (a * b).relu().retrieve();
+    let (sc, _) = scalar(&cx);
+    let quant = QuantConfig::default();
+    let coarse = quant.scale_bits - 4;
+    let scales = NodeScales::from_tensor_scales(&sc, &[(c.id, coarse)].into_iter().collect());
+    assert!(!scales.scale_bits.is_empty());
+    assert_eq!(scales.spread(&quant), 4);
+    assert_eq!(
+      render_with(&sc, &quant, &NodeScales::default()).unwrap(),
+      render(&sc, &quant).unwrap()
+    );
+    let circom = render_with(&sc, &quant, &scales).unwrap();
+    assert!(circom.contains(&format!("SignedLessThan({})", quant.value_bits + 4)));
+    assert!(circom.contains(&format!("Rescale({}, ", quant.scale_bits + 4)));
+  }
+
+  #[test]
+  fn test_signed_comparisons_render_as_less_than() {
+    let mut cx = Graph::new();
+    let a = cx.tensor::<R1<3>>();
+    let b = cx.tensor::<R1<3>>();
+    let _c = a.less_than(b).retrieve();
+    let quant = QuantConfig::default();
+    let render_lt = |signed: bool| {
+      // canonical, so both scalarizations have the same ids
+      let sc = scalar(&cx).0.canonicalize();
+      let scales = NodeScales::from_tensor_scales(&sc, &[(b.id, 8)].into_iter().collect());
+      if signed {
+        // without the range checks, which have their own nodes
+        let mut quantized = QuantizedGraph {
+          scalar: sc,
+          quant,
+          scales,
+        };
+        quantized.signed_comparisons();
+        render_with(&quantized.scalar, &quant, &quantized.scales).unwrap()
+      } else {
+        render_with(&sc, &quant, &scales).unwrap()
+      }
+    };
+    let circom = render_lt(true);
+    assert_eq!(circom, render_lt(false));
+    assert!(circom.contains(&format!(
+      "SignedLessThan({})",
+      quant.value_bits + quant.scale_bits - 8
+    )));
+  }
+
+  #[test]
+  fn test_render_range_checks() {
+    let mut cx = Graph::new();
+    let a = cx.tensor::<R1<3>>();
+    let b = cx.tensor::<R1<3>>();
+    let _c = (a * b).less_than(a).retrieve();
+    let quant = QuantConfig::default();
+    let quantized = QuantizedGraph::new(scalar(&cx).0, quant, NodeScales::default());
+    let circom = render_with(&quantized.scalar, &quant, &quantized.scales).unwrap();
+    let checks = format!("= RangeCheck({});", quant.value_bits);
+    // a and the products
+    assert_eq!(circom.matches(&checks).count(), 2 * 3);
+  }
+
+  #[test]
+  fn test_render_recip_hint() {
+    let mut cx = Graph::new();
+    let a = cx.tensor::<R1<3>>();
+    let _c = a.recip().retrieve();
+    let quant = QuantConfig::default();
+    let quantized = QuantizedGraph::new(scalar(&cx).0, quant, NodeScales::default());
+    let circom = render_with(&quantized.scalar, &quant, &quantized.scales).unwrap();
+    assert!(circom.contains(&format!(
+      "= RecipHint({}, {});",
+      2 * quant.scale_bits,
+      quant.value_bits
+    )));
+  }
+}
@@ -0,0 +1,72 @@
+//!
+//! Exporters of the scalar graph to circuit languages of other proving toolchains.
+//!
+pub mod circom;
+pub mod noir;
+
+use std::{collections::HashMap, error::Error};
+
+use itertools::Itertools;
+use luminal::prelude::NodeIndex;
+use num_bigint::BigInt;
+
+use crate::scalar::ScalarGraph;
+
+/// Order of the bn254 scalar field, the default field of both circom and noir.
+pub const BN254_PRIME: &str =
+  "21888242871839275222246405745257275088548364400416034343698204186575808495617";
+
+/// Quantized value as a decimal field element, negative values wrapping around the bn254 prime.
+pub fn field_repr(n: i64) -> String {
+  let n = BigInt::from(n);
+  if n < BigInt::from(0) {
+    (n + BN254_PRIME.parse::<BigInt>().unwrap()).to_string()
+  } else {
+    n.to_string()
+  }
+}
+
+/// Flattens the inputs given per original tensor graph input into the order of [crate::scalar::InputsTracker::ordered_inputs].
+pub fn ordered_input_values(
+  scalar: &ScalarGraph,
+  inputs: &HashMap<NodeIndex, Vec<f32>>,
+) -> Result<Vec<f32>, Box<dyn Error>> {
+  let mut values = vec![];
+  for (x, little_ids) in scalar
+    .inputs_tracker
+    .new_inputs
+    .iter()
+    .sorted_by_key(|(k, _)| **k)
+  {
+    let data = inputs
+      .get(x)
+      .ok_or_else(|| format!("Missing input for {:?}", x))?;
+    if data.len() != little_ids.len() {
+      return Err(
+        format!(
+          "Input {:?} expects {} values, got {}",
+          x,
+          little_ids.len(),
+          data.len()
+        )
+        .into(),
+      );
+    }
+    values.extend(data.iter().copied());
+  }
+  Ok(values)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::field_repr;
+
+  #[test]
+  fn test_field_repr() {
+    assert_eq!(field_repr(5), "5");
+    assert_eq!(
+      field_repr(-1),
+      "21888242871839275222246405745257275088548364400416034343698204186575808495616"
+    );
+  }
+}
@@ -0,0 +1,364 @@
+//!
+//! Export of the scalar graph to a Noir program, for the Aztec toolchain (nargo).
+//!
+//! Same fixed-point encoding as the circom export (see [QuantConfig] and [super::circom]):
+//!  - Add: `a + b`
+//!  - Sub, Neg: `a - b`, `0 - a`
+//!  - Mul: `a * b` rescaled with a hinted floor division, constrained by `x == q * SCALE + r` and range checks on `q` and `r`
+//!  - LessThan, SignedLessThan: `Field::lt` on values moved by `2^value_bits`, the result scaled to fixed-point
+//!  - LessThanConst, GreaterThanConst: the same against the constant at the scale of the argument
+//!    (see [crate::quant::const_threshold])
+//!  - RangeCheck: `assert_max_bit_size` of the value moved by `2^bits`
+//!  - DivConst: by a power of two, the rescale of a product (see [crate::quant::QuantizedGraph::explicit_rescales])
+//!  - Max: `lt * (b - a) + a`
+//!  - ReluOp: `lt(0, a) * a`
+//!
+//! With per node scales (see [render_with]) values are moved between scales by a multiplication by a power of two,
+//! or by a rescale with the shift as the divisor.
+//!
+//! `main` takes all inputs (also weights) as a single private array in the order of [crate::scalar::InputsTracker::ordered_inputs]
+//! and returns the outputs as a public array in the order of [crate::scalar::InputsTracker::ordered_outputs].
+//!
+
+use std::{
+  cmp::Ordering,
+  collections::{BTreeSet, HashMap},
+  convert::Infallible,
+  error::Error,
+  fs,
+  path::Path,
+};
+
+use itertools::Itertools;
+use luminal::prelude::NodeIndex;
+use num_bigint::BigInt;
+use tracing::instrument;
+
+use crate::{
+  quant::{const_threshold, divisor_shift, NodeScales, QuantConfig, RangeCheckGadget},
+  scalar::{ScalarGraph, ScalarOp},
+};
+
+use super::{field_repr, ordered_input_values};
+
+fn pow2(bits: u32) -> BigInt {
+  BigInt::from(1) << bits
+}
+
+fn helpers(quant: &QuantConfig, spread: u32) -> String {
+  let (k, n) = (quant.scale_bits, quant.value_bits);
+  format!(
+    r#"global SCALE: Field = {scale};
+// products are below 2^(2 * value_bits), moving them by OFFSET makes them positive for the integer division
+global OFFSET: Field = {offset};
+global OFFSET_Q: Field = {offset_q};
+global CMP_OFFSET: Field = {cmp_offset};
+
+unconstrained fn rescale_hint(x: Field) -> (Field, Field) {{
+    let shifted = (x + OFFSET) as u128;
+    let q = shifted / {scale_u128};
+    let r = shifted % {scale_u128};
+    (q as Field - OFFSET_Q, r as Field)
+}}
+
+// floor(x / SCALE)
+fn rescale(x: Field) -> Field {{
+    let (q, r) = rescale_hint(x);
+    assert(x == q * SCALE + r);
+    r.assert_max_bit_size({k});
+    (q + OFFSET_Q).assert_max_bit_size({q_bits});
+    q
+}}
+
+// signed a < b, scaled to fixed-point
+fn lt(a: Field, b: Field) -> Field {{
+    if (a + CMP_OFFSET).lt(b + CMP_OFFSET) {{ SCALE }} else {{ 0 }}
+}}
+"#,
+    scale = pow2(k),
+    scale_u128 = pow2(k),
+    offset = pow2(2 * n),
+    offset_q = pow2(2 * n - k),
+    cmp_offset = pow2(n + spread),
+    k = k,
+    q_bits = 2 * n - k + 1,
+  )
+}
+
+/// Name of the helper dividing by `2^shift`, `rescale` is the one of the default scale.
+fn rescale_name(quant: &QuantConfig, shift: u32) -> String {
+  if shift == quant.scale_bits {
+    "rescale".to_string()
+  } else {
+    format!("rescale_by_{}", shift)
+  }
+}
+
+/// `rescale` of [helpers] for another shift.
+fn rescale_helper(quant: &QuantConfig, shift: u32) -> String {
+  let n = quant.value_bits;
+  format!(
+    r#"
+unconstrained fn {name}_hint(x: Field) -> (Field, Field) {{
+    let shifted = (x + OFFSET) as u128;
+    let q = shifted / {divisor};
+    let r = shifted % {divisor};
+    (q as Field - {offset_q}, r as Field)
+}}
+
+// floor(x / {divisor})
+fn {name}(x: Field) -> Field {{
+    let (q, r) = {name}_hint(x);
+    assert(x == q * {divisor} + r);
+    r.assert_max_bit_size({shift});
+    (q + {offset_q}).assert_max_bit_size({q_bits});
+    q
+}}
+"#,
+    name = rescale_name(quant, shift),
+    divisor = pow2(shift),
+    offset_q = pow2(2 * n - shift),
+    shift = shift,
+    q_bits = 2 * n - shift + 1,
+  )
+}
+
+/// The range checks of the program, by `assert_max_bit_size` on the variables.
+struct AssertBits<'a> {
+  body: &'a mut Vec<String>,
+}
+
+impl RangeCheckGadget for AssertBits<'_> {
+  type Var = String;
+  type Error = Infallible;
+
+  fn range_check(&mut self, x: &String, bits: u32) -> Result<(), Infallible> {
+    self.body.push(format!(
+      "    ({} + {}).assert_max_bit_size({});",
+      x,
+      pow2(bits),
+      bits + 1
+    ));
+    Ok(())
+  }
+}
+
+/// Renders `src/main.nr` of a nargo project.
+pub fn render(scalar: &ScalarGraph, quant: &QuantConfig) -> Result<String, Box<dyn Error>> {
+  render_with(scalar, quant, &NodeScales::default())
+}
+
+/// [render] with every node at its own scale, as [super::circom::render_with].
+#[instrument(
+  level = "info",
+  name = "quantize",
+  skip_all,
+  fields(backend = "noir", nodes = scalar.graph.graph.node_count())
+)]
+pub fn render_with(
+  scalar: &ScalarGraph,
+  quant: &QuantConfig,
+  scales: &NodeScales,
+) -> Result<String, Box<dyn Error>> {
+  let spread = scales.spread(quant);
+  let k = |y: NodeIndex| scales.get(quant, y);
+  let input_index: HashMap<NodeIndex, usize> = scalar
+    .inputs_tracker
+    .ordered_inputs()
+    .into_iter()
+    .enumerate()
+    .map(|(i, x)| (x, i))
+    .collect();
+  let outputs = scalar.inputs_tracker.ordered_outputs();
+
+  // shifts of the rescale helpers used, besides the default one
+  let mut shifts = BTreeSet::new();
+  // `value` at the scale `from` as an expression at the scale `to`
+  let mut convert = |value: String, from: u32, to: u32| match from.cmp(&to) {
+    Ordering::Equal => value,
+    Ordering::Less => format!("{} * {}", value, pow2(to - from)),
+    Ordering::Greater => {
+      if from - to != quant.scale_bits {
+        shifts.insert(from - to);
+      }
+      format!("{}({})", rescale_name(quant, from - to), value)
+    }
+  };
+
+  let mut body: Vec<String> = vec![];
+  for node in scalar.freeze_ops().nodes {
+    let (x, args) = (node.id, node.args);
+    let s = |y: NodeIndex| format!("s{}", y.index());
+    let kx = k(x);
+    let expr = match node.op {
+      ScalarOp::Input => {
+        let j = input_index
+          .get(&x)
+          .ok_or_else(|| format!("Input node {:?} is not tracked", x))?;
+        format!("inputs[{}]", j)
+      }
+      ScalarOp::Constant(val) => {
+        let c = scales.config(quant, x).quantize(val);
+        if c < 0 {
+          format!("0 - {}", -c)
+        } else {
+          c.to_string()
+        }
+      }
+      ScalarOp::Add => {
+        let a = convert(s(args[0]), k(args[0]), kx);
+        let b = convert(s(args[1]), k(args[1]), kx);
+        format!("{} + {}", a, b)
+      }
+      ScalarOp::Sub => {
+        let a = convert(s(args[0]), k(args[0]), kx);
+        let b = convert(s(args[1]), k(args[1]), kx);
+        format!("{} - {}", a, b)
+      }
+      ScalarOp::Neg => format!("0 - {}", convert(s(args[0]), k(args[0]), kx)),
+      ScalarOp::Mul => {
+        let product = format!("{} * {}", s(args[0]), s(args[1]));
+        convert(product, k(args[0]) + k(args[1]), kx)
+      }
+      ScalarOp::Fma => {
+        let product = format!("{} * {}", s(args[0]), s(args[1]));
+        let result = convert(product, k(args[0]) + k(args[1]), kx);
+        format!("{} + {}", result, convert(s(args[2]), k(args[2]), kx))
+      }
+      ScalarOp::LessThan | ScalarOp::SignedLessThan(_) => {
+        // compared at the larger of the scales, moving there is exact
+        // `Field::lt` isn't sized, the offset of `lt` covers the bits of every SignedLessThan
+        let c = k(args[0]).max(k(args[1]));
+        let a = convert(s(args[0]), k(args[0]), c);
+        let b = convert(s(args[1]), k(args[1]), c);
+        if kx == quant.scale_bits {
+          format!("lt({}, {})", a, b)
+        } else {
+          format!("(lt({}, {}) / SCALE) * {}", a, b, pow2(kx))
+        }
+      }
+      ScalarOp::LessThanConst(c) | ScalarOp::GreaterThanConst(c) => {
+        let less_than = matches!(node.op, ScalarOp::LessThanConst(_));
+        let t = const_threshold(c, k(args[0]), less_than);
+        let t = if t < 0 {
+          format!("(0 - {})", -t)
+        } else {
+          t.to_string()
+        };
+        let (a, b) = if less_than {
+          (s(args[0]), t)
+        } else {
+          (t, s(args[0]))
+        };
+        if kx == quant.scale_bits {
+          format!("lt({}, {})", a, b)
+        } else {
+          format!("(lt({}, {}) / SCALE) * {}", a, b, pow2(kx))
+        }
+      }
+      ScalarOp::RangeCheck(bits) => {
+        AssertBits { body: &mut body }.range_check(&s(args[0]), bits)?;
+        s(args[0])
+      }
+      ScalarOp::DivConst(divisor) => {
+        let shift = divisor_shift(divisor).ok_or_else(|| {
+          format!(
+            "Noir export: DivConst by {} at {:?}, not a power of two",
+            divisor, x
+          )
+        })?;
+        convert(s(args[0]), shift, 0)
+      }
+      ScalarOp::Max => format!(
+        "(lt({a}, {b}) / SCALE) * ({b} - {a}) + {a}",
+        a = convert(s(args[0]), k(args[0]), kx),
+        b = convert(s(args[1]), k(args[1]), kx)
+      ),
+      ScalarOp::Relu => format!(
+        "(lt(0, {a}) / SCALE) * {a}",
+        a = convert(s(args[0]), k(args[0]), kx)
+      ),
+      op => {
+        return Err(
+          format!(
+            "Noir export: unsupported scalar op {} at {:?}",
+            op.name(),
+            x
+          )
+          .into(),
+        )
+      }
+    };
+    body.push(format!("    let {} = {};", s(x), expr));
+  }
+  let result = outputs.iter().map(|y| format!("s{}", y.index())).join(", ");
+
+  let helpers = std::iter::once(helpers(quant, spread))
+    .chain(shifts.into_iter().map(|d| rescale_helper(quant, d)))
+    .join("");
+
+  Ok(format!(
+    "{}\nfn main(inputs: [Field; {}]) -> pub [Field; {}] {{\n{}\n    [{}]\n}}\n",
+    helpers,
+    input_index.len(),
+    outputs.len(),
+    body.join("\n"),
+    result
+  ))
+}
+
+/// `Prover.toml` with the quantized inputs, given per original tensor graph input as for [ScalarGraph::evaluate].
+pub fn prover_toml(
+  scalar: &ScalarGraph,
+  quant: &QuantConfig,
+  inputs: &HashMap<NodeIndex, Vec<f32>>,
+) -> Result<String, Box<dyn Error>> {
+  prover_toml_with(scalar, quant, &NodeScales::default(), inputs)
+}
+
+/// [prover_toml] for a program made by [render_with], every input quantized at the scale of its node.
+pub fn prover_toml_with(
+  scalar: &ScalarGraph,
+  quant: &QuantConfig,
+  scales: &NodeScales,
+  inputs: &HashMap<NodeIndex, Vec<f32>>,
+) -> Result<String, Box<dyn Error>> {
+  let values = ordered_input_values(scalar, inputs)?
+    .into_iter()
+    .zip(scalar.inputs_tracker.ordered_inputs())
+    .map(|(v, x)| format!("\"{}\"", field_repr(scales.config(quant, x).quantize(v))))
+    .join(", ");
+  Ok(format!("inputs = [{}]\n", values))
+}
+
+/// Writes a nargo project: `Nargo.toml` and `src/main.nr` into the directory.
+pub fn write(scalar: &ScalarGraph, quant: &QuantConfig, dir: &Path) -> Result<(), Box<dyn Error>> {
+  fs::create_dir_all(dir.join("src"))?;
+  fs::write(
+    dir.join("Nargo.toml"),
+    "[package]\nname = \"model\"\ntype = \"bin\"\nauthors = [\"\"]\n\n[dependencies]\n",
+  )?;
+  fs::write(dir.join("src").join("main.nr"), render(scalar, quant)?)?;
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use luminal::{graph::Graph, shape::R1};
+
+  use crate::{quant::QuantConfig, scalar::scalar};
+
+  use super::render;
+
+  #[test]
+  fn test_render_relu_layer() {
+    let mut cx = Graph::new();
+    let a = cx.tensor::<R1<3>>();
+    let b = cx.tensor::<R1<3>>();
+    let _c = (a * b).relu().retrieve();
+    let (sc, _) = scalar(&cx);
+    let noir = render(&sc, &QuantConfig::default()).unwrap();
+    assert!(noir.contains("fn main(inputs: [Field; 6]) -> pub [Field; 3]"));
+    assert!(noir.contains("rescale("));
+  }
+}
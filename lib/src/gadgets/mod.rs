@@ -0,0 +1,4 @@
+//!
+//! Subcircuits appended to a scalar graph out of the basic scalar ops, so that no backend needs code of its own for them.
+//!
+pub mod poseidon;
@@ -0,0 +1,256 @@
+//!
+//! The Poseidon hash of scalar nodes, made of Add, Mul and constant nodes of the scalar graph.
+//!
+//! The permutation and the sponge are those of [crate::snark::poseidon] (see there for the parameters and the padding),
+//! run on nodes instead of field elements. The nodes compute the hash with Add and Mul read as the operations of the
+//! scalar field and the constants as integers, as an exact backend reads them. Under the fixed point encoding of
+//! [crate::snark::MLSnark], where a Mul rescales its result, they compute something else.
+//!
+//! The round constants and the MDS matrix are elements of the field, too large for a constant node. They are put
+//! together from digits below `2^24`, exact in an f32: `c = ((d_k * 2^24 + d_(k-1)) * 2^24 + ...) * 2^24 + d_0`.
+//!
+
+use std::{
+  cell::RefCell,
+  collections::HashMap,
+  convert::TryFrom,
+  ops::{Add, Mul},
+};
+
+use ark_ff::PrimeField;
+use luminal::{
+  op::{self, Operator},
+  prelude::{Dependency, NodeIndex},
+  shape::{Shape, R0},
+};
+use num_bigint::BigUint;
+
+use crate::{
+  scalar::{ConstantOp, ScalarGraph},
+  snark::{poseidon::sponge, CircuitField},
+};
+
+/// Bits of the digits of the field constants, see the module docs.
+const DIGIT_BITS: u32 = 24;
+
+struct Builder<'a> {
+  scalar: &'a mut ScalarGraph,
+  /// The origin of the new nodes, that of the first input.
+  origin: Option<(NodeIndex, usize)>,
+  /// The nodes of the field constants made so far.
+  fields: HashMap<CircuitField, NodeIndex>,
+  /// The constant nodes of the digits made so far.
+  digits: HashMap<u32, NodeIndex>,
+}
+
+impl Builder<'_> {
+  fn node<T: Operator + 'static>(&mut self, op: T, args: &[NodeIndex]) -> NodeIndex {
+    let new = self.scalar.graph.add_op(op).finish();
+    for (input_order, y) in args.iter().enumerate() {
+      self.scalar.graph.add_edge(
+        *y,
+        new,
+        Dependency::Data {
+          input_order: input_order as u8,
+          output_order: 0,
+          shape: R0::to_tracker(),
+        },
+      );
+    }
+    if let Some(origin) = self.origin {
+      self.scalar.inputs_tracker.origin.insert(new, origin);
+    }
+    new
+  }
+
+  fn digit(&mut self, d: u32) -> NodeIndex {
+    if let Some(c) = self.digits.get(&d) {
+      return *c;
+    }
+    let val = d as f32;
+    let c = self.node(ConstantOp { val }, &[]);
+    self.scalar.inputs_tracker.constants.insert(c, val);
+    self.digits.insert(d, c);
+    c
+  }
+
+  /// The node of a field element, by its digits.
+  fn field(&mut self, x: CircuitField) -> NodeIndex {
+    if let Some(c) = self.fields.get(&x) {
+      return *c;
+    }
+    let mut n: BigUint = x.into_repr().into();
+    let mask = (BigUint::from(1u32) << DIGIT_BITS) - 1u32;
+    let mut digits = vec![];
+    loop {
+      digits.push(u32::try_from(&n & &mask).unwrap());
+      n >>= DIGIT_BITS;
+      if n.bits() == 0 {
+        break;
+      }
+    }
+    let base = self.digit(1 << DIGIT_BITS);
+    let mut c = self.digit(digits.pop().unwrap());
+    for d in digits.into_iter().rev() {
+      c = self.node(op::Mul {}, &[c, base]);
+      if d != 0 {
+        let d = self.digit(d);
+        c = self.node(op::Add {}, &[c, d]);
+      }
+    }
+    self.fields.insert(x, c);
+    c
+  }
+}
+
+/// A node under construction, for the generic permutation: the field operations on it add nodes.
+#[derive(Clone)]
+struct Wire<'b, 'a> {
+  node: NodeIndex,
+  builder: &'b RefCell<Builder<'a>>,
+}
+
+impl Add for Wire<'_, '_> {
+  type Output = Self;
+
+  fn add(self, rhs: Self) -> Self {
+    let node = self
+      .builder
+      .borrow_mut()
+      .node(op::Add {}, &[self.node, rhs.node]);
+    Wire { node, ..self }
+  }
+}
+
+impl Add<CircuitField> for Wire<'_, '_> {
+  type Output = Self;
+
+  fn add(self, rhs: CircuitField) -> Self {
+    let node = {
+      let mut b = self.builder.borrow_mut();
+      let c = b.field(rhs);
+      b.node(op::Add {}, &[self.node, c])
+    };
+    Wire { node, ..self }
+  }
+}
+
+impl Mul<CircuitField> for Wire<'_, '_> {
+  type Output = Self;
+
+  fn mul(self, rhs: CircuitField) -> Self {
+    let node = {
+      let mut b = self.builder.borrow_mut();
+      let c = b.field(rhs);
+      b.node(op::Mul {}, &[self.node, c])
+    };
+    Wire { node, ..self }
+  }
+}
+
+/// Appends the [crate::snark::poseidon::poseidon_hash] of the inputs to the scalar graph, see the module docs.
+/// Returns the node of the hash. Nothing is retrieved, make it an output to publish the hash.
+pub fn append(scalar: &mut ScalarGraph, inputs: &[NodeIndex]) -> NodeIndex {
+  let origin = inputs
+    .first()
+    .and_then(|x| scalar.inputs_tracker.origin.get(x).copied());
+  let builder = RefCell::new(Builder {
+    scalar,
+    origin,
+    fields: HashMap::new(),
+    digits: HashMap::new(),
+  });
+  let wire = |node| Wire {
+    node,
+    builder: &builder,
+  };
+  let zero = CircuitField::from(0u64);
+  let state = [CircuitField::from(inputs.len() as u64), zero, zero]
+    .map(|x| wire(builder.borrow_mut().field(x)));
+  let message: Vec<Wire> = inputs.iter().map(|x| wire(*x)).collect();
+  let hash = sponge(state, &message, |x| {
+    let mut b = x.builder.borrow_mut();
+    let x2 = b.node(op::Mul {}, &[x.node, x.node]);
+    let x4 = b.node(op::Mul {}, &[x2, x2]);
+    let node = b.node(op::Mul {}, &[x4, x.node]);
+    Ok(Wire {
+      node,
+      builder: x.builder,
+    })
+  })
+  .unwrap();
+  hash.node
+}
+
+#[cfg(test)]
+mod tests {
+  use std::collections::HashMap;
+
+  use itertools::Itertools;
+  use luminal::{graph::Graph, prelude::NodeIndex, shape::R1};
+
+  use super::append;
+  use crate::{
+    scalar::{scalar, ScalarGraph, ScalarOp},
+    snark::{poseidon::poseidon_hash, CircuitField},
+  };
+
+  /// Every node of the graph, with Add and Mul in the field and the constants as integers.
+  fn evaluate_exact(
+    sc: &ScalarGraph,
+    inputs: &HashMap<NodeIndex, CircuitField>,
+  ) -> HashMap<NodeIndex, CircuitField> {
+    let mut values: HashMap<NodeIndex, CircuitField> = HashMap::new();
+    for node in sc.freeze_ops().nodes {
+      let a = node.args.iter().map(|y| values[y]).collect_vec();
+      let v = match node.op {
+        ScalarOp::Input => inputs[&node.id],
+        ScalarOp::Constant(c) => {
+          assert!(c >= 0.0 && c.fract() == 0.0, "{} is not a digit", c);
+          CircuitField::from(c as u64)
+        }
+        ScalarOp::Add => a[0] + a[1],
+        ScalarOp::Mul => a[0] * a[1],
+        op => panic!("{} in the hash", op.name()),
+      };
+      values.insert(node.id, v);
+    }
+    values
+  }
+
+  #[test]
+  fn test_append_poseidon() {
+    let mut cx = Graph::new();
+    let a = cx.tensor::<R1<5>>();
+    let b = cx.tensor::<R1<5>>();
+    let c = (a * b).retrieve();
+    let (mut sc, _) = scalar(&cx);
+    let products = sc.inputs_tracker.new_outputs[&c.id].clone();
+    let hash = append(&mut sc, &products);
+    let empty = append(&mut sc, &[]);
+    let origin = &sc.inputs_tracker.origin;
+    assert_eq!(origin[&hash], origin[&products[0]]);
+
+    let xs = [3u64, 1, 4, 1, 5];
+    let ys = [2u64, 7, 1, 8, 2];
+    let little_inputs = |tensor: NodeIndex, vals: &[u64]| {
+      sc.inputs_tracker.new_inputs[&tensor]
+        .iter()
+        .zip(vals)
+        .map(|(x, v)| (*x, CircuitField::from(*v)))
+        .collect_vec()
+    };
+    let inputs = [little_inputs(a.id, &xs), little_inputs(b.id, &ys)]
+      .concat()
+      .into_iter()
+      .collect();
+    let values = evaluate_exact(&sc, &inputs);
+    let message = xs
+      .iter()
+      .zip(ys)
+      .map(|(x, y)| CircuitField::from(x * y))
+      .collect_vec();
+    assert_eq!(values[&hash], poseidon_hash(&message));
+    assert_eq!(values[&empty], poseidon_hash(&[]));
+  }
+}
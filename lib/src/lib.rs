@@ -1,41 +1,138 @@
-use std::{collections::HashMap, vec};
+//!
+//! Everything but the verification of proofs is behind the `native` feature (on by default). Without it the crate builds
+//! for `wasm32-unknown-unknown`: [snark::bundle::verify_bundle] and what it needs, with the browser bindings of [wasm]
+//! under the `wasm` feature.
+//!
 
-use model::TrainedGraph;
-use scalar::scalar;
-use snark::{scaling_helpers::ScaleT, CircuitField, MLSnark, SourceType};
+#[cfg(feature = "native")]
+use std::{collections::HashMap, error::Error, vec};
 
+#[cfg(feature = "native")]
+use itertools::Itertools;
+#[cfg(feature = "native")]
+use luminal::prelude::NodeIndex;
+#[cfg(feature = "native")]
+use model::{GraphForSnark, ParamRegistry, TrainedGraph};
+#[cfg(feature = "native")]
+use scalar::{scalar, ScalarGraph};
+use snark::scaling_helpers::ScaleT;
+#[cfg(feature = "native")]
+use snark::{CircuitField, MLSnark, SourceType};
+
+#[cfg(feature = "native")]
 pub mod model;
+#[cfg(feature = "native")]
 pub mod subcommands;
 
+#[cfg(feature = "native")]
+pub mod accuracy;
+#[cfg(feature = "native")]
+pub mod cost;
+#[cfg(feature = "native")]
+pub mod dtype;
+#[cfg(feature = "native")]
+pub mod export;
+#[cfg(feature = "native")]
+pub mod gadgets;
 pub mod notes;
+#[cfg(feature = "native")]
+pub mod quant;
+#[cfg(feature = "native")]
 pub mod scalar;
+#[cfg(feature = "service")]
+pub mod service;
 pub mod snark;
+#[cfg(feature = "native")]
 pub mod utils;
+#[cfg(feature = "wasm")]
+pub mod wasm;
 
 pub const SCALE: ScaleT = ScaleT {
   s: 100_000,
   z: u128::MAX << 2, /* ~ 1e38 */
 }; // giving float range from about -1e32 to 1e32
 
+/// How the weights of the model enter the circuit.
+#[cfg(feature = "native")]
+#[derive(Debug, Clone, Default, PartialEq)]
+pub enum WeightsMode {
+  /// Public inputs, one per element, checked by the verifier (see [snark::verifier::PublicInputsSchema]).
+  #[default]
+  PublicInputs,
+  /// Constants of the circuit, fixed by the verifying key (see [scalar::ScalarGraph::freeze_params]): the model is
+  /// public, only the input is private. No public inputs for the weights, a smaller circuit and a faster verifier,
+  /// but new weights need a new setup. Every registered parameter, see [ParamRegistry].
+  Constants,
+  /// [WeightsMode::Constants] for the named parameters, the other weights are public inputs.
+  ConstantsOf(Vec<String>),
+}
+
+#[cfg(feature = "native")]
+impl WeightsMode {
+  /// The parameters of the registry baked into the circuit.
+  pub fn baked(&self, params: &ParamRegistry) -> Result<ParamRegistry, Box<dyn Error>> {
+    match self {
+      WeightsMode::PublicInputs => Ok(ParamRegistry::default()),
+      WeightsMode::Constants => Ok(params.clone()),
+      WeightsMode::ConstantsOf(names) => params.select(names),
+    }
+  }
+}
+
 /// Main crate export. Take a tensor computation and rewrite to snark.
+#[cfg(feature = "native")]
 pub fn compile(c: &TrainedGraph) -> MLSnark<CircuitField> {
-  let graph_for_snark = c.graph.copy_graph_roughly();
-  let graph = graph_for_snark.graph;
-  let weights = graph_for_snark.weights;
-  let input_id = graph_for_snark.input_id;
-  // let weights = c.weights.clone();
+  compile_graph(&c.graph)
+}
+
+/// [compile] with the weights entering the circuit as given.
+#[cfg(feature = "native")]
+pub fn compile_with(c: &TrainedGraph, mode: &WeightsMode) -> MLSnark<CircuitField> {
+  compile_graph_with(&c.graph, mode)
+}
+
+/// [compile] for a graph that wasn't trained here, e.g. from [GraphForSnark::from_spec_and_weights].
+#[cfg(feature = "native")]
+pub fn compile_graph(g: &GraphForSnark) -> MLSnark<CircuitField> {
+  compile_graph_with(g, &WeightsMode::PublicInputs)
+}
+
+/// [compile_graph] with the weights entering the circuit as given.
+#[cfg(feature = "native")]
+pub fn compile_graph_with(g: &GraphForSnark, mode: &WeightsMode) -> MLSnark<CircuitField> {
+  let input_id = g.input_id;
+  g.validate_weights()
+    .unwrap_or_else(|e| panic!("Wrong weights: {}", e));
   // We set here the weights already. Set input with ::set_input.
-  let sc = scalar(graph);
+  let (mut sc, _) = scalar(&g.graph);
+  assert_snark_supported(&sc);
+  let baked = mode
+    .baked(&g.params)
+    .unwrap_or_else(|e| panic!("Wrong weights mode: {}", e));
+  sc.freeze_params(&baked, &g.weights)
+    .unwrap_or_else(|e| panic!("Wrong weights: {:?}", e));
+  let weights = g
+    .weights
+    .iter()
+    .filter(|(x, _)| baked.by_id(*x).is_none())
+    .cloned()
+    .collect::<Vec<_>>();
+  for output in g.outputs.iter() {
+    assert!(
+      sc.inputs_tracker.new_outputs.contains_key(output),
+      "Output {:?} is not retrieved",
+      output
+    );
+  }
   let mut source_map = HashMap::new();
   // set public
   for (i, w_i) in weights {
-    let little_ids = sc
+    let assigned = sc
       .inputs_tracker
-      .new_inputs
-      .get(&i)
-      .unwrap_or_else(|| panic!("Wrong id"));
-    for (little_id, v) in little_ids.into_iter().zip(w_i) {
-      source_map.insert(*little_id, SourceType::Public(v));
+      .assign(i, &w_i)
+      .unwrap_or_else(|e| panic!("Wrong weights: {:?}", e));
+    for (little_id, v) in assigned {
+      source_map.insert(little_id, SourceType::Public(v));
     }
   }
   // set private
@@ -48,33 +145,80 @@ pub fn compile(c: &TrainedGraph) -> MLSnark<CircuitField> {
     source_map.insert(*little_id, SourceType::Private(None));
   }
   MLSnark {
-    graph: sc,
+    graph: sc.freeze_ops(),
     scale: SCALE,
     source_map: source_map,
     og_input_id: input_id,
+    input_hash: false,
+    commitments: vec![],
+    weight_opening: None,
     recorded_public_inputs: vec![],
+    recorded_public_nodes: vec![],
+    trace: Default::default(),
   }
 }
 
-#[cfg(test)]
+/// The nodes of the graph [MLSnark] has no constraints for, with the scalar ops they need: the lookups (e.g. exp2, sin)
+/// and Max, among others. [MLSnark] synthesizes the ops of the [cost::CostModel] of [snark::backend::Groth16Backend],
+/// [compile_graph_with] panics on the rest. One entry per node and op, by node.
+#[cfg(feature = "native")]
+pub fn check_snark_supported(g: &GraphForSnark) -> Vec<(NodeIndex, &'static str)> {
+  unsupported_by_snark(&scalar(&g.graph).0)
+}
+
+/// Panics on the nodes of [check_snark_supported], as [compile_graph_with] does. For the chunks of
+/// [snark::aggregate] too.
+#[cfg(feature = "native")]
+pub(crate) fn assert_snark_supported(sc: &ScalarGraph) {
+  let unsupported = unsupported_by_snark(sc);
+  assert!(
+    unsupported.is_empty(),
+    "No constraints for the ops (node, op): {:?}",
+    unsupported
+  );
+}
+
+#[cfg(feature = "native")]
+fn unsupported_by_snark(sc: &ScalarGraph) -> Vec<(NodeIndex, &'static str)> {
+  cost::estimate_cost(sc, &snark::backend::Groth16Backend)
+    .unsupported
+    .into_iter()
+    .map(|(x, op)| {
+      let t = sc.inputs_tracker.origin.get(&x).map_or(x, |(t, _)| *t);
+      (t, op.name())
+    })
+    .sorted()
+    .dedup()
+    .collect()
+}
+
+#[cfg(all(test, feature = "native"))]
 mod tests {
 
   use crate::{
-    compile,
-    model::{parse_dataset, TrainParams, TrainedGraph},
+    check_snark_supported, compile, compile_with,
+    model::{
+      parse_dataset, GraphForSnark, OutputHead, Param, ParamRegistry, TrainParams, TrainedGraph,
+    },
+    scalar::copy_graph_roughly,
     snark::{
       scaling_helpers::{f_from_bigint_unsafe, field_close_as_floats, scaled_float, unscaled_f},
       CircuitField,
     },
-    SCALE,
+    WeightsMode, SCALE,
   };
   use ark_bls12_381::Bls12_381;
   use ark_groth16::Groth16;
   use ark_snark::SNARK;
   use itertools::Itertools;
+  use luminal::{
+    graph::Graph,
+    prelude::GraphTensor,
+    shape::{Axis, R1},
+  };
 
   pub fn test_trained_into_snark(
-    mut trained_model: TrainedGraph,
+    trained_model: TrainedGraph,
     input: Vec<f32>,
   ) -> Result<(), String> {
     let err = |e| format!("{:?}", e).to_string();
@@ -93,27 +237,35 @@ mod tests {
     // Verifier: verify the proof
     let verified = Groth16::<Bls12_381>::verify(&vk, &public_inputs, &proof);
 
-    // God: and compare the results obtained
-    let snark_eval_result = snark.get_evaluation_result(); // this really just is public_inputs[-1], a publicly known result of the circuit
-    let model_eval_res_float = trained_model.evaluate(input)[0];
-    let model_eval_result: CircuitField =
-      f_from_bigint_unsafe(scaled_float(model_eval_res_float, &SCALE));
-    tracing::info!(
-      "{:?} {:?} as floats {:?} {:?}",
-      snark_eval_result,
-      model_eval_result,
-      unscaled_f(snark_eval_result, &SCALE),
-      model_eval_res_float
-    );
-
-    let diff = field_close_as_floats(snark_eval_result, model_eval_result, &SCALE);
-
+    // God: and compare the results obtained, of every output
+    let snark_eval_results = snark.get_evaluation_results(); // these are public inputs, publicly known results of the circuit
+    let model_eval_results = trained_model.evaluate(input);
     assert!(verified == Ok(true), "Proof is verified");
-    assert!(
-      diff,
-      "The snark evaluates to the correct result (~ float precision)"
-    );
-    tracing::info!("evaluated the model to {:?}, which is represented by a field element {:?}. Also evaluated the snark to a field element {:?}. The two results are within 0.01 float margin. Verifier correctly verified the proof that snark evaluates to that value.", model_eval_res_float, model_eval_result, snark_eval_result);
+    for output in trained_model.graph.outputs.iter() {
+      let model_eval_res_floats = &model_eval_results[output];
+      let snark_eval_res = &snark_eval_results[output];
+      assert_eq!(model_eval_res_floats.len(), snark_eval_res.len());
+      for (model_eval_res_float, snark_eval_result) in
+        model_eval_res_floats.iter().zip(snark_eval_res)
+      {
+        let model_eval_result: CircuitField =
+          f_from_bigint_unsafe(scaled_float(*model_eval_res_float, &SCALE));
+        tracing::info!(
+          "{:?} {:?} as floats {:?} {:?}",
+          snark_eval_result,
+          model_eval_result,
+          unscaled_f(*snark_eval_result, &SCALE),
+          model_eval_res_float
+        );
+
+        let diff = field_close_as_floats(*snark_eval_result, model_eval_result, &SCALE);
+        assert!(
+          diff,
+          "The snark evaluates to the correct result (~ float precision)"
+        );
+        tracing::info!("evaluated the model to {:?}, which is represented by a field element {:?}. Also evaluated the snark to a field element {:?}. The two results are within 0.01 float margin. Verifier correctly verified the proof that snark evaluates to that value.", model_eval_res_float, model_eval_result, snark_eval_result);
+      }
+    }
 
     drop(scope);
     Ok(())
@@ -124,7 +276,11 @@ mod tests {
     // See the model shape at https://dreampuf.github.io/GraphvizOnline/#digraph%20%7B%0A%20%20%20%200%20%5B%20label%20%3D%20%22Weight%20Load%20%7C%200%22%20%5D%0A%20%20%20%201%20%5B%20label%20%3D%20%22Tensor%20Load%20%7C%201%22%20%5D%0A%20%20%20%202%20%5B%20label%20%3D%20%22Mul%20%7C%202%22%20%5D%0A%20%20%20%203%20%5B%20label%20%3D%20%22SumReduce(2)%20%7C%203%22%20%5D%0A%20%20%20%200%20-%3E%202%20%5B%20%20%5D%0A%20%20%20%201%20-%3E%202%20%5B%20%20%5D%0A%20%20%20%202%20-%3E%203%20%5B%20%20%5D%0A%7D%0A
     tracing::info!("linear layer, data A");
     let data = parse_dataset(include_str!("../../data/rp.data").to_string());
-    let trained_model = crate::model::tiny_model::run_model(TrainParams { data, epochs: 2 });
+    let trained_model = crate::model::tiny_model::run_model(TrainParams {
+      data,
+      epochs: 2,
+      ..Default::default()
+    });
     let input = (0..9).map(|x| f32::from(x as i16)).collect_vec();
     test_trained_into_snark(trained_model, input)
   }
@@ -133,7 +289,11 @@ mod tests {
   pub fn test_trained_into_snark_1() -> Result<(), String> {
     tracing::info!("linear layer, data B");
     let data = parse_dataset(include_str!("../../data/rp.data").to_string());
-    let trained_model = crate::model::tiny_model::run_model(TrainParams { data, epochs: 2 });
+    let trained_model = crate::model::tiny_model::run_model(TrainParams {
+      data,
+      epochs: 2,
+      ..Default::default()
+    });
     let input = (9..18).map(|x| f32::from(x as i16)).collect_vec();
     test_trained_into_snark(trained_model, input)
   }
@@ -143,7 +303,11 @@ mod tests {
     // see the model shape at https://dreampuf.github.io/GraphvizOnline/#digraph%20%7B%0A%20%20%20%200%20%5B%20label%20%3D%20%22Weight%20Load%20%7C%200%22%20%5D%0A%20%20%20%201%20%5B%20label%20%3D%20%22Weight%20Load%20%7C%201%22%20%5D%0A%20%20%20%202%20%5B%20label%20%3D%20%22Tensor%20Load%20%7C%202%22%20%5D%0A%20%20%20%203%20%5B%20label%20%3D%20%22Mul%20%7C%203%22%20%5D%0A%20%20%20%204%20%5B%20label%20%3D%20%22SumReduce(2)%20%7C%204%22%20%5D%0A%20%20%20%205%20%5B%20label%20%3D%20%22Constant(0.0)%20%7C%205%22%20%5D%0A%20%20%20%206%20%5B%20label%20%3D%20%22LessThan%20%7C%206%22%20%5D%0A%20%20%20%207%20%5B%20label%20%3D%20%22Mul%20%7C%207%22%20%5D%0A%20%20%20%208%20%5B%20label%20%3D%20%22LessThan%20%7C%208%22%20%5D%0A%20%20%20%209%20%5B%20label%20%3D%20%22Constant(-1.0)%20%7C%209%22%20%5D%0A%20%20%20%2010%20%5B%20label%20%3D%20%22Mul%20%7C%2010%22%20%5D%0A%20%20%20%2011%20%5B%20label%20%3D%20%22Constant(1.0)%20%7C%2011%22%20%5D%0A%20%20%20%2012%20%5B%20label%20%3D%20%22Add%20%7C%2012%22%20%5D%0A%20%20%20%2013%20%5B%20label%20%3D%20%22Mul%20%7C%2013%22%20%5D%0A%20%20%20%2014%20%5B%20label%20%3D%20%22Add%20%7C%2014%22%20%5D%0A%20%20%20%2015%20%5B%20label%20%3D%20%22Mul%20%7C%2015%22%20%5D%0A%20%20%20%2016%20%5B%20label%20%3D%20%22SumReduce(2)%20%7C%2016%22%20%5D%0A%20%20%20%200%20-%3E%203%20%5B%20%20%5D%0A%20%20%20%201%20-%3E%2015%20%5B%20%20%5D%0A%20%20%20%202%20-%3E%203%20%5B%20%20%5D%0A%20%20%20%203%20-%3E%204%20%5B%20%20%5D%0A%20%20%20%204%20-%3E%208%20%5B%20%20%5D%0A%20%20%20%204%20-%3E%206%20%5B%20%20%5D%0A%20%20%20%204%20-%3E%2013%20%5B%20%20%5D%0A%20%20%20%205%20-%3E%208%20%5B%20%20%5D%0A%20%20%20%205%20-%3E%207%20%5B%20%20%5D%0A%20%20%20%205%20-%3E%206%20%5B%20%20%5D%0A%20%20%20%206%20-%3E%207%20%5B%20%20%5D%0A%20%20%20%207%20-%3E%2014%20%5B%20%20%5D%0A%20%20%20%208%20-%3E%2010%20%5B%20%20%5D%0A%20%20%20%209%20-%3E%2010%20%5B%20%20%5D%0A%20%20%20%2010%20-%3E%2012%20%5B%20%20%5D%0A%20%20%20%2011%20-%3E%2012%20%5B%20%20%5D%0A%20%20%20%2012%20-%3E%2013%20%5B%20%20%5D%0A%20%20%20%2013%20-%3E%2014%20%5B%20%20%5D%0A%20%20%20%2014%20-%3E%2015%20%5B%20%20%5D%0A%20%20%20%2015%20-%3E%2016%20%5B%20%20%5D%0A%7D%0A
     tracing::info!("linear layer into ReLU, data A");
     let data = parse_dataset(include_str!("../../data/rp.data").to_string());
-    let trained_model = crate::model::lessthan_model::run_model(TrainParams { data, epochs: 2 });
+    let trained_model = crate::model::lessthan_model::run_model(TrainParams {
+      data,
+      epochs: 2,
+      ..Default::default()
+    });
     let input = (0..9).map(|x| f32::from(x as i16)).collect_vec();
     test_trained_into_snark(trained_model, input)
   }
@@ -152,7 +316,11 @@ mod tests {
   pub fn test_trained_into_snark_3() -> Result<(), String> {
     tracing::info!("linear layer into ReLU, data B");
     let data = parse_dataset(include_str!("../../data/rp.data").to_string());
-    let trained_model = crate::model::lessthan_model::run_model(TrainParams { data, epochs: 2 });
+    let trained_model = crate::model::lessthan_model::run_model(TrainParams {
+      data,
+      epochs: 2,
+      ..Default::default()
+    });
     let input = (9..18).map(|x| f32::from(x as i16)).collect_vec();
     test_trained_into_snark(trained_model, input)
   }
@@ -161,7 +329,11 @@ mod tests {
   pub fn test_trained_into_snark_4() -> Result<(), String> {
     tracing::info!("linear layer into ReLU, data C");
     let data = parse_dataset(include_str!("../../data/rp.data").to_string());
-    let trained_model = crate::model::lessthan_model::run_model(TrainParams { data, epochs: 2 });
+    let trained_model = crate::model::lessthan_model::run_model(TrainParams {
+      data,
+      epochs: 2,
+      ..Default::default()
+    });
     let input: Vec<f32> = [
       1.001231212412512,
       0.3141512,
@@ -181,11 +353,162 @@ mod tests {
   #[test]
   pub fn test_trained_into_snark_5() -> Result<(), String> {
     let data = parse_dataset(include_str!("../../data/rp.data").to_string());
-    let trained_model = crate::model::medium_model::run_model(TrainParams { data, epochs: 1 });
+    let trained_model = crate::model::medium_model::run_model(TrainParams {
+      data,
+      ..Default::default()
+    });
     let input = (0..9).map(|x| f32::from(x as i16)).collect_vec();
     test_trained_into_snark(trained_model, input)
   }
 
+  #[ignore = "runs for too long"]
+  #[test]
+  pub fn test_trained_into_snark_decision() -> Result<(), String> {
+    tracing::info!("medium model with the thresholded decision head");
+    let data = parse_dataset(include_str!("../../data/rp.data").to_string());
+    let trained_model = crate::model::medium_model::run_model(TrainParams {
+      data,
+      head: OutputHead::Decision,
+      ..Default::default()
+    });
+    assert!(trained_model.threshold.is_some());
+    let input = (0..9).map(|x| f32::from(x as i16)).collect_vec();
+    test_trained_into_snark(trained_model, input)
+  }
+
+  #[test]
+  pub fn test_multiple_outputs_into_snark() -> Result<(), String> {
+    tracing::info!("two heads proven in one circuit: a score and a ReLU layer");
+    let mut cx = Graph::new();
+    let input = cx.tensor::<R1<3>>();
+    let w = cx.tensor::<R1<3>>();
+    let score = (input * w).sum_reduce::<_, Axis<0>>().retrieve();
+    let layer = (input + w).relu().retrieve();
+    let (cx_og, remap) = copy_graph_roughly(&cx);
+    let target = cx.tensor::<R1<1>>(); // unused, but evaluate expects it
+    let weights = vec![0.5, -3.0, 1.5];
+    let trained_model = TrainedGraph {
+      graph: GraphForSnark {
+        graph: cx_og,
+        input_id: remap[&input.id],
+        weights: vec![(remap[&w.id], weights.clone())],
+        outputs: vec![remap[&score.id], remap[&layer.id]],
+        params: ParamRegistry {
+          params: vec![Param {
+            name: "w".to_string(),
+            id: remap[&w.id],
+            shape: vec![3],
+          }],
+        },
+      },
+      cx,
+      cx_weights: vec![(w.id, weights)],
+      cx_input_id: input.id,
+      cx_target_id: target.id,
+      cx_output_ids: vec![score.id, layer.id],
+      scaler: None,
+      threshold: None,
+    };
+    test_trained_into_snark(trained_model, vec![1.0, 2.0, 3.0])
+  }
+
+  #[test]
+  pub fn test_layer_norm_into_snark() -> Result<(), String> {
+    tracing::info!("layer norm: a Sqrt, and the Recip of a Sqrt lowered to rsqrt");
+    let mut cx = Graph::new();
+    let input = cx.tensor::<R1<4>>();
+    let w = cx.tensor::<R1<4>>();
+    let centered = input - input.mean_reduce::<_, Axis<0>>().expand::<R1<4>, _>();
+    let std = ((centered * centered).mean_reduce::<_, Axis<0>>() + 1e-5).sqrt();
+    let normed = (centered * std.recip().expand::<R1<4>, _>() * w).retrieve();
+    let std = std.retrieve();
+    let (cx_og, remap) = copy_graph_roughly(&cx);
+    let target = cx.tensor::<R1<1>>(); // unused, but evaluate expects it
+    let weights = vec![1.0, 0.5, -2.0, 1.0];
+    let trained_model = TrainedGraph {
+      graph: GraphForSnark {
+        graph: cx_og,
+        input_id: remap[&input.id],
+        weights: vec![(remap[&w.id], weights.clone())],
+        outputs: vec![remap[&normed.id], remap[&std.id]],
+        params: ParamRegistry {
+          params: vec![Param {
+            name: "w".to_string(),
+            id: remap[&w.id],
+            shape: vec![4],
+          }],
+        },
+      },
+      cx,
+      cx_weights: vec![(w.id, weights)],
+      cx_input_id: input.id,
+      cx_target_id: target.id,
+      cx_output_ids: vec![normed.id, std.id],
+      scaler: None,
+      threshold: None,
+    };
+    test_trained_into_snark(trained_model, vec![1.0, 2.0, 4.0, -1.0])
+  }
+
+  #[test]
+  pub fn test_snark_rejects_lookups() {
+    let trained = crate::model::fixed_weights::run_model();
+    assert_eq!(check_snark_supported(&trained.graph), vec![]);
+
+    let snark_graph = |unop: fn(GraphTensor<R1<3>>) -> GraphTensor<R1<3>>| {
+      let mut cx = Graph::new();
+      let input = cx.tensor::<R1<3>>();
+      let w = cx.tensor::<R1<3>>();
+      let root = unop(input * w).retrieve();
+      let (graph, remap) = copy_graph_roughly(&cx);
+      let g = GraphForSnark {
+        graph,
+        input_id: remap[&input.id],
+        weights: vec![(remap[&w.id], vec![1.0; 3])],
+        outputs: vec![remap[&root.id]],
+        params: ParamRegistry::default(),
+      };
+      (g, remap[&root.id])
+    };
+    let (g, _) = snark_graph(|x| x.sqrt());
+    assert_eq!(check_snark_supported(&g), vec![]);
+    let (g, root) = snark_graph(|x| x.sin());
+    assert_eq!(check_snark_supported(&g), vec![(root, "sin")]);
+  }
+
+  #[test]
+  pub fn test_public_weights_mode() {
+    let trained = crate::model::fixed_weights::run_model();
+    let input = vec![1.0, 2.0, 3.0];
+    let prove = |mode: &WeightsMode| {
+      let mut snark = compile_with(&trained, mode);
+      let (pk, vk) = snark.make_keys().unwrap();
+      snark.set_input(input.clone());
+      let proof = snark.make_proof(&pk).unwrap();
+      let public_inputs = snark.recorded_public_inputs.clone();
+      assert_eq!(
+        Groth16::<Bls12_381>::verify(&vk, &public_inputs, &proof),
+        Ok(true)
+      );
+      (public_inputs.len(), snark.get_evaluation_results())
+    };
+    let (n_public, results) = prove(&WeightsMode::PublicInputs);
+    let (n_baked, baked_results) = prove(&WeightsMode::Constants);
+    let weight_elements: usize = trained.graph.weights.iter().map(|(_, w)| w.len()).sum();
+    assert_eq!(n_baked, n_public - weight_elements);
+    assert_eq!(baked_results, results);
+
+    let first = &trained.graph.params.params[0];
+    let (n_some, _) = prove(&WeightsMode::ConstantsOf(vec![first.name.clone()]));
+    assert_eq!(
+      n_some,
+      n_public - trained.graph.weight(&first.name).unwrap().len()
+    );
+    assert!(WeightsMode::ConstantsOf(vec!["nope".to_string()])
+      .baked(&trained.graph.params)
+      .is_err());
+  }
+
   #[test]
   pub fn test_trained_into_snark_fixed_6() -> Result<(), String> {
     tracing::info!("linear layer into ReLU, fixed weights, 3 inputs");
@@ -0,0 +1,232 @@
+//!
+//! The activation functions of [super::ModelSpec] and how each one is proven, to trade accuracy for circuit cost.
+//!
+//! Every activation is built from the primitive tensor ops in the shape the scalarization recognizes
+//! (see [crate::scalar::ScalarGraph::fuse_relus] and [crate::scalar::ScalarGraph::fuse_lookups]),
+//! so it becomes the scalar ops of its [ActivationStrategy], which the backends and the quantization handle.
+//!
+
+use std::f32::consts::LN_2;
+
+use luminal::{
+  graph::Graph,
+  op::{Add, Constant, Exp2, LessThan, MaxReduce, Mul, Operator, Recip},
+  prelude::{NodeIndex, ShapeTracker},
+  shape::Expression,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::scalar::LookupKind;
+
+use super::spec::contiguous;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "function", rename_all = "snake_case")]
+pub enum Activation {
+  Relu,
+  /// `x` for positive `x`, `alpha * x` otherwise.
+  LeakyRelu {
+    alpha: f32,
+  },
+  Sigmoid,
+  Tanh,
+  /// `x * x`, a cheap nonlinearity for the snark, e.g. in CryptoNets.
+  Squared,
+}
+
+/// How an activation is computed in the scalar graph, from the cheapest in a snark.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ActivationStrategy {
+  /// Field multiplications only, exact in the snark. The quantized product is rescaled like any other.
+  Arithmetic,
+  /// A comparison with zero ([crate::scalar::ReluOp] or LessThan) and multiplications. The comparison is a range check
+  /// of the value, [crate::quant::QuantConfig::value_bits] constraints, and exact under quantization.
+  Comparison,
+  /// A single [crate::scalar::LookupOp] on the table of the function, the input clamped to the domain of the table
+  /// and the result quantized (see [crate::scalar::LookupTable::quantized]). One lookup in the lookup-capable backends,
+  /// unsupported by the R1CS one.
+  Lookup(LookupKind),
+}
+
+impl Activation {
+  pub fn strategy(&self) -> ActivationStrategy {
+    match self {
+      Activation::Relu | Activation::LeakyRelu { .. } => ActivationStrategy::Comparison,
+      Activation::Sigmoid => ActivationStrategy::Lookup(LookupKind::Sigmoid),
+      Activation::Tanh => ActivationStrategy::Lookup(LookupKind::Tanh),
+      Activation::Squared => ActivationStrategy::Arithmetic,
+    }
+  }
+
+  pub fn eval(&self, x: f32) -> f32 {
+    match self {
+      Activation::Relu => x.max(0.0),
+      Activation::LeakyRelu { alpha } => {
+        if x > 0.0 {
+          x
+        } else {
+          alpha * x
+        }
+      }
+      Activation::Sigmoid => LookupKind::Sigmoid.eval(x),
+      Activation::Tanh => LookupKind::Tanh.eval(x),
+      Activation::Squared => x * x,
+    }
+  }
+
+  /// Applies the activation to the vector `x` of `n` elements (contiguous) in the tensor graph.
+  pub(super) fn apply(&self, cx: &mut Graph, x: NodeIndex, n: usize) -> NodeIndex {
+    let mut b = Builder { cx, n };
+    match self {
+      Activation::Relu => {
+        // max over x and a padded zero: a single Max with 0, fused into a ReluOp
+        let mut sh = contiguous(&[n, 1]);
+        sh.pad(&[
+          (Expression::from(0), Expression::from(0)),
+          (Expression::from(0), Expression::from(1)),
+        ]);
+        b.cx.add_op(MaxReduce(1)).input(x, 0, sh).finish()
+      }
+      Activation::LeakyRelu { alpha } => {
+        // x * (alpha + (1 - alpha) * (0 < x))
+        let zero = b.constant(0.0);
+        let positive = b.binary(LessThan {}, zero, x);
+        let slope = b.with_constant(Mul {}, positive, 1.0 - alpha);
+        let slope = b.with_constant(Add {}, slope, *alpha);
+        b.binary(Mul {}, x, slope)
+      }
+      Activation::Sigmoid => b.sigmoid(x),
+      Activation::Tanh => {
+        // 2 * sigmoid(2x) - 1
+        let doubled = b.with_constant(Mul {}, x, 2.0);
+        let s = b.sigmoid(doubled);
+        let s = b.with_constant(Mul {}, s, 2.0);
+        b.with_constant(Add {}, s, -1.0)
+      }
+      Activation::Squared => b.binary(Mul {}, x, x),
+    }
+  }
+}
+
+/// Elementwise ops on vectors of `n` elements.
+struct Builder<'a> {
+  cx: &'a mut Graph,
+  n: usize,
+}
+
+impl Builder<'_> {
+  fn shape(&self, x: NodeIndex) -> ShapeTracker {
+    // constants are scalars, broadcast
+    if self.cx.check_node_type::<Constant>(x) {
+      let mut sh = contiguous(&[]);
+      sh.expand(0, self.n);
+      sh
+    } else {
+      contiguous(&[self.n])
+    }
+  }
+
+  fn constant(&mut self, v: f32) -> NodeIndex {
+    self.cx.constant(v).id
+  }
+
+  fn unary<T: Operator + 'static>(&mut self, op: T, x: NodeIndex) -> NodeIndex {
+    let sh = self.shape(x);
+    self.cx.add_op(op).input(x, 0, sh).finish()
+  }
+
+  fn binary<T: Operator + 'static>(&mut self, op: T, a: NodeIndex, b: NodeIndex) -> NodeIndex {
+    let (a_sh, b_sh) = (self.shape(a), self.shape(b));
+    self
+      .cx
+      .add_op(op)
+      .input(a, 0, a_sh)
+      .input(b, 0, b_sh)
+      .finish()
+  }
+
+  fn with_constant<T: Operator + 'static>(&mut self, op: T, x: NodeIndex, v: f32) -> NodeIndex {
+    let c = self.constant(v);
+    self.binary(op, x, c)
+  }
+
+  /// `recip(1 + exp2(x * -1/ln2))`, luminal's sigmoid.
+  fn sigmoid(&mut self, x: NodeIndex) -> NodeIndex {
+    let scaled = self.with_constant(Mul {}, x, -1.0 / LN_2);
+    let e = self.unary(Exp2 {}, scaled);
+    let denominator = self.with_constant(Add {}, e, 1.0);
+    self.unary(Recip {}, denominator)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::collections::HashMap;
+
+  use luminal::prelude::NodeIndex;
+  use rand::{rngs::StdRng, Rng, SeedableRng};
+
+  use super::{Activation, ActivationStrategy};
+  use crate::{
+    model::{safetensors::NamedTensor, GraphForSnark, LayerSpec, ModelSpec},
+    quant::{NodeScales, QuantConfig, QuantizedGraph},
+    scalar::{evaluate_tensor_graph, scalar, verify_scalarization, LookupOp, ReluOp},
+  };
+
+  #[test]
+  fn test_activations_scalarize_to_their_strategy() {
+    let mut rng = StdRng::seed_from_u64(0);
+    let activations = [
+      Activation::Relu,
+      Activation::LeakyRelu { alpha: 0.1 },
+      Activation::Sigmoid,
+      Activation::Tanh,
+      Activation::Squared,
+    ];
+    for activation in activations {
+      let spec = ModelSpec {
+        inputs: 3,
+        layers: vec![
+          LayerSpec::Linear { outputs: 4 },
+          LayerSpec::Activation(activation),
+        ],
+      };
+      let w: Vec<f32> = (0..12).map(|_| rng.gen_range(-1.0..1.0)).collect();
+      let weights = vec![NamedTensor {
+        name: "layer0.weight".to_string(),
+        shape: vec![3, 4],
+        data: w.clone(),
+      }];
+      let g = GraphForSnark::from_spec_and_weights(&spec, weights).unwrap();
+      let input = vec![0.5, -1.0, 2.0];
+      let mut inputs: HashMap<NodeIndex, Vec<f32>> = g.weights.iter().cloned().collect();
+      inputs.insert(g.input_id, input.clone());
+
+      let result = &evaluate_tensor_graph(&g.graph, &inputs)[&g.outputs[0]];
+      for (j, y) in result.iter().enumerate() {
+        let z: f32 = (0..3).map(|i| input[i] * w[i * 4 + j]).sum();
+        assert!((y - activation.eval(z)).abs() < 1e-4, "{:?}", activation);
+      }
+
+      let (sc, _) = scalar(&g.graph);
+      assert_eq!(verify_scalarization(&g.graph, &sc, &inputs), Ok(()));
+      let count = |f: &dyn Fn(NodeIndex) -> bool| sc.graph.node_indices().filter(|x| f(*x)).count();
+      match activation.strategy() {
+        ActivationStrategy::Lookup(kind) => {
+          assert_eq!(count(&|x| sc.graph.check_node_type::<LookupOp>(x)), 4);
+          assert!(sc.tables.tables.iter().any(|t| t.kind == kind));
+          // the quantized tables are close to the floats
+          let quantized = QuantizedGraph::new(sc, QuantConfig::default(), NodeScales::default());
+          let ints = quantized.dequantize_outputs(&quantized.evaluate_int(&inputs));
+          for (a, b) in ints[&g.outputs[0]].iter().zip(result) {
+            assert!((a - b).abs() < 1e-3, "{:?}", activation);
+          }
+        }
+        ActivationStrategy::Comparison if activation == Activation::Relu => {
+          assert_eq!(count(&|x| sc.graph.check_node_type::<ReluOp>(x)), 4);
+        }
+        _ => assert_eq!(count(&|x| sc.graph.check_node_type::<LookupOp>(x)), 0),
+      }
+    }
+  }
+}
@@ -0,0 +1,201 @@
+//!
+//! Dividing a dataset into training and test samples, and making more training samples with noise.
+//!
+//! [split] gives the samples of each [Fold] by their index in the dataset, either one holdout split or the `k` folds
+//! of a cross-validation, where every sample is tested once by the model trained on the other folds. Stratified splits
+//! keep the proportions of the labels in every part, so a fold of an unbalanced dataset isn't left with a single
+//! class. The samples are shuffled first, unlike [super::split_dataset] taking the dataset in its order.
+//!
+//! [augment] adds copies of the samples with Gaussian noise on the features. Augment only the training part, noisy
+//! copies of a tested sample would be scored as if unseen. See [crate::accuracy::cross_validate] for the accuracy of a
+//! model over the folds.
+//!
+
+use std::collections::BTreeMap;
+
+use rand::{rngs::StdRng, seq::SliceRandom, Rng};
+
+use super::{InputsVec, OutputsVec};
+
+/// How [split] divides the samples.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Split {
+  /// One fold, training on the first `ratio` of the samples and testing on the rest.
+  Holdout { ratio: f32 },
+  /// `k` folds of (nearly) equal test sets, every sample in the test set of one of them.
+  KFold { k: usize },
+}
+
+/// The samples to train on and the ones to test on, by their index in the dataset, in random order.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Fold {
+  pub train: Vec<usize>,
+  pub test: Vec<usize>,
+}
+
+impl Fold {
+  /// The training and the test samples of the dataset.
+  pub fn select(
+    &self,
+    data: &(InputsVec, OutputsVec),
+  ) -> ((InputsVec, OutputsVec), (InputsVec, OutputsVec)) {
+    let pick = |indices: &[usize]| {
+      (
+        indices.iter().map(|i| data.0[*i]).collect(),
+        indices.iter().map(|i| data.1[*i]).collect(),
+      )
+    };
+    (pick(&self.train), pick(&self.test))
+  }
+}
+
+/// Divides the samples with the `labels` into folds, see the module docs. Stratified splits divide every label's
+/// samples on their own.
+pub fn split(labels: &[f32], how: Split, stratified: bool, rng: &mut StdRng) -> Vec<Fold> {
+  let mut groups: BTreeMap<u32, Vec<usize>> = BTreeMap::new();
+  for (i, label) in labels.iter().enumerate() {
+    let key = if stratified { label.to_bits() } else { 0 };
+    groups.entry(key).or_default().push(i);
+  }
+  for group in groups.values_mut() {
+    group.shuffle(rng);
+  }
+
+  let mut folds = match how {
+    Split::Holdout { ratio } => {
+      let mut fold = Fold {
+        train: vec![],
+        test: vec![],
+      };
+      for group in groups.values() {
+        let k = (group.len() as f32 * ratio) as usize;
+        fold.train.extend(&group[..k]);
+        fold.test.extend(&group[k..]);
+      }
+      vec![fold]
+    }
+    Split::KFold { k } => {
+      assert!(
+        (2..=labels.len()).contains(&k),
+        "{} folds of {} samples",
+        k,
+        labels.len()
+      );
+      // dealt out one by one, the groups one after another: the sizes differ by at most one, in every label too
+      let mut tests = vec![vec![]; k];
+      for (n, i) in groups.values().flatten().enumerate() {
+        tests[n % k].push(*i);
+      }
+      (0..k)
+        .map(|f| Fold {
+          train: (0..k)
+            .filter(|g| *g != f)
+            .flat_map(|g| tests[g].iter().copied())
+            .collect(),
+          test: tests[f].clone(),
+        })
+        .collect()
+    }
+  };
+  // the groups one after another would be runs of a single label
+  for fold in folds.iter_mut() {
+    fold.train.shuffle(rng);
+    fold.test.shuffle(rng);
+  }
+  folds
+}
+
+/// Gaussian noise added to copies of the samples, see [augment].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Augmentation {
+  /// Noisy copies of every sample.
+  pub copies: usize,
+  /// Standard deviation of the noise, in the units of the features (before any [super::Scaler]).
+  pub sigma: f32,
+}
+
+/// The samples, each followed by its noisy copies with the same label.
+pub fn augment(
+  data: &(InputsVec, OutputsVec),
+  augmentation: Augmentation,
+  rng: &mut StdRng,
+) -> (InputsVec, OutputsVec) {
+  let (mut x, mut y) = (vec![], vec![]);
+  for (sample, label) in data.0.iter().zip(data.1.iter()) {
+    x.push(*sample);
+    y.push(*label);
+    for _ in 0..augmentation.copies {
+      x.push(sample.map(|v| v + augmentation.sigma * standard_normal(rng)));
+      y.push(*label);
+    }
+  }
+  (x, y)
+}
+
+/// A sample of the standard normal distribution, by the Box-Muller transform.
+fn standard_normal(rng: &mut StdRng) -> f32 {
+  let u: f32 = rng.gen_range(f32::EPSILON..1.0);
+  let v: f32 = rng.gen();
+  (-2.0 * u.ln()).sqrt() * (2.0 * std::f32::consts::PI * v).cos()
+}
+
+#[cfg(test)]
+mod tests {
+  use rand::{rngs::StdRng, SeedableRng};
+
+  use super::{augment, split, Augmentation, Split};
+
+  #[test]
+  fn test_split() {
+    // a quarter of ones
+    let labels: Vec<f32> = (0..40).map(|i| (i % 4 == 0) as u8 as f32).collect();
+    let mut rng = StdRng::seed_from_u64(0);
+    let folds = split(&labels, Split::KFold { k: 5 }, true, &mut rng);
+    assert_eq!(folds.len(), 5);
+    let mut tested: Vec<usize> = folds.iter().flat_map(|f| f.test.clone()).collect();
+    tested.sort();
+    assert_eq!(tested, (0..40).collect::<Vec<_>>());
+    for fold in folds.iter() {
+      assert_eq!((fold.train.len(), fold.test.len()), (32, 8));
+      assert!(fold.train.iter().all(|i| !fold.test.contains(i)));
+      let ones = fold.test.iter().filter(|i| labels[**i] == 1.0).count();
+      assert_eq!(ones, 2);
+    }
+
+    let folds = split(&labels, Split::Holdout { ratio: 0.8 }, true, &mut rng);
+    assert_eq!(folds.len(), 1);
+    assert_eq!((folds[0].train.len(), folds[0].test.len()), (32, 8));
+    let ones = folds[0].test.iter().filter(|i| labels[**i] == 1.0).count();
+    assert_eq!(ones, 2);
+    // the same seed, the same folds
+    let again = |stratified| {
+      split(
+        &labels,
+        Split::KFold { k: 3 },
+        stratified,
+        &mut StdRng::seed_from_u64(1),
+      )
+    };
+    assert_eq!(again(false), again(false));
+    assert_ne!(again(false), again(true));
+  }
+
+  #[test]
+  fn test_augment() {
+    let data = (vec![[0.0; 9], [1.0; 9]], vec![0.0, 1.0]);
+    let augmentation = Augmentation {
+      copies: 500,
+      sigma: 0.5,
+    };
+    let (x, y) = augment(&data, augmentation, &mut StdRng::seed_from_u64(0));
+    assert_eq!((x.len(), y.len()), (1002, 1002));
+    assert_eq!((x[0], x[501]), (data.0[0], data.0[1]));
+    assert!(y[..501].iter().all(|l| *l == 0.0) && y[501..].iter().all(|l| *l == 1.0));
+    let noise: Vec<f32> = x[1..501].iter().flatten().copied().collect();
+    let n = noise.len() as f32;
+    let mean = noise.iter().sum::<f32>() / n;
+    let std = (noise.iter().map(|v| (v - mean).powi(2)).sum::<f32>() / n).sqrt();
+    assert!(mean.abs() < 0.05, "{}", mean);
+    assert!((std - 0.5).abs() < 0.05, "{}", std);
+  }
+}
@@ -0,0 +1,65 @@
+//!
+//! Where the training graph runs: luminal's CUDA or Metal compiler with the `cuda`/`metal` features, the CPU otherwise.
+//!
+//! Only the training graph is compiled. The graph for the snark is copied out before that and stays un-optimized,
+//! the compilers fuse and replace ops with kernels we know nothing about.
+//!
+
+use luminal::prelude::*;
+
+#[cfg(all(feature = "cuda", feature = "metal"))]
+compile_error!("Features `cuda` and `metal` are mutually exclusive");
+
+/// Whether training runs on a GPU. The trained graph then can't be used for evaluation as is,
+/// the models rebuild the graph on the CPU with the trained weights.
+pub const ON_GPU: bool = cfg!(any(feature = "cuda", feature = "metal"));
+
+/// Compiles the training graph for the device, keeping the given ids up to date.
+/// On the CPU the graph runs as is.
+pub fn compile_for_device<T: ToIdsMut>(cx: &mut Graph, remap: T) {
+  #[cfg(feature = "cuda")]
+  cx.compile(
+    (
+      GenericCompiler::default(),
+      luminal_cuda::CudaCompiler::<f32>::default(),
+    ),
+    remap,
+  );
+  #[cfg(feature = "metal")]
+  cx.compile(
+    (
+      GenericCompiler::default(),
+      luminal_metal::MetalCompiler::<f32>::default(),
+    ),
+    remap,
+  );
+  #[cfg(not(any(feature = "cuda", feature = "metal")))]
+  let _ = (cx, remap);
+}
+
+/// Copies the (kept) tensor to the host.
+pub fn tensor_data(cx: &Graph, id: NodeIndex) -> Vec<f32> {
+  let tensor = cx
+    .tensors
+    .get(&(id, 0 /* assuming single output */))
+    .unwrap_or_else(|| panic!("Tensor {:?} was not kept", id));
+  #[cfg(feature = "cuda")]
+  {
+    let slice = &tensor
+      .downcast_ref::<luminal_cuda::CudaData<f32>>()
+      .unwrap()
+      .0;
+    slice.device().dtoh_sync_copy(slice).unwrap()
+  }
+  #[cfg(feature = "metal")]
+  {
+    let buffer = &tensor
+      .downcast_ref::<luminal_metal::MetalBuffer>()
+      .unwrap()
+      .0;
+    let len = buffer.length() as usize / std::mem::size_of::<f32>();
+    unsafe { std::slice::from_raw_parts(buffer.contents() as *const f32, len) }.to_vec()
+  }
+  #[cfg(not(any(feature = "cuda", feature = "metal")))]
+  crate::dtype::tensor_f32(tensor).unwrap()
+}
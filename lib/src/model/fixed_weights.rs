@@ -1,10 +1,12 @@
 
+use std::cell::RefCell;
+
 use luminal::prelude::*;
 use luminal_nn::{Linear, ReLU};
 use petgraph::Direction::Outgoing;
 
 use crate::{
-  model::GraphForSnark,
+  model::{GraphForSnark, ParamRegistry},
   scalar::copy_graph_roughly,
 };
 
@@ -12,6 +14,14 @@ use super::TrainedGraph;
 
 pub type Model = (Linear<3, 2>, ReLU, Linear<2, 1>);
 
+/// Names of the weights of the model, see [ParamRegistry].
+pub fn param_registry(model: &Model) -> ParamRegistry {
+  let mut registry = ParamRegistry::default();
+  registry.register_tensor("layer0.weight", model.0.weight);
+  registry.register_tensor("layer2.weight", model.2.weight);
+  registry
+}
+
 // from scalar
 fn get_own_size(x: NodeIndex, gg: &Graph) -> usize {
   let get_own_shape = |x, gg: &Graph| {
@@ -72,12 +82,16 @@ pub fn run_model() -> TrainedGraph {
       graph: cx_og,
       weights: weights_vec,
       input_id,
+      outputs: vec![remap[&output.id]],
+      params: param_registry(&model).remap(&remap),
     },
-    cx: cx,
+    cx: RefCell::new(cx),
     cx_weights: cx_weights_vec,
-    cx_output_id: output.id,
+    cx_output_ids: vec![output.id],
     cx_input_id: input.id,
     cx_target_id: target.id,
+    scaler: None,
+    threshold: None,
     // cx_target_id: output.id, // <- whatever
   }
 }
@@ -0,0 +1,137 @@
+//!
+//! [GraphForSnark] of a luminal graph built by hand, outside of the models of this crate.
+//!
+//! The graph is copied without whatever the compilers added (see [crate::scalar::try_copy_graph_roughly]), the
+//! weights are taken from the tensors of the graph: set them (or execute the graph keeping them, see
+//! [Graph::keep_tensors]) before the conversion. The outputs are the retrieved tensors.
+//!
+
+use std::error::Error;
+
+use itertools::Itertools;
+use luminal::{graph::Graph, op::Function, prelude::NodeIndex};
+
+use super::{GraphForSnark, ParamRegistry};
+use crate::{
+  dtype::tensor_f32,
+  scalar::{get_own_size, try_copy_graph_roughly},
+};
+
+impl GraphForSnark {
+  /// The snark graph of an arbitrary luminal graph with the given input and weights, see the module docs.
+  /// The weights are named `param{i}` in the order of `params`.
+  pub fn from_graph(
+    graph: &Graph,
+    input: NodeIndex,
+    params: &[NodeIndex],
+  ) -> Result<Self, Box<dyn Error>> {
+    for x in std::iter::once(&input).chain(params) {
+      if graph.node_weight(*x).is_none() || !graph.check_node_type::<Function>(*x) {
+        return Err(format!("{:?} is not a tensor loaded into the graph", x).into());
+      }
+    }
+    if params.contains(&input) || !params.iter().all_unique() {
+      return Err("The input and the weights have to be distinct tensors".into());
+    }
+    let weights = params
+      .iter()
+      .map(|x| {
+        let data = graph
+          .tensors
+          .get(&(*x, 0))
+          .and_then(tensor_f32)
+          .ok_or_else(|| format!("Weight {:?} has no data, set it in the graph first", x))?;
+        let size = get_own_size(*x, graph);
+        if data.len() != size {
+          return Err(format!(
+            "Weight {:?} has {} values, the graph uses {}",
+            x,
+            data.len(),
+            size
+          ));
+        }
+        Ok((*x, data))
+      })
+      .collect::<Result<Vec<_>, _>>()?;
+    let outputs = graph
+      .to_retrieve
+      .keys()
+      .filter(|x| **x != input && !params.contains(*x))
+      .sorted()
+      .copied()
+      .collect::<Vec<_>>();
+    if outputs.is_empty() {
+      return Err("The graph retrieves no outputs".into());
+    }
+
+    let (g, remap) = try_copy_graph_roughly(graph)?;
+    let mut registry = ParamRegistry::default();
+    for (i, (x, data)) in weights.iter().enumerate() {
+      registry.register(&format!("param{}", i), *x, vec![data.len()]);
+    }
+    Ok(GraphForSnark {
+      graph: g,
+      input_id: remap[&input],
+      weights: weights
+        .into_iter()
+        .map(|(x, data)| (remap[&x], data))
+        .collect(),
+      outputs: outputs.iter().map(|x| remap[x]).collect(),
+      params: registry.remap(&remap),
+    })
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::collections::HashMap;
+
+  use luminal::{
+    prelude::{Graph, Tensor},
+    shape::{R1, R2},
+  };
+
+  use crate::{model::GraphForSnark, scalar::evaluate_tensor_graph};
+
+  #[test]
+  fn test_from_graph() {
+    let mut cx = Graph::new();
+    let input = cx.tensor::<R1<2>>();
+    let w = cx.tensor::<R2<2, 3>>();
+    let b = cx.tensor::<R1<3>>();
+    (input.matmul(w) + b).relu().retrieve();
+    let w_data = vec![1.0, -1.0, 0.5, 2.0, 0.0, -3.0];
+    cx.tensors.insert((w.id, 0), Tensor::new(w_data.clone()));
+    cx.tensors
+      .insert((b.id, 0), Tensor::new(vec![0.0, 1.0, 0.0]));
+
+    let g = GraphForSnark::from_graph(&cx, input.id, &[w.id, b.id]).unwrap();
+    assert_eq!(g.outputs.len(), 1);
+    assert_eq!(g.weight("param0"), Some(w_data.as_slice()));
+    assert_eq!(g.params.get("param1").unwrap().shape, vec![3]);
+
+    let mut inputs: HashMap<_, _> = g.weights.iter().cloned().collect();
+    inputs.insert(g.input_id, vec![1.0, 2.0]);
+    let results = evaluate_tensor_graph(&g.graph, &inputs);
+    // [1 + 4, -1 + 0 + 1, 0.5 - 6]
+    assert_eq!(results[&g.outputs[0]], vec![5.0, 0.0, 0.0]);
+  }
+
+  #[test]
+  fn test_from_graph_errors() {
+    let mut cx = Graph::new();
+    let input = cx.tensor::<R1<2>>();
+    let w = cx.tensor::<R1<2>>();
+    let out = (input * w).retrieve();
+    let err = |cx: &Graph, params: &[_]| {
+      GraphForSnark::from_graph(cx, input.id, params)
+        .unwrap_err()
+        .to_string()
+    };
+    assert!(err(&cx, &[w.id]).contains("has no data"));
+    assert!(err(&cx, &[out.id]).contains("is not a tensor"));
+    assert!(err(&cx, &[input.id]).contains("distinct"));
+    cx.tensors.insert((w.id, 0), Tensor::new(vec![1.0; 3]));
+    assert!(err(&cx, &[w.id]).contains("the graph uses 2"));
+  }
+}
@@ -0,0 +1,82 @@
+//!
+//! Output heads of the binary classifier.
+//!
+
+use std::cmp::Ordering;
+
+use itertools::Itertools;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum OutputHead {
+  /// The raw score, thresholding it is up to the user.
+  #[default]
+  Score,
+  /// Trained as `sigmoid(score)`, evaluated (and proven) as the decision `threshold < sigmoid(score)`, 1.0 or 0.0.
+  /// The threshold is calibrated on the validation data.
+  ///
+  /// Sigmoid is monotonic, so the graph compares the score against `logit(threshold)` with a single LessThan
+  /// and the proof attests to the decision, without the sigmoid.
+  Decision,
+}
+
+/// Probabilities are clamped to `[LOGIT_EPS, 1 - LOGIT_EPS]` before the [logit], so that a threshold of 0 or 1 still
+/// makes a finite cut point.
+pub const LOGIT_EPS: f32 = 1e-6;
+
+pub fn logit(p: f32) -> f32 {
+  let p = p.clamp(LOGIT_EPS, 1.0 - LOGIT_EPS);
+  (p / (1.0 - p)).ln()
+}
+
+/// The threshold on the probabilities maximizing the accuracy against the 0/1 labels.
+/// Candidates are the midpoints between consecutive distinct probabilities and ones below and above all of them,
+/// on ties the one closest to 0.5 wins.
+pub fn calibrate_threshold(probabilities: &[f32], labels: &[f32]) -> f32 {
+  assert_eq!(probabilities.len(), labels.len());
+  let accuracy = |t: f32| {
+    probabilities
+      .iter()
+      .zip(labels)
+      .filter(|(p, y)| (**p > t) == (**y > 0.5))
+      .count()
+  };
+  let sorted = probabilities
+    .iter()
+    .copied()
+    .sorted_by(|a, b| a.partial_cmp(b).unwrap_or(Ordering::Equal))
+    .dedup()
+    .collect_vec();
+  let (lo, hi) = match (sorted.first(), sorted.last()) {
+    (Some(lo), Some(hi)) => (lo / 2.0, (hi + 1.0) / 2.0),
+    _ => return 0.5,
+  };
+  sorted
+    .iter()
+    .tuple_windows()
+    .map(|(a, b)| (a + b) / 2.0)
+    .chain([0.5, lo, hi])
+    .max_by(|a, b| {
+      accuracy(*a)
+        .cmp(&accuracy(*b))
+        .then((b - 0.5).abs().total_cmp(&(a - 0.5).abs()))
+    })
+    .unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{calibrate_threshold, logit};
+
+  #[test]
+  fn test_calibrated_threshold_separates() {
+    let probabilities = [0.55, 0.6, 0.62, 0.7, 0.8, 0.9];
+    let labels = [0.0, 0.0, 0.0, 1.0, 1.0, 1.0];
+    let t = calibrate_threshold(&probabilities, &labels);
+    assert!((t - 0.66).abs() < 1e-5);
+    assert!(logit(0.5).abs() < 1e-6);
+    assert!(logit(0.0).is_finite() && logit(1.0).is_finite());
+    // all positive
+    assert_eq!(calibrate_threshold(&[0.3, 0.7], &[1.0, 1.0]), 0.15);
+  }
+}
@@ -1,13 +1,17 @@
-use std::iter::zip;
+use std::cell::RefCell;
 
 use luminal::prelude::*;
 use luminal_nn::{Linear, ReLU};
-use luminal_training::{mse_loss, sgd_on_graph, Autograd};
+use luminal_training::{mse_loss, Autograd};
+use rand::{rngs::StdRng, SeedableRng};
 use tracing::info;
 
 use crate::{
   model::{
-    normalize_data, split_dataset, ExponentialAverage, GraphForSnark, InputsVec, OutputsVec,
+    epoch_order,
+    optim::{check_finite, sgd_clipped},
+    seed_weights, split_dataset, ExponentialAverage, GraphForSnark, InputsVec, OutputsVec,
+    ParamRegistry, Scaler, ScalerKind,
   },
   scalar::copy_graph_roughly,
 };
@@ -16,6 +20,14 @@ use super::{TrainParams, TrainedGraph};
 
 pub type Model = (Linear<9, 2>, ReLU, Linear<2, 1>);
 
+/// Names of the weights of the model, see [ParamRegistry].
+pub fn param_registry(model: &Model) -> ParamRegistry {
+  let mut registry = ParamRegistry::default();
+  registry.register_tensor("layer0.weight", model.0.weight);
+  registry.register_tensor("layer2.weight", model.2.weight);
+  registry
+}
+
 pub fn run_model(train_params: TrainParams) -> TrainedGraph {
   let dataset: (InputsVec, OutputsVec) = train_params.data;
   let epochs = train_params.epochs;
@@ -24,6 +36,8 @@ pub fn run_model(train_params: TrainParams) -> TrainedGraph {
   let model = <Model>::initialize(&mut cx);
   let mut input = cx.tensor::<R1<9>>();
   let mut output = model.forward(input).retrieve();
+  let mut rng = StdRng::seed_from_u64(train_params.seed);
+  seed_weights(&mut cx, &params(&model), &mut rng);
 
   // todo: remove x=n
   // cx.display();
@@ -40,10 +54,9 @@ pub fn run_model(train_params: TrainParams) -> TrainedGraph {
   let weights = params(&model);
 
   let grads = cx.compile(Autograd::new(&weights, loss), ());
-  let (new_weights, lr) = sgd_on_graph(&mut cx, &weights, &grads);
+  let (new_weights, lr) = sgd_clipped(&mut cx, &weights, &grads, train_params.clip_grad_norm);
   cx.keep_tensors(&new_weights);
   cx.keep_tensors(&weights);
-  lr.set(5e-3);
 
   let (mut loss_avg, mut acc_avg) = (ExponentialAverage::new(1.0), ExponentialAverage::new(0.0));
   let start = std::time::Instant::now();
@@ -51,15 +64,19 @@ pub fn run_model(train_params: TrainParams) -> TrainedGraph {
 
   let (X, Y) = dataset;
   let (X_train, _x_test, y_train, _y_test) = split_dataset(X, Y, 0.8);
-  let X_train = normalize_data(X_train);
+  let scaler = Scaler::fit(ScalerKind::MinMax, &X_train);
+  let X_train = scaler.transform(&X_train);
   let mut iter = 0;
-  for _ in 0..epochs {
-    for (x, y) in zip(X_train.iter(), y_train.iter()) {
+  for epoch in 0..epochs {
+    lr.set(train_params.lr.lr(epoch, epochs));
+    for i in epoch_order(X_train.len(), &mut rng) {
+      let (x, y) = (&X_train[i], &y_train[i]);
       let answer = [y.to_owned()];
       input.set(x.to_owned());
       target.set(answer);
 
       cx.execute();
+      check_finite("the loss", &loss.data(), iter).unwrap_or_else(|e| panic!("{}", e));
       transfer_data_same_graph(&new_weights, &weights, &mut cx);
       loss_avg.update(loss.data()[0]);
       loss.drop();
@@ -81,7 +98,10 @@ pub fn run_model(train_params: TrainParams) -> TrainedGraph {
       iter += 1;
     }
   }
-  println!("Finished in {iter} iterations");
+  println!(
+    "Finished in {iter} iterations, learning rate {}",
+    train_params.lr
+  );
   println!(
     "Took {:.2}s, {:.2}µs / iter",
     start.elapsed().as_secs_f32(),
@@ -104,6 +124,9 @@ pub fn run_model(train_params: TrainParams) -> TrainedGraph {
       )
     })
     .collect();
+  for (w, data) in cx_weights_vec.iter() {
+    check_finite(&format!("weight {:?}", w), data, iter).unwrap_or_else(|e| panic!("{}", e));
+  }
   let weights_vec = cx_weights_vec
     .iter()
     .map(|(a, b)| (remap[&a], b.clone()))
@@ -113,11 +136,15 @@ pub fn run_model(train_params: TrainParams) -> TrainedGraph {
       graph: cx_og,
       weights: weights_vec,
       input_id,
+      outputs: vec![remap[&output.id]],
+      params: param_registry(&model).remap(&remap),
     },
-    cx: cx,
+    cx: RefCell::new(cx),
     cx_weights: cx_weights_vec,
-    cx_output_id: output.id,
+    cx_output_ids: vec![output.id],
     cx_input_id: input.id,
     cx_target_id: target.id,
+    scaler: Some(scaler),
+    threshold: None,
   }
 }
@@ -1,17 +1,32 @@
 use std::{
+  cell::RefCell,
   collections::HashMap,
   convert::TryInto,
+  error::Error,
+  fmt,
   fs::{self},
-  iter::zip,
   path::Path,
 };
 
 use luminal::prelude::*;
 use luminal_nn::{Linear, ReLU};
-use luminal_training::{mse_loss, sgd_on_graph, Autograd};
+use luminal_training::Autograd;
+use rand::{rngs::StdRng, seq::SliceRandom, Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
 use tracing::info;
 
-use crate::scalar::copy_graph_roughly;
+use crate::{
+  dtype::tensor_f32,
+  model::{
+    device::{compile_for_device, tensor_data, ON_GPU},
+    head::{calibrate_threshold, logit, OutputHead},
+    optim::{check_finite, sgd_clipped},
+    qat::FakeQuant,
+    ParamRegistry, Scaler, ScalerKind,
+  },
+  quant::QuantConfig,
+  scalar::{copy_graph_roughly, get_own_size},
+};
 
 // const FILE_PATH: &str = "data/rp.data";
 
@@ -20,6 +35,15 @@ pub type OutputsVec = Vec<f32>;
 
 pub type Model = (Linear<9, 16>, ReLU, Linear<16, 16>, ReLU, Linear<16, 1>);
 
+/// Names of the weights of the model, see [ParamRegistry].
+pub fn param_registry(model: &Model) -> ParamRegistry {
+  let mut registry = ParamRegistry::default();
+  registry.register_tensor("layer0.weight", model.0.weight);
+  registry.register_tensor("layer2.weight", model.2.weight);
+  registry.register_tensor("layer4.weight", model.4.weight);
+  registry
+}
+
 pub fn read_dataset(path: &Path) -> Result<(InputsVec, OutputsVec), std::io::Error> {
   let content: String = fs::read_to_string(path)?;
   Ok(parse_dataset(content))
@@ -61,28 +85,6 @@ pub fn split_dataset(
   (x_train, x_test, y_train, y_test)
 }
 
-pub fn normalize_data(x: InputsVec) -> InputsVec {
-  let mut mins: [f32; 9] = [11 as f32; 9];
-  let mut maxs: [f32; 9] = [-1 as f32; 9];
-
-  for a in x.iter() {
-    for i in 0..9 {
-      mins[i] = f32::min(mins[i], a[i]);
-      maxs[i] = f32::min(maxs[i], a[i]);
-    }
-  }
-
-  let mut xp: InputsVec = Vec::new();
-  for a in x.iter() {
-    let mut ap: [f32; 9] = [0 as f32; 9];
-    for i in 0..9 {
-      ap[i] = (a[i] - mins[i]) / (maxs[i] - mins[i]);
-    }
-    xp.push(ap);
-  }
-  xp
-}
-
 pub fn get_weights(graph: &Graph, model: &Model) -> HashMap<NodeIndex, Vec<f32>> {
   let weights_indices = params(&model);
   weights_indices
@@ -105,11 +107,151 @@ pub fn get_weights(graph: &Graph, model: &Model) -> HashMap<NodeIndex, Vec<f32>>
 pub struct TrainParams {
   pub data: (InputsVec, OutputsVec),
   pub epochs: usize,
-  // pub lr: f32,
+  /// Only the medium model has a choice of the head, the others output the score.
+  pub head: OutputHead,
+  /// Seeds the initialization of the weights and the order of the training samples: the same seed trains the same weights.
+  pub seed: u64,
+  /// Train on the weights and inputs quantized at this precision, see [super::qat]. Only the medium model does.
+  pub quantization: Option<QuantConfig>,
+  /// Weigh the loss of a sample by its class, for imbalanced datasets. Only the medium model does, the others train
+  /// on the plain mean squared error, as with `None`.
+  pub class_weights: Option<ClassWeights>,
+  /// The learning rate of every epoch.
+  pub lr: LrSchedule,
+  /// Scale the gradients of a step down to this norm if over it, see [super::optim].
+  pub clip_grad_norm: Option<f32>,
   // pub batch_size: u32,
   // pub model: Model,
 }
 
+/// No data, one epoch of the score head at the default learning rate, seed 0, nothing else. Set what differs with
+/// `..Default::default()`.
+impl Default for TrainParams {
+  fn default() -> Self {
+    TrainParams {
+      data: (vec![], vec![]),
+      epochs: 1,
+      head: OutputHead::Score,
+      seed: 0,
+      quantization: None,
+      class_weights: None,
+      lr: LrSchedule::default(),
+      clip_grad_norm: None,
+    }
+  }
+}
+
+/// The weights of the samples of the two classes in the loss, see [weighted_mse_loss].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ClassWeights {
+  /// Inversely to how often the class is among the training samples, so both classes weigh the same in total.
+  Balanced,
+  /// The weights of the samples labeled 0 and 1.
+  Fixed(f32, f32),
+}
+
+impl ClassWeights {
+  /// The weights of the labels 0 and 1, for the training samples with these labels.
+  pub fn weights(&self, labels: &[f32]) -> [f32; 2] {
+    match *self {
+      ClassWeights::Balanced => {
+        let ones = labels.iter().filter(|y| **y > 0.5).count();
+        let weight = |count: usize| match count {
+          0 => 1.0,
+          _ => labels.len() as f32 / (2 * count) as f32,
+        };
+        [weight(labels.len() - ones), weight(ones)]
+      }
+      ClassWeights::Fixed(zero, one) => [zero, one],
+    }
+  }
+}
+
+/// The mean squared error with the error of the sample multiplied by `weight`, the weight of its class. The weight is
+/// an input of the training graph only, the snark graph is copied before the loss is added.
+pub fn weighted_mse_loss(
+  prediction: GraphTensor<R1<1>>,
+  target: GraphTensor<R1<1>>,
+  weight: GraphTensor<R1<1>>,
+) -> GraphTensor<R0> {
+  let error = prediction - target;
+  (error * error * weight).mean_reduce::<_, Axis<0>>()
+}
+
+/// The learning rate of SGD over the epochs of the training.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LrSchedule {
+  Constant(f32),
+  /// `lr` multiplied by `gamma` every `every` epochs.
+  StepDecay {
+    lr: f32,
+    gamma: f32,
+    every: usize,
+  },
+  /// From `lr` in the first epoch down to `min_lr` in the last, along half a period of the cosine.
+  Cosine {
+    lr: f32,
+    min_lr: f32,
+  },
+}
+
+impl Default for LrSchedule {
+  fn default() -> Self {
+    LrSchedule::Constant(5e-3)
+  }
+}
+
+impl LrSchedule {
+  /// The learning rate of the epoch, counted from 0, out of `epochs`.
+  pub fn lr(&self, epoch: usize, epochs: usize) -> f32 {
+    match *self {
+      LrSchedule::Constant(lr) => lr,
+      LrSchedule::StepDecay { lr, gamma, every } => lr * gamma.powi((epoch / every.max(1)) as i32),
+      LrSchedule::Cosine { lr, min_lr } => {
+        let progress = match epochs {
+          0 | 1 => 0.0,
+          _ => epoch as f32 / (epochs - 1) as f32,
+        };
+        min_lr + (lr - min_lr) * (1.0 + (std::f32::consts::PI * progress).cos()) / 2.0
+      }
+    }
+  }
+}
+
+impl fmt::Display for LrSchedule {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      LrSchedule::Constant(lr) => write!(f, "constant {}", lr),
+      LrSchedule::StepDecay { lr, gamma, every } => {
+        write!(
+          f,
+          "step decay from {}, times {} every {} epochs",
+          lr, gamma, every
+        )
+      }
+      LrSchedule::Cosine { lr, min_lr } => write!(f, "cosine from {} to {}", lr, min_lr),
+    }
+  }
+}
+
+/// Re-initializes the weights from the seeded rng, uniformly in [-1, 1) as luminal's `Linear` does (from an unseeded one).
+/// Call it once the model is applied, the sizes of the weights are read from the graph.
+pub fn seed_weights(cx: &mut Graph, weights: &[NodeIndex], rng: &mut StdRng) {
+  for w in weights {
+    let data: Vec<f32> = (0..get_own_size(*w, cx))
+      .map(|_| rng.gen_range(-1.0..1.0))
+      .collect();
+    cx.get_op_mut::<Function>(*w).1 = Box::new(move |_| vec![Tensor::new(data.clone())]);
+  }
+}
+
+/// Shuffled order of the `n` training samples, for an epoch.
+pub fn epoch_order(n: usize, rng: &mut StdRng) -> Vec<usize> {
+  let mut order: Vec<usize> = (0..n).collect();
+  order.shuffle(rng);
+  order
+}
+
 /// Contains everything needed to define the snark: the ml graph but without the gradients, trained weights and indexes.
 /// Note: this is quite a specific and frankly poor interface between training and snark synthesiz, so don't take it as engraved in stone.
 #[derive(Debug)]
@@ -118,6 +260,10 @@ pub struct GraphForSnark {
   pub graph: Graph,
   pub input_id: NodeIndex,
   pub weights: Vec<(NodeIndex, Vec<f32>)>,
+  /// the retrieved tensors, results of the model. A model can have many heads, all are proven in one circuit
+  pub outputs: Vec<NodeIndex>,
+  /// names of the weights
+  pub params: ParamRegistry,
 }
 
 impl GraphForSnark {
@@ -131,6 +277,8 @@ impl GraphForSnark {
         .iter()
         .map(|(a, b)| (remap[a], b.clone()))
         .collect(),
+      outputs: self.outputs.iter().map(|x| remap[x]).collect(),
+      params: self.params.remap(&remap),
     }
   }
 }
@@ -143,33 +291,94 @@ pub struct TrainedGraph {
   /// the original ml computation graph, without gradients + input id + trained weights
   pub graph: GraphForSnark,
   // below are needed to evaluate the model to compare result against a snark derived from GraphForSnark:
-  pub cx: Graph, /// full trained graph for evaluation, the above "graph" is similar but without gradients
+  /// full trained graph for evaluation, the above "graph" is similar but without gradients
+  pub cx: RefCell<Graph>,
   pub cx_weights: Vec<(NodeIndex, Vec<f32>)>, // needed for evaluation, mostly tests. redundant a bit
   pub cx_input_id: NodeIndex, // needed for evaluation, mostly tests
   pub cx_target_id: NodeIndex, // needed for evaluation, mostly tests
-  pub cx_output_id: NodeIndex,
+  pub cx_output_ids: Vec<NodeIndex>, // in the order of graph.outputs
+  /// normalization fitted on the train data, apply it to the inputs before evaluating
+  pub scaler: Option<Scaler>,
+  /// calibrated threshold of the [OutputHead::Decision] head, the model outputs the decision then
+  pub threshold: Option<f32>,
 }
 
 impl TrainedGraph {
-  pub fn evaluate(&mut self, input_data: Vec<f32>) -> Vec<f32> {
-    self.cx.get_op_mut::<Function>(self.cx_input_id).1 =
-      Box::new(move |_| vec![Tensor::new(input_data.to_owned())]);
-    self.cx.get_op_mut::<Function>(self.cx_target_id).1 =
-      Box::new(move |_| vec![Tensor::new(vec![0.0])]); // doesnt matter
-    let weights = self.cx_weights.clone();
-    for (a, b) in weights {
-      self.cx.get_op_mut::<Function>(a).1 = Box::new(move |_| vec![Tensor::new(b.clone())]);
+  /// Evaluates the model on the input. The results are by the output in the snark graph, see [GraphForSnark::outputs].
+  /// The first evaluation sets the weights in the graph, the later ones only the input.
+  pub fn evaluate(&self, input_data: Vec<f32>) -> HashMap<NodeIndex, Vec<f32>> {
+    let mut cx = self.cx.borrow_mut();
+    // kept in the graph, so the execution doesn't run the loads again
+    for (w, data) in self.cx_weights.iter() {
+      if !cx.tensors.contains_key(&(*w, 0)) {
+        cx.tensors.insert((*w, 0), Tensor::new(data.clone()));
+      }
+    }
+    cx.tensors
+      .insert((self.cx_input_id, 0), Tensor::new(input_data));
+    let target = vec![0.0; get_own_size(self.cx_target_id, &cx)];
+    cx.tensors
+      .insert((self.cx_target_id, 0), Tensor::new(target)); // doesnt matter
+    cx.execute();
+    let results = self
+      .graph
+      .outputs
+      .iter()
+      .zip(self.cx_output_ids.iter())
+      .map(|(output, x)| {
+        let d = cx
+          .get_tensor_ref(*x, 0)
+          .unwrap()
+          .downcast_ref::<Vec<f32>>()
+          .unwrap()
+          .clone();
+        (*output, d)
+      })
+      .collect();
+    // the execution skips the nodes with a tensor, the outputs left would be the results of the next evaluation
+    cx.tensors
+      .retain(|(x, _), _| self.cx_weights.iter().any(|(w, _)| w == x));
+    results
+  }
+
+  /// Evaluates the model on the input, keeping the result of every node of the snark graph (see [GraphForSnark]) the
+  /// filter lets through, e.g. the nodes of a layer (see [ParamRegistry::layer_of]). The results are by the node of the
+  /// snark graph, the one the scalar graph's [crate::scalar::InputsTracker::origin] refers to, in the physical order of
+  /// the tensor. For finding where the scalar or quantized evaluation departs from the floats.
+  pub fn evaluate_with_intermediates(
+    &self,
+    input_data: Vec<f32>,
+    filter: impl Fn(NodeIndex) -> bool,
+  ) -> HashMap<NodeIndex, Vec<f32>> {
+    let g = &self.graph;
+    let (mut cx, remap) = copy_graph_roughly(&g.graph);
+    let sources = g.weights.iter().cloned().chain([(g.input_id, input_data)]);
+    for (x, data) in sources {
+      cx.get_op_mut::<Function>(remap[&x]).1 = Box::new(move |_| vec![Tensor::new(data.clone())]);
+    }
+    let kept: Vec<NodeIndex> = g.graph.node_indices().filter(|x| filter(*x)).collect();
+    cx.keep_tensors(kept.iter().map(|x| remap[x]).collect::<Vec<_>>());
+    cx.execute();
+    kept
+      .into_iter()
+      .filter_map(|x| Some((x, tensor_f32(cx.get_tensor_ref(remap[&x], 0)?)?)))
+      .collect()
+  }
+}
+
+/// Adds the output head on top of the score.
+/// Returns the output (the one to prove) and the cut point input of the decision head, `logit(threshold)`, set like a weight.
+fn add_head(
+  cx: &mut Graph,
+  score: GraphTensor<R1<1>>,
+  head: OutputHead,
+) -> (GraphTensor<R1<1>>, Option<GraphTensor<R1<1>>>) {
+  match head {
+    OutputHead::Score => (score.retrieve(), None),
+    OutputHead::Decision => {
+      let cut = cx.named_tensor::<R1<1>>("Cut");
+      (cut.less_than(score).retrieve(), Some(cut))
     }
-    self.cx.execute();
-    let d = self
-      .cx
-      .get_tensor_ref(self.cx_output_id, 0)
-      .unwrap()
-      .clone()
-      .downcast_ref::<Vec<f32>>()
-      .unwrap()
-      .clone();
-    d
   }
 }
 
@@ -179,40 +388,107 @@ pub fn run_model(train_params: TrainParams) -> TrainedGraph {
   // Setup gradient graph
   let mut cx = Graph::new();
   let model = <Model>::initialize(&mut cx);
-  let input = cx.tensor::<R1<9>>();
-  let output = model.forward(input).retrieve();
+  let mut input = cx.tensor::<R1<9>>();
+  let score = model.forward(input);
+  let (decision, cut) = add_head(&mut cx, score, train_params.head);
+  let mut rng = StdRng::seed_from_u64(train_params.seed);
+  seed_weights(&mut cx, &params(&model), &mut rng);
 
   // cx.display();
   // record graph without gradients. assuming nodeids dont change in Autograd::compile
   let (cx_og, remap) = copy_graph_roughly(&cx);
   let input_id = remap[&input.id];
+  // the decision isn't differentiable, train on the probability
+  let mut output = match cut {
+    Some(cut) => {
+      cut.set(vec![0.0]); // set after calibration
+      score.sigmoid().retrieve()
+    }
+    None => decision,
+  };
 
-  let target = cx.tensor::<R1<1>>();
-  let loss = mse_loss(output, target).retrieve();
-  let weights = params(&model);
+  let mut target = cx.tensor::<R1<1>>();
+  let mut class_weight = cx.named_tensor::<R1<1>>("ClassWeight");
+  let mut loss = weighted_mse_loss(output, target, class_weight).retrieve();
+  let mut weights = params(&model);
+  // before device compilation, these map onto the snark graph
+  let og_weights = weights.clone();
 
   let grads = cx.compile(Autograd::new(&weights, loss), ());
-  let (new_weights, lr) = sgd_on_graph(&mut cx, &weights, &grads);
+  let (mut new_weights, mut lr) =
+    sgd_clipped(&mut cx, &weights, &grads, train_params.clip_grad_norm);
   cx.keep_tensors(&new_weights);
   cx.keep_tensors(&weights);
-  lr.set(5e-3);
+  lr.set(train_params.lr.lr(0, EPOCHS));
+  compile_for_device(
+    &mut cx,
+    (
+      &mut input,
+      &mut target,
+      &mut class_weight,
+      &mut loss,
+      &mut output,
+      &mut weights,
+      &mut new_weights,
+      &mut lr,
+    ),
+  );
+
+  let mut fake_quant = train_params
+    .quantization
+    .map(|quant| FakeQuant::new(quant, &mut cx, &weights));
+  // the circuit gets the inputs quantized too
+  let quantize_input = |x: &[f32]| match &fake_quant {
+    Some(fake_quant) => fake_quant.quantize(x),
+    None => x.to_vec(),
+  };
 
   let (mut loss_avg, mut acc_avg) = (ExponentialAverage::new(1.0), ExponentialAverage::new(0.0));
   let start = std::time::Instant::now();
   // let EPOCHS = 20;
 
   let (X, Y) = dataset;
-  let (X_train, _x_test, y_train, _y_test) = split_dataset(X, Y, 0.8);
-  let X_train = normalize_data(X_train);
+  let (X_train, x_test, y_train, y_test) = split_dataset(X, Y, 0.8);
+  // the threshold is calibrated on a validation split of the train data, the test split stays held out
+  let (X_train, x_val, y_train, y_val) = match cut {
+    Some(_) => split_dataset(X_train, y_train, 0.9),
+    None => (X_train, vec![], y_train, vec![]),
+  };
+  let class_weights = train_params
+    .class_weights
+    .map_or([1.0, 1.0], |c| c.weights(&y_train));
+  let scaler = Scaler::fit(ScalerKind::MinMax, &X_train);
+  let X_train: Vec<Vec<f32>> = scaler
+    .transform(&X_train)
+    .iter()
+    .map(|x| quantize_input(&x[..]))
+    .collect();
+  let x_val: Vec<Vec<f32>> = scaler
+    .transform(&x_val)
+    .iter()
+    .map(|x| quantize_input(&x[..]))
+    .collect();
+  let x_test: Vec<Vec<f32>> = scaler
+    .transform(&x_test)
+    .iter()
+    .map(|x| quantize_input(&x[..]))
+    .collect();
   let mut iter = 0;
-  for _ in 0..EPOCHS {
-    for (x, y) in zip(X_train.iter(), y_train.iter()) {
+  for epoch in 0..EPOCHS {
+    lr.set(train_params.lr.lr(epoch, EPOCHS));
+    for i in epoch_order(X_train.len(), &mut rng) {
+      let (x, y) = (&X_train[i], &y_train[i]);
       let answer = [y.to_owned()];
       input.set(x.to_owned());
       target.set(answer);
+      class_weight.set([class_weights[(*y > 0.5) as usize]]);
 
       cx.execute();
+      check_finite("the loss", &loss.data(), iter).unwrap_or_else(|e| panic!("{}", e));
       transfer_data_same_graph(&new_weights, &weights, &mut cx);
+      if let Some(fake_quant) = fake_quant.as_mut() {
+        fake_quant.step(&mut cx);
+      }
       loss_avg.update(loss.data()[0]);
       loss.drop();
       // println!("{:}, {:}", output.data()[0], answer[0]);
@@ -232,45 +508,204 @@ pub fn run_model(train_params: TrainParams) -> TrainedGraph {
       iter += 1;
     }
   }
-  println!("Finished in {iter} iterations");
+  println!(
+    "Finished in {iter} iterations, learning rate {}",
+    train_params.lr
+  );
   println!(
     "Took {:.2}s, {:.2}µs / iter",
     start.elapsed().as_secs_f32(),
     start.elapsed().as_micros() / iter
   );
+  let mut probabilities = |xs: Vec<Vec<f32>>| {
+    let mut probabilities = vec![];
+    for x in xs {
+      input.set(x);
+      target.set([0.0]); // doesnt matter
+      cx.execute();
+      probabilities.push(output.data()[0]);
+      loss.drop();
+      output.drop();
+    }
+    probabilities
+  };
+  let threshold = cut.map(|_| {
+    let t = calibrate_threshold(&probabilities(x_val), &y_val);
+    let test = probabilities(x_test);
+    let correct = test
+      .iter()
+      .zip(&y_test)
+      .filter(|(p, y)| (**p > t) == (**y > 0.5))
+      .count();
+    println!(
+      "Threshold {:.3}, accuracy on the test split {:.3}",
+      t,
+      correct as f32 / test.len().max(1) as f32
+    );
+    t
+  });
   // cx.display();
   let cx_weights_vec: Vec<(NodeIndex, Vec<f32>)> = weights
     .into_iter()
-    .map(|a| {
-      (
-        a,
-        cx.tensors
-          .get(&(a, 0 /* assuming single output */))
-          .unwrap()
-          .downcast_ref::<Vec<f32>>()
-          .unwrap()
-          .clone()
-          .into_iter()
-          .collect(),
-      )
-    })
+    .map(|a| (a, tensor_data(&cx, a)))
     .collect();
-  let weights_vec = cx_weights_vec
+  for (w, data) in cx_weights_vec.iter() {
+    check_finite(&format!("weight {:?}", w), data, iter).unwrap_or_else(|e| panic!("{}", e));
+  }
+  if ON_GPU {
+    // the compiled graph runs kernels, rebuild it for evaluation on the CPU
+    let registry = param_registry(&model);
+    return load_model(SavedModel {
+      names: og_weights
+        .iter()
+        .map(|x| registry.by_id(*x).unwrap().name.clone())
+        .collect(),
+      weights: cx_weights_vec.into_iter().map(|(_, w)| w).collect(),
+      scaler: Some(scaler),
+      threshold,
+    });
+  }
+  let mut weights_vec: Vec<_> = og_weights
     .iter()
-    .map(|(a, b)| (remap[&a], b.clone()))
+    .zip(cx_weights_vec.iter())
+    .map(|(a, (_, b))| (remap[a], b.clone()))
     .collect();
+  let mut params = param_registry(&model).remap(&remap);
+  if let (Some(cut), Some(t)) = (cut, threshold) {
+    cut.set(vec![logit(t)]);
+    weights_vec.push((remap[&cut.id], vec![logit(t)]));
+    params.register("cut", remap[&cut.id], vec![1]);
+  }
   // assert!(input_id == input.id);
   TrainedGraph {
     graph: GraphForSnark {
       graph: cx_og,
       weights: weights_vec,
       input_id,
+      outputs: vec![remap[&decision.id]],
+      params,
+    },
+    cx: RefCell::new(cx),
+    cx_weights: cx_weights_vec,
+    cx_output_ids: vec![decision.id],
+    cx_input_id: input.id,
+    cx_target_id: target.id,
+    scaler: Some(scaler),
+    threshold,
+  }
+}
+
+/// Trained weights of the medium model in the order of `params(&model)`, together with the input normalization.
+/// That's enough to rebuild the model without retraining, see [load_model].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SavedModel {
+  /// Names of the weights (see [ParamRegistry]) in the order of `weights`.
+  /// Models saved before the names were introduced don't have them, the weights are matched by the order then.
+  #[serde(default)]
+  pub names: Vec<String>,
+  pub weights: Vec<Vec<f32>>,
+  pub scaler: Option<Scaler>,
+  /// Present for models with the [OutputHead::Decision] head.
+  #[serde(default)]
+  pub threshold: Option<f32>,
+}
+
+impl SavedModel {
+  pub fn from_trained(trained: &TrainedGraph) -> Self {
+    // the weights of the snark graph start with the ones of the evaluation graph, in the same order
+    let names = trained
+      .graph
+      .weights
+      .iter()
+      .zip(trained.cx_weights.iter())
+      .map(|((x, _), _)| {
+        let param = trained.graph.params.by_id(*x);
+        param.map(|p| p.name.clone()).unwrap_or_default()
+      })
+      .collect();
+    SavedModel {
+      names,
+      weights: trained.cx_weights.iter().map(|(_, w)| w.clone()).collect(),
+      scaler: trained.scaler.clone(),
+      threshold: trained.threshold,
+    }
+  }
+
+  pub fn save(&self, path: &Path) -> Result<(), Box<dyn Error>> {
+    fs::write(path, serde_json::to_string(self)?)?;
+    Ok(())
+  }
+
+  pub fn load(path: &Path) -> Result<Self, Box<dyn Error>> {
+    Ok(serde_json::from_str(&fs::read_to_string(path)?)?)
+  }
+}
+
+/// Rebuild the medium model with the given weights. Same graph as [run_model] produces, but without the gradients.
+pub fn load_model(saved: SavedModel) -> TrainedGraph {
+  let mut cx = Graph::new();
+  let model = <Model>::initialize(&mut cx);
+  let input = cx.tensor::<R1<9>>();
+  let head = match saved.threshold {
+    Some(_) => OutputHead::Decision,
+    None => OutputHead::Score,
+  };
+  let (output, cut) = add_head(&mut cx, model.forward(input), head);
+
+  let (cx_og, remap) = copy_graph_roughly(&cx);
+  let input_id = remap[&input.id];
+
+  let target = cx.tensor::<R1<1>>(); // unused, but evaluate expects it
+  let weights = params(&model);
+  assert!(
+    weights.len() == saved.weights.len(),
+    "Expected {} weight tensors, got {}",
+    weights.len(),
+    saved.weights.len()
+  );
+  let registry = param_registry(&model);
+  let saved_weights: Vec<Vec<f32>> = if saved.names.is_empty() {
+    saved.weights
+  } else {
+    weights
+      .iter()
+      .map(|x| {
+        let name = &registry.by_id(*x).unwrap().name;
+        let i = saved
+          .names
+          .iter()
+          .position(|n| n == name)
+          .unwrap_or_else(|| panic!("Missing weight {}", name));
+        saved.weights[i].clone()
+      })
+      .collect()
+  };
+  let cx_weights_vec: Vec<(NodeIndex, Vec<f32>)> = weights.into_iter().zip(saved_weights).collect();
+  let mut weights_vec: Vec<_> = cx_weights_vec
+    .iter()
+    .map(|(a, b)| (remap[&a], b.clone()))
+    .collect();
+  let mut params = registry.remap(&remap);
+  if let (Some(cut), Some(t)) = (cut, saved.threshold) {
+    cut.set(vec![logit(t)]);
+    weights_vec.push((remap[&cut.id], vec![logit(t)]));
+    params.register("cut", remap[&cut.id], vec![1]);
+  }
+  TrainedGraph {
+    graph: GraphForSnark {
+      graph: cx_og,
+      weights: weights_vec,
+      input_id,
+      outputs: vec![remap[&output.id]],
+      params,
     },
-    cx: cx,
+    cx: RefCell::new(cx),
     cx_weights: cx_weights_vec,
-    cx_output_id: output.id,
+    cx_output_ids: vec![output.id],
     cx_input_id: input.id,
     cx_target_id: target.id,
+    scaler: saved.scaler,
+    threshold: saved.threshold,
   }
 }
 
@@ -306,3 +741,71 @@ impl ExponentialAverage {
     self.t = 0;
   }
 }
+
+#[cfg(test)]
+mod tests {
+  use luminal::prelude::*;
+
+  use super::{ClassWeights, LrSchedule, TrainParams};
+  use crate::model::{fixed_weights::run_model, parse_dataset};
+
+  #[test]
+  fn test_evaluate_with_intermediates() {
+    let trained = run_model();
+    let input = vec![0.5, -1.0, 2.0];
+    let (input_id, output) = (trained.graph.input_id, trained.graph.outputs[0]);
+    let all = trained.evaluate_with_intermediates(input.clone(), |_| true);
+    assert_eq!(all[&input_id], input);
+    assert_eq!(all[&output], trained.evaluate(input.clone())[&output]);
+    assert!(all.len() > 2);
+
+    let only_output = trained.evaluate_with_intermediates(input, |x| x == output);
+    assert_eq!(only_output.keys().collect::<Vec<_>>(), vec![&output]);
+  }
+
+  #[test]
+  fn test_class_weights() {
+    let labels = [0.0, 0.0, 0.0, 1.0];
+    assert_eq!(ClassWeights::Balanced.weights(&labels), [2.0 / 3.0, 2.0]);
+    assert_eq!(ClassWeights::Fixed(1.0, 3.0).weights(&labels), [1.0, 3.0]);
+
+    let data = parse_dataset(include_str!("../../../data/rp.data").to_string());
+    let trained = super::run_model(TrainParams {
+      data,
+      class_weights: Some(ClassWeights::Balanced),
+      ..Default::default()
+    });
+    // the weight of the class is an input of the loss, not of the snark graph
+    let g = &trained.graph;
+    let sources = g
+      .graph
+      .node_indices()
+      .filter(|x| g.graph.check_node_type::<Function>(*x))
+      .collect::<Vec<_>>();
+    assert!(sources
+      .iter()
+      .all(|x| *x == g.input_id || g.weights.iter().any(|(w, _)| w == x)));
+    assert_eq!(sources.len(), g.weights.len() + 1);
+  }
+
+  #[test]
+  fn test_lr_schedule() {
+    let constant = LrSchedule::default();
+    assert_eq!(constant.lr(7, 10), 5e-3);
+    let step = LrSchedule::StepDecay {
+      lr: 1.0,
+      gamma: 0.5,
+      every: 3,
+    };
+    let rates: Vec<f32> = (0..7).map(|e| step.lr(e, 7)).collect();
+    assert_eq!(rates, vec![1.0, 1.0, 1.0, 0.5, 0.5, 0.5, 0.25]);
+    let cosine = LrSchedule::Cosine {
+      lr: 1.0,
+      min_lr: 0.1,
+    };
+    for (epoch, epochs, lr) in [(0, 5, 1.0), (2, 5, 0.55), (4, 5, 0.1), (0, 1, 1.0)] {
+      assert!((cosine.lr(epoch, epochs) - lr).abs() < 1e-6);
+    }
+    assert_eq!(cosine.to_string(), "cosine from 1 to 0.1");
+  }
+}
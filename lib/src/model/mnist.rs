@@ -0,0 +1,176 @@
+//!
+//! MNIST in the IDX format of its distribution (http://yann.lecun.com/exdb/mnist/), decompressed.
+//!
+//! An IDX file is a big-endian header: two zero bytes, the type of the elements, the number of dimensions
+//! and the size of each as u32, followed by the elements in row-major order. MNIST only uses unsigned bytes.
+//!
+
+use std::{error::Error, fs, path::Path};
+
+/// Type byte of unsigned byte elements, the only type we read.
+const IDX_UBYTE: u8 = 0x08;
+
+pub const MNIST_TRAIN_IMAGES: &str = "train-images-idx3-ubyte";
+pub const MNIST_TRAIN_LABELS: &str = "train-labels-idx1-ubyte";
+pub const MNIST_TEST_IMAGES: &str = "t10k-images-idx3-ubyte";
+pub const MNIST_TEST_LABELS: &str = "t10k-labels-idx1-ubyte";
+
+/// Why the bytes are not an IDX file we can read.
+#[derive(Debug, Clone, PartialEq)]
+pub enum IdxError {
+  /// The first two bytes are not zero.
+  Magic {
+    got: [u8; 2],
+  },
+  UnsupportedType {
+    got: u8,
+  },
+  /// Shorter than the header or the elements it declares.
+  Truncated {
+    expected: usize,
+    got: usize,
+  },
+}
+
+/// The dimensions and the elements of an IDX file of unsigned bytes.
+pub fn parse_idx(bytes: &[u8]) -> Result<(Vec<usize>, Vec<u8>), IdxError> {
+  let truncated = |expected| IdxError::Truncated {
+    expected,
+    got: bytes.len(),
+  };
+  if bytes.len() < 4 {
+    return Err(truncated(4));
+  }
+  if bytes[0..2] != [0, 0] {
+    return Err(IdxError::Magic {
+      got: [bytes[0], bytes[1]],
+    });
+  }
+  if bytes[2] != IDX_UBYTE {
+    return Err(IdxError::UnsupportedType { got: bytes[2] });
+  }
+  let header_len = 4 + 4 * bytes[3] as usize;
+  if bytes.len() < header_len {
+    return Err(truncated(header_len));
+  }
+  let dims: Vec<usize> = bytes[4..header_len]
+    .chunks(4)
+    .map(|d| u32::from_be_bytes([d[0], d[1], d[2], d[3]]) as usize)
+    .collect();
+  let len = header_len + dims.iter().product::<usize>();
+  if bytes.len() < len {
+    return Err(truncated(len));
+  }
+  Ok((dims, bytes[header_len..len].to_vec()))
+}
+
+/// Images with their digits. Pixels are normalized from 0..=255 onto [0, 1].
+#[derive(Debug, Clone, PartialEq)]
+pub struct MnistDataset {
+  /// Row-major images, `rows * cols` pixels each.
+  pub images: Vec<Vec<f32>>,
+  pub labels: Vec<u8>,
+  pub rows: usize,
+  pub cols: usize,
+}
+
+impl MnistDataset {
+  /// From the contents of an images file and a labels file.
+  pub fn parse(images: &[u8], labels: &[u8]) -> Result<Self, Box<dyn Error>> {
+    let (image_dims, pixels) = parse_idx(images).map_err(|e| format!("Images: {:?}", e))?;
+    let (label_dims, labels) = parse_idx(labels).map_err(|e| format!("Labels: {:?}", e))?;
+    let (n, rows, cols) = match image_dims[..] {
+      [n, rows, cols] => (n, rows, cols),
+      _ => return Err(format!("Images have dimensions {:?}, expected 3", image_dims).into()),
+    };
+    if label_dims != [n] {
+      return Err(format!("{} images but labels of dimensions {:?}", n, label_dims).into());
+    }
+    let images = pixels
+      .chunks(rows * cols)
+      .map(|image| image.iter().map(|p| f32::from(*p) / 255.0).collect())
+      .collect();
+    Ok(MnistDataset {
+      images,
+      labels,
+      rows,
+      cols,
+    })
+  }
+
+  pub fn read(images: &Path, labels: &Path) -> Result<Self, Box<dyn Error>> {
+    Self::parse(&fs::read(images)?, &fs::read(labels)?)
+  }
+
+  /// The standard train and test splits, from a directory with the four files of the distribution.
+  pub fn read_dir(dir: &Path) -> Result<(Self, Self), Box<dyn Error>> {
+    Ok((
+      Self::read(&dir.join(MNIST_TRAIN_IMAGES), &dir.join(MNIST_TRAIN_LABELS))?,
+      Self::read(&dir.join(MNIST_TEST_IMAGES), &dir.join(MNIST_TEST_LABELS))?,
+    ))
+  }
+
+  pub fn len(&self) -> usize {
+    self.labels.len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.labels.is_empty()
+  }
+
+  /// The first `ratio` of the samples and the rest, as [super::split_dataset] (but keeping the last sample).
+  pub fn split(self, ratio: f32) -> (Self, Self) {
+    let k = (self.len() as f32 * ratio) as usize;
+    let (mut images, mut labels) = (self.images, self.labels);
+    let (rest_images, rest_labels) = (images.split_off(k), labels.split_off(k));
+    let part = |images, labels| MnistDataset {
+      images,
+      labels,
+      rows: self.rows,
+      cols: self.cols,
+    };
+    (part(images, labels), part(rest_images, rest_labels))
+  }
+
+  /// Labels as the 0/1 targets of a binary classifier recognizing the digit.
+  pub fn binary_labels(&self, digit: u8) -> Vec<f32> {
+    self
+      .labels
+      .iter()
+      .map(|l| if *l == digit { 1.0 } else { 0.0 })
+      .collect()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{parse_idx, IdxError, MnistDataset};
+
+  fn idx(dims: &[u32], data: &[u8]) -> Vec<u8> {
+    let mut bytes = vec![0, 0, 0x08, dims.len() as u8];
+    bytes.extend(dims.iter().flat_map(|d| d.to_be_bytes()));
+    bytes.extend(data);
+    bytes
+  }
+
+  #[test]
+  fn test_parse_mnist() {
+    let images = idx(&[3, 2, 2], &[0, 255, 51, 0, 255, 255, 255, 255, 0, 0, 0, 0]);
+    let labels = idx(&[3], &[7, 1, 7]);
+    let data = MnistDataset::parse(&images, &labels).unwrap();
+    assert_eq!((data.rows, data.cols, data.len()), (2, 2, 3));
+    assert_eq!(data.images[0], vec![0.0, 1.0, 0.2, 0.0]);
+    assert_eq!(data.binary_labels(7), vec![1.0, 0.0, 1.0]);
+    let (train, test) = data.split(0.7);
+    assert_eq!((train.labels, test.labels), (vec![7, 1], vec![7]));
+
+    assert_eq!(
+      parse_idx(&images[..10]),
+      Err(IdxError::Truncated {
+        expected: 16,
+        got: 10
+      })
+    );
+    assert!(MnistDataset::parse(&images, &idx(&[2], &[7, 1])).is_err());
+  }
+}
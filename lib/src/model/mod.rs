@@ -1,8 +1,31 @@
 // todo: abstract away the training loop. split from the lib crate
 
+pub mod activation;
+pub mod dataset;
+pub mod device;
 pub mod fixed_weights;
+pub mod from_graph;
+pub mod head;
 pub mod lessthan_model;
 pub mod medium_model;
+pub mod mnist;
+pub mod optim;
+pub mod params;
+pub mod qat;
+pub mod safetensors;
+pub mod scaler;
+pub mod search;
+pub mod spec;
+#[cfg(feature = "tflite")]
+pub mod tflite;
 pub mod tiny_model;
+pub mod train_step;
+pub mod validate;
+pub mod zoo;
 
+pub use activation::*;
+pub use head::OutputHead;
 pub use medium_model::*;
+pub use params::*;
+pub use scaler::*;
+pub use spec::*;
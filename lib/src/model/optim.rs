@@ -0,0 +1,158 @@
+//!
+//! The SGD step of the training graphs, with the gradients optionally clipped, and the check that the training
+//! hasn't diverged.
+//!
+//! [sgd_clipped] is luminal's `sgd_on_graph` on the gradients scaled by `min(1, c / ‖g‖)`, where `‖g‖` is the norm of
+//! all the gradients together. The scale is computed in the graph, every step, as `recip(1 + relu(‖g‖ / c - 1))`: no
+//! division by a zero norm.
+//!
+//! A NaN or an infinity in the loss or the weights only gets worse with the next steps and the snark of such weights
+//! is useless. The models check the loss of every step and the weights they export with [check_finite] and abort the
+//! training with a [Diverged].
+//!
+
+use std::{error::Error, fmt};
+
+use luminal::{
+  op::{Mul, SumReduce},
+  prelude::*,
+};
+use luminal_training::sgd_on_graph;
+
+/// The gradients scaled so that their norm, all together, is at most `max_norm`. In the shapes of the weights.
+pub fn clip_grad_norm(
+  cx: &mut Graph,
+  grads: &[(NodeIndex, ShapeTracker)],
+  max_norm: f32,
+) -> Vec<(NodeIndex, ShapeTracker)> {
+  assert!(
+    max_norm > 0.0,
+    "Clipping the gradients to a norm of {}",
+    max_norm
+  );
+  let graph_ref: *mut Graph = cx;
+  let mut squares: Option<GraphTensor<R0>> = None;
+  for (g, sh) in grads.iter() {
+    let dims = sh.shape();
+    let mut x = cx
+      .add_op(Mul {})
+      .input(*g, 0, *sh)
+      .input(*g, 0, *sh)
+      .finish();
+    // the last axis summed up until it's a scalar
+    for k in (0..dims.len()).rev() {
+      x = cx
+        .add_op(SumReduce(k))
+        .input(x, 0, ShapeTracker::new(&dims[..=k]))
+        .finish();
+    }
+    let x = GraphTensor::<R0>::from_id(x, R0::to_tracker(), graph_ref);
+    squares = Some(match squares {
+      Some(squares) => squares + x,
+      None => x,
+    });
+  }
+  let squares = squares.expect("Clipping no gradients");
+  let scale = ((squares.sqrt() * (1.0 / max_norm) - 1.0).relu() + 1.0).recip();
+
+  grads
+    .iter()
+    .map(|(g, sh)| {
+      let dims = sh.shape();
+      let mut scale_sh = R0::to_tracker();
+      for (k, d) in dims.iter().enumerate() {
+        scale_sh.expand(k, *d);
+      }
+      let clipped = cx
+        .add_op(Mul {})
+        .input(*g, 0, *sh)
+        .input(scale.id, 0, scale_sh)
+        .finish();
+      (clipped, ShapeTracker::new(&dims))
+    })
+    .collect()
+}
+
+/// luminal's `sgd_on_graph`, on the gradients clipped to `max_norm` (see [clip_grad_norm]) if given. Returns the new
+/// weights and the learning rate input.
+pub fn sgd_clipped<W: ToIds>(
+  cx: &mut Graph,
+  weights: W,
+  grads: &[(NodeIndex, ShapeTracker)],
+  max_norm: Option<f32>,
+) -> (Vec<NodeIndex>, GraphTensor<R0>) {
+  match max_norm {
+    Some(max_norm) => {
+      let clipped = clip_grad_norm(cx, grads, max_norm);
+      sgd_on_graph(cx, weights, &clipped)
+    }
+    None => sgd_on_graph(cx, weights, grads),
+  }
+}
+
+/// The training went to NaN or infinity, see the module docs.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diverged {
+  /// What isn't finite, e.g. the loss.
+  pub what: String,
+  /// The training step, counted from 0 over all the epochs.
+  pub iteration: usize,
+}
+
+impl fmt::Display for Diverged {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(
+      f,
+      "Training diverged: {} is not finite at iteration {}",
+      self.what, self.iteration
+    )?;
+    write!(
+      f,
+      ", lower the learning rate or clip the gradients (TrainParams::clip_grad_norm)"
+    )
+  }
+}
+
+impl Error for Diverged {}
+
+/// An error if any of the values, of `what`, is NaN or infinite.
+pub fn check_finite(what: &str, values: &[f32], iteration: usize) -> Result<(), Diverged> {
+  match values.iter().all(|v| v.is_finite()) {
+    true => Ok(()),
+    false => Err(Diverged {
+      what: what.to_string(),
+      iteration,
+    }),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use luminal::prelude::*;
+
+  use super::{check_finite, clip_grad_norm};
+
+  #[test]
+  fn test_clip_grad_norm() {
+    let norm_after = |max_norm: f32| {
+      let mut cx = Graph::new();
+      let a = cx.tensor::<R2<2, 2>>().set(vec![3.0, 0.0, 0.0, 0.0]);
+      let b = cx.tensor::<R1<2>>().set(vec![0.0, 4.0]);
+      let clipped = clip_grad_norm(&mut cx, &[(a.id, a.shape), (b.id, b.shape)], max_norm);
+      let a = GraphTensor::<R2<2, 2>>::from_id(clipped[0].0, clipped[0].1, a.graph_ref).retrieve();
+      let b = GraphTensor::<R1<2>>::from_id(clipped[1].0, clipped[1].1, b.graph_ref).retrieve();
+      cx.execute();
+      let data = [a.data(), b.data()].concat();
+      data.iter().map(|v| v * v).sum::<f32>().sqrt()
+    };
+    // the norm is 5
+    assert!((norm_after(1.0) - 1.0).abs() < 1e-5);
+    assert!((norm_after(2.5) - 2.5).abs() < 1e-5);
+    assert!((norm_after(10.0) - 5.0).abs() < 1e-5);
+
+    assert_eq!(check_finite("the loss", &[1.0, -2.0], 3), Ok(()));
+    let e = check_finite("the loss", &[1.0, f32::NAN], 3).unwrap_err();
+    assert_eq!(e.iteration, 3);
+    assert!(e.to_string().contains("the loss is not finite"));
+  }
+}
@@ -0,0 +1,166 @@
+//!
+//! Human-readable names of the parameters (weights) of a model, like `layer0.weight`.
+//!
+//! Node ids change with every copy of the graph, the names don't: the registry is remapped together with the graph
+//! (see [GraphForSnark::copy_graph_roughly]) and names the weights in the saved models and in the safetensors files.
+//! Layers are named by their position in the model tuple, as in PyTorch's `nn.Sequential`.
+//!
+
+use std::{
+  collections::{HashMap, HashSet, VecDeque},
+  error::Error,
+};
+
+use luminal::{
+  graph::Graph,
+  prelude::{petgraph::Direction::Incoming, GraphTensor, NodeIndex},
+  shape::Shape,
+};
+
+use crate::scalar::ScalarGraph;
+
+use super::GraphForSnark;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Param {
+  pub name: String,
+  pub id: NodeIndex,
+  /// Logical shape, the data is in row-major order.
+  pub shape: Vec<usize>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ParamRegistry {
+  pub params: Vec<Param>,
+}
+
+impl ParamRegistry {
+  pub fn register(&mut self, name: &str, id: NodeIndex, shape: Vec<usize>) {
+    assert!(
+      self.get(name).is_none() && self.by_id(id).is_none(),
+      "Parameter {} ({:?}) registered twice",
+      name,
+      id
+    );
+    self.params.push(Param {
+      name: name.to_string(),
+      id,
+      shape,
+    });
+  }
+
+  pub fn register_tensor<S: Shape>(&mut self, name: &str, tensor: GraphTensor<S>) {
+    self.register(name, tensor.id, tensor.shape.shape_usize());
+  }
+
+  pub fn get(&self, name: &str) -> Option<&Param> {
+    self.params.iter().find(|p| p.name == name)
+  }
+
+  pub fn by_id(&self, id: NodeIndex) -> Option<&Param> {
+    self.params.iter().find(|p| p.id == id)
+  }
+
+  /// The named parameters, in the order of the names.
+  pub fn select(&self, names: &[String]) -> Result<ParamRegistry, Box<dyn Error>> {
+    let params = names
+      .iter()
+      .map(|name| {
+        self
+          .get(name)
+          .cloned()
+          .ok_or_else(|| format!("Unknown parameter {}", name))
+      })
+      .collect::<Result<Vec<_>, _>>()?;
+    Ok(ParamRegistry { params })
+  }
+
+  /// The registry for a copy of the graph, see [crate::scalar::copy_graph_roughly].
+  pub fn remap(&self, remap: &HashMap<NodeIndex, NodeIndex>) -> Self {
+    ParamRegistry {
+      params: self
+        .params
+        .iter()
+        .map(|p| Param {
+          id: remap[&p.id],
+          ..p.clone()
+        })
+        .collect(),
+    }
+  }
+
+  /// The parameter a little node of the scalar graph (made from the graph of the registry) stands for,
+  /// with the physical index of its element. See [crate::scalar::InputsTracker::origin].
+  pub fn origin_of(&self, scalar: &ScalarGraph, little: NodeIndex) -> Option<(&Param, usize)> {
+    let (x, i) = scalar.inputs_tracker.origin.get(&little)?;
+    self.by_id(*x).map(|p| (p, *i))
+  }
+
+  /// The layer computing a tensor of the graph of the registry: that of the nearest parameter the tensor depends on,
+  /// named as the parameter up to the last dot (`layer0` of `layer0.weight`).
+  pub fn layer_of(&self, graph: &Graph, x: NodeIndex) -> Option<String> {
+    let mut seen = HashSet::from([x]);
+    let mut queue = VecDeque::from([x]);
+    while let Some(y) = queue.pop_front() {
+      if let Some(p) = self.by_id(y) {
+        let layer = p
+          .name
+          .rsplit_once('.')
+          .map_or(p.name.as_str(), |(layer, _)| layer);
+        return Some(layer.to_string());
+      }
+      for z in graph.neighbors_directed(y, Incoming) {
+        if seen.insert(z) {
+          queue.push_back(z);
+        }
+      }
+    }
+    None
+  }
+}
+
+impl GraphForSnark {
+  /// The weight of the named parameter.
+  pub fn weight(&self, name: &str) -> Option<&[f32]> {
+    let id = self.params.get(name)?.id;
+    self
+      .weights
+      .iter()
+      .find(|(x, _)| *x == id)
+      .map(|(_, w)| w.as_slice())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::scalar::scalar;
+
+  #[test]
+  fn test_params_follow_the_graph() {
+    let model = crate::model::fixed_weights::run_model().graph;
+    let copy = model.copy_graph_roughly();
+    assert_eq!(copy.params.params.len(), model.weights.len());
+    for p in model.params.params.iter() {
+      assert_eq!(copy.weight(&p.name), model.weight(&p.name));
+      let q = copy.params.get(&p.name).unwrap();
+      assert_eq!(p.shape, q.shape);
+      assert_eq!(
+        p.shape.iter().product::<usize>(),
+        model.weight(&p.name).unwrap().len()
+      );
+    }
+    let (sc, _) = scalar(&copy.graph);
+    let first = copy.params.get("layer0.weight").unwrap();
+    let little = sc.inputs_tracker.new_inputs[&first.id][1];
+    let (p, i) = copy.params.origin_of(&sc, little).unwrap();
+    assert_eq!((p.name.as_str(), i), ("layer0.weight", 1));
+    assert_eq!(
+      copy
+        .params
+        .layer_of(&copy.graph, copy.outputs[0])
+        .as_deref(),
+      Some("layer2")
+    );
+    assert_eq!(copy.params.layer_of(&copy.graph, copy.input_id), None);
+  }
+}
@@ -0,0 +1,108 @@
+//!
+//! Quantization-aware training: the model trains on the weights and inputs the circuit computes with.
+//!
+//! The circuit works on fixed-point values, `round(x * 2^scale_bits)` (see [QuantConfig]), so the proven model is the
+//! trained one with every weight and input rounded. Trained in floats, the rounding costs accuracy the training
+//! never saw. [FakeQuant] keeps the float weights the optimizer updates and runs every step on them quantized.
+//! The update computed at the quantized weights moves the float ones as it is, the rounding counted as the identity
+//! in the gradient: the straight-through estimator. The weights of the trained model are the quantized ones.
+//!
+//! The products inside the layers are rescaled, rounded, in the circuit too. That's half a unit of the last place per
+//! product, it's not simulated.
+//!
+
+use luminal::{
+  graph::Graph,
+  op::{Function, Operator},
+  prelude::{NodeIndex, Tensor},
+};
+
+use crate::{
+  dtype::tensor_f32,
+  model::device::{tensor_data, ON_GPU},
+  quant::QuantConfig,
+};
+
+/// The float weights of quantization-aware training, see the module docs.
+#[derive(Debug, Clone)]
+pub struct FakeQuant {
+  pub quant: QuantConfig,
+  /// The float weights by the weight node, the graph has them quantized.
+  pub shadow: Vec<(NodeIndex, Vec<f32>)>,
+}
+
+impl FakeQuant {
+  /// Takes the initial weights from their source ops (see [super::seed_weights]), the graph starts from them quantized.
+  /// The graph is the one compiled for training, the weights are kept in it.
+  pub fn new(quant: QuantConfig, cx: &mut Graph, weights: &[NodeIndex]) -> Self {
+    assert!(!ON_GPU, "Quantization-aware training runs on the CPU");
+    let fake_quant = FakeQuant {
+      quant,
+      shadow: weights
+        .iter()
+        .map(|w| {
+          let data = tensor_f32(&cx.get_op_mut::<Function>(*w).process(vec![])[0]).unwrap();
+          (*w, data)
+        })
+        .collect(),
+    };
+    fake_quant.set_quantized(cx);
+    fake_quant
+  }
+
+  pub fn quantize(&self, data: &[f32]) -> Vec<f32> {
+    data.iter().map(|x| self.quant.fake_quantize(*x)).collect()
+  }
+
+  /// Sets the weights of the graph to the float ones quantized, the next execution doesn't recompute them.
+  fn set_quantized(&self, cx: &mut Graph) {
+    for (w, data) in self.shadow.iter() {
+      cx.tensors.insert((*w, 0), Tensor::new(self.quantize(data)));
+    }
+  }
+
+  /// After a step, its new weights transferred to the weights: moves the float weights by the update of the quantized
+  /// ones and quantizes them again for the next step.
+  pub fn step(&mut self, cx: &mut Graph) {
+    for (w, data) in self.shadow.iter_mut() {
+      let before = data
+        .iter()
+        .map(|x| self.quant.fake_quantize(*x))
+        .collect::<Vec<f32>>();
+      let after = tensor_data(cx, *w);
+      for ((x, b), a) in data.iter_mut().zip(before).zip(after) {
+        *x += a - b;
+      }
+    }
+    self.set_quantized(cx);
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::{
+    model::{medium_model::run_model, parse_dataset, TrainParams},
+    quant::QuantConfig,
+  };
+
+  #[test]
+  fn test_quantization_aware_training() {
+    let data = parse_dataset(include_str!("../../data/rp.data").to_string());
+    let quant = QuantConfig {
+      scale_bits: 6,
+      ..Default::default()
+    };
+    let trained = run_model(TrainParams {
+      data,
+      quantization: Some(quant),
+      ..Default::default()
+    });
+    // the trained weights are the quantized ones, exact in the circuit
+    for (_, w) in trained.graph.weights.iter() {
+      assert!(w.iter().all(|x| quant.fake_quantize(*x) == *x));
+    }
+    let input = vec![0.5; 9];
+    let score = trained.evaluate(input)[&trained.graph.outputs[0]].clone();
+    assert!(score[0].is_finite());
+  }
+}
@@ -0,0 +1,209 @@
+//!
+//! Weights in the safetensors format (https://github.com/huggingface/safetensors), to exchange them with PyTorch tooling
+//! and audit them apart from the graph.
+//!
+//! The format is simple enough to write by hand: a little-endian u64 length of the JSON header,
+//! the header mapping tensor names to `{"dtype", "shape", "data_offsets"}`, then the raw data.
+//! We write F32 unless told otherwise and read every [DType], converted to f32.
+//!
+
+use std::{error::Error, fs, path::Path};
+
+use luminal::prelude::NodeIndex;
+use serde_json::{json, Map, Value};
+
+use super::GraphForSnark;
+use crate::dtype::DType;
+
+/// A tensor of the file, data in row-major order, as f32 whatever the dtype in the file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct NamedTensor {
+  pub name: String,
+  pub shape: Vec<usize>,
+  pub data: Vec<f32>,
+}
+
+/// The bytes of a safetensors file with the tensors, the data in the given order.
+pub fn write_safetensors(tensors: &[NamedTensor]) -> Vec<u8> {
+  write_safetensors_with(tensors, DType::F32)
+}
+
+/// [write_safetensors] with the data stored as the dtype, rounded by [DType::encode].
+pub fn write_safetensors_with(tensors: &[NamedTensor], dtype: DType) -> Vec<u8> {
+  let mut header = Map::new();
+  let mut data: Vec<u8> = vec![];
+  for t in tensors {
+    let begin = data.len();
+    data.extend(dtype.encode(&t.data));
+    header.insert(
+      t.name.clone(),
+      json!({ "dtype": dtype.name(), "shape": t.shape, "data_offsets": [begin, data.len()] }),
+    );
+  }
+  let mut header = Value::Object(header).to_string().into_bytes();
+  // the data is aligned to 8 bytes by padding the header with spaces
+  header.resize(header.len().div_ceil(8) * 8, b' ');
+  let mut bytes = (header.len() as u64).to_le_bytes().to_vec();
+  bytes.extend(header);
+  bytes.extend(data);
+  bytes
+}
+
+/// The tensors of a safetensors file, in the order of their data.
+pub fn read_safetensors(bytes: &[u8]) -> Result<Vec<NamedTensor>, Box<dyn Error>> {
+  let header_len = bytes
+    .get(0..8)
+    .map(|b| u64::from_le_bytes(b.try_into().unwrap()) as usize)
+    .ok_or("Missing the header length")?;
+  let header = bytes.get(8..8 + header_len).ok_or("Truncated header")?;
+  let data = &bytes[8 + header_len..];
+  let header: Map<String, Value> = serde_json::from_slice(header)?;
+  let mut tensors = vec![];
+  for (name, info) in header.into_iter().filter(|(k, _)| k != "__metadata__") {
+    let dtype = info["dtype"].as_str().unwrap_or_default();
+    let dtype = DType::from_name(dtype).ok_or_else(|| {
+      format!(
+        "Tensor {} has dtype {}, only F32, F16, BF16 and I8 are supported",
+        name, dtype
+      )
+    })?;
+    let shape: Vec<usize> = serde_json::from_value(info["shape"].clone())?;
+    let (begin, end): (usize, usize) = serde_json::from_value(info["data_offsets"].clone())?;
+    let raw = data
+      .get(begin..end)
+      .ok_or_else(|| format!("Data of tensor {} out of the file", name))?;
+    if raw.len() != dtype.size() * shape.iter().product::<usize>() {
+      return Err(
+        format!(
+          "Tensor {} has {} bytes of data for the shape {:?}",
+          name,
+          raw.len(),
+          shape
+        )
+        .into(),
+      );
+    }
+    let data = dtype.decode(raw);
+    tensors.push((begin, NamedTensor { name, shape, data }));
+  }
+  tensors.sort_by_key(|(begin, _)| *begin);
+  Ok(tensors.into_iter().map(|(_, t)| t).collect())
+}
+
+impl GraphForSnark {
+  /// Names of the weights in the safetensors file, from [GraphForSnark::params].
+  /// A weight missing from the registry is named `param{i}` for the i-th of [GraphForSnark::weights].
+  pub fn weight_names(&self) -> Vec<(String, NodeIndex)> {
+    self
+      .weights
+      .iter()
+      .enumerate()
+      .map(|(i, (x, _))| match self.params.by_id(*x) {
+        Some(p) => (p.name.clone(), *x),
+        None => (format!("param{}", i), *x),
+      })
+      .collect()
+  }
+
+  /// The weights as a safetensors file, named by [GraphForSnark::weight_names].
+  /// The shapes are the registered ones, unregistered weights are flat.
+  pub fn weights_safetensors(&self) -> Vec<u8> {
+    let tensors: Vec<NamedTensor> = self
+      .weight_names()
+      .into_iter()
+      .zip(self.weights.iter())
+      .map(|((name, x), (_, w))| NamedTensor {
+        name,
+        shape: match self.params.by_id(x) {
+          Some(p) => p.shape.clone(),
+          None => vec![w.len()],
+        },
+        data: w.clone(),
+      })
+      .collect();
+    write_safetensors(&tensors)
+  }
+
+  pub fn export_weights_safetensors(&self, path: &Path) -> Result<(), Box<dyn Error>> {
+    fs::write(path, self.weights_safetensors())?;
+    Ok(())
+  }
+
+  /// Replaces the weights with the tensors of the safetensors file, matched by [GraphForSnark::weight_names].
+  /// Every weight needs a tensor of the same number of elements, the shape doesn't matter.
+  pub fn set_weights_safetensors(&mut self, bytes: &[u8]) -> Result<(), Box<dyn Error>> {
+    let tensors = read_safetensors(bytes)?;
+    let mut weights = vec![];
+    for ((name, x), (_, w)) in self.weight_names().into_iter().zip(self.weights.iter()) {
+      let t = tensors
+        .iter()
+        .find(|t| t.name == name)
+        .ok_or_else(|| format!("Missing weight {}", name))?;
+      if t.data.len() != w.len() {
+        return Err(
+          format!(
+            "Weight {} has {} elements, expected {}",
+            name,
+            t.data.len(),
+            w.len()
+          )
+          .into(),
+        );
+      }
+      weights.push((x, t.data.clone()));
+    }
+    self.weights = weights;
+    Ok(())
+  }
+
+  pub fn import_weights_safetensors(&mut self, path: &Path) -> Result<(), Box<dyn Error>> {
+    self.set_weights_safetensors(&fs::read(path)?)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{read_safetensors, write_safetensors, write_safetensors_with, NamedTensor};
+  use crate::dtype::DType;
+
+  #[test]
+  fn test_safetensors_roundtrip() {
+    let tensors = vec![
+      NamedTensor {
+        name: "b".to_string(),
+        shape: vec![2, 3],
+        data: vec![1.0, -2.0, 3.5, 0.0, 1e-3, 7.0],
+      },
+      NamedTensor {
+        name: "a".to_string(),
+        shape: vec![1],
+        data: vec![0.25],
+      },
+    ];
+    let bytes = write_safetensors(&tensors);
+    let header_len = u64::from_le_bytes(bytes[0..8].try_into().unwrap()) as usize;
+    assert_eq!(header_len % 8, 0);
+    assert_eq!(bytes.len(), 8 + header_len + 4 * 7);
+    assert_eq!(read_safetensors(&bytes).unwrap(), tensors);
+    // 0.25 is exact in half precision, i8 rounds 3.5 away from zero and 1e-3 and 0.25 to zero
+    let half = write_safetensors_with(&tensors[1..], DType::F16);
+    assert_eq!(read_safetensors(&half).unwrap(), tensors[1..].to_vec());
+    let quantized = read_safetensors(&write_safetensors_with(&tensors, DType::I8)).unwrap();
+    assert_eq!(quantized[0].data, vec![1.0, -2.0, 4.0, 0.0, 0.0, 7.0]);
+    assert_eq!(quantized[1].data, vec![0.0]);
+
+    let mut model = crate::model::fixed_weights::run_model().graph;
+    let exported = model.weights_safetensors();
+    let original = model.weights.clone();
+    model.weights.iter_mut().for_each(|(_, w)| w.fill(0.0));
+    model.set_weights_safetensors(&exported).unwrap();
+    assert_eq!(model.weights, original);
+    let names: Vec<String> = read_safetensors(&exported)
+      .unwrap()
+      .into_iter()
+      .map(|t| t.name)
+      .collect();
+    assert_eq!(names, vec!["layer0.weight", "layer2.weight"]);
+    assert!(model.set_weights_safetensors(&bytes).is_err());
+  }
+}
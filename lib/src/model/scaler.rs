@@ -0,0 +1,140 @@
+use serde::{Deserialize, Serialize};
+
+/// How a [Scaler] normalizes every feature.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ScalerKind {
+  /// x => (x - min) / (max - min), maps the train data onto [0, 1]
+  MinMax,
+  /// x => (x - mean) / std
+  ZScore,
+}
+
+/// Per feature normalization, fitted on the train data and then applied to any other data (test data, prover's input).
+///
+/// Every feature gets normalized by an affine map `x => (x - offset) * factor`.
+/// The parameters are plain floats so the same normalization can be baked into the snark as constants, see [Scaler::affine].
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Scaler {
+  pub kind: ScalerKind,
+  pub offsets: Vec<f32>,
+  pub factors: Vec<f32>,
+}
+
+impl Scaler {
+  /// Fit the scaler on rows of the train data. All rows are expected to have the same number of features.
+  pub fn fit<R: AsRef<[f32]>>(kind: ScalerKind, data: &[R]) -> Self {
+    let n_features = data.first().map(|r| r.as_ref().len()).unwrap_or(0);
+    assert!(
+      data.iter().all(|r| r.as_ref().len() == n_features),
+      "All rows are expected to have the same number of features"
+    );
+    let (offsets, spreads): (Vec<f32>, Vec<f32>) = (0..n_features)
+      .map(|i| {
+        let column = data.iter().map(|r| r.as_ref()[i]);
+        match kind {
+          ScalerKind::MinMax => {
+            let (min, max) = column.fold((f32::INFINITY, f32::NEG_INFINITY), |(min, max), a| {
+              (f32::min(min, a), f32::max(max, a))
+            });
+            (min, max - min)
+          }
+          ScalerKind::ZScore => {
+            let n = data.len() as f32;
+            let mean = column.clone().sum::<f32>() / n;
+            let var = column.map(|a| (a - mean) * (a - mean)).sum::<f32>() / n;
+            (mean, var.sqrt())
+          }
+        }
+      })
+      .unzip();
+    // a constant feature carries no information, just move it to zero instead of dividing by zero
+    let factors = spreads
+      .into_iter()
+      .map(|d| {
+        if d > 0.0 && d.is_finite() {
+          1.0 / d
+        } else {
+          1.0
+        }
+      })
+      .collect();
+    Scaler {
+      kind,
+      offsets,
+      factors,
+    }
+  }
+
+  pub fn n_features(&self) -> usize {
+    self.offsets.len()
+  }
+
+  pub fn transform_row(&self, row: &[f32]) -> Vec<f32> {
+    assert!(
+      row.len() == self.n_features(),
+      "Scaler fitted on {} features, got a row of {}",
+      self.n_features(),
+      row.len()
+    );
+    row
+      .iter()
+      .zip(self.offsets.iter().zip(self.factors.iter()))
+      .map(|(x, (o, f))| (x - o) * f)
+      .collect()
+  }
+
+  pub fn transform<const N: usize>(&self, data: &[[f32; N]]) -> Vec<[f32; N]> {
+    data
+      .iter()
+      .map(|row| {
+        let mut out = [0.0; N];
+        out.copy_from_slice(&self.transform_row(row));
+        out
+      })
+      .collect()
+  }
+
+  /// The normalization as `x => x * mul + add` per feature.
+  /// That's the form that maps directly onto a Mul and an Add with constants in the circuit.
+  pub fn affine(&self) -> Vec<(f32, f32)> {
+    self
+      .offsets
+      .iter()
+      .zip(self.factors.iter())
+      .map(|(o, f)| (*f, -o * f))
+      .collect()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{Scaler, ScalerKind};
+
+  #[test]
+  fn test_minmax_fits_train_onto_unit_range() {
+    let train = vec![[1.0, 5.0, 3.0], [3.0, 10.0, 3.0], [2.0, 7.5, 3.0]];
+    let scaler = Scaler::fit(ScalerKind::MinMax, &train);
+    let out = scaler.transform(&train);
+    assert_eq!(out[0], [0.0, 0.0, 0.0]);
+    assert_eq!(out[1], [1.0, 1.0, 0.0]);
+    assert_eq!(out[2], [0.5, 0.5, 0.0]);
+  }
+
+  #[test]
+  fn test_affine_matches_transform() {
+    let train = vec![[1.0, -2.0], [4.0, 2.0], [7.0, 0.5]];
+    let scaler = Scaler::fit(ScalerKind::ZScore, &train);
+    let row = [3.0, 1.0];
+    let out = scaler.transform_row(&row);
+    for ((x, (mul, add)), y) in row.iter().zip(scaler.affine()).zip(out) {
+      assert!((x * mul + add - y).abs() < 1e-5);
+    }
+  }
+
+  #[test]
+  fn test_serde_roundtrip() {
+    let scaler = Scaler::fit(ScalerKind::MinMax, &[[0.0, 1.0], [2.0, 3.0]]);
+    let s = serde_json::to_string(&scaler).unwrap();
+    assert_eq!(scaler, serde_json::from_str::<Scaler>(&s).unwrap());
+  }
+}
@@ -0,0 +1,280 @@
+//!
+//! Architecture search under a circuit budget: the widths and depths of a [ModelSpec] swept for the most accurate
+//! model whose circuit fits.
+//!
+//! The cost of the circuit (see [crate::cost]) depends on the architecture alone, not on the weights, so every
+//! candidate is costed from its graph before any training ([ModelSpec::cost]). Only the candidates within the budget
+//! are trained ([train_spec], as the [super::zoo] models train) and tested on the test split of the dataset.
+//!
+
+use std::cell::RefCell;
+
+use luminal::prelude::*;
+use luminal_training::{mse_loss, Autograd};
+use rand::{rngs::StdRng, SeedableRng};
+use tracing::info;
+
+use crate::{
+  accuracy::path_stats,
+  cost::{estimate_cost, CostBreakdown, CostModel},
+  model::{
+    device::tensor_data,
+    epoch_order,
+    optim::{check_finite, sgd_clipped},
+    seed_weights, split_dataset, Activation, GraphForSnark, LayerSpec, LrSchedule, ModelSpec,
+    Scaler, ScalerKind, TrainParams, TrainedGraph,
+  },
+  scalar::{copy_graph_roughly, scalar},
+};
+
+impl ModelSpec {
+  /// The cost of proving the model with the backend of the cost model, whatever the weights.
+  pub fn cost(&self, model: &dyn CostModel) -> CostBreakdown {
+    let mut cx = Graph::new();
+    self.forward(&mut cx);
+    estimate_cost(&scalar(&cx).0, model)
+  }
+}
+
+/// The architectures to try: every depth with every width.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SearchSpace {
+  /// Units of the hidden layers.
+  pub widths: Vec<usize>,
+  /// Number of hidden layers.
+  pub depths: Vec<usize>,
+  /// After every hidden layer.
+  pub activation: Activation,
+}
+
+impl SearchSpace {
+  /// `depth` hidden layers of `width` units, each followed by the activation, then a linear layer to the single
+  /// output. Depth 0 is the linear model, tried once whatever the widths.
+  pub fn candidates(&self, inputs: usize) -> Vec<ModelSpec> {
+    let mut candidates = vec![];
+    for depth in self.depths.iter() {
+      let widths = match depth {
+        0 => &self.widths[..self.widths.len().min(1)],
+        _ => &self.widths[..],
+      };
+      for width in widths {
+        let mut layers = vec![];
+        for _ in 0..*depth {
+          layers.push(LayerSpec::Linear { outputs: *width });
+          layers.push(LayerSpec::Activation(self.activation));
+        }
+        layers.push(LayerSpec::Linear { outputs: 1 });
+        candidates.push(ModelSpec { inputs, layers });
+      }
+    }
+    candidates
+  }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Candidate {
+  pub spec: ModelSpec,
+  /// Total of the [CostBreakdown].
+  pub cost: usize,
+  /// Within the budget and provable by the backend, so trained.
+  pub feasible: bool,
+  /// On the test split, `None` for the candidates not trained.
+  pub accuracy: Option<f32>,
+}
+
+#[derive(Debug)]
+pub struct SearchResult {
+  /// In the order of [SearchSpace::candidates].
+  pub candidates: Vec<Candidate>,
+  /// The most accurate feasible candidate (the cheapest of the equally accurate), by its index, and its model.
+  /// `None` if no candidate fits the budget.
+  pub best: Option<(usize, TrainedGraph)>,
+}
+
+/// Trains the candidates of the space that cost at most `budget` with the backend of the cost model, see the module
+/// docs. The dataset, the epochs, the seed, the learning rate and the clipping are of the params, the rest is ignored:
+/// the models output a score.
+pub fn search(
+  space: &SearchSpace,
+  budget: usize,
+  cost_model: &dyn CostModel,
+  train_params: TrainParams,
+) -> SearchResult {
+  let (x, y) = train_params.data;
+  let (x_train, x_test, y_train, y_test) = split_dataset(x, y, 0.8);
+  let scaler = Scaler::fit(ScalerKind::MinMax, &x_train);
+  let samples: Vec<(Vec<f32>, Vec<f32>)> = scaler
+    .transform(&x_train)
+    .into_iter()
+    .zip(y_train)
+    .map(|(x, y)| (x.to_vec(), vec![y]))
+    .collect();
+  let x_test: Vec<Vec<f32>> = scaler
+    .transform(&x_test)
+    .into_iter()
+    .map(|x| x.to_vec())
+    .collect();
+  let inputs = samples.first().map_or(0, |(x, _)| x.len());
+
+  let mut candidates = vec![];
+  let mut best: Option<(usize, TrainedGraph)> = None;
+  for (i, spec) in space.candidates(inputs).into_iter().enumerate() {
+    let cost = spec.cost(cost_model);
+    let feasible = cost.unsupported.is_empty() && cost.total <= budget;
+    info!("Candidate {:?}: cost {}", spec.layers, cost.total);
+    let mut candidate = Candidate {
+      spec,
+      cost: cost.total,
+      feasible,
+      accuracy: None,
+    };
+    if feasible {
+      let mut trained = train_spec(
+        &candidate.spec,
+        &samples,
+        train_params.epochs,
+        train_params.seed,
+        train_params.lr,
+        train_params.clip_grad_norm,
+      );
+      trained.scaler = Some(scaler.clone());
+      let output = trained.graph.outputs[0];
+      let outputs: Vec<f32> = x_test
+        .iter()
+        .map(|x| trained.evaluate(x.clone())[&output][0])
+        .collect();
+      let accuracy = path_stats(&outputs, &outputs, &y_test).accuracy;
+      info!(
+        "Candidate {:?}: accuracy {:.3}",
+        candidate.spec.layers, accuracy
+      );
+      candidate.accuracy = Some(accuracy);
+      let better = match &best {
+        None => true,
+        Some((j, _)) => {
+          let (best_accuracy, best_cost) = (candidates[*j].accuracy.unwrap(), candidates[*j].cost);
+          accuracy > best_accuracy || (accuracy == best_accuracy && cost.total < best_cost)
+        }
+      };
+      if better {
+        best = Some((i, trained));
+      }
+    }
+    candidates.push(candidate);
+  }
+  SearchResult { candidates, best }
+}
+
+/// Trains the model of the spec (of a single output) on the `(input, target)` samples as [super::zoo::train] trains.
+pub fn train_spec(
+  spec: &ModelSpec,
+  samples: &[(Vec<f32>, Vec<f32>)],
+  epochs: usize,
+  seed: u64,
+  lr: LrSchedule,
+  clip_grad_norm: Option<f32>,
+) -> TrainedGraph {
+  assert_eq!(spec.outputs(), 1, "Training models of a single output");
+  let mut cx = Graph::new();
+  let (input, output, params) = spec.forward(&mut cx);
+  let weights: Vec<NodeIndex> = params.params.iter().map(|p| p.id).collect();
+  let mut rng = StdRng::seed_from_u64(seed);
+  seed_weights(&mut cx, &weights, &mut rng);
+  // record graph without gradients
+  let (cx_og, remap) = copy_graph_roughly(&cx);
+
+  let graph_ref: *mut Graph = &mut cx;
+  let prediction = GraphTensor::<R1<1>>::from_id(output, R1::<1>::to_tracker(), graph_ref);
+  let target = cx.tensor::<R1<1>>();
+  let loss = mse_loss(prediction, target).retrieve();
+  let grads = cx.compile(Autograd::new(&weights, loss), ());
+  let (new_weights, rate) = sgd_clipped(&mut cx, &weights, &grads, clip_grad_norm);
+  cx.keep_tensors(&new_weights);
+  cx.keep_tensors(&weights);
+
+  let mut iter = 0;
+  for epoch in 0..epochs {
+    rate.set(lr.lr(epoch, epochs));
+    for i in epoch_order(samples.len(), &mut rng) {
+      let (x, y) = &samples[i];
+      // the input is a load without data, see ModelSpec::forward
+      cx.tensors.insert((input, 0), Tensor::new(x.clone()));
+      target.set(y.clone());
+      cx.execute();
+      check_finite("the loss", &loss.data(), iter).unwrap_or_else(|e| panic!("{}", e));
+      transfer_data_same_graph(&new_weights, &weights, &mut cx);
+      loss.drop();
+      prediction.drop();
+      iter += 1;
+    }
+  }
+
+  let cx_weights: Vec<(NodeIndex, Vec<f32>)> =
+    weights.iter().map(|a| (*a, tensor_data(&cx, *a))).collect();
+  for (w, data) in cx_weights.iter() {
+    check_finite(&format!("weight {:?}", w), data, iter).unwrap_or_else(|e| panic!("{}", e));
+  }
+  TrainedGraph {
+    graph: GraphForSnark {
+      graph: cx_og,
+      weights: cx_weights
+        .iter()
+        .map(|(a, b)| (remap[a], b.clone()))
+        .collect(),
+      input_id: remap[&input],
+      outputs: vec![remap[&output]],
+      params: params.remap(&remap),
+    },
+    cx: RefCell::new(cx),
+    cx_weights,
+    cx_output_ids: vec![output],
+    cx_input_id: input,
+    cx_target_id: target.id,
+    scaler: None,
+    threshold: None,
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{search, SearchSpace};
+  use crate::{
+    model::{parse_dataset, Activation, TrainParams},
+    snark::backend::Groth16Backend,
+  };
+
+  #[test]
+  fn test_search_under_budget() {
+    let data = parse_dataset(include_str!("../../../data/rp.data").to_string());
+    let space = SearchSpace {
+      widths: vec![2, 8],
+      depths: vec![0, 1],
+      activation: Activation::Relu,
+    };
+    let specs = space.candidates(9);
+    assert_eq!(specs.len(), 3);
+    // the narrow hidden layer fits, the wide one doesn't
+    let budget = specs[1].cost(&Groth16Backend).total;
+    assert!(specs[2].cost(&Groth16Backend).total > budget);
+
+    let result = search(
+      &space,
+      budget,
+      &Groth16Backend,
+      TrainParams {
+        data,
+        ..Default::default()
+      },
+    );
+    let feasible = result
+      .candidates
+      .iter()
+      .map(|c| (c.feasible, c.accuracy.is_some()))
+      .collect::<Vec<_>>();
+    assert_eq!(feasible, vec![(true, true), (true, true), (false, false)]);
+    let (best, trained) = result.best.unwrap();
+    assert!(best < 2);
+    assert!(result.candidates[best].cost <= budget);
+    assert_eq!(trained.graph.weights.len(), best + 1);
+  }
+}
@@ -0,0 +1,224 @@
+//!
+//! Models trained elsewhere: the graph for the snark built from a description of the architecture and named weights,
+//! e.g. from a safetensors file exported by PyTorch. No training, no evaluation graph, see [GraphForSnark::from_spec_and_weights].
+//!
+//! The layers are the ones of the models here: a linear layer (no bias) multiplies by a `(inputs, outputs)` weight
+//! named `layer<i>.weight`, `i` its position among the layers, as in PyTorch's `nn.Sequential`
+//! (a PyTorch `nn.Linear` weight is `(outputs, inputs)`, transpose it on export).
+//!
+
+use std::error::Error;
+
+use luminal::{
+  graph::Graph,
+  op::{Function, Mul, SumReduce},
+  prelude::{NodeIndex, ShapeTracker},
+  shape::Expression,
+};
+use serde::{Deserialize, Serialize};
+
+use super::{safetensors::NamedTensor, Activation, GraphForSnark, ParamRegistry};
+
+/// The weights of a model by name, as read by [super::safetensors::read_safetensors].
+pub type NamedWeights = Vec<NamedTensor>;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum LayerSpec {
+  /// `x · W` for the weight `W` of shape `(inputs, outputs)`.
+  Linear { outputs: usize },
+  /// Elementwise, e.g. `{"kind": "activation", "function": "leaky_relu", "alpha": 0.01}`.
+  Activation(Activation),
+}
+
+/// A feed forward model on a vector input, the result of the last layer retrieved.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ModelSpec {
+  pub inputs: usize,
+  pub layers: Vec<LayerSpec>,
+}
+
+impl ModelSpec {
+  /// The architecture of the [super::medium_model].
+  pub fn medium() -> Self {
+    let linear = |outputs| LayerSpec::Linear { outputs };
+    let relu = LayerSpec::Activation(Activation::Relu);
+    ModelSpec {
+      inputs: 9,
+      layers: vec![linear(16), relu.clone(), linear(16), relu, linear(1)],
+    }
+  }
+}
+
+pub(super) fn contiguous(shape: &[usize]) -> ShapeTracker {
+  ShapeTracker::new(
+    &shape
+      .iter()
+      .map(|d| Expression::from(*d))
+      .collect::<Vec<_>>(),
+  )
+}
+
+fn source(cx: &mut Graph, name: &str) -> NodeIndex {
+  cx.add_op(Function(
+    name.to_string(),
+    Box::new(|_| panic!("set the input first")),
+  ))
+  .finish()
+}
+
+impl ModelSpec {
+  /// Size of the result of the model.
+  pub fn outputs(&self) -> usize {
+    self
+      .layers
+      .iter()
+      .rev()
+      .find_map(|layer| match layer {
+        LayerSpec::Linear { outputs } => Some(*outputs),
+        LayerSpec::Activation(_) => None,
+      })
+      .unwrap_or(self.inputs)
+  }
+
+  /// Adds the forward graph of the model to `cx`, the result retrieved. Returns the input, the result and the weights
+  /// of the linear layers, named as in the module docs. The input and the weights are loads without data, set them.
+  pub fn forward(&self, cx: &mut Graph) -> (NodeIndex, NodeIndex, ParamRegistry) {
+    let input_id = source(cx, "Input");
+    let mut params = ParamRegistry::default();
+    let (mut x, mut n) = (input_id, self.inputs);
+    for (i, layer) in self.layers.iter().enumerate() {
+      match layer {
+        LayerSpec::Linear { outputs } => {
+          let name = format!("layer{}.weight", i);
+          let shape = vec![n, *outputs];
+          let w_id = source(cx, &name);
+          params.register(&name, w_id, shape.clone());
+          // x broadcast over the columns of w, the products summed over the rows
+          let mut x_sh = contiguous(&[n]);
+          x_sh.expand(1, *outputs);
+          let products = cx
+            .add_op(Mul {})
+            .input(x, 0, x_sh)
+            .input(w_id, 0, contiguous(&shape))
+            .finish();
+          x = cx
+            .add_op(SumReduce(0))
+            .input(products, 0, contiguous(&shape))
+            .finish();
+          n = *outputs;
+        }
+        LayerSpec::Activation(activation) => {
+          x = activation.apply(cx, x, n);
+        }
+      }
+    }
+    cx.no_delete.insert(x);
+    cx.to_retrieve.insert(x, (0, contiguous(&[n])));
+    (input_id, x, params)
+  }
+}
+
+impl GraphForSnark {
+  /// The forward graph of the model with the weights, see the module docs. Every linear layer needs its weight,
+  /// of the right shape. Weights the spec doesn't use are an error too, they're likely misnamed.
+  pub fn from_spec_and_weights(
+    spec: &ModelSpec,
+    weights: NamedWeights,
+  ) -> Result<Self, Box<dyn Error>> {
+    let mut cx = Graph::new();
+    let (input_id, output, params) = spec.forward(&mut cx);
+    let mut graph_weights = vec![];
+    for p in params.params.iter() {
+      let w = weights
+        .iter()
+        .find(|t| t.name == p.name)
+        .ok_or_else(|| format!("Missing weight {}", p.name))?;
+      if w.shape != p.shape {
+        return Err(
+          format!(
+            "Weight {} has shape {:?}, expected {:?}",
+            p.name, w.shape, p.shape
+          )
+          .into(),
+        );
+      }
+      graph_weights.push((p.id, w.data.clone()));
+    }
+    if let Some(t) = weights.iter().find(|t| params.get(&t.name).is_none()) {
+      return Err(format!("Weight {} is not in the spec", t.name).into());
+    }
+    Ok(GraphForSnark {
+      graph: cx,
+      input_id,
+      weights: graph_weights,
+      outputs: vec![output],
+      params,
+    })
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::collections::HashMap;
+
+  use luminal::prelude::NodeIndex;
+
+  use super::{Activation, LayerSpec, ModelSpec};
+  use crate::{
+    compile_graph,
+    model::{
+      safetensors::{read_safetensors, NamedTensor},
+      GraphForSnark,
+    },
+    scalar::{evaluate_tensor_graph, scalar, verify_scalarization},
+  };
+
+  fn evaluate(g: &GraphForSnark, input: Vec<f32>) -> Vec<f32> {
+    let mut inputs: HashMap<NodeIndex, Vec<f32>> = g.weights.iter().cloned().collect();
+    inputs.insert(g.input_id, input);
+    evaluate_tensor_graph(&g.graph, &inputs)[&g.outputs[0]].clone()
+  }
+
+  #[test]
+  fn test_from_spec_and_weights() {
+    let trained = crate::model::fixed_weights::run_model().graph;
+    let spec = ModelSpec {
+      inputs: 3,
+      layers: vec![
+        LayerSpec::Linear { outputs: 2 },
+        LayerSpec::Activation(Activation::Relu),
+        LayerSpec::Linear { outputs: 1 },
+      ],
+    };
+    let weights = read_safetensors(&trained.weights_safetensors()).unwrap();
+    let imported = GraphForSnark::from_spec_and_weights(&spec, weights.clone()).unwrap();
+    for input in [vec![1.0, 2.0, 3.0], vec![-1.0, 0.5, -3.0]] {
+      assert_eq!(
+        evaluate(&imported, input.clone()),
+        evaluate(&trained, input)
+      );
+    }
+    let (sc, _) = scalar(&imported.graph);
+    let mut inputs: HashMap<NodeIndex, Vec<f32>> = imported.weights.iter().cloned().collect();
+    inputs.insert(imported.input_id, vec![1.0, -2.0, 3.0]);
+    assert_eq!(verify_scalarization(&imported.graph, &sc, &inputs), Ok(()));
+    let snark = compile_graph(&imported);
+    assert_eq!(
+      snark.graph.inputs_tracker.new_inputs[&imported.input_id].len(),
+      3
+    );
+
+    let mut wrong_shape = spec.clone();
+    wrong_shape.layers[0] = LayerSpec::Linear { outputs: 3 };
+    assert!(GraphForSnark::from_spec_and_weights(&wrong_shape, weights.clone()).is_err());
+    assert!(GraphForSnark::from_spec_and_weights(&spec, weights[..1].to_vec()).is_err());
+    let mut extra = weights.clone();
+    extra.push(NamedTensor {
+      name: "layer3.weight".to_string(),
+      shape: vec![1],
+      data: vec![0.0],
+    });
+    assert!(GraphForSnark::from_spec_and_weights(&spec, extra).is_err());
+  }
+}
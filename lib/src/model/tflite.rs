@@ -0,0 +1,1043 @@
+//!
+//! Models quantized by TensorFlow Lite: a `.tflite` flatbuffer imported straight into a [QuantizedGraph], behind the
+//! `tflite` feature.
+//!
+//! TFLite stores a quantized tensor as 8-bit integers `q` of the real values `scale * (q - zero_point)`, with a scale
+//! per tensor (or per output channel of a weight). The importer keeps those integers instead of quantizing the real
+//! values again: the scalar graph computes on them at scale 0 (see [NodeScales]), as TFLite's integer kernels do.
+//! A fully connected layer accumulates `(q_in - zp_in) * (q_w - zp_w)` and the int32 bias, then requantizes to the
+//! output as `floor(acc * M + 1/2) + zp_out` for `M = s_in * s_w / s_out`, the multiplier a constant at
+//! [MULTIPLIER_BITS] fractional bits, clamped to the range of the type and the fused activation. TFLite rounds the
+//! same products its own way, the results can be one off.
+//!
+//! The ops are FULLY_CONNECTED, RELU, SOFTMAX (on the real values, with an exp2 lookup), QUANTIZE and DEQUANTIZE, on
+//! int8 and uint8 tensors, float32 ones only into QUANTIZE and out of DEQUANTIZE. The inputs and the outputs of the
+//! model are real values at [QuantConfig::scale_bits]: integer ones are quantized on the way in and dequantized on the
+//! way out. The tensors are the ones of the first subgraph, by their index as a `NodeIndex`.
+//!
+//! The flatbuffer is read by hand, just the tables of the schema
+//! (https://github.com/tensorflow/tensorflow/blob/master/tensorflow/compiler/mlir/lite/schema/schema.fbs) we need.
+//!
+
+use std::{
+  collections::HashMap,
+  convert::{TryFrom, TryInto},
+  error::Error,
+  f32::consts::LOG2_E,
+  fs,
+  path::Path,
+};
+
+use luminal::prelude::NodeIndex;
+
+use crate::{
+  quant::{NodeScales, QuantConfig, QuantizedGraph},
+  scalar::{FrozenNode, FrozenScalarGraph, InputsTracker, LookupKind, ScalarOp, TableRegistry},
+};
+
+/// Fractional bits of the constant multipliers of the requantization, see the module docs.
+pub const MULTIPLIER_BITS: u32 = 31;
+
+// BuiltinOperator
+const DEQUANTIZE: i32 = 6;
+const FULLY_CONNECTED: i32 = 9;
+const RELU: i32 = 19;
+const SOFTMAX: i32 = 25;
+const QUANTIZE: i32 = 114;
+
+// TensorType
+const FLOAT32: i8 = 0;
+const INT32: i8 = 2;
+const UINT8: i8 = 3;
+const INT8: i8 = 9;
+
+fn read<const N: usize>(buf: &[u8], pos: usize) -> Result<[u8; N], Box<dyn Error>> {
+  buf
+    .get(pos..pos.saturating_add(N))
+    .map(|b| b.try_into().unwrap())
+    .ok_or_else(|| "Truncated flatbuffer".into())
+}
+
+fn offset_at(buf: &[u8], pos: usize) -> Result<usize, Box<dyn Error>> {
+  Ok(pos + u32::from_le_bytes(read(buf, pos)?) as usize)
+}
+
+/// A table of the flatbuffer.
+#[derive(Clone, Copy)]
+struct Table<'a> {
+  buf: &'a [u8],
+  pos: usize,
+}
+
+/// A vector of the flatbuffer, of `len` elements from `start`.
+#[derive(Clone, Copy)]
+struct Vector<'a> {
+  buf: &'a [u8],
+  start: usize,
+  len: usize,
+}
+
+impl<'a> Table<'a> {
+  /// Where the field is, none if it's left at its default.
+  fn field(&self, id: usize) -> Result<Option<usize>, Box<dyn Error>> {
+    let soffset = i32::from_le_bytes(read(self.buf, self.pos)?);
+    let vtable = usize::try_from(self.pos as i64 - soffset as i64)?;
+    let size = u16::from_le_bytes(read(self.buf, vtable)?) as usize;
+    let entry = 4 + 2 * id;
+    if entry + 2 > size {
+      return Ok(None);
+    }
+    let offset = u16::from_le_bytes(read(self.buf, vtable + entry)?) as usize;
+    Ok((offset != 0).then_some(self.pos + offset))
+  }
+
+  fn scalar<const N: usize>(&self, id: usize) -> Result<Option<[u8; N]>, Box<dyn Error>> {
+    self.field(id)?.map(|p| read(self.buf, p)).transpose()
+  }
+
+  fn i8(&self, id: usize) -> Result<i8, Box<dyn Error>> {
+    Ok(self.scalar(id)?.map_or(0, i8::from_le_bytes))
+  }
+
+  fn u32(&self, id: usize) -> Result<u32, Box<dyn Error>> {
+    Ok(self.scalar(id)?.map_or(0, u32::from_le_bytes))
+  }
+
+  fn i32(&self, id: usize) -> Result<i32, Box<dyn Error>> {
+    Ok(self.scalar(id)?.map_or(0, i32::from_le_bytes))
+  }
+
+  fn f32(&self, id: usize) -> Result<Option<f32>, Box<dyn Error>> {
+    Ok(self.scalar(id)?.map(f32::from_le_bytes))
+  }
+
+  fn table(&self, id: usize) -> Result<Option<Table<'a>>, Box<dyn Error>> {
+    let Some(p) = self.field(id)? else {
+      return Ok(None);
+    };
+    Ok(Some(Table {
+      buf: self.buf,
+      pos: offset_at(self.buf, p)?,
+    }))
+  }
+
+  /// An absent vector is empty.
+  fn vector(&self, id: usize) -> Result<Vector<'a>, Box<dyn Error>> {
+    let Some(p) = self.field(id)? else {
+      return Ok(Vector {
+        buf: self.buf,
+        start: 0,
+        len: 0,
+      });
+    };
+    let pos = offset_at(self.buf, p)?;
+    Ok(Vector {
+      buf: self.buf,
+      start: pos + 4,
+      len: u32::from_le_bytes(read(self.buf, pos)?) as usize,
+    })
+  }
+}
+
+impl<'a> Vector<'a> {
+  fn values<const N: usize, T>(&self, f: fn([u8; N]) -> T) -> Result<Vec<T>, Box<dyn Error>> {
+    (0..self.len)
+      .map(|i| read(self.buf, self.start + N * i).map(f))
+      .collect()
+  }
+
+  fn bytes(&self) -> Result<&'a [u8], Box<dyn Error>> {
+    self
+      .buf
+      .get(self.start..self.start + self.len)
+      .ok_or_else(|| "Truncated flatbuffer".into())
+  }
+
+  fn tables(&self) -> Result<Vec<Table<'a>>, Box<dyn Error>> {
+    (0..self.len)
+      .map(|i| {
+        let pos = offset_at(self.buf, self.start + 4 * i)?;
+        Ok(Table { buf: self.buf, pos })
+      })
+      .collect()
+  }
+}
+
+/// A tensor of the subgraph, with the data of its buffer if it's a constant.
+#[derive(Debug, Clone)]
+struct TensorInfo {
+  name: String,
+  shape: Vec<usize>,
+  dtype: i8,
+  data: Vec<u8>,
+  scale: Vec<f32>,
+  zero_point: Vec<i64>,
+}
+
+#[derive(Debug, Clone)]
+struct OpInfo {
+  code: i32,
+  /// -1 for an optional input left out.
+  inputs: Vec<i32>,
+  outputs: Vec<i32>,
+  /// The fused activation of the options, ActivationFunctionType.
+  activation: i8,
+  /// Of SOFTMAX.
+  beta: f32,
+}
+
+#[derive(Debug, Clone)]
+struct Subgraph {
+  tensors: Vec<TensorInfo>,
+  ops: Vec<OpInfo>,
+  inputs: Vec<usize>,
+  outputs: Vec<usize>,
+}
+
+fn parse(bytes: &[u8]) -> Result<Subgraph, Box<dyn Error>> {
+  if bytes.get(4..8) != Some(b"TFL3") {
+    return Err("Not a TFLite flatbuffer, no TFL3 identifier".into());
+  }
+  let model = Table {
+    buf: bytes,
+    pos: offset_at(bytes, 0)?,
+  };
+  let codes = model
+    .vector(1)?
+    .tables()?
+    .iter()
+    .map(|c| Ok(c.i32(3)?.max(c.i8(0)? as i32)))
+    .collect::<Result<Vec<i32>, Box<dyn Error>>>()?;
+  let buffers = model
+    .vector(4)?
+    .tables()?
+    .iter()
+    .map(|b| {
+      let data = b.vector(0)?.bytes()?;
+      // buffers of big models are after the flatbuffer, by offset and size
+      let (offset, size) = (b.scalar(1)?, b.scalar(2)?);
+      match (data.is_empty(), offset.map(u64::from_le_bytes), size) {
+        (true, Some(offset), Some(size)) if offset > 1 => {
+          let (offset, size) = (offset as usize, u64::from_le_bytes(size) as usize);
+          Ok(
+            bytes
+              .get(offset..offset + size)
+              .ok_or("Buffer out of the file")?,
+          )
+        }
+        _ => Ok(data),
+      }
+    })
+    .collect::<Result<Vec<&[u8]>, Box<dyn Error>>>()?;
+  let subgraph = *model
+    .vector(2)?
+    .tables()?
+    .first()
+    .ok_or("No subgraph in the model")?;
+
+  let tensors = subgraph
+    .vector(0)?
+    .tables()?
+    .iter()
+    .map(|t| {
+      let name = String::from_utf8_lossy(t.vector(3)?.bytes()?).to_string();
+      let (scale, zero_point) = match t.table(4)? {
+        Some(q) => (
+          q.vector(2)?.values(f32::from_le_bytes)?,
+          q.vector(3)?.values(i64::from_le_bytes)?,
+        ),
+        None => (vec![], vec![]),
+      };
+      let shape = t.vector(0)?.values(i32::from_le_bytes)?;
+      Ok(TensorInfo {
+        shape: shape.into_iter().map(|d| d.max(1) as usize).collect(),
+        dtype: t.i8(1)?,
+        data: buffers
+          .get(t.u32(2)? as usize)
+          .map(|d| d.to_vec())
+          .unwrap_or_default(),
+        name,
+        scale,
+        zero_point,
+      })
+    })
+    .collect::<Result<Vec<_>, Box<dyn Error>>>()?;
+  let ops = subgraph
+    .vector(3)?
+    .tables()?
+    .iter()
+    .map(|op| {
+      let options = op.table(4)?;
+      let code = *codes
+        .get(op.u32(0)? as usize)
+        .ok_or("Operator code out of the model")?;
+      Ok(OpInfo {
+        code,
+        inputs: op.vector(1)?.values(i32::from_le_bytes)?,
+        outputs: op.vector(2)?.values(i32::from_le_bytes)?,
+        activation: match (code, options) {
+          (FULLY_CONNECTED, Some(o)) => o.i8(0)?,
+          _ => 0,
+        },
+        beta: match (code, options) {
+          (SOFTMAX, Some(o)) => o.f32(0)?.unwrap_or(1.0),
+          _ => 1.0,
+        },
+      })
+    })
+    .collect::<Result<Vec<_>, Box<dyn Error>>>()?;
+  let indices = |v: Vec<i32>| v.into_iter().map(|x| x as usize).collect();
+  Ok(Subgraph {
+    tensors,
+    ops,
+    inputs: indices(subgraph.vector(1)?.values(i32::from_le_bytes)?),
+    outputs: indices(subgraph.vector(2)?.values(i32::from_le_bytes)?),
+  })
+}
+
+/// Integers `q` of the real values `scale * (q - zero_point)`, within `range`.
+#[derive(Debug, Clone, Copy)]
+struct Quantization {
+  scale: f32,
+  zero_point: i64,
+  range: (i64, i64),
+}
+
+/// How a tensor is held by its little nodes.
+#[derive(Debug, Clone, Copy)]
+enum Repr {
+  /// TFLite's integers, at scale 0.
+  Int(Quantization),
+  /// The real values.
+  Real,
+}
+
+#[derive(Debug, Clone)]
+struct Value {
+  nodes: Vec<NodeIndex>,
+  repr: Repr,
+}
+
+/// The quantization of an int8 or uint8 tensor, per tensor.
+fn quantization(t: &TensorInfo) -> Result<Quantization, Box<dyn Error>> {
+  let range = match t.dtype {
+    INT8 => (-128, 127),
+    UINT8 => (0, 255),
+    dtype => {
+      return Err(
+        format!(
+          "Tensor {} of type {}, expected int8 or uint8",
+          t.name, dtype
+        )
+        .into(),
+      )
+    }
+  };
+  match (t.scale.as_slice(), t.zero_point.as_slice()) {
+    ([scale], [zero_point]) => Ok(Quantization {
+      scale: *scale,
+      zero_point: *zero_point,
+      range,
+    }),
+    _ => Err(format!("Tensor {} isn't quantized per tensor", t.name).into()),
+  }
+}
+
+/// The scalar graph under construction, every node at its scale.
+struct Builder {
+  nodes: Vec<FrozenNode>,
+  scales: HashMap<NodeIndex, u32>,
+  constants: HashMap<NodeIndex, f32>,
+  origin: HashMap<NodeIndex, (NodeIndex, usize)>,
+  /// The tensor and the element the nodes made now compute, see [InputsTracker::origin].
+  element: (NodeIndex, usize),
+  /// Of the real values.
+  scale_bits: u32,
+}
+
+impl Builder {
+  fn node(&mut self, op: ScalarOp, args: &[NodeIndex], scale: u32) -> NodeIndex {
+    let id = NodeIndex::new(self.nodes.len());
+    self.nodes.push(FrozenNode {
+      id,
+      op,
+      args: args.to_vec(),
+    });
+    self.scales.insert(id, scale);
+    self.origin.insert(id, self.element);
+    if let ScalarOp::Constant(v) = op {
+      self.constants.insert(id, v);
+    }
+    id
+  }
+
+  fn constant(&mut self, v: f32, scale: u32) -> NodeIndex {
+    self.node(ScalarOp::Constant(v), &[], scale)
+  }
+
+  fn real(&mut self, op: ScalarOp, args: &[NodeIndex]) -> NodeIndex {
+    self.node(op, args, self.scale_bits)
+  }
+
+  fn clamp(&mut self, x: NodeIndex, (lo, hi): (i64, i64)) -> NodeIndex {
+    let lo = self.constant(lo as f32, 0);
+    let x = self.node(ScalarOp::Max, &[x, lo], 0);
+    // min(x, hi) = -max(-x, -hi)
+    let neg = self.node(ScalarOp::Neg, &[x], 0);
+    let neg_hi = self.constant(-hi as f32, 0);
+    let x = self.node(ScalarOp::Max, &[neg, neg_hi], 0);
+    self.node(ScalarOp::Neg, &[x], 0)
+  }
+
+  /// `floor(x * multiplier + 1/2) + zero_point` clamped to the range, an integer.
+  fn requantize(
+    &mut self,
+    x: NodeIndex,
+    multiplier: f32,
+    zero_point: i64,
+    range: (i64, i64),
+  ) -> NodeIndex {
+    let m = self.constant(multiplier, MULTIPLIER_BITS);
+    let y = self.real(ScalarOp::Mul, &[x, m]);
+    let half = self.constant(0.5, self.scale_bits);
+    let y = self.real(ScalarOp::Add, &[y, half]);
+    let zero_point = self.constant(zero_point as f32, 0);
+    let y = self.node(ScalarOp::Add, &[y, zero_point], 0);
+    self.clamp(y, range)
+  }
+
+  /// `q - zero_point`, an integer.
+  fn centered(&mut self, q: NodeIndex, zero_point: i64) -> NodeIndex {
+    match zero_point {
+      0 => q,
+      _ => {
+        let zero_point = self.constant(zero_point as f32, 0);
+        self.node(ScalarOp::Sub, &[q, zero_point], 0)
+      }
+    }
+  }
+
+  /// The real value of the integer.
+  fn dequantize(&mut self, q: NodeIndex, quant: Quantization) -> NodeIndex {
+    let c = self.centered(q, quant.zero_point);
+    let s = self.constant(quant.scale, MULTIPLIER_BITS);
+    self.real(ScalarOp::Mul, &[c, s])
+  }
+}
+
+/// The range of the output integers with the fused activation, ActivationFunctionType.
+fn activation_range(activation: i8, quant: Quantization) -> Result<(i64, i64), Box<dyn Error>> {
+  let Quantization {
+    scale,
+    zero_point,
+    range: (lo, hi),
+  } = quant;
+  let q = |x: f32| zero_point + (x / scale).round() as i64;
+  match activation {
+    0 => Ok((lo, hi)),
+    1 => Ok((lo.max(zero_point), hi)),
+    2 => Ok((lo.max(q(-1.0)), hi.min(q(1.0)))),
+    3 => Ok((lo.max(zero_point), hi.min(q(6.0)))),
+    a => Err(format!("Fused activation {} not supported", a).into()),
+  }
+}
+
+/// The integers of a constant tensor, int8, uint8 or int32.
+fn constant_ints(t: &TensorInfo) -> Result<Vec<i64>, Box<dyn Error>> {
+  let ints: Vec<i64> = match t.dtype {
+    INT8 => t.data.iter().map(|b| *b as i8 as i64).collect(),
+    UINT8 => t.data.iter().map(|b| *b as i64).collect(),
+    INT32 => t
+      .data
+      .chunks_exact(4)
+      .map(|b| i32::from_le_bytes(b.try_into().unwrap()) as i64)
+      .collect(),
+    dtype => return Err(format!("Constant {} of type {}", t.name, dtype).into()),
+  };
+  if ints.len() != t.shape.iter().product::<usize>() {
+    return Err(format!("Tensor {} isn't a constant of shape {:?}", t.name, t.shape).into());
+  }
+  Ok(ints)
+}
+
+/// A model quantized by TFLite as a [QuantizedGraph], see the module docs.
+pub struct TfliteModel {
+  /// Its [QuantConfig::scale_bits] are of the inputs and the outputs, the nodes in between have their own.
+  pub graph: QuantizedGraph,
+  /// The input tensors, in the order of the model.
+  pub inputs: Vec<NodeIndex>,
+  pub outputs: Vec<NodeIndex>,
+}
+
+/// The model of the bytes of a `.tflite` file, see the module docs.
+pub fn import_tflite(bytes: &[u8], quant: &QuantConfig) -> Result<TfliteModel, Box<dyn Error>> {
+  let sg = parse(bytes)?;
+  let tensor = |x: i32| {
+    usize::try_from(x)
+      .ok()
+      .and_then(|x| sg.tensors.get(x))
+      .ok_or_else(|| format!("No tensor {}", x))
+  };
+  let mut b = Builder {
+    nodes: vec![],
+    scales: HashMap::new(),
+    constants: HashMap::new(),
+    origin: HashMap::new(),
+    element: (NodeIndex::new(0), 0),
+    scale_bits: quant.scale_bits,
+  };
+  let mut values: HashMap<usize, Value> = HashMap::new();
+  let mut new_inputs = HashMap::new();
+
+  for x in sg.inputs.iter() {
+    let t = tensor(*x as i32)?;
+    let n = t.shape.iter().product::<usize>();
+    let repr = match t.dtype {
+      FLOAT32 => Repr::Real,
+      _ => Repr::Int(quantization(t)?),
+    };
+    let mut inputs = vec![];
+    let mut nodes = vec![];
+    for i in 0..n {
+      b.element = (NodeIndex::new(*x), i);
+      let input = b.real(ScalarOp::Input, &[]);
+      inputs.push(input);
+      nodes.push(match repr {
+        Repr::Int(q) => b.requantize(input, 1.0 / q.scale, q.zero_point, q.range),
+        Repr::Real => input,
+      });
+    }
+    new_inputs.insert(NodeIndex::new(*x), inputs);
+    values.insert(*x, Value { nodes, repr });
+  }
+
+  for op in sg.ops.iter() {
+    let arg = |k: usize| -> Result<&Value, Box<dyn Error>> {
+      let x = *op.inputs.get(k).ok_or("Missing operand")?;
+      values
+        .get(&(x as usize))
+        .ok_or_else(|| format!("Tensor {} used before it's computed", x).into())
+    };
+    let out = *op.outputs.first().ok_or("Operator without an output")?;
+    let out_tensor = tensor(out)?;
+    let out_id = NodeIndex::new(out as usize);
+    let value = match op.code {
+      FULLY_CONNECTED => {
+        let input = arg(0)?.clone();
+        let Repr::Int(q_in) = input.repr else {
+          return Err("FULLY_CONNECTED of a float input, only quantized ones are supported".into());
+        };
+        let w = tensor(*op.inputs.get(1).ok_or("Missing operand")?)?;
+        let (outputs, inputs) = match w.shape.as_slice() {
+          [o, i] => (*o, *i),
+          s => return Err(format!("Weight {} of shape {:?}", w.name, s).into()),
+        };
+        let weights = constant_ints(w)?;
+        let bias = match op.inputs.get(2) {
+          Some(x) if *x >= 0 => Some(constant_ints(tensor(*x)?)?),
+          _ => None,
+        };
+        // the constants of the graph are f32, exact up to 2^24
+        if bias
+          .as_ref()
+          .is_some_and(|bias| bias.len() != outputs || bias.iter().any(|v| v.abs() > 1 << 24))
+        {
+          return Err(format!("Bias of {} isn't {} integers within ±2^24", w.name, outputs).into());
+        }
+        // per tensor or per output channel
+        let per_channel = |len: usize| len == 1 || len == outputs;
+        if !per_channel(w.scale.len())
+          || !(w.zero_point.is_empty() || per_channel(w.zero_point.len()))
+        {
+          return Err(
+            format!(
+              "Weight {} isn't quantized per tensor or per channel",
+              w.name
+            )
+            .into(),
+          );
+        }
+        if input.nodes.len() % inputs != 0 {
+          return Err(
+            format!(
+              "FULLY_CONNECTED of {} inputs by {}",
+              input.nodes.len(),
+              w.name
+            )
+            .into(),
+          );
+        }
+        let channel = |v: usize, c: usize| if v == 1 { 0 } else { c };
+        let q_out = quantization(out_tensor)?;
+        let range = activation_range(op.activation, q_out)?;
+        let mut nodes = vec![];
+        for (r, row) in input.nodes.chunks(inputs).enumerate() {
+          b.element = (out_id, r * outputs);
+          let row: Vec<NodeIndex> = row
+            .iter()
+            .map(|q| b.centered(*q, q_in.zero_point))
+            .collect();
+          for c in 0..outputs {
+            b.element = (out_id, r * outputs + c);
+            let zp_w = match w.zero_point.is_empty() {
+              true => 0,
+              false => w.zero_point[channel(w.zero_point.len(), c)],
+            };
+            let mut acc = bias.as_ref().map(|bias| b.constant(bias[c] as f32, 0));
+            for (i, x) in row.iter().enumerate() {
+              let wi = weights[c * inputs + i] - zp_w;
+              if wi == 0 {
+                continue;
+              }
+              let wi = b.constant(wi as f32, 0);
+              acc = Some(match acc {
+                Some(acc) => b.node(ScalarOp::Fma, &[wi, *x, acc], 0),
+                None => b.node(ScalarOp::Mul, &[wi, *x], 0),
+              });
+            }
+            let acc = acc.unwrap_or_else(|| b.constant(0.0, 0));
+            let multiplier = q_in.scale * w.scale[channel(w.scale.len(), c)] / q_out.scale;
+            nodes.push(b.requantize(acc, multiplier, q_out.zero_point, range));
+          }
+        }
+        Value {
+          nodes,
+          repr: Repr::Int(q_out),
+        }
+      }
+      RELU => {
+        let input = arg(0)?.clone();
+        match input.repr {
+          Repr::Int(q_in) => {
+            let q_out = quantization(out_tensor)?;
+            let range = activation_range(1, q_out)?;
+            let mut nodes = vec![];
+            for (i, q) in input.nodes.iter().enumerate() {
+              b.element = (out_id, i);
+              let c = b.centered(*q, q_in.zero_point);
+              let multiplier = q_in.scale / q_out.scale;
+              nodes.push(b.requantize(c, multiplier, q_out.zero_point, range));
+            }
+            Value {
+              nodes,
+              repr: Repr::Int(q_out),
+            }
+          }
+          Repr::Real => {
+            let mut nodes = vec![];
+            for (i, x) in input.nodes.iter().enumerate() {
+              b.element = (out_id, i);
+              nodes.push(b.real(ScalarOp::Relu, &[*x]));
+            }
+            Value {
+              nodes,
+              repr: Repr::Real,
+            }
+          }
+        }
+      }
+      SOFTMAX => {
+        let input = arg(0)?.clone();
+        let Repr::Int(q_in) = input.repr else {
+          return Err("SOFTMAX of a float input, only quantized ones are supported".into());
+        };
+        let q_out = quantization(out_tensor)?;
+        let n = *out_tensor.shape.last().unwrap_or(&1);
+        if input.nodes.len() % n != 0 {
+          return Err(format!("SOFTMAX of {} inputs over rows of {}", input.nodes.len(), n).into());
+        }
+        let mut nodes = vec![];
+        for (r, row) in input.nodes.chunks(n).enumerate() {
+          // exp(beta * s_in * (q - max q)) = exp2 of that times log2(e), at most 1
+          b.element = (out_id, r * n);
+          let max = row[1..]
+            .iter()
+            .fold(row[0], |m, q| b.node(ScalarOp::Max, &[m, *q], 0));
+          let c = b.constant(op.beta * q_in.scale * LOG2_E, MULTIPLIER_BITS);
+          let mut exps = vec![];
+          for (j, q) in row.iter().enumerate() {
+            b.element = (out_id, r * n + j);
+            let d = b.node(ScalarOp::Sub, &[*q, max], 0);
+            let d = b.real(ScalarOp::Mul, &[d, c]);
+            exps.push(b.real(ScalarOp::Lookup(LookupKind::Exp2), &[d]));
+          }
+          b.element = (out_id, r * n);
+          let sum = exps[1..]
+            .iter()
+            .fold(exps[0], |s, e| b.real(ScalarOp::Add, &[s, *e]));
+          let recip = b.real(ScalarOp::Recip, &[sum]);
+          for (j, e) in exps.iter().enumerate() {
+            b.element = (out_id, r * n + j);
+            let p = b.real(ScalarOp::Mul, &[*e, recip]);
+            nodes.push(b.requantize(p, 1.0 / q_out.scale, q_out.zero_point, q_out.range));
+          }
+        }
+        Value {
+          nodes,
+          repr: Repr::Int(q_out),
+        }
+      }
+      QUANTIZE => {
+        let input = arg(0)?.clone();
+        let q_out = quantization(out_tensor)?;
+        let mut nodes = vec![];
+        for (i, x) in input.nodes.iter().enumerate() {
+          b.element = (out_id, i);
+          let (x, multiplier) = match input.repr {
+            Repr::Real => (*x, 1.0 / q_out.scale),
+            Repr::Int(q_in) => (b.centered(*x, q_in.zero_point), q_in.scale / q_out.scale),
+          };
+          nodes.push(b.requantize(x, multiplier, q_out.zero_point, q_out.range));
+        }
+        Value {
+          nodes,
+          repr: Repr::Int(q_out),
+        }
+      }
+      DEQUANTIZE => {
+        let input = arg(0)?.clone();
+        let Repr::Int(q_in) = input.repr else {
+          return Err("DEQUANTIZE of a float tensor".into());
+        };
+        let mut nodes = vec![];
+        for (i, q) in input.nodes.iter().enumerate() {
+          b.element = (out_id, i);
+          nodes.push(b.dequantize(*q, q_in));
+        }
+        Value {
+          nodes,
+          repr: Repr::Real,
+        }
+      }
+      code => return Err(format!("TFLite operator {} not supported", code).into()),
+    };
+    values.insert(out as usize, value);
+  }
+
+  let mut new_outputs = HashMap::new();
+  for x in sg.outputs.iter() {
+    let value = values
+      .get(x)
+      .ok_or_else(|| format!("Output tensor {} isn't computed", x))?
+      .clone();
+    let nodes = match value.repr {
+      Repr::Int(q_out) => {
+        let mut nodes = vec![];
+        for (i, q) in value.nodes.iter().enumerate() {
+          b.element = (NodeIndex::new(*x), i);
+          nodes.push(b.dequantize(*q, q_out));
+        }
+        nodes
+      }
+      Repr::Real => value.nodes,
+    };
+    new_outputs.insert(NodeIndex::new(*x), nodes);
+  }
+
+  let mut tables = TableRegistry::default();
+  tables.register(LookupKind::Exp2);
+  let frozen = FrozenScalarGraph {
+    nodes: b.nodes,
+    inputs_tracker: InputsTracker {
+      new_inputs,
+      new_outputs,
+      origin: b.origin,
+      constants: b.constants,
+      ..Default::default()
+    },
+    tables,
+  };
+  // the nodes are numbered in order, thawing keeps their ids
+  let graph = QuantizedGraph::new(
+    frozen.thaw(),
+    *quant,
+    NodeScales {
+      scale_bits: b.scales,
+    },
+  );
+  Ok(TfliteModel {
+    graph,
+    inputs: sg.inputs.iter().map(|x| NodeIndex::new(*x)).collect(),
+    outputs: sg.outputs.iter().map(|x| NodeIndex::new(*x)).collect(),
+  })
+}
+
+/// [import_tflite] of the file.
+pub fn read_tflite(path: &Path, quant: &QuantConfig) -> Result<TfliteModel, Box<dyn Error>> {
+  import_tflite(&fs::read(path)?, quant)
+}
+
+#[cfg(test)]
+mod tests {
+  use std::collections::HashMap;
+
+  use luminal::prelude::NodeIndex;
+
+  use super::{
+    import_tflite, DEQUANTIZE, FLOAT32, FULLY_CONNECTED, INT32, INT8, QUANTIZE, SOFTMAX,
+  };
+  use crate::quant::QuantConfig;
+
+  /// A field of a table to encode, see [encode].
+  enum Fb {
+    U8(u8),
+    U32(u32),
+    I32(i32),
+    Table(Vec<Option<Fb>>),
+    Tables(Vec<Vec<Option<Fb>>>),
+    Bytes(Vec<u8>),
+    I32s(Vec<i32>),
+    F32s(Vec<f32>),
+    I64s(Vec<i64>),
+  }
+
+  fn patch(out: &mut [u8], at: usize, pos: usize) {
+    out[at..at + 4].copy_from_slice(&((pos - at) as u32).to_le_bytes());
+  }
+
+  /// The vtable, the table, then what it refers to: every offset points forward.
+  fn write_table(out: &mut Vec<u8>, fields: &[Option<Fb>]) -> usize {
+    let size = |f: &Fb| match f {
+      Fb::U8(_) => 1,
+      _ => 4,
+    };
+    let vtable = out.len();
+    out.extend(((4 + 2 * fields.len()) as u16).to_le_bytes());
+    let inline: usize = fields.iter().flatten().map(size).sum();
+    out.extend(((4 + inline) as u16).to_le_bytes());
+    let mut offset = 4;
+    for f in fields.iter() {
+      let entry = f.as_ref().map_or(0, |f| {
+        offset += size(f);
+        offset - size(f)
+      });
+      out.extend((entry as u16).to_le_bytes());
+    }
+    let table = out.len();
+    out.extend(((table - vtable) as i32).to_le_bytes());
+    let mut refs = vec![];
+    for f in fields.iter().flatten() {
+      match f {
+        Fb::U8(v) => out.push(*v),
+        Fb::U32(v) => out.extend(v.to_le_bytes()),
+        Fb::I32(v) => out.extend(v.to_le_bytes()),
+        _ => {
+          refs.push((out.len(), f));
+          out.extend([0; 4]);
+        }
+      }
+    }
+    for (at, f) in refs {
+      let pos = write_ref(out, f);
+      patch(out, at, pos);
+    }
+    table
+  }
+
+  fn write_vector(out: &mut Vec<u8>, len: usize, data: Vec<u8>) -> usize {
+    let pos = out.len();
+    out.extend((len as u32).to_le_bytes());
+    out.extend(data);
+    pos
+  }
+
+  fn write_ref(out: &mut Vec<u8>, f: &Fb) -> usize {
+    match f {
+      Fb::Table(fields) => write_table(out, fields),
+      Fb::Tables(tables) => {
+        let pos = write_vector(out, tables.len(), vec![0; 4 * tables.len()]);
+        for (i, t) in tables.iter().enumerate() {
+          let table = write_table(out, t);
+          patch(out, pos + 4 + 4 * i, table);
+        }
+        pos
+      }
+      Fb::Bytes(v) => write_vector(out, v.len(), v.clone()),
+      Fb::I32s(v) => write_vector(
+        out,
+        v.len(),
+        v.iter().flat_map(|x| x.to_le_bytes()).collect(),
+      ),
+      Fb::F32s(v) => write_vector(
+        out,
+        v.len(),
+        v.iter().flat_map(|x| x.to_le_bytes()).collect(),
+      ),
+      Fb::I64s(v) => write_vector(
+        out,
+        v.len(),
+        v.iter().flat_map(|x| x.to_le_bytes()).collect(),
+      ),
+      _ => unreachable!(),
+    }
+  }
+
+  /// A flatbuffer with the root table and the TFLite identifier.
+  fn encode(root: &[Option<Fb>]) -> Vec<u8> {
+    let mut out = vec![0; 4];
+    out.extend(b"TFL3");
+    let table = write_table(&mut out, root);
+    patch(&mut out, 0, table);
+    out
+  }
+
+  fn tensor(
+    shape: &[i32],
+    dtype: i8,
+    buffer: u32,
+    quantization: Option<(f32, i64)>,
+  ) -> Vec<Option<Fb>> {
+    vec![
+      Some(Fb::I32s(shape.to_vec())),
+      Some(Fb::U8(dtype as u8)),
+      Some(Fb::U32(buffer)),
+      None,
+      quantization.map(|(scale, zero_point)| {
+        Fb::Table(vec![
+          None,
+          None,
+          Some(Fb::F32s(vec![scale])),
+          Some(Fb::I64s(vec![zero_point])),
+        ])
+      }),
+    ]
+  }
+
+  fn op(opcode: u32, inputs: &[i32], outputs: &[i32], options: Option<Fb>) -> Vec<Option<Fb>> {
+    vec![
+      Some(Fb::U32(opcode)),
+      Some(Fb::I32s(inputs.to_vec())),
+      Some(Fb::I32s(outputs.to_vec())),
+      options.as_ref().map(|_| Fb::U8(1)),
+      options,
+    ]
+  }
+
+  #[test]
+  fn test_import_tflite() {
+    // float input -> quantize -> fully connected with relu -> softmax -> dequantize
+    let (s_x, zp_x) = (0.05, 3);
+    let w: Vec<i8> = vec![10, -20, 30, 40, -50, 60, 70, -80, 90, 100, 110, -120];
+    let w_scales = vec![0.02, 0.01, 0.03];
+    let bias: Vec<i32> = vec![100, -200, 300];
+    let (s_h, zp_h) = (0.1, -10);
+    let (s_p, zp_p) = (1.0 / 256.0, -128);
+
+    let mut weight = tensor(&[3, 4], INT8, 1, None);
+    weight[4] = Some(Fb::Table(vec![
+      None,
+      None,
+      Some(Fb::F32s(w_scales.clone())),
+      Some(Fb::I64s(vec![0; 3])),
+    ]));
+    let tensors = vec![
+      tensor(&[1, 4], FLOAT32, 0, None),
+      tensor(&[1, 4], INT8, 0, Some((s_x, zp_x))),
+      weight,
+      tensor(&[3], INT32, 2, Some((s_x * 0.02, 0))),
+      tensor(&[1, 3], INT8, 0, Some((s_h, zp_h))),
+      tensor(&[1, 3], INT8, 0, Some((s_p, zp_p))),
+      tensor(&[1, 3], FLOAT32, 0, None),
+    ];
+    // FullyConnectedOptions with RELU, SoftmaxOptions with beta 1
+    let ops = vec![
+      op(0, &[0], &[1], None),
+      op(1, &[1, 2, 3], &[4], Some(Fb::Table(vec![Some(Fb::U8(1))]))),
+      op(
+        2,
+        &[4],
+        &[5],
+        Some(Fb::Table(vec![Some(Fb::U32(1f32.to_bits()))])),
+      ),
+      op(3, &[5], &[6], None),
+    ];
+    let code = |c: i32| {
+      vec![
+        Some(Fb::U8(c as u8)),
+        None,
+        Some(Fb::I32(1)),
+        Some(Fb::I32(c)),
+      ]
+    };
+    let buffers = vec![
+      vec![],
+      vec![Some(Fb::Bytes(w.iter().map(|v| *v as u8).collect()))],
+      vec![Some(Fb::Bytes(
+        bias.iter().flat_map(|v| v.to_le_bytes()).collect(),
+      ))],
+    ];
+    let model = encode(&[
+      Some(Fb::U32(3)),
+      Some(Fb::Tables(vec![
+        code(QUANTIZE),
+        code(FULLY_CONNECTED),
+        code(SOFTMAX),
+        code(DEQUANTIZE),
+      ])),
+      Some(Fb::Tables(vec![vec![
+        Some(Fb::Tables(tensors)),
+        Some(Fb::I32s(vec![0])),
+        Some(Fb::I32s(vec![6])),
+        Some(Fb::Tables(ops)),
+      ]])),
+      None,
+      Some(Fb::Tables(buffers)),
+    ]);
+
+    let imported = import_tflite(&model, &QuantConfig::default()).unwrap();
+    assert_eq!(imported.inputs, vec![NodeIndex::new(0)]);
+    assert_eq!(imported.outputs, vec![NodeIndex::new(6)]);
+
+    // TFLite's integer arithmetic, in f64
+    let reference = |x: &[f32]| -> Vec<f32> {
+      let q: Vec<i64> = x
+        .iter()
+        .map(|v| ((*v as f64 / s_x as f64).round() as i64 + zp_x).clamp(-128, 127))
+        .collect();
+      let h: Vec<i64> = (0..3)
+        .map(|c| {
+          let acc: i64 = bias[c] as i64
+            + (0..4)
+              .map(|i| (q[i] - zp_x) * w[c * 4 + i] as i64)
+              .sum::<i64>();
+          let m = s_x as f64 * w_scales[c] as f64 / s_h as f64;
+          ((acc as f64 * m).round() as i64 + zp_h).clamp(zp_h, 127)
+        })
+        .collect();
+      let max = *h.iter().max().unwrap();
+      let e: Vec<f64> = h
+        .iter()
+        .map(|v| ((v - max) as f64 * s_h as f64).exp())
+        .collect();
+      let sum: f64 = e.iter().sum();
+      e.iter()
+        .map(|v| {
+          let p = ((v / sum * 256.0).round() as i64 + zp_p).clamp(-128, 127);
+          ((p - zp_p) as f64 / 256.0) as f32
+        })
+        .collect()
+    };
+    for x in [
+      [0.3, -0.7, 1.1, 0.2],
+      [-1.3, 0.45, 0.0, 2.2],
+      [0.01, 0.02, -0.03, 0.04],
+    ] {
+      let inputs = HashMap::from([(NodeIndex::new(0), x.to_vec())]);
+      let graph = &imported.graph;
+      let outputs = graph.dequantize_outputs(&graph.evaluate_int(&inputs));
+      let expected = reference(&x);
+      for (a, b) in outputs[&NodeIndex::new(6)].iter().zip(expected.iter()) {
+        // one off at most, by the rounding
+        assert!(
+          (a - b).abs() <= 1.0 / 256.0 + 1e-4,
+          "{:?} vs {:?}",
+          outputs,
+          expected
+        );
+      }
+    }
+
+    assert!(import_tflite(&model[..model.len() / 2], &QuantConfig::default()).is_err());
+    assert!(import_tflite(b"not a model", &QuantConfig::default()).is_err());
+  }
+}
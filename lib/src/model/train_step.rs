@@ -0,0 +1,183 @@
+//!
+//! One step of training as a circuit: the forward pass on a batch, the gradients of the loss and the SGD update of the
+//! weights.
+//!
+//! [TrainingStep::new] builds the graph of a step as [super::zoo::train] trains, from a `build` of the model with the
+//! batch as the first dim of its input. [TrainingStep::compile] makes its snark: the batch and the weights before the
+//! step are private, the learning rate is public. The circuit commits (see [Commitment]) to the batch with its targets,
+//! the weights before the step and the weights after it, in this order, so a proof attests that `W_{t+1}` is a step
+//! from `W_t` on the committed batch without revealing either. The commitment to `W_{t+1}` is to the fixed-point
+//! values the circuit computed.
+//!
+//! The backward pass is made of the same primitive ops as the forward one, the scalarization lowers it as any graph.
+//!
+
+use std::collections::HashMap;
+
+use luminal::prelude::*;
+use luminal_training::{mse_loss, sgd_on_graph, Autograd};
+
+use super::{spec::contiguous, zoo::Forward, ParamRegistry};
+use crate::{
+  scalar::{get_own_size, scalar},
+  snark::{CircuitField, Commitment, MLSnark, SourceType},
+  SCALE,
+};
+
+/// The graph of one SGD step, see the module docs.
+#[derive(Debug)]
+pub struct TrainingStep {
+  pub graph: Graph,
+  pub input: NodeIndex,
+  pub target: NodeIndex,
+  /// Before the step, in the order of the model's weights.
+  pub weights: Vec<NodeIndex>,
+  /// After the step, retrieved, in the order of [TrainingStep::weights].
+  pub new_weights: Vec<NodeIndex>,
+  /// The learning rate, a scalar input.
+  pub lr: NodeIndex,
+  pub params: ParamRegistry,
+}
+
+impl TrainingStep {
+  /// The step of the model of `build` with the mean squared error of `loss_on(output)`, as in [super::zoo::train].
+  /// Only the new weights are retrieved, the scalarization leaves out e.g. the loss itself.
+  pub fn new<I: Shape, O: Shape>(
+    build: impl FnOnce(&mut Graph) -> Forward<I, O>,
+    loss_on: impl FnOnce(GraphTensor<O>) -> GraphTensor<O>,
+  ) -> Self {
+    let mut cx = Graph::new();
+    let Forward {
+      input,
+      output,
+      weights,
+      params,
+    } = build(&mut cx);
+    let target = cx.tensor::<O>();
+    let loss = mse_loss(loss_on(output), target);
+    let grads = cx.compile(Autograd::new(&weights, loss), ());
+    let (new_weights, lr) = sgd_on_graph(&mut cx, &weights, &grads);
+    cx.to_retrieve.clear();
+    for (w, new) in weights.iter().zip(new_weights.iter()) {
+      let n = get_own_size(*w, &cx);
+      cx.to_retrieve.insert(*new, (0, contiguous(&[n])));
+    }
+
+    TrainingStep {
+      graph: cx,
+      input: input.id,
+      target: target.id,
+      weights,
+      new_weights,
+      lr: lr.id,
+      params,
+    }
+  }
+
+  /// The snark of the step with the learning rate, see the module docs. The batch is set with [MLSnark::set_input],
+  /// the targets and the weights with [MLSnark::set_private].
+  pub fn compile(&self, lr: f32) -> MLSnark<CircuitField> {
+    let (sc, _) = scalar(&self.graph);
+    let tracker = &sc.inputs_tracker;
+    let mut source_map = HashMap::new();
+    for x in [self.input, self.target].iter().chain(self.weights.iter()) {
+      for little in tracker.new_inputs[x].iter() {
+        source_map.insert(*little, SourceType::Private(None));
+      }
+    }
+    for little in tracker.new_inputs[&self.lr].iter() {
+      source_map.insert(*little, SourceType::Public(lr));
+    }
+    MLSnark {
+      graph: sc.freeze_ops(),
+      scale: SCALE,
+      source_map,
+      og_input_id: self.input,
+      input_hash: false,
+      commitments: vec![
+        Commitment {
+          tensors: vec![self.input, self.target],
+        },
+        Commitment {
+          tensors: self.weights.clone(),
+        },
+        Commitment {
+          tensors: self.new_weights.clone(),
+        },
+      ],
+      weight_opening: None,
+      recorded_public_inputs: vec![],
+      recorded_public_nodes: vec![],
+      trace: Default::default(),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::collections::HashMap;
+
+  use luminal::prelude::*;
+  use luminal_nn::Linear;
+  use rand::{rngs::StdRng, Rng, SeedableRng};
+
+  use super::TrainingStep;
+  use crate::{
+    model::{zoo::Forward, ParamRegistry},
+    scalar::{scalar, verify_scalarization},
+    snark::{
+      backend::{Groth16Backend, ProvingBackend},
+      poseidon::input_hash,
+    },
+  };
+
+  #[test]
+  fn test_training_step() {
+    let step = TrainingStep::new(
+      |cx| {
+        let model = Linear::<2, 1>::initialize(cx);
+        let input = cx.tensor::<R2<3, 2>>();
+        let output = model.forward(input).retrieve();
+        Forward {
+          input,
+          output,
+          weights: params(&model),
+          params: ParamRegistry::default(),
+        }
+      },
+      |output| output,
+    );
+    let mut rng = StdRng::seed_from_u64(0);
+    let mut random = |n: usize| {
+      (0..n)
+        .map(|_| rng.gen_range(-1.0..1.0))
+        .collect::<Vec<f32>>()
+    };
+    let (x, y, w) = (random(6), random(3), random(2));
+
+    let mut inputs: HashMap<NodeIndex, Vec<f32>> = HashMap::new();
+    inputs.insert(step.input, x.clone());
+    inputs.insert(step.target, y.clone());
+    inputs.insert(step.weights[0], w.clone());
+    inputs.insert(step.lr, vec![0.1]);
+    let (sc, _) = scalar(&step.graph);
+    assert_eq!(verify_scalarization(&step.graph, &sc, &inputs), Ok(()));
+    assert_eq!(
+      sc.inputs_tracker.new_outputs.keys().collect::<Vec<_>>(),
+      vec![&step.new_weights[0]]
+    );
+
+    let mut snark = step.compile(0.1);
+    snark.set_input(x.clone());
+    snark.set_private(step.target, y.clone());
+    snark.set_private(step.weights[0], w.clone());
+    let results = Groth16Backend.witness_outputs(&mut snark).unwrap();
+    // the new weights are committed to, not public
+    assert!(results.is_empty());
+    let commitments = snark.get_commitments();
+    assert_eq!(commitments.len(), 3);
+    let batch: Vec<f32> = x.into_iter().chain(y).collect();
+    assert_eq!(commitments[0], input_hash(&batch, &snark.scale));
+    assert_eq!(commitments[1], input_hash(&w, &snark.scale));
+  }
+}
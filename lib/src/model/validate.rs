@@ -0,0 +1,175 @@
+//!
+//! The check of the weights and constants of a [GraphForSnark] before the scalarization.
+//!
+//! The circuit encodes a float as a field element of its scaled value (see [crate::snark::scaling_helpers]), the
+//! exporters as a fixed-point integer (see [crate::quant]). A NaN or an infinity has no encoding and a value too big
+//! wraps around the field, the results are garbage without any error. [GraphForSnark::validate_weights] finds such
+//! values up front, with the tensor and the index of every one.
+//!
+
+use std::{error::Error, fmt};
+
+use luminal::{
+  op::{Constant, Operator},
+  prelude::NodeIndex,
+};
+
+use super::GraphForSnark;
+use crate::{dtype::tensor_f32, quant::QuantConfig};
+
+/// A weight or a constant the circuit can't represent.
+#[derive(Debug, Clone, PartialEq)]
+pub struct InvalidValue {
+  /// The weight, or the Constant node of the graph.
+  pub tensor: NodeIndex,
+  /// The name of the weight, see [super::ParamRegistry].
+  pub name: Option<String>,
+  /// The index in the data of the weight, 0 for a constant.
+  pub index: usize,
+  pub value: f32,
+}
+
+/// The values failing [GraphForSnark::validate_weights], in the order of the weights, then the constants.
+#[derive(Debug, Clone, PartialEq)]
+pub struct InvalidWeights {
+  /// The largest magnitude allowed.
+  pub max_abs: f64,
+  pub values: Vec<InvalidValue>,
+}
+
+impl fmt::Display for InvalidWeights {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(
+      f,
+      "{} weights or constants are NaN, infinite or beyond ±{}:",
+      self.values.len(),
+      self.max_abs
+    )?;
+    for v in self.values.iter().take(10) {
+      write!(f, " {:?}", v.tensor)?;
+      if let Some(name) = &v.name {
+        write!(f, " ({})", name)?;
+      }
+      write!(f, "[{}] = {},", v.index, v.value)?;
+    }
+    if self.values.len() > 10 {
+      write!(f, " ...")?;
+    }
+    Ok(())
+  }
+}
+
+impl Error for InvalidWeights {}
+
+impl GraphForSnark {
+  /// Every weight and constant finite and within the range of the default [QuantConfig], see the module docs.
+  pub fn validate_weights(&self) -> Result<(), InvalidWeights> {
+    self.validate_weights_for(&QuantConfig::default())
+  }
+
+  /// [GraphForSnark::validate_weights] for the range of the quantization.
+  pub fn validate_weights_for(&self, quant: &QuantConfig) -> Result<(), InvalidWeights> {
+    let valid = |v: f32| v.is_finite() && quant.in_range(quant.quantize(v));
+    let mut values = vec![];
+    for (x, data) in self.weights.iter() {
+      let name = self.params.by_id(*x).map(|p| p.name.clone());
+      values.extend(
+        data
+          .iter()
+          .enumerate()
+          .filter(|(_, v)| !valid(**v))
+          .map(|(index, value)| InvalidValue {
+            tensor: *x,
+            name: name.clone(),
+            index,
+            value: *value,
+          }),
+      );
+    }
+    for x in self.graph.node_indices() {
+      if !self.graph.check_node_type::<Constant>(x) {
+        continue;
+      }
+      let op = self.graph.get_op::<Constant>(x);
+      let value = tensor_f32(&Constant(op.0.clone(), op.1).process(vec![])[0]).unwrap()[0];
+      if !valid(value) {
+        values.push(InvalidValue {
+          tensor: x,
+          name: None,
+          index: 0,
+          value,
+        });
+      }
+    }
+    match values.is_empty() {
+      true => Ok(()),
+      false => Err(InvalidWeights {
+        max_abs: 2f64.powi(quant.value_bits as i32 - quant.scale_bits as i32),
+        values,
+      }),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use luminal::{graph::Graph, shape::R1};
+
+  use crate::{
+    model::{GraphForSnark, ParamRegistry},
+    quant::QuantConfig,
+  };
+
+  #[test]
+  fn test_validate_weights() {
+    let mut cx = Graph::new();
+    let a = cx.tensor::<R1<3>>();
+    let w = cx.tensor::<R1<3>>();
+    let b = (a * w).retrieve();
+    let mut params = ParamRegistry::default();
+    params.register("w", w.id, vec![3]);
+    let mut g = GraphForSnark {
+      graph: cx,
+      input_id: a.id,
+      weights: vec![(w.id, vec![1.0, -2.0, 1e3])],
+      outputs: vec![b.id],
+      params,
+    };
+    assert_eq!(g.validate_weights(), Ok(()));
+
+    g.weights[0].1 = vec![f32::NAN, 0.5, -f32::INFINITY];
+    let e = g.validate_weights().unwrap_err();
+    assert_eq!(
+      e.values.iter().map(|v| v.index).collect::<Vec<_>>(),
+      vec![0, 2]
+    );
+    assert_eq!(e.values[0].name.as_deref(), Some("w"));
+    assert!(e.to_string().contains("(w)[2] = -inf"));
+
+    // 1e3 is beyond 2^8 at 8 fractional bits out of 16
+    g.weights[0].1 = vec![1.0, -2.0, 1e3];
+    let quant = QuantConfig {
+      scale_bits: 8,
+      value_bits: 16,
+    };
+    let e = g.validate_weights_for(&quant).unwrap_err();
+    assert_eq!((e.values.len(), e.max_abs), (1, 256.0));
+  }
+
+  #[test]
+  fn test_validate_constants() {
+    let mut cx = Graph::new();
+    let a = cx.tensor::<R1<3>>();
+    let b = (a * f32::INFINITY).retrieve();
+    let g = GraphForSnark {
+      graph: cx,
+      input_id: a.id,
+      weights: vec![],
+      outputs: vec![b.id],
+      params: ParamRegistry::default(),
+    };
+    let e = g.validate_weights().unwrap_err();
+    assert_eq!(e.values.len(), 1);
+    assert_eq!(e.values[0].value, f32::INFINITY);
+  }
+}
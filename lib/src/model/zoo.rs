@@ -0,0 +1,143 @@
+//!
+//! Small reference models beyond the [super::medium_model], as templates of what's provable today:
+//!  - [logistic]: logistic regression on the bundled dataset, proving the logit.
+//!  - [cnn]: a tiny convolutional classifier of 8x8 images, proving the logits of the classes.
+//!  - [autoencoder]: a small autoencoder of the bundled dataset, proving the reconstruction.
+//!
+//! Each has a `run_model` training it like [super::run_model] (on the CPU only) into a [TrainedGraph],
+//! whose [super::GraphForSnark] is the forward pass alone, ready for [crate::compile].
+//!
+
+use std::cell::RefCell;
+
+use luminal::prelude::*;
+use luminal_training::{mse_loss, Autograd};
+use rand::{rngs::StdRng, SeedableRng};
+
+use crate::{
+  model::{
+    device::tensor_data,
+    epoch_order,
+    optim::{check_finite, sgd_clipped},
+    seed_weights, GraphForSnark, LrSchedule, ParamRegistry,
+  },
+  scalar::copy_graph_roughly,
+};
+
+use super::TrainedGraph;
+
+pub mod autoencoder;
+pub mod cnn;
+pub mod logistic;
+
+/// A model applied to its input, made by the `build` of [train].
+pub struct Forward<I: Shape, O: Shape> {
+  pub input: GraphTensor<I>,
+  /// The result of the model, retrieved.
+  pub output: GraphTensor<O>,
+  pub weights: Vec<NodeIndex>,
+  pub params: ParamRegistry,
+}
+
+/// The training loop of the zoo: builds the model, seeds its weights, copies out the graph for the snark
+/// and trains on the `(input, target)` samples with the mean squared error and SGD at the rates of `lr`, the gradients
+/// clipped to `clip_grad_norm` if given (see [super::optim]).
+/// The loss is on `loss_on(output)`, e.g. the probability of a logit, the snark graph ends at the output.
+pub fn train<I: Shape, O: Shape>(
+  build: impl FnOnce(&mut Graph) -> Forward<I, O>,
+  loss_on: impl FnOnce(GraphTensor<O>) -> GraphTensor<O>,
+  samples: &[(Vec<f32>, Vec<f32>)],
+  epochs: usize,
+  seed: u64,
+  lr: LrSchedule,
+  clip_grad_norm: Option<f32>,
+) -> TrainedGraph {
+  let mut cx = Graph::new();
+  let Forward {
+    input,
+    output,
+    weights,
+    params,
+  } = build(&mut cx);
+  let mut rng = StdRng::seed_from_u64(seed);
+  seed_weights(&mut cx, &weights, &mut rng);
+  // record graph without gradients
+  let (cx_og, remap) = copy_graph_roughly(&cx);
+
+  let trained = loss_on(output).retrieve();
+  let target = cx.tensor::<O>();
+  let loss = mse_loss(trained, target).retrieve();
+  let grads = cx.compile(Autograd::new(&weights, loss), ());
+  let (new_weights, rate) = sgd_clipped(&mut cx, &weights, &grads, clip_grad_norm);
+  cx.keep_tensors(&new_weights);
+  cx.keep_tensors(&weights);
+
+  let mut iter = 0;
+  for epoch in 0..epochs {
+    rate.set(lr.lr(epoch, epochs));
+    for i in epoch_order(samples.len(), &mut rng) {
+      let (x, y) = &samples[i];
+      input.set(x.clone());
+      target.set(y.clone());
+      cx.execute();
+      check_finite("the loss", &loss.data(), iter).unwrap_or_else(|e| panic!("{}", e));
+      transfer_data_same_graph(&new_weights, &weights, &mut cx);
+      loss.drop();
+      trained.drop();
+      output.drop();
+      iter += 1;
+    }
+  }
+
+  let cx_weights: Vec<(NodeIndex, Vec<f32>)> =
+    weights.iter().map(|a| (*a, tensor_data(&cx, *a))).collect();
+  for (w, data) in cx_weights.iter() {
+    check_finite(&format!("weight {:?}", w), data, iter).unwrap_or_else(|e| panic!("{}", e));
+  }
+  TrainedGraph {
+    graph: GraphForSnark {
+      graph: cx_og,
+      weights: cx_weights
+        .iter()
+        .map(|(a, b)| (remap[a], b.clone()))
+        .collect(),
+      input_id: remap[&input.id],
+      outputs: vec![remap[&output.id]],
+      params: params.remap(&remap),
+    },
+    cx: RefCell::new(cx),
+    cx_weights,
+    cx_output_ids: vec![output.id],
+    cx_input_id: input.id,
+    cx_target_id: target.id,
+    scaler: None,
+    threshold: None,
+  }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+  use std::collections::HashMap;
+
+  use luminal::prelude::NodeIndex;
+
+  use crate::{
+    compile,
+    model::TrainedGraph,
+    scalar::{scalar, verify_scalarization},
+  };
+
+  /// The model scalarizes to the same results as the graph, and compiles to a snark.
+  pub(crate) fn assert_provable(trained: &TrainedGraph, input: Vec<f32>) {
+    let graph = &trained.graph;
+    let (sc, _) = scalar(&graph.graph);
+    let mut inputs: HashMap<NodeIndex, Vec<f32>> = graph.weights.iter().cloned().collect();
+    inputs.insert(graph.input_id, input);
+    assert_eq!(verify_scalarization(&graph.graph, &sc, &inputs), Ok(()));
+    let snark = compile(trained);
+    assert_eq!(
+      snark.graph.inputs_tracker.new_outputs.len(),
+      graph.outputs.len()
+    );
+  }
+}
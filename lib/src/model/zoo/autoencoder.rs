@@ -0,0 +1,73 @@
+//!
+//! A small autoencoder of the bundled dataset: the 9 features squeezed through 4 and back.
+//! The model proves the reconstruction, compare it with the input (privately) for an anomaly score.
+//!
+
+use luminal::prelude::*;
+use luminal_nn::{Linear, ReLU};
+
+use crate::model::{split_dataset, ParamRegistry, Scaler, ScalerKind, TrainParams, TrainedGraph};
+
+use super::{train, Forward};
+
+pub type Model = (Linear<9, 4>, ReLU, Linear<4, 9>);
+
+/// Names of the weights of the model, see [ParamRegistry].
+pub fn param_registry(model: &Model) -> ParamRegistry {
+  let mut registry = ParamRegistry::default();
+  registry.register_tensor("layer0.weight", model.0.weight);
+  registry.register_tensor("layer2.weight", model.2.weight);
+  registry
+}
+
+/// Trains to reconstruct the (normalized) inputs of the dataset, the labels and the head of the params are ignored.
+pub fn run_model(train_params: TrainParams) -> TrainedGraph {
+  let (x, y) = train_params.data;
+  let (x_train, _x_test, _y_train, _y_test) = split_dataset(x, y, 0.8);
+  let scaler = Scaler::fit(ScalerKind::MinMax, &x_train);
+  let samples: Vec<(Vec<f32>, Vec<f32>)> = scaler
+    .transform(&x_train)
+    .into_iter()
+    .map(|x| (x.to_vec(), x.to_vec()))
+    .collect();
+  let mut trained = train(
+    |cx| {
+      let model = <Model>::initialize(cx);
+      let input = cx.tensor::<R1<9>>();
+      Forward {
+        input,
+        output: model.forward(input).retrieve(),
+        weights: params(&model),
+        params: param_registry(&model),
+      }
+    },
+    |reconstruction| reconstruction,
+    &samples,
+    train_params.epochs,
+    train_params.seed,
+    train_params.lr,
+    train_params.clip_grad_norm,
+  );
+  trained.scaler = Some(scaler);
+  trained
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::model::{parse_dataset, zoo::tests::assert_provable, TrainParams};
+
+  use super::run_model;
+
+  #[test]
+  fn test_autoencoder_is_provable() {
+    let data = parse_dataset(include_str!("../../../../data/rp.data").to_string());
+    let trained = run_model(TrainParams {
+      data: data.clone(),
+      ..Default::default()
+    });
+    let input = trained.scaler.as_ref().unwrap().transform_row(&data.0[0]);
+    assert_provable(&trained, input.clone());
+    let reconstruction = trained.evaluate(input)[&trained.graph.outputs[0]].clone();
+    assert_eq!(reconstruction.len(), 9);
+  }
+}
@@ -0,0 +1,171 @@
+//!
+//! A tiny convolutional classifier of 8x8 grayscale images: a 3x3 convolution into 4 channels, ReLU,
+//! the mean of every channel over the image and a linear layer to the logits of the classes.
+//! The model proves the logits, append [crate::scalar::append_argmax] to prove the class alone.
+//!
+//! The convolution is the sum over the kernel offsets of the shifted window of the image times that kernel entry
+//! (a vector over the channels), the windows views of the input without copying it. Trained on [bars_dataset].
+//!
+
+use luminal::{
+  prelude::*,
+  shape::{Axes2, Axis, Expression, R3},
+};
+use luminal_nn::Linear;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+use crate::model::{LrSchedule, ParamRegistry, TrainedGraph};
+
+use super::{train, Forward};
+
+pub const SIDE: usize = 8;
+pub const KERNEL: usize = 3;
+/// Side of the output of the convolution, no padding.
+pub const CONV_SIDE: usize = SIDE - KERNEL + 1;
+pub const CHANNELS: usize = 4;
+pub const CLASSES: usize = 2;
+
+pub struct Model {
+  /// The entries of the 3x3 kernel in row-major order, each over the output channels.
+  pub kernel: Vec<GraphTensor<R1<CHANNELS>>>,
+  pub head: Linear<CHANNELS, CLASSES>,
+}
+
+impl Model {
+  pub fn initialize(cx: &mut Graph) -> Self {
+    Model {
+      kernel: (0..KERNEL * KERNEL)
+        .map(|_| cx.named_tensor::<R1<CHANNELS>>("Kernel"))
+        .collect(),
+      head: Linear::initialize(cx),
+    }
+  }
+
+  pub fn forward(&self, image: GraphTensor<R2<SIDE, SIDE>>) -> GraphTensor<R1<CLASSES>> {
+    let conv = self
+      .kernel
+      .iter()
+      .enumerate()
+      .map(|(k, w)| {
+        window(image, k / KERNEL, k % KERNEL)
+          .expand::<R3<CONV_SIDE, CONV_SIDE, CHANNELS>, Axis<2>>()
+          * w.expand::<R3<CONV_SIDE, CONV_SIDE, CHANNELS>, Axes2<0, 1>>()
+      })
+      .reduce(|a, b| a + b)
+      .unwrap();
+    let features = conv.relu().mean_reduce::<R1<CHANNELS>, Axes2<0, 1>>();
+    self.head.forward(features)
+  }
+
+  pub fn weights(&self) -> Vec<NodeIndex> {
+    let mut weights: Vec<NodeIndex> = self.kernel.iter().map(|w| w.id).collect();
+    weights.push(self.head.weight.id);
+    weights
+  }
+}
+
+/// The `CONV_SIDE`-sided window of the image at the offset, a view of it.
+fn window(
+  image: GraphTensor<R2<SIDE, SIDE>>,
+  dy: usize,
+  dx: usize,
+) -> GraphTensor<R2<CONV_SIDE, CONV_SIDE>> {
+  let mut shape = image.shape;
+  shape.slice(&[
+    (Expression::from(dy), Expression::from(dy + CONV_SIDE)),
+    (Expression::from(dx), Expression::from(dx + CONV_SIDE)),
+  ]);
+  GraphTensor::from_id(image.id, shape, image.graph_ref)
+}
+
+/// Names of the weights of the model, see [ParamRegistry]. The kernel entries are `conv.weight.<row><column>`.
+pub fn param_registry(model: &Model) -> ParamRegistry {
+  let mut registry = ParamRegistry::default();
+  for (k, w) in model.kernel.iter().enumerate() {
+    let name = format!("conv.weight.{}{}", k / KERNEL, k % KERNEL);
+    registry.register_tensor(&name, *w);
+  }
+  registry.register_tensor("head.weight", model.head.weight);
+  registry
+}
+
+/// `n` seeded images of a bar on noise: class 0 a horizontal bar, class 1 a vertical one, at a random row or column.
+/// Row-major pixels in [0, 1].
+pub fn bars_dataset(n: usize, seed: u64) -> Vec<(Vec<f32>, usize)> {
+  let mut rng = StdRng::seed_from_u64(seed);
+  (0..n)
+    .map(|_| {
+      let class = rng.gen_range(0..CLASSES);
+      let at = rng.gen_range(0..SIDE);
+      let image = (0..SIDE * SIDE)
+        .map(|i| {
+          let (row, col) = (i / SIDE, i % SIDE);
+          let on_bar = if class == 0 { row == at } else { col == at };
+          if on_bar {
+            1.0
+          } else {
+            rng.gen_range(0.0..0.2)
+          }
+        })
+        .collect();
+      (image, class)
+    })
+    .collect()
+}
+
+/// Trains the logits on the one-hot classes of the images.
+pub fn run_model(images: &[(Vec<f32>, usize)], epochs: usize, seed: u64) -> TrainedGraph {
+  let samples: Vec<(Vec<f32>, Vec<f32>)> = images
+    .iter()
+    .map(|(image, class)| {
+      let one_hot = (0..CLASSES).map(|c| if c == *class { 1.0 } else { 0.0 });
+      (image.clone(), one_hot.collect())
+    })
+    .collect();
+  train(
+    |cx| {
+      let model = Model::initialize(cx);
+      let input = cx.tensor::<R2<SIDE, SIDE>>();
+      Forward {
+        input,
+        output: model.forward(input).retrieve(),
+        weights: model.weights(),
+        params: param_registry(&model),
+      }
+    },
+    |logits| logits,
+    &samples,
+    epochs,
+    seed,
+    LrSchedule::default(),
+    None,
+  )
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::{model::zoo::tests::assert_provable, scalar::scalar};
+
+  use super::{bars_dataset, run_model, CHANNELS, CLASSES, KERNEL, SIDE};
+
+  #[test]
+  fn test_cnn_is_provable() {
+    let images = bars_dataset(64, 0);
+    let trained = run_model(&images, 2, 0);
+    assert_provable(&trained, images[0].0.clone());
+    let output = trained.graph.outputs[0];
+    assert_eq!(
+      trained.evaluate(images[0].0.clone())[&output].len(),
+      CLASSES
+    );
+
+    // the image is read in place, the windows don't copy it
+    let (sc, _) = scalar(&trained.graph.graph);
+    assert_eq!(
+      sc.inputs_tracker.new_inputs[&trained.graph.input_id].len(),
+      SIDE * SIDE
+    );
+    let weights: usize = trained.graph.weights.iter().map(|(_, w)| w.len()).sum();
+    assert_eq!(weights, KERNEL * KERNEL * CHANNELS + CHANNELS * CLASSES);
+  }
+}
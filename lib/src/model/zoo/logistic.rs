@@ -0,0 +1,73 @@
+//!
+//! Logistic regression on the bundled dataset. The model proves the logit `w·x`, the probability is its sigmoid
+//! and the class `logit > 0`: the sigmoid is only in the loss, it would cost an exponential in the circuit.
+//!
+
+use luminal::prelude::*;
+use luminal_nn::Linear;
+
+use crate::model::{split_dataset, ParamRegistry, Scaler, ScalerKind, TrainParams, TrainedGraph};
+
+use super::{train, Forward};
+
+pub type Model = Linear<9, 1>;
+
+/// Names of the weights of the model, see [ParamRegistry].
+pub fn param_registry(model: &Model) -> ParamRegistry {
+  let mut registry = ParamRegistry::default();
+  registry.register_tensor("layer0.weight", model.weight);
+  registry
+}
+
+/// Trains on the 0/1 labels of the dataset. The head of the params is ignored, the model outputs the logit.
+pub fn run_model(train_params: TrainParams) -> TrainedGraph {
+  let (x, y) = train_params.data;
+  let (x_train, _x_test, y_train, _y_test) = split_dataset(x, y, 0.8);
+  let scaler = Scaler::fit(ScalerKind::MinMax, &x_train);
+  let samples: Vec<(Vec<f32>, Vec<f32>)> = scaler
+    .transform(&x_train)
+    .into_iter()
+    .zip(y_train)
+    .map(|(x, y)| (x.to_vec(), vec![y]))
+    .collect();
+  let mut trained = train(
+    |cx| {
+      let model = <Model>::initialize(cx);
+      let input = cx.tensor::<R1<9>>();
+      Forward {
+        input,
+        output: model.forward(input).retrieve(),
+        weights: params(&model),
+        params: param_registry(&model),
+      }
+    },
+    |logit| logit.sigmoid(),
+    &samples,
+    train_params.epochs,
+    train_params.seed,
+    train_params.lr,
+    train_params.clip_grad_norm,
+  );
+  trained.scaler = Some(scaler);
+  trained
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::model::{parse_dataset, zoo::tests::assert_provable, TrainParams};
+
+  use super::run_model;
+
+  #[test]
+  fn test_logistic_is_provable() {
+    let data = parse_dataset(include_str!("../../../../data/rp.data").to_string());
+    let input = data.0[0].to_vec();
+    let trained = run_model(TrainParams {
+      data,
+      ..Default::default()
+    });
+    assert_provable(&trained, input.clone());
+    let logit = trained.evaluate(input)[&trained.graph.outputs[0]].clone();
+    assert_eq!(logit.len(), 1);
+  }
+}
@@ -0,0 +1,1045 @@
+///
+/// Fixed-point quantization: floats as integers `round(x * 2^scale_bits)`.
+///
+/// This is the encoding used by the exporters to external circuit languages, where it's simpler to work with signed integers
+/// (field elements p - n for negative n) than with the offset encoding of [crate::snark] (see [Note: floats as ints]).
+///
+use std::{
+  cmp::Ordering,
+  collections::{HashMap, HashSet},
+};
+
+use itertools::Itertools;
+use luminal::{
+  op::{Function, InputTensor, LessThan, Operator, Recip},
+  prelude::{
+    petgraph::{visit::EdgeRef, Direction::Incoming},
+    NodeIndex, ShapeTracker, Tensor,
+  },
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+  dtype::tensor_f32,
+  model::TrainedGraph,
+  scalar::{copy_graph_roughly, DivConstOp, ScalarGraph, ScalarOp},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct QuantConfig {
+  /// Fractional bits: x is represented by round(x * 2^scale_bits).
+  pub scale_bits: u32,
+  /// Bound on the magnitude of every quantized value, in bits. That is |round(x * 2^scale_bits)| < 2^value_bits.
+  /// Range checks and comparisons in the circuit are sized by it.
+  /// Products before rescaling need 2 * value_bits, which has to stay well below the field size.
+  pub value_bits: u32,
+}
+
+impl Default for QuantConfig {
+  fn default() -> Self {
+    QuantConfig {
+      scale_bits: 16,
+      value_bits: 48,
+    }
+  }
+}
+
+impl QuantConfig {
+  pub fn scale(&self) -> i64 {
+    1 << self.scale_bits
+  }
+
+  pub fn quantize(&self, x: f32) -> i64 {
+    (f64::from(x) * self.scale() as f64).round() as i64
+  }
+
+  pub fn dequantize(&self, n: i64) -> f32 {
+    (n as f64 / self.scale() as f64) as f32
+  }
+
+  /// The value the circuit computes with in place of x, x rounded to the precision.
+  pub fn fake_quantize(&self, x: f32) -> f32 {
+    self.dequantize(self.quantize(x))
+  }
+
+  /// Whether a quantized value fits the declared value range.
+  pub fn in_range(&self, n: i64) -> bool {
+    n.unsigned_abs() < (1u64 << self.value_bits)
+  }
+}
+
+/// Fractional bits per scalar node, for quantizing every layer at its own precision:
+/// a single scale is either too coarse for the small values of the early layers or overflows in the later ones.
+/// Nodes without a scale of their own use [QuantConfig::scale_bits].
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct NodeScales {
+  pub scale_bits: HashMap<NodeIndex, u32>,
+}
+
+impl NodeScales {
+  /// Scales given per tensor of the original graph, for every scalar node computed for it (see [crate::scalar::InputsTracker::origin]).
+  pub fn from_tensor_scales(scalar: &ScalarGraph, tensor_scales: &HashMap<NodeIndex, u32>) -> Self {
+    NodeScales {
+      scale_bits: scalar
+        .inputs_tracker
+        .origin
+        .iter()
+        .filter_map(|(x, (t, _))| tensor_scales.get(t).map(|k| (*x, *k)))
+        .collect(),
+    }
+  }
+
+  pub fn get(&self, quant: &QuantConfig, x: NodeIndex) -> u32 {
+    self.scale_bits.get(&x).copied().unwrap_or(quant.scale_bits)
+  }
+
+  /// The config quantizing at the scale of the node.
+  pub fn config(&self, quant: &QuantConfig, x: NodeIndex) -> QuantConfig {
+    QuantConfig {
+      scale_bits: self.get(quant, x),
+      ..*quant
+    }
+  }
+
+  /// Difference between the largest and the smallest scale in use.
+  /// Moving a value to a larger scale grows it past `value_bits` by at most that many bits.
+  pub fn spread(&self, quant: &QuantConfig) -> u32 {
+    let scales = || self.scale_bits.values().copied().chain([quant.scale_bits]);
+    scales().max().unwrap() - scales().min().unwrap()
+  }
+}
+
+/// The scalar graph with its fixed-point encoding: the scale of every node.
+/// This is what the exporters compile (see [crate::export::circom::render_with]), [QuantizedGraph::evaluate_int] runs it on integers.
+#[derive(Debug)]
+pub struct QuantizedGraph {
+  pub scalar: ScalarGraph,
+  pub quant: QuantConfig,
+  pub scales: NodeScales,
+}
+
+impl QuantizedGraph {
+  /// The quantized graph with its comparisons rewritten to [SignedLessThan], reciprocals to [RecipHint] and the
+  /// rescaling of products to [DivConstOp], and the [RangeCheck]s they need, see [QuantizedGraph::signed_comparisons],
+  /// [QuantizedGraph::recip_hints], [QuantizedGraph::explicit_rescales] and [QuantizedGraph::range_checks].
+  pub fn new(scalar: ScalarGraph, quant: QuantConfig, scales: NodeScales) -> Self {
+    let mut quantized = QuantizedGraph {
+      scalar,
+      quant,
+      scales,
+    };
+    quantized.signed_comparisons();
+    quantized.recip_hints();
+    quantized.explicit_rescales();
+    quantized.range_checks();
+    quantized
+  }
+
+  /// Makes the rescaling of every truncated product, a Mul or Fma at a smaller scale than the product, a node of its
+  /// own: the product at its full scale, then a [DivConstOp] by the power of two down to the scale of the node.
+  /// The backends decompose it with range checks, see [DivConstOp]. A truncated Fma is split into its product and
+  /// the addition first, see [ScalarGraph::split_fma]. Old nodes keep their ids, what read a product reads its division.
+  pub fn explicit_rescales(&mut self) {
+    let k = |y: NodeIndex| self.scales.get(&self.quant, y);
+    // decided on the scales before any change, a product read by another one is read through its division after
+    let truncated = self
+      .scalar
+      .freeze_ops()
+      .nodes
+      .into_iter()
+      .filter(|n| matches!(n.op, ScalarOp::Mul | ScalarOp::Fma))
+      .map(|n| (n.id, n.op, k(n.args[0]) + k(n.args[1]), k(n.id)))
+      .filter(|(_, _, product, kx)| product > kx)
+      .collect_vec();
+    for (x, op, product, kx) in truncated {
+      let m = if op == ScalarOp::Fma {
+        self.scalar.split_fma(x)
+      } else {
+        x
+      };
+      self.scales.scale_bits.insert(m, product);
+      let divisor = 2f32.powi((product - kx) as i32);
+      let rescaled = self.scalar.insert_after(m, DivConstOp { divisor });
+      self.scales.scale_bits.insert(rescaled, kx);
+    }
+  }
+
+  /// The products [QuantizedGraph::explicit_rescales] divides, at the scale of the product. They're bounded by the
+  /// budget of the products, not by [QuantConfig::value_bits]: nothing but their division reads them.
+  fn rescaled_products(&self) -> HashSet<NodeIndex> {
+    let graph = &self.scalar.graph;
+    graph
+      .node_indices()
+      .filter(|x| graph.check_node_type::<DivConstOp>(*x))
+      .flat_map(|x| graph.edges_directed(x, Incoming).map(|e| e.source()))
+      .collect()
+  }
+
+  /// Replaces every [LessThan] with a [SignedLessThan] sized for the scales of its arguments. Node ids don't change.
+  pub fn signed_comparisons(&mut self) {
+    let graph = &mut self.scalar.graph;
+    for x in graph.node_indices().collect_vec() {
+      if !graph.check_node_type::<LessThan>(x) {
+        continue;
+      }
+      let (ka, kb) = graph
+        .edges_directed(x, Incoming)
+        .filter_map(|e| e.weight().as_data().map(|(inp, _, _)| (inp, e.source())))
+        .sorted_by_key(|(inp, _)| *inp)
+        .map(|(_, y)| self.scales.get(&self.quant, y))
+        .collect_tuple()
+        .unwrap();
+      let bits = self.quant.value_bits + ka.abs_diff(kb);
+      *graph.node_weight_mut(x).unwrap() = Box::new(SignedLessThan { bits });
+    }
+  }
+
+  /// Puts a [RangeCheck] in front of every value the gadgets of the graph assume in range. The arguments of a
+  /// [SignedLessThan] of `bits` are moved up to the larger scale, by the difference of the scales, so they need `bits`
+  /// less that difference. A rescaled product, a [DivConstOp] (see [QuantizedGraph::explicit_rescales]) or a Mul or
+  /// Fma at a smaller scale than the product, needs [QuantConfig::value_bits]. Constants are in range or not at compile
+  /// time and aren't checked.
+  ///
+  /// A value needing several checks gets a single one, of the fewest bits. The checks take the scales of their
+  /// arguments and the old nodes keep their ids. Run once, after [QuantizedGraph::signed_comparisons].
+  pub fn range_checks(&mut self) {
+    let k = |y: NodeIndex| self.scales.get(&self.quant, y);
+    let frozen = self.scalar.freeze_ops();
+    let ops: HashMap<NodeIndex, ScalarOp> = frozen.nodes.iter().map(|n| (n.id, n.op)).collect();
+    let mut checks: HashMap<NodeIndex, u32> = HashMap::new();
+    let mut need = |y: NodeIndex, bits: u32| {
+      if !matches!(ops[&y], ScalarOp::Constant(_)) {
+        let b = checks.entry(y).or_insert(bits);
+        *b = (*b).min(bits);
+      }
+    };
+    for node in frozen.nodes.iter() {
+      match node.op {
+        ScalarOp::SignedLessThan(bits) => {
+          let c = node.args.iter().map(|y| k(*y)).max().unwrap();
+          for y in node.args.iter() {
+            need(*y, bits - (c - k(*y)));
+          }
+        }
+        ScalarOp::Mul | ScalarOp::Fma if k(node.args[0]) + k(node.args[1]) > k(node.id) => {
+          need(node.id, self.quant.value_bits)
+        }
+        ScalarOp::DivConst(_) => need(node.id, self.quant.value_bits),
+        _ => {}
+      }
+    }
+    for (y, bits) in checks.into_iter().sorted() {
+      let check = self.scalar.insert_after(y, RangeCheck { bits });
+      if let Some(ky) = self.scales.scale_bits.get(&y).copied() {
+        self.scales.scale_bits.insert(check, ky);
+      }
+    }
+  }
+
+  /// Replaces every [Recip] with a [RecipHint]. Node ids don't change.
+  /// Reciprocals of constants are folded before (see [ScalarGraph::fold_constant_recips]), these are of runtime values.
+  pub fn recip_hints(&mut self) {
+    let graph = &mut self.scalar.graph;
+    for x in graph.node_indices().collect_vec() {
+      if graph.check_node_type::<Recip>(x) {
+        *graph.node_weight_mut(x).unwrap() = Box::new(RecipHint {});
+      }
+    }
+  }
+}
+
+/// Comparison `a < b` of quantized values. The spec for the comparison gadgets of the backends.
+///
+/// The arguments are signed integers at the scales of their nodes, negative ones are field elements `p - n`.
+/// Both are moved to the larger of their scales (exactly, by a multiplication by a power of two),
+/// then offset by `2^bits` and compared as unsigned `bits + 1` bit integers.
+/// That's the signed comparison of `bits + 1` bit two's complement integers, `-2^bits <= a, b < 2^bits`.
+/// Out of that range the offset value fails the range check of the comparison and the circuit has no witness.
+/// The result is 1 at the scale of the node, or 0.
+///
+/// [QuantizedGraph::new] rewrites luminal's [LessThan] into it. A value within [QuantConfig::value_bits]
+/// grows by the difference of the scales on the way to the larger one, so `bits` is `value_bits` plus that difference.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SignedLessThan {
+  pub bits: u32,
+}
+
+impl Operator for SignedLessThan {
+  fn process(&mut self, _inp: Vec<(InputTensor, ShapeTracker)>) -> Vec<Tensor> {
+    panic!("SignedLessThan: We wont be evaluating it either way")
+  }
+}
+
+impl SignedLessThan {
+  /// Reference evaluation on the arguments at the same scale. None out of the range, where the circuit has no witness.
+  pub fn eval(&self, a: i128, b: i128) -> Option<bool> {
+    let range = RangeCheck { bits: self.bits };
+    (range.eval(a) && range.eval(b)).then(|| a < b)
+  }
+}
+
+/// The argument unchanged, checked to be a `bits + 1` bit two's complement integer, `-2^bits <= x < 2^bits`.
+/// The spec for the range gadgets of the backends, see [RangeCheckGadget].
+///
+/// [QuantizedGraph::range_checks] puts them in front of the values the other gadgets assume in range: the arguments
+/// of the comparisons and the truncated products. Whatever reads a checked value reads the check instead, so a value
+/// is checked once however many gadgets need it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RangeCheck {
+  pub bits: u32,
+}
+
+impl Operator for RangeCheck {
+  fn process(&mut self, _inp: Vec<(InputTensor, ShapeTracker)>) -> Vec<Tensor> {
+    panic!("RangeCheck: We wont be evaluating it either way")
+  }
+}
+
+impl RangeCheck {
+  /// Whether the value is in the range, out of it the circuit has no witness.
+  pub fn eval(&self, x: i128) -> bool {
+    let offset = 1i128 << self.bits;
+    (0..2 * offset).contains(&(x + offset))
+  }
+}
+
+/// A backend's range check, the gadget of every [RangeCheck] node. A bit decomposition of `x + 2^bits` into `bits + 1`
+/// bits, or lookups of its limbs in a table of the range, the nodes don't care which.
+pub trait RangeCheckGadget {
+  /// A value of the circuit.
+  type Var;
+  type Error;
+
+  /// Constrains `-2^bits <= x < 2^bits`.
+  fn range_check(&mut self, x: &Self::Var, bits: u32) -> Result<(), Self::Error>;
+}
+
+/// Reciprocal `1 / x` of a runtime value, computed out of the circuit and checked in it.
+/// The spec for the division gadgets of the backends.
+///
+/// With the argument `x` at scale `ka` and the result at `kr`, the result is the integer `r = trunc(2^(ka + kr) / x)`
+/// and 0 for `x == 0`. The backend witnesses `r` (the hint) together with the zero flag `z` and the remainder `rem`, and constrains
+///  - `x * r + rem == 2^(ka + kr) * (1 - z)`, that's "`x * inv == 1`" up to the rounding
+///  - `0 <= rem < |x|` (`rem == 0` for `x == 0`) and `|r| <= 2^(ka + kr)`, so the hint is the only solution
+///  - `z` is the is-zero flag of `x`, and `r * z == 0`
+///
+/// [QuantizedGraph::new] rewrites luminal's [Recip] into it, [RecipHint::eval] computes the hint.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct RecipHint {}
+
+impl Operator for RecipHint {
+  fn process(&mut self, _inp: Vec<(InputTensor, ShapeTracker)>) -> Vec<Tensor> {
+    panic!("RecipHint: We wont be evaluating it either way")
+  }
+}
+
+impl RecipHint {
+  /// The hint for the argument `x`, with `shift` the sum of the scales of the argument and the result.
+  pub fn eval(x: i128, shift: u32) -> i128 {
+    if x == 0 {
+      0
+    } else {
+      // rounds towards zero
+      (1i128 << shift) / x
+    }
+  }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum OverflowKind {
+  /// The value of the node.
+  Value,
+  /// The product of a Mul (or Fma) node before rescaling.
+  Product,
+}
+
+/// A value out of the budget of the circuit, see [QuantizedGraph::overflow_report].
+#[derive(Debug, Clone, PartialEq)]
+pub struct Overflow {
+  pub node: NodeIndex,
+  /// The node of the original tensor graph (the layer) and the physical index in its result, see [crate::scalar::InputsTracker::origin].
+  pub origin: Option<(NodeIndex, usize)>,
+  pub kind: OverflowKind,
+  /// Bits of the magnitude of the value.
+  pub bits: u32,
+  /// Bits the circuit has for it.
+  pub budget: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct OverflowReport {
+  /// Sorted by node.
+  pub overflows: Vec<Overflow>,
+}
+
+impl OverflowReport {
+  pub fn is_empty(&self) -> bool {
+    self.overflows.is_empty()
+  }
+
+  /// The nodes of the original graph with overflows, each with the most bits over its budget.
+  pub fn layers(&self) -> Vec<(NodeIndex, u32)> {
+    self
+      .overflows
+      .iter()
+      .filter_map(|o| o.origin.map(|(t, _)| (t, o.bits - o.budget)))
+      .into_grouping_map()
+      .max()
+      .into_iter()
+      .sorted()
+      .collect()
+  }
+}
+
+impl std::fmt::Display for OverflowReport {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    for o in self.overflows.iter() {
+      let origin = match o.origin {
+        Some((t, i)) => format!("element {} of tensor node {:?}", i, t),
+        None => "unknown origin".to_string(),
+      };
+      writeln!(
+        f,
+        "{:?} of {:?} ({}) needs {} bits, the budget is {}",
+        o.kind, o.node, origin, o.bits, o.budget
+      )?;
+    }
+    Ok(())
+  }
+}
+
+/// Bits of the magnitude of `v`.
+fn bits_of(v: i128) -> u32 {
+  128 - v.unsigned_abs().leading_zeros()
+}
+
+/// The shift of a divisor that's a power of two, as those of [QuantizedGraph::explicit_rescales].
+pub fn divisor_shift(divisor: f32) -> Option<u32> {
+  let d = divisor as u64;
+  (divisor.fract() == 0.0 && d.is_power_of_two()).then(|| d.trailing_zeros())
+}
+
+/// `v` at `2^-from` fixed-point moved to `2^-to`: exact going up, rounded down going down (the circuit's `Rescale`).
+fn rescale(v: i128, from: u32, to: u32) -> i128 {
+  match from.cmp(&to) {
+    Ordering::Equal => v,
+    Ordering::Less => v << (to - from),
+    Ordering::Greater => v >> (from - to),
+  }
+}
+
+/// The integer the backends compare an argument at scale `scale_bits` with for [ScalarOp::LessThanConst] (`less_than`)
+/// and [ScalarOp::GreaterThanConst]: `x < c` is `n < ceil(c * 2^scale_bits)` and `c < x` is
+/// `floor(c * 2^scale_bits) < n` for the quantized `n`, exactly, with no rounding of the constant to get wrong.
+pub fn const_threshold(c: f32, scale_bits: u32, less_than: bool) -> i128 {
+  let scaled = f64::from(c) * (1u64 << scale_bits) as f64;
+  if less_than {
+    scaled.ceil() as i128
+  } else {
+    scaled.floor() as i128
+  }
+}
+
+impl QuantizedGraph {
+  /// Evaluates the quantized graph exactly as the exported circuit does, on integers:
+  /// products rounded down to the scale of the result, comparisons at the larger scale of the two sides.
+  /// Inputs are floats given per original tensor graph input (as for [ScalarGraph::evaluate]), quantized at the scales of their nodes.
+  /// Returns the outputs in the order of [crate::scalar::InputsTracker::ordered_outputs], each at the scale of its node.
+  ///
+  /// Values out of [QuantConfig::value_bits] are computed anyway, the circuit wouldn't accept them. See [QuantizedGraph::overflows].
+  pub fn evaluate_int(&self, inputs: &HashMap<NodeIndex, Vec<f32>>) -> Vec<i64> {
+    let values = self.evaluate_int_all(inputs);
+    self
+      .scalar
+      .inputs_tracker
+      .ordered_outputs()
+      .into_iter()
+      .map(|y| values[&y])
+      .collect()
+  }
+
+  /// [QuantizedGraph::evaluate_int] of every node but the rescaled products (see [QuantizedGraph::explicit_rescales]),
+  /// at twice the bits of the values they needn't fit an i64.
+  pub fn evaluate_int_all(&self, inputs: &HashMap<NodeIndex, Vec<f32>>) -> HashMap<NodeIndex, i64> {
+    let (values, _) = self.evaluate_wide(inputs);
+    let rescaled = self.rescaled_products();
+    values
+      .into_iter()
+      .filter(|(x, _)| !rescaled.contains(x))
+      .map(|(x, v)| {
+        let v = i64::try_from(v).unwrap_or_else(|_| panic!("Value of {:?} overflows i64", x));
+        (x, v)
+      })
+      .collect()
+  }
+
+  /// The values of [QuantizedGraph::evaluate_int_all] before the conversion to i64,
+  /// and the products of the Mul nodes before rescaling.
+  fn evaluate_wide(
+    &self,
+    inputs: &HashMap<NodeIndex, Vec<f32>>,
+  ) -> (HashMap<NodeIndex, i128>, HashMap<NodeIndex, i128>) {
+    let k = |y: NodeIndex| self.scales.get(&self.quant, y);
+    let mut input_values: HashMap<NodeIndex, f32> = HashMap::new();
+    for (x, little_ids) in self.scalar.inputs_tracker.new_inputs.iter() {
+      let data = inputs
+        .get(x)
+        .unwrap_or_else(|| panic!("Missing input for {:?}", x));
+      assert!(
+        data.len() == little_ids.len(),
+        "Input {:?} expects {} values, got {}",
+        x,
+        little_ids.len(),
+        data.len()
+      );
+      input_values.extend(little_ids.iter().copied().zip(data.iter().copied()));
+    }
+
+    let mut values: HashMap<NodeIndex, i128> = HashMap::new();
+    let mut products: HashMap<NodeIndex, i128> = HashMap::new();
+    for node in self.scalar.freeze_ops().nodes {
+      let x = node.id;
+      let args: Vec<(i128, u32)> = node.args.iter().map(|y| (values[y], k(*y))).collect();
+      let kx = k(x);
+      let at_kx = |(v, ky): (i128, u32)| rescale(v, ky, kx);
+      let v = match node.op {
+        ScalarOp::Constant(val) => i128::from(self.scales.config(&self.quant, x).quantize(val)),
+        ScalarOp::Input => {
+          let v = *input_values
+            .get(&x)
+            .unwrap_or_else(|| panic!("Input node {:?} without a value", x));
+          i128::from(self.scales.config(&self.quant, x).quantize(v))
+        }
+        ScalarOp::Add => at_kx(args[0]) + at_kx(args[1]),
+        ScalarOp::Sub => at_kx(args[0]) - at_kx(args[1]),
+        ScalarOp::Neg => -at_kx(args[0]),
+        ScalarOp::Mul | ScalarOp::Fma => {
+          // saturating, past the overflow the values are meaningless anyway
+          let product = args[0].0.saturating_mul(args[1].0);
+          products.insert(x, product);
+          let v = rescale(product, args[0].1 + args[1].1, kx);
+          match args.get(2) {
+            Some(c) => v + at_kx(*c),
+            None => v,
+          }
+        }
+        ScalarOp::LessThan => {
+          let c = args[0].1.max(args[1].1);
+          if rescale(args[0].0, args[0].1, c) < rescale(args[1].0, args[1].1, c) {
+            1 << kx
+          } else {
+            0
+          }
+        }
+        ScalarOp::SignedLessThan(bits) => {
+          let c = args[0].1.max(args[1].1);
+          let (a, b) = (
+            rescale(args[0].0, args[0].1, c),
+            rescale(args[1].0, args[1].1, c),
+          );
+          // out of range the circuit has no witness, the arguments are among the overflows then
+          if (SignedLessThan { bits }).eval(a, b).unwrap_or(a < b) {
+            1 << kx
+          } else {
+            0
+          }
+        }
+        ScalarOp::LessThanConst(c) => {
+          if args[0].0 < const_threshold(c, args[0].1, true) {
+            1 << kx
+          } else {
+            0
+          }
+        }
+        ScalarOp::GreaterThanConst(c) => {
+          if const_threshold(c, args[0].1, false) < args[0].0 {
+            1 << kx
+          } else {
+            0
+          }
+        }
+        ScalarOp::RecipHint => RecipHint::eval(args[0].0, args[0].1 + kx),
+        // of the integer, whatever the scales: by a power of two it's a rescale, see explicit_rescales
+        ScalarOp::DivConst(divisor) => args[0].0.div_euclid(divisor as i128),
+        ScalarOp::ModConst(modulus) => args[0].0.rem_euclid(modulus as i128),
+        // out of range the circuit has no witness, the argument is among the overflows then
+        ScalarOp::RangeCheck(_) => at_kx(args[0]),
+        ScalarOp::Max => at_kx(args[0]).max(at_kx(args[1])),
+        ScalarOp::Relu => at_kx(args[0]).max(0),
+        ScalarOp::Lookup(kind) => {
+          // the table of the quantized input, clamped to the domain as the backends do
+          let table = self.scalar.tables.find(kind).unwrap();
+          let input = QuantConfig {
+            scale_bits: args[0].1,
+            ..self.quant
+          }
+          .dequantize(args[0].0 as i64)
+          .clamp(table.domain.0, table.domain.1);
+          i128::from(
+            self
+              .scales
+              .config(&self.quant, x)
+              .quantize(table.kind.eval(input)),
+          )
+        }
+        op => panic!(
+          "Quantized evaluation: unsupported scalar op {} at {:?}",
+          op.name(),
+          x
+        ),
+      };
+      values.insert(x, v);
+    }
+    (values, products)
+  }
+
+  /// Everything out of the budget of the circuit in the evaluation on the inputs (as for [QuantizedGraph::evaluate_int]):
+  /// values out of [QuantConfig::value_bits] and products out of twice that, the bound of the rescaling after a Mul.
+  /// The circuit has no witness for such inputs, better to change the scales (e.g. [select_tensor_scales]) than to find out when proving.
+  /// The [RangeCheck]s are left out, they check at least `value_bits` of the values of their arguments, and so are
+  /// the values of the rescaled products, which are their products.
+  pub fn overflow_report(&self, inputs: &HashMap<NodeIndex, Vec<f32>>) -> OverflowReport {
+    let (values, products) = self.evaluate_wide(inputs);
+    let n = self.quant.value_bits;
+    let origin = |x: &NodeIndex| self.scalar.inputs_tracker.origin.get(x).copied();
+    let rescaled = self.rescaled_products();
+    let values = values
+      .iter()
+      .filter(|(x, _)| !self.scalar.graph.check_node_type::<RangeCheck>(**x))
+      .filter(|(x, _)| !rescaled.contains(*x))
+      .map(|(x, v)| (x, v, OverflowKind::Value, n));
+    let products = products
+      .iter()
+      .map(|(x, v)| (x, v, OverflowKind::Product, 2 * n));
+    let overflows = values
+      .chain(products)
+      .filter(|(_, v, _, budget)| bits_of(**v) > *budget)
+      .map(|(x, v, kind, budget)| Overflow {
+        node: *x,
+        origin: origin(x),
+        kind,
+        bits: bits_of(*v),
+        budget,
+      })
+      .sorted_by_key(|o| (o.node, o.kind))
+      .collect();
+    OverflowReport { overflows }
+  }
+
+  /// Nodes whose values (from [QuantizedGraph::evaluate_int_all]) don't fit [QuantConfig::value_bits].
+  /// For where they come from and the products see [QuantizedGraph::overflow_report].
+  pub fn overflows(&self, values: &HashMap<NodeIndex, i64>) -> Vec<NodeIndex> {
+    values
+      .iter()
+      .filter(|(x, v)| {
+        !self.quant.in_range(**v) && !self.scalar.graph.check_node_type::<RangeCheck>(**x)
+      })
+      .map(|(x, _)| *x)
+      .sorted()
+      .collect()
+  }
+
+  /// Outputs of [QuantizedGraph::evaluate_int] back to floats, collected into the retrieved tensors of the original graph.
+  pub fn dequantize_outputs(&self, outputs: &[i64]) -> HashMap<NodeIndex, Vec<f32>> {
+    let tracker = &self.scalar.inputs_tracker;
+    let value: HashMap<NodeIndex, f32> = tracker
+      .ordered_outputs()
+      .into_iter()
+      .zip(outputs)
+      .map(|(y, v)| (y, self.scales.config(&self.quant, y).dequantize(*v)))
+      .collect();
+    tracker
+      .new_outputs
+      .iter()
+      .map(|(x, little_ids)| (*x, little_ids.iter().map(|y| value[y]).collect()))
+      .collect()
+  }
+}
+
+/// The largest scale at which values up to `max_abs` in magnitude stay below `2^(value_bits - headroom_bits)`.
+/// At most `value_bits - headroom_bits`, for values that are all zeros.
+pub fn select_scale_bits(quant: &QuantConfig, max_abs: f32, headroom_bits: u32) -> u32 {
+  let bits = quant.value_bits.saturating_sub(headroom_bits);
+  if max_abs.is_nan() || max_abs <= 0.0 {
+    return bits;
+  }
+  // |x| * 2^k < 2^bits  <=>  k < bits - log2 |x|
+  let k = (f64::from(bits) - f64::from(max_abs).log2()).ceil() - 1.0;
+  k.clamp(0.0, f64::from(bits)) as u32
+}
+
+/// Scales per tensor of the original graph selected from the ranges of their values, see [select_scale_bits].
+pub fn select_tensor_scales(
+  quant: &QuantConfig,
+  ranges: &HashMap<NodeIndex, (f32, f32)>,
+  headroom_bits: u32,
+) -> HashMap<NodeIndex, u32> {
+  ranges
+    .iter()
+    .map(|(t, (lo, hi))| {
+      let max_abs = lo.abs().max(hi.abs());
+      (*t, select_scale_bits(quant, max_abs, headroom_bits))
+    })
+    .collect()
+}
+
+/// Min and max of the values computed for every tensor of the original graph over the samples, by evaluating the scalar graph.
+/// The intermediate nodes count as well (e.g. the partial sums of a reduction), see [crate::scalar::InputsTracker::origin].
+/// Samples are given per original input, as for [ScalarGraph::evaluate].
+pub fn tensor_ranges(
+  scalar: &ScalarGraph,
+  samples: &[HashMap<NodeIndex, Vec<f32>>],
+) -> HashMap<NodeIndex, (f32, f32)> {
+  let mut ranges: HashMap<NodeIndex, (f32, f32)> = HashMap::new();
+  for sample in samples {
+    for (x, v) in scalar.evaluate(sample) {
+      if let Some((t, _)) = scalar.inputs_tracker.origin.get(&x) {
+        let (lo, hi) = ranges.entry(*t).or_insert((v, v));
+        *lo = lo.min(v);
+        *hi = hi.max(v);
+      }
+    }
+  }
+  ranges
+}
+
+/// Min and max of every tensor of the model (see [crate::model::GraphForSnark::graph]) over representative inputs.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ActivationRanges {
+  pub ranges: HashMap<NodeIndex, (f32, f32)>,
+}
+
+impl ActivationRanges {
+  /// Scales per tensor fitting the observed ranges, see [select_tensor_scales] and [NodeScales::from_tensor_scales].
+  pub fn tensor_scales(&self, quant: &QuantConfig, headroom_bits: u32) -> HashMap<NodeIndex, u32> {
+    select_tensor_scales(quant, &self.ranges, headroom_bits)
+  }
+}
+
+/// Runs the model over the samples (model inputs, as for [TrainedGraph::evaluate]) keeping every intermediate tensor,
+/// and records the range of each. Unlike [crate::scalar::range_analysis] these are the values actually met,
+/// so leave some headroom when selecting scales from them.
+pub fn calibrate(trained: &TrainedGraph, samples: &[Vec<f32>]) -> ActivationRanges {
+  let model = &trained.graph;
+  let mut ranges: HashMap<NodeIndex, (f32, f32)> = HashMap::new();
+  for sample in samples {
+    let (mut g, remap) = copy_graph_roughly(&model.graph);
+    let sources = model
+      .weights
+      .iter()
+      .cloned()
+      .chain([(model.input_id, sample.clone())]);
+    for (x, data) in sources {
+      g.get_op_mut::<Function>(remap[&x]).1 = Box::new(move |_| vec![Tensor::new(data.clone())]);
+    }
+    g.no_delete.extend(remap.values().copied());
+    g.execute();
+    for (x, y) in remap.iter() {
+      let Some(data) = g.get_tensor_ref(*y, 0).and_then(tensor_f32) else {
+        continue;
+      };
+      for v in data.iter() {
+        let (lo, hi) = ranges.entry(*x).or_insert((*v, *v));
+        *lo = lo.min(*v);
+        *hi = hi.max(*v);
+      }
+    }
+  }
+  ActivationRanges { ranges }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::collections::HashMap;
+
+  use luminal::{graph::Graph, prelude::NodeIndex, shape::R1};
+
+  use super::{
+    calibrate, select_scale_bits, NodeScales, OverflowKind, QuantConfig, QuantizedGraph,
+    RangeCheck, RecipHint, SignedLessThan,
+  };
+  use crate::scalar::{scalar, ScalarOp};
+
+  #[test]
+  fn test_quantize_roundtrip() {
+    let q = QuantConfig::default();
+    for x in [0.0, 1.0, -1.5, 3.25, -1000.125] {
+      assert_eq!(q.dequantize(q.quantize(x)), x);
+    }
+    assert_eq!(q.quantize(1.0), 1 << 16);
+    assert!(q.in_range(q.quantize(-1e6)));
+  }
+
+  #[test]
+  fn test_selected_scales_fit_the_range() {
+    let q = QuantConfig {
+      scale_bits: 16,
+      value_bits: 24,
+    };
+    assert_eq!(select_scale_bits(&q, 0.5, 0), 24);
+    assert_eq!(select_scale_bits(&q, 1.0, 0), 23);
+    assert_eq!(select_scale_bits(&q, 1000.0, 2), 12);
+    assert_eq!(select_scale_bits(&q, 0.0, 2), 22);
+    for max_abs in [0.001, 0.7, 3.0, 1e5] {
+      let k = select_scale_bits(&q, max_abs, 0);
+      let scaled = QuantConfig { scale_bits: k, ..q };
+      assert!(scaled.in_range(scaled.quantize(max_abs)));
+      assert!(k == 24 || k == 0 || (max_abs * 2f32.powi(k as i32 + 1)) >= 2f32.powi(24));
+    }
+  }
+
+  #[test]
+  fn test_calibrate_records_every_tensor() {
+    let trained = crate::model::fixed_weights::run_model();
+    let samples = [vec![1.0, 2.0, 3.0], vec![-1.0, 0.5, 0.0]];
+    let activations = calibrate(&trained, &samples);
+    let model = &trained.graph;
+    assert_eq!(activations.ranges[&model.input_id], (-1.0, 3.0));
+    for output in model.outputs.iter() {
+      let (lo, hi) = activations.ranges[output];
+      for sample in samples.iter() {
+        let v = crate::scalar::evaluate_tensor_graph(
+          &model.graph,
+          &model
+            .weights
+            .iter()
+            .cloned()
+            .chain([(model.input_id, sample.clone())])
+            .collect(),
+        )[output][0];
+        assert!(lo <= v && v <= hi);
+      }
+    }
+    let scales = activations.tensor_scales(&QuantConfig::default(), 2);
+    assert_eq!(scales.len(), activations.ranges.len());
+  }
+
+  #[test]
+  fn test_evaluate_int_close_to_floats() {
+    let mut cx = Graph::new();
+    let a = cx.tensor::<R1<3>>();
+    let b = cx.tensor::<R1<3>>();
+    let d = cx.tensor::<R1<3>>();
+    let c = (a * b + d).relu().retrieve();
+    let inputs: HashMap<_, Vec<f32>> = [
+      (a.id, vec![1.5, -2.0, 0.1]),
+      (b.id, vec![2.0, 3.0, -0.7]),
+      (d.id, vec![0.25, 0.25, 0.25]),
+    ]
+    .into_iter()
+    .collect();
+    let quant = QuantConfig::default();
+    for output_bits in [quant.scale_bits, 6] {
+      let (sc, _) = scalar(&cx);
+      let expected = sc.evaluate_outputs(&inputs)[&c.id].clone();
+      let scales =
+        NodeScales::from_tensor_scales(&sc, &[(c.id, output_bits)].into_iter().collect());
+      let quantized = QuantizedGraph {
+        scalar: sc,
+        quant,
+        scales,
+      };
+      let outputs = quantized.evaluate_int(&inputs);
+      let got = &quantized.dequantize_outputs(&outputs)[&c.id];
+      for (e, g) in expected.iter().zip(got) {
+        assert!((e - g).abs() <= 2f32.powi(-(output_bits as i32)) + 1e-4);
+      }
+      assert!(quantized
+        .overflows(&quantized.evaluate_int_all(&inputs))
+        .is_empty());
+    }
+  }
+
+  #[test]
+  fn test_signed_comparisons() {
+    let lt = SignedLessThan { bits: 2 };
+    assert_eq!(lt.eval(-4, 3), Some(true));
+    assert_eq!(lt.eval(3, -4), Some(false));
+    assert_eq!(lt.eval(-1, -1), Some(false));
+    assert_eq!(lt.eval(4, 0), None);
+    assert_eq!(lt.eval(0, -5), None);
+
+    let mut cx = Graph::new();
+    let a = cx.tensor::<R1<3>>();
+    let b = cx.tensor::<R1<3>>();
+    let c = a.less_than(b).retrieve();
+    let inputs: HashMap<_, Vec<f32>> =
+      [(a.id, vec![1.5, -2.0, 0.25]), (b.id, vec![2.0, -3.0, 0.25])]
+        .into_iter()
+        .collect();
+    let (sc, _) = scalar(&cx);
+    let expected = sc.evaluate_outputs(&inputs)[&c.id].clone();
+    let scales = NodeScales::from_tensor_scales(&sc, &[(b.id, 8)].into_iter().collect());
+    let quant = QuantConfig::default();
+    let quantized = QuantizedGraph::new(sc, quant, scales);
+    let graph = &quantized.scalar.graph;
+    let comparisons = graph
+      .node_indices()
+      .filter(|x| graph.check_node_type::<SignedLessThan>(*x))
+      .collect::<Vec<_>>();
+    assert_eq!(comparisons.len(), 3);
+    for x in comparisons {
+      assert_eq!(
+        graph.get_op::<SignedLessThan>(x).bits,
+        quant.value_bits + quant.scale_bits - 8
+      );
+    }
+    assert!(!graph
+      .node_indices()
+      .any(|x| graph.check_node_type::<luminal::op::LessThan>(x)));
+    let outputs = quantized.evaluate_int(&inputs);
+    assert_eq!(quantized.dequantize_outputs(&outputs)[&c.id], expected);
+  }
+
+  #[test]
+  fn test_range_checks() {
+    let rc = RangeCheck { bits: 2 };
+    assert!(rc.eval(-4) && rc.eval(3));
+    assert!(!rc.eval(4) && !rc.eval(-5));
+
+    let mut cx = Graph::new();
+    let a = cx.tensor::<R1<3>>();
+    let b = cx.tensor::<R1<3>>();
+    // the product is truncated and compared, a is compared twice
+    let p = (a * b).retrieve();
+    let c = p.less_than(a).retrieve();
+    let d = (b * 2.0).less_than(a).retrieve();
+    let inputs: HashMap<_, Vec<f32>> =
+      [(a.id, vec![1.5, -2.0, 0.25]), (b.id, vec![2.0, -3.0, 0.5])]
+        .into_iter()
+        .collect();
+    let (sc, _) = scalar(&cx);
+    let expected = sc.evaluate_outputs(&inputs);
+    let quant = QuantConfig::default();
+    let quantized = QuantizedGraph::new(sc, quant, NodeScales::default());
+    let frozen = quantized.scalar.freeze_ops();
+    let checks = frozen
+      .nodes
+      .iter()
+      .filter(|n| matches!(n.op, ScalarOp::RangeCheck(_)))
+      .collect::<Vec<_>>();
+    // a, the products a * b and b * 2, one check each however many reads
+    assert_eq!(checks.len(), 3 * 3);
+    assert!(checks
+      .iter()
+      .all(|n| n.op == ScalarOp::RangeCheck(quant.value_bits)));
+    let checked = checks.iter().map(|n| n.id).collect::<Vec<_>>();
+    for n in frozen.nodes.iter() {
+      if matches!(n.op, ScalarOp::SignedLessThan(_)) {
+        assert!(n.args.iter().all(|y| checked.contains(y)));
+      }
+    }
+    let outputs = quantized.dequantize_outputs(&quantized.evaluate_int(&inputs));
+    for x in [p.id, c.id, d.id] {
+      assert_eq!(outputs[&x], expected[&x]);
+    }
+    assert!(quantized.overflow_report(&inputs).is_empty());
+  }
+
+  #[test]
+  fn test_recip_hints() {
+    assert_eq!(RecipHint::eval(3, 4), 5);
+    assert_eq!(RecipHint::eval(-3, 4), -5);
+    assert_eq!(RecipHint::eval(0, 4), 0);
+
+    let mut cx = Graph::new();
+    let a = cx.tensor::<R1<4>>();
+    let c = a.recip().retrieve();
+    let (sc, _) = scalar(&cx);
+    let quant = QuantConfig::default();
+    let quantized = QuantizedGraph::new(sc, quant, NodeScales::default());
+    let graph = &quantized.scalar.graph;
+    assert!(graph
+      .node_indices()
+      .any(|x| graph.check_node_type::<RecipHint>(x)));
+    assert!(!graph
+      .node_indices()
+      .any(|x| graph.check_node_type::<luminal::op::Recip>(x)));
+    let inputs = [(a.id, vec![2.0, -0.5, 3.0, 0.0])].into_iter().collect();
+    let outputs = quantized.evaluate_int(&inputs);
+    let got = &quantized.dequantize_outputs(&outputs)[&c.id];
+    for (e, g) in [0.5, -2.0, 1.0 / 3.0, 0.0].iter().zip(got) {
+      assert!((e - g).abs() <= 2f32.powi(-(quant.scale_bits as i32)));
+    }
+  }
+
+  #[test]
+  fn test_explicit_rescales() {
+    let mut cx = Graph::new();
+    let a = cx.tensor::<R1<3>>();
+    let b = cx.tensor::<R1<3>>();
+    let d = cx.tensor::<R1<3>>();
+    let c = (a * b + d).retrieve();
+    let inputs: HashMap<_, Vec<f32>> = [
+      (a.id, vec![1.5, -2.0, 0.1]),
+      (b.id, vec![2.0, 3.0, -0.7]),
+      (d.id, vec![0.25, 0.25, 0.25]),
+    ]
+    .into_iter()
+    .collect();
+    let (sc, _) = scalar(&cx);
+    let expected = sc.evaluate_outputs(&inputs)[&c.id].clone();
+    let quant = QuantConfig::default();
+    let quantized = QuantizedGraph::new(sc, quant, NodeScales::default());
+    let frozen = quantized.scalar.freeze_ops();
+    let k = |y: NodeIndex| quantized.scales.get(&quant, y);
+    let divisions = frozen
+      .nodes
+      .iter()
+      .filter(|n| matches!(n.op, ScalarOp::DivConst(_)))
+      .collect::<Vec<_>>();
+    assert_eq!(divisions.len(), 3);
+    for n in divisions {
+      assert_eq!(n.op, ScalarOp::DivConst(2f32.powi(quant.scale_bits as i32)));
+      assert_eq!(k(n.args[0]), 2 * quant.scale_bits);
+    }
+    // no product is truncated any more
+    for n in frozen.nodes.iter() {
+      if matches!(n.op, ScalarOp::Mul | ScalarOp::Fma) {
+        assert_eq!(k(n.args[0]) + k(n.args[1]), k(n.id));
+      }
+    }
+    let got = &quantized.dequantize_outputs(&quantized.evaluate_int(&inputs))[&c.id];
+    for (e, g) in expected.iter().zip(got) {
+      assert!((e - g).abs() <= 2f32.powi(-(quant.scale_bits as i32)) + 1e-4);
+    }
+    assert!(quantized.overflow_report(&inputs).is_empty());
+  }
+
+  #[test]
+  fn test_overflow_report() {
+    let mut cx = Graph::new();
+    let a = cx.tensor::<R1<2>>();
+    let b = cx.tensor::<R1<2>>();
+    let c = (a * b).retrieve();
+    let quant = QuantConfig {
+      scale_bits: 8,
+      value_bits: 12,
+    };
+    let quantized = QuantizedGraph::new(scalar(&cx).0, quant, NodeScales::default());
+    let inputs = [(a.id, vec![10.0, 100.0]), (b.id, vec![12.0, 12.0])]
+      .into_iter()
+      .collect();
+    let report = quantized.overflow_report(&inputs);
+    let found = |origin, kind| {
+      report
+        .overflows
+        .iter()
+        .find(|o| o.origin == Some(origin) && o.kind == kind)
+        .map(|o| (o.bits, o.budget))
+    };
+    // 100 * 2^8 needs 15 bits, 10 * 2^8 still fits
+    assert_eq!(found((a.id, 1), OverflowKind::Value), Some((15, 12)));
+    assert_eq!(found((a.id, 0), OverflowKind::Value), None);
+    assert_eq!(found((c.id, 0), OverflowKind::Value), Some((15, 12)));
+    assert_eq!(found((c.id, 0), OverflowKind::Product), None);
+    assert_eq!(found((c.id, 1), OverflowKind::Product), Some((27, 24)));
+    assert_eq!(report.layers(), vec![(a.id, 3), (c.id, 7)]);
+    assert_eq!(
+      quantized
+        .overflows(&quantized.evaluate_int_all(&inputs))
+        .len(),
+      3
+    );
+  }
+}
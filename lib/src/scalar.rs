@@ -7,15 +7,27 @@
 // Problem: What about nodes that output multiple values? Add, Mul, LessThan, ReduceAdd - are not like that right?
 use luminal::graph::Graph;
 
-use std::{collections::HashMap, error::Error, fs::File, io::Write};
+use std::{
+  cmp::Reverse,
+  collections::{BinaryHeap, HashMap, HashSet},
+  error::Error,
+  fs::File,
+  io::Write,
+};
 
+use crate::{
+  dtype::tensor_f32,
+  quant::{RangeCheck, RecipHint, SignedLessThan},
+  utils::sampled,
+};
+use blake2::{Blake2s, Digest};
 use itertools::Itertools;
 use petgraph::{
   graph::EdgeIndex,
   visit::{EdgeRef, IntoEdgeReferences, IntoNodeIdentifiers, NodeRef},
   Direction::{Incoming, Outgoing},
 };
-use tracing::{debug, instrument, warn};
+use tracing::{field, info_span, instrument, trace, warn};
 
 use luminal::{
   op::{Constant, InputTensor, Operator},
@@ -25,6 +37,51 @@ use luminal::{
 
 // use crate::model::copy_graph_roughly;
 
+pub mod argmax;
+pub mod batch;
+pub mod compiler;
+pub mod contiguous;
+pub mod eval;
+pub mod freeze;
+pub mod frozen;
+pub mod gather;
+pub mod graphviz;
+pub mod ir;
+pub mod lookup;
+pub mod op;
+pub mod parallel;
+pub mod partition;
+pub mod plan;
+pub mod pool;
+pub mod range;
+pub mod rewrite;
+pub mod shapes;
+pub mod stream;
+pub mod support;
+pub mod templates;
+pub mod testing;
+pub use argmax::*;
+pub use batch::*;
+pub use compiler::*;
+pub use contiguous::*;
+pub use eval::*;
+pub use freeze::*;
+pub use frozen::*;
+pub use gather::*;
+pub use graphviz::*;
+pub use ir::*;
+pub use lookup::*;
+pub use op::*;
+pub use parallel::*;
+pub use partition::*;
+pub use plan::*;
+pub use pool::*;
+pub use range::*;
+pub use shapes::*;
+pub use stream::*;
+pub use support::*;
+pub use templates::*;
+
 /// Asserts (in non-strictly-typed way) that all input tensors are single values.
 #[derive(Debug)]
 pub struct ScalarGraph {
@@ -44,6 +101,8 @@ pub struct ScalarGraph {
   pub graph: Graph,
   /// In the rewrite to scalar we substitute nodes for multiple nodes, here's a mapping tracking that.
   pub inputs_tracker: InputsTracker,
+  /// Tables of the [LookupOp] nodes.
+  pub tables: TableRegistry,
 }
 
 impl ScalarGraph {
@@ -53,24 +112,366 @@ impl ScalarGraph {
     ScalarGraph {
       graph: g,
       inputs_tracker,
+      tables: self.tables.clone(),
+    }
+  }
+
+  /// Relabels the nodes deterministically, so that structurally equal scalar graphs get equal node ids
+  /// no matter how they were built (and e.g. cached proving keys stay valid across runs).
+  ///
+  /// Nodes are numbered in a topological order, BFS-like from the inputs:
+  /// among the nodes whose arguments are all numbered, the next is the one with the smallest
+  /// (canonical ids of the arguments, op), ties broken by how the nodes are used, down to the outputs. Inputs go first in
+  /// the order of [InputsTracker::ordered_inputs].
+  pub fn canonicalize(&self) -> Self {
+    let (g, remap) = copy_graph_in_order(&self.graph, self.canonical_order());
+    ScalarGraph {
+      graph: g,
+      inputs_tracker: self.inputs_tracker.remap(remap),
+      tables: self.tables.clone(),
+    }
+  }
+
+  /// The nodes in the order of [ScalarGraph::canonicalize], the canonical id of a node is its position.
+  pub fn canonical_order(&self) -> Vec<NodeIndex> {
+    let graph = &self.graph;
+    let input_pos: HashMap<NodeIndex, usize> = self
+      .inputs_tracker
+      .ordered_inputs()
+      .into_iter()
+      .enumerate()
+      .map(|(i, x)| (x, i))
+      .collect();
+    let mut missing_args: HashMap<NodeIndex, usize> = graph
+      .node_indices()
+      .map(|x| (x, graph.edges_directed(x, Incoming).count()))
+      .collect();
+    // nodes equal so far (e.g. equal subexpressions CSE didn't merge) are told apart by how they're used, not by
+    // their ids: by a digest of the node and of the nodes using it, recursively, down to the outputs
+    let digest = |s: String| {
+      let mut d = [0u8; 32];
+      d.copy_from_slice(&Blake2s::digest(s.as_bytes()));
+      d
+    };
+    let output_pos: HashMap<NodeIndex, usize> = self
+      .inputs_tracker
+      .ordered_outputs()
+      .into_iter()
+      .enumerate()
+      .map(|(i, x)| (x, i))
+      .collect();
+    let topo = petgraph::algo::toposort(&graph.graph, None).expect("Scalar graph has a cycle");
+    let mut class: HashMap<NodeIndex, [u8; 32]> = HashMap::new();
+    for x in topo.iter().copied() {
+      let args: Vec<(u8, [u8; 32])> = graph
+        .edges_directed(x, Incoming)
+        .filter_map(|e| {
+          e.weight()
+            .as_data()
+            .map(|(inp, _, _)| (inp, class[&e.source()]))
+        })
+        .sorted()
+        .collect();
+      let op = graph.node_weight(x).unwrap();
+      let d = digest(format!("{:?} {:?} {:?}", input_pos.get(&x), op, args));
+      class.insert(x, d);
+    }
+    let mut uses: HashMap<NodeIndex, [u8; 32]> = HashMap::new();
+    for x in topo.iter().rev().copied() {
+      let consumers: Vec<(u8, [u8; 32])> = graph
+        .edges_directed(x, Outgoing)
+        .filter_map(|e| {
+          e.weight()
+            .as_data()
+            .map(|(inp, _, _)| (inp, uses[&e.target()]))
+        })
+        .sorted()
+        .collect();
+      let d = digest(format!(
+        "{:?} {:?} {:?}",
+        class[&x],
+        output_pos.get(&x),
+        consumers
+      ));
+      uses.insert(x, d);
+    }
+
+    let mut canonical: HashMap<NodeIndex, usize> = HashMap::new();
+    let key = |x: NodeIndex, canonical: &HashMap<NodeIndex, usize>| {
+      let args: Vec<(u8, usize)> = graph
+        .edges_directed(x, Incoming)
+        .filter_map(|e| {
+          e.weight()
+            .as_data()
+            .map(|(inp, _, _)| (inp, canonical[&e.source()]))
+        })
+        .sorted()
+        .collect();
+      // inputs before everything else, then by arguments, then by op (with its parameters), then by the uses
+      let pos = input_pos.get(&x).copied().unwrap_or(usize::MAX);
+      let op = format!("{:?}", graph.node_weight(x).unwrap());
+      (pos, args, op, uses[&x], x)
+    };
+    let mut ready: BinaryHeap<Reverse<_>> = missing_args
+      .iter()
+      .filter(|(_, n)| **n == 0)
+      .map(|(x, _)| Reverse(key(*x, &canonical)))
+      .collect();
+    let mut order = vec![];
+    while let Some(Reverse((_, _, _, _, x))) = ready.pop() {
+      canonical.insert(x, order.len());
+      order.push(x);
+      for y in graph.neighbors_directed(x, Outgoing).collect_vec() {
+        let n = missing_args.get_mut(&y).unwrap();
+        *n -= 1;
+        if *n == 0 {
+          ready.push(Reverse(key(y, &canonical)));
+        }
+      }
+    }
+    assert!(
+      order.len() == graph.node_count(),
+      "Scalar graph has a cycle"
+    );
+    order
+  }
+
+  /// Hash of the structure of the computation, including the values of the constants.
+  /// See [ScalarGraph::structural_hash_with].
+  pub fn structural_hash(&self) -> [u8; 32] {
+    self.structural_hash_with(true)
+  }
+
+  /// Hash of the [canonicalized](ScalarGraph::canonicalize) graph: the ops with their arguments,
+  /// the order of the inputs and outputs and the lookup tables. Equal for scalar graphs of the same circuit,
+  /// so it can key a cache of the circuit setup (e.g. proving keys).
+  ///
+  /// Without `with_constants` the values of the [ConstantOp]s are left out, so the hash doesn't change when only they do
+  /// (e.g. with new weights, see [ScalarGraph::update_constants]).
+  pub fn structural_hash_with(&self, with_constants: bool) -> [u8; 32] {
+    let canonical = if with_constants {
+      self.canonicalize()
+    } else {
+      // zero the constants first, their values would decide their canonical order
+      let mut masked = self.copy_graph_roughly();
+      for x in masked.graph.node_indices().collect_vec() {
+        if masked.graph.check_node_type::<ConstantOp>(x) {
+          *masked.graph.node_weight_mut(x).unwrap() = Box::new(ConstantOp { val: 0.0 });
+        }
+      }
+      masked.canonicalize()
+    };
+    let graph = &canonical.graph;
+    let mut hasher = Blake2s::new();
+    for x in graph.node_indices().sorted() {
+      let op = format!("{:?}", graph.node_weight(x).unwrap());
+      let args: Vec<(u8, usize)> = graph
+        .edges_directed(x, Incoming)
+        .filter_map(|e| {
+          e.weight()
+            .as_data()
+            .map(|(inp, _, _)| (inp, e.source().index()))
+        })
+        .sorted()
+        .collect();
+      hasher.update(format!("{} {} {:?};", x.index(), op, args).as_bytes());
     }
+    let inputs = canonical.inputs_tracker.ordered_inputs();
+    let outputs = canonical.inputs_tracker.ordered_outputs();
+    let ids = |xs: Vec<NodeIndex>| xs.iter().map(|x| x.index()).collect_vec();
+    hasher.update(format!("inputs {:?};", ids(inputs)).as_bytes());
+    hasher.update(format!("outputs {:?};", ids(outputs)).as_bytes());
+    hasher.update(format!("tables {:?};", canonical.tables).as_bytes());
+    let mut hash = [0u8; 32];
+    hash.copy_from_slice(&hasher.finalize());
+    hash
   }
 }
 
 /// Rewrite the static tensor computation to scalar computation.
-pub fn scalar(mut cx: Graph) -> ScalarGraph {
-  // TODO: unfortunetely original cx is destroyed in the process
-  // let mut cx1 = (&cx).clone().clone();
-  // we dont care about remap for now
-  let mut remap: Vec<NodeIndex> = vec![];
-  let inputs_tracker = cx.compile(ScalarCompiler::default(), &mut remap);
-  ScalarGraph {
-    graph: cx,
-    inputs_tracker,
+/// The compilation destroys the graph, so it runs on a copy and `cx` stays usable (e.g. to evaluate it for comparison).
+/// Returns the remap of the nodes of `cx` to the nodes of that copy. The returned [InputsTracker] refers to the nodes of `cx` already.
+pub fn scalar(cx: &Graph) -> (ScalarGraph, HashMap<NodeIndex, NodeIndex>) {
+  scalar_with(cx, ScalarCompiler::default())
+}
+
+/// [scalar] with the given compiler settings, a [ScalarCompiler] or the [Scalarize] lowering with the default passes.
+/// Panics listing everything it can't scalarize, see [check_supported], and when over the node budget, see
+/// [try_scalar_with].
+pub fn scalar_with(
+  cx: &Graph,
+  compiler: impl Into<ScalarCompiler>,
+) -> (ScalarGraph, HashMap<NodeIndex, NodeIndex>) {
+  try_scalar_with(cx, compiler).unwrap_or_else(|e| panic!("{}", e))
+}
+
+/// [scalar_with] failing when the scalar graph grows over [Scalarize::max_scalar_nodes], with the nodes made per
+/// tensor of `cx` so far.
+pub fn try_scalar_with(
+  cx: &Graph,
+  compiler: impl Into<ScalarCompiler>,
+) -> Result<(ScalarGraph, HashMap<NodeIndex, NodeIndex>), NodeBudgetExceeded> {
+  let compiler = compiler.into();
+  let span = info_span!(
+    "scalarize",
+    nodes = cx.graph.node_count(),
+    edges = cx.graph.edge_count(),
+    scalar_nodes = field::Empty,
+    scalar_edges = field::Empty
+  );
+  let _enter = span.enter();
+  if compiler.check_supported {
+    let unsupported = check_supported(cx);
+    assert!(
+      unsupported.is_empty(),
+      "Can't scalarize: {}",
+      unsupported.iter().join(", ")
+    );
+  }
+  let (mut g, remap) = copy_graph_roughly(cx);
+  let mut ids: Vec<NodeIndex> = vec![];
+  let back: HashMap<NodeIndex, NodeIndex> = remap.iter().map(|(x, y)| (*y, *x)).collect();
+  let (inputs_tracker, tables) = g
+    .compile(compiler.lowering, &mut ids)
+    .map_err(|e| e.remap_tensors(&back))?;
+  let mut sc = ScalarGraph {
+    graph: g,
+    inputs_tracker: inputs_tracker.remap_tensors(&back),
+    tables,
+  };
+  if compiler.fuse_patterns {
+    sc.fuse_lookups();
+    sc.fuse_relus();
+    sc.fuse_subs();
+  }
+  if compiler.fold_constants {
+    sc.fold_constant_recips();
+  }
+  if compiler.const_comparisons {
+    sc.specialize_constant_comparisons();
+  }
+  if compiler.cse {
+    sc.eliminate_common_subexpressions();
+  }
+  let sc = sc.canonicalize();
+  span.record("scalar_nodes", sc.graph.graph.node_count());
+  span.record("scalar_edges", sc.graph.graph.edge_count());
+  Ok((sc, remap))
+}
+
+/// The nodes some retrieved node reads, the rest is left out of the scalar graph. E.g. after luminal's `Autograd` the
+/// loss itself when only the gradients are retrieved.
+fn live_nodes(graph: &Graph) -> HashSet<NodeIndex> {
+  let mut live = HashSet::new();
+  let mut stack = graph.to_retrieve.keys().copied().collect_vec();
+  while let Some(x) = stack.pop() {
+    if live.insert(x) {
+      stack.extend(
+        graph
+          .edges_directed(x, Incoming)
+          .filter(|e| e.weight().as_data().is_some())
+          .map(|e| e.source()),
+      );
+    }
+  }
+  live
+}
+
+/// Removes the nodes no retrieved node reads, see [live_nodes].
+fn remove_dead_tensors(graph: &mut Graph) {
+  let live = live_nodes(graph);
+  for x in graph
+    .node_indices()
+    .filter(|x| !live.contains(x))
+    .collect_vec()
+  {
+    graph.remove_node(x);
+  }
+}
+
+/// The table the unary op of the node is lowered to, see [LookupKind].
+fn lookup_kind(graph: &Graph, x: NodeIndex) -> Option<LookupKind> {
+  if graph.check_node_type::<Exp2>(x) {
+    Some(LookupKind::Exp2)
+  } else if graph.check_node_type::<Log2>(x) {
+    Some(LookupKind::Log2)
+  } else if graph.check_node_type::<Sin>(x) {
+    Some(LookupKind::Sin)
+  } else {
+    None
+  }
+}
+
+/// The axis of a SumReduce or MaxReduce node, with whether it sums.
+fn reduction_axis(graph: &Graph, x: NodeIndex) -> Option<(bool, usize)> {
+  if graph.check_node_type::<SumReduce>(x) {
+    Some((true, graph.get_op::<SumReduce>(x).0))
+  } else if graph.check_node_type::<MaxReduce>(x) {
+    Some((false, graph.get_op::<MaxReduce>(x).0))
+  } else {
+    None
   }
 }
 
-pub type ScalarCompiler = Scalarize;
+/// Merges the reduction of a reduction (of the same op, the inner one read only by the outer one through a plain view),
+/// as luminal builds reductions over several axes, into one reduction over all their axes.
+/// The merged reduction takes the input of the inner one, whose node is removed.
+/// Returns the axes of every merged reduction in the shape of its input, sorted.
+fn merge_reductions(graph: &mut Graph) -> HashMap<NodeIndex, Vec<usize>> {
+  let data_edges = |graph: &Graph, x: NodeIndex, direction| {
+    graph
+      .edges_directed(x, direction)
+      .filter_map(|e| e.weight().as_data().map(|d| (e.source(), e.target(), d)))
+      .collect_vec()
+  };
+  let mut merged: HashMap<NodeIndex, Vec<usize>> = HashMap::new();
+  for x in petgraph::algo::toposort(&graph.graph, None).unwrap() {
+    let (sums, ax) = match reduction_axis(graph, x) {
+      Some(r) => r,
+      None => continue,
+    };
+    let (y, _, (_, _, sh)) = match data_edges(graph, x, Incoming)[..] {
+      [edge] => edge,
+      _ => continue,
+    };
+    let plain = sh == ShapeTracker::new(&sh.shape());
+    match reduction_axis(graph, y) {
+      Some((inner_sums, _)) if inner_sums == sums && plain => {}
+      _ => continue,
+    }
+    if data_edges(graph, y, Outgoing).len() != 1
+      || graph.to_retrieve.contains_key(&y)
+      || graph.no_delete.contains(&y)
+    {
+      continue;
+    }
+    let (src, _, (input_order, output_order, y_sh)) = match data_edges(graph, y, Incoming)[..] {
+      [edge] => edge,
+      _ => continue,
+    };
+    let mut axes = merged
+      .remove(&y)
+      .unwrap_or_else(|| vec![reduction_axis(graph, y).unwrap().1]);
+    // the axis of x among the ones y keeps
+    let kept = (0..y_sh.shape().len())
+      .filter(|d| !axes.contains(d))
+      .collect_vec();
+    axes.push(kept[ax]);
+    axes.sort();
+    graph.remove_node(y);
+    graph.add_edge(
+      src,
+      x,
+      Dependency::Data {
+        input_order,
+        output_order,
+        shape: y_sh,
+      },
+    );
+    merged.insert(x, axes);
+  }
+  merged
+}
 
 #[derive(Debug, Default, Clone)]
 /// In the scalar graph used for source nodes no matter they original Op.
@@ -104,40 +505,341 @@ impl Operator for Max {
   }
 }
 
+/// `x => max(x, 0)`, recognized from the luminal relu (see [ScalarGraph::fuse_relus]).
+///
+/// ReLU dominates MLP circuits, so backends should give it a specialized constraint instead of a general comparison:
+/// witness the sign bit `b` and the result `y`, constrain `b` boolean and `y == b * x`,
+/// and range-check `y` and `y - x` to be non-negative.
+#[derive(Debug, Default, Clone)]
+pub struct ReluOp {}
+
+impl Operator for ReluOp {
+  fn process(&mut self, _inp: Vec<(InputTensor, ShapeTracker)>) -> Vec<Tensor> {
+    panic!("ReluOp: We wont be evaluating it either way")
+  }
+}
+
+/// `(a, b) => a - b`, recognized from luminal's `a + b * -1` (see [ScalarGraph::fuse_subs]).
+/// Linear like an addition, a backend constrains it the same way.
+#[derive(Debug, Default, Clone)]
+pub struct SubOp {}
+
+impl Operator for SubOp {
+  fn process(&mut self, _inp: Vec<(InputTensor, ShapeTracker)>) -> Vec<Tensor> {
+    panic!("SubOp: We wont be evaluating it either way")
+  }
+}
+
+/// `x => -x`, the `x * -1` not fused into a [SubOp]. Linear, no multiplication.
+#[derive(Debug, Default, Clone)]
+pub struct NegOp {}
+
+impl Operator for NegOp {
+  fn process(&mut self, _inp: Vec<(InputTensor, ShapeTracker)>) -> Vec<Tensor> {
+    panic!("NegOp: We wont be evaluating it either way")
+  }
+}
+
+/// `(a, b, c) => a * b + c`, see [ScalarGraph::fuse_fmas].
+/// A backend takes it as one gate, or as the [Mul] and [Add] it replaces.
+#[derive(Debug, Default, Clone)]
+pub struct FmaOp {}
+
+impl Operator for FmaOp {
+  fn process(&mut self, _inp: Vec<(InputTensor, ShapeTracker)>) -> Vec<Tensor> {
+    panic!("FmaOp: We wont be evaluating it either way")
+  }
+}
+
+/// Floor division by a positive constant: `x => floor(x / divisor)`.
+///
+/// Meant for the integer (quantized) circuit, where rescaling a fixed-point value is a division by a constant:
+/// [crate::quant::QuantizedGraph::explicit_rescales] lowers the rescaling of the products to it. In a quantized graph
+/// it divides the integer, whatever the scales of the argument and the result. [crate::snark::MLSnark] decomposes it
+/// the same way in its offset encoding, for a divisor that's a whole number of its scale.
+/// Contract for snark backends, given the input `x` and the constant `d`:
+///   - witness `q` (the result) and `r`
+///   - constrain `x == q * d + r`
+///   - range-check `0 <= r < d` (bit decomposition of `r` into `ceil(log2(d))` bits, or a lookup)
+///   - range-check `q` to the value range, so `x == q * d + r` can't wrap around the field
+/// Together with [ModConstOp] that's one decomposition, a backend seeing both on the same input may share it.
+#[derive(Debug, Default, Clone)]
+pub struct DivConstOp {
+  pub divisor: f32,
+}
+
+impl Operator for DivConstOp {
+  fn process(&mut self, _inp: Vec<(InputTensor, ShapeTracker)>) -> Vec<Tensor> {
+    panic!("DivConstOp: We wont be evaluating it either way")
+  }
+}
+
+/// Remainder of the floor division by a positive constant: `x => x - modulus * floor(x / modulus)`, always in `[0, modulus)`.
+///
+/// Same contract as [DivConstOp], with `r` being the result.
+#[derive(Debug, Default, Clone)]
+pub struct ModConstOp {
+  pub modulus: f32,
+}
+
+impl Operator for ModConstOp {
+  fn process(&mut self, _inp: Vec<(InputTensor, ShapeTracker)>) -> Vec<Tensor> {
+    panic!("ModConstOp: We wont be evaluating it either way")
+  }
+}
+
+/// `x => x < c` for a constant `c`, 1 or 0. See [ScalarGraph::specialize_constant_comparisons].
+///
+/// The constant is a parameter of the op, not an argument: a backend compares with a fixed value, without the
+/// constant's public input and without moving it to the scale of `x`, so the range check is of `x` alone.
+/// Quantized, the constant is rounded up at the scale of `x`, which keeps the comparison exact
+/// (see [crate::quant::const_threshold]).
+#[derive(Debug, Default, Clone)]
+pub struct LessThanConstOp {
+  pub c: f32,
+}
+
+impl Operator for LessThanConstOp {
+  fn process(&mut self, _inp: Vec<(InputTensor, ShapeTracker)>) -> Vec<Tensor> {
+    panic!("LessThanConstOp: We wont be evaluating it either way")
+  }
+}
+
+/// `x => c < x` for a constant `c`, 1 or 0. As [LessThanConstOp], with the constant rounded down.
+#[derive(Debug, Default, Clone)]
+pub struct GreaterThanConstOp {
+  pub c: f32,
+}
+
+impl Operator for GreaterThanConstOp {
+  fn process(&mut self, _inp: Vec<(InputTensor, ShapeTracker)>) -> Vec<Tensor> {
+    panic!("GreaterThanConstOp: We wont be evaluating it either way")
+  }
+}
+
 #[derive(Debug, Default, Clone)]
 /// Remembers how to supply inputs to scalar graph to match inputs to tensor graph.
 /// Tracks inputs and constant.
 pub struct InputsTracker {
   /// If x was of shape (2, 3) then new_inputs[x] should be a vector of length 6
   pub new_inputs: HashMap<NodeIndex, Vec<NodeIndex>>,
+  /// Same for the retrieved nodes: the little nodes in the order of physical indices of the retrieved tensor.
+  pub new_outputs: HashMap<NodeIndex, Vec<NodeIndex>>,
+  /// For every scalar node: the original tensor node it implements a part of, and the physical index in its result.
+  /// Helper nodes (e.g. the chain of a reduction, constants of a lowering) get the index of the first result that depends on them.
+  pub origin: HashMap<NodeIndex, (NodeIndex, usize)>,
+  /// Constant little nodes not coming from a Constant of the tensor graph, with their values.
+  /// That's the zero shared by the masked out (padding) elements, the folded reciprocals (see [ScalarGraph::fold_constant_recips])
+  /// and the weights frozen by [ScalarGraph::freeze_params].
+  /// The snark bakes them into the circuit, the Constants of the tensor graph are its public inputs.
+  pub constants: HashMap<NodeIndex, f32>,
+  /// With [Scalarize::argmax]: for every MaxReduce and max [Pool2D] node, the little nodes of the index of the max of
+  /// every result, in the order of its physical indices. The index counts over the reduced elements (row major over the
+  /// reduced axes, or over the window). The index nodes are retrieved, results of the circuit like the outputs.
+  pub argmax: HashMap<NodeIndex, Vec<NodeIndex>>,
 }
 
 impl InputsTracker {
   pub fn remap(&self, remap: HashMap<NodeIndex, NodeIndex>) -> Self {
-    let mut m = HashMap::new();
-    for (k, v) in self.new_inputs.iter() {
-      m.insert(*k, v.iter().map(|x| *remap.get(x).unwrap()).collect());
+    let remap_pack = |packs: &HashMap<NodeIndex, Vec<NodeIndex>>| {
+      let mut m = HashMap::new();
+      for (k, v) in packs.iter() {
+        m.insert(*k, v.iter().map(|x| *remap.get(x).unwrap()).collect());
+      }
+      m
+    };
+    InputsTracker {
+      new_inputs: remap_pack(&self.new_inputs),
+      new_outputs: remap_pack(&self.new_outputs),
+      origin: self
+        .origin
+        .iter()
+        .filter_map(|(x, o)| remap.get(x).map(|y| (*y, *o)))
+        .collect(),
+      constants: self
+        .constants
+        .iter()
+        .filter_map(|(x, v)| remap.get(x).map(|y| (*y, *v)))
+        .collect(),
+      argmax: remap_pack(&self.argmax),
+    }
+  }
+
+  /// Renames the original tensor nodes the tracker refers to (the little nodes stay).
+  pub fn remap_tensors(&self, remap: &HashMap<NodeIndex, NodeIndex>) -> Self {
+    let remap_keys = |packs: &HashMap<NodeIndex, Vec<NodeIndex>>| {
+      packs.iter().map(|(k, v)| (remap[k], v.clone())).collect()
+    };
+    InputsTracker {
+      new_inputs: remap_keys(&self.new_inputs),
+      new_outputs: remap_keys(&self.new_outputs),
+      origin: self
+        .origin
+        .iter()
+        .map(|(x, (t, i))| (*x, (remap[t], *i)))
+        .collect(),
+      constants: self.constants.clone(),
+      argmax: remap_keys(&self.argmax),
+    }
+  }
+
+  /// All little input nodes in a stable order: by the original input node, then by physical index.
+  pub fn ordered_inputs(&self) -> Vec<NodeIndex> {
+    ordered_packs(&self.new_inputs)
+  }
+
+  /// All little output nodes in a stable order: by the original retrieved node, then by physical index.
+  pub fn ordered_outputs(&self) -> Vec<NodeIndex> {
+    ordered_packs(&self.new_outputs)
+  }
+
+  /// Pairs the data of the original input node with its little input nodes.
+  /// The data is in row-major order, as passed to luminal's `set`: for an input that's exactly the physical order of `new_inputs`.
+  pub fn assign(
+    &self,
+    original_node: NodeIndex,
+    data: &[f32],
+  ) -> Result<Vec<(NodeIndex, f32)>, AssignError> {
+    let little = self
+      .new_inputs
+      .get(&original_node)
+      .ok_or(AssignError::UnknownInput {
+        input: original_node,
+      })?;
+    if little.len() != data.len() {
+      return Err(AssignError::Length {
+        input: original_node,
+        expected: little.len(),
+        got: data.len(),
+      });
+    }
+    Ok(little.iter().copied().zip(data.iter().copied()).collect())
+  }
+
+  /// [InputsTracker::assign] for every given input, in the order of the original nodes.
+  /// Inputs without data are left out, it is up to the caller whether that's fine.
+  pub fn assign_all(
+    &self,
+    data: &HashMap<NodeIndex, Vec<f32>>,
+  ) -> Result<Vec<(NodeIndex, f32)>, AssignError> {
+    let mut result = vec![];
+    for (x, d) in data.iter().sorted_by_key(|(x, _)| **x) {
+      result.extend(self.assign(*x, d)?);
     }
-    InputsTracker { new_inputs: m }
+    Ok(result)
   }
 }
 
-#[derive(Debug, Default)]
-pub struct Scalarize;
+/// Data that doesn't fit the inputs of the scalar graph.
+#[derive(Debug, Clone, PartialEq)]
+pub enum AssignError {
+  /// Not an input of the tensor graph (or not used by the computation, so scalarized away).
+  UnknownInput { input: NodeIndex },
+  Length {
+    input: NodeIndex,
+    expected: usize,
+    got: usize,
+  },
+}
+
+fn ordered_packs(packs: &HashMap<NodeIndex, Vec<NodeIndex>>) -> Vec<NodeIndex> {
+  packs
+    .iter()
+    .sorted_by_key(|(k, _)| **k)
+    .flat_map(|(_, v)| v.iter().copied())
+    .collect()
+}
+
+/// Physical size of the tensor produced by the node.
+/// We expect one of two cases: there is some outgoing edge OR it is a retrieval node.
+///
+/// The tensor can be read under a different view by every edge (broadcast by one, permuted by another, ...).
+/// Every view is asked for the size, they may disagree when a view leaves out part of the tensor, so we take the
+/// largest: the little nodes have to cover every physical index read. See [shape_report] for all the nodes at once.
+pub fn get_own_size(x: NodeIndex, gg: &Graph) -> usize {
+  match own_size(x, gg) {
+    NodeSize::Static(n) => n,
+    NodeSize::Unread => panic!("A node has no outgoing edges and is not a retrieval node."),
+    NodeSize::MultipleOutputs => panic!("Assuming single output, node {:?}", x),
+    // assuming (and we have to) a staticly known shape
+    NodeSize::Dynamic => panic!("Node's output shape is not static, node {:?}", x),
+  }
+}
+
+#[derive(Debug, Clone)]
+pub struct Scalarize {
+  /// Sqrt is lowered to this many Newton-Raphson iterations `y => (y + x / y) / 2`.
+  pub sqrt_iterations: usize,
+  /// Starting point of the Sqrt iterations. Convergence is quadratic once close, but from far away
+  /// the iterations only halve the error, so inputs far from `sqrt_initial_guess^2` need more iterations.
+  pub sqrt_initial_guess: f32,
+  /// Recip of a Sqrt (the rsqrt of normalization layers) is lowered on its own, to this many Newton-Raphson iterations
+  /// `y => y * (1.5 - 0.5 * x * y^2)`. Only Add and Mul, where Recip of the lowered Sqrt takes a Recip per iteration.
+  pub rsqrt_iterations: usize,
+  /// Starting point of the Rsqrt iterations. They converge for `0 < x < 3 / rsqrt_initial_guess^2` only, and from
+  /// a guess far below `1 / sqrt(x)` the iterations grow it by at most 1.5.
+  pub rsqrt_initial_guess: f32,
+  /// Shape of the circuits of SumReduce and MaxReduce.
+  pub reduction: ReductionStyle,
+  /// MaxReduce and max pooling compute the index of the max too, see [InputsTracker::argmax].
+  pub argmax: bool,
+  /// The lowering panics once it made more scalar nodes than this, rather than running out of memory.
+  pub max_scalar_nodes: Option<usize>,
+}
+
+impl Default for Scalarize {
+  fn default() -> Self {
+    Scalarize {
+      sqrt_iterations: 12,
+      sqrt_initial_guess: 1.0,
+      rsqrt_iterations: 16,
+      rsqrt_initial_guess: 0.5,
+      reduction: ReductionStyle::default(),
+      argmax: false,
+      max_scalar_nodes: None,
+    }
+  }
+}
+
+/// How a reduction of `n` elements is laid out in the scalar graph.
+/// The depth matters for some backends, and a long chain leaves witness generation nothing to do in parallel.
+///
+/// Reductions over several axes (luminal's sequential single axis reductions) are merged and laid out over all the
+/// reduced elements at once. Reducing every axis of a tensor with several is one balanced tree, whatever the style.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ReductionStyle {
+  /// `((neutral + x0) + x1) + ...`, depth `n` (`n - 1` for max, which has no neutral element).
+  #[default]
+  Chain,
+  /// Pairs up the elements level by level, depth `ceil(log2 n)`.
+  BalancedTree,
+  /// Chains over consecutive chunks of `k` elements, then a chain over the chunk results, depth about `k + n / k`.
+  Chunked(usize),
+}
+
+/// An argument in the reduction circuit: a node made already, or the element of the reduced tensor at the logical index.
+#[derive(Debug, Clone, Copy)]
+enum Operand {
+  Node(NodeIndex),
+  Elem(usize),
+}
+
+/// An [Operand] with the node of its index in the window, when tracking the argmax.
+type Tracked = (Operand, Option<NodeIndex>);
 
 impl Compiler for Scalarize {
-  type Output = InputsTracker;
+  /// Fails once the scalar graph grows over [Scalarize::max_scalar_nodes], leaving the graph half lowered.
+  type Output = Result<(InputsTracker, TableRegistry), NodeBudgetExceeded>;
 
-  #[instrument(level = "debug", name = "compile", skip(_ids))]
+  #[instrument(level = "debug", name = "compile", skip(graph, _ids))]
   /// Start from the sinks in graph and go backwards.
   /// We want to rewrite it to many little nodes.
   /// From previous steps the outgoing edges are already multiplied into shape many edges.
   /// We want to create shape many little nodes with outputs (and as many as needed nodes to implement the rest of the circuit).
   /// We connect the outgoing edges to corresponding little nodes using indices like with tensors.
   /// We create edges connecting our little nodes to source nodes. For every source there will source's shape many edges going from that source.
-  fn compile<T: ToIdsMut>(&self, graph: &mut Graph, mut _ids: T) -> InputsTracker {
-    // Assumes that all outgoing edges have same shape from a given node. NOTE: why? not needed once realized physical shape is always going to be same for single output.
-    // FIX: ^ Not true.
+  fn compile<T: ToIdsMut>(&self, graph: &mut Graph, mut _ids: T) -> Self::Output {
+    // Outgoing edges of a node can have different shapes (views), every edge is indexed with its own shape, see connect_out_edges.
 
     // Q: do inefficient but simpler with Looped<(AddCompile, MulCompile)> etc and pattern matching
     //    or efficiently in a single for loop in toposort order (and meticoulous manual pattern matching)
@@ -163,33 +865,6 @@ impl Compiler for Scalarize {
       }
     };
 
-    let get_own_size = |x, gg: &Graph| {
-      let get_own_shape = |x, gg: &Graph| {
-        // reasonably we expect one of two cases: there is some outgoing edge OR it is a retrieval node
-        if let Some(w) = gg.to_retrieve.get(&x) {
-          w.clone().1
-        } else {
-          match gg
-            .edges_directed(x.clone(), Outgoing)
-            .filter_map(|e| e.weight().as_data())
-            .next()
-          {
-            Some((_, _, shape)) => shape,
-            None => {
-              panic!("A node has no outgoing edges and is not a retrieval node.")
-            }
-          }
-        }
-      };
-      // assuming (and we have to) a staticly known shape
-      match get_own_shape(x, gg).n_physical_elements().to_usize() {
-        Some(n) => n,
-        None => {
-          panic!("Node's output shape is not static.")
-        }
-      }
-    };
-
     // We split node into multiple nodes instead.
     // This helper creates the little nodes all with same Op.
     fn make_nodes<T: Operator + 'static + Clone>(
@@ -210,6 +885,8 @@ impl Compiler for Scalarize {
       x: NodeIndex,
       little_nodes: &Vec<NodeIndex>,
       edge_src_indices: &HashMap<EdgeIndex, usize>,
+      index_cache: &mut IndexCache,
+      zero: &mut Option<NodeIndex>,
       graph: &mut Graph,
     ) {
       let out_edges: Vec<_> = graph
@@ -221,26 +898,100 @@ impl Compiler for Scalarize {
       for (e, (input_order, output_order, shape), target) in out_edges {
         let logical_index = edge_src_indices[&e];
         // using output_order as the remembered index in logical shape
-        // TODO: not recalculate the index_expressions so much
-        let phys_index = match logical_to_physical(
-          &(shape.index_expression(), shape.valid_expression()),
-          logical_index,
-        ) {
-          Some(i) => i,
-          None => {
-            panic!("Something fucked up, outgoing edge index outside of expected physical size")
+        match index_cache.logical_to_physical(&shape, logical_index) {
+          Some(phys_index) => {
+            graph.add_edge(
+              little_nodes[phys_index],
+              target,
+              Dependency::Data {
+                input_order,
+                output_order,
+                shape: R0::to_tracker(),
+              },
+            );
           }
-        };
-        graph.add_edge(
-          little_nodes[phys_index],
-          target,
-          Dependency::Data {
-            input_order,
-            output_order,
-            shape: R0::to_tracker(),
-          },
-        );
+          // masked out by the edge's shape (padding), reads as zero
+          None => connect_zero(target, input_order, zero, graph),
+        }
+      }
+    }
+
+    /// Connects the zero constant as the `input_order` argument of target.
+    /// There's a single zero node per graph, shared by all the masked out reads and made on first use.
+    fn connect_zero(
+      target: NodeIndex,
+      input_order: u8,
+      zero: &mut Option<NodeIndex>,
+      graph: &mut Graph,
+    ) {
+      let zero = *zero.get_or_insert_with(|| graph.add_op(ConstantOp { val: 0.0 }).finish());
+      graph.add_edge(
+        zero,
+        target,
+        Dependency::Data {
+          input_order,
+          output_order: 0,
+          shape: R0::to_tracker(),
+        },
+      );
+    }
+
+    /// Contiguous materializes the view of its input (e.g. before a reshape), there's nothing to compute in the scalar graph.
+    /// The copies of plain views are gone already, see [remove_noop_contiguous].
+    /// The outgoing edges are rewired straight to the input, asking for the index in the input's view.
+    /// Only a retrieved Contiguous needs little nodes of its own, these are `y + 0`.
+    fn contiguous_op(
+      x: NodeIndex,
+      size: usize,
+      yy: &(EdgeIndex, (u8, u8, ShapeTracker), NodeIndex),
+      edge_src_indices: &mut HashMap<EdgeIndex, usize>,
+      index_cache: &mut IndexCache,
+      zero: &mut Option<NodeIndex>,
+      graph: &mut Graph,
+    ) -> Vec<NodeIndex> {
+      let (_, (_, from_output, sh), y) = yy;
+      if graph.to_retrieve.contains_key(&x) {
+        let little_nodes = make_nodes(size, Add {}, graph);
+        connect_out_edges(x, &little_nodes, edge_src_indices, index_cache, zero, graph);
+        for (i, l) in little_nodes.iter().enumerate() {
+          let e = graph.add_edge(
+            *y,
+            *l,
+            Dependency::Data {
+              input_order: 0,
+              output_order: *from_output,
+              shape: *sh,
+            },
+          );
+          edge_src_indices.insert(e, i);
+          connect_zero(*l, 1, zero, graph);
+        }
+        return little_nodes;
+      }
+      let out_edges: Vec<_> = graph
+        .graph
+        .edges_directed(x, Outgoing)
+        .filter_map(|e| e.weight().as_data().map(|d| (e.id(), d, e.target())))
+        .collect();
+      for (e, (input_order, _, shape), target) in out_edges {
+        // the result of x is contiguous: its physical index is the logical index in the input's view
+        match index_cache.logical_to_physical(&shape, edge_src_indices[&e]) {
+          Some(i) => {
+            let e_y = graph.add_edge(
+              *y,
+              target,
+              Dependency::Data {
+                input_order,
+                output_order: *from_output,
+                shape: *sh,
+              },
+            );
+            edge_src_indices.insert(e_y, i);
+          }
+          None => connect_zero(target, input_order, zero, graph),
+        }
       }
+      vec![]
     }
 
     fn pointwise_op<T: Operator + 'static + Clone>(
@@ -249,10 +1000,12 @@ impl Compiler for Scalarize {
       size: usize,
       incoming: &Vec<(EdgeIndex, (u8, u8, ShapeTracker), NodeIndex)>,
       edge_src_indices: &mut HashMap<EdgeIndex, usize>,
+      index_cache: &mut IndexCache,
+      zero: &mut Option<NodeIndex>,
       graph: &mut Graph,
     ) -> Vec<NodeIndex> {
       let little_nodes = make_nodes(size, op, graph);
-      connect_out_edges(x, &little_nodes, edge_src_indices, graph);
+      connect_out_edges(x, &little_nodes, edge_src_indices, index_cache, zero, graph);
 
       for (_e, (b, output_order, shape), source) in incoming {
         // assert!(*output_order == 0, "Assuming sigle valued Op's"); // actually idk if we do
@@ -261,7 +1014,9 @@ impl Compiler for Scalarize {
         assert!( k == size, "Expected physical shape to be the same as incoming logical shape. size = {}, k = {}, src = {:?}", size, k, source ); // Op specific
         for j in 0..k {
           let (from, to) = (j, j); // pointwise
-          debug!("k={:?}, j={:?}, b={:?}", k, j, b);
+          if sampled(j) {
+            trace!("k={:?}, j={:?}, b={:?}", k, j, b);
+          }
           let new_e = graph.add_edge(
             source.clone(),
             little_nodes[to],
@@ -277,69 +1032,555 @@ impl Compiler for Scalarize {
       little_nodes
     }
 
-    fn reduce_op<T: Operator + 'static + Clone>(
-      op: T,
-      neutral: f32,
+    /// Lowers Sqrt to a fixed number of Newton-Raphson iterations built from Add, Mul and Recip.
+    fn sqrt_op(
+      iterations: usize,
+      initial_guess: f32,
       x: NodeIndex,
       size: usize,
-      ax: usize, /* reduce axis */
       yy: &(EdgeIndex, (u8, u8, ShapeTracker), NodeIndex),
       edge_src_indices: &mut HashMap<EdgeIndex, usize>,
+      index_cache: &mut IndexCache,
+      zero: &mut Option<NodeIndex>,
       graph: &mut Graph,
     ) -> Vec<NodeIndex> {
-      let (_, (_, from_output, sh), y) = yy;
-      let dims = sh.shape_usize();
-      let ax_len = dims[ax];
-      let front_size = dims.iter().take(ax).product::<usize>().max(1);
-      let back_size = dims.iter().skip(ax + 1).product::<usize>().max(1);
-      // assert!(
-      //   ax_len > 1,
-      //   "Why reducing scalar? but also im lazy to implement that edgecase. ax_len={:?}, ax={:?}, dims={:?}, sh={:?}",
-      //   ax_len, ax, dims, sh
-      // );
-      assert!(*from_output == 0, "Thats not strictly necessary but 1) is always the case 2) is needed for this lazy implementation." );
+      let (_, (_, output_order, shape), src) = yy;
+      assert!(iterations > 0, "Sqrt needs at least one Newton iteration");
       assert!(
-        size == sh.n_elements().to_usize().unwrap() / ax_len,
-        "Expect result size to be the size after collapsing the ax dim."
+        shape.n_elements().to_usize().unwrap() == size,
+        "Expected physical shape to be the same as incoming logical shape."
       );
-      assert!(size == front_size * back_size);
-      let neutral_node = graph.add_op(ConstantOp { val: neutral }).finish();
-      let create_reduce_circuit = |i| {
-        let front_i = i / back_size;
-        let back_i = i % back_size;
-        let xs = (0..ax_len).map(|k| {
-          front_i * back_size * ax_len + k * back_size + back_i // index in y of k-th element in current axe
-        });
-        xs.fold(neutral_node, |l_node, k| {
-          let new = graph.add_op(op.clone()).finish();
-          let _ = graph.add_edge(
-            l_node,
-            new,
-            Dependency::Data {
-              input_order: 0,
-              output_order: 0, /* assuming yy outputs one vector */
-              shape: R0::to_tracker(),
-            },
-          );
-          let e_r = graph.add_edge(
-            *y,
-            new,
+      let scalar_edge = |input_order: u8| Dependency::Data {
+        input_order,
+        output_order: 0,
+        shape: R0::to_tracker(),
+      };
+      let half = graph.add_op(ConstantOp { val: 0.5 }).finish();
+      let guess = graph.add_op(ConstantOp { val: initial_guess }).finish();
+      let little_nodes: Vec<NodeIndex> = (0..size)
+        .map(|j| {
+          (0..iterations).fold(guess, |y, _| {
+            // y' = (y + x * recip(y)) * 0.5
+            let r = graph.add_op(Recip {}).finish();
+            graph.add_edge(y, r, scalar_edge(0));
+            let m = graph.add_op(Mul {}).finish();
+            let e = graph.add_edge(
+              *src,
+              m,
+              Dependency::Data {
+                input_order: 0,
+                output_order: *output_order,
+                shape: *shape, // saving the original shape
+              },
+            );
+            edge_src_indices.insert(e, j);
+            graph.add_edge(r, m, scalar_edge(1));
+            let a = graph.add_op(Add {}).finish();
+            graph.add_edge(y, a, scalar_edge(0));
+            graph.add_edge(m, a, scalar_edge(1));
+            let new_y = graph.add_op(Mul {}).finish();
+            graph.add_edge(a, new_y, scalar_edge(0));
+            graph.add_edge(half, new_y, scalar_edge(1));
+            new_y
+          })
+        })
+        .collect();
+      connect_out_edges(x, &little_nodes, edge_src_indices, index_cache, zero, graph);
+      little_nodes
+    }
+
+    /// Lowers Recip of the Sqrt `yy` to a fixed number of Newton-Raphson iterations for `1 / sqrt(x)`, built from Add
+    /// and Mul. Reads the argument of the Sqrt, the Sqrt is lowered only if something else reads it.
+    fn rsqrt_op(
+      iterations: usize,
+      initial_guess: f32,
+      x: NodeIndex,
+      size: usize,
+      yy: &(EdgeIndex, (u8, u8, ShapeTracker), NodeIndex),
+      edge_src_indices: &mut HashMap<EdgeIndex, usize>,
+      index_cache: &mut IndexCache,
+      zero: &mut Option<NodeIndex>,
+      graph: &mut Graph,
+    ) -> Vec<NodeIndex> {
+      let (_, (_, _, sh), sqrt) = yy;
+      assert!(iterations > 0, "Rsqrt needs at least one Newton iteration");
+      let (output_order, shape, src) = graph
+        .edges_directed(*sqrt, Incoming)
+        .find_map(|e| e.weight().as_data().map(|(_, o, sh)| (o, sh, e.source())))
+        .unwrap();
+      let minus_half = graph.add_op(ConstantOp { val: -0.5 }).finish();
+      let three_halves = graph.add_op(ConstantOp { val: 1.5 }).finish();
+      let guess = graph.add_op(ConstantOp { val: initial_guess }).finish();
+      let little_nodes: Vec<NodeIndex> = (0..size)
+        .map(|j| {
+          // -x / 2, x the element of the argument of the Sqrt under the j-th element of its result read here
+          let h = graph.add_op(Mul {}).finish();
+          match index_cache.logical_to_physical(sh, j) {
+            Some(i) => {
+              let e = graph.add_edge(
+                src,
+                h,
+                Dependency::Data {
+                  input_order: 0,
+                  output_order,
+                  shape, // saving the original shape
+                },
+              );
+              edge_src_indices.insert(e, i);
+            }
+            None => connect_zero(h, 0, zero, graph),
+          }
+          graph.add_edge(
+            minus_half,
+            h,
             Dependency::Data {
               input_order: 1,
-              output_order: 0, /* assuming yy outputs one vector */
+              output_order: 0,
               shape: R0::to_tracker(),
             },
           );
-          edge_src_indices.insert(e_r, k); /* recording logical index of a scalar edge */
-          new
+          (0..iterations).fold(guess, |y, _| {
+            // y' = y * (1.5 - 0.5 * x * y * y)
+            let y2 = binop(Mul {}, y, y, graph);
+            let t = binop(Mul {}, h, y2, graph);
+            let a = binop(Add {}, t, three_halves, graph);
+            binop(Mul {}, y, a, graph)
+          })
         })
-      };
-      let little_nodes: Vec<NodeIndex> = (0..size).map(create_reduce_circuit).collect();
-      connect_out_edges(x, &little_nodes, &edge_src_indices, graph);
+        .collect();
+      connect_out_edges(x, &little_nodes, edge_src_indices, index_cache, zero, graph);
       little_nodes
     }
 
-    let mut inputs_tracker = InputsTracker::default();
+    /// A new scalar node of the op on two scalar nodes.
+    fn binop<T: Operator + 'static>(
+      op: T,
+      l: NodeIndex,
+      r: NodeIndex,
+      graph: &mut Graph,
+    ) -> NodeIndex {
+      let new = graph.add_op(op).finish();
+      for (input_order, y) in [(0, l), (1, r)] {
+        graph.add_edge(
+          y,
+          new,
+          Dependency::Data {
+            input_order,
+            output_order: 0,
+            shape: R0::to_tracker(),
+          },
+        );
+      }
+      new
+    }
+
+    /// With `indices`, the index of the max of every result goes there, see [InputsTracker::argmax].
+    fn reduce_op<T: Operator + 'static + Clone>(
+      op: T,
+      neutral: Option<f32>, /* the chain starts from it, max has none */
+      style: ReductionStyle,
+      indices: Option<&mut Vec<NodeIndex>>,
+      x: NodeIndex,
+      size: usize,
+      axes: &[usize], /* reduced axes, sorted */
+      yy: &(EdgeIndex, (u8, u8, ShapeTracker), NodeIndex),
+      edge_src_indices: &mut HashMap<EdgeIndex, usize>,
+      index_cache: &mut IndexCache,
+      zero: &mut Option<NodeIndex>,
+      graph: &mut Graph,
+    ) -> Vec<NodeIndex> {
+      let (_, (_, from_output, sh), _) = yy;
+      let dims = sh.shape_usize();
+      let ax_len = axes.iter().map(|ax| dims[*ax]).product::<usize>();
+      let kept = (0..dims.len()).filter(|d| !axes.contains(d)).collect_vec();
+      let strides = (0..dims.len())
+        .map(|d| dims[d + 1..].iter().product::<usize>())
+        .collect_vec();
+      // logical offset in y of the i-th element (row major) over the given dims
+      let offset = |i: usize, over: &[usize]| {
+        let mut rest = i;
+        let mut off = 0;
+        for d in over.iter().rev() {
+          off += (rest % dims[*d]) * strides[*d];
+          rest /= dims[*d];
+        }
+        off
+      };
+      assert!(*from_output == 0, "Thats not strictly necessary but 1) is always the case 2) is needed for this lazy implementation." );
+      assert!(
+        size == sh.n_elements().to_usize().unwrap() / ax_len,
+        "Expect result size to be the size after collapsing the reduced dims."
+      );
+      assert!(size == kept.iter().map(|d| dims[*d]).product::<usize>());
+      // reducing everything at once: one balanced tree, not the style's circuit over all the elements
+      let style = if axes.len() > 1 && kept.is_empty() {
+        ReductionStyle::BalancedTree
+      } else {
+        style
+      };
+      if ax_len == 1 {
+        // every result is the single element, as is
+        if let Some(indices) = indices {
+          let first = graph.add_op(ConstantOp { val: 0.0 }).finish();
+          indices.extend(std::iter::repeat(first).take(size));
+        }
+        return contiguous_op(x, size, yy, edge_src_indices, index_cache, zero, graph);
+      }
+      let windows = (0..size)
+        .map(|i| {
+          // index in y of k-th element over the reduced axes
+          (0..ax_len)
+            .map(|k| offset(i, &kept) + offset(k, axes))
+            .collect()
+        })
+        .collect();
+      let results = reduce_windows(
+        op,
+        neutral,
+        style,
+        indices.is_some(),
+        windows,
+        yy,
+        edge_src_indices,
+        graph,
+      );
+      if let Some(indices) = indices {
+        indices.extend(results.iter().map(|(_, index)| index.unwrap()));
+      }
+      let little_nodes: Vec<NodeIndex> = results
+        .into_iter()
+        .map(|(result, _)| match result {
+          Operand::Node(n) => n,
+          Operand::Elem(_) => unreachable!("Reductions of a single element are passed through"),
+        })
+        .collect();
+      connect_out_edges(
+        x,
+        &little_nodes,
+        &edge_src_indices,
+        index_cache,
+        zero,
+        graph,
+      );
+      little_nodes
+    }
+
+    /// Connects the operand as the `input_order` argument of target, an element of y read by logical index.
+    fn connect_operand(
+      operand: Operand,
+      target: NodeIndex,
+      input_order: u8,
+      yy: &(EdgeIndex, (u8, u8, ShapeTracker), NodeIndex),
+      edge_src_indices: &mut HashMap<EdgeIndex, usize>,
+      graph: &mut Graph,
+    ) {
+      let (_, (_, from_output, sh), y) = yy;
+      match operand {
+        Operand::Node(n) => {
+          graph.add_edge(
+            n,
+            target,
+            Dependency::Data {
+              input_order,
+              output_order: 0,
+              shape: R0::to_tracker(),
+            },
+          );
+        }
+        Operand::Elem(k) => {
+          let e = graph.add_edge(
+            *y,
+            target,
+            Dependency::Data {
+              input_order,
+              output_order: *from_output,
+              shape: *sh, // saving the original shape
+            },
+          );
+          edge_src_indices.insert(e, k); /* recording logical index of a scalar edge */
+        }
+      }
+    }
+
+    /// The reduction circuit of every window of logical indices into y, laid out in the style.
+    /// A window of a single element is that element, left unconnected.
+    ///
+    /// With `argmax` (of a max, which has no neutral element) every result comes with the node of the position of the
+    /// max in its window: every Max of the circuit selects the index of its larger operand as well,
+    /// `l_index + (l < r) * (r_index - l_index)`. The left operand is the earlier one, so ties go to the first max.
+    fn reduce_windows<T: Operator + 'static + Clone>(
+      op: T,
+      neutral: Option<f32>,
+      style: ReductionStyle,
+      argmax: bool,
+      windows: Vec<Vec<usize>>,
+      yy: &(EdgeIndex, (u8, u8, ShapeTracker), NodeIndex),
+      edge_src_indices: &mut HashMap<EdgeIndex, usize>,
+      graph: &mut Graph,
+    ) -> Vec<(Operand, Option<NodeIndex>)> {
+      if let ReductionStyle::Chunked(k) = style {
+        assert!(k > 0, "Chunks of a reduction can't be empty");
+      }
+      assert!(
+        !argmax || neutral.is_none(),
+        "Argmax of a reduction with a neutral element"
+      );
+      let neutral_node = neutral
+        .filter(|_| style == ReductionStyle::Chain)
+        .map(|val| graph.add_op(ConstantOp { val }).finish());
+      let window_len = windows.iter().map(Vec::len).max().unwrap_or(0);
+      let positions: Vec<NodeIndex> = (0..window_len)
+        .filter(|_| argmax)
+        .map(|p| graph.add_op(ConstantOp { val: p as f32 }).finish())
+        .collect();
+      let minus_one = argmax.then(|| graph.add_op(ConstantOp { val: -1.0 }).finish());
+      // a new op node applied to the two operands, with the index of the selected one
+      let mut combine = |(l, l_index): Tracked, (r, r_index): Tracked| {
+        let new = graph.add_op(op.clone()).finish();
+        for (input_order, operand) in [(0, l), (1, r)] {
+          connect_operand(operand, new, input_order, yy, edge_src_indices, graph);
+        }
+        let index = l_index.zip(r_index).map(|(l_index, r_index)| {
+          let better = graph.add_op(LessThan {}).finish();
+          for (input_order, operand) in [(0, l), (1, r)] {
+            connect_operand(operand, better, input_order, yy, edge_src_indices, graph);
+          }
+          let negated = binop(Mul {}, l_index, minus_one.unwrap(), graph);
+          let diff = binop(Add {}, r_index, negated, graph);
+          let step = binop(Mul {}, better, diff, graph);
+          binop(Add {}, l_index, step, graph)
+        });
+        (Operand::Node(new), index)
+      };
+      let chain = |operands: Vec<Tracked>, combine: &mut dyn FnMut(Tracked, Tracked) -> Tracked| {
+        operands.into_iter().reduce(|l, r| combine(l, r)).unwrap()
+      };
+      windows
+        .into_iter()
+        .map(|window| {
+          let xs = window
+            .into_iter()
+            .enumerate()
+            .map(|(p, k)| (Operand::Elem(k), positions.get(p).copied()));
+          match style {
+            ReductionStyle::Chain => chain(
+              neutral_node
+                .map(|n| (Operand::Node(n), None))
+                .into_iter()
+                .chain(xs)
+                .collect(),
+              &mut combine,
+            ),
+            ReductionStyle::BalancedTree => {
+              let mut level: Vec<Tracked> = xs.collect();
+              while level.len() > 1 {
+                level = level
+                  .chunks(2)
+                  .map(|pair| match pair {
+                    [l, r] => combine(*l, *r),
+                    [single] => *single,
+                    _ => unreachable!(),
+                  })
+                  .collect();
+              }
+              level[0]
+            }
+            ReductionStyle::Chunked(k) => {
+              let chunks = xs
+                .collect_vec()
+                .chunks(k)
+                .map(|c| chain(c.to_vec(), &mut combine))
+                .collect();
+              chain(chunks, &mut combine)
+            }
+          }
+        })
+        .collect()
+    }
+
+    /// Lowers [Pool2D]: every output is the reduction circuit of its window (see [Pool2D::window]),
+    /// a mean then multiplied by `1 / kernel^2`. With `indices`, a max pool puts the index of the max of every output
+    /// there, see [InputsTracker::argmax].
+    fn pool_op(
+      op: Pool2D,
+      style: ReductionStyle,
+      indices: Option<&mut Vec<NodeIndex>>,
+      x: NodeIndex,
+      size: usize,
+      yy: &(EdgeIndex, (u8, u8, ShapeTracker), NodeIndex),
+      edge_src_indices: &mut HashMap<EdgeIndex, usize>,
+      index_cache: &mut IndexCache,
+      zero: &mut Option<NodeIndex>,
+      graph: &mut Graph,
+    ) -> Vec<NodeIndex> {
+      let (_, (_, _, sh), _) = yy;
+      assert!(size == op.output_size(), "Expect a result per window");
+      assert!(sh.n_elements().to_usize().unwrap() == op.channels * op.height * op.width);
+      let windows = (0..size).map(|i| op.window(i)).collect();
+      let results = match op.kind {
+        PoolKind::Max => {
+          let results = reduce_windows(
+            Max {},
+            None,
+            style,
+            indices.is_some(),
+            windows,
+            yy,
+            edge_src_indices,
+            graph,
+          );
+          if let Some(indices) = indices {
+            indices.extend(results.iter().map(|(_, index)| index.unwrap()));
+          }
+          results
+        }
+        PoolKind::Mean => reduce_windows(
+          Add {},
+          Some(0.0),
+          style,
+          false,
+          windows,
+          yy,
+          edge_src_indices,
+          graph,
+        ),
+      };
+      let factor = match op.kind {
+        PoolKind::Max => 1.0,
+        PoolKind::Mean => 1.0 / (op.kernel * op.kernel) as f32,
+      };
+      let mut factor_node: Option<NodeIndex> = None;
+      let mut little_nodes = vec![];
+      for (result, _) in results {
+        match (op.kind, result) {
+          (PoolKind::Max, Operand::Node(n)) => little_nodes.push(n),
+          // a mean, or the max of a 1x1 window: the element needs a node of its own
+          (_, result) => {
+            let c =
+              *factor_node.get_or_insert_with(|| graph.add_op(ConstantOp { val: factor }).finish());
+            let m = graph.add_op(Mul {}).finish();
+            connect_operand(result, m, 0, yy, edge_src_indices, graph);
+            connect_operand(Operand::Node(c), m, 1, yy, edge_src_indices, graph);
+            little_nodes.push(m);
+          }
+        }
+      }
+      connect_out_edges(x, &little_nodes, edge_src_indices, index_cache, zero, graph);
+      little_nodes
+    }
+
+    /// Lowers [Gather] to the one-hot of the index times the table: `out[s, d] = sum_v (indexes[s] == v) * table[v, d]`,
+    /// with the equality as `1 - ((i < v) + (v < i))`. The one-hot of an index is shared by the whole gathered row.
+    fn gather_op(
+      op: Gather,
+      x: NodeIndex,
+      size: usize,
+      indexes: &(EdgeIndex, (u8, u8, ShapeTracker), NodeIndex),
+      table: &(EdgeIndex, (u8, u8, ShapeTracker), NodeIndex),
+      edge_src_indices: &mut HashMap<EdgeIndex, usize>,
+      index_cache: &mut IndexCache,
+      zero: &mut Option<NodeIndex>,
+      graph: &mut Graph,
+    ) -> Vec<NodeIndex> {
+      let Gather { rows, dim } = op;
+      let (_, (_, idx_output, idx_sh), idx) = indexes;
+      let (_, (_, table_output, table_sh), tab) = table;
+      let n_indexes = idx_sh.n_elements().to_usize().unwrap();
+      assert!(rows > 0, "Gathering from an empty table");
+      assert!(
+        size == n_indexes * dim,
+        "Expect a row of the table per index"
+      );
+      assert!(table_sh.n_elements().to_usize().unwrap() == rows * dim);
+      let scalar_edge = |input_order: u8| Dependency::Data {
+        input_order,
+        output_order: 0,
+        shape: R0::to_tracker(),
+      };
+      // an edge from the tensor source, reading its element at the logical index
+      let mut read = |graph: &mut Graph,
+                      (src, output_order, shape): (NodeIndex, u8, ShapeTracker),
+                      index: usize,
+                      target: NodeIndex,
+                      input_order: u8| {
+        let e = graph.add_edge(
+          src,
+          target,
+          Dependency::Data {
+            input_order,
+            output_order,
+            shape, // saving the original shape
+          },
+        );
+        edge_src_indices.insert(e, index);
+      };
+      let one = graph.add_op(ConstantOp { val: 1.0 }).finish();
+      let minus_one = graph.add_op(ConstantOp { val: -1.0 }).finish();
+      let row_ids: Vec<NodeIndex> = (0..rows)
+        .map(|v| graph.add_op(ConstantOp { val: v as f32 }).finish())
+        .collect();
+      let mut one_hot: Vec<Vec<NodeIndex>> = vec![];
+      for s in 0..n_indexes {
+        let mut hot = vec![];
+        for v in row_ids.iter() {
+          let below = graph.add_op(LessThan {}).finish();
+          read(graph, (*idx, *idx_output, *idx_sh), s, below, 0);
+          graph.add_edge(*v, below, scalar_edge(1));
+          let above = graph.add_op(LessThan {}).finish();
+          graph.add_edge(*v, above, scalar_edge(0));
+          read(graph, (*idx, *idx_output, *idx_sh), s, above, 1);
+          let differ = binop(Add {}, below, above, graph);
+          let negated = binop(Mul {}, differ, minus_one, graph);
+          hot.push(binop(Add {}, one, negated, graph));
+        }
+        one_hot.push(hot);
+      }
+      let mut little_nodes = vec![];
+      for i in 0..size {
+        let (s, d) = (i / dim, i % dim);
+        let mut products = vec![];
+        for v in 0..rows {
+          let m = graph.add_op(Mul {}).finish();
+          graph.add_edge(one_hot[s][v], m, scalar_edge(0));
+          read(graph, (*tab, *table_output, *table_sh), v * dim + d, m, 1);
+          products.push(m);
+        }
+        let sum = products
+          .into_iter()
+          .reduce(|l, r| binop(Add {}, l, r, graph))
+          .unwrap();
+        little_nodes.push(sum);
+      }
+      connect_out_edges(x, &little_nodes, edge_src_indices, index_cache, zero, graph);
+      little_nodes
+    }
+
+    /// Records x as the origin of its little nodes and of the helper nodes created for them.
+    fn record_origin(
+      x: NodeIndex,
+      little_nodes: &Vec<NodeIndex>,
+      pending: &HashSet<NodeIndex>,
+      origin: &mut HashMap<NodeIndex, (NodeIndex, usize)>,
+      graph: &Graph,
+    ) {
+      for (i, l) in little_nodes.iter().enumerate() {
+        let mut stack = vec![*l];
+        while let Some(y) = stack.pop() {
+          if pending.contains(&y) || origin.contains_key(&y) {
+            continue;
+          }
+          origin.insert(y, (x, i));
+          stack.extend(graph.neighbors_directed(y, Incoming));
+        }
+      }
+    }
+
+    let mut inputs_tracker = InputsTracker::default();
+    let mut tables = TableRegistry::default();
+
+    remove_dead_tensors(graph);
+    remove_noop_contiguous(graph);
+    let merged_axes = merge_reductions(graph);
 
     // precalculate all physical sizes as we're going to be removing edges
     let sizes = graph
@@ -349,12 +1590,21 @@ impl Compiler for Scalarize {
 
     // when creating an edge targeting a newly made little node we need to remember for what index in the incoming shape it was made
     let mut edge_src_indices: HashMap<EdgeIndex, usize> = HashMap::new();
+    let mut index_cache = IndexCache::default();
+    // the zero constant read by masked out elements, see connect_zero
+    let mut zero: Option<NodeIndex> = None;
 
     let pi = {
       let mut pi = petgraph::algo::toposort(&graph.graph, None).unwrap();
       pi.reverse();
       pi
     };
+    // original nodes not yet substituted, the new nodes of x are the ones upstream of its little nodes but not these
+    let mut pending: HashSet<NodeIndex> = pi.iter().copied().collect();
+    // scalar nodes made so far: the graph but the original nodes still to lower
+    let made =
+      |graph: &Graph, pending: &HashSet<NodeIndex>| graph.graph.node_count() - pending.len();
+    let mut made_per_tensor: HashMap<NodeIndex, usize> = HashMap::new();
 
     // for every node:
     // 0. Match x on Op and arity
@@ -362,7 +1612,7 @@ impl Compiler for Scalarize {
     // 2. Connect outgoing edges, based on indices of the edges which from previous step are indexed like shape's logical indexes
     // 3. Create edges for incoming edges, connect as needed by the Op. Record wanted src index in map.
     // 4. Remove x. Mark the new nodes for retrieval.
-    for x in pi {
+    for (i, x) in pi.into_iter().enumerate() {
       // Invariant of the loop:
       //  - all nodes upstream from x (later in toposort) were already substituted for many scalar nodes.
       //  - the outgoing edges are of scalar shape and we have recorded *what physical index in the result of x the edge connects to*
@@ -373,22 +1623,36 @@ impl Compiler for Scalarize {
         .sorted_by_key(|(_, (inp, _, _), _)| *inp)
         .collect();
       let size = sizes[&x];
+      let made_before = made(graph, &pending);
+      // the argmax of a max reduction, see InputsTracker::argmax
+      let mut indices: Vec<NodeIndex> = vec![];
 
       let little_nodes = if incoming.is_empty() {
         // x is source
         if graph.check_node_type::<Function>(x) {
           // Function op could be in anything but as a source node in practical terms it means an input.
           let little_nodes = make_nodes(size, InputOp {}, graph);
-          connect_out_edges(x, &little_nodes, &edge_src_indices, graph);
+          connect_out_edges(
+            x,
+            &little_nodes,
+            &edge_src_indices,
+            &mut index_cache,
+            &mut zero,
+            graph,
+          );
           inputs_tracker.new_inputs.insert(x, little_nodes.clone());
           little_nodes
         } else if graph.check_node_type::<Constant>(x) {
-          let val = graph.node_weight_mut(x).unwrap().process(vec![])[0]
-            .downcast_ref::<Vec<f32>>()
-            .unwrap()
-            .clone()[0];
+          let val = tensor_f32(&graph.node_weight_mut(x).unwrap().process(vec![])[0]).unwrap()[0];
           let little_nodes = make_nodes(size, ConstantOp { val }, graph);
-          connect_out_edges(x, &little_nodes, &edge_src_indices, graph);
+          connect_out_edges(
+            x,
+            &little_nodes,
+            &edge_src_indices,
+            &mut index_cache,
+            &mut zero,
+            graph,
+          );
           assert!(
             little_nodes.len() == 1,
             "Constants are expected to be scalars"
@@ -398,8 +1662,71 @@ impl Compiler for Scalarize {
           panic!("Unsupported source node type!")
         }
       } else if let Some((yy,)) = incoming.iter().collect_tuple() {
-        if graph.check_node_type::<Recip>(x) {
-          pointwise_op(Recip {}, x, size, &incoming, &mut edge_src_indices, graph)
+        if graph.check_node_type::<Recip>(x) && graph.check_node_type::<Sqrt>(yy.2) {
+          rsqrt_op(
+            self.rsqrt_iterations,
+            self.rsqrt_initial_guess,
+            x,
+            size,
+            yy,
+            &mut edge_src_indices,
+            &mut index_cache,
+            &mut zero,
+            graph,
+          )
+        } else if graph.check_node_type::<Recip>(x) {
+          pointwise_op(
+            Recip {},
+            x,
+            size,
+            &incoming,
+            &mut edge_src_indices,
+            &mut index_cache,
+            &mut zero,
+            graph,
+          )
+        } else if let Some(kind) = lookup_kind(graph, x) {
+          let table_id = tables.register(kind);
+          pointwise_op(
+            LookupOp { table_id },
+            x,
+            size,
+            &incoming,
+            &mut edge_src_indices,
+            &mut index_cache,
+            &mut zero,
+            graph,
+          )
+        } else if graph.check_node_type::<Sqrt>(x)
+          && !graph.to_retrieve.contains_key(&x)
+          && graph
+            .edges_directed(x, Outgoing)
+            .all(|e| e.weight().as_data().is_none())
+        {
+          // read by Recips only, lowered with them, see rsqrt_op
+          vec![]
+        } else if graph.check_node_type::<Sqrt>(x) {
+          sqrt_op(
+            self.sqrt_iterations,
+            self.sqrt_initial_guess,
+            x,
+            size,
+            yy,
+            &mut edge_src_indices,
+            &mut index_cache,
+            &mut zero,
+            graph,
+          )
+        } else if graph.check_node_type::<Contiguous>(x) {
+          contiguous_op(
+            x,
+            size,
+            yy,
+            &mut edge_src_indices,
+            &mut index_cache,
+            &mut zero,
+            graph,
+          )
         } else if graph.check_node_type::<SumReduce>(x) {
           let ax: &SumReduce = graph
             .node_weight(x)
@@ -407,7 +1734,21 @@ impl Compiler for Scalarize {
             .as_any()
             .downcast_ref()
             .unwrap();
-          reduce_op(Add {}, 0.0, x, size, ax.0, yy, &mut edge_src_indices, graph)
+          let axes = merged_axes.get(&x).cloned().unwrap_or_else(|| vec![ax.0]);
+          reduce_op(
+            Add {},
+            Some(0.0),
+            self.reduction,
+            None,
+            x,
+            size,
+            &axes,
+            yy,
+            &mut edge_src_indices,
+            &mut index_cache,
+            &mut zero,
+            graph,
+          )
         } else if graph.check_node_type::<MaxReduce>(x) {
           let ax: &MaxReduce = graph
             .node_weight(x)
@@ -415,7 +1756,34 @@ impl Compiler for Scalarize {
             .as_any()
             .downcast_ref()
             .unwrap();
-          reduce_op(Max {}, 1.0, x, size, ax.0, yy, &mut edge_src_indices, graph)
+          let axes = merged_axes.get(&x).cloned().unwrap_or_else(|| vec![ax.0]);
+          reduce_op(
+            Max {},
+            None,
+            self.reduction,
+            self.argmax.then_some(&mut indices),
+            x,
+            size,
+            &axes,
+            yy,
+            &mut edge_src_indices,
+            &mut index_cache,
+            &mut zero,
+            graph,
+          )
+        } else if graph.check_node_type::<Pool2D>(x) {
+          pool_op(
+            graph.get_op::<Pool2D>(x).clone(),
+            self.reduction,
+            self.argmax.then_some(&mut indices),
+            x,
+            size,
+            yy,
+            &mut edge_src_indices,
+            &mut index_cache,
+            &mut zero,
+            graph,
+          )
         } else {
           panic!("Unsupported unop OP")
         }
@@ -423,19 +1791,71 @@ impl Compiler for Scalarize {
       // x is binop
       else if let Some((ll, rr)) = incoming.iter().collect_tuple() {
         if graph.check_node_type::<Add>(x) {
-          debug!("Add {:?} {:?}", ll, rr);
-          pointwise_op(Add {}, x, size, &incoming, &mut edge_src_indices, graph)
+          if sampled(i) {
+            trace!("Add {:?} {:?}", ll, rr);
+          }
+          pointwise_op(
+            Add {},
+            x,
+            size,
+            &incoming,
+            &mut edge_src_indices,
+            &mut index_cache,
+            &mut zero,
+            graph,
+          )
         } else if graph.check_node_type::<Mul>(x) {
-          debug!("Mul {:?} {:?}", ll, rr);
-          pointwise_op(Mul {}, x, size, &incoming, &mut edge_src_indices, graph)
+          if sampled(i) {
+            trace!("Mul {:?} {:?}", ll, rr);
+          }
+          pointwise_op(
+            Mul {},
+            x,
+            size,
+            &incoming,
+            &mut edge_src_indices,
+            &mut index_cache,
+            &mut zero,
+            graph,
+          )
         } else if graph.check_node_type::<LessThan>(x) {
-          debug!("LessThan {:?} {:?}", ll, rr);
+          if sampled(i) {
+            trace!("LessThan {:?} {:?}", ll, rr);
+          }
           pointwise_op(
             LessThan {},
             x,
             size,
             &incoming,
             &mut edge_src_indices,
+            &mut index_cache,
+            &mut zero,
+            graph,
+          )
+        } else if graph.check_node_type::<Mod>(x) {
+          if sampled(i) {
+            trace!("Mod {:?} {:?}", ll, rr);
+          }
+          pointwise_op(
+            Mod {},
+            x,
+            size,
+            &incoming,
+            &mut edge_src_indices,
+            &mut index_cache,
+            &mut zero,
+            graph,
+          )
+        } else if graph.check_node_type::<Gather>(x) {
+          gather_op(
+            graph.get_op::<Gather>(x).clone(),
+            x,
+            size,
+            ll,
+            rr,
+            &mut edge_src_indices,
+            &mut index_cache,
+            &mut zero,
             graph,
           )
         } else {
@@ -446,12 +1866,49 @@ impl Compiler for Scalarize {
         panic!("unexpected node type")
       };
 
+      pending.remove(&x);
+      record_origin(
+        x,
+        &little_nodes,
+        &pending,
+        &mut inputs_tracker.origin,
+        graph,
+      );
+      if !indices.is_empty() {
+        record_origin(x, &indices, &pending, &mut inputs_tracker.origin, graph);
+        for index in indices.iter() {
+          graph.to_retrieve.insert(*index, (0, R0::to_tracker()));
+        }
+        inputs_tracker.argmax.insert(x, indices);
+      }
+
       // !!!
+      if graph.to_retrieve.contains_key(&x) {
+        inputs_tracker.new_outputs.insert(x, little_nodes.clone());
+      }
       mark_retrieve(&x, little_nodes, graph);
+      graph.to_retrieve.remove(&x);
       graph.remove_node(x);
+
+      let reached = made(graph, &pending);
+      made_per_tensor.insert(x, reached - made_before);
+      if let Some(max) = self.max_scalar_nodes.filter(|max| reached > *max) {
+        return Err(NodeBudgetExceeded {
+          max,
+          reached,
+          at: x,
+          per_tensor: made_per_tensor
+            .into_iter()
+            .sorted_by_key(|(x, n)| (Reverse(*n), *x))
+            .collect(),
+        });
+      }
     }
 
-    return inputs_tracker;
+    if let Some(zero) = zero {
+      inputs_tracker.constants.insert(zero, 0.0);
+    }
+    return Ok((inputs_tracker, tables));
   }
 }
 
@@ -477,78 +1934,774 @@ pub fn pretty_print_g(graph: &Graph) -> Result<(), Box<dyn Error>> {
   Ok(())
 }
 
+/// Ops of the source graph that [try_copy_graph_roughly] can't copy, with their type names.
+#[derive(Debug, Clone, PartialEq)]
+pub struct UnsupportedOps {
+  pub ops: Vec<(NodeIndex, String)>,
+}
+
+impl std::fmt::Display for UnsupportedOps {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    write!(f, "Can't copy the ops: ")?;
+    let ops = self
+      .ops
+      .iter()
+      .map(|(x, name)| format!("{} ({:?})", name, x))
+      .join(", ");
+    write!(f, "{}", ops)
+  }
+}
+
+impl Error for UnsupportedOps {}
+
 // copies things that are relevant. very much not exact copy
 // Expects a graph with indices from the [0..n] range without gaps (check the commented lines).
+/// Panics on ops it can't copy, see [try_copy_graph_roughly].
 pub fn copy_graph_roughly(src: &Graph) -> (Graph, HashMap<NodeIndex, NodeIndex>) {
+  copy_graph_in_order(src, src.node_indices().sorted())
+}
+
+/// Copy of the graph with the configuration of every op: reduce axes, constants, names of the functions.
+/// Functions (the loads of tensors) are copied without their closure, the copy has to be set its tensors.
+/// Fails with all the ops that can't be copied instead of producing a different graph.
+pub fn try_copy_graph_roughly(
+  src: &Graph,
+) -> Result<(Graph, HashMap<NodeIndex, NodeIndex>), UnsupportedOps> {
+  try_copy_graph_in_order(src, src.node_indices().sorted())
+}
+
+/// Like [copy_graph_roughly] but adds the nodes in the given order, so the copy gets indices in that order.
+pub fn copy_graph_in_order(
+  src: &Graph,
+  order: impl IntoIterator<Item = NodeIndex>,
+) -> (Graph, HashMap<NodeIndex, NodeIndex>) {
+  try_copy_graph_in_order(src, order).unwrap_or_else(|e| panic!("{}", e))
+}
+
+/// Like [try_copy_graph_roughly] but adds the nodes in the given order, so the copy gets indices in that order.
+pub fn try_copy_graph_in_order(
+  src: &Graph,
+  order: impl IntoIterator<Item = NodeIndex>,
+) -> Result<(Graph, HashMap<NodeIndex, NodeIndex>), UnsupportedOps> {
   let mut g = Graph::new();
   let mut map: HashMap<NodeIndex, NodeIndex> = HashMap::new();
+  let mut unsupported = vec![];
   // copy nodes
-  for x in src.node_indices().sorted() {
-    let n = if src.check_node_type::<Add>(x) {
-      g.add_op(Add {}).finish()
-    } else if src.check_node_type::<Mul>(x) {
-      g.add_op(Mul {}).finish()
-    } else if src.check_node_type::<LessThan>(x) {
-      g.add_op(LessThan {}).finish()
-    } else if src.check_node_type::<Function>(x) {
-      g.add_op(Function(
-        "Load".to_string(),
-        Box::new(|_| panic!("dont run")),
-      ))
-      .finish()
-    } else if src.check_node_type::<Recip>(x) {
-      g.add_op(Recip {}).finish()
-    } else if src.check_node_type::<MaxReduce>(x) {
-      let op = src.get_op::<MaxReduce>(x);
-      g.add_op(MaxReduce(op.0)).finish()
-    } else if src.check_node_type::<SumReduce>(x) {
-      let op = src.get_op::<SumReduce>(x);
-      g.add_op(SumReduce(op.0)).finish()
-    } else if src.check_node_type::<Constant>(x) {
-      let op = src.get_op::<Constant>(x);
-      g.add_op(Constant(op.0.clone(), op.1)).finish()
-    // !!
-    } else if src.check_node_type::<ConstantOp>(x) {
-      let op = src.get_op::<ConstantOp>(x);
-      g.add_op(op.clone()).finish()
-    } else if src.check_node_type::<InputOp>(x) {
-      g.add_op(InputOp {}).finish()
-    } else {
-      panic!(
-        "Unknown node type: {:?}",
-        src.node_weight(x).unwrap().type_name()
-      )
-    };
-    map.insert(x, n);
+  for x in order {
+    match try_copy_op(src, x, &mut g) {
+      Some(n) => {
+        map.insert(x, n);
+      }
+      None => unsupported.push((x, src.node_weight(x).unwrap().type_name().to_string())),
+    }
     // assert!(x == n)
   }
+  if !unsupported.is_empty() {
+    return Err(UnsupportedOps { ops: unsupported });
+  }
   // copy edges
-  for e in src.edge_references() {
-    // g.add_edge(e.source(), e.target(), e.weight().clone());
+  for e in src
+    .edge_references()
+    .sorted_by_key(|e| (map[&e.target()], map[&e.source()]))
+  {
     g.add_edge(map[&e.source()], map[&e.target()], e.weight().clone());
   }
   // copy retrieval marks
-  // src.to_retrieve.iter().for_each(|(id, sh)| {g.to_retrieve.insert(map[id], *sh);});
   src.to_retrieve.iter().for_each(|(id, sh)| {
-    g.to_retrieve.insert(map[id], *sh);
+    if let Some(n) = map.get(id) {
+      g.to_retrieve.insert(*n, *sh);
+    }
   });
 
-  (g, map)
+  Ok((g, map))
+}
+
+/// Adds a node with a copy of the op at `x` in `src`. Panics on ops that can't be copied.
+fn copy_op(src: &Graph, x: NodeIndex, g: &mut Graph) -> NodeIndex {
+  try_copy_op(src, x, g).unwrap_or_else(|| {
+    panic!(
+      "Unknown node type: {:?}",
+      src.node_weight(x).unwrap().type_name()
+    )
+  })
+}
+
+/// Adds a node with a copy of the op at `x` in `src`, if it's an op we know.
+fn try_copy_op(src: &Graph, x: NodeIndex, g: &mut Graph) -> Option<NodeIndex> {
+  let n = if src.check_node_type::<Add>(x) {
+    g.add_op(Add {}).finish()
+  } else if src.check_node_type::<Mul>(x) {
+    g.add_op(Mul {}).finish()
+  } else if src.check_node_type::<LessThan>(x) {
+    g.add_op(LessThan {}).finish()
+  } else if src.check_node_type::<Mod>(x) {
+    g.add_op(Mod {}).finish()
+  } else if src.check_node_type::<Function>(x) {
+    // the closure can't be cloned, the name tells what it was
+    let name = src.get_op::<Function>(x).0.clone();
+    let msg = format!("Function {} of a copied graph has no closure", name);
+    g.add_op(Function(name, Box::new(move |_| panic!("{}", msg))))
+      .finish()
+  } else if src.check_node_type::<Recip>(x) {
+    g.add_op(Recip {}).finish()
+  } else if src.check_node_type::<Sqrt>(x) {
+    g.add_op(Sqrt {}).finish()
+  } else if src.check_node_type::<Exp2>(x) {
+    g.add_op(Exp2 {}).finish()
+  } else if src.check_node_type::<Log2>(x) {
+    g.add_op(Log2 {}).finish()
+  } else if src.check_node_type::<Sin>(x) {
+    g.add_op(Sin {}).finish()
+  } else if src.check_node_type::<Contiguous>(x) {
+    g.add_op(Contiguous {}).finish()
+  } else if src.check_node_type::<Gather>(x) {
+    let op = src.get_op::<Gather>(x);
+    g.add_op(op.clone()).finish()
+  } else if src.check_node_type::<Pool2D>(x) {
+    let op = src.get_op::<Pool2D>(x);
+    g.add_op(op.clone()).finish()
+  } else if src.check_node_type::<MaxReduce>(x) {
+    let op = src.get_op::<MaxReduce>(x);
+    g.add_op(MaxReduce(op.0)).finish()
+  } else if src.check_node_type::<SumReduce>(x) {
+    let op = src.get_op::<SumReduce>(x);
+    g.add_op(SumReduce(op.0)).finish()
+  } else if src.check_node_type::<Constant>(x) {
+    let op = src.get_op::<Constant>(x);
+    g.add_op(Constant(op.0.clone(), op.1)).finish()
+  // !!
+  } else if src.check_node_type::<ConstantOp>(x) {
+    let op = src.get_op::<ConstantOp>(x);
+    g.add_op(op.clone()).finish()
+  } else if src.check_node_type::<InputOp>(x) {
+    g.add_op(InputOp {}).finish()
+  } else if src.check_node_type::<Max>(x) {
+    g.add_op(Max {}).finish()
+  } else if src.check_node_type::<ReluOp>(x) {
+    g.add_op(ReluOp {}).finish()
+  } else if src.check_node_type::<SubOp>(x) {
+    g.add_op(SubOp {}).finish()
+  } else if src.check_node_type::<NegOp>(x) {
+    g.add_op(NegOp {}).finish()
+  } else if src.check_node_type::<FmaOp>(x) {
+    g.add_op(FmaOp {}).finish()
+  } else if src.check_node_type::<DivConstOp>(x) {
+    let op = src.get_op::<DivConstOp>(x);
+    g.add_op(op.clone()).finish()
+  } else if src.check_node_type::<ModConstOp>(x) {
+    let op = src.get_op::<ModConstOp>(x);
+    g.add_op(op.clone()).finish()
+  } else if src.check_node_type::<LessThanConstOp>(x) {
+    let op = src.get_op::<LessThanConstOp>(x);
+    g.add_op(op.clone()).finish()
+  } else if src.check_node_type::<GreaterThanConstOp>(x) {
+    let op = src.get_op::<GreaterThanConstOp>(x);
+    g.add_op(op.clone()).finish()
+  } else if src.check_node_type::<LookupOp>(x) {
+    let op = src.get_op::<LookupOp>(x);
+    g.add_op(op.clone()).finish()
+  } else if src.check_node_type::<SignedLessThan>(x) {
+    g.add_op(*src.get_op::<SignedLessThan>(x)).finish()
+  } else if src.check_node_type::<RangeCheck>(x) {
+    g.add_op(*src.get_op::<RangeCheck>(x)).finish()
+  } else if src.check_node_type::<RecipHint>(x) {
+    g.add_op(RecipHint {}).finish()
+  } else {
+    return None;
+  };
+  Some(n)
 }
 
 #[cfg(test)]
 mod tests {
-  use std::error::Error;
+  use std::{collections::HashMap, error::Error};
 
+  use itertools::Itertools;
   use luminal::{
     graph::Graph,
-    shape::{Const, R1, R2},
+    op::Recip,
+    prelude::{Dependency, NodeIndex, ShapeTracker},
+    shape::{Axis, Const, Expression, Shape, R0, R1, R2},
   };
+  use luminal_training::{mse_loss, Autograd};
   use tracing::info;
 
   use crate::{scalar::save_graphviz, utils};
+  use proptest::prelude::*;
+  use rand::{rngs::StdRng, SeedableRng};
+
+  use super::{
+    random_inputs, scalar, scalar_with,
+    testing::{arb_expr, build_graph, TensorExpr},
+    try_copy_graph_roughly, verify_scalarization, AssignError, ConstantOp, IndexCache,
+    ReductionStyle, Scalarize,
+  };
+  use petgraph::Direction::{Incoming, Outgoing};
+
+  #[test]
+  fn test_sqrt_newton() {
+    let mut cx = Graph::new();
+    let a = cx.tensor::<R1<5>>();
+    let _b = a.sqrt().retrieve();
+    let (sc, _) = scalar(&cx);
+    let inputs = [(a.id, vec![0.25, 1.0, 2.0, 9.0, 100.0])]
+      .into_iter()
+      .collect();
+    assert_eq!(verify_scalarization(&cx, &sc, &inputs), Ok(()));
+  }
+
+  #[test]
+  fn test_rsqrt_newton() {
+    let mut cx = Graph::new();
+    let a = cx.tensor::<R1<4>>();
+    let _b = a.sqrt().recip().retrieve();
+    let (sc, _) = scalar(&cx);
+    // no Recip left, the Sqrt is gone with it
+    assert!(sc
+      .graph
+      .node_indices()
+      .all(|x| !sc.graph.check_node_type::<Recip>(x)));
+    let inputs = [(a.id, vec![0.25, 1.0, 2.0, 9.0])].into_iter().collect();
+    assert_eq!(verify_scalarization(&cx, &sc, &inputs), Ok(()));
+
+    // larger inputs need a smaller guess
+    let compiler = Scalarize {
+      rsqrt_initial_guess: 0.05,
+      ..Default::default()
+    };
+    let (sc, _) = scalar_with(&cx, compiler);
+    let inputs = [(a.id, vec![1.0, 9.0, 100.0, 1000.0])]
+      .into_iter()
+      .collect();
+    assert_eq!(verify_scalarization(&cx, &sc, &inputs), Ok(()));
+  }
+
+  #[test]
+  fn test_reduce_axis_of_length_1() {
+    let mut cx = Graph::new();
+    let a = cx.tensor::<R2<3, 1>>();
+    let b = cx.tensor::<R1<3>>();
+    let _sum = a.sum_reduce::<_, luminal::shape::Axis<1>>().retrieve();
+    // max of a single negative element is that element
+    let _max = (a.max_reduce::<_, luminal::shape::Axis<1>>() + b).retrieve();
+    let inputs = [(a.id, vec![-1.0, 2.0, -3.0]), (b.id, vec![0.5, 0.5, 0.5])]
+      .into_iter()
+      .collect();
+    for reduction in [ReductionStyle::Chain, ReductionStyle::BalancedTree] {
+      let compiler = Scalarize {
+        reduction,
+        ..Default::default()
+      };
+      let (sc, _) = scalar_with(&cx, compiler);
+      assert_eq!(verify_scalarization(&cx, &sc, &inputs), Ok(()));
+    }
+  }
+
+  #[test]
+  fn test_max_reduce_of_negatives() {
+    let mut cx = Graph::new();
+    let a = cx.tensor::<R2<2, 3>>();
+    let _max = a.max_reduce::<_, luminal::shape::Axis<1>>().retrieve();
+    let inputs = [(a.id, vec![-1.0, -2.0, -3.0, 0.5, -0.5, 0.25])]
+      .into_iter()
+      .collect();
+    for reduction in [
+      ReductionStyle::Chain,
+      ReductionStyle::BalancedTree,
+      ReductionStyle::Chunked(2),
+    ] {
+      let compiler = Scalarize {
+        reduction,
+        ..Default::default()
+      };
+      let (sc, _) = scalar_with(&cx, compiler);
+      assert_eq!(verify_scalarization(&cx, &sc, &inputs), Ok(()));
+    }
+  }
 
-  use super::ScalarCompiler;
+  #[test]
+  fn test_log2_and_sin_are_lookups() {
+    let mut cx = Graph::new();
+    let a = cx.tensor::<R1<4>>();
+    let _log = a.log2().retrieve();
+    let _sin = (a * 3.0).sin().retrieve();
+    let (sc, _) = scalar(&cx);
+    assert_eq!(sc.tables.tables.len(), 2);
+    let inputs = [(a.id, vec![0.25, 0.5, 1.5, 3.0])].into_iter().collect();
+    assert_eq!(verify_scalarization(&cx, &sc, &inputs), Ok(()));
+  }
+
+  #[test]
+  fn test_scalarize_gradients() {
+    let mut cx = Graph::new();
+    let x = cx.tensor::<R2<3, 2>>();
+    let w = cx.tensor::<R2<2, 4>>();
+    let b = cx.tensor::<R1<4>>();
+    // the transpose of the matmul, the broadcast of the bias and the max all get undone in the backward pass
+    let y = (x.matmul(w) + b.expand()).sin().max_reduce::<_, Axis<1>>();
+    let target = cx.tensor::<R1<3>>();
+    let loss = mse_loss(y, target).retrieve();
+    let weights = vec![w.id, b.id];
+    let grads = cx.compile(Autograd::new(&weights, loss), ());
+    for (grad, shape) in grads.iter() {
+      cx.to_retrieve.insert(*grad, (0, *shape));
+    }
+    let mut rng = StdRng::seed_from_u64(0);
+    let (sc, _) = scalar(&cx);
+    for _ in 0..3 {
+      let inputs = random_inputs(&cx, &mut rng);
+      assert_eq!(verify_scalarization(&cx, &sc, &inputs), Ok(()));
+    }
+
+    // without the loss, what only the loss needs is left out
+    cx.to_retrieve.remove(&loss.id);
+    let (without_loss, _) = scalar(&cx);
+    assert!(without_loss.graph.node_count() < sc.graph.node_count());
+    assert_eq!(
+      without_loss
+        .inputs_tracker
+        .new_outputs
+        .keys()
+        .sorted()
+        .collect_vec(),
+      grads.iter().map(|(grad, _)| grad).sorted().collect_vec()
+    );
+    let inputs = random_inputs(&cx, &mut rng);
+    assert_eq!(verify_scalarization(&cx, &without_loss, &inputs), Ok(()));
+  }
+
+  #[test]
+  fn test_max_reduce_argmax() {
+    let mut cx = Graph::new();
+    let a = cx.tensor::<R2<3, 4>>();
+    // the max itself isn't retrieved, only its argmax is a result
+    let max = a.max_reduce::<_, luminal::shape::Axis<1>>();
+    let _ = (max * 2.0).retrieve();
+    // ties go to the first
+    let xs = vec![
+      -1.0, 3.0, -2.0, 3.0, //
+      -4.0, -3.0, -2.0, -0.5, //
+      0.5, 0.25, 0.0, 0.5,
+    ];
+    let inputs = [(a.id, xs)].into_iter().collect();
+    for reduction in [
+      ReductionStyle::Chain,
+      ReductionStyle::BalancedTree,
+      ReductionStyle::Chunked(3),
+    ] {
+      let compiler = Scalarize {
+        reduction,
+        argmax: true,
+        ..Default::default()
+      };
+      let (sc, _) = scalar_with(&cx, compiler);
+      assert_eq!(verify_scalarization(&cx, &sc, &inputs), Ok(()));
+      assert_eq!(sc.inputs_tracker.argmax.len(), 1);
+      let indices = &sc.inputs_tracker.argmax[&max.id];
+      let values = sc.evaluate(&inputs);
+      let got: Vec<f32> = indices.iter().map(|x| values[x]).collect();
+      assert_eq!(got, vec![1.0, 3.0, 0.0], "{:?}", reduction);
+    }
+    let (sc, _) = scalar(&cx);
+    assert!(sc.inputs_tracker.argmax.is_empty());
+  }
+
+  #[test]
+  fn test_copy_keeps_op_config() {
+    let mut cx = Graph::new();
+    let a = cx.named_tensor::<R2<3, 2>>("A");
+    let _sum = a.sum_reduce::<_, luminal::shape::Axis<1>>().retrieve();
+    let _max = a.max_reduce::<_, luminal::shape::Axis<0>>().retrieve();
+    // relu takes the max with a constant
+    let _relu = a.relu().retrieve();
+    let (g, remap) = try_copy_graph_roughly(&cx).unwrap();
+    assert_eq!(g.node_count(), cx.node_count());
+    for x in cx.node_indices() {
+      assert_eq!(
+        format!("{:?}", cx.node_weight(x).unwrap()),
+        format!("{:?}", g.node_weight(remap[&x]).unwrap())
+      );
+    }
+
+    #[derive(Debug)]
+    struct Unknown;
+    impl luminal::op::Operator for Unknown {
+      fn process(
+        &mut self,
+        _inp: Vec<(luminal::op::InputTensor, ShapeTracker)>,
+      ) -> Vec<luminal::prelude::Tensor> {
+        vec![]
+      }
+    }
+    let unknown = cx.add_op(Unknown).input(a.id, 0, a.shape).finish();
+    let err = try_copy_graph_roughly(&cx).unwrap_err();
+    assert_eq!(
+      err.ops.iter().map(|(x, _)| *x).collect::<Vec<_>>(),
+      vec![unknown]
+    );
+  }
+
+  #[test]
+  fn test_reduction_styles() {
+    let mut cx = Graph::new();
+    let a = cx.tensor::<R1<8>>();
+    let _b = a.sum_reduce::<_, luminal::shape::Axis<0>>().retrieve();
+    // edges on the longest path
+    let depth = |sc: &super::ScalarGraph| {
+      let mut d: HashMap<NodeIndex, usize> = HashMap::new();
+      for x in petgraph::algo::toposort(&sc.graph.graph, None).unwrap() {
+        let dx = sc
+          .graph
+          .neighbors_directed(x, Incoming)
+          .map(|y| d[&y] + 1)
+          .max()
+          .unwrap_or(0);
+        d.insert(x, dx);
+      }
+      d.into_values().max().unwrap()
+    };
+    let inputs = random_inputs(&cx, &mut StdRng::seed_from_u64(0));
+    for (reduction, expected_depth) in [
+      (ReductionStyle::Chain, 8),
+      (ReductionStyle::BalancedTree, 3),
+      (ReductionStyle::Chunked(3), 4),
+    ] {
+      let compiler = Scalarize {
+        reduction,
+        ..Default::default()
+      };
+      let (sc, _) = scalar_with(&cx, compiler);
+      assert_eq!(verify_scalarization(&cx, &sc, &inputs), Ok(()));
+      assert_eq!(depth(&sc), expected_depth, "{:?}", reduction);
+    }
+  }
+
+  #[test]
+  fn test_reduce_several_axes() {
+    use luminal::shape::{Axes2, Axes3, Axis, R3};
+    let mut cx = Graph::new();
+    let a = cx.tensor::<R3<2, 3, 4>>();
+    let _rows = a.sum_reduce::<_, Axes2<1, 2>>().retrieve();
+    let _cols = a.max_reduce::<_, Axes2<0, 2>>().retrieve();
+    let _all = a.sum_reduce::<_, Axes3<0, 1, 2>>().retrieve();
+    // the inner reduction is retrieved, so it stays
+    let inner = a.sum_reduce::<_, Axis<2>>().retrieve();
+    let _outer = inner.sum_reduce::<_, Axis<0>>().retrieve();
+    let inputs = random_inputs(&cx, &mut StdRng::seed_from_u64(0));
+    for reduction in [
+      ReductionStyle::Chain,
+      ReductionStyle::BalancedTree,
+      ReductionStyle::Chunked(5),
+    ] {
+      let compiler = Scalarize {
+        reduction,
+        ..Default::default()
+      };
+      let (sc, _) = scalar_with(&cx, compiler);
+      assert_eq!(verify_scalarization(&cx, &sc, &inputs), Ok(()));
+    }
+
+    // the full sum is a single balanced tree of 23 additions, depth 5
+    let mut cx = Graph::new();
+    let a = cx.tensor::<R3<2, 3, 4>>();
+    let all = a.sum_reduce::<_, Axes3<0, 1, 2>>().retrieve();
+    let (sc, _) = scalar(&cx);
+    let out = sc.inputs_tracker.new_outputs[&all.id][0];
+    let mut depth: HashMap<NodeIndex, usize> = HashMap::new();
+    for x in petgraph::algo::toposort(&sc.graph.graph, None).unwrap() {
+      let dx = sc
+        .graph
+        .neighbors_directed(x, Incoming)
+        .map(|y| depth[&y] + 1)
+        .max()
+        .unwrap_or(0);
+      depth.insert(x, dx);
+    }
+    assert_eq!(depth[&out], 5);
+    assert_eq!(sc.graph.node_count(), 24 + 23);
+  }
+
+  #[test]
+  fn test_canonical_ids_are_stable() {
+    let build = || {
+      let mut cx = Graph::new();
+      let a = cx.tensor::<R1<3>>();
+      let b = cx.tensor::<R1<3>>();
+      let _c = (a * b + a)
+        .sum_reduce::<_, luminal::shape::Axis<0>>()
+        .retrieve();
+      scalar(&cx).0
+    };
+    let ops = |sc: &super::ScalarGraph| {
+      sc.graph
+        .node_indices()
+        .map(|x| format!("{:?}", sc.graph.node_weight(x).unwrap()))
+        .collect::<Vec<_>>()
+    };
+    let (sc1, sc2) = (build(), build());
+    assert_eq!(ops(&sc1), ops(&sc2));
+    assert_eq!(
+      sc1.inputs_tracker.ordered_inputs(),
+      sc2.inputs_tracker.ordered_inputs()
+    );
+    let again = sc1.canonicalize();
+    assert_eq!(ops(&sc1), ops(&again));
+    assert_eq!(
+      sc1.inputs_tracker.ordered_outputs(),
+      again.inputs_tracker.ordered_outputs()
+    );
+  }
+
+  #[test]
+  fn test_structural_hash() {
+    let build = |n_sums: usize| {
+      let mut cx = Graph::new();
+      let a = cx.tensor::<R1<3>>();
+      let b = cx.tensor::<R1<3>>();
+      let mut c = a * b;
+      for _ in 0..n_sums {
+        c = c + a;
+      }
+      c.retrieve();
+      scalar(&cx).0
+    };
+    assert_eq!(build(1).structural_hash(), build(1).structural_hash());
+    assert_ne!(build(1).structural_hash(), build(2).structural_hash());
+
+    let with_constant = |val: f32| {
+      let mut sc = build(1);
+      sc.graph.add_op(ConstantOp { val }).finish();
+      sc
+    };
+    let (sc1, sc2) = (with_constant(1.0), with_constant(2.0));
+    assert_ne!(sc1.structural_hash(), sc2.structural_hash());
+    assert_eq!(
+      sc1.structural_hash_with(false),
+      sc2.structural_hash_with(false)
+    );
+  }
+
+  #[test]
+  fn test_structural_hash_of_insertion_order() {
+    // two equal constants used by different nodes, added in either order
+    let build = |mul_first: bool| {
+      let mut cx = Graph::new();
+      let a = cx.tensor::<R1<2>>();
+      (a * a).retrieve();
+      let (mut sc, _) = scalar(&cx);
+      let xs = sc.inputs_tracker.new_inputs[&a.id].clone();
+      let g = &mut sc.graph;
+      let mut constants = [(); 2].map(|_| g.add_op(ConstantOp { val: 2.0 }).finish());
+      if !mul_first {
+        constants.reverse();
+      }
+      let mul = g.add_op(luminal::op::Mul {}).finish();
+      let add = g.add_op(luminal::op::Add {}).finish();
+      for (y, x, c) in [(mul, xs[0], constants[0]), (add, xs[1], constants[1])] {
+        for (input_order, arg) in [(0, x), (1, c)] {
+          g.add_edge(
+            arg,
+            y,
+            Dependency::Data {
+              input_order,
+              output_order: 0,
+              shape: R0::to_tracker(),
+            },
+          );
+        }
+      }
+      sc
+    };
+    assert_eq!(
+      build(true).structural_hash(),
+      build(false).structural_hash()
+    );
+  }
+
+  #[test]
+  fn test_origin_covers_all_nodes() {
+    let mut cx = Graph::new();
+    let a = cx.tensor::<R2<2, 3>>();
+    let b = (a * a).sum_reduce::<_, luminal::shape::Axis<1>>();
+    let c = b.retrieve();
+    let (sc, _) = scalar(&cx);
+    let origin = &sc.inputs_tracker.origin;
+    assert!(sc.graph.node_indices().all(|x| origin.contains_key(&x)));
+    for (i, x) in sc.inputs_tracker.new_outputs[&c.id].iter().enumerate() {
+      assert_eq!(origin[x], (c.id, i));
+    }
+    for (i, x) in sc.inputs_tracker.new_inputs[&a.id].iter().enumerate() {
+      assert_eq!(origin[x], (a.id, i));
+    }
+  }
+
+  #[test]
+  fn test_assign_inputs() {
+    let mut cx = Graph::new();
+    let a = cx.tensor::<R2<2, 3>>();
+    let b = cx.tensor::<R2<2, 3>>();
+    let c = (a + b).retrieve();
+    let (sc, _) = scalar(&cx);
+    let tracker = &sc.inputs_tracker;
+
+    let data = vec![1.0, 2.0, 3.0, 4.0, 5.0, 6.0];
+    let assigned = tracker.assign(a.id, &data).unwrap();
+    assert_eq!(
+      assigned.iter().map(|(x, _)| *x).collect::<Vec<_>>(),
+      tracker.new_inputs[&a.id]
+    );
+    assert_eq!(assigned.iter().map(|(_, v)| *v).collect::<Vec<_>>(), data);
+
+    assert_eq!(
+      tracker.assign(a.id, &data[..5]),
+      Err(AssignError::Length {
+        input: a.id,
+        expected: 6,
+        got: 5
+      })
+    );
+    let inputs = [(a.id, data.clone()), (b.id, data.clone())]
+      .into_iter()
+      .collect();
+    assert_eq!(tracker.assign_all(&inputs).unwrap().len(), 12);
+    let unknown = [(c.id, data)].into_iter().collect();
+    assert_eq!(
+      tracker.assign_all(&unknown),
+      Err(AssignError::UnknownInput { input: c.id })
+    );
+  }
+
+  #[test]
+  fn test_scalarize_views() {
+    use TensorExpr::*;
+    let input = |sh: &[usize]| Box::new(Input(sh.to_vec()));
+    let exprs = [
+      Add(
+        Box::new(Permute(input(&[2, 3]), vec![1, 0])),
+        input(&[3, 2]),
+      ),
+      // reshape of a contiguous tensor is just a different view
+      Mul(
+        Box::new(Reshape(input(&[2, 3]), vec![3, 2])),
+        input(&[3, 2]),
+      ),
+      // reshape of a permuted one goes through Contiguous
+      Add(
+        Box::new(Reshape(
+          Box::new(Permute(input(&[2, 3]), vec![1, 0])),
+          vec![6],
+        )),
+        input(&[6]),
+      ),
+      // ... and so does a retrieved one
+      Reshape(
+        Box::new(Permute(
+          Box::new(Mul(input(&[2, 3]), input(&[2, 3]))),
+          vec![1, 0],
+        )),
+        vec![6],
+      ),
+      Add(
+        Box::new(Slice(input(&[3, 3]), vec![(1, 3), (0, 2)])),
+        input(&[2, 2]),
+      ),
+      Add(Box::new(Pad(input(&[2]), vec![(1, 2)])), input(&[5])),
+      SumReduce(Box::new(Pad(input(&[2, 2]), vec![(0, 1), (1, 0)])), 1),
+      Mul(
+        Box::new(Pad(
+          Box::new(Slice(input(&[3]), vec![(1, 3)])),
+          vec![(2, 0)],
+        )),
+        input(&[4]),
+      ),
+    ];
+    for (i, expr) in exprs.iter().enumerate() {
+      let (cx, _) = build_graph(expr);
+      let (sc, _) = scalar(&cx);
+      let inputs = random_inputs(&cx, &mut StdRng::seed_from_u64(i as u64));
+      assert_eq!(
+        verify_scalarization(&cx, &sc, &inputs),
+        Ok(()),
+        "expr: {:?}",
+        expr
+      );
+    }
+  }
+
+  #[test]
+  fn test_padding_shares_one_zero() {
+    use TensorExpr::*;
+    let expr = Add(
+      Box::new(Pad(Box::new(Input(vec![2])), vec![(1, 2)])),
+      Box::new(Pad(Box::new(Input(vec![3])), vec![(2, 0)])),
+    );
+    let (cx, _) = build_graph(&expr);
+    let (sc, _) = scalar(&cx);
+    let constants = &sc.inputs_tracker.constants;
+    assert_eq!(constants.len(), 1);
+    let (zero, val) = constants.iter().next().unwrap();
+    assert_eq!(*val, 0.0);
+    assert_eq!(sc.graph.get_op::<ConstantOp>(*zero).val, 0.0);
+    // 3 + 2 padded elements
+    assert_eq!(sc.graph.edges_directed(*zero, Outgoing).count(), 5);
+    let inputs = random_inputs(&cx, &mut StdRng::seed_from_u64(0));
+    assert_eq!(verify_scalarization(&cx, &sc, &inputs), Ok(()));
+  }
+
+  #[test]
+  fn test_padding_is_masked_out() {
+    let mut sh = ShapeTracker::new(&[Expression::from(2)]);
+    sh.pad(&[(Expression::from(1), Expression::from(0))]);
+    let mut cache = IndexCache::default();
+    assert_eq!(cache.logical_to_physical(&sh, 0), None);
+    assert_eq!(cache.logical_to_physical(&sh, 1), Some(0));
+    assert_eq!(cache.logical_to_physical(&sh, 2), Some(1));
+  }
+
+  #[test]
+  fn test_node_read_under_different_views() {
+    let mut cx = Graph::new();
+    let a = cx.tensor::<R2<2, 3>>();
+    let dims = |d: &[usize]| d.iter().map(|n| Expression::from(*n)).collect::<Vec<_>>();
+    let as_is = ShapeTracker::new(&dims(&[2, 3]));
+    let mut permuted = as_is;
+    permuted.permute(&[1, 0]);
+    let mut sliced = as_is;
+    sliced.slice(&[
+      (Expression::from(1), Expression::from(2)),
+      (Expression::from(0), Expression::from(3)),
+    ]);
+    let mut broadcast = as_is;
+    broadcast.expand(0, 2);
+    // every consumer reads a twice under its own view
+    for view in [as_is, permuted, sliced, broadcast] {
+      let x = cx
+        .add_op(luminal::op::Add {})
+        .input(a.id, 0, view)
+        .input(a.id, 0, view)
+        .finish();
+      cx.no_delete.insert(x);
+      cx.to_retrieve
+        .insert(x, (0, ShapeTracker::new(&dims(&view.shape_usize()))));
+    }
+    assert_eq!(super::get_own_size(a.id, &cx), 6);
+    let (sc, _) = scalar(&cx);
+    assert_eq!(sc.inputs_tracker.new_inputs[&a.id].len(), 6);
+    let inputs = random_inputs(&cx, &mut StdRng::seed_from_u64(0));
+    assert_eq!(verify_scalarization(&cx, &sc, &inputs), Ok(()));
+  }
+
+  proptest! {
+    #![proptest_config(ProptestConfig::with_cases(64))]
+
+    #[test]
+    fn test_scalarize_random_exprs(expr in arb_expr(3), seed in any::<u64>()) {
+      let (cx, _) = build_graph(&expr);
+      let (sc, _) = scalar(&cx);
+      let inputs = random_inputs(&cx, &mut StdRng::seed_from_u64(seed));
+      prop_assert_eq!(verify_scalarization(&cx, &sc, &inputs), Ok(()), "expr: {:?}", expr);
+    }
+  }
 
   #[ignore = "debugging purpose test"]
   #[test]
@@ -561,7 +2714,7 @@ mod tests {
     let mut c = ((a + b) + d).retrieve();
     print!("{:?}", cx);
     save_graphviz("test_run_tensor.dot".to_string(), &cx)?;
-    let r = cx.compile(ScalarCompiler::default(), &mut c);
+    let r = cx.compile(Scalarize::default(), &mut c);
     print!("{:?}", cx);
     print!("{:?}", r);
     // pretty_print_g(&cx)?;
@@ -591,7 +2744,7 @@ mod tests {
     let mut c = ((a + b).expand::<(_, Const<3>), _>() + d).retrieve();
     print!("{:?}", cx);
     save_graphviz("test_run2_tensor.dot".to_string(), &cx)?;
-    let r = cx.compile(ScalarCompiler::default(), &mut c);
+    let r = cx.compile(Scalarize::default(), &mut c);
     print!("{:?}", cx);
     print!("{:?}", r);
     // pretty_print_g(&cx)?;
@@ -617,3 +2770,44 @@ fn logical_to_physical((ind, val): &(BigExpression, BigExpression), index: usize
     None
   }
 }
+
+/// Physical indices for many logical indices of the same shape, sharing the index and validity expressions.
+pub fn logical_to_physical_many(
+  exprs: &(BigExpression, BigExpression),
+  indices: impl IntoIterator<Item = usize>,
+) -> Vec<Option<usize>> {
+  indices
+    .into_iter()
+    .map(|i| logical_to_physical(exprs, i))
+    .collect()
+}
+
+/// Memoizes the logical to physical index mapping per ShapeTracker.
+///
+/// Scalarization asks for the physical index of every logical index of every edge.
+/// Building the symbolic index expression is expensive and most edges share a handful of shapes,
+/// so we build the expressions once per shape and tabulate the whole (static) logical range at once.
+#[derive(Debug, Default)]
+pub struct IndexCache {
+  tables: HashMap<ShapeTracker, Vec<Option<usize>>>,
+}
+
+impl IndexCache {
+  /// Logical index -> physical index for every logical index of the shape. None for masked out (padding) elements.
+  pub fn table(&mut self, shape: &ShapeTracker) -> &Vec<Option<usize>> {
+    self.tables.entry(*shape).or_insert_with(|| {
+      let n = shape
+        .n_elements()
+        .to_usize()
+        .unwrap_or_else(|| panic!("Shape is not static: {:?}", shape));
+      logical_to_physical_many(&(shape.index_expression(), shape.valid_expression()), 0..n)
+    })
+  }
+
+  pub fn logical_to_physical(&mut self, shape: &ShapeTracker, index: usize) -> Option<usize> {
+    match self.table(shape).get(index) {
+      Some(i) => *i,
+      None => panic!("Logical index {} outside of the shape {:?}", index, shape),
+    }
+  }
+}
@@ -0,0 +1,145 @@
+//!
+//! The class id instead of the scores: an argmax appended to an output of the scalar graph.
+//!
+//! Proving the index of the largest score alone keeps the scores private and the public inputs down to one per output.
+//! The argmax is a running selection out of LessThan, Mul, Add and constants, so every backend supports it:
+//!
+//! ```text
+//! better = best < v_i
+//! best   = best + better * (v_i - best)
+//! index  = index + better * (i - index)
+//! ```
+//!
+//! Ties go to the first of the largest scores, as numpy's argmax.
+//!
+
+use luminal::{
+  op::{Add, LessThan, Mul, Operator},
+  prelude::{Dependency, NodeIndex},
+  shape::{Shape, R0},
+};
+
+use super::{ConstantOp, ScalarGraph};
+
+struct Builder<'a> {
+  scalar: &'a mut ScalarGraph,
+  /// The origin of the new nodes, the first element of the output.
+  origin: (NodeIndex, usize),
+}
+
+impl Builder<'_> {
+  fn node<T: Operator + 'static>(&mut self, op: T, args: &[NodeIndex]) -> NodeIndex {
+    let new = self.scalar.graph.add_op(op).finish();
+    for (input_order, y) in args.iter().enumerate() {
+      self.scalar.graph.add_edge(
+        *y,
+        new,
+        Dependency::Data {
+          input_order: input_order as u8,
+          output_order: 0,
+          shape: R0::to_tracker(),
+        },
+      );
+    }
+    self.scalar.inputs_tracker.origin.insert(new, self.origin);
+    new
+  }
+
+  fn constant(&mut self, val: f32) -> NodeIndex {
+    let c = self.node(ConstantOp { val }, &[]);
+    self.scalar.inputs_tracker.constants.insert(c, val);
+    c
+  }
+
+  /// `current + flag * (candidate - current)`: the candidate if the flag is 1, the current value if 0.
+  fn select(
+    &mut self,
+    flag: NodeIndex,
+    candidate: NodeIndex,
+    current: NodeIndex,
+    minus_one: NodeIndex,
+  ) -> NodeIndex {
+    let negated = self.node(Mul {}, &[current, minus_one]);
+    let diff = self.node(Add {}, &[candidate, negated]);
+    let step = self.node(Mul {}, &[flag, diff]);
+    self.node(Add {}, &[current, step])
+  }
+}
+
+/// Replaces the retrieved tensor `output` of the scalar graph by the index of its largest element, see the module docs.
+/// The elements are taken in the order of [super::InputsTracker::new_outputs] (physical indices) and stop being outputs.
+/// Returns the node of the index, the single little output of `output` now.
+pub fn append_argmax(scalar: &mut ScalarGraph, output: NodeIndex) -> NodeIndex {
+  let scores = scalar
+    .inputs_tracker
+    .new_outputs
+    .get(&output)
+    .unwrap_or_else(|| panic!("{:?} is not an output of the scalar graph", output))
+    .clone();
+  assert!(!scores.is_empty(), "Argmax of an empty output");
+  for x in scores.iter() {
+    scalar.graph.to_retrieve.remove(x);
+  }
+
+  let mut b = Builder {
+    scalar,
+    origin: (output, 0),
+  };
+  let minus_one = b.constant(-1.0);
+  let mut best = scores[0];
+  let mut index = b.constant(0.0);
+  for (i, v) in scores.iter().enumerate().skip(1) {
+    let better = b.node(LessThan {}, &[best, *v]);
+    best = b.select(better, *v, best, minus_one);
+    let i = b.constant(i as f32);
+    index = b.select(better, i, index, minus_one);
+  }
+
+  scalar
+    .inputs_tracker
+    .new_outputs
+    .insert(output, vec![index]);
+  scalar
+    .graph
+    .to_retrieve
+    .insert(index, (0, R0::to_tracker()));
+  index
+}
+
+#[cfg(test)]
+mod tests {
+  use luminal::{graph::Graph, shape::R1};
+  use rand::{rngs::StdRng, Rng, SeedableRng};
+
+  use super::append_argmax;
+  use crate::scalar::scalar;
+
+  #[test]
+  fn test_append_argmax() {
+    let mut cx = Graph::new();
+    let a = cx.tensor::<R1<5>>();
+    let b = cx.tensor::<R1<5>>();
+    let c = (a * b).retrieve();
+    let (mut sc, _) = scalar(&cx);
+    let index = append_argmax(&mut sc, c.id);
+    assert_eq!(sc.inputs_tracker.new_outputs[&c.id], vec![index]);
+    assert_eq!(
+      sc.graph.to_retrieve.keys().collect::<Vec<_>>(),
+      vec![&index]
+    );
+
+    let mut rng = StdRng::seed_from_u64(0);
+    let mut check = |xs: Vec<f32>, expected: f32| {
+      let inputs = [(a.id, xs), (b.id, vec![1.0; 5])].into_iter().collect();
+      assert_eq!(sc.evaluate_outputs(&inputs)[&c.id], vec![expected]);
+    };
+    // ties go to the first
+    check(vec![1.0, 3.0, -2.0, 3.0, 0.5], 1.0);
+    check(vec![-1.0, -3.0, -2.0, -3.0, -0.5], 4.0);
+    for _ in 0..10 {
+      let xs: Vec<f32> = (0..5).map(|_| rng.gen_range(-1.0..1.0)).collect();
+      let expected = (0..5).fold(0, |best, i| if xs[best] < xs[i] { i } else { best });
+      check(xs, expected as f32);
+    }
+  }
+}
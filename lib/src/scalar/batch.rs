@@ -0,0 +1,295 @@
+//!
+//! Many inferences of the model in one scalar graph, scored against their labels: the circuit of the accuracy on a
+//! test set.
+//!
+//! [accuracy_circuit] copies the scalarized model once per sample. The sources other than the input (the weights and
+//! constants) are shared by the copies, every copy gets inputs of its own. The prediction of every copy is compared
+//! with a label, private as the samples, and the comparisons are summed up:
+//!
+//! ```text
+//! correct = 1 - (predicted < label) - (label < predicted)
+//! count   = correct_0 + ... + correct_n
+//! ```
+//!
+//! The count is the only result of the circuit, the verifier learns how many samples the model got right and nothing
+//! about them. With the hash of the input exposed (see [crate::snark::MLSnark::with_input_hash]), the proof commits to
+//! the samples and labels as well.
+//!
+
+use std::collections::{HashMap, HashSet};
+
+use itertools::Itertools;
+use luminal::prelude::NodeIndex;
+
+use super::{FrozenNode, FrozenScalarGraph, InputsTracker, ScalarOp};
+
+/// How the outputs of the model are turned into a label.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Prediction {
+  /// Label 1 for the first element of the output above the threshold, 0 otherwise: the binary classifiers, see
+  /// [crate::accuracy].
+  Threshold(f32),
+  /// The index of the largest element of the output, ties going to the first (see [super::append_argmax]).
+  Argmax,
+}
+
+struct Builder {
+  nodes: Vec<FrozenNode>,
+  tracker: InputsTracker,
+  next: usize,
+  /// The origin of the nodes made here, the first element of the output.
+  origin: (NodeIndex, usize),
+}
+
+impl Builder {
+  fn node(&mut self, op: ScalarOp, args: Vec<NodeIndex>, origin: (NodeIndex, usize)) -> NodeIndex {
+    let id = NodeIndex::new(self.next);
+    self.next += 1;
+    self.nodes.push(FrozenNode { id, op, args });
+    self.tracker.origin.insert(id, origin);
+    id
+  }
+
+  fn op(&mut self, op: ScalarOp, args: &[NodeIndex]) -> NodeIndex {
+    self.node(op, args.to_vec(), self.origin)
+  }
+
+  fn constant(&mut self, val: f32) -> NodeIndex {
+    let c = self.op(ScalarOp::Constant(val), &[]);
+    self.tracker.constants.insert(c, val);
+    c
+  }
+
+  /// `current + flag * (candidate - current)`: the candidate if the flag is 1, the current value if 0.
+  fn select(
+    &mut self,
+    flag: NodeIndex,
+    candidate: NodeIndex,
+    current: NodeIndex,
+    minus_one: NodeIndex,
+  ) -> NodeIndex {
+    let negated = self.op(ScalarOp::Mul, &[current, minus_one]);
+    let diff = self.op(ScalarOp::Add, &[candidate, negated]);
+    let step = self.op(ScalarOp::Mul, &[flag, diff]);
+    self.op(ScalarOp::Add, &[current, step])
+  }
+}
+
+/// The scalar graph counting the samples the model classifies correctly, see the module docs. `input` and `output`
+/// are the tensors of the model, the prediction is made from `output`.
+///
+/// The input of the result takes the `samples` samples one after another, then their labels. Its output is the count,
+/// under `output`. The shared sources keep their node ids, so their values (e.g. the weights) are set as for the model.
+pub fn accuracy_circuit(
+  model: &FrozenScalarGraph,
+  input: NodeIndex,
+  output: NodeIndex,
+  samples: usize,
+  prediction: Prediction,
+) -> FrozenScalarGraph {
+  assert!(samples > 0, "Accuracy over no samples");
+  let tracker = &model.inputs_tracker;
+  let features = tracker
+    .new_inputs
+    .get(&input)
+    .unwrap_or_else(|| panic!("{:?} is not an input of the scalar graph", input));
+  let scores = tracker
+    .new_outputs
+    .get(&output)
+    .unwrap_or_else(|| panic!("{:?} is not an output of the scalar graph", output));
+  assert!(!scores.is_empty(), "Accuracy of an empty output");
+  let scores = match prediction {
+    Prediction::Threshold(_) => &scores[..1],
+    Prediction::Argmax => &scores[..],
+  };
+
+  // only what the prediction needs is copied, e.g. not the other outputs
+  let mut needed: HashSet<NodeIndex> = scores.iter().copied().collect();
+  for node in model.nodes.iter().rev() {
+    if needed.contains(&node.id) {
+      needed.extend(node.args.iter().copied());
+    }
+  }
+  let is_feature: HashSet<NodeIndex> = features.iter().copied().collect();
+  let shared = model
+    .nodes
+    .iter()
+    .filter(|n| n.args.is_empty() && !is_feature.contains(&n.id))
+    .collect_vec();
+  let is_shared: HashSet<NodeIndex> = shared.iter().map(|n| n.id).collect();
+
+  let mut b = Builder {
+    nodes: shared.iter().map(|n| (*n).clone()).collect(),
+    tracker: InputsTracker {
+      new_inputs: tracker
+        .new_inputs
+        .iter()
+        .filter(|(x, _)| **x != input)
+        .map(|(x, little)| (*x, little.clone()))
+        .collect(),
+      origin: tracker
+        .origin
+        .iter()
+        .filter(|(x, _)| is_shared.contains(x))
+        .map(|(x, o)| (*x, *o))
+        .collect(),
+      constants: tracker
+        .constants
+        .iter()
+        .filter(|(x, _)| is_shared.contains(x))
+        .map(|(x, v)| (*x, *v))
+        .collect(),
+      ..Default::default()
+    },
+    next: model
+      .nodes
+      .iter()
+      .map(|n| n.id.index() + 1)
+      .max()
+      .unwrap_or(0),
+    origin: (output, 0),
+  };
+  let one = b.constant(1.0);
+  let minus_one = b.constant(-1.0);
+  let threshold = match prediction {
+    Prediction::Threshold(t) => Some(b.constant(t)),
+    Prediction::Argmax => None,
+  };
+
+  let mut little_inputs = vec![];
+  let mut labels = vec![];
+  let mut count: Option<NodeIndex> = None;
+  for s in 0..samples {
+    let mut copy: HashMap<NodeIndex, NodeIndex> = HashMap::new();
+    for (k, x) in features.iter().enumerate() {
+      let y = b.node(ScalarOp::Input, vec![], (input, s * features.len() + k));
+      little_inputs.push(y);
+      copy.insert(*x, y);
+    }
+    for node in model
+      .nodes
+      .iter()
+      .filter(|n| !n.args.is_empty() && needed.contains(&n.id))
+    {
+      let args = node
+        .args
+        .iter()
+        .map(|y| copy.get(y).copied().unwrap_or(*y))
+        .collect();
+      let origin = tracker.origin.get(&node.id).copied().unwrap_or(b.origin);
+      let y = b.node(node.op, args, origin);
+      copy.insert(node.id, y);
+    }
+
+    let predicted = match threshold {
+      Some(t) => b.op(ScalarOp::LessThan, &[t, copy[&scores[0]]]),
+      None => {
+        let mut best = copy[&scores[0]];
+        let mut index = b.constant(0.0);
+        for (i, v) in scores.iter().enumerate().skip(1) {
+          let v = copy[v];
+          let better = b.op(ScalarOp::LessThan, &[best, v]);
+          best = b.select(better, v, best, minus_one);
+          let i = b.constant(i as f32);
+          index = b.select(better, i, index, minus_one);
+        }
+        index
+      }
+    };
+    let label = b.node(
+      ScalarOp::Input,
+      vec![],
+      (input, samples * features.len() + s),
+    );
+    labels.push(label);
+    let below = b.op(ScalarOp::LessThan, &[predicted, label]);
+    let above = b.op(ScalarOp::LessThan, &[label, predicted]);
+    let wrong = b.op(ScalarOp::Add, &[below, above]);
+    let negated = b.op(ScalarOp::Mul, &[wrong, minus_one]);
+    let correct = b.op(ScalarOp::Add, &[one, negated]);
+    count = Some(match count {
+      Some(c) => b.op(ScalarOp::Add, &[c, correct]),
+      None => correct,
+    });
+  }
+
+  little_inputs.extend(labels);
+  b.tracker.new_inputs.insert(input, little_inputs);
+  b.tracker.new_outputs.insert(output, vec![count.unwrap()]);
+  FrozenScalarGraph {
+    nodes: b.nodes,
+    inputs_tracker: b.tracker,
+    tables: model.tables.clone(),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use luminal::{graph::Graph, shape::R2};
+  use rand::{rngs::StdRng, Rng, SeedableRng};
+
+  use super::{accuracy_circuit, Prediction};
+  use crate::scalar::scalar;
+
+  #[test]
+  fn test_accuracy_circuit() {
+    let mut cx = Graph::new();
+    let a = cx.tensor::<R2<1, 2>>();
+    let w = cx.tensor::<R2<2, 3>>();
+    let c = a.matmul(w).retrieve();
+    let (sc, _) = scalar(&cx);
+    let model = sc.freeze_ops();
+
+    let mut rng = StdRng::seed_from_u64(0);
+    let weights: Vec<f32> = (0..6).map(|_| rng.gen_range(-1.0..1.0)).collect();
+    let samples: Vec<Vec<f32>> = (0..6)
+      .map(|_| (0..2).map(|_| rng.gen_range(-1.0..1.0)).collect())
+      .collect();
+    let predicted = samples
+      .iter()
+      .map(|x| {
+        let inputs = [(a.id, x.clone()), (w.id, weights.clone())]
+          .into_iter()
+          .collect();
+        let scores = &sc.evaluate_outputs(&inputs)[&c.id];
+        (0..3).fold(0, |best, i| if scores[best] < scores[i] { i } else { best }) as f32
+      })
+      .collect::<Vec<_>>();
+    // the first four right
+    let labels: Vec<f32> = predicted
+      .iter()
+      .enumerate()
+      .map(|(i, p)| if i < 4 { *p } else { (*p + 1.0) % 3.0 })
+      .collect();
+
+    let circuit = accuracy_circuit(&model, a.id, c.id, 6, Prediction::Argmax);
+    let input = samples.concat().into_iter().chain(labels).collect();
+    let inputs = [(a.id, input), (w.id, weights.clone())]
+      .into_iter()
+      .collect();
+    assert_eq!(circuit.evaluate_outputs(&inputs)[&c.id], vec![4.0]);
+    // one copy of the weights
+    assert_eq!(
+      circuit
+        .nodes
+        .iter()
+        .filter(|n| n.op == crate::scalar::ScalarOp::Input)
+        .count(),
+      6 + 6 * 2 + 6
+    );
+
+    let circuit = accuracy_circuit(&model, a.id, c.id, 6, Prediction::Threshold(0.0));
+    let labels: Vec<f32> = samples
+      .iter()
+      .map(|x| {
+        let inputs = [(a.id, x.clone()), (w.id, weights.clone())]
+          .into_iter()
+          .collect();
+        (sc.evaluate_outputs(&inputs)[&c.id][0] > 0.0) as u8 as f32
+      })
+      .collect();
+    let input = samples.concat().into_iter().chain(labels).collect();
+    let inputs = [(a.id, input), (w.id, weights)].into_iter().collect();
+    assert_eq!(circuit.evaluate_outputs(&inputs)[&c.id], vec![6.0]);
+  }
+}
@@ -0,0 +1,272 @@
+//!
+//! The settings of the whole scalarization, made with a [ScalarCompilerBuilder].
+//!
+//! [Scalarize] is the luminal compiler lowering the tensor ops, [super::scalar_with] runs more around it: the check of
+//! the graph up front (see [super::check_supported]) and the rewrites of the scalar graph after. A [ScalarCompiler]
+//! says which of them run, next to the settings of the lowering. The default is what [super::scalar] does.
+//!
+//! A model too big to scalarize can take all the memory before anything fails. With a node budget
+//! ([Scalarize::max_scalar_nodes]) the lowering stops as soon as it's over, with a [NodeBudgetExceeded] telling where
+//! the nodes went.
+//!
+
+use std::{collections::HashMap, error::Error, fmt};
+
+use itertools::Itertools;
+use luminal::{graph::Graph, prelude::NodeIndex};
+
+use super::{ReductionStyle, Scalarize};
+use crate::model::ParamRegistry;
+
+#[derive(Debug, Clone)]
+pub struct ScalarCompiler {
+  pub lowering: Scalarize,
+  /// Checks the whole graph with [super::check_supported] first, for the complete list of what it can't lower.
+  /// Without the check the lowering panics on the first such node.
+  pub check_supported: bool,
+  /// [super::ScalarGraph::eliminate_common_subexpressions].
+  pub cse: bool,
+  /// [super::ScalarGraph::fold_constant_recips].
+  pub fold_constants: bool,
+  /// The rewrites recognizing what luminal expands ops into: [super::ScalarGraph::fuse_lookups],
+  /// [super::ScalarGraph::fuse_relus] and [super::ScalarGraph::fuse_subs].
+  pub fuse_patterns: bool,
+  /// [super::ScalarGraph::specialize_constant_comparisons]. Off by default, not every backend takes the ops it makes.
+  pub const_comparisons: bool,
+}
+
+impl Default for ScalarCompiler {
+  fn default() -> Self {
+    Scalarize::default().into()
+  }
+}
+
+impl From<Scalarize> for ScalarCompiler {
+  /// The lowering with the default checks and rewrites.
+  fn from(lowering: Scalarize) -> Self {
+    ScalarCompiler {
+      lowering,
+      check_supported: true,
+      cse: false,
+      fold_constants: true,
+      fuse_patterns: true,
+      const_comparisons: false,
+    }
+  }
+}
+
+impl ScalarCompiler {
+  pub fn builder() -> ScalarCompilerBuilder {
+    ScalarCompilerBuilder::default()
+  }
+}
+
+/// Builds a [ScalarCompiler], starting from the default one.
+#[derive(Debug, Clone, Default)]
+pub struct ScalarCompilerBuilder {
+  compiler: ScalarCompiler,
+}
+
+impl ScalarCompilerBuilder {
+  pub fn check_supported(mut self, on: bool) -> Self {
+    self.compiler.check_supported = on;
+    self
+  }
+
+  pub fn reduction(mut self, style: ReductionStyle) -> Self {
+    self.compiler.lowering.reduction = style;
+    self
+  }
+
+  /// See [Scalarize::sqrt_iterations].
+  pub fn sqrt(mut self, iterations: usize, initial_guess: f32) -> Self {
+    self.compiler.lowering.sqrt_iterations = iterations;
+    self.compiler.lowering.sqrt_initial_guess = initial_guess;
+    self
+  }
+
+  /// See [Scalarize::rsqrt_iterations].
+  pub fn rsqrt(mut self, iterations: usize, initial_guess: f32) -> Self {
+    self.compiler.lowering.rsqrt_iterations = iterations;
+    self.compiler.lowering.rsqrt_initial_guess = initial_guess;
+    self
+  }
+
+  pub fn argmax(mut self, on: bool) -> Self {
+    self.compiler.lowering.argmax = on;
+    self
+  }
+
+  pub fn cse(mut self, on: bool) -> Self {
+    self.compiler.cse = on;
+    self
+  }
+
+  pub fn fold_constants(mut self, on: bool) -> Self {
+    self.compiler.fold_constants = on;
+    self
+  }
+
+  pub fn fuse_patterns(mut self, on: bool) -> Self {
+    self.compiler.fuse_patterns = on;
+    self
+  }
+
+  pub fn const_comparisons(mut self, on: bool) -> Self {
+    self.compiler.const_comparisons = on;
+    self
+  }
+
+  /// See [Scalarize::max_scalar_nodes] and [NodeBudgetExceeded].
+  pub fn max_scalar_nodes(mut self, max: usize) -> Self {
+    self.compiler.lowering.max_scalar_nodes = Some(max);
+    self
+  }
+
+  pub fn build(self) -> ScalarCompiler {
+    self.compiler
+  }
+}
+
+/// The lowering made more scalar nodes than [Scalarize::max_scalar_nodes].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NodeBudgetExceeded {
+  pub max: usize,
+  /// Scalar nodes made when the lowering stopped.
+  pub reached: usize,
+  /// The tensor whose lowering went over the budget.
+  pub at: NodeIndex,
+  /// Scalar nodes made by the lowering of every tensor lowered so far, the most first.
+  pub per_tensor: Vec<(NodeIndex, usize)>,
+}
+
+impl NodeBudgetExceeded {
+  /// Renames the tensors, e.g. from the copy the compiler works on to the original graph.
+  pub fn remap_tensors(self, remap: &HashMap<NodeIndex, NodeIndex>) -> Self {
+    NodeBudgetExceeded {
+      at: remap[&self.at],
+      per_tensor: self
+        .per_tensor
+        .into_iter()
+        .map(|(x, n)| (remap[&x], n))
+        .collect(),
+      ..self
+    }
+  }
+
+  /// [NodeBudgetExceeded::per_tensor] summed up by the layer of the tensor (see [ParamRegistry::layer_of]), the most
+  /// first. `None` collects the tensors of no layer, e.g. the input.
+  pub fn per_layer(&self, cx: &Graph, params: &ParamRegistry) -> Vec<(Option<String>, usize)> {
+    let mut layers: HashMap<Option<String>, usize> = HashMap::new();
+    for (x, n) in self.per_tensor.iter() {
+      *layers.entry(params.layer_of(cx, *x)).or_default() += n;
+    }
+    layers
+      .into_iter()
+      .sorted_by(|(l1, n1), (l2, n2)| n2.cmp(n1).then_with(|| l1.cmp(l2)))
+      .collect()
+  }
+}
+
+impl fmt::Display for NodeBudgetExceeded {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(
+      f,
+      "Scalarization exceeds the budget of {} scalar nodes at {:?}: {} made, the most by",
+      self.max, self.at, self.reached
+    )?;
+    for (x, n) in self.per_tensor.iter().take(5) {
+      write!(f, " {:?} ({})", x, n)?;
+    }
+    Ok(())
+  }
+}
+
+impl Error for NodeBudgetExceeded {}
+
+#[cfg(test)]
+mod tests {
+  use luminal::{graph::Graph, op::LessThan, shape::R1};
+
+  use super::ScalarCompiler;
+  use crate::scalar::{scalar_with, try_scalar_with, verify_scalarization, ReductionStyle, ReluOp};
+
+  #[test]
+  fn test_builder_toggles() {
+    let mut cx = Graph::new();
+    let a = cx.tensor::<R1<8>>();
+    let _ = a
+      .relu()
+      .max_reduce::<_, luminal::shape::Axis<0>>()
+      .retrieve();
+    let inputs = [(a.id, vec![-1.0, 2.0, -3.0, 0.5, 4.0, -0.5, 0.25, 1.0])]
+      .into_iter()
+      .collect();
+    let count = |compiler: ScalarCompiler, relu: bool| {
+      let (sc, _) = scalar_with(&cx, compiler);
+      assert_eq!(verify_scalarization(&cx, &sc, &inputs), Ok(()));
+      sc.graph
+        .node_indices()
+        .filter(|x| match relu {
+          true => sc.graph.check_node_type::<ReluOp>(*x),
+          false => sc.graph.check_node_type::<LessThan>(*x),
+        })
+        .count()
+    };
+    assert_eq!(count(ScalarCompiler::default(), true), 8);
+    let unfused = ScalarCompiler::builder().fuse_patterns(false).build();
+    assert_eq!(count(unfused.clone(), true), 0);
+    assert!(count(unfused, false) > 0);
+
+    let compiler = ScalarCompiler::builder()
+      .reduction(ReductionStyle::BalancedTree)
+      .argmax(true)
+      .check_supported(false)
+      .build();
+    assert_eq!(compiler.lowering.reduction, ReductionStyle::BalancedTree);
+    let (sc, _) = scalar_with(&cx, compiler);
+    assert_eq!(sc.inputs_tracker.argmax.values().flatten().count(), 1);
+  }
+
+  #[test]
+  #[should_panic(expected = "scalar nodes")]
+  fn test_node_budget() {
+    let mut cx = Graph::new();
+    let a = cx.tensor::<R1<8>>();
+    let _ = (a * a).retrieve();
+    scalar_with(&cx, ScalarCompiler::builder().max_scalar_nodes(10).build());
+  }
+
+  #[test]
+  fn test_node_budget_breakdown() {
+    let model = crate::model::fixed_weights::run_model().graph;
+    let (sc, _) = scalar_with(&model.graph, ScalarCompiler::default());
+    let total = sc.graph.node_count();
+    // the rewrites only shrink the graph
+    assert!(try_scalar_with(
+      &model.graph,
+      ScalarCompiler::builder()
+        .max_scalar_nodes(10 * total)
+        .build()
+    )
+    .is_ok());
+
+    let max = total / 2;
+    let e = try_scalar_with(
+      &model.graph,
+      ScalarCompiler::builder().max_scalar_nodes(max).build(),
+    )
+    .unwrap_err();
+    assert_eq!(e.max, max);
+    assert!(e.reached > max);
+    assert_eq!(
+      e.per_tensor.iter().map(|(_, n)| n).sum::<usize>(),
+      e.reached
+    );
+    assert!(e.per_tensor.iter().any(|(x, _)| *x == e.at));
+    assert!(e.per_tensor.windows(2).all(|w| w[0].1 >= w[1].1));
+    let layers = e.per_layer(&model.graph, &model.params);
+    assert_eq!(layers.iter().map(|(_, n)| n).sum::<usize>(), e.reached);
+    assert!(layers.iter().any(|(layer, _)| layer.is_some()));
+  }
+}
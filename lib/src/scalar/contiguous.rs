@@ -0,0 +1,107 @@
+//!
+//! Removal of the Contiguous nodes copying their input as it is.
+//!
+//! luminal makes tensors contiguous before reshapes and around other ops, whether their view is reshaped or not. The
+//! Contiguous of a plain view (no permute, broadcast, slice or padding) has the elements of its input in the same
+//! order, so a view of the result is the same view of the input. [remove_noop_contiguous] rewires the readers of such
+//! nodes to the input, each under its own view, and removes the nodes, before the lowering. That's fewer tensors to
+//! lower and reductions with a copy in between are merged (see [super::ReductionStyle]).
+//!
+//! The Contiguous of any other view reorders the elements, the lowering reads through it (see [super::Scalarize]).
+//! Retrieved ones stay, their little nodes are the retrieved tensor.
+//!
+
+use itertools::Itertools;
+use luminal::{
+  graph::Graph,
+  op::Contiguous,
+  prelude::{
+    petgraph::{
+      self,
+      visit::EdgeRef,
+      Direction::{Incoming, Outgoing},
+    },
+    Dependency, ShapeTracker,
+  },
+};
+
+/// Removes the Contiguous nodes of plain views that aren't retrieved, see the module docs. Returns how many.
+pub fn remove_noop_contiguous(graph: &mut Graph) -> usize {
+  let mut removed = 0;
+  // inputs first, so a copy of a copy reads the original when it's its turn
+  for x in petgraph::algo::toposort(&graph.graph, None).unwrap() {
+    if !graph.check_node_type::<Contiguous>(x)
+      || graph.to_retrieve.contains_key(&x)
+      || graph.no_delete.contains(&x)
+    {
+      continue;
+    }
+    let incoming = graph
+      .edges_directed(x, Incoming)
+      .filter_map(|e| e.weight().as_data().map(|d| (e.source(), d)))
+      .collect_vec();
+    let (y, (_, output_order, sh)) = match incoming[..] {
+      [edge] => edge,
+      _ => continue,
+    };
+    if sh != ShapeTracker::new(&sh.shape()) {
+      continue;
+    }
+    let readers = graph
+      .edges_directed(x, Outgoing)
+      .filter_map(|e| e.weight().as_data().map(|d| (e.target(), d)))
+      .collect_vec();
+    for (target, (input_order, _, shape)) in readers {
+      graph.add_edge(
+        y,
+        target,
+        Dependency::Data {
+          input_order,
+          output_order,
+          shape,
+        },
+      );
+    }
+    graph.remove_node(x);
+    removed += 1;
+  }
+  removed
+}
+
+#[cfg(test)]
+mod tests {
+  use luminal::prelude::*;
+  use rand::{rngs::StdRng, SeedableRng};
+
+  use super::remove_noop_contiguous;
+  use crate::scalar::{copy_graph_roughly, random_inputs, scalar, verify_scalarization};
+
+  #[test]
+  fn test_remove_noop_contiguous() {
+    let mut cx = Graph::new();
+    let a = cx.tensor::<R2<2, 3>>();
+    let b = cx.tensor::<R1<6>>();
+    // a copy of a copy of a, read flat
+    let c1 = cx.add_op(Contiguous).input(a.id, 0, a.shape).finish();
+    let c2 = cx.add_op(Contiguous).input(c1, 0, a.shape).finish();
+    let flat = GraphTensor::<R1<6>>::from_id(c2, R1::<6>::to_tracker(), a.graph_ref);
+    // the copy of the transpose reorders
+    let mut transposed = a.shape;
+    transposed.permute(&[1, 0]);
+    let c3 = cx.add_op(Contiguous).input(a.id, 0, transposed).finish();
+    let flat_t = GraphTensor::<R1<6>>::from_id(c3, R1::<6>::to_tracker(), a.graph_ref);
+    let _d = (flat + b + flat_t).retrieve();
+
+    let (mut g, remap) = copy_graph_roughly(&cx);
+    assert_eq!(remove_noop_contiguous(&mut g), 2);
+    let left = g
+      .node_indices()
+      .filter(|x| g.check_node_type::<Contiguous>(*x))
+      .collect::<Vec<_>>();
+    assert_eq!(left, vec![remap[&c3]]);
+
+    let (sc, _) = scalar(&cx);
+    let inputs = random_inputs(&cx, &mut StdRng::seed_from_u64(0));
+    assert_eq!(verify_scalarization(&cx, &sc, &inputs), Ok(()));
+  }
+}
@@ -0,0 +1,170 @@
+//!
+//! Reference evaluation of the scalar graph on floats, and comparing it against the tensor graph it was made from.
+//!
+
+use std::collections::HashMap;
+
+use itertools::Itertools;
+use luminal::{
+  graph::Graph,
+  op::Function,
+  prelude::{petgraph::Direction::Incoming, NodeIndex, Tensor},
+};
+use rand::Rng;
+
+use crate::dtype::tensor_f32;
+
+use super::{copy_graph_roughly, get_own_size, ScalarGraph};
+
+impl ScalarGraph {
+  /// Evaluates every node of the scalar graph.
+  /// Inputs are given per original tensor graph input (as in [super::InputsTracker::new_inputs]), as flat vectors of physical elements.
+  pub fn evaluate(&self, inputs: &HashMap<NodeIndex, Vec<f32>>) -> HashMap<NodeIndex, f32> {
+    self.freeze_ops().evaluate(inputs)
+  }
+
+  /// Evaluates the scalar graph and collects the little outputs back into the retrieved tensors of the original graph.
+  pub fn evaluate_outputs(
+    &self,
+    inputs: &HashMap<NodeIndex, Vec<f32>>,
+  ) -> HashMap<NodeIndex, Vec<f32>> {
+    self.freeze_ops().evaluate_outputs(inputs)
+  }
+}
+
+/// Evaluates the tensor graph on the given inputs (every source node needs one) and returns the retrieved tensors.
+/// The graph is copied first, so the original stays intact.
+pub fn evaluate_tensor_graph(
+  graph: &Graph,
+  inputs: &HashMap<NodeIndex, Vec<f32>>,
+) -> HashMap<NodeIndex, Vec<f32>> {
+  let (mut g, remap) = copy_graph_roughly(graph);
+  for (x, data) in inputs.iter() {
+    let data = data.clone();
+    g.get_op_mut::<Function>(remap[x]).1 = Box::new(move |_| vec![Tensor::new(data.clone())]);
+  }
+  g.execute();
+  graph
+    .to_retrieve
+    .keys()
+    .map(|x| {
+      let data = tensor_f32(g.get_tensor_ref(remap[x], 0).unwrap()).unwrap();
+      (*x, data)
+    })
+    .collect()
+}
+
+/// Random values in [-1, 1] for every input (source Function node) of the tensor graph.
+pub fn random_inputs<R: Rng>(graph: &Graph, rng: &mut R) -> HashMap<NodeIndex, Vec<f32>> {
+  graph
+    .node_indices()
+    .filter(|x| graph.check_node_type::<Function>(*x))
+    .filter(|x| graph.edges_directed(*x, Incoming).next().is_none())
+    .map(|x| {
+      let data = (0..get_own_size(x, graph))
+        .map(|_| rng.gen_range(-1.0..1.0))
+        .collect();
+      (x, data)
+    })
+    .collect()
+}
+
+/// Where the scalar graph disagrees with the tensor graph.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Mismatch {
+  /// A retrieved tensor of the original graph has no little outputs in the scalar graph.
+  MissingOutput { output: NodeIndex },
+  Length {
+    output: NodeIndex,
+    expected: usize,
+    got: usize,
+  },
+  Value {
+    output: NodeIndex,
+    /// physical index into the retrieved tensor, equivalently index into `new_outputs[output]`
+    index: usize,
+    expected: f32,
+    got: f32,
+  },
+}
+
+/// Relative tolerance for comparing the scalar and tensor graph outputs. These should agree up to float reordering.
+pub const SCALARIZATION_TOLERANCE: f32 = 1e-4;
+
+fn close(a: f32, b: f32) -> bool {
+  (a.is_nan() && b.is_nan())
+    || a == b
+    || (a - b).abs() <= SCALARIZATION_TOLERANCE * a.abs().max(b.abs()).max(1.0)
+}
+
+/// Runs both the tensor graph and the scalar graph derived from it and compares the retrieved outputs elementwise.
+///
+/// Assumes `scalar` was made from `original` (or an index preserving copy of it), so that the node ids in the inputs tracker refer to `original`.
+pub fn verify_scalarization(
+  original: &Graph,
+  scalar: &ScalarGraph,
+  inputs: &HashMap<NodeIndex, Vec<f32>>,
+) -> Result<(), Mismatch> {
+  let expected = evaluate_tensor_graph(original, inputs);
+  let got = scalar.evaluate_outputs(inputs);
+  for (output, expected) in expected.into_iter().sorted_by_key(|(x, _)| *x) {
+    let got = got.get(&output).ok_or(Mismatch::MissingOutput { output })?;
+    if got.len() != expected.len() {
+      return Err(Mismatch::Length {
+        output,
+        expected: expected.len(),
+        got: got.len(),
+      });
+    }
+    for (index, (e, g)) in expected.iter().zip(got.iter()).enumerate() {
+      if !close(*e, *g) {
+        return Err(Mismatch::Value {
+          output,
+          index,
+          expected: *e,
+          got: *g,
+        });
+      }
+    }
+  }
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use luminal::{
+    graph::Graph,
+    shape::{Const, R1, R2},
+  };
+  use rand::{rngs::StdRng, SeedableRng};
+
+  use crate::scalar::scalar;
+
+  use super::{random_inputs, verify_scalarization};
+
+  #[test]
+  fn test_verify_scalarization_expand() {
+    let mut cx = Graph::new();
+    let a = cx.tensor::<R1<2>>();
+    let b = cx.tensor::<R1<2>>();
+    let d = cx.tensor::<R2<2, 3>>();
+    let _c = ((a + b).expand::<(_, Const<3>), _>() * d).retrieve();
+    let (sc, _) = scalar(&cx);
+    let mut rng = StdRng::seed_from_u64(0);
+    for _ in 0..5 {
+      let inputs = random_inputs(&cx, &mut rng);
+      assert_eq!(verify_scalarization(&cx, &sc, &inputs), Ok(()));
+    }
+  }
+
+  #[test]
+  fn test_verify_scalarization_matmul() {
+    let mut cx = Graph::new();
+    let a = cx.tensor::<R2<2, 3>>();
+    let b = cx.tensor::<R2<3, 4>>();
+    let _c = a.matmul(b).retrieve();
+    let (sc, _) = scalar(&cx);
+    let inputs = random_inputs(&cx, &mut StdRng::seed_from_u64(1));
+    assert_eq!(verify_scalarization(&cx, &sc, &inputs), Ok(()));
+  }
+}
@@ -0,0 +1,193 @@
+//!
+//! Weights baked into the scalar graph as constants, and updated in place after retraining.
+//!
+//! By default the weights are inputs of the scalar graph, assigned with the witness. Frozen into [ConstantOp]s
+//! they become part of the circuit instead (see [crate::WeightsMode]). Retraining changes only the values of those constants, so
+//! [ScalarGraph::update_constants] replaces them without scalarizing again, and the circuit keeps its
+//! [structural hash](ScalarGraph::structural_hash_with) (without constants), so a cached setup stays valid.
+//!
+
+use luminal::prelude::NodeIndex;
+
+use crate::model::ParamRegistry;
+
+use super::{ConstantOp, InputOp, ScalarGraph};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum FreezeError {
+  /// No weight given for a parameter of the registry.
+  MissingWeight { name: String },
+  /// The weight has a different number of elements than the parameter.
+  WrongSize {
+    name: String,
+    expected: usize,
+    got: usize,
+  },
+  /// The parameter is not an input of the scalar graph (e.g. frozen already).
+  NotAnInput { name: String },
+  /// The parameter has no constants in the scalar graph, see [ScalarGraph::freeze_params].
+  NotFrozen { name: String },
+  /// The update changed more than the values of the constants.
+  StructureChanged,
+}
+
+/// The weight of the parameter from `weights`, checked against its shape.
+fn weight_of<'a>(
+  weights: &'a [(NodeIndex, Vec<f32>)],
+  id: NodeIndex,
+  name: &str,
+  expected: usize,
+) -> Result<&'a [f32], FreezeError> {
+  let (_, w) =
+    weights
+      .iter()
+      .find(|(x, _)| *x == id)
+      .ok_or_else(|| FreezeError::MissingWeight {
+        name: name.to_string(),
+      })?;
+  if w.len() != expected {
+    return Err(FreezeError::WrongSize {
+      name: name.to_string(),
+      expected,
+      got: w.len(),
+    });
+  }
+  Ok(w)
+}
+
+impl ScalarGraph {
+  /// Replaces the inputs of the registered parameters with constants of their weights.
+  /// The registry and the weights refer to the tensor graph the scalar graph was made from, as in [crate::model::GraphForSnark].
+  pub fn freeze_params(
+    &mut self,
+    params: &ParamRegistry,
+    weights: &[(NodeIndex, Vec<f32>)],
+  ) -> Result<(), FreezeError> {
+    // check everything before changing anything
+    for p in params.params.iter() {
+      let little =
+        self
+          .inputs_tracker
+          .new_inputs
+          .get(&p.id)
+          .ok_or_else(|| FreezeError::NotAnInput {
+            name: p.name.clone(),
+          })?;
+      weight_of(weights, p.id, &p.name, little.len())?;
+    }
+    for p in params.params.iter() {
+      let w = weight_of(
+        weights,
+        p.id,
+        &p.name,
+        self.inputs_tracker.new_inputs[&p.id].len(),
+      )?;
+      for (y, val) in self
+        .inputs_tracker
+        .new_inputs
+        .remove(&p.id)
+        .unwrap()
+        .into_iter()
+        .zip(w)
+      {
+        assert!(self.graph.check_node_type::<InputOp>(y));
+        *self.graph.node_weight_mut(y).unwrap() = Box::new(ConstantOp { val: *val });
+        self.inputs_tracker.constants.insert(y, *val);
+      }
+    }
+    Ok(())
+  }
+
+  /// Sets the constants of the parameters frozen by [ScalarGraph::freeze_params] to the new weights.
+  /// Only the witness and the proof need to be computed again, the circuit is the same up to the constants:
+  /// checked by comparing [ScalarGraph::structural_hash_with] (without constants) before and after.
+  pub fn update_constants(
+    &mut self,
+    params: &ParamRegistry,
+    new_weights: &[(NodeIndex, Vec<f32>)],
+  ) -> Result<(), FreezeError> {
+    let hash = self.structural_hash_with(false);
+    let mut updates = vec![];
+    for p in params.params.iter() {
+      let constants: Vec<(NodeIndex, usize)> = self
+        .inputs_tracker
+        .origin
+        .iter()
+        .filter(|(y, (x, _))| *x == p.id && self.graph.check_node_type::<ConstantOp>(**y))
+        .map(|(y, (_, i))| (*y, *i))
+        .collect();
+      if constants.is_empty() {
+        return Err(FreezeError::NotFrozen {
+          name: p.name.clone(),
+        });
+      }
+      let w = weight_of(new_weights, p.id, &p.name, constants.len())?;
+      updates.extend(constants.into_iter().map(|(y, i)| (y, w[i])));
+    }
+    for (y, val) in updates {
+      self.graph.get_op_mut::<ConstantOp>(y).val = val;
+      self.inputs_tracker.constants.insert(y, val);
+    }
+    if self.structural_hash_with(false) != hash {
+      return Err(FreezeError::StructureChanged);
+    }
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::collections::HashMap;
+
+  use luminal::prelude::NodeIndex;
+
+  use super::FreezeError;
+  use crate::scalar::scalar;
+
+  #[test]
+  fn test_update_constants_keeps_the_circuit() {
+    let model = crate::model::fixed_weights::run_model().graph;
+    let (mut sc, _) = scalar(&model.graph);
+    let input = vec![0.5; sc.inputs_tracker.new_inputs[&model.input_id].len()];
+    let eval = |sc: &crate::scalar::ScalarGraph, weights: &[(NodeIndex, Vec<f32>)]| {
+      let mut inputs: HashMap<NodeIndex, Vec<f32>> = weights.iter().cloned().collect();
+      inputs.insert(model.input_id, input.clone());
+      sc.evaluate_outputs(&inputs)
+    };
+    let before = eval(&sc, &model.weights);
+    let (structure, full) = (sc.structural_hash_with(false), sc.structural_hash());
+
+    sc.freeze_params(&model.params, &model.weights).unwrap();
+    assert_eq!(eval(&sc, &[]), before);
+    assert_eq!(
+      sc.freeze_params(&model.params, &model.weights),
+      Err(FreezeError::NotAnInput {
+        name: "layer0.weight".to_string()
+      })
+    );
+
+    let retrained: Vec<(NodeIndex, Vec<f32>)> = model
+      .weights
+      .iter()
+      .map(|(x, w)| (*x, w.iter().map(|v| v * 2.0).collect()))
+      .collect();
+    let frozen = sc.structural_hash_with(false);
+    sc.update_constants(&model.params, &retrained).unwrap();
+    assert_eq!(sc.structural_hash_with(false), frozen);
+    assert_ne!(frozen, structure);
+    assert_ne!(sc.structural_hash(), full);
+
+    let mut unfrozen = scalar(&model.graph).0;
+    assert_eq!(eval(&sc, &[]), eval(&unfrozen, &retrained));
+    assert_eq!(
+      unfrozen.update_constants(&model.params, &retrained),
+      Err(FreezeError::NotFrozen {
+        name: "layer0.weight".to_string()
+      })
+    );
+    assert!(matches!(
+      sc.update_constants(&model.params, &retrained[..1]),
+      Err(FreezeError::MissingWeight { .. })
+    ));
+  }
+}
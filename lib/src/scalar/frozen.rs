@@ -0,0 +1,268 @@
+//!
+//! The scalar graph as plain data, to move it between threads.
+//!
+//! A [ScalarGraph] holds its ops as boxed luminal operators, neither `Send` nor `Sync`, so it stays on the thread that
+//! scalarized the model. [FrozenScalarGraph] is the same computation with every op a [ScalarOp]: a server can scalarize
+//! in a worker, send the frozen graph to another thread or share it behind an `Arc`. It evaluates on its own
+//! ([FrozenScalarGraph::evaluate], [FrozenScalarGraph::witness_program]), the scalar graph's evaluators go through it.
+//! Where an API needs the luminal graph, [FrozenScalarGraph::thaw] rebuilds it on the thread that uses it.
+//!
+//! It is the compact form of the graph as well. Every edge of a luminal graph carries a whole `ShapeTracker`, a few
+//! kilobytes, even though all the shapes of a scalar graph are `()`. A frozen node keeps just its arguments in order,
+//! so the circuit of [crate::snark::MLSnark], kept for every synthesis, is a frozen graph. The lowering itself still
+//! works on the luminal graph.
+//!
+
+use std::collections::{HashMap, HashSet};
+
+use itertools::Itertools;
+use luminal::{
+  graph::Graph,
+  prelude::{
+    petgraph::{self, visit::EdgeRef, Direction::Incoming},
+    Dependency, NodeIndex,
+  },
+  shape::{Shape, R0},
+};
+
+use super::{InputsTracker, ScalarGraph, ScalarOp, TableRegistry};
+
+/// A node of a [FrozenScalarGraph]: its id in the scalar graph, op and arguments in argument order.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FrozenNode {
+  pub id: NodeIndex,
+  pub op: ScalarOp,
+  pub args: Vec<NodeIndex>,
+}
+
+/// A [ScalarGraph] of plain data, `Send` and `Sync`, see the module docs.
+#[derive(Debug, Clone)]
+pub struct FrozenScalarGraph {
+  /// In a topological order, every node after its arguments.
+  pub nodes: Vec<FrozenNode>,
+  /// As in the scalar graph, by the same node ids.
+  pub inputs_tracker: InputsTracker,
+  pub tables: TableRegistry,
+}
+
+impl From<&ScalarGraph> for FrozenScalarGraph {
+  fn from(sc: &ScalarGraph) -> Self {
+    let graph = &sc.graph;
+    let nodes = petgraph::algo::toposort(&graph.graph, None)
+      .unwrap()
+      .into_iter()
+      .map(|x| FrozenNode {
+        id: x,
+        op: sc.scalar_op(x),
+        args: graph
+          .edges_directed(x, Incoming)
+          .filter_map(|e| e.weight().as_data().map(|(inp, _, _)| (inp, e.source())))
+          .sorted_by_key(|(inp, _)| *inp)
+          .map(|(_, y)| y)
+          .collect(),
+      })
+      .collect();
+    FrozenScalarGraph {
+      nodes,
+      inputs_tracker: sc.inputs_tracker.clone(),
+      tables: sc.tables.clone(),
+    }
+  }
+}
+
+impl ScalarGraph {
+  pub fn freeze_ops(&self) -> FrozenScalarGraph {
+    FrozenScalarGraph::from(self)
+  }
+}
+
+impl FrozenScalarGraph {
+  pub fn node_count(&self) -> usize {
+    self.nodes.len()
+  }
+
+  /// The nodes the circuit binds to public inputs: the sinks and the retrieved nodes (e.g. the boundary outputs of a
+  /// chunk, used further).
+  pub fn results(&self) -> HashSet<NodeIndex> {
+    let used: HashSet<NodeIndex> = self
+      .nodes
+      .iter()
+      .flat_map(|n| n.args.iter().copied())
+      .collect();
+    self
+      .nodes
+      .iter()
+      .map(|n| n.id)
+      .filter(|x| !used.contains(x))
+      .chain(
+        self
+          .inputs_tracker
+          .new_outputs
+          .values()
+          .chain(self.inputs_tracker.argmax.values())
+          .flatten()
+          .copied(),
+      )
+      .collect()
+  }
+
+  /// As [ScalarGraph::canonical_order], in the node ids of the frozen graph.
+  pub fn canonical_order(&self) -> Vec<NodeIndex> {
+    // thawing numbers the nodes in the order of their ids
+    let ids = self.nodes.iter().map(|n| n.id).sorted().collect_vec();
+    self
+      .thaw()
+      .canonical_order()
+      .into_iter()
+      .map(|x| ids[x.index()])
+      .collect()
+  }
+
+  /// As [ScalarGraph::structural_hash], which doesn't depend on the node ids.
+  pub fn structural_hash(&self) -> [u8; 32] {
+    self.thaw().structural_hash()
+  }
+
+  /// As [ScalarGraph::evaluate].
+  pub fn evaluate(&self, inputs: &HashMap<NodeIndex, Vec<f32>>) -> HashMap<NodeIndex, f32> {
+    let mut input_values: HashMap<NodeIndex, f32> = HashMap::new();
+    for (x, little_ids) in self.inputs_tracker.new_inputs.iter() {
+      let data = inputs
+        .get(x)
+        .unwrap_or_else(|| panic!("Missing input for {:?}", x));
+      assert!(
+        data.len() == little_ids.len(),
+        "Input {:?} expects {} values, got {}",
+        x,
+        little_ids.len(),
+        data.len()
+      );
+      input_values.extend(little_ids.iter().copied().zip(data.iter().copied()));
+    }
+
+    let mut values: HashMap<NodeIndex, f32> = HashMap::new();
+    for node in self.nodes.iter() {
+      let v = match node.op {
+        ScalarOp::Input => *input_values
+          .get(&node.id)
+          .unwrap_or_else(|| panic!("Input node {:?} without a value", node.id)),
+        op => op.eval(&node.args.iter().map(|y| values[y]).collect_vec()),
+      };
+      values.insert(node.id, v);
+    }
+    values
+  }
+
+  /// As [ScalarGraph::evaluate_outputs].
+  pub fn evaluate_outputs(
+    &self,
+    inputs: &HashMap<NodeIndex, Vec<f32>>,
+  ) -> HashMap<NodeIndex, Vec<f32>> {
+    let values = self.evaluate(inputs);
+    self
+      .inputs_tracker
+      .new_outputs
+      .iter()
+      .map(|(x, little_ids)| (*x, little_ids.iter().map(|y| values[y]).collect()))
+      .collect()
+  }
+
+  /// The [ScalarGraph] again, with the nodes renumbered in the order of their ids.
+  pub fn thaw(&self) -> ScalarGraph {
+    let mut tables = self.tables.clone();
+    let mut g = Graph::new();
+    let mut remap: HashMap<NodeIndex, NodeIndex> = HashMap::new();
+    for node in self.nodes.iter().sorted_by_key(|n| n.id) {
+      let new = g.graph.add_node(node.op.operator(&mut tables));
+      remap.insert(node.id, new);
+    }
+    for node in self.nodes.iter() {
+      for (input_order, y) in node.args.iter().enumerate() {
+        g.add_edge(
+          remap[y],
+          remap[&node.id],
+          Dependency::Data {
+            input_order: input_order as u8,
+            output_order: 0,
+            shape: R0::to_tracker(),
+          },
+        );
+      }
+    }
+    let inputs_tracker = self.inputs_tracker.remap(remap);
+    for x in inputs_tracker
+      .new_outputs
+      .values()
+      .chain(inputs_tracker.argmax.values())
+      .flatten()
+    {
+      g.to_retrieve.insert(*x, (0, R0::to_tracker()));
+    }
+    ScalarGraph {
+      graph: g,
+      inputs_tracker,
+      tables,
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::{sync::Arc, thread};
+
+  use luminal::{graph::Graph, shape::R2};
+  use rand::{rngs::StdRng, SeedableRng};
+
+  use super::FrozenScalarGraph;
+  use crate::scalar::{random_inputs, scalar};
+
+  fn assert_send_sync<T: Send + Sync>() {}
+
+  #[test]
+  fn test_frozen_graph_across_threads() {
+    assert_send_sync::<FrozenScalarGraph>();
+    // scalarized in a worker, the luminal graphs stay there
+    let (frozen, inputs, expected) = thread::spawn(|| {
+      let mut cx = Graph::new();
+      let a = cx.tensor::<R2<2, 3>>();
+      let b = cx.tensor::<R2<3, 2>>();
+      let _c = (a.matmul(b).relu() + 1.0).recip().retrieve();
+      let inputs = random_inputs(&cx, &mut StdRng::seed_from_u64(0));
+      let (sc, _) = scalar(&cx);
+      let expected = sc.evaluate_outputs(&inputs);
+      (sc.freeze_ops(), inputs, expected)
+    })
+    .join()
+    .unwrap();
+
+    let frozen = Arc::new(frozen);
+    let outputs = {
+      let (frozen, inputs) = (frozen.clone(), inputs.clone());
+      thread::spawn(move || frozen.evaluate_outputs(&inputs))
+        .join()
+        .unwrap()
+    };
+    assert_eq!(outputs, expected);
+    let thawed = frozen.thaw();
+    assert_eq!(thawed.graph.node_count(), frozen.node_count());
+    assert_eq!(thawed.evaluate_outputs(&inputs), expected);
+    assert_eq!(thawed.structural_hash(), frozen.structural_hash());
+  }
+
+  #[test]
+  fn test_frozen_canonical_order() {
+    let mut cx = Graph::new();
+    let a = cx.tensor::<R2<2, 3>>();
+    let b = cx.tensor::<R2<3, 2>>();
+    let c = a.matmul(b).relu().retrieve();
+    let (sc, _) = scalar(&cx);
+    let frozen = sc.freeze_ops();
+    assert_eq!(frozen.canonical_order(), sc.canonical_order());
+    assert_eq!(frozen.structural_hash(), sc.structural_hash());
+    let results = frozen.results();
+    assert_eq!(results.len(), 4);
+    assert!(sc.inputs_tracker.new_outputs[&c.id]
+      .iter()
+      .all(|x| results.contains(x)));
+  }
+}
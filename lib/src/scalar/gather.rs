@@ -0,0 +1,96 @@
+//!
+//! Gathering rows of a table by index, as in an embedding layer.
+//!
+//! [Gather] is a tensor op of our own, so that the gather survives as one node until scalarization.
+//! There it's lowered to a one-hot vector of the index times the table, out of Mul, Add and LessThan little nodes.
+//! That's `rows * dim` multiplications per gathered row, fine for small embedding tables and vocabularies.
+//!
+
+use luminal::{
+  op::{InputTensor, Operator},
+  prelude::{GraphTensor, ShapeTracker, Tensor},
+  shape::{Shape, R1, R2},
+};
+
+use super::logical_to_physical_many;
+use crate::dtype::tensor_f32;
+
+/// `out[s, d] = table[indexes[s], d]` for the `(rows, dim)` table (input 1) and the indexes (input 0).
+/// An index matching no row, out of range or fractional, gathers zeros. That's what the one-hot lowering computes.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Gather {
+  pub rows: usize,
+  pub dim: usize,
+}
+
+impl Operator for Gather {
+  fn process(&mut self, inp: Vec<(InputTensor, ShapeTracker)>) -> Vec<Tensor> {
+    // logical elements of the input, masked out ones are zeros
+    let read = |(tensor, shape): &(InputTensor, ShapeTracker)| -> Vec<f32> {
+      let data = tensor_f32(tensor.borrowed()).unwrap();
+      let n = shape.n_elements().to_usize().unwrap();
+      logical_to_physical_many(&(shape.index_expression(), shape.valid_expression()), 0..n)
+        .into_iter()
+        .map(|i| i.map_or(0.0, |i| data[i]))
+        .collect()
+    };
+    let (indexes, table) = (read(&inp[0]), read(&inp[1]));
+    let out = indexes
+      .iter()
+      .flat_map(|i| {
+        let row = (0..self.rows).find(|v| *v as f32 == *i);
+        (0..self.dim).map(move |d| row.map_or(0.0, |v| table[v * self.dim + d]))
+      })
+      .collect();
+    vec![Tensor::new(out)]
+  }
+}
+
+/// Rows of the table at the indexes, see [Gather].
+pub fn gather<const ROWS: usize, const DIM: usize, const S: usize>(
+  table: GraphTensor<R2<ROWS, DIM>>,
+  indexes: GraphTensor<R1<S>>,
+) -> GraphTensor<R2<S, DIM>> {
+  let id = table
+    .graph()
+    .add_op(Gather {
+      rows: ROWS,
+      dim: DIM,
+    })
+    .input(indexes.id, 0, indexes.shape)
+    .input(table.id, 0, table.shape)
+    .finish();
+  GraphTensor::from_id(id, R2::<S, DIM>::to_tracker(), table.graph_ref)
+}
+
+#[cfg(test)]
+mod tests {
+  use luminal::{
+    graph::Graph,
+    shape::{R1, R2},
+  };
+
+  use super::gather;
+  use crate::scalar::{evaluate_tensor_graph, scalar, verify_scalarization};
+
+  #[test]
+  fn test_gather_rows() {
+    let mut cx = Graph::new();
+    let table = cx.tensor::<R2<4, 3>>();
+    let indexes = cx.tensor::<R1<3>>();
+    let rows = gather(table, indexes).retrieve();
+    let (sc, _) = scalar(&cx);
+    let inputs = [
+      (table.id, (0..12).map(|v| v as f32).collect()),
+      // the last one matches no row
+      (indexes.id, vec![2.0, 0.0, 7.0]),
+    ]
+    .into_iter()
+    .collect();
+    assert_eq!(
+      evaluate_tensor_graph(&cx, &inputs)[&rows.id],
+      vec![6.0, 7.0, 8.0, 0.0, 1.0, 2.0, 0.0, 0.0, 0.0]
+    );
+    assert_eq!(verify_scalarization(&cx, &sc, &inputs), Ok(()));
+  }
+}
@@ -0,0 +1,271 @@
+//!
+//! Graphviz rendering of the scalar graph, readable enough to map the little nodes back onto the model.
+//!
+
+use std::{
+  collections::{HashMap, HashSet, VecDeque},
+  error::Error,
+  fmt::Write as _,
+  fs::File,
+  io::Write,
+  path::Path,
+};
+
+use itertools::Itertools;
+use luminal::{
+  op::Function,
+  prelude::{
+    petgraph::{
+      visit::EdgeRef,
+      Direction::{Incoming, Outgoing},
+    },
+    NodeIndex,
+  },
+};
+
+use super::{try_scalar_op, ScalarGraph, ScalarOp};
+
+#[derive(Debug, Clone, Default)]
+pub struct GraphvizOptions {
+  /// Render only the nodes within this many edges (in either direction) of the given node.
+  /// Scalar graphs of real models have way too many nodes for graphviz to lay out.
+  pub neighborhood: Option<(NodeIndex, usize)>,
+  /// Group the little nodes coming from the same original tensor node (see [super::InputsTracker::origin]) into a cluster.
+  pub cluster: bool,
+}
+
+/// Where a little node comes from in the tensor graph.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Provenance {
+  /// The `index`th physical element of the input (or weight) tensor `node`
+  Input { node: NodeIndex, index: usize },
+  /// The `index`th physical element of the retrieved tensor `node`
+  Output { node: NodeIndex, index: usize },
+  /// Part of the computation of the `index`th physical element of the tensor `node`
+  Inner { node: NodeIndex, index: usize },
+}
+
+impl Provenance {
+  fn tensor(&self) -> NodeIndex {
+    match self {
+      Provenance::Input { node, .. }
+      | Provenance::Output { node, .. }
+      | Provenance::Inner { node, .. } => *node,
+    }
+  }
+}
+
+fn provenance(scalar: &ScalarGraph) -> HashMap<NodeIndex, Provenance> {
+  let mut m: HashMap<NodeIndex, Provenance> = scalar
+    .inputs_tracker
+    .origin
+    .iter()
+    .map(|(x, (node, index))| {
+      let p = Provenance::Inner {
+        node: *node,
+        index: *index,
+      };
+      (*x, p)
+    })
+    .collect();
+  for (node, little) in scalar.inputs_tracker.new_outputs.iter() {
+    for (index, x) in little.iter().enumerate() {
+      m.insert(*x, Provenance::Output { node: *node, index });
+    }
+  }
+  // an input retrieved directly is labeled as input
+  for (node, little) in scalar.inputs_tracker.new_inputs.iter() {
+    for (index, x) in little.iter().enumerate() {
+      m.insert(*x, Provenance::Input { node: *node, index });
+    }
+  }
+  m
+}
+
+/// Short name of the op in the node.
+fn op_label(scalar: &ScalarGraph, x: NodeIndex) -> String {
+  let graph = &scalar.graph;
+  match try_scalar_op(graph, &scalar.tables, x) {
+    Some(ScalarOp::Input) => "input".to_string(),
+    Some(ScalarOp::Constant(val)) => format!("{}", val),
+    Some(ScalarOp::Add) => "+".to_string(),
+    Some(ScalarOp::Sub) => "-".to_string(),
+    Some(ScalarOp::Neg) => "neg".to_string(),
+    Some(ScalarOp::Mul) => "*".to_string(),
+    Some(ScalarOp::Fma) => "*+".to_string(),
+    Some(ScalarOp::LessThan) => "<".to_string(),
+    Some(ScalarOp::SignedLessThan(bits)) => format!("<{}", bits),
+    Some(ScalarOp::LessThanConst(c)) => format!("< {}", c),
+    Some(ScalarOp::GreaterThanConst(c)) => format!("> {}", c),
+    Some(ScalarOp::RangeCheck(bits)) => format!("range {}", bits),
+    Some(ScalarOp::Max) => "max".to_string(),
+    Some(ScalarOp::Relu) => "relu".to_string(),
+    Some(ScalarOp::Recip) => "1/x".to_string(),
+    Some(ScalarOp::RecipHint) => "1/x hint".to_string(),
+    Some(ScalarOp::Mod) => "%".to_string(),
+    Some(ScalarOp::DivConst(divisor)) => format!("floor(x / {})", divisor),
+    Some(ScalarOp::ModConst(modulus)) => format!("x mod {}", modulus),
+    Some(ScalarOp::Lookup(kind)) => format!("lookup {}", kind.name()),
+    None if graph.check_node_type::<Function>(x) => graph.get_op::<Function>(x).0.clone(),
+    None => format!("{:?}", graph.node_weight(x).unwrap()),
+  }
+}
+
+fn node_label(scalar: &ScalarGraph, x: NodeIndex, prov: Option<&Provenance>) -> String {
+  let op = op_label(scalar, x);
+  let label = match prov {
+    Some(Provenance::Input { node, index }) => format!("{}\\n{}[{}]", op, node.index(), index),
+    Some(Provenance::Output { node, index }) => {
+      format!("{}\\nout {}[{}]", op, node.index(), index)
+    }
+    Some(Provenance::Inner { node, index }) => format!("{}\\nof {}[{}]", op, node.index(), index),
+    None => op,
+  };
+  format!("{}: {}", x.index(), label).replace('"', "\\\"")
+}
+
+fn neighborhood(scalar: &ScalarGraph, center: NodeIndex, radius: usize) -> HashSet<NodeIndex> {
+  let mut seen = HashSet::from([center]);
+  let mut queue = VecDeque::from([(center, 0)]);
+  while let Some((x, d)) = queue.pop_front() {
+    if d == radius {
+      continue;
+    }
+    let around = scalar
+      .graph
+      .neighbors_directed(x, Incoming)
+      .chain(scalar.graph.neighbors_directed(x, Outgoing));
+    for y in around.collect_vec() {
+      if seen.insert(y) {
+        queue.push_back((y, d + 1));
+      }
+    }
+  }
+  seen
+}
+
+/// Renders the scalar graph in the dot format.
+/// Nodes are labeled with their id, op (constants with the value) and the element of the original tensor they stand for (or are computed for).
+/// Edges are labeled with the position of the argument.
+pub fn render_graphviz(scalar: &ScalarGraph, opts: &GraphvizOptions) -> String {
+  let graph = &scalar.graph;
+  let nodes: Vec<NodeIndex> = match opts.neighborhood {
+    Some((center, radius)) => neighborhood(scalar, center, radius)
+      .into_iter()
+      .sorted()
+      .collect(),
+    None => graph.node_indices().sorted().collect(),
+  };
+  let shown: HashSet<NodeIndex> = nodes.iter().copied().collect();
+  let prov = provenance(scalar);
+
+  let mut out = String::new();
+  writeln!(out, "digraph {{").unwrap();
+  writeln!(out, "  node [shape=box];").unwrap();
+  let node_line = |x: NodeIndex| {
+    let p = prov.get(&x);
+    let style = match (opts.neighborhood, p) {
+      (Some((center, _)), _) if center == x => ", style=bold",
+      (_, Some(Provenance::Input { .. })) => ", shape=ellipse",
+      (_, Some(Provenance::Output { .. })) => ", shape=doubleoctagon",
+      _ => "",
+    };
+    format!(
+      "{} [label=\"{}\"{}];",
+      x.index(),
+      node_label(scalar, x, p),
+      style
+    )
+  };
+  if opts.cluster {
+    let clusters = nodes
+      .iter()
+      .filter_map(|x| prov.get(x).map(|p| (p.tensor(), *x)))
+      .into_group_map();
+    for (tensor, little) in clusters.into_iter().sorted_by_key(|(t, _)| *t) {
+      writeln!(out, "  subgraph cluster_{} {{", tensor.index()).unwrap();
+      writeln!(out, "    label=\"tensor {}\";", tensor.index()).unwrap();
+      for x in little {
+        writeln!(out, "    {}", node_line(x)).unwrap();
+      }
+      writeln!(out, "  }}").unwrap();
+    }
+    for x in nodes.iter().filter(|x| !prov.contains_key(x)) {
+      writeln!(out, "  {}", node_line(*x)).unwrap();
+    }
+  } else {
+    for x in nodes.iter() {
+      writeln!(out, "  {}", node_line(*x)).unwrap();
+    }
+  }
+  for e in graph
+    .edge_references()
+    .filter(|e| shown.contains(&e.source()) && shown.contains(&e.target()))
+    .sorted_by_key(|e| (e.target(), e.source()))
+  {
+    let label = e
+      .weight()
+      .as_data()
+      .map(|(inp, _, _)| format!(" [label=\"{}\"]", inp))
+      .unwrap_or_default();
+    writeln!(
+      out,
+      "  {} -> {}{};",
+      e.source().index(),
+      e.target().index(),
+      label
+    )
+    .unwrap();
+  }
+  writeln!(out, "}}").unwrap();
+  out
+}
+
+pub fn save_scalar_graphviz(
+  path: &Path,
+  scalar: &ScalarGraph,
+  opts: &GraphvizOptions,
+) -> Result<(), Box<dyn Error>> {
+  let mut file = File::create(path)?;
+  file.write_all(render_graphviz(scalar, opts).as_bytes())?;
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use luminal::{graph::Graph, shape::R1};
+
+  use super::{render_graphviz, GraphvizOptions};
+  use crate::scalar::scalar;
+
+  #[test]
+  fn test_render_clusters_and_neighborhood() {
+    let mut cx = Graph::new();
+    let a = cx.tensor::<R1<3>>();
+    let b = cx.tensor::<R1<3>>();
+    let _c = (a * b + a).retrieve();
+    let (sc, _) = scalar(&cx);
+
+    let full = render_graphviz(
+      &sc,
+      &GraphvizOptions {
+        cluster: true,
+        ..Default::default()
+      },
+    );
+    assert!(full.contains(&format!("subgraph cluster_{}", a.id.index())));
+    assert!(full.contains(&format!("subgraph cluster_{}", b.id.index())));
+    assert_eq!(full.matches(" -> ").count(), sc.graph.edge_count());
+
+    // an input element together with its product and sum
+    let x = sc.inputs_tracker.new_inputs[&b.id][0];
+    let part = render_graphviz(
+      &sc,
+      &GraphvizOptions {
+        neighborhood: Some((x, 1)),
+        ..Default::default()
+      },
+    );
+    assert_eq!(part.matches(" -> ").count(), 1);
+  }
+}
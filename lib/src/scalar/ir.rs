@@ -0,0 +1,374 @@
+//!
+//! The scalar graph as JSON, for tools outside of Rust (analysis scripts, other provers).
+//!
+//! [ScalarGraph::to_json] writes a [ScalarGraphIr], [ScalarGraph::from_json] reads it back. The node ids are the
+//! indices of the scalar graph, tensors are named by the indices of the nodes of the tensor graph. For example:
+//!
+//! ```json
+//! {
+//!   "version": 1,
+//!   "nodes": [
+//!     {"id": 0, "op": "Input"},
+//!     {"id": 1, "op": "Constant", "value": 2.0},
+//!     {"id": 2, "op": "Mul"}
+//!   ],
+//!   "edges": [{"from": 0, "to": 2, "input_order": 0}, {"from": 1, "to": 2, "input_order": 1}],
+//!   "inputs": [{"tensor": 0, "nodes": [0]}],
+//!   "outputs": [{"tensor": 2, "nodes": [2]}],
+//!   "origin": [{"node": 2, "tensor": 2, "index": 0}, ...],
+//!   "circuit_constants": [],
+//!   "argmax": [],
+//!   "tables": {"tables": []}
+//! }
+//! ```
+//!
+//!  - `nodes` are in a topological order, every node after its arguments. `op` is the [ScalarOp::name] of the op, but
+//!    `"Lookup"` for the lookups. `value` is the parameter of the op: the value of a `Constant`, the divisor of a
+//!    `DivConst`, the modulus of a `ModConst`, the bits of a `SignedLessThan` or a `RangeCheck`, the constant of a
+//!    `LessThanConst` or a `GreaterThanConst`. A `Lookup` has `table` instead, the index in `tables`, which has the
+//!    function and the domain of the table.
+//!  - `edges` go from an argument to the node using it, `input_order` is the position among the arguments.
+//!  - `inputs`, `outputs`, `argmax` and `origin` are the [InputsTracker]: the little nodes of every input and retrieved
+//!    tensor, in the order of its physical elements, and the tensor and element every node computes a part of.
+//!  - `circuit_constants` are the constants the circuit bakes in ([InputsTracker::constants]), the other constants are
+//!    its public inputs.
+//!
+//! The ops are evaluated as by [ScalarOp::eval].
+//!
+
+use std::{
+  collections::{HashMap, HashSet},
+  error::Error,
+};
+
+use itertools::Itertools;
+use luminal::prelude::NodeIndex;
+use serde::{Deserialize, Serialize};
+
+use super::{FrozenNode, FrozenScalarGraph, InputsTracker, ScalarGraph, ScalarOp, TableRegistry};
+
+/// Of the format of [ScalarGraphIr], changed on every incompatible change.
+pub const IR_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct IrNode {
+  pub id: usize,
+  pub op: String,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub value: Option<f32>,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub table: Option<usize>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IrEdge {
+  pub from: usize,
+  pub to: usize,
+  pub input_order: usize,
+}
+
+/// The little nodes of a tensor of the tensor graph.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IrTensor {
+  pub tensor: usize,
+  pub nodes: Vec<usize>,
+}
+
+/// The node computes a part of the physical element `index` of `tensor`, see [InputsTracker::origin].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IrOrigin {
+  pub node: usize,
+  pub tensor: usize,
+  pub index: usize,
+}
+
+/// The scalar graph in the JSON format, see the module docs.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ScalarGraphIr {
+  pub version: u32,
+  pub nodes: Vec<IrNode>,
+  pub edges: Vec<IrEdge>,
+  pub inputs: Vec<IrTensor>,
+  pub outputs: Vec<IrTensor>,
+  pub origin: Vec<IrOrigin>,
+  #[serde(default)]
+  pub circuit_constants: Vec<usize>,
+  #[serde(default)]
+  pub argmax: Vec<IrTensor>,
+  #[serde(default)]
+  pub tables: TableRegistry,
+}
+
+fn ir_tensors(packs: &HashMap<NodeIndex, Vec<NodeIndex>>) -> Vec<IrTensor> {
+  packs
+    .iter()
+    .sorted_by_key(|(x, _)| **x)
+    .map(|(x, little_ids)| IrTensor {
+      tensor: x.index(),
+      nodes: little_ids.iter().map(|y| y.index()).collect(),
+    })
+    .collect()
+}
+
+fn packs(tensors: &[IrTensor]) -> HashMap<NodeIndex, Vec<NodeIndex>> {
+  tensors
+    .iter()
+    .map(|t| {
+      let little_ids = t.nodes.iter().map(|y| NodeIndex::new(*y)).collect();
+      (NodeIndex::new(t.tensor), little_ids)
+    })
+    .collect()
+}
+
+impl From<&FrozenScalarGraph> for ScalarGraphIr {
+  fn from(frozen: &FrozenScalarGraph) -> Self {
+    let nodes = frozen
+      .nodes
+      .iter()
+      .map(|n| {
+        let (op, value, table) = match n.op {
+          ScalarOp::Constant(v)
+          | ScalarOp::DivConst(v)
+          | ScalarOp::ModConst(v)
+          | ScalarOp::LessThanConst(v)
+          | ScalarOp::GreaterThanConst(v) => (n.op.name(), Some(v), None),
+          ScalarOp::SignedLessThan(bits) | ScalarOp::RangeCheck(bits) => {
+            (n.op.name(), Some(bits as f32), None)
+          }
+          ScalarOp::Lookup(kind) => {
+            let table = frozen.tables.tables.iter().position(|t| t.kind == kind);
+            ("Lookup", None, table)
+          }
+          op => (op.name(), None, None),
+        };
+        IrNode {
+          id: n.id.index(),
+          op: op.to_string(),
+          value,
+          table,
+        }
+      })
+      .collect();
+    let edges = frozen
+      .nodes
+      .iter()
+      .flat_map(|n| {
+        n.args.iter().enumerate().map(|(input_order, y)| IrEdge {
+          from: y.index(),
+          to: n.id.index(),
+          input_order,
+        })
+      })
+      .collect();
+    let tracker = &frozen.inputs_tracker;
+    ScalarGraphIr {
+      version: IR_VERSION,
+      nodes,
+      edges,
+      inputs: ir_tensors(&tracker.new_inputs),
+      outputs: ir_tensors(&tracker.new_outputs),
+      origin: tracker
+        .origin
+        .iter()
+        .sorted_by_key(|(x, _)| **x)
+        .map(|(x, (tensor, index))| IrOrigin {
+          node: x.index(),
+          tensor: tensor.index(),
+          index: *index,
+        })
+        .collect(),
+      circuit_constants: tracker
+        .constants
+        .keys()
+        .map(|x| x.index())
+        .sorted()
+        .collect(),
+      argmax: ir_tensors(&tracker.argmax),
+      tables: frozen.tables.clone(),
+    }
+  }
+}
+
+impl ScalarGraphIr {
+  fn op(&self, node: &IrNode) -> Result<ScalarOp, Box<dyn Error>> {
+    let value = || {
+      node
+        .value
+        .ok_or_else(|| format!("Node {} ({}) without a value", node.id, node.op))
+    };
+    let op = match node.op.as_str() {
+      "Input" => ScalarOp::Input,
+      "Constant" => ScalarOp::Constant(value()?),
+      "Add" => ScalarOp::Add,
+      "Sub" => ScalarOp::Sub,
+      "Neg" => ScalarOp::Neg,
+      "Mul" => ScalarOp::Mul,
+      "Fma" => ScalarOp::Fma,
+      "LessThan" => ScalarOp::LessThan,
+      "SignedLessThan" => ScalarOp::SignedLessThan(value()? as u32),
+      "LessThanConst" => ScalarOp::LessThanConst(value()?),
+      "GreaterThanConst" => ScalarOp::GreaterThanConst(value()?),
+      "RangeCheck" => ScalarOp::RangeCheck(value()? as u32),
+      "Max" => ScalarOp::Max,
+      "Relu" => ScalarOp::Relu,
+      "Recip" => ScalarOp::Recip,
+      "RecipHint" => ScalarOp::RecipHint,
+      "Mod" => ScalarOp::Mod,
+      "DivConst" => ScalarOp::DivConst(value()?),
+      "ModConst" => ScalarOp::ModConst(value()?),
+      "Lookup" => {
+        let table = node
+          .table
+          .and_then(|t| self.tables.tables.get(t))
+          .ok_or_else(|| format!("Lookup node {} without a table", node.id))?;
+        ScalarOp::Lookup(table.kind)
+      }
+      op => return Err(format!("Unknown op of node {}: {}", node.id, op).into()),
+    };
+    Ok(op)
+  }
+
+  /// The graph, checking the format: the version, the ops, the arguments of every node given before it.
+  pub fn to_frozen(&self) -> Result<FrozenScalarGraph, Box<dyn Error>> {
+    if self.version != IR_VERSION {
+      return Err(
+        format!(
+          "Scalar graph IR of version {}, expected {}",
+          self.version, IR_VERSION
+        )
+        .into(),
+      );
+    }
+    let mut args: HashMap<usize, Vec<(usize, usize)>> = HashMap::new();
+    for e in self.edges.iter() {
+      args.entry(e.to).or_default().push((e.input_order, e.from));
+    }
+    let mut seen = HashSet::new();
+    let mut nodes = vec![];
+    for node in self.nodes.iter() {
+      let mut node_args = args.remove(&node.id).unwrap_or_default();
+      node_args.sort();
+      let mut ids = vec![];
+      for (k, (input_order, y)) in node_args.into_iter().enumerate() {
+        if input_order != k {
+          return Err(format!("Node {} has no argument {}", node.id, k).into());
+        }
+        if !seen.contains(&y) {
+          return Err(format!("Node {} before its argument {}", node.id, y).into());
+        }
+        ids.push(NodeIndex::new(y));
+      }
+      if !seen.insert(node.id) {
+        return Err(format!("Node {} given twice", node.id).into());
+      }
+      nodes.push(FrozenNode {
+        id: NodeIndex::new(node.id),
+        op: self.op(node)?,
+        args: ids,
+      });
+    }
+    if let Some(to) = args.keys().next() {
+      return Err(format!("Edge to a missing node {}", to).into());
+    }
+
+    let ops: HashMap<NodeIndex, ScalarOp> = nodes.iter().map(|n| (n.id, n.op)).collect();
+    let mut constants = HashMap::new();
+    for x in self.circuit_constants.iter() {
+      match ops.get(&NodeIndex::new(*x)) {
+        Some(ScalarOp::Constant(v)) => constants.insert(NodeIndex::new(*x), *v),
+        _ => return Err(format!("Circuit constant {} is not a Constant node", x).into()),
+      };
+    }
+    let inputs_tracker = InputsTracker {
+      new_inputs: packs(&self.inputs),
+      new_outputs: packs(&self.outputs),
+      origin: self
+        .origin
+        .iter()
+        .map(|o| {
+          let origin = (NodeIndex::new(o.tensor), o.index);
+          (NodeIndex::new(o.node), origin)
+        })
+        .collect(),
+      constants,
+      argmax: packs(&self.argmax),
+    };
+    for x in inputs_tracker
+      .new_inputs
+      .values()
+      .chain(inputs_tracker.new_outputs.values())
+      .chain(inputs_tracker.argmax.values())
+      .flatten()
+    {
+      if !ops.contains_key(x) {
+        return Err(format!("Tensor of a missing node {}", x.index()).into());
+      }
+    }
+    Ok(FrozenScalarGraph {
+      nodes,
+      inputs_tracker,
+      tables: self.tables.clone(),
+    })
+  }
+}
+
+impl ScalarGraph {
+  /// The graph in the JSON format of [super::ir].
+  pub fn to_json(&self) -> String {
+    serde_json::to_string(&ScalarGraphIr::from(&self.freeze_ops())).unwrap()
+  }
+
+  /// The graph of [ScalarGraph::to_json], with the nodes renumbered in the order of their ids (see
+  /// [FrozenScalarGraph::thaw]).
+  pub fn from_json(json: &str) -> Result<ScalarGraph, Box<dyn Error>> {
+    let ir: ScalarGraphIr = serde_json::from_str(json)?;
+    Ok(ir.to_frozen()?.thaw())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::collections::HashMap;
+
+  use luminal::{graph::Graph, prelude::NodeIndex, shape::R1};
+
+  use super::ScalarGraphIr;
+  use crate::scalar::{scalar, ScalarGraph};
+
+  #[test]
+  fn test_json_round_trip() {
+    let mut cx = Graph::new();
+    let a = cx.tensor::<R1<3>>();
+    let w = cx.tensor::<R1<3>>();
+    let b = ((a * w).relu().sigmoid() + 0.5).sum_reduce::<_, luminal::shape::Axis<0>>();
+    let b = b.retrieve();
+    let (sc, _) = scalar(&cx);
+
+    let json = sc.to_json();
+    let ir: ScalarGraphIr = serde_json::from_str(&json).unwrap();
+    assert!(ir
+      .nodes
+      .iter()
+      .any(|n| n.op == "Lookup" && n.table == Some(0)));
+    assert!(ir
+      .nodes
+      .iter()
+      .any(|n| n.op == "Constant" && n.value == Some(0.5)));
+    let back = ScalarGraph::from_json(&json).unwrap();
+    assert_eq!(back.structural_hash(), sc.structural_hash());
+
+    let inputs: HashMap<NodeIndex, Vec<f32>> =
+      HashMap::from([(a.id, vec![1.0, -2.0, 3.0]), (w.id, vec![0.5, 0.5, -1.0])]);
+    assert_eq!(
+      back.evaluate_outputs(&inputs)[&b.id],
+      sc.evaluate_outputs(&inputs)[&b.id]
+    );
+
+    let mut bad = ir.clone();
+    bad.nodes.reverse();
+    assert!(bad.to_frozen().is_err());
+    let mut bad = ir;
+    bad.version += 1;
+    assert!(bad.to_frozen().is_err());
+  }
+}
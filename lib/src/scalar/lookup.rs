@@ -0,0 +1,237 @@
+//!
+//! Lookup table nodes for the nonlinearities we can't express with field arithmetic.
+//!
+//! Exp2 is scalarized into a [LookupOp] on the exp2 table. Then the luminal expansions of sigmoid and tanh
+//! (`1 / (1 + exp(-x))` and `2 * sigmoid(2x) - 1`, where `exp(x) = exp2(x / ln 2)`) are recognized in the scalar graph
+//! and collapsed into a single lookup each. Lookup-capable backends (plonkish, halo2) implement those as one table lookup,
+//! instead of a polynomial approximation.
+//!
+//! Log2 and Sin, the other transcendental primops of luminal, are lookups as well. They come up in the gradients too,
+//! e.g. the derivative of `sin(x)` is `sin(x + pi/2)`.
+//!
+
+use std::f32::consts::{LN_2, PI};
+
+use itertools::Itertools;
+use luminal::{
+  op::{Add, InputTensor, Mul, Operator, Recip},
+  prelude::{NodeIndex, ShapeTracker, Tensor},
+};
+use serde::{Deserialize, Serialize};
+
+use super::{
+  rewrite::{approx, args, strip_mul_one, with_const},
+  ScalarGraph,
+};
+use crate::quant::QuantConfig;
+
+/// Applies the table `table_id` of the graph's [TableRegistry] to its single argument.
+#[derive(Debug, Default, Clone)]
+pub struct LookupOp {
+  pub table_id: usize,
+}
+
+impl Operator for LookupOp {
+  fn process(&mut self, _inp: Vec<(InputTensor, ShapeTracker)>) -> Vec<Tensor> {
+    panic!("LookupOp: We wont be evaluating it either way")
+  }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum LookupKind {
+  Exp2,
+  Log2,
+  Sin,
+  Sigmoid,
+  Tanh,
+}
+
+impl LookupKind {
+  pub fn eval(&self, x: f32) -> f32 {
+    match self {
+      LookupKind::Exp2 => x.exp2(),
+      LookupKind::Log2 => x.log2(),
+      LookupKind::Sin => x.sin(),
+      LookupKind::Sigmoid => 1.0 / (1.0 + (-x).exp()),
+      LookupKind::Tanh => x.tanh(),
+    }
+  }
+
+  pub fn name(&self) -> &'static str {
+    match self {
+      LookupKind::Exp2 => "exp2",
+      LookupKind::Log2 => "log2",
+      LookupKind::Sin => "sin",
+      LookupKind::Sigmoid => "sigmoid",
+      LookupKind::Tanh => "tanh",
+    }
+  }
+
+  /// Inputs outside of the domain are clamped by the backends.
+  /// Sigmoid and tanh are saturated there, exp2 overflows the quantized value range soon after. Log2 is defined for
+  /// positive inputs only, sin over a couple of periods, with room for the shift of its derivative.
+  pub fn default_domain(&self) -> (f32, f32) {
+    match self {
+      LookupKind::Exp2 => (-16.0, 16.0),
+      LookupKind::Log2 => (1.0 / 64.0, 64.0),
+      LookupKind::Sin => (-2.0 * PI, 2.0 * PI),
+      LookupKind::Sigmoid => (-8.0, 8.0),
+      LookupKind::Tanh => (-4.0, 4.0),
+    }
+  }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LookupTable {
+  pub kind: LookupKind,
+  pub domain: (f32, f32),
+}
+
+impl LookupTable {
+  /// The table as pairs of quantized (input, output), for every representable input in the domain.
+  pub fn quantized(&self, quant: &QuantConfig) -> Vec<(i64, i64)> {
+    let (lo, hi) = (quant.quantize(self.domain.0), quant.quantize(self.domain.1));
+    (lo..=hi)
+      .map(|n| (n, quant.quantize(self.kind.eval(quant.dequantize(n)))))
+      .collect()
+  }
+}
+
+/// The tables used by the [LookupOp]s of a scalar graph, `table_id` is the index in `tables`.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct TableRegistry {
+  pub tables: Vec<LookupTable>,
+}
+
+impl TableRegistry {
+  /// Id of the table of the given function, added with the default domain if not there yet.
+  pub fn register(&mut self, kind: LookupKind) -> usize {
+    match self.tables.iter().position(|t| t.kind == kind) {
+      Some(id) => id,
+      None => {
+        self.tables.push(LookupTable {
+          kind,
+          domain: kind.default_domain(),
+        });
+        self.tables.len() - 1
+      }
+    }
+  }
+
+  pub fn get(&self, table_id: usize) -> &LookupTable {
+    &self.tables[table_id]
+  }
+
+  /// The table of the function, there's at most one.
+  pub fn find(&self, kind: LookupKind) -> Option<&LookupTable> {
+    self.tables.iter().find(|t| t.kind == kind)
+  }
+}
+
+impl ScalarGraph {
+  fn lookup_arg(&self, x: NodeIndex, kind: LookupKind) -> Option<NodeIndex> {
+    if self.graph.check_node_type::<LookupOp>(x)
+      && self
+        .tables
+        .get(self.graph.get_op::<LookupOp>(x).table_id)
+        .kind
+        == kind
+    {
+      args(&self.graph, x).first().copied()
+    } else {
+      None
+    }
+  }
+
+  /// `recip(1 + exp2(x * -1/ln2))` => x
+  fn match_sigmoid(&self, r: NodeIndex) -> Option<NodeIndex> {
+    let g = &self.graph;
+    if !g.check_node_type::<Recip>(r) {
+      return None;
+    }
+    let (e, one) = with_const::<Add>(g, args(g, r)[0])?;
+    let m = self.lookup_arg(e, LookupKind::Exp2)?;
+    let (x, c) = with_const::<Mul>(g, m)?;
+    (approx(one, 1.0) && approx(c, -1.0 / LN_2)).then_some(x)
+  }
+
+  /// `2 * sigmoid(x * 2) - 1` => x, with the sigmoid already fused
+  fn match_tanh(&self, t: NodeIndex) -> Option<NodeIndex> {
+    let g = &self.graph;
+    let (u, minus_one) = with_const::<Add>(g, t)?;
+    let (s, two) = with_const::<Mul>(g, u)?;
+    let m = self.lookup_arg(strip_mul_one(g, s), LookupKind::Sigmoid)?;
+    let (x, two_in) = with_const::<Mul>(g, m)?;
+    (approx(minus_one, -1.0) && approx(two, 2.0) && approx(two_in, 2.0)).then_some(x)
+  }
+
+  fn fuse(&mut self, kind: LookupKind, matcher: fn(&Self, NodeIndex) -> Option<NodeIndex>) {
+    let found = self
+      .graph
+      .node_indices()
+      .sorted()
+      .filter_map(|x| matcher(self, x).map(|arg| (x, arg)))
+      .collect_vec();
+    if found.is_empty() {
+      return;
+    }
+    let table_id = self.tables.register(kind);
+    for (x, arg) in found {
+      self.replace_with_unop(x, LookupOp { table_id }, arg);
+    }
+  }
+
+  /// Collapses the scalarized sigmoids and tanhs into single lookups.
+  /// Leaves gaps in the node indices, see [ScalarGraph::canonicalize].
+  pub fn fuse_lookups(&mut self) {
+    self.fuse(LookupKind::Sigmoid, Self::match_sigmoid);
+    self.fuse(LookupKind::Tanh, Self::match_tanh);
+    self.remove_dead_nodes();
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use luminal::{graph::Graph, shape::R1};
+
+  use super::{LookupKind, LookupOp, LookupTable};
+  use crate::{
+    quant::QuantConfig,
+    scalar::{scalar, verify_scalarization},
+  };
+
+  #[test]
+  fn test_sigmoid_and_tanh_become_lookups() {
+    let mut cx = Graph::new();
+    let a = cx.tensor::<R1<4>>();
+    let _b = a.sigmoid().retrieve();
+    let _c = a.tanh().retrieve();
+    let (sc, _) = scalar(&cx);
+    let kinds = sc
+      .graph
+      .node_indices()
+      .filter(|x| sc.graph.check_node_type::<LookupOp>(*x))
+      .map(|x| sc.tables.get(sc.graph.get_op::<LookupOp>(x).table_id).kind)
+      .collect::<Vec<_>>();
+    assert_eq!(kinds.len(), 8);
+    assert!(kinds.iter().all(|k| *k != LookupKind::Exp2));
+    let inputs = [(a.id, vec![-3.0, -0.5, 0.0, 2.0])].into_iter().collect();
+    assert_eq!(verify_scalarization(&cx, &sc, &inputs), Ok(()));
+  }
+
+  #[test]
+  fn test_quantized_table() {
+    let quant = QuantConfig {
+      scale_bits: 2,
+      value_bits: 16,
+    };
+    let table = LookupTable {
+      kind: LookupKind::Exp2,
+      domain: (-1.0, 1.0),
+    };
+    let entries = table.quantized(&quant);
+    assert_eq!(entries.len(), 9);
+    assert_eq!(entries[0], (-4, 2));
+    assert_eq!(entries[8], (4, 8));
+  }
+}
@@ -0,0 +1,259 @@
+//!
+//! A read-only view of the ops of the scalar graph as one enum.
+//!
+//! The [ScalarGraph] still stores luminal's boxed operators: the scalar compiler is a luminal compiler, and the
+//! placeholder operators of this crate (e.g. [SignedLessThan], [RangeCheck], [RecipHint], [ReluOp], [SubOp]) panic
+//! when luminal tries to run them. Whatever builds or rewrites the graph (the compiler, the quantizer) still tests
+//! the node for operator types. Only what reads it (evaluation, range analysis, the backends and exports) matches on
+//! a [ScalarOp] instead: [ScalarGraph::scalar_op] converts from the boxed operator, [ScalarOp::operator] back.
+//! The [super::FrozenScalarGraph] keeps a copy of the graph in this form.
+//!
+//! Storing the scalar graph itself as a petgraph of [ScalarOp] is out of the scope of this enum: the lowering works on
+//! the tensor graph and its shapes in place, node by node, so it would need a port of the compiler and of every rewrite
+//! off luminal's Graph. Until then the graph of plain [ScalarOp]s is the frozen one, made once the rewrites are done.
+//!
+
+use luminal::{
+  graph::Graph,
+  op::{Add, LessThan, Mod, Mul, Operator, Recip},
+  prelude::NodeIndex,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::quant::{RangeCheck, RecipHint, SignedLessThan};
+
+use super::{
+  ConstantOp, DivConstOp, FmaOp, GreaterThanConstOp, InputOp, LessThanConstOp, LookupKind,
+  LookupOp, Max, ModConstOp, NegOp, ReluOp, ScalarGraph, SubOp, TableRegistry,
+};
+
+/// The op of a scalar node as plain data, e.g. to evaluate it on other threads (see [super::FrozenScalarGraph]).
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum ScalarOp {
+  /// Takes the value from the inputs.
+  Input,
+  Constant(f32),
+  Add,
+  /// The first argument minus the second.
+  Sub,
+  Neg,
+  Mul,
+  /// `a * b + c` of the arguments `(a, b, c)`.
+  Fma,
+  /// 1 or 0.
+  LessThan,
+  /// [SignedLessThan] of a quantized graph, with its bits.
+  SignedLessThan(u32),
+  /// `x < c`, see [super::LessThanConstOp].
+  LessThanConst(f32),
+  /// `c < x`, see [super::GreaterThanConstOp].
+  GreaterThanConst(f32),
+  /// [RangeCheck] of a quantized graph, with its bits.
+  RangeCheck(u32),
+  Max,
+  Relu,
+  Recip,
+  /// [Recip] that is 0 for 0.
+  RecipHint,
+  Mod,
+  DivConst(f32),
+  ModConst(f32),
+  Lookup(LookupKind),
+}
+
+impl ScalarOp {
+  /// The op without its parameters, e.g. for reports.
+  pub fn name(&self) -> &'static str {
+    match self {
+      ScalarOp::Input => "Input",
+      ScalarOp::Constant(_) => "Constant",
+      ScalarOp::Add => "Add",
+      ScalarOp::Sub => "Sub",
+      ScalarOp::Neg => "Neg",
+      ScalarOp::Mul => "Mul",
+      ScalarOp::Fma => "Fma",
+      ScalarOp::LessThan => "LessThan",
+      ScalarOp::SignedLessThan(_) => "SignedLessThan",
+      ScalarOp::LessThanConst(_) => "LessThanConst",
+      ScalarOp::GreaterThanConst(_) => "GreaterThanConst",
+      ScalarOp::RangeCheck(_) => "RangeCheck",
+      ScalarOp::Max => "Max",
+      ScalarOp::Relu => "Relu",
+      ScalarOp::Recip => "Recip",
+      ScalarOp::RecipHint => "RecipHint",
+      ScalarOp::Mod => "Mod",
+      ScalarOp::DivConst(_) => "DivConst",
+      ScalarOp::ModConst(_) => "ModConst",
+      ScalarOp::Lookup(kind) => kind.name(),
+    }
+  }
+
+  /// The value on the arguments, in argument order. Panics for inputs.
+  pub fn eval(&self, args: &[f32]) -> f32 {
+    match self {
+      ScalarOp::Input => panic!("Inputs take their values from the outside"),
+      ScalarOp::Constant(val) => *val,
+      ScalarOp::Add => args[0] + args[1],
+      ScalarOp::Sub => args[0] - args[1],
+      ScalarOp::Neg => -args[0],
+      ScalarOp::Mul => args[0] * args[1],
+      ScalarOp::Fma => args[0] * args[1] + args[2],
+      ScalarOp::LessThan | ScalarOp::SignedLessThan(_) => {
+        if args[0] < args[1] {
+          1.0
+        } else {
+          0.0
+        }
+      }
+      ScalarOp::LessThanConst(c) => {
+        if args[0] < c {
+          1.0
+        } else {
+          0.0
+        }
+      }
+      ScalarOp::GreaterThanConst(c) => {
+        if c < args[0] {
+          1.0
+        } else {
+          0.0
+        }
+      }
+      ScalarOp::RangeCheck(_) => args[0],
+      ScalarOp::Max => f32::max(args[0], args[1]),
+      ScalarOp::Relu => f32::max(args[0], 0.0),
+      ScalarOp::Recip => 1.0 / args[0],
+      ScalarOp::RecipHint => {
+        if args[0] == 0.0 {
+          0.0
+        } else {
+          1.0 / args[0]
+        }
+      }
+      ScalarOp::Mod => args[0] % args[1],
+      ScalarOp::DivConst(divisor) => (args[0] / divisor).floor(),
+      ScalarOp::ModConst(m) => args[0] - m * (args[0] / m).floor(),
+      ScalarOp::Lookup(kind) => kind.eval(args[0]),
+    }
+  }
+
+  /// The boxed operator of the op in a scalar graph with these tables, a lookup registers its table.
+  pub fn operator(&self, tables: &mut TableRegistry) -> Box<dyn Operator> {
+    match *self {
+      ScalarOp::Input => Box::new(InputOp {}),
+      ScalarOp::Constant(val) => Box::new(ConstantOp { val }),
+      ScalarOp::Add => Box::new(Add {}),
+      ScalarOp::Sub => Box::new(SubOp {}),
+      ScalarOp::Neg => Box::new(NegOp {}),
+      ScalarOp::Mul => Box::new(Mul {}),
+      ScalarOp::Fma => Box::new(FmaOp {}),
+      ScalarOp::LessThan => Box::new(LessThan {}),
+      ScalarOp::SignedLessThan(bits) => Box::new(SignedLessThan { bits }),
+      ScalarOp::LessThanConst(c) => Box::new(LessThanConstOp { c }),
+      ScalarOp::GreaterThanConst(c) => Box::new(GreaterThanConstOp { c }),
+      ScalarOp::RangeCheck(bits) => Box::new(RangeCheck { bits }),
+      ScalarOp::Max => Box::new(Max {}),
+      ScalarOp::Relu => Box::new(ReluOp {}),
+      ScalarOp::Recip => Box::new(Recip {}),
+      ScalarOp::RecipHint => Box::new(RecipHint {}),
+      ScalarOp::Mod => Box::new(Mod {}),
+      ScalarOp::DivConst(divisor) => Box::new(DivConstOp { divisor }),
+      ScalarOp::ModConst(modulus) => Box::new(ModConstOp { modulus }),
+      ScalarOp::Lookup(kind) => Box::new(LookupOp {
+        table_id: tables.register(kind),
+      }),
+    }
+  }
+}
+
+/// The op of the node of a scalar graph with these tables, `None` for an operator of no [ScalarOp].
+pub fn try_scalar_op(graph: &Graph, tables: &TableRegistry, x: NodeIndex) -> Option<ScalarOp> {
+  let op = if graph.check_node_type::<ConstantOp>(x) {
+    ScalarOp::Constant(graph.get_op::<ConstantOp>(x).val)
+  } else if graph.check_node_type::<InputOp>(x) {
+    ScalarOp::Input
+  } else if graph.check_node_type::<Add>(x) {
+    ScalarOp::Add
+  } else if graph.check_node_type::<SubOp>(x) {
+    ScalarOp::Sub
+  } else if graph.check_node_type::<NegOp>(x) {
+    ScalarOp::Neg
+  } else if graph.check_node_type::<Mul>(x) {
+    ScalarOp::Mul
+  } else if graph.check_node_type::<FmaOp>(x) {
+    ScalarOp::Fma
+  } else if graph.check_node_type::<LessThan>(x) {
+    ScalarOp::LessThan
+  } else if graph.check_node_type::<SignedLessThan>(x) {
+    ScalarOp::SignedLessThan(graph.get_op::<SignedLessThan>(x).bits)
+  } else if graph.check_node_type::<LessThanConstOp>(x) {
+    ScalarOp::LessThanConst(graph.get_op::<LessThanConstOp>(x).c)
+  } else if graph.check_node_type::<GreaterThanConstOp>(x) {
+    ScalarOp::GreaterThanConst(graph.get_op::<GreaterThanConstOp>(x).c)
+  } else if graph.check_node_type::<RangeCheck>(x) {
+    ScalarOp::RangeCheck(graph.get_op::<RangeCheck>(x).bits)
+  } else if graph.check_node_type::<Max>(x) {
+    ScalarOp::Max
+  } else if graph.check_node_type::<ReluOp>(x) {
+    ScalarOp::Relu
+  } else if graph.check_node_type::<Recip>(x) {
+    ScalarOp::Recip
+  } else if graph.check_node_type::<RecipHint>(x) {
+    ScalarOp::RecipHint
+  } else if graph.check_node_type::<Mod>(x) {
+    ScalarOp::Mod
+  } else if graph.check_node_type::<DivConstOp>(x) {
+    ScalarOp::DivConst(graph.get_op::<DivConstOp>(x).divisor)
+  } else if graph.check_node_type::<ModConstOp>(x) {
+    ScalarOp::ModConst(graph.get_op::<ModConstOp>(x).modulus)
+  } else if graph.check_node_type::<LookupOp>(x) {
+    ScalarOp::Lookup(tables.get(graph.get_op::<LookupOp>(x).table_id).kind)
+  } else {
+    return None;
+  };
+  Some(op)
+}
+
+impl ScalarGraph {
+  /// The op of the node. Panics for an operator of no [ScalarOp].
+  pub fn scalar_op(&self, x: NodeIndex) -> ScalarOp {
+    try_scalar_op(&self.graph, &self.tables, x).unwrap_or_else(|| {
+      panic!(
+        "Unknown scalar op: {:?}",
+        self.graph.node_weight(x).unwrap().type_name()
+      )
+    })
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use luminal::{graph::Graph, shape::R2};
+
+  use super::ScalarOp;
+  use crate::scalar::{scalar, LookupKind, TableRegistry};
+
+  #[test]
+  fn test_scalar_op_round_trip() {
+    let mut cx = Graph::new();
+    let a = cx.tensor::<R2<2, 3>>();
+    let _ = (a.relu() * 2.0).less_than(a).retrieve();
+    let (sc, _) = scalar(&cx);
+    for x in sc.graph.node_indices() {
+      let op = sc.scalar_op(x);
+      let mut tables = sc.tables.clone();
+      let mut g = Graph::new();
+      let y = g.graph.add_node(op.operator(&mut tables));
+      assert_eq!(super::try_scalar_op(&g, &tables, y), Some(op));
+    }
+
+    let mut tables = TableRegistry::default();
+    let op = ScalarOp::Lookup(LookupKind::Sigmoid);
+    let mut g = Graph::new();
+    let y = g.graph.add_node(op.operator(&mut tables));
+    assert_eq!(tables.tables.len(), 1);
+    assert_eq!(super::try_scalar_op(&g, &tables, y), Some(op));
+    let json = serde_json::to_string(&op).unwrap();
+    assert_eq!(serde_json::from_str::<ScalarOp>(&json).unwrap(), op);
+  }
+}
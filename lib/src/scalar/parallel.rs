@@ -0,0 +1,276 @@
+//!
+//! Evaluation of the scalar graph on many threads, for the witnesses of big circuits.
+//!
+//! A node's level is one past the highest level of its arguments, so the nodes of a level depend only on earlier levels
+//! and are evaluated in parallel. Within a level the threads take blocks of nodes from a shared counter:
+//! a thread done early takes the next block, nobody waits on a fixed share (work stealing without the queues).
+//!
+//! The graph can't be shared between threads (the ops are boxed trait objects), so it's compiled once into a
+//! [WitnessProgram] of plain [ScalarOp]s, from its [FrozenScalarGraph]. Keep the program to compute the witnesses of many inputs.
+//! The values go to a [WitnessStore]: a vector, or a memory-mapped file ([MmapWitness]) for witnesses bigger than memory.
+//!
+
+use std::{
+  collections::HashMap,
+  fs::OpenOptions,
+  io,
+  path::Path,
+  sync::atomic::{AtomicUsize, Ordering},
+  thread,
+};
+
+use itertools::Itertools;
+use luminal::prelude::NodeIndex;
+use memmap2::MmapMut;
+
+use super::{FrozenScalarGraph, ScalarGraph, ScalarOp};
+
+/// Values of the nodes, by slot: the index of the node.
+pub trait WitnessStore: Sync {
+  fn get(&self, slot: usize) -> f32;
+  fn set(&mut self, slot: usize, v: f32);
+}
+
+impl WitnessStore for Vec<f32> {
+  fn get(&self, slot: usize) -> f32 {
+    self[slot]
+  }
+
+  fn set(&mut self, slot: usize, v: f32) {
+    self[slot] = v;
+  }
+}
+
+/// Values in a file mapped to memory, 4 little-endian bytes per slot. The OS pages them in and out as needed.
+pub struct MmapWitness {
+  map: MmapMut,
+}
+
+impl MmapWitness {
+  /// Creates (or truncates) the file at `path` with room for `slots` values.
+  pub fn create(path: &Path, slots: usize) -> io::Result<Self> {
+    let file = OpenOptions::new()
+      .read(true)
+      .write(true)
+      .create(true)
+      .truncate(true)
+      .open(path)?;
+    file.set_len(4 * slots as u64)?;
+    // the file is ours for the lifetime of the map, nobody else resizes it
+    let map = unsafe { MmapMut::map_mut(&file)? };
+    Ok(MmapWitness { map })
+  }
+
+  pub fn flush(&self) -> io::Result<()> {
+    self.map.flush()
+  }
+}
+
+impl WitnessStore for MmapWitness {
+  fn get(&self, slot: usize) -> f32 {
+    f32::from_le_bytes(self.map[4 * slot..4 * slot + 4].try_into().unwrap())
+  }
+
+  fn set(&mut self, slot: usize, v: f32) {
+    self.map[4 * slot..4 * slot + 4].copy_from_slice(&v.to_le_bytes());
+  }
+}
+
+/// A node to evaluate: its slot, op and the slots of the arguments in argument order.
+#[derive(Debug, Clone)]
+struct Step {
+  slot: usize,
+  op: ScalarOp,
+  args: Vec<usize>,
+}
+
+/// The scalar graph compiled for [WitnessProgram::evaluate], see the module docs.
+#[derive(Debug, Clone)]
+pub struct WitnessProgram {
+  /// The nodes of every level but the inputs, in order.
+  levels: Vec<Vec<Step>>,
+  /// The slots of the little nodes of every input of the original graph, as [super::InputsTracker::new_inputs].
+  inputs: Vec<(NodeIndex, Vec<usize>)>,
+  nodes: Vec<NodeIndex>,
+  /// Nodes a thread takes at once. Levels up to this size are evaluated on the calling thread.
+  pub block_size: usize,
+}
+
+impl FrozenScalarGraph {
+  pub fn witness_program(&self) -> WitnessProgram {
+    let mut level: HashMap<NodeIndex, usize> = HashMap::new();
+    let mut levels: Vec<Vec<Step>> = vec![];
+    for node in self.nodes.iter() {
+      let l = node.args.iter().map(|y| level[y] + 1).max().unwrap_or(0);
+      level.insert(node.id, l);
+      if node.op == ScalarOp::Input {
+        continue;
+      }
+      if levels.len() <= l {
+        levels.resize(l + 1, vec![]);
+      }
+      levels[l].push(Step {
+        slot: node.id.index(),
+        op: node.op,
+        args: node.args.iter().map(|y| y.index()).collect(),
+      });
+    }
+    WitnessProgram {
+      levels,
+      inputs: self
+        .inputs_tracker
+        .new_inputs
+        .iter()
+        .map(|(x, little)| (*x, little.iter().map(|y| y.index()).collect()))
+        .sorted()
+        .collect(),
+      nodes: self.nodes.iter().map(|n| n.id).sorted().collect(),
+      block_size: 1024,
+    }
+  }
+}
+
+impl ScalarGraph {
+  pub fn witness_program(&self) -> WitnessProgram {
+    self.freeze_ops().witness_program()
+  }
+
+  /// [ScalarGraph::evaluate] on `threads` threads, see [WitnessProgram].
+  pub fn evaluate_parallel(
+    &self,
+    inputs: &HashMap<NodeIndex, Vec<f32>>,
+    threads: usize,
+  ) -> HashMap<NodeIndex, f32> {
+    self.witness_program().evaluate(inputs, threads)
+  }
+}
+
+impl WitnessProgram {
+  /// Size of the [WitnessStore] the program needs.
+  pub fn slots(&self) -> usize {
+    self.nodes.last().map_or(0, |x| x.index() + 1)
+  }
+
+  pub fn depth(&self) -> usize {
+    self.levels.len()
+  }
+
+  /// Values of all the nodes, as [ScalarGraph::evaluate].
+  pub fn evaluate(
+    &self,
+    inputs: &HashMap<NodeIndex, Vec<f32>>,
+    threads: usize,
+  ) -> HashMap<NodeIndex, f32> {
+    let mut store = vec![0.0; self.slots()];
+    self.evaluate_with(inputs, threads, &mut store);
+    self.nodes.iter().map(|x| (*x, store[x.index()])).collect()
+  }
+
+  /// Evaluates into the store, which needs [WitnessProgram::slots] slots.
+  /// Inputs are given per original tensor graph input, as for [ScalarGraph::evaluate].
+  pub fn evaluate_with<S: WitnessStore>(
+    &self,
+    inputs: &HashMap<NodeIndex, Vec<f32>>,
+    threads: usize,
+    store: &mut S,
+  ) {
+    assert!(threads > 0, "Evaluation needs at least one thread");
+    for (x, little) in self.inputs.iter() {
+      let data = inputs
+        .get(x)
+        .unwrap_or_else(|| panic!("Missing input for {:?}", x));
+      assert!(
+        data.len() == little.len(),
+        "Input {:?} expects {} values, got {}",
+        x,
+        little.len(),
+        data.len()
+      );
+      for (slot, v) in little.iter().zip(data) {
+        store.set(*slot, *v);
+      }
+    }
+
+    let eval = |step: &Step, store: &S| {
+      let args: Vec<f32> = step.args.iter().map(|y| store.get(*y)).collect();
+      (step.slot, step.op.eval(&args))
+    };
+    for level in self.levels.iter() {
+      let results: Vec<(usize, f32)> = if threads == 1 || level.len() <= self.block_size {
+        level.iter().map(|step| eval(step, store)).collect()
+      } else {
+        let counter = AtomicUsize::new(0);
+        let (next, reader): (&AtomicUsize, &S) = (&counter, &*store);
+        thread::scope(|s| {
+          let workers = (0..threads)
+            .map(|_| {
+              s.spawn(move || {
+                let mut results = vec![];
+                loop {
+                  let begin = next.fetch_add(self.block_size, Ordering::Relaxed);
+                  if begin >= level.len() {
+                    break results;
+                  }
+                  let end = (begin + self.block_size).min(level.len());
+                  results.extend(level[begin..end].iter().map(|step| eval(step, reader)));
+                }
+              })
+            })
+            .collect_vec();
+          workers
+            .into_iter()
+            .flat_map(|w| w.join().unwrap())
+            .collect()
+        })
+      };
+      for (slot, v) in results {
+        store.set(slot, v);
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::collections::HashMap;
+
+  use luminal::{graph::Graph, shape::R2};
+  use rand::{rngs::StdRng, SeedableRng};
+
+  use super::{MmapWitness, WitnessStore};
+  use crate::scalar::{random_inputs, scalar};
+
+  #[test]
+  fn test_parallel_evaluation_matches() {
+    let mut cx = Graph::new();
+    let a = cx.tensor::<R2<4, 8>>();
+    let b = cx.tensor::<R2<8, 3>>();
+    let c = a.matmul(b).relu().retrieve();
+    let (sc, _) = scalar(&cx);
+    let inputs = random_inputs(&cx, &mut StdRng::seed_from_u64(0));
+    let expected = sc.evaluate(&inputs);
+
+    let mut program = sc.witness_program();
+    assert!(program.depth() > 1);
+    // small blocks, so that the levels are split between the threads
+    program.block_size = 2;
+    for threads in [1, 4] {
+      assert_eq!(program.evaluate(&inputs, threads), expected);
+    }
+
+    let path = std::env::temp_dir().join(format!("zkml-witness-{}", std::process::id()));
+    let mut store = MmapWitness::create(&path, program.slots()).unwrap();
+    program.evaluate_with(&inputs, 3, &mut store);
+    store.flush().unwrap();
+    let outputs: HashMap<_, Vec<f32>> = sc
+      .inputs_tracker
+      .new_outputs
+      .iter()
+      .map(|(x, little)| (*x, little.iter().map(|y| store.get(y.index())).collect()))
+      .collect();
+    assert_eq!(outputs, sc.evaluate_outputs(&inputs));
+    assert_eq!(outputs[&c.id].len(), 12);
+    drop(store);
+    std::fs::remove_file(path).unwrap();
+  }
+}
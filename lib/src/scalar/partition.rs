@@ -0,0 +1,221 @@
+//!
+//! Cutting the scalar graph into sequential chunks, to prove very large models chunk by chunk.
+//!
+//! Every chunk is a standalone [ScalarGraph]. Values a chunk takes from earlier chunks become its inputs
+//! and values later chunks (or the model output) need become its outputs. Composing the chunk proofs (by recursion
+//! or commitments to the boundary values) has to check that these are the same values, the wiring is in
+//! [ScalarChunk::boundary_inputs] and [ScalarChunk::boundary_outputs].
+//!
+
+use std::collections::{HashMap, HashSet};
+
+use itertools::Itertools;
+use luminal::{
+  graph::Graph,
+  prelude::{
+    petgraph::{
+      self,
+      visit::EdgeRef,
+      Direction::{Incoming, Outgoing},
+    },
+    NodeIndex,
+  },
+  shape::{Shape, R0},
+};
+
+use super::{copy_op, ConstantOp, InputOp, InputsTracker, ScalarGraph};
+
+#[derive(Debug)]
+pub struct ScalarChunk {
+  /// The chunk as a scalar graph of its own.
+  /// Its [InputsTracker] is keyed by the node ids in the whole graph, with a pack of one little node per key:
+  /// the original inputs the chunk uses, the boundary inputs and the boundary outputs.
+  /// Constants are copied into every chunk using them.
+  pub scalar: ScalarGraph,
+  /// Original inputs (InputOp nodes of the whole graph) used by the chunk.
+  pub inputs: Vec<NodeIndex>,
+  /// Values computed by earlier chunks: (index of the chunk computing it, node in the whole graph).
+  pub boundary_inputs: Vec<(usize, NodeIndex)>,
+  /// Nodes (of the whole graph) computed here and used by later chunks or being outputs of the whole graph.
+  pub boundary_outputs: Vec<NodeIndex>,
+}
+
+impl ScalarChunk {
+  /// The node of the chunk standing for the node `x` of the whole graph (for inputs and boundary nodes).
+  pub fn local(&self, x: NodeIndex) -> Option<NodeIndex> {
+    let tracker = &self.scalar.inputs_tracker;
+    tracker
+      .new_inputs
+      .get(&x)
+      .or_else(|| tracker.new_outputs.get(&x))
+      .map(|v| v[0])
+  }
+}
+
+impl ScalarGraph {
+  /// Cuts the graph into chunks of at most `max_nodes` computing nodes each (sources are not counted),
+  /// such that a chunk only depends on the chunks before it.
+  pub fn partition(&self, max_nodes: usize) -> Vec<ScalarChunk> {
+    assert!(max_nodes > 0, "Chunks need to have at least one node");
+    let graph = &self.graph;
+    let is_source = |x: &NodeIndex| graph.edges_directed(*x, Incoming).next().is_none();
+    let order = petgraph::algo::toposort(&graph.graph, None)
+      .unwrap()
+      .into_iter()
+      .filter(|x| !is_source(x))
+      .collect_vec();
+    let parts: Vec<Vec<NodeIndex>> = order.chunks(max_nodes).map(|c| c.to_vec()).collect();
+    let chunk_of: HashMap<NodeIndex, usize> = parts
+      .iter()
+      .enumerate()
+      .flat_map(|(i, p)| p.iter().map(move |x| (*x, i)))
+      .collect();
+    let model_outputs: HashSet<NodeIndex> =
+      self.inputs_tracker.ordered_outputs().into_iter().collect();
+
+    parts
+      .iter()
+      .enumerate()
+      .map(|(i, part)| {
+        let mut g = Graph::new();
+        let mut local: HashMap<NodeIndex, NodeIndex> = HashMap::new();
+        let mut tracker = InputsTracker::default();
+        let mut inputs = vec![];
+        let mut boundary_inputs = vec![];
+        for x in part {
+          let args = graph
+            .edges_directed(*x, Incoming)
+            .map(|e| e.source())
+            .sorted()
+            .dedup()
+            .collect_vec();
+          for y in args {
+            if local.contains_key(&y) {
+              continue;
+            }
+            let new = if graph.check_node_type::<ConstantOp>(y) {
+              copy_op(graph, y, &mut g)
+            } else {
+              let new = g.add_op(InputOp {}).finish();
+              tracker.new_inputs.insert(y, vec![new]);
+              if graph.check_node_type::<InputOp>(y) {
+                inputs.push(y);
+              } else {
+                boundary_inputs.push((chunk_of[&y], y));
+              }
+              new
+            };
+            local.insert(y, new);
+          }
+          local.insert(*x, copy_op(graph, *x, &mut g));
+        }
+        for x in part {
+          for e in graph.edges_directed(*x, Incoming) {
+            g.add_edge(local[&e.source()], local[x], e.weight().clone());
+          }
+        }
+        let boundary_outputs = part
+          .iter()
+          .copied()
+          .filter(|x| {
+            model_outputs.contains(x)
+              || graph
+                .neighbors_directed(*x, Outgoing)
+                .any(|y| chunk_of[&y] != i)
+          })
+          .collect_vec();
+        for x in boundary_outputs.iter() {
+          tracker.new_outputs.insert(*x, vec![local[x]]);
+          g.to_retrieve.insert(local[x], (0, R0::to_tracker()));
+        }
+        tracker.origin = local
+          .iter()
+          .filter_map(|(x, l)| self.inputs_tracker.origin.get(x).map(|o| (*l, *o)))
+          .collect();
+        tracker.constants = local
+          .iter()
+          .filter_map(|(x, l)| self.inputs_tracker.constants.get(x).map(|v| (*l, *v)))
+          .collect();
+        ScalarChunk {
+          scalar: ScalarGraph {
+            graph: g,
+            inputs_tracker: tracker,
+            tables: self.tables.clone(),
+          },
+          inputs,
+          boundary_inputs,
+          boundary_outputs,
+        }
+      })
+      .collect()
+  }
+}
+
+/// Evaluates the chunks one after another, feeding the boundary outputs into the later chunks.
+/// Inputs are per input node of the whole graph. Returns the values of all the boundary outputs.
+pub fn evaluate_chunks(
+  chunks: &[ScalarChunk],
+  inputs: &HashMap<NodeIndex, f32>,
+) -> HashMap<NodeIndex, f32> {
+  let mut boundary: HashMap<NodeIndex, f32> = HashMap::new();
+  for chunk in chunks {
+    let chunk_inputs = chunk
+      .inputs
+      .iter()
+      .map(|x| (*x, vec![inputs[x]]))
+      .chain(
+        chunk
+          .boundary_inputs
+          .iter()
+          .map(|(_, x)| (*x, vec![boundary[x]])),
+      )
+      .collect();
+    let values = chunk.scalar.evaluate(&chunk_inputs);
+    for x in chunk.boundary_outputs.iter() {
+      boundary.insert(*x, values[&chunk.local(*x).unwrap()]);
+    }
+  }
+  boundary
+}
+
+#[cfg(test)]
+mod tests {
+  use std::collections::HashMap;
+
+  use luminal::{graph::Graph, shape::R2};
+  use rand::{rngs::StdRng, SeedableRng};
+
+  use super::evaluate_chunks;
+  use crate::scalar::{random_inputs, scalar};
+
+  #[test]
+  fn test_chunks_compose_to_the_whole() {
+    let mut cx = Graph::new();
+    let a = cx.tensor::<R2<2, 3>>();
+    let b = cx.tensor::<R2<3, 2>>();
+    let _c = a.matmul(b).relu().retrieve();
+    let (sc, _) = scalar(&cx);
+    let inputs = random_inputs(&cx, &mut StdRng::seed_from_u64(0));
+    let whole = sc.evaluate(&inputs);
+
+    let chunks = sc.partition(5);
+    assert!(chunks.len() > 1);
+    for (i, chunk) in chunks.iter().enumerate() {
+      assert!(chunk
+        .boundary_inputs
+        .iter()
+        .all(|(j, x)| *j < i && chunks[*j].boundary_outputs.contains(x)));
+    }
+    let little_inputs: HashMap<_, _> = sc
+      .inputs_tracker
+      .new_inputs
+      .iter()
+      .flat_map(|(x, little)| little.iter().zip(inputs[x].iter()))
+      .map(|(l, v)| (*l, *v))
+      .collect();
+    let boundary = evaluate_chunks(&chunks, &little_inputs);
+    for y in sc.inputs_tracker.ordered_outputs() {
+      assert_eq!(boundary[&y], whole[&y]);
+    }
+  }
+}
@@ -0,0 +1,317 @@
+//!
+//! What the scalarization of a graph would make, without making it.
+//!
+//! [Scalarize::plan] runs the size computations of the scalar compiler and counts the scalar nodes every lowering
+//! would add, on a copy of the graph with the reductions merged as the compiler merges them. No scalar node is made,
+//! so it's cheap next to [super::scalar] and leaves the graph as it is: for estimates of the circuit, and to check that
+//! a graph scalarizes at all (see [check_supported]) before compiling it.
+//!
+//! The counts are of the graph straight out of the compiler, before the rewrites of [super::scalar_with]
+//! (fused relus and lookups, folded constants) shrink it.
+//!
+
+use std::collections::HashMap;
+
+use itertools::Itertools;
+use luminal::{
+  graph::Graph,
+  op::{Add, Constant, Contiguous, Function, LessThan, Mod, Mul, Recip, Sqrt},
+  prelude::{
+    petgraph::{
+      self,
+      graph::EdgeIndex,
+      visit::EdgeRef,
+      Direction::{Incoming, Outgoing},
+    },
+    NodeIndex,
+  },
+};
+
+use super::{
+  check_supported, copy_graph_roughly, get_own_size, lookup_kind, merge_reductions, reduction_axis,
+  remove_dead_tensors, remove_noop_contiguous, Gather, IndexCache, Pool2D, PoolKind,
+  ReductionStyle, Scalarize, UnsupportedOp,
+};
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ScalarizationPlan {
+  /// Little nodes of every node of the graph, the physical size of its result.
+  /// Reductions merged into the reduction reading them (see [ReductionStyle]) are left out.
+  pub sizes: HashMap<NodeIndex, usize>,
+  /// Scalar nodes the lowering of every node makes: its little nodes and the rest of its circuit.
+  pub nodes: HashMap<NodeIndex, usize>,
+  /// Little nodes per input, as [super::InputsTracker::new_inputs] will have them.
+  pub inputs: HashMap<NodeIndex, usize>,
+  /// Little nodes per retrieved node, as [super::InputsTracker::new_outputs] will have them.
+  pub outputs: HashMap<NodeIndex, usize>,
+  /// Whether some padding is read, which makes the one zero constant shared by the whole graph.
+  pub reads_padding: bool,
+}
+
+impl ScalarizationPlan {
+  /// Nodes of the compiled scalar graph.
+  pub fn total_nodes(&self) -> usize {
+    self.nodes.values().sum::<usize>() + usize::from(self.reads_padding)
+  }
+}
+
+impl Scalarize {
+  /// The sizes and node counts of scalarizing the graph with these settings, see the module docs.
+  /// Fails with everything [check_supported] finds.
+  pub fn plan(&self, cx: &Graph) -> Result<ScalarizationPlan, Vec<UnsupportedOp>> {
+    let unsupported = check_supported(cx);
+    if !unsupported.is_empty() {
+      return Err(unsupported);
+    }
+    let (mut graph, remap) = copy_graph_roughly(cx);
+    let back: HashMap<NodeIndex, NodeIndex> = remap.iter().map(|(x, y)| (*y, *x)).collect();
+    remove_dead_tensors(&mut graph);
+    remove_noop_contiguous(&mut graph);
+    let merged_axes = merge_reductions(&mut graph);
+    let graph = &graph;
+
+    let mut plan = ScalarizationPlan::default();
+    let mut index_cache = IndexCache::default();
+    // logical indices read off every edge, as the lowering of its target reads them
+    let mut reads: HashMap<EdgeIndex, Vec<usize>> = HashMap::new();
+    let mut pi = petgraph::algo::toposort(&graph.graph, None).unwrap();
+    pi.reverse();
+    for x in pi {
+      let size = get_own_size(x, graph);
+      let incoming = graph
+        .edges_directed(x, Incoming)
+        .filter_map(|e| e.weight().as_data().map(|(_, _, sh)| (e.id(), sh)))
+        .collect_vec();
+      let retrieved = graph.to_retrieve.contains_key(&x);
+      let all = |graph: &Graph, e: EdgeIndex| -> Vec<usize> {
+        let sh = graph.edge_weight(e).unwrap().as_data().unwrap().2;
+        (0..sh.n_elements().to_usize().unwrap()).collect()
+      };
+      // the result of x is the input, the reads of the consumers go through to the input
+      let pass_through = |reads: &mut HashMap<EdgeIndex, Vec<usize>>,
+                          plan: &mut ScalarizationPlan,
+                          index_cache: &mut IndexCache|
+       -> usize {
+        let (e_in, _) = incoming[0];
+        if retrieved {
+          // copied by additions of the zero
+          reads.insert(e_in, (0..size).collect());
+          plan.reads_padding |= size > 0;
+          return size;
+        }
+        let mut through = vec![];
+        for e in graph.edges_directed(x, Outgoing) {
+          let sh = match e.weight().as_data() {
+            Some((_, _, sh)) => sh,
+            None => continue,
+          };
+          for l in reads.get(&e.id()).into_iter().flatten() {
+            match index_cache.logical_to_physical(&sh, *l) {
+              Some(p) => through.push(p),
+              None => plan.reads_padding = true,
+            }
+          }
+        }
+        reads.insert(e_in, through.into_iter().unique().collect());
+        0
+      };
+
+      // a Recip of a Sqrt is lowered on its own, reading the argument of the Sqrt
+      let rsqrt = |x: NodeIndex| {
+        graph.check_node_type::<Recip>(x)
+          && graph
+            .edges_directed(x, Incoming)
+            .any(|e| graph.check_node_type::<Sqrt>(e.source()))
+      };
+
+      let n = if graph.check_node_type::<Function>(x) {
+        plan.inputs.insert(back[&x], size);
+        size
+      } else if graph.check_node_type::<Constant>(x) {
+        size
+      } else if rsqrt(x) {
+        reads.insert(incoming[0].0, all(graph, incoming[0].0));
+        // the factor, the constant and the initial guess, then per element the factor times the argument and per
+        // iteration a Mul, Mul, Add and Mul
+        3 + size * (1 + self.rsqrt_iterations * 4)
+      } else if graph.check_node_type::<Sqrt>(x)
+        && !retrieved
+        && graph
+          .edges_directed(x, Outgoing)
+          .all(|e| e.weight().as_data().is_none() || rsqrt(e.target()))
+      {
+        // lowered with the Recips reading it
+        pass_through(&mut reads, &mut plan, &mut index_cache)
+      } else if graph.check_node_type::<Recip>(x)
+        || lookup_kind(graph, x).is_some()
+        || graph.check_node_type::<Add>(x)
+        || graph.check_node_type::<Mul>(x)
+        || graph.check_node_type::<LessThan>(x)
+        || graph.check_node_type::<Mod>(x)
+      {
+        for (e, _) in incoming.iter() {
+          reads.insert(*e, all(graph, *e));
+        }
+        size
+      } else if graph.check_node_type::<Sqrt>(x) {
+        reads.insert(incoming[0].0, all(graph, incoming[0].0));
+        // the half and the initial guess, then per iteration a Recip, Mul, Add and Mul
+        2 + size * self.sqrt_iterations * 4
+      } else if graph.check_node_type::<Contiguous>(x) {
+        pass_through(&mut reads, &mut plan, &mut index_cache)
+      } else if let Some((sums, ax)) = reduction_axis(graph, x) {
+        let (e, sh) = incoming[0];
+        let axes = merged_axes.get(&x).cloned().unwrap_or_else(|| vec![ax]);
+        let dims = sh.shape_usize();
+        let ax_len: usize = axes.iter().map(|ax| dims[*ax]).product();
+        if ax_len == 1 {
+          pass_through(&mut reads, &mut plan, &mut index_cache)
+        } else {
+          reads.insert(e, all(graph, e));
+          let style = if axes.len() > 1 && axes.len() == dims.len() {
+            ReductionStyle::BalancedTree
+          } else {
+            self.reduction
+          };
+          // every style combines the elements of a reduction pairwise, a chained sum starts from a shared zero
+          let neutral = sums && style == ReductionStyle::Chain;
+          size * (ax_len - 1) + if neutral { size + 1 } else { 0 }
+        }
+      } else if graph.check_node_type::<Pool2D>(x) {
+        let op = graph.get_op::<Pool2D>(x);
+        let window = op.kernel * op.kernel;
+        reads.insert(
+          incoming[0].0,
+          (0..size).flat_map(|i| op.window(i)).unique().collect(),
+        );
+        let neutral = op.kind == PoolKind::Mean && self.reduction == ReductionStyle::Chain;
+        let combined = size * (window - 1) + if neutral { size + 1 } else { 0 };
+        // a mean is scaled, the max of a single element gets a node of its own, by a shared factor
+        let scaled = if op.kind == PoolKind::Mean || window == 1 {
+          size + 1
+        } else {
+          0
+        };
+        combined + scaled
+      } else if graph.check_node_type::<Gather>(x) {
+        let Gather { rows, dim } = *graph.get_op::<Gather>(x);
+        let data = graph
+          .edges_directed(x, Incoming)
+          .filter_map(|e| e.weight().as_data().map(|(inp, _, _)| (inp, e.id())))
+          .sorted()
+          .collect_vec();
+        for (_, e) in data.iter() {
+          reads.insert(*e, all(graph, *e));
+        }
+        let indexes = size / dim;
+        // one and minus one, the row ids, the one-hots (two comparisons, their sum, negated, from one),
+        // then the products with the table rows and their sums
+        2 + rows + indexes * rows * 5 + size * rows + size * (rows - 1)
+      } else {
+        unreachable!("Checked by check_supported")
+      };
+      plan.sizes.insert(back[&x], size);
+      plan.nodes.insert(back[&x], n);
+      if retrieved {
+        plan.outputs.insert(back[&x], size);
+      }
+    }
+
+    // padding read by the lowerings
+    for (e, read) in reads.iter() {
+      let sh = graph.edge_weight(*e).unwrap().as_data().unwrap().2;
+      let table = index_cache.table(&sh);
+      if read.iter().any(|l| table[*l].is_none()) {
+        plan.reads_padding = true;
+      }
+    }
+    Ok(plan)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use luminal::{
+    graph::Graph,
+    prelude::NodeIndex,
+    shape::{R1, R2, R3},
+  };
+  use proptest::prelude::*;
+
+  use crate::scalar::{
+    avg_pool2d, copy_graph_roughly, gather, max_pool2d,
+    testing::{arb_expr, build_graph},
+    ReductionStyle, Scalarize,
+  };
+
+  /// Nodes of the graph compiled by the scalar compiler, before the rewrites.
+  fn compiled_nodes(cx: &Graph, compiler: Scalarize) -> usize {
+    let (mut g, _) = copy_graph_roughly(cx);
+    let mut ids: Vec<NodeIndex> = vec![];
+    g.compile(compiler, &mut ids).unwrap();
+    g.node_count()
+  }
+
+  fn styles() -> Vec<Scalarize> {
+    [
+      ReductionStyle::Chain,
+      ReductionStyle::BalancedTree,
+      ReductionStyle::Chunked(3),
+    ]
+    .into_iter()
+    .map(|reduction| Scalarize {
+      reduction,
+      ..Default::default()
+    })
+    .collect()
+  }
+
+  #[test]
+  fn test_plan_counts_the_compiled_nodes() {
+    let mut cx = Graph::new();
+    let a = cx.tensor::<R2<2, 3>>();
+    let b = cx.tensor::<R2<3, 4>>();
+    let c = a.matmul(b).relu().retrieve();
+    let image = cx.tensor::<R3<2, 4, 4>>();
+    let _pools = (max_pool2d::<2, 4, 4, 2, 2>(image, 2, 2)
+      + avg_pool2d::<2, 4, 4, 2, 2>(image, 2, 2)
+      + max_pool2d::<2, 4, 4, 2, 2>(image, 1, 2))
+    .retrieve();
+    let _sums = (image.sum_reduce::<_, luminal::shape::Axes3<0, 1, 2>>()
+      + image
+        .sum_reduce::<_, luminal::shape::Axes2<1, 2>>()
+        .sum_reduce::<_, luminal::shape::Axis<0>>())
+    .retrieve();
+    let table = cx.tensor::<R2<3, 2>>();
+    let indexes = cx.tensor::<R1<4>>();
+    let _rows = (gather(table, indexes).sqrt() + 1.0).retrieve();
+    let _rsqrt = (indexes.sqrt().recip() * indexes).retrieve();
+
+    let nodes = cx.node_count();
+    for compiler in styles() {
+      let plan = compiler.plan(&cx).unwrap();
+      assert_eq!(cx.node_count(), nodes);
+      assert_eq!(plan.sizes[&c.id], 8);
+      assert_eq!(plan.inputs[&a.id], 6);
+      assert_eq!(plan.outputs[&c.id], 8);
+      assert_eq!(plan.total_nodes(), compiled_nodes(&cx, compiler));
+    }
+    let mut cx = Graph::new();
+    let a = cx.tensor::<R1<3>>();
+    let _ = a.log2().retrieve();
+    assert_eq!(Scalarize::default().plan(&cx).unwrap_err().len(), 1);
+  }
+
+  proptest! {
+    #![proptest_config(ProptestConfig::with_cases(32))]
+
+    #[test]
+    fn test_plan_random_exprs(expr in arb_expr(3)) {
+      let (cx, _) = build_graph(&expr);
+      for compiler in styles() {
+        let plan = compiler.plan(&cx).unwrap();
+        prop_assert_eq!(plan.total_nodes(), compiled_nodes(&cx, compiler), "expr: {:?}", expr);
+      }
+    }
+  }
+}
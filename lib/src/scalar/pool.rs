@@ -0,0 +1,208 @@
+//!
+//! Max and average pooling over the spatial dims of `(channels, height, width)` images.
+//!
+//! [Pool2D] is a tensor op of our own, as [super::Gather]. Luminal pools through a strided view of the input with
+//! the windows as an extra dim, whose index expressions are as large as the image; here every output is a reduction
+//! circuit over the elements of its window, read straight off the input by logical index (see [Pool2D::window]).
+//! The circuits are laid out in the [super::ReductionStyle] of the compiler, a mean is the sum times `1 / kernel^2`.
+//!
+
+use luminal::{
+  op::{InputTensor, Operator},
+  prelude::{GraphTensor, ShapeTracker, Tensor},
+  shape::{Shape, R3},
+};
+
+use super::logical_to_physical_many;
+use crate::dtype::tensor_f32;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PoolKind {
+  Max,
+  Mean,
+}
+
+/// Pooling of the `(channels, height, width)` input over square windows of `kernel` side, `stride` apart, no padding.
+/// The output is `(channels, (height - kernel) / stride + 1, (width - kernel) / stride + 1)`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Pool2D {
+  pub kind: PoolKind,
+  pub channels: usize,
+  pub height: usize,
+  pub width: usize,
+  pub kernel: usize,
+  pub stride: usize,
+}
+
+impl Pool2D {
+  pub fn out_height(&self) -> usize {
+    (self.height - self.kernel) / self.stride + 1
+  }
+
+  pub fn out_width(&self) -> usize {
+    (self.width - self.kernel) / self.stride + 1
+  }
+
+  pub fn output_size(&self) -> usize {
+    self.channels * self.out_height() * self.out_width()
+  }
+
+  /// Logical indices in the input of the window of the `i`-th output element, row by row.
+  pub fn window(&self, i: usize) -> Vec<usize> {
+    let (out_h, out_w) = (self.out_height(), self.out_width());
+    let (c, oy, ox) = (i / (out_h * out_w), i / out_w % out_h, i % out_w);
+    let (y0, x0) = (oy * self.stride, ox * self.stride);
+    (0..self.kernel * self.kernel)
+      .map(|k| {
+        let (y, x) = (y0 + k / self.kernel, x0 + k % self.kernel);
+        (c * self.height + y) * self.width + x
+      })
+      .collect()
+  }
+}
+
+impl Operator for Pool2D {
+  fn process(&mut self, inp: Vec<(InputTensor, ShapeTracker)>) -> Vec<Tensor> {
+    let (tensor, shape) = &inp[0];
+    let data = tensor_f32(tensor.borrowed()).unwrap();
+    let n = shape.n_elements().to_usize().unwrap();
+    // logical elements of the input, masked out ones are zeros
+    let input: Vec<f32> =
+      logical_to_physical_many(&(shape.index_expression(), shape.valid_expression()), 0..n)
+        .into_iter()
+        .map(|i| i.map_or(0.0, |i| data[i]))
+        .collect();
+    let out = (0..self.output_size())
+      .map(|i| {
+        let window = self.window(i).into_iter().map(|k| input[k]);
+        match self.kind {
+          PoolKind::Max => window.fold(f32::NEG_INFINITY, f32::max),
+          PoolKind::Mean => window.sum::<f32>() / (self.kernel * self.kernel) as f32,
+        }
+      })
+      .collect();
+    vec![Tensor::new(out)]
+  }
+}
+
+fn pool2d<const C: usize, const H: usize, const W: usize, const OH: usize, const OW: usize>(
+  kind: PoolKind,
+  x: GraphTensor<R3<C, H, W>>,
+  kernel: usize,
+  stride: usize,
+) -> GraphTensor<R3<C, OH, OW>> {
+  let op = Pool2D {
+    kind,
+    channels: C,
+    height: H,
+    width: W,
+    kernel,
+    stride,
+  };
+  assert!(
+    kernel > 0 && stride > 0 && kernel <= H && kernel <= W,
+    "Pooling windows of side {} don't fit {}x{}",
+    kernel,
+    H,
+    W
+  );
+  assert!(
+    (op.out_height(), op.out_width()) == (OH, OW),
+    "Pooling {}x{} by {} with stride {} gives {}x{}",
+    H,
+    W,
+    kernel,
+    stride,
+    op.out_height(),
+    op.out_width()
+  );
+  let id = x.graph().add_op(op).input(x.id, 0, x.shape).finish();
+  GraphTensor::from_id(id, R3::<C, OH, OW>::to_tracker(), x.graph_ref)
+}
+
+/// The largest element of every window, see [Pool2D].
+pub fn max_pool2d<
+  const C: usize,
+  const H: usize,
+  const W: usize,
+  const OH: usize,
+  const OW: usize,
+>(
+  x: GraphTensor<R3<C, H, W>>,
+  kernel: usize,
+  stride: usize,
+) -> GraphTensor<R3<C, OH, OW>> {
+  pool2d(PoolKind::Max, x, kernel, stride)
+}
+
+/// The mean of every window, see [Pool2D].
+pub fn avg_pool2d<
+  const C: usize,
+  const H: usize,
+  const W: usize,
+  const OH: usize,
+  const OW: usize,
+>(
+  x: GraphTensor<R3<C, H, W>>,
+  kernel: usize,
+  stride: usize,
+) -> GraphTensor<R3<C, OH, OW>> {
+  pool2d(PoolKind::Mean, x, kernel, stride)
+}
+
+#[cfg(test)]
+mod tests {
+  use luminal::{graph::Graph, shape::R3};
+  use rand::{rngs::StdRng, SeedableRng};
+
+  use super::{avg_pool2d, max_pool2d};
+  use crate::scalar::{
+    evaluate_tensor_graph, random_inputs, scalar_with, verify_scalarization, ReductionStyle,
+    Scalarize,
+  };
+
+  #[test]
+  fn test_pooling() {
+    let mut cx = Graph::new();
+    let a = cx.tensor::<R3<1, 4, 4>>();
+    let max = max_pool2d::<1, 4, 4, 2, 2>(a, 2, 2).retrieve();
+    let mean = avg_pool2d::<1, 4, 4, 2, 2>(a, 2, 2).retrieve();
+    let inputs = [(a.id, (0..16).map(|v| v as f32).collect())]
+      .into_iter()
+      .collect();
+    let results = evaluate_tensor_graph(&cx, &inputs);
+    assert_eq!(results[&max.id], vec![5.0, 7.0, 13.0, 15.0]);
+    assert_eq!(results[&mean.id], vec![2.5, 4.5, 10.5, 12.5]);
+
+    // overlapping windows, a border left out, 1x1 windows
+    let mut cx = Graph::new();
+    let a = cx.tensor::<R3<2, 5, 6>>();
+    let max = max_pool2d::<2, 5, 6, 2, 2>(a, 3, 2).retrieve();
+    let _mean = avg_pool2d::<2, 5, 6, 2, 2>(a, 3, 2).retrieve();
+    let _single = max_pool2d::<2, 5, 6, 3, 3>(a, 1, 2).retrieve();
+    let inputs = random_inputs(&cx, &mut StdRng::seed_from_u64(0));
+    let image = &inputs[&a.id];
+    let expected: Vec<f32> = (0..2)
+      .flat_map(|c| (0..2).flat_map(move |oy| (0..2).map(move |ox| (c, oy, ox))))
+      .map(|(c, oy, ox)| {
+        (0..3)
+          .flat_map(|ky| (0..3).map(move |kx| (ky, kx)))
+          .map(|(ky, kx)| image[c * 30 + (2 * oy + ky) * 6 + 2 * ox + kx])
+          .fold(f32::NEG_INFINITY, f32::max)
+      })
+      .collect();
+    assert_eq!(evaluate_tensor_graph(&cx, &inputs)[&max.id], expected);
+    for reduction in [
+      ReductionStyle::Chain,
+      ReductionStyle::BalancedTree,
+      ReductionStyle::Chunked(4),
+    ] {
+      let compiler = Scalarize {
+        reduction,
+        ..Default::default()
+      };
+      let (sc, _) = scalar_with(&cx, compiler);
+      assert_eq!(verify_scalarization(&cx, &sc, &inputs), Ok(()));
+    }
+  }
+}
@@ -0,0 +1,168 @@
+//!
+//! Bounds of the values of every scalar node, by interval arithmetic from bounds of the inputs.
+//!
+//! The bounds are sound but not tight: every node is bounded on its own, so e.g. `x - x` is bounded by `[lo - hi, hi - lo]`.
+//! That's what quantization needs to be safe from overflows, for realistic scales see [crate::quant::tensor_ranges].
+//!
+
+use std::collections::HashMap;
+
+use luminal::prelude::NodeIndex;
+
+use super::{LookupKind, ScalarGraph, ScalarOp};
+
+const UNBOUNDED: (f64, f64) = (f64::NEG_INFINITY, f64::INFINITY);
+
+/// Smallest interval containing all the values, an infinite bound times zero counts as zero.
+fn hull(values: impl IntoIterator<Item = f64>) -> (f64, f64) {
+  values
+    .into_iter()
+    .map(|v| if v.is_nan() { 0.0 } else { v })
+    .fold((f64::INFINITY, f64::NEG_INFINITY), |(lo, hi), v| {
+      (lo.min(v), hi.max(v))
+    })
+}
+
+fn mul((a_lo, a_hi): (f64, f64), (b_lo, b_hi): (f64, f64)) -> (f64, f64) {
+  hull([a_lo * b_lo, a_lo * b_hi, a_hi * b_lo, a_hi * b_hi])
+}
+
+/// Of `a < b`, 1 or 0.
+fn less_than((a_lo, a_hi): (f64, f64), (b_lo, b_hi): (f64, f64)) -> (f64, f64) {
+  if a_hi < b_lo {
+    (1.0, 1.0)
+  } else if a_lo >= b_hi {
+    (0.0, 0.0)
+  } else {
+    (0.0, 1.0)
+  }
+}
+
+fn recip((lo, hi): (f64, f64)) -> (f64, f64) {
+  if lo > 0.0 || hi < 0.0 {
+    hull([1.0 / lo, 1.0 / hi])
+  } else {
+    UNBOUNDED
+  }
+}
+
+/// Bounds `(min, max)` of the value of every node of the scalar graph.
+/// Inputs are bounded per original tensor graph input (as in [super::InputsTracker::new_inputs]),
+/// all the elements of an input by the same interval. Inputs without bounds are unbounded.
+pub fn range_analysis(
+  scalar: &ScalarGraph,
+  input_bounds: &HashMap<NodeIndex, (f64, f64)>,
+) -> HashMap<NodeIndex, (f64, f64)> {
+  let mut ranges: HashMap<NodeIndex, (f64, f64)> = HashMap::new();
+  for (x, little_ids) in scalar.inputs_tracker.new_inputs.iter() {
+    let bounds = input_bounds.get(x).copied().unwrap_or(UNBOUNDED);
+    ranges.extend(little_ids.iter().map(|y| (*y, bounds)));
+  }
+
+  for node in scalar.freeze_ops().nodes {
+    let x = node.id;
+    let args: Vec<(f64, f64)> = node.args.iter().map(|y| ranges[y]).collect();
+    let r = match node.op {
+      ScalarOp::Constant(val) => {
+        let v = f64::from(val);
+        (v, v)
+      }
+      ScalarOp::Input => *ranges
+        .get(&x)
+        .unwrap_or_else(|| panic!("Input node {:?} is not tracked", x)),
+      ScalarOp::Add => (args[0].0 + args[1].0, args[0].1 + args[1].1),
+      ScalarOp::Sub => (args[0].0 - args[1].1, args[0].1 - args[1].0),
+      ScalarOp::Neg => (-args[0].1, -args[0].0),
+      ScalarOp::Mul => mul(args[0], args[1]),
+      ScalarOp::Fma => {
+        let (lo, hi) = mul(args[0], args[1]);
+        (lo + args[2].0, hi + args[2].1)
+      }
+      ScalarOp::LessThan | ScalarOp::SignedLessThan(_) => less_than(args[0], args[1]),
+      ScalarOp::LessThanConst(c) => less_than(args[0], (f64::from(c), f64::from(c))),
+      ScalarOp::GreaterThanConst(c) => less_than((f64::from(c), f64::from(c)), args[0]),
+      ScalarOp::RangeCheck(_) => args[0],
+      ScalarOp::Max => (args[0].0.max(args[1].0), args[0].1.max(args[1].1)),
+      ScalarOp::Relu => (args[0].0.max(0.0), args[0].1.max(0.0)),
+      ScalarOp::Recip | ScalarOp::RecipHint => recip(args[0]),
+      ScalarOp::Mod => {
+        // the remainder takes the sign of the dividend and is smaller than the divisor in magnitude
+        let m = args[1].0.abs().max(args[1].1.abs());
+        let (lo, hi) = args[0];
+        (lo.max(-m).min(0.0), hi.min(m).max(0.0))
+      }
+      ScalarOp::DivConst(divisor) => {
+        let d = f64::from(divisor);
+        let (lo, hi) = hull([args[0].0 / d, args[0].1 / d]);
+        (lo.floor(), hi.floor())
+      }
+      ScalarOp::ModConst(modulus) => hull([0.0, f64::from(modulus)]),
+      ScalarOp::Lookup(LookupKind::Sin) => (-1.0, 1.0),
+      // no lower bound near zero, the backends clamp to the domain of the table
+      ScalarOp::Lookup(LookupKind::Log2) if args[0].0 <= 0.0 => {
+        (f64::NEG_INFINITY, args[0].1.max(0.0).log2())
+      }
+      ScalarOp::Lookup(kind) => {
+        // the other tables are of increasing functions
+        let eval = |v: f64| f64::from(kind.eval(v as f32));
+        (eval(args[0].0), eval(args[0].1))
+      }
+    };
+    ranges.insert(x, r);
+  }
+  ranges
+}
+
+/// Bits needed for the integer part of values within the bounds, the sign not counted.
+pub fn integer_bits((lo, hi): (f64, f64)) -> u32 {
+  let m = lo.abs().max(hi.abs());
+  if m < 1.0 {
+    0
+  } else if m.is_infinite() {
+    u32::MAX
+  } else {
+    m.log2().floor() as u32 + 1
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::collections::HashMap;
+
+  use luminal::{
+    graph::Graph,
+    shape::{Axis, R1},
+  };
+
+  use super::{integer_bits, range_analysis};
+  use crate::scalar::scalar;
+
+  #[test]
+  fn test_range_analysis_bounds_the_evaluation() {
+    let mut cx = Graph::new();
+    let a = cx.tensor::<R1<3>>();
+    let b = cx.tensor::<R1<3>>();
+    let c = (a * b + a).relu().sum_reduce::<_, Axis<0>>().retrieve();
+    let (sc, _) = scalar(&cx);
+    let bounds = [(a.id, (-1.0, 2.0)), (b.id, (0.0, 3.0))]
+      .into_iter()
+      .collect();
+    let ranges = range_analysis(&sc, &bounds);
+    let out = sc.inputs_tracker.new_outputs[&c.id][0];
+    // every element at most 2 * 3 + 2
+    assert_eq!(ranges[&out], (0.0, 24.0));
+    assert_eq!(integer_bits(ranges[&out]), 5);
+
+    let samples = [
+      (vec![-1.0, 0.5, 2.0], vec![3.0, 0.0, 1.5]),
+      (vec![2.0, 2.0, 2.0], vec![3.0, 3.0, 3.0]),
+    ];
+    for (va, vb) in samples {
+      let inputs: HashMap<_, Vec<f32>> = [(a.id, va), (b.id, vb)].into_iter().collect();
+      for (x, v) in sc.evaluate(&inputs) {
+        let (lo, hi) = ranges[&x];
+        assert!(lo <= f64::from(v) && f64::from(v) <= hi);
+      }
+    }
+  }
+}
@@ -0,0 +1,634 @@
+//!
+//! Small pattern rewrites of the scalar graph, recognizing what luminal expands high level ops into.
+//!
+
+use std::collections::{HashMap, HashSet};
+
+use itertools::Itertools;
+use luminal::{
+  graph::Graph,
+  op::{Add, LessThan, Mul, Operator, Recip},
+  prelude::{
+    petgraph::{
+      self,
+      visit::EdgeRef,
+      Direction::{Incoming, Outgoing},
+    },
+    Dependency, NodeIndex,
+  },
+  shape::{Shape, R0},
+};
+
+use super::{
+  ConstantOp, FmaOp, GreaterThanConstOp, LessThanConstOp, Max, NegOp, ReluOp, ScalarGraph, SubOp,
+};
+
+/// Arguments of the node by input order.
+pub(super) fn args(graph: &Graph, x: NodeIndex) -> Vec<NodeIndex> {
+  graph
+    .edges_directed(x, Incoming)
+    .filter_map(|e| e.weight().as_data().map(|(inp, _, _)| (inp, e.source())))
+    .sorted_by_key(|(inp, _)| *inp)
+    .map(|(_, y)| y)
+    .collect()
+}
+
+/// Value of a node computed from constants only. Constants arrive e.g. as `1 * -1` for a `- 1`.
+pub(super) fn const_value(graph: &Graph, x: NodeIndex) -> Option<f32> {
+  if graph.check_node_type::<ConstantOp>(x) {
+    Some(graph.get_op::<ConstantOp>(x).val)
+  } else if graph.check_node_type::<Add>(x) || graph.check_node_type::<Mul>(x) {
+    let vs: Vec<f32> = args(graph, x)
+      .into_iter()
+      .map(|y| const_value(graph, y))
+      .collect::<Option<_>>()?;
+    if graph.check_node_type::<Add>(x) {
+      Some(vs[0] + vs[1])
+    } else {
+      Some(vs[0] * vs[1])
+    }
+  } else if graph.check_node_type::<Recip>(x) {
+    const_value(graph, args(graph, x)[0]).map(|v| 1.0 / v)
+  } else {
+    None
+  }
+}
+
+/// For a binary op with exactly one constant argument: the other argument and the constant.
+pub(super) fn with_const<T: Operator + 'static>(
+  graph: &Graph,
+  x: NodeIndex,
+) -> Option<(NodeIndex, f32)> {
+  if !graph.check_node_type::<T>(x) {
+    return None;
+  }
+  let (l, r) = args(graph, x).into_iter().collect_tuple()?;
+  match (const_value(graph, l), const_value(graph, r)) {
+    (Some(c), None) => Some((r, c)),
+    (None, Some(c)) => Some((l, c)),
+    _ => None,
+  }
+}
+
+pub(super) fn approx(a: f32, b: f32) -> bool {
+  (a - b).abs() <= 1e-6 * b.abs().max(1.0)
+}
+
+/// Skips multiplications by one, like the `1.0 * recip(..)` of `1.0 / ..`.
+pub(super) fn strip_mul_one(graph: &Graph, x: NodeIndex) -> NodeIndex {
+  match with_const::<Mul>(graph, x) {
+    Some((y, c)) if approx(c, 1.0) => strip_mul_one(graph, y),
+    _ => x,
+  }
+}
+
+impl ScalarGraph {
+  /// Moves the consumers (and the output role) of `old` over to `new`.
+  pub(super) fn replace_node(&mut self, old: NodeIndex, new: NodeIndex) {
+    let outs = self
+      .graph
+      .edges_directed(old, Outgoing)
+      .map(|e| (e.id(), e.target(), e.weight().clone()))
+      .collect_vec();
+    for (e, target, w) in outs {
+      self.graph.graph.remove_edge(e);
+      self.graph.add_edge(new, target, w);
+    }
+    for little in self
+      .inputs_tracker
+      .new_outputs
+      .values_mut()
+      .chain(self.inputs_tracker.argmax.values_mut())
+    {
+      little
+        .iter_mut()
+        .filter(|y| **y == old)
+        .for_each(|y| *y = new);
+    }
+    if let Some(w) = self.graph.to_retrieve.remove(&old) {
+      self.graph.to_retrieve.insert(new, w);
+    }
+    if let Some(o) = self.inputs_tracker.origin.get(&old).copied() {
+      self.inputs_tracker.origin.insert(new, o);
+    }
+  }
+
+  /// Replaces `x` by a new node applying `op` to `arg`.
+  pub(super) fn replace_with_unop<T: Operator + 'static>(
+    &mut self,
+    x: NodeIndex,
+    op: T,
+    arg: NodeIndex,
+  ) {
+    let new = self.graph.add_op(op).finish();
+    self.graph.add_edge(
+      arg,
+      new,
+      Dependency::Data {
+        input_order: 0,
+        output_order: 0,
+        shape: R0::to_tracker(),
+      },
+    );
+    self.replace_node(x, new);
+  }
+
+  /// Puts a new node applying `op` to `x` in the place of `x`, the consumers (and the output role) of `x` move to it.
+  pub(crate) fn insert_after<T: Operator + 'static>(&mut self, x: NodeIndex, op: T) -> NodeIndex {
+    let new = self.graph.add_op(op).finish();
+    self.replace_node(x, new);
+    self.graph.add_edge(
+      x,
+      new,
+      Dependency::Data {
+        input_order: 0,
+        output_order: 0,
+        shape: R0::to_tracker(),
+      },
+    );
+    new
+  }
+
+  /// Splits the [FmaOp] `x` in place, into a new Mul node of its first two arguments and `x` adding the third to it.
+  /// `x` keeps its id and its consumers, the Mul node is returned.
+  pub(crate) fn split_fma(&mut self, x: NodeIndex) -> NodeIndex {
+    let (a, b, c) = args(&self.graph, x).into_iter().collect_tuple().unwrap();
+    let incoming = self
+      .graph
+      .edges_directed(x, Incoming)
+      .map(|e| e.id())
+      .collect_vec();
+    for e in incoming {
+      self.graph.graph.remove_edge(e);
+    }
+    *self.graph.node_weight_mut(x).unwrap() = Box::new(Add {});
+    let product = self.graph.add_op(Mul {}).finish();
+    let data = |input_order| Dependency::Data {
+      input_order,
+      output_order: 0,
+      shape: R0::to_tracker(),
+    };
+    self.graph.add_edge(a, product, data(0));
+    self.graph.add_edge(b, product, data(1));
+    self.graph.add_edge(product, x, data(0));
+    self.graph.add_edge(c, x, data(1));
+    if let Some(o) = self.inputs_tracker.origin.get(&x).copied() {
+      self.inputs_tracker.origin.insert(product, o);
+    }
+    product
+  }
+
+  /// Replaces `x` by a new node applying `op` to `l` and `r`.
+  pub(super) fn replace_with_binop<T: Operator + 'static>(
+    &mut self,
+    x: NodeIndex,
+    op: T,
+    l: NodeIndex,
+    r: NodeIndex,
+  ) {
+    self.replace_with_op(x, op, &[l, r]);
+  }
+
+  /// Replaces `x` by a new node applying `op` to the arguments, in argument order.
+  pub(super) fn replace_with_op<T: Operator + 'static>(
+    &mut self,
+    x: NodeIndex,
+    op: T,
+    args: &[NodeIndex],
+  ) {
+    let new = self.graph.add_op(op).finish();
+    for (input_order, arg) in args.iter().copied().enumerate() {
+      self.graph.add_edge(
+        arg,
+        new,
+        Dependency::Data {
+          input_order: input_order as u8,
+          output_order: 0,
+          shape: R0::to_tracker(),
+        },
+      );
+    }
+    self.replace_node(x, new);
+  }
+
+  /// Removes the nodes nothing depends on, except for the inputs and outputs.
+  pub fn remove_dead_nodes(&mut self) {
+    let keep: HashSet<NodeIndex> = self
+      .inputs_tracker
+      .ordered_inputs()
+      .into_iter()
+      .chain(self.inputs_tracker.ordered_outputs())
+      .collect();
+    loop {
+      let dead = self
+        .graph
+        .node_indices()
+        .filter(|x| {
+          !keep.contains(x)
+            && !self.graph.to_retrieve.contains_key(x)
+            && self.graph.edges_directed(*x, Outgoing).next().is_none()
+        })
+        .collect_vec();
+      if dead.is_empty() {
+        break;
+      }
+      for x in dead {
+        self.inputs_tracker.origin.remove(&x);
+        self.inputs_tracker.constants.remove(&x);
+        self.graph.remove_node(x);
+      }
+    }
+  }
+
+  /// `max(x, 0)`, or the luminal expansion of `x.relu()` (that is `x.max_f32(0.0)`): `(x < 0) * 0 + (0 < x) * x` => x
+  fn match_relu(&self, t: NodeIndex) -> Option<NodeIndex> {
+    let g = &self.graph;
+    if let Some((x, c)) = with_const::<Max>(g, t) {
+      return (c == 0.0).then_some(x);
+    }
+    if !g.check_node_type::<Add>(t) {
+      return None;
+    }
+    let (p, q) = args(g, t).into_iter().collect_tuple()?;
+    [(p, q), (q, p)]
+      .into_iter()
+      .find_map(|(zero_term, relu_term)| {
+        // anything times zero
+        let (_, zero) = with_const::<Mul>(g, zero_term)?;
+        if zero != 0.0 || !g.check_node_type::<Mul>(relu_term) {
+          return None;
+        }
+        let (l, r) = args(g, relu_term).into_iter().collect_tuple()?;
+        // (0 < x) * x
+        [(l, r), (r, l)].into_iter().find_map(|(lt, x)| {
+          if !g.check_node_type::<LessThan>(lt) {
+            return None;
+          }
+          let (a, b) = args(g, lt).into_iter().collect_tuple()?;
+          (const_value(g, a) == Some(0.0) && b == x).then_some(x)
+        })
+      })
+  }
+
+  /// Replaces the scalarized relus by [ReluOp] nodes.
+  /// Leaves gaps in the node indices, see [ScalarGraph::canonicalize].
+  pub fn fuse_relus(&mut self) {
+    let found = self
+      .graph
+      .node_indices()
+      .sorted()
+      .filter_map(|x| self.match_relu(x).map(|arg| (x, arg)))
+      .collect_vec();
+    for (x, arg) in found {
+      self.replace_with_unop(x, ReluOp {}, arg);
+    }
+    self.remove_dead_nodes();
+  }
+
+  /// `x * -1` => x
+  fn match_neg(&self, t: NodeIndex) -> Option<NodeIndex> {
+    with_const::<Mul>(&self.graph, t).and_then(|(x, c)| (c == -1.0).then_some(x))
+  }
+
+  /// `a + b * -1` (either way round) => (a, b)
+  fn match_sub(&self, t: NodeIndex) -> Option<(NodeIndex, NodeIndex)> {
+    if !self.graph.check_node_type::<Add>(t) {
+      return None;
+    }
+    let (p, q) = args(&self.graph, t).into_iter().collect_tuple()?;
+    [(p, q), (q, p)]
+      .into_iter()
+      .find_map(|(a, neg)| self.match_neg(neg).map(|b| (a, b)))
+  }
+
+  /// Replaces luminal's subtractions, `a + b * -1`, by [SubOp] nodes and the negations left over by [NegOp] nodes.
+  /// A multiplication by -1 used only by subtractions is removed, that's a node less per subtraction.
+  /// Leaves gaps in the node indices, see [ScalarGraph::canonicalize].
+  pub fn fuse_subs(&mut self) {
+    let subs = self
+      .graph
+      .node_indices()
+      .sorted()
+      .filter_map(|x| self.match_sub(x).map(|(a, b)| (x, a, b)))
+      .collect_vec();
+    for (x, a, b) in subs {
+      self.replace_with_binop(x, SubOp {}, a, b);
+    }
+    self.remove_dead_nodes();
+    let negs = self
+      .graph
+      .node_indices()
+      .sorted()
+      .filter_map(|x| self.match_neg(x).map(|arg| (x, arg)))
+      .collect_vec();
+    for (x, arg) in negs {
+      self.replace_with_unop(x, NegOp {}, arg);
+    }
+    self.remove_dead_nodes();
+  }
+
+  /// `a * b + c` (either way round), the product used by nothing else => (a, b, c)
+  fn match_fma(&self, t: NodeIndex) -> Option<(NodeIndex, NodeIndex, NodeIndex)> {
+    let g = &self.graph;
+    if !g.check_node_type::<Add>(t) {
+      return None;
+    }
+    let (p, q) = args(g, t).into_iter().collect_tuple()?;
+    [(p, q), (q, p)].into_iter().find_map(|(product, c)| {
+      let single_use = g.edges_directed(product, Outgoing).count() == 1
+        && !g.to_retrieve.contains_key(&product)
+        && !self
+          .inputs_tracker
+          .new_outputs
+          .values()
+          .any(|little| little.contains(&product));
+      if !single_use || !g.check_node_type::<Mul>(product) {
+        return None;
+      }
+      let (a, b) = args(g, product).into_iter().collect_tuple()?;
+      Some((a, b, c))
+    })
+  }
+
+  /// Fuses the additions of a product, `a * b + c`, into [FmaOp] nodes when the product isn't used elsewhere.
+  /// A dot product becomes a chain of them, about half the nodes of a linear layer.
+  /// Plonkish backends make it a single gate. [crate::snark::MLSnark] doesn't take them, so [ScalarGraph::scalar_with]
+  /// doesn't run this.
+  /// Leaves gaps in the node indices, see [ScalarGraph::canonicalize].
+  pub fn fuse_fmas(&mut self) {
+    // matched one at a time, an addition of an addition fused before takes the fused node
+    for x in self.graph.node_indices().sorted().collect_vec() {
+      if let Some((a, b, c)) = self.match_fma(x) {
+        self.replace_with_op(x, FmaOp {}, &[a, b, c]);
+      }
+    }
+    self.remove_dead_nodes();
+  }
+
+  /// Folds the reciprocals of constants into constant nodes, one per value.
+  ///
+  /// Luminal divides a mean (and so a variance, the mean of the squared deviations) by the element count
+  /// as `sum * recip(n)` with `n` broadcast to the shape of the sum, which scalarizes to a Recip of the same constant per result.
+  /// Folded, a mean is its reduction circuit and a multiplication by the single constant `1 / n`.
+  /// Leaves gaps in the node indices, see [ScalarGraph::canonicalize].
+  pub fn fold_constant_recips(&mut self) {
+    let found = self
+      .graph
+      .node_indices()
+      .sorted()
+      .filter(|x| self.graph.check_node_type::<Recip>(*x))
+      .filter_map(|x| const_value(&self.graph, x).map(|v| (x, v)))
+      .filter(|(_, v)| v.is_finite())
+      .collect_vec();
+    let mut folded: HashMap<u32, NodeIndex> = HashMap::new();
+    for (x, v) in found {
+      let c = *folded.entry(v.to_bits()).or_insert_with(|| {
+        let c = self.graph.add_op(ConstantOp { val: v }).finish();
+        self.inputs_tracker.constants.insert(c, v);
+        c
+      });
+      // a shared constant stays with the first result depending on it
+      let origin = self.inputs_tracker.origin.get(&c).copied();
+      self.replace_node(x, c);
+      if let Some(o) = origin {
+        self.inputs_tracker.origin.insert(c, o);
+      }
+    }
+    self.remove_dead_nodes();
+  }
+
+  /// `x < c` => (x, c, true), `c < x` => (x, c, false), for a finite constant `c`.
+  fn match_const_comparison(&self, t: NodeIndex) -> Option<(NodeIndex, f32, bool)> {
+    let g = &self.graph;
+    if !g.check_node_type::<LessThan>(t) {
+      return None;
+    }
+    let (l, r) = args(g, t).into_iter().collect_tuple()?;
+    match (const_value(g, l), const_value(g, r)) {
+      (None, Some(c)) if c.is_finite() => Some((l, c, true)),
+      (Some(c), None) if c.is_finite() => Some((r, c, false)),
+      _ => None,
+    }
+  }
+
+  /// Replaces the comparisons with a constant by [LessThanConstOp] and [GreaterThanConstOp] nodes, e.g. the
+  /// thresholds of a classifier or the masks of a `max` with a constant.
+  /// A backend checks the range of the other argument only, the constant is fixed in the circuit.
+  /// Not every backend takes them, so [ScalarGraph::scalar_with] runs this only if asked to
+  /// (see [super::ScalarCompiler::const_comparisons]).
+  /// Leaves gaps in the node indices, see [ScalarGraph::canonicalize].
+  pub fn specialize_constant_comparisons(&mut self) {
+    let found = self
+      .graph
+      .node_indices()
+      .sorted()
+      .filter_map(|x| self.match_const_comparison(x).map(|m| (x, m)))
+      .collect_vec();
+    for (x, (arg, c, less_than)) in found {
+      if less_than {
+        self.replace_with_unop(x, LessThanConstOp { c }, arg);
+      } else {
+        self.replace_with_unop(x, GreaterThanConstOp { c }, arg);
+      }
+    }
+    self.remove_dead_nodes();
+  }
+
+  /// Merges the nodes applying the same op to the same arguments (in the same order) into one of them, e.g. the
+  /// tensor luminal computes twice from the same inputs. Retrieved nodes stay, results of their own, the others merge
+  /// into a retrieved one if there is one.
+  /// Leaves gaps in the node indices, see [ScalarGraph::canonicalize].
+  pub fn eliminate_common_subexpressions(&mut self) {
+    let order = petgraph::algo::toposort(&self.graph.graph, None).unwrap();
+    // arguments merged already, in topological order
+    let mut kept: HashMap<(String, Vec<NodeIndex>), NodeIndex> = HashMap::new();
+    for x in order {
+      let xs = args(&self.graph, x);
+      if xs.is_empty() {
+        continue;
+      }
+      let key = (format!("{:?}", self.graph.node_weight(x).unwrap()), xs);
+      let y = match kept.get(&key) {
+        Some(y) => *y,
+        None => {
+          kept.insert(key, x);
+          continue;
+        }
+      };
+      if !self.graph.to_retrieve.contains_key(&x) {
+        self.merge_into(x, y);
+      } else if !self.graph.to_retrieve.contains_key(&y) {
+        self.merge_into(y, x);
+        kept.insert(key, x);
+      }
+    }
+    self.remove_dead_nodes();
+  }
+
+  /// [ScalarGraph::replace_node] keeping the origin of `new`.
+  fn merge_into(&mut self, old: NodeIndex, new: NodeIndex) {
+    let origin = self.inputs_tracker.origin.get(&new).copied();
+    self.replace_node(old, new);
+    if let Some(o) = origin {
+      self.inputs_tracker.origin.insert(new, o);
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use luminal::{
+    graph::Graph,
+    op::{LessThan, Recip},
+    shape::{Axis, Const, R1, R2},
+  };
+  use rand::{rngs::StdRng, SeedableRng};
+
+  use crate::scalar::{
+    random_inputs, scalar, scalar_with, verify_scalarization, ConstantOp, ReluOp, ScalarCompiler,
+    ScalarGraph, ScalarOp,
+  };
+
+  #[test]
+  fn test_relu_becomes_relu_op() {
+    let mut cx = Graph::new();
+    let a = cx.tensor::<R1<4>>();
+    let b = cx.tensor::<R1<4>>();
+    let _c = (a * b).relu().retrieve();
+    let (sc, _) = scalar(&cx);
+    let relus = sc
+      .graph
+      .node_indices()
+      .filter(|x| sc.graph.check_node_type::<ReluOp>(*x))
+      .count();
+    assert_eq!(relus, 4);
+    assert!(sc
+      .graph
+      .node_indices()
+      .all(|x| !sc.graph.check_node_type::<LessThan>(x)));
+    let inputs = [
+      (a.id, vec![-1.0, 0.0, 2.0, 3.0]),
+      (b.id, vec![1.0, 5.0, -2.0, 0.5]),
+    ]
+    .into_iter()
+    .collect();
+    assert_eq!(verify_scalarization(&cx, &sc, &inputs), Ok(()));
+  }
+
+  #[test]
+  fn test_subtractions_become_sub_ops() {
+    let mut cx = Graph::new();
+    let a = cx.tensor::<R1<4>>();
+    let b = cx.tensor::<R1<4>>();
+    let diff = a - b;
+    let _loss = (diff * diff).retrieve();
+    let _neg = (-b).retrieve();
+    let (sc, _) = scalar(&cx);
+    let count = |op: ScalarOp| {
+      sc.graph
+        .node_indices()
+        .filter(|x| sc.scalar_op(*x) == op)
+        .count()
+    };
+    assert_eq!(count(ScalarOp::Sub), 4);
+    assert_eq!(count(ScalarOp::Neg), 4);
+    // the inputs, the differences, their squares and the negations
+    assert_eq!(sc.graph.node_count(), 8 + 4 + 4 + 4);
+    let inputs = random_inputs(&cx, &mut StdRng::seed_from_u64(0));
+    assert_eq!(verify_scalarization(&cx, &sc, &inputs), Ok(()));
+  }
+
+  #[test]
+  fn test_linear_layer_fuses_into_fmas() {
+    let mut cx = Graph::new();
+    let a = cx.tensor::<R2<2, 3>>();
+    let b = cx.tensor::<R2<3, 2>>();
+    let c = cx.tensor::<R1<2>>();
+    let _y = (a.matmul(b) + c.expand::<(Const<2>, _), _>()).retrieve();
+    let (mut sc, _) = scalar(&cx);
+    let inputs = random_inputs(&cx, &mut StdRng::seed_from_u64(0));
+    let expected = sc.evaluate_outputs(&inputs);
+    let before = sc.graph.node_count();
+    sc.fuse_fmas();
+    let fmas = sc
+      .graph
+      .node_indices()
+      .filter(|x| sc.scalar_op(*x) == ScalarOp::Fma)
+      .count();
+    // every product of the dot products, 3 per output, feeds one addition
+    assert_eq!(fmas, 2 * 2 * 3);
+    // a product and its addition per fma
+    assert_eq!(sc.graph.node_count(), before - fmas);
+    assert_eq!(sc.evaluate_outputs(&inputs), expected);
+    assert_eq!(verify_scalarization(&cx, &sc, &inputs), Ok(()));
+  }
+
+  #[test]
+  fn test_mean_and_variance_divide_by_one_constant() {
+    let mut cx = Graph::new();
+    let a = cx.tensor::<R2<2, 4>>();
+    let mean = a.mean_reduce::<_, Axis<1>>();
+    let centered = a - mean.expand::<(_, Const<4>), _>();
+    let _mean = mean.retrieve();
+    let _variance = (centered * centered).mean_reduce::<_, Axis<1>>().retrieve();
+    let (sc, _) = scalar(&cx);
+    let g = &sc.graph;
+    assert!(g.node_indices().all(|x| !g.check_node_type::<Recip>(x)));
+    let quarters = g
+      .node_indices()
+      .filter(|x| g.check_node_type::<ConstantOp>(*x) && g.get_op::<ConstantOp>(*x).val == 0.25)
+      .count();
+    assert_eq!(quarters, 1);
+    let inputs = random_inputs(&cx, &mut StdRng::seed_from_u64(0));
+    assert_eq!(verify_scalarization(&cx, &sc, &inputs), Ok(()));
+  }
+
+  #[test]
+  fn test_common_subexpressions_are_merged() {
+    let mut cx = Graph::new();
+    let a = cx.tensor::<R1<4>>();
+    let b = cx.tensor::<R1<4>>();
+    // the product twice, and retrieved on its own too
+    let p = (a * b).retrieve();
+    let _c = ((a * b) + (a * b)).retrieve();
+    let muls = |sc: &ScalarGraph| {
+      sc.graph
+        .node_indices()
+        .filter(|x| sc.scalar_op(*x) == ScalarOp::Mul)
+        .count()
+    };
+    let (sc, _) = scalar(&cx);
+    assert_eq!(muls(&sc), 3 * 4);
+    let (sc, _) = scalar_with(&cx, ScalarCompiler::builder().cse(true).build());
+    // the retrieved product stays, the other two merge into it
+    assert_eq!(muls(&sc), 4);
+    assert_eq!(sc.inputs_tracker.new_outputs[&p.id].len(), 4);
+    let inputs = random_inputs(&cx, &mut StdRng::seed_from_u64(0));
+    assert_eq!(verify_scalarization(&cx, &sc, &inputs), Ok(()));
+  }
+
+  #[test]
+  fn test_constant_comparisons_are_specialized() {
+    let mut cx = Graph::new();
+    let a = cx.tensor::<R1<4>>();
+    let _below = a.less_than(cx.constant(0.5).expand()).retrieve();
+    let _above = cx.constant(-1.0).expand().less_than(a).retrieve();
+    let count = |sc: &ScalarGraph, op: ScalarOp| {
+      sc.graph
+        .node_indices()
+        .filter(|x| sc.scalar_op(*x) == op)
+        .count()
+    };
+    let (sc, _) = scalar(&cx);
+    assert_eq!(count(&sc, ScalarOp::LessThanConst(0.5)), 0);
+    let (sc, _) = scalar_with(
+      &cx,
+      ScalarCompiler::builder().const_comparisons(true).build(),
+    );
+    assert_eq!(count(&sc, ScalarOp::LessThanConst(0.5)), 4);
+    assert_eq!(count(&sc, ScalarOp::GreaterThanConst(-1.0)), 4);
+    assert_eq!(count(&sc, ScalarOp::LessThan), 0);
+    let inputs = [(a.id, vec![-2.0, -1.0, 0.5, 3.0])].into_iter().collect();
+    assert_eq!(verify_scalarization(&cx, &sc, &inputs), Ok(()));
+  }
+}
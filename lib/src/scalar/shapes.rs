@@ -0,0 +1,206 @@
+//!
+//! The sizes the scalarization gives the nodes of a graph, found without scalarizing it.
+//!
+//! Every node becomes as many little nodes as the physical size of its result, read from the views of the node's
+//! output (see [super::get_own_size]). The size has to be a number: a dynamic dimension left in the graph panics in
+//! the middle of the scalarization. [shape_report] sizes every node the same way and reports the ones that can't be
+//! sized instead, with the views that leave the size symbolic.
+//!
+
+use std::{collections::HashMap, fmt};
+
+use itertools::Itertools;
+use luminal::{
+  graph::Graph,
+  prelude::{petgraph::Direction::Outgoing, NodeIndex, ShapeTracker},
+};
+use tracing::trace;
+
+/// A view of a node's output: an edge reading it, or its retrieval.
+#[derive(Debug, Clone, PartialEq)]
+pub struct View {
+  pub output: u8,
+  pub shape: ShapeTracker,
+  /// Physical elements under the view, `None` if they depend on a dynamic dimension.
+  pub physical: Option<usize>,
+}
+
+/// Size of a node as the scalarization computes it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NodeSize {
+  /// The number of little nodes, the largest size of the views.
+  Static(usize),
+  /// Some view has a dynamic size.
+  Dynamic,
+  /// Nothing reads the node and it's not retrieved.
+  Unread,
+  /// Read at another output than the first, every op has one.
+  MultipleOutputs,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct NodeShape {
+  pub node: NodeIndex,
+  /// The op as luminal prints it.
+  pub name: String,
+  pub views: Vec<View>,
+  pub size: NodeSize,
+}
+
+/// Sizes of all the nodes of a graph, in node order. See the module docs.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ShapeReport {
+  pub nodes: Vec<NodeShape>,
+}
+
+impl ShapeReport {
+  /// The nodes with a dynamic size.
+  pub fn dynamic(&self) -> impl Iterator<Item = &NodeShape> {
+    self.nodes.iter().filter(|n| n.size == NodeSize::Dynamic)
+  }
+
+  /// The nodes that can't be sized, the scalarization would panic on them.
+  pub fn unsized_nodes(&self) -> impl Iterator<Item = &NodeShape> {
+    self
+      .nodes
+      .iter()
+      .filter(|n| !matches!(n.size, NodeSize::Static(_)))
+  }
+
+  /// Whether every node has a size.
+  pub fn is_static(&self) -> bool {
+    self.unsized_nodes().next().is_none()
+  }
+
+  /// The sizes of the nodes that have one.
+  pub fn sizes(&self) -> HashMap<NodeIndex, usize> {
+    self
+      .nodes
+      .iter()
+      .filter_map(|n| match n.size {
+        NodeSize::Static(size) => Some((n.node, size)),
+        _ => None,
+      })
+      .collect()
+  }
+}
+
+impl fmt::Display for ShapeReport {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    for n in self.nodes.iter() {
+      write!(f, "{:?} {}: ", n.node, n.name)?;
+      match n.size {
+        NodeSize::Static(size) => writeln!(f, "{}", size)?,
+        NodeSize::Dynamic => {
+          let symbolic = n
+            .views
+            .iter()
+            .filter(|v| v.physical.is_none())
+            .map(|v| format!("{:?}", v.shape.n_physical_elements()))
+            .join(", ");
+          writeln!(f, "dynamic ({})", symbolic)?
+        }
+        NodeSize::Unread => writeln!(f, "unread")?,
+        NodeSize::MultipleOutputs => writeln!(f, "multiple outputs")?,
+      }
+    }
+    Ok(())
+  }
+}
+
+/// The distinct views of the output of the node, see [View].
+pub fn views(x: NodeIndex, graph: &Graph) -> Vec<View> {
+  graph
+    .edges_directed(x, Outgoing)
+    .filter_map(|e| e.weight().as_data())
+    .map(|(_, output, shape)| (output, shape))
+    .chain(graph.to_retrieve.get(&x).copied())
+    .unique()
+    .map(|(output, shape)| View {
+      output,
+      shape,
+      physical: shape.n_physical_elements().to_usize(),
+    })
+    .collect()
+}
+
+/// The size of the node, see [NodeSize].
+/// The views may disagree on the size when one leaves out part of the tensor, the little nodes have to cover
+/// every physical index read so it's the largest.
+pub fn own_size(x: NodeIndex, graph: &Graph) -> NodeSize {
+  size_of_views(x, &views(x, graph))
+}
+
+fn size_of_views(x: NodeIndex, views: &[View]) -> NodeSize {
+  if views.is_empty() {
+    return NodeSize::Unread;
+  }
+  if views.iter().any(|v| v.output != 0) {
+    return NodeSize::MultipleOutputs;
+  }
+  let sizes: Option<Vec<usize>> = views.iter().map(|v| v.physical).collect();
+  match sizes {
+    None => NodeSize::Dynamic,
+    Some(sizes) => {
+      if !sizes.iter().all_equal() {
+        trace!("Views of {:?} disagree on the size: {:?}", x, sizes);
+      }
+      NodeSize::Static(sizes.into_iter().max().unwrap())
+    }
+  }
+}
+
+/// The size of every node of the graph, see the module docs.
+pub fn shape_report(graph: &Graph) -> ShapeReport {
+  let nodes = graph
+    .node_indices()
+    .sorted()
+    .map(|x| {
+      let views = views(x, graph);
+      NodeShape {
+        node: x,
+        name: format!("{:?}", graph.node_weight(x).unwrap()),
+        size: size_of_views(x, &views),
+        views,
+      }
+    })
+    .collect();
+  ShapeReport { nodes }
+}
+
+#[cfg(test)]
+mod tests {
+  use luminal::{
+    graph::Graph,
+    shape::{Const, Dyn, R1, R2},
+  };
+
+  use super::{shape_report, NodeSize};
+
+  #[test]
+  fn test_shape_report() {
+    let mut cx = Graph::new();
+    let a = cx.tensor::<R2<2, 3>>();
+    let b = cx.tensor::<R1<3>>();
+    let c = (a + b.expand()).retrieve();
+    let _unread = cx.tensor::<R1<4>>();
+    let report = shape_report(&cx);
+    assert!(!report.is_static());
+    let sizes = report.sizes();
+    assert_eq!((sizes[&a.id], sizes[&b.id], sizes[&c.id]), (6, 3, 6));
+    assert_eq!(report.unsized_nodes().count(), 1);
+    assert_eq!(report.dynamic().count(), 0);
+
+    let d = cx.tensor::<(Dyn<'s'>, Const<3>)>();
+    let e = (d * 2.0).retrieve();
+    let report = shape_report(&cx);
+    let dynamic = report.dynamic().map(|n| n.node).collect::<Vec<_>>();
+    assert!(dynamic.contains(&d.id) && dynamic.contains(&e.id));
+    assert!(!dynamic.contains(&a.id));
+    assert!(report.to_string().contains("dynamic"));
+    assert_eq!(
+      report.nodes.iter().find(|n| n.node == a.id).unwrap().size,
+      NodeSize::Static(6)
+    );
+  }
+}
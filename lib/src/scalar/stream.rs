@@ -0,0 +1,527 @@
+//!
+//! Streaming scalarization: the scalar nodes are handed to a [ScalarSink] as they are made, instead of building a [super::ScalarGraph].
+//!
+//! For graphs whose scalar graph doesn't fit comfortably in memory (conv nets). The tensor graph is walked forwards, from the inputs,
+//! so every scalar node comes after its arguments and a backend can constrain it right away. All we hold are the ids of the little nodes
+//! of the tensors that are still to be used.
+//!
+//! Unlike [super::scalar], no rewrites run on the stream (ReLU and lookup fusion, canonical ids), and a Recip of a Sqrt
+//! is the Recip of the lowered Sqrt rather than [super::Scalarize::rsqrt_iterations] of its own.
+//! Ids are consecutive in the order of emission, which is deterministic for a given tensor graph.
+//!
+
+use std::{
+  collections::HashMap,
+  error::Error,
+  fmt,
+  io::{self, Write},
+};
+
+use itertools::Itertools;
+use luminal::{
+  graph::Graph,
+  op::{
+    Add, Constant, Contiguous, Function, LessThan, MaxReduce, Mod, Mul, Operator, Recip, Sqrt,
+    SumReduce,
+  },
+  prelude::{
+    petgraph::{
+      self,
+      visit::EdgeRef,
+      Direction::{Incoming, Outgoing},
+    },
+    NodeIndex, ShapeTracker,
+  },
+};
+
+use crate::dtype::tensor_f32;
+
+use super::{
+  check_supported, get_own_size, live_nodes, lookup_kind, Gather, IndexCache, LookupKind, Pool2D,
+  ReductionStyle, Scalarize, UnsupportedOp, UnsupportedReason,
+};
+
+/// The op of a streamed scalar node. Lookups name their function, there's no table registry in the stream.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StreamOp {
+  /// The element `index` (physical) of the input `tensor` of the original graph.
+  Input {
+    tensor: NodeIndex,
+    index: usize,
+  },
+  Constant(f32),
+  Add,
+  Mul,
+  LessThan,
+  Mod,
+  Recip,
+  Max,
+  Lookup(LookupKind),
+}
+
+impl fmt::Display for StreamOp {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      StreamOp::Input { tensor, index } => write!(f, "input {} {}", tensor.index(), index),
+      StreamOp::Constant(val) => write!(f, "const {}", val),
+      StreamOp::Add => write!(f, "add"),
+      StreamOp::Mul => write!(f, "mul"),
+      StreamOp::LessThan => write!(f, "lt"),
+      StreamOp::Mod => write!(f, "mod"),
+      StreamOp::Recip => write!(f, "recip"),
+      StreamOp::Max => write!(f, "max"),
+      StreamOp::Lookup(kind) => write!(f, "lookup {}", kind.name()),
+    }
+  }
+}
+
+/// Receives the scalar graph node by node.
+pub trait ScalarSink {
+  /// A new node `id` applying `op` to the nodes `args` (in argument order), all of them emitted before.
+  /// Ids are consecutive from 0.
+  fn node(&mut self, id: usize, op: StreamOp, args: &[usize]) -> io::Result<()>;
+  /// The little nodes of a retrieved tensor of the original graph, in the order of physical indices.
+  /// Any earlier nodes, e.g. the inputs themselves for a retrieved view of an input.
+  fn output(&mut self, tensor: NodeIndex, little: &[usize]) -> io::Result<()>;
+}
+
+impl<S: ScalarSink + ?Sized> ScalarSink for &mut S {
+  fn node(&mut self, id: usize, op: StreamOp, args: &[usize]) -> io::Result<()> {
+    (**self).node(id, op, args)
+  }
+
+  fn output(&mut self, tensor: NodeIndex, little: &[usize]) -> io::Result<()> {
+    (**self).output(tensor, little)
+  }
+}
+
+/// Writes the stream as text: a line `<id> = <op> <args>` per node and `output <tensor> <ids>` per retrieved tensor.
+pub struct ScalarWriter<W: Write> {
+  pub out: W,
+}
+
+impl<W: Write> ScalarWriter<W> {
+  pub fn new(out: W) -> Self {
+    ScalarWriter { out }
+  }
+}
+
+impl<W: Write> ScalarSink for ScalarWriter<W> {
+  fn node(&mut self, id: usize, op: StreamOp, args: &[usize]) -> io::Result<()> {
+    writeln!(self.out, "{} = {} {}", id, op, args.iter().join(" "))
+  }
+
+  fn output(&mut self, tensor: NodeIndex, little: &[usize]) -> io::Result<()> {
+    writeln!(
+      self.out,
+      "output {} {}",
+      tensor.index(),
+      little.iter().join(" ")
+    )
+  }
+}
+
+struct Stream<S> {
+  sink: S,
+  next: usize,
+  /// shared by the masked out (padding) elements, as in [super::scalar]
+  zero: Option<usize>,
+  index_cache: IndexCache,
+}
+
+impl<S: ScalarSink> Stream<S> {
+  fn emit(&mut self, op: StreamOp, args: &[usize]) -> io::Result<usize> {
+    let id = self.next;
+    self.sink.node(id, op, args)?;
+    self.next += 1;
+    Ok(id)
+  }
+
+  fn zero(&mut self) -> io::Result<usize> {
+    match self.zero {
+      Some(z) => Ok(z),
+      None => {
+        let z = self.emit(StreamOp::Constant(0.0), &[])?;
+        self.zero = Some(z);
+        Ok(z)
+      }
+    }
+  }
+
+  fn chain(&mut self, op: StreamOp, operands: &[usize]) -> io::Result<usize> {
+    operands[1..]
+      .iter()
+      .try_fold(operands[0], |acc, y| self.emit(op, &[acc, *y]))
+  }
+
+  /// Reduces the nodes with the op, laid out in the given style.
+  /// Unlike [super::scalar] the chains start from the first element, there's no neutral element.
+  fn reduce(
+    &mut self,
+    op: StreamOp,
+    operands: &[usize],
+    style: ReductionStyle,
+  ) -> io::Result<usize> {
+    match style {
+      ReductionStyle::Chain => self.chain(op, operands),
+      ReductionStyle::BalancedTree => {
+        let mut level = operands.to_vec();
+        while level.len() > 1 {
+          level = level
+            .chunks(2)
+            .map(|pair| match pair {
+              [l, r] => self.emit(op, &[*l, *r]),
+              [single] => Ok(*single),
+              _ => unreachable!(),
+            })
+            .collect::<io::Result<_>>()?;
+        }
+        Ok(level[0])
+      }
+      ReductionStyle::Chunked(k) => {
+        assert!(k > 0, "Chunks of a reduction can't be empty");
+        let results = operands
+          .chunks(k)
+          .map(|c| self.chain(op, c))
+          .collect::<io::Result<Vec<_>>>()?;
+        self.chain(op, &results)
+      }
+    }
+  }
+
+  /// The node of the logical `index` of the view `shape` of the source's little nodes.
+  fn arg(&mut self, little: &[usize], shape: &ShapeTracker, index: usize) -> io::Result<usize> {
+    match self.index_cache.logical_to_physical(shape, index) {
+      Some(i) => Ok(little[i]),
+      None => self.zero(),
+    }
+  }
+}
+
+/// Why a graph wasn't streamed.
+#[derive(Debug)]
+pub enum StreamError {
+  /// Everything the stream can't lower, see [check_streamable]. Found before any node is emitted.
+  Unsupported(Vec<UnsupportedOp>),
+  /// Of the sink.
+  Io(io::Error),
+}
+
+impl fmt::Display for StreamError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      StreamError::Unsupported(ops) => {
+        write!(f, "Can't stream the ops: {}", ops.iter().join(", "))
+      }
+      StreamError::Io(e) => write!(f, "{}", e),
+    }
+  }
+}
+
+impl Error for StreamError {}
+
+impl From<io::Error> for StreamError {
+  fn from(e: io::Error) -> Self {
+    StreamError::Io(e)
+  }
+}
+
+/// Everything in the graph the stream can't lower, in node order: what [check_supported] reports, and the ops
+/// only [super::scalar] lowers ([Pool2D], [Gather]).
+pub fn check_streamable(cx: &Graph) -> Vec<UnsupportedOp> {
+  let live = live_nodes(cx);
+  let mut unsupported = check_supported(cx);
+  unsupported.extend(
+    cx.node_indices()
+      .filter(|x| live.contains(x))
+      .filter(|x| cx.check_node_type::<Pool2D>(*x) || cx.check_node_type::<Gather>(*x))
+      .map(|x| unknown_op(cx, x)),
+  );
+  // stable, the reasons of a node stay in order
+  unsupported.sort_by_key(|u| u.node);
+  unsupported
+}
+
+fn unknown_op(cx: &Graph, x: NodeIndex) -> UnsupportedOp {
+  UnsupportedOp {
+    node: x,
+    name: format!("{:?}", cx.node_weight(x).unwrap()),
+    reason: UnsupportedReason::UnknownOp,
+  }
+}
+
+/// Streams the scalar graph of `graph` into the sink, see the module docs. Sqrt is lowered with the default [Scalarize] settings.
+pub fn scalarize_streaming(graph: &Graph, sink: impl ScalarSink) -> Result<(), StreamError> {
+  Scalarize::default().stream(graph, sink)
+}
+
+impl Scalarize {
+  /// Streams the scalar graph of `graph` into the sink, see [scalarize_streaming].
+  pub fn stream(&self, graph: &Graph, sink: impl ScalarSink) -> Result<(), StreamError> {
+    let unsupported = check_streamable(graph);
+    if !unsupported.is_empty() {
+      return Err(StreamError::Unsupported(unsupported));
+    }
+    let mut stream = Stream {
+      sink,
+      next: 0,
+      zero: None,
+      index_cache: IndexCache::default(),
+    };
+    let live = live_nodes(graph);
+    // ids of the little nodes of the processed tensors, dropped after the last use
+    let mut little: HashMap<NodeIndex, Vec<usize>> = HashMap::new();
+    let mut uses_left: HashMap<NodeIndex, usize> = graph
+      .node_indices()
+      .map(|x| {
+        let uses = graph
+          .edges_directed(x, Outgoing)
+          .filter(|e| e.weight().as_data().is_some() && live.contains(&e.target()))
+          .count();
+        (x, uses)
+      })
+      .collect();
+
+    for x in petgraph::algo::toposort(&graph.graph, None).unwrap() {
+      if !live.contains(&x) {
+        continue;
+      }
+      let incoming: Vec<(ShapeTracker, NodeIndex)> = graph
+        .edges_directed(x, Incoming)
+        .filter_map(|e| e.weight().as_data().map(|d| (d, e.source())))
+        .sorted_by_key(|((inp, _, _), _)| *inp)
+        .map(|((_, _, shape), y)| (shape, y))
+        .collect();
+      let size = get_own_size(x, graph);
+
+      let ids: Vec<usize> = if incoming.is_empty() {
+        if graph.check_node_type::<Function>(x) {
+          (0..size)
+            .map(|index| stream.emit(StreamOp::Input { tensor: x, index }, &[]))
+            .collect::<io::Result<_>>()?
+        } else if graph.check_node_type::<Constant>(x) {
+          let op = graph.get_op::<Constant>(x);
+          let val = tensor_f32(&Constant(op.0.clone(), op.1).process(vec![])[0]).unwrap()[0];
+          vec![stream.emit(StreamOp::Constant(val), &[])?]
+        } else {
+          return Err(StreamError::Unsupported(vec![unknown_op(graph, x)]));
+        }
+      } else if let Some(((sh, y),)) = incoming.iter().collect_tuple() {
+        let ys = &little[y];
+        let unop = if graph.check_node_type::<Recip>(x) {
+          Some(StreamOp::Recip)
+        } else {
+          lookup_kind(graph, x).map(StreamOp::Lookup)
+        };
+        if let Some(op) = unop {
+          (0..size)
+            .map(|i| {
+              let a = stream.arg(ys, sh, i)?;
+              stream.emit(op, &[a])
+            })
+            .collect::<io::Result<_>>()?
+        } else if graph.check_node_type::<Contiguous>(x) {
+          // no nodes, the result is the view of the input
+          (0..size)
+            .map(|i| stream.arg(ys, sh, i))
+            .collect::<io::Result<_>>()?
+        } else if graph.check_node_type::<Sqrt>(x) {
+          assert!(
+            self.sqrt_iterations > 0,
+            "Sqrt needs at least one Newton iteration"
+          );
+          let half = stream.emit(StreamOp::Constant(0.5), &[])?;
+          let guess = stream.emit(StreamOp::Constant(self.sqrt_initial_guess), &[])?;
+          (0..size)
+            .map(|i| {
+              let a = stream.arg(ys, sh, i)?;
+              (0..self.sqrt_iterations).try_fold(guess, |y, _| {
+                // y' = (y + x * recip(y)) * 0.5
+                let r = stream.emit(StreamOp::Recip, &[y])?;
+                let m = stream.emit(StreamOp::Mul, &[a, r])?;
+                let s = stream.emit(StreamOp::Add, &[y, m])?;
+                stream.emit(StreamOp::Mul, &[s, half])
+              })
+            })
+            .collect::<io::Result<_>>()?
+        } else if graph.check_node_type::<SumReduce>(x) || graph.check_node_type::<MaxReduce>(x) {
+          let (op, ax) = if graph.check_node_type::<SumReduce>(x) {
+            (StreamOp::Add, graph.get_op::<SumReduce>(x).0)
+          } else {
+            (StreamOp::Max, graph.get_op::<MaxReduce>(x).0)
+          };
+          let dims = sh.shape_usize();
+          let ax_len = dims[ax];
+          let back_size = dims.iter().skip(ax + 1).product::<usize>().max(1);
+          (0..size)
+            .map(|i| {
+              let (front_i, back_i) = (i / back_size, i % back_size);
+              let first = front_i * back_size * ax_len + back_i;
+              let operands = (0..ax_len)
+                .map(|k| stream.arg(ys, sh, first + k * back_size))
+                .collect::<io::Result<Vec<_>>>()?;
+              stream.reduce(op, &operands, self.reduction)
+            })
+            .collect::<io::Result<_>>()?
+        } else {
+          return Err(StreamError::Unsupported(vec![unknown_op(graph, x)]));
+        }
+      } else if let Some(((l_sh, l), (r_sh, r))) = incoming.iter().collect_tuple() {
+        let op = if graph.check_node_type::<Add>(x) {
+          StreamOp::Add
+        } else if graph.check_node_type::<Mul>(x) {
+          StreamOp::Mul
+        } else if graph.check_node_type::<LessThan>(x) {
+          StreamOp::LessThan
+        } else if graph.check_node_type::<Mod>(x) {
+          StreamOp::Mod
+        } else {
+          return Err(StreamError::Unsupported(vec![unknown_op(graph, x)]));
+        };
+        let (ls, rs) = (&little[l], &little[r]);
+        (0..size)
+          .map(|i| {
+            let a = stream.arg(ls, l_sh, i)?;
+            let b = stream.arg(rs, r_sh, i)?;
+            stream.emit(op, &[a, b])
+          })
+          .collect::<io::Result<_>>()?
+      } else {
+        return Err(StreamError::Unsupported(vec![unknown_op(graph, x)]));
+      };
+
+      if graph.to_retrieve.contains_key(&x) {
+        stream.sink.output(x, &ids)?;
+      }
+      for (_, y) in incoming.iter() {
+        let n = uses_left.get_mut(y).unwrap();
+        *n -= 1;
+        if *n == 0 {
+          little.remove(y);
+        }
+      }
+      if uses_left[&x] > 0 {
+        little.insert(x, ids);
+      }
+    }
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::{collections::HashMap, io};
+
+  use luminal::{
+    graph::Graph,
+    prelude::{NodeIndex, ShapeTracker},
+    shape::R1,
+  };
+  use proptest::prelude::*;
+  use rand::{rngs::StdRng, SeedableRng};
+
+  use super::{scalarize_streaming, ScalarSink, ScalarWriter, StreamError, StreamOp};
+  use crate::scalar::{
+    evaluate_tensor_graph, random_inputs,
+    testing::{arb_expr, build_graph},
+    SCALARIZATION_TOLERANCE,
+  };
+
+  /// Evaluates the nodes as they come.
+  struct EvalSink<'a> {
+    inputs: &'a HashMap<NodeIndex, Vec<f32>>,
+    values: Vec<f32>,
+    outputs: HashMap<NodeIndex, Vec<f32>>,
+  }
+
+  impl ScalarSink for EvalSink<'_> {
+    fn node(&mut self, id: usize, op: StreamOp, args: &[usize]) -> io::Result<()> {
+      assert_eq!(id, self.values.len(), "Ids are consecutive");
+      let a: Vec<f32> = args.iter().map(|y| self.values[*y]).collect();
+      let v = match op {
+        StreamOp::Input { tensor, index } => self.inputs[&tensor][index],
+        StreamOp::Constant(val) => val,
+        StreamOp::Add => a[0] + a[1],
+        StreamOp::Mul => a[0] * a[1],
+        StreamOp::LessThan => (a[0] < a[1]) as i32 as f32,
+        StreamOp::Mod => a[0] % a[1],
+        StreamOp::Recip => 1.0 / a[0],
+        StreamOp::Max => f32::max(a[0], a[1]),
+        StreamOp::Lookup(kind) => kind.eval(a[0]),
+      };
+      self.values.push(v);
+      Ok(())
+    }
+
+    fn output(&mut self, tensor: NodeIndex, little: &[usize]) -> io::Result<()> {
+      let v = little.iter().map(|y| self.values[*y]).collect();
+      self.outputs.insert(tensor, v);
+      Ok(())
+    }
+  }
+
+  #[test]
+  fn test_writer() {
+    let mut cx = Graph::new();
+    let a = cx.tensor::<R1<2>>();
+    let b = cx.tensor::<R1<2>>();
+    let c = (a + b).retrieve();
+    let mut writer = ScalarWriter::new(vec![]);
+    scalarize_streaming(&cx, &mut writer).unwrap();
+    let text = String::from_utf8(writer.out).unwrap();
+    let lines: Vec<&str> = text.lines().collect();
+    assert_eq!(lines.len(), 7);
+    assert_eq!(lines.iter().filter(|l| l.contains("= input")).count(), 4);
+    assert!(lines[4].starts_with("4 = add ") && lines[5].starts_with("5 = add "));
+    assert_eq!(lines[6], format!("output {} 4 5", c.id.index()));
+  }
+
+  #[test]
+  fn test_unsupported_ops_are_reported() {
+    #[derive(Debug)]
+    struct Unknown;
+    impl luminal::op::Operator for Unknown {
+      fn process(
+        &mut self,
+        _inp: Vec<(luminal::op::InputTensor, ShapeTracker)>,
+      ) -> Vec<luminal::prelude::Tensor> {
+        vec![]
+      }
+    }
+    let mut cx = Graph::new();
+    let a = cx.tensor::<R1<4>>();
+    let unknown = cx.add_op(Unknown).input(a.id, 0, a.shape).finish();
+    cx.to_retrieve.insert(unknown, (0, a.shape));
+    let mut writer = ScalarWriter::new(vec![]);
+    match scalarize_streaming(&cx, &mut writer) {
+      Err(StreamError::Unsupported(ops)) => {
+        assert_eq!(
+          ops.iter().map(|u| u.node).collect::<Vec<_>>(),
+          vec![unknown]
+        );
+      }
+      other => panic!("Expected the unsupported op, got {:?}", other),
+    }
+    // nothing is emitted
+    assert!(writer.out.is_empty());
+  }
+
+  proptest! {
+    #![proptest_config(ProptestConfig::with_cases(64))]
+
+    #[test]
+    fn test_stream_random_exprs(expr in arb_expr(3), seed in any::<u64>()) {
+      let (cx, _) = build_graph(&expr);
+      let inputs = random_inputs(&cx, &mut StdRng::seed_from_u64(seed));
+      let mut sink = EvalSink { inputs: &inputs, values: vec![], outputs: HashMap::new() };
+      scalarize_streaming(&cx, &mut sink).unwrap();
+      for (x, expected) in evaluate_tensor_graph(&cx, &inputs) {
+        let got = &sink.outputs[&x];
+        prop_assert_eq!(got.len(), expected.len());
+        for (g, e) in got.iter().zip(expected.iter()) {
+          prop_assert!((g - e).abs() <= SCALARIZATION_TOLERANCE * g.abs().max(e.abs()).max(1.0), "expr: {:?}", expr);
+        }
+      }
+    }
+  }
+}
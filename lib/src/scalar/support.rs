@@ -0,0 +1,189 @@
+//!
+//! What the scalarization can't handle, found up front.
+//!
+//! The scalar compiler works on a copy of the graph it takes apart node by node, and panics on the first node it
+//! can't lower. [check_supported] scans the whole graph for the same conditions without touching it, so a model gets
+//! the complete list at once. [super::scalar] runs it first.
+//!
+//! Nodes no retrieved node reads are left out of the scalar graph, whatever they are, so they aren't checked either.
+//!
+
+use itertools::Itertools;
+use luminal::{
+  graph::Graph,
+  op::{
+    Add, Constant, Contiguous, Function, LessThan, MaxReduce, Mod, Mul, Recip, Sqrt, SumReduce,
+  },
+  prelude::{
+    petgraph::{
+      visit::EdgeRef,
+      Direction::{Incoming, Outgoing},
+    },
+    NodeIndex,
+  },
+};
+
+use super::{live_nodes, lookup_kind, Gather, Pool2D};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum UnsupportedReason {
+  /// No lowering for the op.
+  UnknownOp,
+  /// A known op with another number of inputs than it's lowered with.
+  Arity { expected: usize, found: usize },
+  /// Read at another output than the first, every op has one.
+  MultipleOutputs,
+  /// A dimension of the node's output or of an input edge is not a number.
+  DynamicShape,
+  /// A constant that's not a single value.
+  NonScalarConstant,
+}
+
+/// A node the scalarization can't handle and why. A node is reported once per reason.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnsupportedOp {
+  pub node: NodeIndex,
+  /// The op as luminal prints it, for inputs with the name of the tensor.
+  pub name: String,
+  pub reason: UnsupportedReason,
+}
+
+impl std::fmt::Display for UnsupportedOp {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    write!(f, "{} ({:?}): {:?}", self.name, self.node, self.reason)
+  }
+}
+
+/// Number of inputs the scalar compiler lowers the op of the node with, `None` for ops it doesn't lower.
+/// Keep in sync with [super::Scalarize].
+fn lowered_arity(graph: &Graph, x: NodeIndex) -> Option<usize> {
+  if graph.check_node_type::<Function>(x) || graph.check_node_type::<Constant>(x) {
+    Some(0)
+  } else if graph.check_node_type::<Recip>(x)
+    || lookup_kind(graph, x).is_some()
+    || graph.check_node_type::<Sqrt>(x)
+    || graph.check_node_type::<Contiguous>(x)
+    || graph.check_node_type::<SumReduce>(x)
+    || graph.check_node_type::<MaxReduce>(x)
+    || graph.check_node_type::<Pool2D>(x)
+  {
+    Some(1)
+  } else if graph.check_node_type::<Add>(x)
+    || graph.check_node_type::<Mul>(x)
+    || graph.check_node_type::<LessThan>(x)
+    || graph.check_node_type::<Mod>(x)
+    || graph.check_node_type::<Gather>(x)
+  {
+    Some(2)
+  } else {
+    None
+  }
+}
+
+/// Everything in the graph the scalarization can't handle, in node order. Empty if it scalarizes.
+pub fn check_supported(cx: &Graph) -> Vec<UnsupportedOp> {
+  let live = live_nodes(cx);
+  let mut unsupported = vec![];
+  for x in cx.node_indices().filter(|x| live.contains(x)).sorted() {
+    let mut report = |reason| {
+      unsupported.push(UnsupportedOp {
+        node: x,
+        name: format!("{:?}", cx.node_weight(x).unwrap()),
+        reason,
+      })
+    };
+    let inputs = cx
+      .edges_directed(x, Incoming)
+      .filter_map(|e| e.weight().as_data())
+      .collect_vec();
+    // the views of the node's output, as the compiler sizes the node
+    let views = cx
+      .edges_directed(x, Outgoing)
+      .filter(|e| live.contains(&e.target()))
+      .filter_map(|e| e.weight().as_data())
+      .map(|(_, output, shape)| (output, shape))
+      .chain(cx.to_retrieve.get(&x).copied())
+      .collect_vec();
+
+    match lowered_arity(cx, x) {
+      None => report(UnsupportedReason::UnknownOp),
+      Some(expected) if expected != inputs.len() => report(UnsupportedReason::Arity {
+        expected,
+        found: inputs.len(),
+      }),
+      Some(_) => {}
+    }
+    if views.iter().any(|(output, _)| *output != 0) {
+      report(UnsupportedReason::MultipleOutputs);
+    }
+    let sizes = views
+      .iter()
+      .map(|(_, shape)| shape.n_physical_elements().to_usize())
+      .collect_vec();
+    let dynamic = sizes.iter().any(|n| n.is_none())
+      || inputs
+        .iter()
+        .any(|(_, _, shape)| shape.n_elements().to_usize().is_none());
+    if dynamic {
+      report(UnsupportedReason::DynamicShape);
+    } else if cx.check_node_type::<Constant>(x) && sizes.iter().any(|n| *n != Some(1)) {
+      report(UnsupportedReason::NonScalarConstant);
+    }
+  }
+  unsupported
+}
+
+#[cfg(test)]
+mod tests {
+  use luminal::{
+    graph::Graph,
+    prelude::ShapeTracker,
+    shape::{R1, R2},
+  };
+
+  use super::{check_supported, UnsupportedReason};
+
+  #[test]
+  fn test_check_supported() {
+    let mut cx = Graph::new();
+    let a = cx.tensor::<R2<2, 3>>();
+    let b = cx.tensor::<R2<3, 4>>();
+    let _c = a.matmul(b).relu().retrieve();
+    assert_eq!(check_supported(&cx), vec![]);
+
+    // lookups
+    let _log = a.log2().retrieve();
+    let _sin = a.sin().retrieve();
+    assert_eq!(check_supported(&cx), vec![]);
+
+    // every unsupported node is reported, not just the first one
+    #[derive(Debug)]
+    struct Unknown;
+    impl luminal::op::Operator for Unknown {
+      fn process(
+        &mut self,
+        _inp: Vec<(luminal::op::InputTensor, ShapeTracker)>,
+      ) -> Vec<luminal::prelude::Tensor> {
+        vec![]
+      }
+    }
+    let unknown = cx.add_op(Unknown).input(a.id, 0, a.shape).finish();
+    let other = cx.add_op(Unknown).input(b.id, 0, b.shape).finish();
+    cx.to_retrieve.insert(unknown, (0, a.shape));
+    cx.to_retrieve.insert(other, (0, b.shape));
+    // what nothing retrieved reads isn't lowered at all
+    let _dead = cx.add_op(Unknown).input(a.id, 0, a.shape).finish();
+    let _unused = cx.tensor::<R1<3>>() * 2.0;
+    let found = check_supported(&cx)
+      .into_iter()
+      .map(|u| (u.node, u.reason))
+      .collect::<Vec<_>>();
+    assert_eq!(
+      found,
+      vec![
+        (unknown, UnsupportedReason::UnknownOp),
+        (other, UnsupportedReason::UnknownOp),
+      ]
+    );
+  }
+}
@@ -0,0 +1,251 @@
+//!
+//! Repeated structure of the scalar graph: the neurons of a layer are the same little circuit on other inputs.
+//!
+//! The graph is cut into trees, one per node used more than once (or by nothing, or retrieved): the tree of such a
+//! root takes in the nodes only it uses, recursively, so a neuron of an MLP is its products, their sum and the
+//! activation. Inputs, constants and the roots of other trees are the bindings of a tree.
+//! Trees computing the same ops on the same pattern of bindings are instances of one [Template].
+//!
+//! A backend can lay out a template once and repeat it over its instances (copy constraints, custom gates, folding)
+//! instead of flattening every instance. The instances partition the computing nodes of the graph, see [Templates].
+//!
+
+use std::collections::HashMap;
+
+use itertools::Itertools;
+use luminal::prelude::{
+  petgraph::{
+    visit::EdgeRef,
+    Direction::{Incoming, Outgoing},
+  },
+  NodeIndex,
+};
+
+use super::{ScalarGraph, ScalarOp};
+
+/// An argument of a [TemplateStep].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TemplateArg {
+  /// The value bound to the instance, an index into [TemplateInstance::bindings].
+  Binding(usize),
+  /// The result of an earlier step.
+  Step(usize),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TemplateStep {
+  pub op: ScalarOp,
+  /// In argument order.
+  pub args: Vec<TemplateArg>,
+}
+
+/// One occurrence of a template in the graph.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TemplateInstance {
+  /// The node computing the last step, the result of the instance.
+  pub root: NodeIndex,
+  /// The node of every step, in the order of the steps.
+  pub nodes: Vec<NodeIndex>,
+  /// Nodes outside of the instance it reads, in the order of [TemplateArg::Binding].
+  pub bindings: Vec<NodeIndex>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Template {
+  /// The computation, every step after its arguments. The last one is the result.
+  pub steps: Vec<TemplateStep>,
+  pub bindings: usize,
+  pub instances: Vec<TemplateInstance>,
+}
+
+impl Template {
+  /// The result of the template on the values of the bindings.
+  pub fn evaluate(&self, bindings: &[f32]) -> f32 {
+    assert_eq!(bindings.len(), self.bindings, "Wrong number of bindings");
+    let mut values: Vec<f32> = Vec::with_capacity(self.steps.len());
+    for step in self.steps.iter() {
+      let args = step
+        .args
+        .iter()
+        .map(|arg| match arg {
+          TemplateArg::Binding(i) => bindings[*i],
+          TemplateArg::Step(i) => values[*i],
+        })
+        .collect_vec();
+      values.push(step.op.eval(&args));
+    }
+    *values.last().unwrap()
+  }
+}
+
+/// The templates of a scalar graph, see the module docs.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Templates {
+  /// Ordered by the first of their roots, the index is the template id.
+  pub templates: Vec<Template>,
+  /// The (template id, instance) of every node of an instance.
+  pub instance_of: HashMap<NodeIndex, (usize, usize)>,
+}
+
+impl Templates {
+  /// Nodes computed by the instances of the templates.
+  pub fn covered(&self) -> usize {
+    self.instance_of.len()
+  }
+}
+
+impl ScalarGraph {
+  /// The templates with at least two instances, see [ScalarGraph::templates_with].
+  pub fn templates(&self) -> Templates {
+    self.templates_with(2)
+  }
+
+  /// Groups the trees of the graph (see the module docs) by their structure,
+  /// keeping the templates with at least `min_instances` instances. With `min_instances` 1 every computing node is
+  /// in an instance.
+  pub fn templates_with(&self, min_instances: usize) -> Templates {
+    let graph = &self.graph;
+    let args = |x: NodeIndex| -> Vec<NodeIndex> {
+      graph
+        .edges_directed(x, Incoming)
+        .filter_map(|e| e.weight().as_data().map(|(inp, _, _)| (inp, e.source())))
+        .sorted_by_key(|(inp, _)| *inp)
+        .map(|(_, y)| y)
+        .collect()
+    };
+    let ops: HashMap<NodeIndex, ScalarOp> = graph
+      .node_indices()
+      .map(|x| (x, self.scalar_op(x)))
+      .collect();
+    let is_source = |x: NodeIndex| matches!(ops[&x], ScalarOp::Input | ScalarOp::Constant(_));
+    let is_root = |x: NodeIndex| {
+      let users = graph
+        .edges_directed(x, Outgoing)
+        .filter(|e| e.weight().as_data().is_some())
+        .map(|e| e.target())
+        .unique()
+        .count();
+      !is_source(x) && (users != 1 || graph.to_retrieve.contains_key(&x))
+    };
+    let internal = |x: NodeIndex| !is_source(x) && !is_root(x);
+
+    // the structure of the tree (its steps, debug printed) to the template
+    let mut by_structure: HashMap<String, Template> = HashMap::new();
+    let mut order: Vec<String> = vec![];
+    for root in graph.node_indices().sorted().filter(|x| is_root(*x)) {
+      let mut steps: Vec<TemplateStep> = vec![];
+      let mut instance = TemplateInstance {
+        root,
+        nodes: vec![],
+        bindings: vec![],
+      };
+      let mut step_of: HashMap<NodeIndex, usize> = HashMap::new();
+      // post order, iterative as the trees of long sums are deep
+      let mut stack = vec![(root, false)];
+      while let Some((x, expanded)) = stack.pop() {
+        if step_of.contains_key(&x) {
+          continue;
+        }
+        let xs = args(x);
+        if !expanded {
+          stack.push((x, true));
+          for y in xs.iter().rev().filter(|y| internal(**y)) {
+            stack.push((*y, false));
+          }
+          continue;
+        }
+        let step_args = xs
+          .iter()
+          .map(|y| {
+            if internal(*y) {
+              TemplateArg::Step(step_of[y])
+            } else {
+              let i = instance
+                .bindings
+                .iter()
+                .position(|b| b == y)
+                .unwrap_or_else(|| {
+                  instance.bindings.push(*y);
+                  instance.bindings.len() - 1
+                });
+              TemplateArg::Binding(i)
+            }
+          })
+          .collect();
+        step_of.insert(x, steps.len());
+        steps.push(TemplateStep {
+          op: ops[&x],
+          args: step_args,
+        });
+        instance.nodes.push(x);
+      }
+
+      let key = format!("{:?}", steps);
+      let template = by_structure.entry(key.clone()).or_insert_with(|| {
+        order.push(key);
+        Template {
+          steps,
+          bindings: instance.bindings.len(),
+          instances: vec![],
+        }
+      });
+      template.instances.push(instance);
+    }
+
+    let mut templates = Templates::default();
+    for key in order {
+      let template = by_structure.remove(&key).unwrap();
+      if template.instances.len() < min_instances {
+        continue;
+      }
+      let id = templates.templates.len();
+      for (i, instance) in template.instances.iter().enumerate() {
+        for x in instance.nodes.iter() {
+          templates.instance_of.insert(*x, (id, i));
+        }
+      }
+      templates.templates.push(template);
+    }
+    templates
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use luminal::{graph::Graph, shape::R2};
+  use rand::{rngs::StdRng, SeedableRng};
+
+  use crate::scalar::{random_inputs, scalar, ScalarOp};
+
+  #[test]
+  fn test_neurons_are_instances_of_one_template() {
+    let mut cx = Graph::new();
+    let a = cx.tensor::<R2<2, 3>>();
+    let b = cx.tensor::<R2<3, 4>>();
+    let _c = a.matmul(b).relu().retrieve();
+    let (sc, _) = scalar(&cx);
+    let templates = sc.templates();
+    assert_eq!(templates.templates.len(), 1);
+    let template = &templates.templates[0];
+    assert_eq!(template.instances.len(), 8);
+    let count = |op: ScalarOp| template.steps.iter().filter(|s| s.op == op).count();
+    assert_eq!((count(ScalarOp::Mul), count(ScalarOp::Relu)), (3, 1));
+    // a row of a and a column of b
+    assert_eq!(template.bindings, 6);
+    let computing = sc
+      .graph
+      .node_indices()
+      .filter(|x| !matches!(sc.scalar_op(*x), ScalarOp::Input | ScalarOp::Constant(_)))
+      .count();
+    assert_eq!(templates.covered(), computing);
+
+    let inputs = random_inputs(&cx, &mut StdRng::seed_from_u64(0));
+    let values = sc.evaluate(&inputs);
+    for instance in template.instances.iter() {
+      let bindings: Vec<f32> = instance.bindings.iter().map(|x| values[x]).collect();
+      assert_eq!(template.evaluate(&bindings), values[&instance.root]);
+    }
+
+    assert!(sc.templates_with(9).templates.is_empty());
+  }
+}
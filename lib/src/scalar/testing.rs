@@ -0,0 +1,329 @@
+//!
+//! Random small tensor computations for testing scalarization (and backends) against the tensor evaluation.
+//!
+//! Expressions are generated shape-first: we pick the wanted output shape and generate an expression producing it,
+//! so that every Add/Mul gets operands of matching shapes.
+//!
+
+use itertools::Itertools;
+use luminal::{
+  graph::Graph,
+  op::{Add, Contiguous, Function, Mul, SumReduce},
+  prelude::{NodeIndex, ShapeTracker},
+  shape::Expression,
+};
+use proptest::prelude::*;
+
+/// Max rank of tensors in generated expressions.
+pub const MAX_RANK: usize = 3;
+/// Max size of a single dimension in generated expressions.
+pub const MAX_DIM: usize = 3;
+
+/// A tensor computation over static shapes.
+#[derive(Debug, Clone)]
+pub enum TensorExpr {
+  /// Fresh input tensor of the given shape.
+  Input(Vec<usize>),
+  Add(Box<TensorExpr>, Box<TensorExpr>),
+  Mul(Box<TensorExpr>, Box<TensorExpr>),
+  /// Insert a (fake) dimension of the given size at the axis.
+  Expand(Box<TensorExpr>, usize, usize),
+  /// Output axis `j` is the input axis `axes[j]`.
+  Permute(Box<TensorExpr>, Vec<usize>),
+  SumReduce(Box<TensorExpr>, usize),
+  /// Keep `start..end` of every axis.
+  Slice(Box<TensorExpr>, Vec<(usize, usize)>),
+  /// Add the given number of zeros before and after every axis.
+  Pad(Box<TensorExpr>, Vec<(usize, usize)>),
+  /// View as the given shape (of the same size), made contiguous first if needed, as luminal's `reshape` does.
+  Reshape(Box<TensorExpr>, Vec<usize>),
+}
+
+impl TensorExpr {
+  pub fn shape(&self) -> Vec<usize> {
+    match self {
+      TensorExpr::Input(sh) => sh.clone(),
+      TensorExpr::Add(a, _) | TensorExpr::Mul(a, _) => a.shape(),
+      TensorExpr::Expand(a, ax, n) => {
+        let mut sh = a.shape();
+        sh.insert(*ax, *n);
+        sh
+      }
+      TensorExpr::Permute(a, axes) => {
+        let sh = a.shape();
+        axes.iter().map(|i| sh[*i]).collect()
+      }
+      TensorExpr::SumReduce(a, ax) => {
+        let mut sh = a.shape();
+        sh.remove(*ax);
+        sh
+      }
+      TensorExpr::Slice(_, ranges) => ranges.iter().map(|(s, e)| e - s).collect(),
+      TensorExpr::Pad(a, padding) => a
+        .shape()
+        .iter()
+        .zip(padding)
+        .map(|(d, (l, r))| l + d + r)
+        .collect(),
+      TensorExpr::Reshape(_, sh) => sh.clone(),
+    }
+  }
+}
+
+fn contiguous(shape: &[usize]) -> ShapeTracker {
+  ShapeTracker::new(
+    &shape
+      .iter()
+      .map(|d| Expression::from(*d))
+      .collect::<Vec<_>>(),
+  )
+}
+
+fn build(expr: &TensorExpr, cx: &mut Graph) -> (NodeIndex, ShapeTracker) {
+  match expr {
+    TensorExpr::Input(sh) => {
+      let x = cx
+        .add_op(Function(
+          "Load".to_string(),
+          Box::new(|_| panic!("set the input first")),
+        ))
+        .finish();
+      (x, contiguous(sh))
+    }
+    TensorExpr::Add(a, b) | TensorExpr::Mul(a, b) => {
+      let (a_id, a_sh) = build(a, cx);
+      let (b_id, b_sh) = build(b, cx);
+      let x = if let TensorExpr::Add(_, _) = expr {
+        cx.add_op(Add {})
+          .input(a_id, 0, a_sh)
+          .input(b_id, 0, b_sh)
+          .finish()
+      } else {
+        cx.add_op(Mul {})
+          .input(a_id, 0, a_sh)
+          .input(b_id, 0, b_sh)
+          .finish()
+      };
+      (x, contiguous(&expr.shape()))
+    }
+    TensorExpr::Expand(a, ax, n) => {
+      let (a_id, mut a_sh) = build(a, cx);
+      a_sh.expand(*ax, *n);
+      (a_id, a_sh)
+    }
+    TensorExpr::Permute(a, axes) => {
+      let (a_id, mut a_sh) = build(a, cx);
+      a_sh.permute(axes);
+      (a_id, a_sh)
+    }
+    TensorExpr::SumReduce(a, ax) => {
+      let (a_id, a_sh) = build(a, cx);
+      let x = cx.add_op(SumReduce(*ax)).input(a_id, 0, a_sh).finish();
+      (x, contiguous(&expr.shape()))
+    }
+    TensorExpr::Slice(a, ranges) => {
+      let (a_id, mut a_sh) = build(a, cx);
+      a_sh.slice(
+        &ranges
+          .iter()
+          .map(|(s, e)| (Expression::from(*s), Expression::from(*e)))
+          .collect::<Vec<_>>(),
+      );
+      (a_id, a_sh)
+    }
+    TensorExpr::Pad(a, padding) => {
+      let (a_id, mut a_sh) = build(a, cx);
+      a_sh.pad(
+        &padding
+          .iter()
+          .map(|(l, r)| (Expression::from(*l), Expression::from(*r)))
+          .collect::<Vec<_>>(),
+      );
+      (a_id, a_sh)
+    }
+    TensorExpr::Reshape(a, sh) => {
+      let (a_id, a_sh) = build(a, cx);
+      let a_id = if a_sh == contiguous(&a.shape()) {
+        a_id
+      } else {
+        cx.add_op(Contiguous).input(a_id, 0, a_sh).finish()
+      };
+      (a_id, contiguous(sh))
+    }
+  }
+}
+
+/// Builds the tensor graph computing the expression, with the result marked for retrieval.
+/// Every `Input` becomes a separate source node, see [super::random_inputs] for feeding them.
+pub fn build_graph(expr: &TensorExpr) -> (Graph, NodeIndex) {
+  let mut cx = Graph::new();
+  let (x, sh) = build(expr, &mut cx);
+  cx.no_delete.insert(x);
+  cx.to_retrieve.insert(x, (0, sh));
+  (cx, x)
+}
+
+/// Strategy for a shape of rank in `0..=MAX_RANK`.
+pub fn arb_shape() -> impl Strategy<Value = Vec<usize>> {
+  prop::collection::vec(1..=MAX_DIM, 0..=MAX_RANK)
+}
+
+/// Strategy for expressions evaluating to a tensor of the given shape, with nesting up to `depth`.
+pub fn arb_expr_of_shape(shape: Vec<usize>, depth: u32) -> BoxedStrategy<TensorExpr> {
+  let leaf = Just(TensorExpr::Input(shape.clone())).boxed();
+  if depth == 0 {
+    return leaf;
+  }
+  let sub = move |sh: Vec<usize>| arb_expr_of_shape(sh, depth - 1);
+  let mut options = vec![
+    leaf,
+    (sub(shape.clone()), sub(shape.clone()))
+      .prop_map(|(a, b)| TensorExpr::Add(Box::new(a), Box::new(b)))
+      .boxed(),
+    (sub(shape.clone()), sub(shape.clone()))
+      .prop_map(|(a, b)| TensorExpr::Mul(Box::new(a), Box::new(b)))
+      .boxed(),
+  ];
+  if !shape.is_empty() {
+    let sh = shape.clone();
+    options.push(
+      (0..shape.len())
+        .prop_flat_map(move |ax| {
+          let mut inner = sh.clone();
+          let n = inner.remove(ax);
+          sub(inner).prop_map(move |a| TensorExpr::Expand(Box::new(a), ax, n))
+        })
+        .boxed(),
+    );
+    let sh = shape.clone();
+    options.push(
+      Just((0..shape.len()).collect_vec())
+        .prop_shuffle()
+        .prop_flat_map(move |axes| {
+          // output axis j is input axis axes[j]
+          let mut inner = vec![0; sh.len()];
+          for (j, i) in axes.iter().enumerate() {
+            inner[*i] = sh[j];
+          }
+          sub(inner).prop_map(move |a| TensorExpr::Permute(Box::new(a), axes.clone()))
+        })
+        .boxed(),
+    );
+  }
+  if !shape.is_empty() {
+    // every axis `d` cut out of one of `d + extra`
+    let sh = shape.clone();
+    let cuts = shape
+      .iter()
+      .map(|d| (0..=MAX_DIM.saturating_sub(*d)).prop_flat_map(|extra| (Just(extra), 0..=extra)))
+      .collect_vec();
+    options.push(
+      cuts
+        .prop_flat_map(move |cuts| {
+          let inner = sh
+            .iter()
+            .zip(&cuts)
+            .map(|(d, (extra, _))| d + extra)
+            .collect();
+          let ranges = sh
+            .iter()
+            .zip(&cuts)
+            .map(|(d, (_, start))| (*start, start + d))
+            .collect_vec();
+          sub(inner).prop_map(move |a| TensorExpr::Slice(Box::new(a), ranges.clone()))
+        })
+        .boxed(),
+    );
+    // every axis `d` padded from a nonempty one
+    let sh = shape.clone();
+    let pads = shape
+      .iter()
+      .map(|d| {
+        let d = *d;
+        (0..d).prop_flat_map(move |l| (Just(l), 0..d - l))
+      })
+      .collect_vec();
+    options.push(
+      pads
+        .prop_flat_map(move |padding| {
+          let inner = sh
+            .iter()
+            .zip(&padding)
+            .map(|(d, (l, r))| d - l - r)
+            .collect();
+          sub(inner).prop_map(move |a| TensorExpr::Pad(Box::new(a), padding.clone()))
+        })
+        .boxed(),
+    );
+    // flattened or with the axes in reverse
+    let sh = shape.clone();
+    let n: usize = shape.iter().product();
+    options.push(
+      prop_oneof![
+        Just(vec![n]),
+        Just(shape.iter().rev().copied().collect_vec())
+      ]
+      .prop_flat_map(move |inner| {
+        let sh = sh.clone();
+        sub(inner).prop_map(move |a| TensorExpr::Reshape(Box::new(a), sh.clone()))
+      })
+      .boxed(),
+    );
+  }
+  if shape.len() < MAX_RANK {
+    let sh = shape.clone();
+    options.push(
+      (0..=shape.len(), 1..=MAX_DIM)
+        .prop_flat_map(move |(ax, n)| {
+          let mut inner = sh.clone();
+          inner.insert(ax, n);
+          sub(inner).prop_map(move |a| TensorExpr::SumReduce(Box::new(a), ax))
+        })
+        .boxed(),
+    );
+  }
+  prop::strategy::Union::new(options).boxed()
+}
+
+/// Strategy for expressions of any small shape.
+pub fn arb_expr(depth: u32) -> impl Strategy<Value = TensorExpr> {
+  arb_shape().prop_flat_map(move |sh| arb_expr_of_shape(sh, depth))
+}
+
+/// A dense net without activations, `widths[0]` inputs and layers `x => W x + b` to the next widths.
+/// Weights and biases are inputs as well. For benchmarks: the scalar graph grows with the sum of `widths[i] * widths[i+1]`.
+pub fn mlp_expr(widths: &[usize]) -> TensorExpr {
+  assert!(!widths.is_empty(), "Expected at least the input width");
+  let mut e = TensorExpr::Input(vec![widths[0]]);
+  for (n, m) in widths.iter().tuple_windows() {
+    let w = TensorExpr::Input(vec![*m, *n]);
+    let prod = TensorExpr::Mul(
+      Box::new(TensorExpr::Expand(Box::new(e), 0, *m)),
+      Box::new(w),
+    );
+    e = TensorExpr::Add(
+      Box::new(TensorExpr::SumReduce(Box::new(prod), 1)),
+      Box::new(TensorExpr::Input(vec![*m])),
+    );
+  }
+  e
+}
+
+#[cfg(test)]
+mod tests {
+  use rand::{rngs::StdRng, SeedableRng};
+
+  use super::{build_graph, mlp_expr};
+  use crate::scalar::{random_inputs, scalar, verify_scalarization};
+
+  #[test]
+  fn test_mlp_expr_scalarizes() {
+    let expr = mlp_expr(&[4, 3, 2]);
+    assert_eq!(expr.shape(), vec![2]);
+    let (cx, _) = build_graph(&expr);
+    let (sc, _) = scalar(&cx);
+    let inputs = random_inputs(&cx, &mut StdRng::seed_from_u64(0));
+    assert_eq!(inputs.len(), 5);
+    assert_eq!(verify_scalarization(&cx, &sc, &inputs), Ok(()));
+  }
+}
@@ -0,0 +1,124 @@
+//!
+//! Proving as an HTTP service, the `service` feature.
+//!
+//! The service serves one model with a [ProverPool]: its keys are made once at start, clients submit inputs and get a
+//! job id back right away, the workers prove in the background. A finished job has its proof as a [ProofBundle],
+//! which the client verifies with the verifying key and the schema the service hands out too
+//! (see [crate::snark::bundle::verify_bundle]). A client submitting to a full queue is told to come back later.
+//! Finished jobs are kept for a while only (see [crate::snark::pool::PoolConfig::retain]), unknown jobs are 404.
+//!
+//! Endpoints, json unless said otherwise:
+//!  - `POST /proofs` with `{"input": [floats]}`: queues a proof, `{"id": <job>}`.
+//!  - `GET /proofs/<job>`: the [JobStatus] with the [JobMetrics] of the job.
+//!  - `GET /proofs/<job>/bundle`: the [ProofBundle] of a finished job, once: the job is forgotten then.
+//!  - `GET /schema`: the [PublicInputsSchema] of the circuit.
+//!  - `GET /verifying_key`: the verifying key, bytes (see [crate::snark::verifier::VERIFYING_KEY_FILE]).
+//!
+
+use std::{error::Error, sync::Arc};
+
+use axum::{
+  extract::{Path, State},
+  http::StatusCode,
+  routing::{get, post},
+  Json, Router,
+};
+use serde::{Deserialize, Serialize};
+use tracing::info;
+
+use crate::snark::{
+  bundle::ProofBundle,
+  pool::{JobMetrics, JobStatus, ProverPool, SubmitError},
+  verifier::PublicInputsSchema,
+};
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ProveRequest {
+  /// Unnormalized, the service applies the scaler of the model.
+  pub input: Vec<f32>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProveResponse {
+  pub id: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StatusResponse {
+  #[serde(flatten)]
+  pub status: JobStatus,
+  #[serde(flatten)]
+  pub metrics: JobMetrics,
+}
+
+/// The endpoints of the service, see the module docs.
+pub fn router(pool: Arc<ProverPool>) -> Router {
+  Router::new()
+    .route("/proofs", post(submit))
+    .route("/proofs/:id", get(status))
+    .route("/proofs/:id/bundle", get(bundle))
+    .route("/schema", get(schema))
+    .route("/verifying_key", get(verifying_key))
+    .with_state(pool)
+}
+
+/// Serves the endpoints on the port, until the server fails.
+pub async fn serve(pool: ProverPool, port: u16) -> Result<(), Box<dyn Error>> {
+  let listener = tokio::net::TcpListener::bind(format!("0.0.0.0:{}", port)).await?;
+  info!("Proving service listening on port {}", port);
+  axum::serve(listener, router(Arc::new(pool))).await?;
+  Ok(())
+}
+
+type Pool = State<Arc<ProverPool>>;
+
+async fn submit(
+  State(pool): Pool,
+  Json(request): Json<ProveRequest>,
+) -> Result<(StatusCode, Json<ProveResponse>), (StatusCode, String)> {
+  let id = pool.submit(request.input).map_err(|e| {
+    let code = match e {
+      SubmitError::Length { .. } => StatusCode::BAD_REQUEST,
+      SubmitError::QueueFull => StatusCode::TOO_MANY_REQUESTS,
+      SubmitError::Stopped => StatusCode::SERVICE_UNAVAILABLE,
+    };
+    (code, e.to_string())
+  })?;
+  Ok((StatusCode::ACCEPTED, Json(ProveResponse { id })))
+}
+
+async fn status(
+  State(pool): Pool,
+  Path(id): Path<u64>,
+) -> Result<Json<StatusResponse>, StatusCode> {
+  match (pool.status(id), pool.metrics(id)) {
+    (Some(status), Some(metrics)) => Ok(Json(StatusResponse { status, metrics })),
+    _ => Err(StatusCode::NOT_FOUND),
+  }
+}
+
+async fn bundle(
+  State(pool): Pool,
+  Path(id): Path<u64>,
+) -> Result<Json<ProofBundle>, (StatusCode, String)> {
+  match pool.status(id) {
+    None => Err((StatusCode::NOT_FOUND, format!("No job {}", id))),
+    // taken or forgotten since the status, as if never known
+    Some(JobStatus::Done) => pool
+      .take_bundle(id)
+      .map(Json)
+      .ok_or_else(|| (StatusCode::NOT_FOUND, format!("No job {}", id))),
+    Some(status) => Err((
+      StatusCode::CONFLICT,
+      format!("Job {} is not done: {:?}", id, status),
+    )),
+  }
+}
+
+async fn schema(State(pool): Pool) -> Json<PublicInputsSchema> {
+  Json(pool.schema.clone())
+}
+
+async fn verifying_key(State(pool): Pool) -> Vec<u8> {
+  pool.verifying_key.clone()
+}
@@ -0,0 +1,574 @@
+//!
+//! Proving a model chunk by chunk (see [crate::scalar::partition]) and aggregating the chunk proofs.
+//!
+//! The aggregation is batched verification: an [AggregatedProof] is the list of the chunk proofs, proven in parallel,
+//! and the verifier checks them all at once (see [super::verifier::VerifyingBackend::verify_batch], for Groth16 a
+//! single pairing check of a random combination of them). The proof and the verifier's work on it stay linear in the
+//! chunks, folding them into one proof (Nova-style) is left out.
+//!
+//! A value crossing a chunk boundary is a public input of both the chunk computing it and the chunks using it,
+//! so the verifier checks the wiring by comparing the two. This reveals the activations at the boundaries,
+//! hiding them needs commitments to the boundary values or folding. The other public inputs of a chunk, its
+//! constants and public weights, are compared with the model, as [super::verifier::PublicInputsSchema::check] does for
+//! a single proof.
+//!
+//! The keys of the chunks are made once, by whoever runs the setup ([setup_chunked]). The prover proves with the
+//! proving keys, the verifier checks the chunk proofs with the published verifying keys rather than making keys of
+//! its own: the randomness of the setup has to stay secret.
+//!
+//! The weights are public, or private and committed to by the root of a Merkle tree ([prove_chunked_committed]):
+//! every chunk opens the leaves of the weights it uses, see [super::merkle].
+//!
+
+use std::{
+  collections::HashMap,
+  sync::{Arc, Mutex},
+  thread,
+};
+
+use ark_groth16::{Proof, ProvingKey, VerifyingKey};
+use ark_relations::r1cs::{ConstraintSynthesizer, ConstraintSystem, SynthesisError};
+use itertools::Itertools;
+use luminal::prelude::NodeIndex;
+use num_bigint::{BigInt, BigUint};
+
+use super::{
+  backend::{Groth16Backend, ProvingBackend},
+  merkle::{weight_positions, WeightOpening, WeightTree},
+  scaling_helpers::{f_to_bigint, positive_bigint, scaled_float},
+  CircuitField, Curve, MLSnark, SourceType,
+};
+use crate::{assert_snark_supported, model::TrainedGraph, scalar::scalar, scalar::ScalarOp, SCALE};
+
+/// A chunk size for [setup_chunked], in computing scalar nodes.
+pub const DEFAULT_CHUNK_NODES: usize = 10_000;
+
+#[derive(Debug, Clone)]
+pub struct ChunkProof<P = Proof<Curve>> {
+  pub proof: P,
+  /// As [MLSnark::recorded_public_inputs] of the chunk.
+  pub public_inputs: Vec<CircuitField>,
+}
+
+#[derive(Debug, Clone)]
+pub struct AggregatedProof<P = Proof<Curve>> {
+  /// The chunk size the model was partitioned with, the verifier partitions it the same way.
+  pub max_nodes: usize,
+  pub chunks: Vec<ChunkProof<P>>,
+}
+
+impl<P> AggregatedProof<P> {
+  /// The public result of the last chunk, see [MLSnark::get_evaluation_result].
+  pub fn get_evaluation_result(&self) -> CircuitField {
+    self
+      .chunks
+      .last()
+      .and_then(|c| c.public_inputs.last())
+      .unwrap()
+      .clone()
+  }
+}
+
+/// The snark of a single chunk together with its wiring (in node ids of the whole scalar graph).
+#[derive(Debug)]
+pub struct ChunkSnark {
+  pub snark: MLSnark<CircuitField>,
+  pub boundary_inputs: Vec<(usize, NodeIndex)>,
+  pub boundary_outputs: Vec<NodeIndex>,
+}
+
+impl ChunkSnark {
+  fn local(&self, x: NodeIndex) -> NodeIndex {
+    let tracker = &self.snark.graph.inputs_tracker;
+    tracker
+      .new_inputs
+      .get(&x)
+      .or_else(|| tracker.new_outputs.get(&x))
+      .map(|v| v[0])
+      .unwrap_or_else(|| panic!("Node {:?} is not on the boundary of the chunk", x))
+  }
+
+  /// The values of the public inputs by (local) node.
+  /// Only complete after synthesizing with all the inputs set.
+  fn public_values(&self) -> HashMap<NodeIndex, CircuitField> {
+    let snark = &self.snark;
+    assert_eq!(
+      snark.recorded_public_nodes.len() + snark.leading_hashes(),
+      snark.recorded_public_inputs.len(),
+      "Public inputs of the chunk are not all known"
+    );
+    snark
+      .recorded_public_nodes
+      .iter()
+      .copied()
+      .zip(
+        snark
+          .recorded_public_inputs
+          .iter()
+          .skip(snark.leading_hashes())
+          .copied(),
+      )
+      .collect()
+  }
+
+  /// The public inputs of the chunk fixed by the model, by (local) node: the encodings of its constants and of its
+  /// public weights. Not the boundary inputs, whose values come from the earlier chunks.
+  fn public_sources(&self) -> HashMap<NodeIndex, BigInt> {
+    let snark = &self.snark;
+    snark
+      .graph
+      .nodes
+      .iter()
+      .filter_map(|node| match (node.op, snark.source_map.get(&node.id)) {
+        (ScalarOp::Constant(val), _) => Some((node.id, scaled_float(val, &snark.scale))),
+        (ScalarOp::Input, Some(SourceType::Public(v))) => {
+          Some((node.id, scaled_float(*v, &snark.scale)))
+        }
+        _ => None,
+      })
+      .collect()
+  }
+}
+
+/// Partitions the model and makes a snark for every chunk.
+/// Weights are public and the input private, as in [crate::compile]. Boundary inputs are public,
+/// their values are set from the earlier chunks while proving (see [SourceType::PublicEncoded]).
+pub fn compile_chunked(
+  trained: &TrainedGraph,
+  max_nodes: usize,
+  input: Option<Vec<f32>>,
+) -> Vec<ChunkSnark> {
+  compile_chunks(trained, max_nodes, input, None)
+}
+
+/// As [compile_chunked] with the weights private, in a [WeightTree] of `leaf_size` elements a leaf. Every chunk using
+/// weights opens their leaves (see [WeightOpening]). The tree is built only with the input, for proving.
+pub fn compile_chunked_committed(
+  trained: &TrainedGraph,
+  max_nodes: usize,
+  input: Option<Vec<f32>>,
+  leaf_size: usize,
+) -> Vec<ChunkSnark> {
+  compile_chunks(trained, max_nodes, input, Some(leaf_size))
+}
+
+fn compile_chunks(
+  trained: &TrainedGraph,
+  max_nodes: usize,
+  input: Option<Vec<f32>>,
+  leaf_size: Option<usize>,
+) -> Vec<ChunkSnark> {
+  // as compile_graph_with does for the whole model
+  trained
+    .graph
+    .validate_weights()
+    .unwrap_or_else(|e| panic!("Wrong weights: {}", e));
+  let (sc, _) = scalar(&trained.graph.graph);
+  let tracker = &sc.inputs_tracker;
+  let proving = input.is_some();
+  let mut sources: HashMap<NodeIndex, SourceType<f32>> = HashMap::new();
+  for (i, w_i) in trained.graph.weights.iter() {
+    for (little_id, v) in tracker.new_inputs[i].iter().zip(w_i) {
+      let source = match leaf_size {
+        None => SourceType::Public(*v),
+        Some(_) => SourceType::Private(Some(*v).filter(|_| proving)),
+      };
+      sources.insert(*little_id, source);
+    }
+  }
+  let positions = weight_positions(&trained.graph, tracker);
+  let len = trained.graph.weights.iter().map(|(_, w)| w.len()).sum();
+  let tree = leaf_size.filter(|_| proving).map(|leaf_size| {
+    Arc::new(WeightTree::of_weights(
+      &trained.graph.weights,
+      leaf_size,
+      &SCALE,
+    ))
+  });
+  for (k, little_id) in tracker.new_inputs[&trained.graph.input_id]
+    .iter()
+    .enumerate()
+  {
+    let v = input.as_ref().map(|input| input[k]);
+    sources.insert(*little_id, SourceType::Private(v));
+  }
+
+  sc.partition(max_nodes)
+    .into_iter()
+    .map(|chunk| {
+      assert_snark_supported(&chunk.scalar);
+      let mut source_map = HashMap::new();
+      for x in chunk.inputs.iter() {
+        source_map.insert(chunk.local(*x).unwrap(), sources[x].clone());
+      }
+      // placeholders, the circuit doesn't depend on them
+      for (_, x) in chunk.boundary_inputs.iter() {
+        source_map.insert(
+          chunk.local(*x).unwrap(),
+          SourceType::PublicEncoded(BigUint::default()),
+        );
+      }
+      let weight_opening = leaf_size.and_then(|leaf_size| {
+        let elements: Vec<(NodeIndex, usize)> = chunk
+          .inputs
+          .iter()
+          .filter_map(|x| Some((chunk.local(*x).unwrap(), *positions.get(x)?)))
+          .collect();
+        (!elements.is_empty()).then(|| WeightOpening {
+          elements,
+          leaf_size,
+          len,
+          tree: tree.clone(),
+        })
+      });
+      ChunkSnark {
+        snark: MLSnark {
+          graph: chunk.scalar.freeze_ops(),
+          scale: SCALE,
+          source_map,
+          og_input_id: trained.graph.input_id,
+          input_hash: false,
+          commitments: vec![],
+          weight_opening,
+          recorded_public_inputs: vec![],
+          recorded_public_nodes: vec![],
+          trace: Default::default(),
+        },
+        boundary_inputs: chunk.boundary_inputs,
+        boundary_outputs: chunk.boundary_outputs,
+      }
+    })
+    .collect()
+}
+
+/// Sets the boundary inputs of the chunk to their values, computed by the earlier chunks.
+pub fn set_boundary_inputs(chunk: &mut ChunkSnark, boundary: &HashMap<NodeIndex, CircuitField>) {
+  for (_, x) in chunk.boundary_inputs.clone() {
+    let v = positive_bigint(f_to_bigint(boundary[&x]));
+    let local = chunk.local(x);
+    chunk
+      .snark
+      .source_map
+      .insert(local, SourceType::PublicEncoded(v));
+  }
+}
+
+/// Evaluates the chunks in order (by synthesizing them without proving), setting the boundary inputs of every chunk.
+/// The exact field values at the boundaries, by node of the whole scalar graph.
+pub fn evaluate_boundaries(
+  chunks: &mut [ChunkSnark],
+) -> Result<HashMap<NodeIndex, CircuitField>, SynthesisError> {
+  let mut boundary: HashMap<NodeIndex, CircuitField> = HashMap::new();
+  for chunk in chunks.iter_mut() {
+    set_boundary_inputs(chunk, &boundary);
+    let cs = ConstraintSystem::<CircuitField>::new_ref();
+    (&mut chunk.snark).generate_constraints(cs)?;
+    let values = chunk.public_values();
+    for x in chunk.boundary_outputs.iter() {
+      boundary.insert(*x, values[&chunk.local(*x)]);
+    }
+  }
+  Ok(boundary)
+}
+
+/// Proves a chunk with its boundary inputs set, with the proving key of the chunk.
+pub fn prove_chunk<B: ProvingBackend>(
+  backend: &B,
+  chunk: &mut ChunkSnark,
+  pk: &B::ProvingKey,
+) -> Result<ChunkProof<B::Proof>, B::Error> {
+  let proof = backend.prove(&mut chunk.snark, pk)?;
+  Ok(ChunkProof {
+    proof,
+    public_inputs: chunk.snark.recorded_public_inputs.clone(),
+  })
+}
+
+/// The keys of the chunks of a model, in order, see the module docs.
+#[derive(Debug, Clone)]
+pub struct ChunkKeys<PK, VK> {
+  /// The chunk size the keys were made for.
+  pub max_nodes: usize,
+  pub proving_keys: Vec<PK>,
+  /// Published, for [verify_chunked_with_backend].
+  pub verifying_keys: Vec<VK>,
+}
+
+fn setup_chunks<B: ProvingBackend>(
+  backend: &B,
+  mut chunks: Vec<ChunkSnark>,
+  max_nodes: usize,
+) -> Result<ChunkKeys<B::ProvingKey, B::VerifyingKey>, B::Error> {
+  let (proving_keys, verifying_keys) = chunks
+    .iter_mut()
+    .map(|chunk| backend.setup(&mut chunk.snark))
+    .collect::<Result<Vec<_>, B::Error>>()?
+    .into_iter()
+    .unzip();
+  Ok(ChunkKeys {
+    max_nodes,
+    proving_keys,
+    verifying_keys,
+  })
+}
+
+/// Makes the keys of every chunk of the model, see [compile_chunked].
+pub fn setup_chunked_with_backend<B: ProvingBackend>(
+  backend: &B,
+  trained: &TrainedGraph,
+  max_nodes: usize,
+) -> Result<ChunkKeys<B::ProvingKey, B::VerifyingKey>, B::Error> {
+  setup_chunks(
+    backend,
+    compile_chunked(trained, max_nodes, None),
+    max_nodes,
+  )
+}
+
+pub fn setup_chunked(
+  trained: &TrainedGraph,
+  max_nodes: usize,
+) -> Result<ChunkKeys<ProvingKey<Curve>, VerifyingKey<Curve>>, SynthesisError> {
+  setup_chunked_with_backend(&Groth16Backend, trained, max_nodes)
+}
+
+/// [setup_chunked_with_backend] for the chunks with private weights, see [compile_chunked_committed].
+pub fn setup_chunked_committed<B: ProvingBackend>(
+  backend: &B,
+  trained: &TrainedGraph,
+  max_nodes: usize,
+  leaf_size: usize,
+) -> Result<ChunkKeys<B::ProvingKey, B::VerifyingKey>, B::Error> {
+  let chunks = compile_chunked_committed(trained, max_nodes, None, leaf_size);
+  setup_chunks(backend, chunks, max_nodes)
+}
+
+pub fn prove_chunked(
+  trained: &TrainedGraph,
+  input: Vec<f32>,
+  keys: &ChunkKeys<ProvingKey<Curve>, VerifyingKey<Curve>>,
+) -> Result<AggregatedProof, SynthesisError> {
+  prove_chunked_with_backend(&Groth16Backend, trained, input, keys)
+}
+
+/// First the chunks are evaluated in order (see [evaluate_boundaries]), to get the exact field values at the boundaries.
+/// Then the chunk proofs are independent, a thread per core takes the chunks one by one and proves them.
+/// For proofs long enough to lose to a crash see [super::job].
+fn prove_chunks<B: ProvingBackend + Sync>(
+  backend: &B,
+  mut chunks: Vec<ChunkSnark>,
+  keys: &ChunkKeys<B::ProvingKey, B::VerifyingKey>,
+) -> Result<AggregatedProof<B::Proof>, B::Error>
+where
+  B::Error: From<SynthesisError> + Send,
+  B::ProvingKey: Sync,
+  B::Proof: Send,
+{
+  assert_eq!(
+    chunks.len(),
+    keys.proving_keys.len(),
+    "The keys are of another model"
+  );
+  evaluate_boundaries(&mut chunks)?;
+  let threads = thread::available_parallelism()
+    .map_or(1, |n| n.get())
+    .min(chunks.len())
+    .max(1);
+  let work = Mutex::new(chunks.iter_mut().zip(keys.proving_keys.iter()).enumerate());
+  let queue = &work;
+  let proven = thread::scope(|s| {
+    let workers = (0..threads)
+      .map(|_| {
+        s.spawn(move || {
+          let mut done = vec![];
+          loop {
+            let next = queue.lock().unwrap().next();
+            match next {
+              Some((i, (chunk, pk))) => done.push((i, prove_chunk(backend, chunk, pk))),
+              None => break done,
+            }
+          }
+        })
+      })
+      .collect_vec();
+    workers
+      .into_iter()
+      .flat_map(|w| w.join().unwrap())
+      .collect_vec()
+  });
+  let chunk_proofs = proven
+    .into_iter()
+    .sorted_by_key(|(i, _)| *i)
+    .map(|(_, proof)| proof)
+    .collect::<Result<Vec<_>, B::Error>>()?;
+  Ok(AggregatedProof {
+    max_nodes: keys.max_nodes,
+    chunks: chunk_proofs,
+  })
+}
+
+/// Proves the model chunk by chunk, with the keys of [setup_chunked_with_backend].
+pub fn prove_chunked_with_backend<B: ProvingBackend + Sync>(
+  backend: &B,
+  trained: &TrainedGraph,
+  input: Vec<f32>,
+  keys: &ChunkKeys<B::ProvingKey, B::VerifyingKey>,
+) -> Result<AggregatedProof<B::Proof>, B::Error>
+where
+  B::Error: From<SynthesisError> + Send,
+  B::ProvingKey: Sync,
+  B::Proof: Send,
+{
+  let chunks = compile_chunked(trained, keys.max_nodes, Some(input));
+  prove_chunks(backend, chunks, keys)
+}
+
+/// [prove_chunked_with_backend] with the weights private, see [compile_chunked_committed], with the keys of
+/// [setup_chunked_committed]. The root of the weights the chunks open, to publish with the proof.
+pub fn prove_chunked_committed<B: ProvingBackend + Sync>(
+  backend: &B,
+  trained: &TrainedGraph,
+  input: Vec<f32>,
+  keys: &ChunkKeys<B::ProvingKey, B::VerifyingKey>,
+  leaf_size: usize,
+) -> Result<(AggregatedProof<B::Proof>, CircuitField), B::Error>
+where
+  B::Error: From<SynthesisError> + Send,
+  B::ProvingKey: Sync,
+  B::Proof: Send,
+{
+  let chunks = compile_chunked_committed(trained, keys.max_nodes, Some(input), leaf_size);
+  // the tree of the chunks, unless none uses weights
+  let root = chunks
+    .iter()
+    .find_map(|c| Some(c.snark.weight_opening.as_ref()?.tree.as_ref()?.root()))
+    .unwrap_or_else(|| WeightTree::of_weights(&trained.graph.weights, leaf_size, &SCALE).root());
+  Ok((prove_chunks(backend, chunks, keys)?, root))
+}
+
+pub fn verify_chunked(
+  trained: &TrainedGraph,
+  aggregated: &AggregatedProof,
+  verifying_keys: &[VerifyingKey<Curve>],
+) -> Result<bool, SynthesisError> {
+  verify_chunked_with_backend(&Groth16Backend, trained, aggregated, verifying_keys)
+}
+
+/// Verifies the chunk proofs, in one batch, with the published verifying keys of the chunks. Checks that the boundary
+/// values of consecutive chunks agree and that the constants and the weights of every chunk are those of the model.
+pub fn verify_chunked_with_backend<B: ProvingBackend>(
+  backend: &B,
+  trained: &TrainedGraph,
+  aggregated: &AggregatedProof<B::Proof>,
+  verifying_keys: &[B::VerifyingKey],
+) -> Result<bool, B::Error> {
+  let chunks = compile_chunked(trained, aggregated.max_nodes, None);
+  verify_chunks(backend, chunks, aggregated, verifying_keys, None)
+}
+
+/// [verify_chunked_with_backend] of a proof by [prove_chunked_committed], also checking that the chunks open the
+/// weights of the root. The weights of the model are not used, only its graph.
+pub fn verify_chunked_committed<B: ProvingBackend>(
+  backend: &B,
+  trained: &TrainedGraph,
+  aggregated: &AggregatedProof<B::Proof>,
+  verifying_keys: &[B::VerifyingKey],
+  leaf_size: usize,
+  root: CircuitField,
+) -> Result<bool, B::Error> {
+  let chunks = compile_chunked_committed(trained, aggregated.max_nodes, None, leaf_size);
+  verify_chunks(backend, chunks, aggregated, verifying_keys, Some(root))
+}
+
+fn verify_chunks<B: ProvingBackend>(
+  backend: &B,
+  mut chunks: Vec<ChunkSnark>,
+  aggregated: &AggregatedProof<B::Proof>,
+  verifying_keys: &[B::VerifyingKey],
+  root: Option<CircuitField>,
+) -> Result<bool, B::Error> {
+  if chunks.len() != aggregated.chunks.len() || chunks.len() != verifying_keys.len() {
+    return Ok(false);
+  }
+
+  let mut boundary: HashMap<NodeIndex, CircuitField> = HashMap::new();
+  let mut batch = vec![];
+  let proofs = aggregated.chunks.iter().zip(verifying_keys);
+  for (chunk, (chunk_proof, vk)) in chunks.iter_mut().zip(proofs) {
+    // records the public nodes of the chunk
+    backend.estimate_constraints(&mut chunk.snark)?;
+    let nodes = &chunk.snark.recorded_public_nodes;
+    let leading = chunk.snark.leading_hashes();
+    if nodes.len() + leading != chunk_proof.public_inputs.len() {
+      return Ok(false);
+    }
+    if chunk.snark.weight_opening.is_some() && chunk_proof.public_inputs.first() != root.as_ref() {
+      return Ok(false);
+    }
+    let public_inputs = nodes
+      .iter()
+      .copied()
+      .zip(chunk_proof.public_inputs[leading..].iter().copied());
+    // a source that is a result as well is recorded twice, both have its value
+    let sources = chunk.public_sources();
+    let fixed =
+      |(x, v): &(NodeIndex, CircuitField)| sources.get(x).map_or(true, |n| *n == f_to_bigint(*v));
+    if !public_inputs.clone().all(|input| fixed(&input)) {
+      return Ok(false);
+    }
+    let values: HashMap<NodeIndex, CircuitField> = public_inputs.collect();
+    for (_, x) in chunk.boundary_inputs.iter() {
+      if boundary.get(x) != Some(&values[&chunk.local(*x)]) {
+        return Ok(false);
+      }
+    }
+    for x in chunk.boundary_outputs.iter() {
+      boundary.insert(*x, values[&chunk.local(*x)]);
+    }
+    batch.push((vk, &chunk_proof.public_inputs[..], &chunk_proof.proof));
+  }
+  backend.verify_batch(&batch)
+}
+
+#[cfg(test)]
+mod tests {
+  use ark_ff::One;
+
+  use super::{prove_chunked, setup_chunked, verify_chunked};
+  use crate::{compile, snark::CircuitField};
+
+  #[test]
+  fn test_chunked_proof_agrees_with_the_whole() -> Result<(), String> {
+    let err = |e| format!("{:?}", e).to_string();
+    let trained = crate::model::fixed_weights::run_model();
+    let input: Vec<f32> = [1.0, 2.0, 3.0].to_vec();
+
+    let keys = setup_chunked(&trained, 4).map_err(err)?;
+    let aggregated = prove_chunked(&trained, input.clone(), &keys).map_err(err)?;
+    assert!(aggregated.chunks.len() > 1);
+    let vks = &keys.verifying_keys;
+    assert_eq!(verify_chunked(&trained, &aggregated, vks), Ok(true));
+
+    let mut snark = compile(&trained);
+    let (pk, _vk) = snark.make_keys().map_err(err)?;
+    snark.set_input(input);
+    snark.make_proof(&pk).map_err(err)?;
+    assert_eq!(
+      aggregated.get_evaluation_result(),
+      snark.get_evaluation_result()
+    );
+
+    // a chunk claiming a different boundary value
+    let mut tampered = aggregated.clone();
+    let first = &mut tampered.chunks[0].public_inputs;
+    *first.last_mut().unwrap() += CircuitField::one();
+    assert_eq!(verify_chunked(&trained, &tampered, vks), Ok(false));
+    // keys of another setup
+    let other = setup_chunked(&trained, 4).map_err(err)?;
+    assert_eq!(
+      verify_chunked(&trained, &aggregated, &other.verifying_keys),
+      Ok(false)
+    );
+    Ok(())
+  }
+}
@@ -0,0 +1,247 @@
+//!
+//! Proof systems behind one interface, so the pipeline (the subcommands, [super::aggregate]) doesn't depend on the one we use.
+//!
+//! A backend proves the evaluation of an [MLSnark]: the quantized scalar graph together with the assignment of its sources.
+//! There's just [Groth16Backend] for now.
+//!
+
+use std::{cmp::Ordering, collections::HashMap, error::Error, fs, path::Path, sync::OnceLock};
+
+use ark_groth16::{Groth16, ProvingKey};
+use ark_r1cs_std::{alloc::AllocVar, fields::fp::FpVar};
+use ark_relations::r1cs::{ConstraintSynthesizer, ConstraintSystem, SynthesisError, SynthesisMode};
+use ark_serialize::CanonicalSerialize;
+use ark_snark::SNARK;
+use luminal::prelude::NodeIndex;
+use num_bigint::BigInt;
+use rand::rngs::OsRng;
+
+use crate::{cost::CostModel, model::ParamRegistry, scalar::ScalarOp, SCALE};
+
+use super::{
+  below_cost, bits_cost,
+  solidity::write_solidity_verifier,
+  verifier::{PublicInputsSchema, SCHEMA_FILE, VERIFYING_KEY_FILE},
+  CircuitField, Curve, MLSnark, ENCODING_BITS,
+};
+
+pub use super::verifier::{Groth16Backend, VerifyingBackend};
+
+/// A proof system, with its verification in [VerifyingBackend] (which builds without the `native` feature).
+pub trait ProvingBackend: VerifyingBackend {
+  type ProvingKey;
+
+  /// Keys for the circuit of the snark. The input doesn't need to be set.
+  fn setup(
+    &self,
+    snark: &mut MLSnark<CircuitField>,
+  ) -> Result<(Self::ProvingKey, Self::VerifyingKey), Self::Error>;
+
+  /// Proves the evaluation on the input set in the snark.
+  /// The public inputs the verifier needs are left in [MLSnark::recorded_public_inputs].
+  fn prove(
+    &self,
+    snark: &mut MLSnark<CircuitField>,
+    pk: &Self::ProvingKey,
+  ) -> Result<Self::Proof, Self::Error>;
+
+  /// Size of the circuit in the measure of the backend (R1CS constraints, rows of a plonkish table, ...),
+  /// without proving anything. The input doesn't need to be set.
+  fn estimate_constraints(&self, snark: &mut MLSnark<CircuitField>) -> Result<usize, Self::Error>;
+
+  /// Computes the witness on the input set in the snark, without proving, and returns the results
+  /// as [MLSnark::get_evaluation_results]. Fails if the witness doesn't satisfy the circuit.
+  fn witness_outputs(
+    &self,
+    snark: &mut MLSnark<CircuitField>,
+  ) -> Result<HashMap<NodeIndex, Vec<CircuitField>>, Self::Error>;
+
+  /// Writes the verifying key and the [PublicInputsSchema] of the snark's circuit into the directory `path`,
+  /// for verifying the proofs without this crate. The snark needs the keys made, see [super::verifier].
+  fn export_verifier(
+    &self,
+    snark: &MLSnark<CircuitField>,
+    params: &ParamRegistry,
+    vk: &Self::VerifyingKey,
+    path: &Path,
+  ) -> Result<(), Box<dyn Error>>;
+
+  /// Writes a Solidity contract verifying the proofs of the snark's circuit to the file `path`, see [super::solidity].
+  /// Not every backend has one.
+  fn export_solidity_verifier(
+    &self,
+    _snark: &MLSnark<CircuitField>,
+    _params: &ParamRegistry,
+    _vk: &Self::VerifyingKey,
+    _path: &Path,
+  ) -> Result<(), Box<dyn Error>> {
+    Err(format!("No Solidity verifier for {}", Self::NAME).into())
+  }
+}
+
+impl ProvingBackend for Groth16Backend {
+  type ProvingKey = ProvingKey<Curve>;
+
+  fn setup(
+    &self,
+    snark: &mut MLSnark<CircuitField>,
+  ) -> Result<(Self::ProvingKey, Self::VerifyingKey), Self::Error> {
+    let rng = &mut OsRng;
+    Groth16::<Curve>::circuit_specific_setup(snark, rng)
+  }
+
+  fn prove(
+    &self,
+    snark: &mut MLSnark<CircuitField>,
+    pk: &Self::ProvingKey,
+  ) -> Result<Self::Proof, Self::Error> {
+    let rng = &mut OsRng;
+    Groth16::<Curve>::prove(pk, snark, rng)
+  }
+
+  fn estimate_constraints(&self, snark: &mut MLSnark<CircuitField>) -> Result<usize, Self::Error> {
+    let cs = ConstraintSystem::<CircuitField>::new_ref();
+    cs.set_mode(SynthesisMode::Setup);
+    snark.generate_constraints(cs.clone())?;
+    Ok(cs.num_constraints())
+  }
+
+  fn witness_outputs(
+    &self,
+    snark: &mut MLSnark<CircuitField>,
+  ) -> Result<HashMap<NodeIndex, Vec<CircuitField>>, Self::Error> {
+    let cs = ConstraintSystem::<CircuitField>::new_ref();
+    // reborrowed, the snark is needed for the results
+    ConstraintSynthesizer::generate_constraints(&mut *snark, cs.clone())?;
+    if !cs.is_satisfied()? {
+      return Err(SynthesisError::Unsatisfiable);
+    }
+    Ok(snark.get_evaluation_results())
+  }
+
+  fn export_verifier(
+    &self,
+    snark: &MLSnark<CircuitField>,
+    params: &ParamRegistry,
+    vk: &Self::VerifyingKey,
+    path: &Path,
+  ) -> Result<(), Box<dyn Error>> {
+    let schema = PublicInputsSchema::new(snark, params, Self::NAME)?;
+    fs::create_dir_all(path)?;
+    vk.serialize(fs::File::create(path.join(VERIFYING_KEY_FILE))?)?;
+    schema.save(&path.join(SCHEMA_FILE))
+  }
+
+  fn export_solidity_verifier(
+    &self,
+    snark: &MLSnark<CircuitField>,
+    params: &ParamRegistry,
+    vk: &Self::VerifyingKey,
+    path: &Path,
+  ) -> Result<(), Box<dyn Error>> {
+    let schema = PublicInputsSchema::new(snark, params, Self::NAME)?;
+    write_solidity_verifier(vk, &schema, path)
+  }
+}
+
+/// Constraints of the range checked comparison of two variables, `enforce_cmp` of ark-r1cs-std as [MLSnark] calls it.
+/// Measured once, it depends on the bits of the field only.
+fn comparison_cost(should_also_check_equality: bool) -> usize {
+  static COSTS: OnceLock<[usize; 2]> = OnceLock::new();
+  let measure = |check_equality| {
+    let cs = ConstraintSystem::<CircuitField>::new_ref();
+    let var = |n: u64| FpVar::new_witness(cs.clone(), || Ok(CircuitField::from(n))).unwrap();
+    var(1)
+      .enforce_cmp(&var(2), Ordering::Less, check_equality)
+      .unwrap();
+    cs.num_constraints()
+  };
+  COSTS.get_or_init(|| [measure(false), measure(true)])[usize::from(should_also_check_equality)]
+}
+
+/// [comparison_cost] of a variable to a constant, as [MLSnark] range checks the decomposition of a DivConst.
+fn constant_comparison_cost() -> usize {
+  static COST: OnceLock<usize> = OnceLock::new();
+  *COST.get_or_init(|| {
+    let cs = ConstraintSystem::<CircuitField>::new_ref();
+    FpVar::new_witness(cs.clone(), || Ok(CircuitField::from(1u64)))
+      .unwrap()
+      .enforce_cmp(
+        &FpVar::Constant(CircuitField::from(2u64)),
+        Ordering::Less,
+        false,
+      )
+      .unwrap();
+    cs.num_constraints()
+  })
+}
+
+/// The range checks of a reciprocal in [MLSnark]: of the remainder, of its distance to the argument and of the result.
+fn recip_range_cost() -> usize {
+  let s = BigInt::from(SCALE.s);
+  2 * bits_cost(ENCODING_BITS) + bits_cost((s.clone() * s * 2).bits() as u32)
+}
+
+/// The constraints [MLSnark] makes per op.
+impl CostModel for Groth16Backend {
+  fn op_cost(&self, op: &ScalarOp) -> Option<usize> {
+    match op {
+      // public inputs or witnesses, unconstrained
+      ScalarOp::Input | ScalarOp::Constant(_) => Some(0),
+      ScalarOp::Add | ScalarOp::Sub | ScalarOp::Neg => Some(1),
+      // the product, the sum for the offset and the rescaling, its remainder below the scale and its result in range
+      ScalarOp::Mul => Some(3 + below_cost(&BigInt::from(SCALE.s)) + bits_cost(ENCODING_BITS)),
+      // the selection of the larger and the bit, then the comparison
+      ScalarOp::Relu => Some(3 + comparison_cost(true)),
+      // as relu, and the bit in the float encoding
+      ScalarOp::LessThan => Some(4 + comparison_cost(false)),
+      // as less than, the bit only in the float encoding
+      ScalarOp::LessThanConst(_) | ScalarOp::GreaterThanConst(_) => {
+        Some(3 + comparison_cost(false))
+      }
+      // the decomposition and the result, the remainder and the quotient compared to constants
+      ScalarOp::DivConst(_) | ScalarOp::ModConst(_) => Some(2 + 2 * constant_comparison_cost()),
+      // the shifted argument below twice the range
+      ScalarOp::RangeCheck(bits) => Some(below_cost(&(BigInt::from(SCALE.s) << (bits + 1)))),
+      // the inverse, the equation, the sign, the remainder and the result in range, and the zero flag of the hint
+      ScalarOp::Recip => Some(4 + recip_range_cost()),
+      ScalarOp::RecipHint => Some(6 + recip_range_cost()),
+      _ => None,
+    }
+  }
+
+  fn output_cost(&self) -> usize {
+    1
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{Groth16Backend, ProvingBackend};
+  use crate::compile;
+
+  fn prove_and_verify<B: ProvingBackend>(backend: &B, input: Vec<f32>) -> Result<bool, B::Error> {
+    let trained = crate::model::fixed_weights::run_model();
+    let mut snark = compile(&trained);
+    let (pk, vk) = backend.setup(&mut snark)?;
+    snark.set_input(input);
+    let proof = backend.prove(&mut snark, &pk)?;
+    let public_inputs = snark.recorded_public_inputs.clone();
+    backend.verify(&vk, &public_inputs, &proof)
+  }
+
+  #[test]
+  fn test_groth16_backend() {
+    assert_eq!(
+      prove_and_verify(&Groth16Backend, vec![1.0, 2.0, 3.0]),
+      Ok(true)
+    );
+    let trained = crate::model::fixed_weights::run_model();
+    let constraints = Groth16Backend.estimate_constraints(&mut compile(&trained));
+    assert!(constraints.unwrap() > 0);
+    let mut snark = compile(&trained);
+    snark.set_input(vec![1.0, 2.0, 3.0]);
+    let outputs = Groth16Backend.witness_outputs(&mut snark).unwrap();
+    assert_eq!(outputs.len(), trained.graph.outputs.len());
+  }
+}
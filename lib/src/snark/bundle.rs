@@ -0,0 +1,230 @@
+//!
+//! A proof with everything needed to check it, in one json file.
+//!
+//! A [ProofBundle] carries the proof with its public inputs and what they were made for: the proof system, the
+//! version of this crate that synthesized the circuit, the [structural hash](crate::scalar::ScalarGraph::structural_hash)
+//! of the circuit and the fixed-point encoding of the floats. The verifier brings the verifying key and the
+//! [PublicInputsSchema] of the circuit (see [super::verifier]), [verify_bundle] checks the bundle against them.
+//!
+//! Optionally the bundle carries the [Poseidon hash](super::poseidon::input_hash) of all the weights. It's metadata the
+//! prover claims: the proof doesn't depend on it and [verify_bundle] doesn't check it, anyone can change it. To bind
+//! the weights to the proof, make them public inputs (fixed by the schema), commit to them in the circuit (see
+//! [super::Commitment]) or open them in a Merkle tree (see [super::merkle]).
+//!
+//! Bytes (of the proof, of the hashes) are hex, field elements decimal strings as in the schema.
+//!
+//! Verifying a bundle builds without the `native` feature, for `wasm32-unknown-unknown` with the bindings of
+//! [crate::wasm]. Making one and the files need it.
+//!
+
+use std::error::Error;
+#[cfg(feature = "native")]
+use std::{fs, path::Path};
+
+use ark_serialize::CanonicalDeserialize;
+#[cfg(feature = "native")]
+use ark_serialize::CanonicalSerialize;
+use num_bigint::BigInt;
+use serde::{Deserialize, Serialize};
+
+#[cfg(feature = "native")]
+use super::{poseidon::input_hash, scaling_helpers::f_to_bigint, MLSnark};
+use super::{
+  scaling_helpers::f_from_bigint,
+  verifier::{PublicInputsSchema, VerifyingBackend},
+  CircuitField,
+};
+#[cfg(feature = "native")]
+use crate::model::GraphForSnark;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProofBundle {
+  /// The [VerifyingBackend::NAME] of the backend that made the proof.
+  pub backend: String,
+  /// Of this crate, which synthesizes the circuit.
+  pub version: String,
+  /// [crate::scalar::ScalarGraph::structural_hash] of the proven scalar graph.
+  pub circuit_hash: String,
+  /// The encoding of the floats, `round(x * scale) + zero`, see [Note: floats as ints].
+  pub scale: String,
+  pub zero: String,
+  /// The proof in the backend's compressed serialization.
+  pub proof: String,
+  pub public_inputs: Vec<String>,
+  /// The hash of the weights, see [ProofBundle::with_weights_commitment]. Not authenticated by the proof.
+  pub weights_commitment: Option<String>,
+}
+
+#[cfg(feature = "native")]
+pub(crate) fn to_hex(bytes: &[u8]) -> String {
+  bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn from_hex(hex: &str) -> Result<Vec<u8>, Box<dyn Error>> {
+  let not_hex = || format!("Not hex: {}", hex);
+  if !hex.is_ascii() || hex.len() % 2 != 0 {
+    return Err(not_hex().into());
+  }
+  (0..hex.len())
+    .step_by(2)
+    .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).map_err(|_| not_hex().into()))
+    .collect()
+}
+
+impl ProofBundle {
+  /// The bundle of a proof of the snark's circuit, made by the backend. The public inputs are the ones recorded when
+  /// proving, see [MLSnark::recorded_public_inputs].
+  #[cfg(feature = "native")]
+  pub fn new<B: VerifyingBackend>(
+    snark: &MLSnark<CircuitField>,
+    proof: &B::Proof,
+  ) -> Result<Self, Box<dyn Error>>
+  where
+    B::Proof: CanonicalSerialize,
+  {
+    let mut bytes = vec![];
+    proof.serialize(&mut bytes)?;
+    Ok(ProofBundle {
+      backend: B::NAME.to_string(),
+      version: env!("CARGO_PKG_VERSION").to_string(),
+      circuit_hash: to_hex(&snark.graph.structural_hash()),
+      scale: snark.scale.s.to_string(),
+      zero: snark.scale.z.to_string(),
+      proof: to_hex(&bytes),
+      public_inputs: snark
+        .recorded_public_inputs
+        .iter()
+        .map(|x| f_to_bigint(*x).to_string())
+        .collect(),
+      weights_commitment: None,
+    })
+  }
+
+  /// Adds the hash of the weights of the model: of their elements, weight after weight in the order of
+  /// [GraphForSnark::weights], encoded as the circuit's floats. Unauthenticated, see the module docs.
+  #[cfg(feature = "native")]
+  pub fn with_weights_commitment(
+    mut self,
+    g: &GraphForSnark,
+    snark: &MLSnark<CircuitField>,
+  ) -> Self {
+    let elements: Vec<f32> = g
+      .weights
+      .iter()
+      .flat_map(|(_, w)| w.iter().copied())
+      .collect();
+    self.weights_commitment = Some(f_to_bigint(input_hash(&elements, &snark.scale)).to_string());
+    self
+  }
+
+  pub fn public_input_fields(&self) -> Result<Vec<CircuitField>, Box<dyn Error>> {
+    self
+      .public_inputs
+      .iter()
+      .map(|s| {
+        let n: BigInt = s.parse()?;
+        f_from_bigint(n).map_err(|_| format!("Public input {} out of the field", s).into())
+      })
+      .collect()
+  }
+
+  pub fn decode_proof<P: CanonicalDeserialize>(&self) -> Result<P, Box<dyn Error>> {
+    Ok(P::deserialize(&from_hex(&self.proof)?[..])?)
+  }
+
+  #[cfg(feature = "native")]
+  pub fn save(&self, path: &Path) -> Result<(), Box<dyn Error>> {
+    fs::write(path, serde_json::to_string_pretty(self)?)?;
+    Ok(())
+  }
+
+  #[cfg(feature = "native")]
+  pub fn load(path: &Path) -> Result<Self, Box<dyn Error>> {
+    Ok(serde_json::from_str(&fs::read_to_string(path)?)?)
+  }
+}
+
+/// Verifies the proof of the bundle with the verifying key, after checking the bundle against the schema: the same
+/// backend, circuit and encoding, and the public inputs with the constants and weights of the schema.
+/// The weights commitment is not checked, see the module docs.
+pub fn verify_bundle<B: VerifyingBackend>(
+  backend: &B,
+  bundle: &ProofBundle,
+  vk: &B::VerifyingKey,
+  schema: &PublicInputsSchema,
+) -> Result<bool, Box<dyn Error>>
+where
+  B::Proof: CanonicalDeserialize,
+{
+  if bundle.backend != B::NAME || schema.backend != B::NAME {
+    return Err(
+      format!(
+        "The bundle is for {}, the schema for {}, verifying with {}",
+        bundle.backend,
+        schema.backend,
+        B::NAME
+      )
+      .into(),
+    );
+  }
+  if bundle.circuit_hash != schema.circuit_hash {
+    return Err("The bundle is for another circuit than the schema".into());
+  }
+  if (&bundle.scale, &bundle.zero) != (&schema.scale, &schema.zero) {
+    return Err("The bundle encodes the floats differently than the schema".into());
+  }
+  let public_inputs = bundle.public_input_fields()?;
+  schema.check(&public_inputs)?;
+  let proof = bundle.decode_proof::<B::Proof>()?;
+  backend
+    .verify(vk, &public_inputs, &proof)
+    .map_err(|e| format!("Failed to verify: {:?}", e).into())
+}
+
+#[cfg(all(test, feature = "native"))]
+mod tests {
+  use num_bigint::BigInt;
+
+  use super::{verify_bundle, ProofBundle};
+  use crate::{
+    compile,
+    snark::{
+      backend::{Groth16Backend, ProvingBackend, VerifyingBackend},
+      verifier::PublicInputsSchema,
+    },
+  };
+
+  #[test]
+  fn test_bundle_round_trip() {
+    let trained = crate::model::fixed_weights::run_model();
+    let mut snark = compile(&trained);
+    let (pk, vk) = Groth16Backend.setup(&mut snark).unwrap();
+    let schema =
+      PublicInputsSchema::new(&snark, &trained.graph.params, Groth16Backend::NAME).unwrap();
+    snark.set_input(vec![1.0, 2.0, 3.0]);
+    let proof = Groth16Backend.prove(&mut snark, &pk).unwrap();
+    let bundle = ProofBundle::new::<Groth16Backend>(&snark, &proof)
+      .unwrap()
+      .with_weights_commitment(&trained.graph, &snark);
+    assert!(bundle.weights_commitment.is_some());
+
+    let path = std::env::temp_dir().join(format!("zkml-bundle-{}.json", std::process::id()));
+    bundle.save(&path).unwrap();
+    let loaded = ProofBundle::load(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(loaded, bundle);
+    assert!(verify_bundle(&Groth16Backend, &loaded, &vk, &schema).unwrap());
+
+    // a result claimed by the prover, changed
+    let mut forged = loaded.clone();
+    let last = forged.public_inputs.last_mut().unwrap();
+    *last = (last.parse::<BigInt>().unwrap() + 1).to_string();
+    assert!(!verify_bundle(&Groth16Backend, &forged, &vk, &schema).unwrap());
+    let mut other = loaded.clone();
+    other.circuit_hash = "00".repeat(32);
+    assert!(verify_bundle(&Groth16Backend, &other, &vk, &schema).is_err());
+    let mut other = loaded;
+    other.backend = "plonk".to_string();
+    assert!(verify_bundle(&Groth16Backend, &other, &vk, &schema).is_err());
+  }
+}
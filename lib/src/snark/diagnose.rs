@@ -0,0 +1,224 @@
+//!
+//! Where a witness breaks the circuit.
+//!
+//! A backend failing on an unsatisfied constraint doesn't say which one, e.g. the
+//! [witness_outputs](super::backend::ProvingBackend::witness_outputs) of Groth16 are just
+//! [Unsatisfiable](ark_relations::r1cs::SynthesisError::Unsatisfiable).
+//! [diagnose_failure] synthesizes the circuit again with the same witness and finds the first constraint it doesn't
+//! satisfy. The [SynthesisTrace] of the snark maps the constraint to the scalar node that made it,
+//! [InputsTracker::origin](crate::scalar::InputsTracker::origin) the node to the tensor of the model and
+//! [ParamRegistry::layer_of](crate::model::ParamRegistry::layer_of) the tensor to its layer. The value the node should
+//! have comes from the reference evaluation on floats
+//! ([FrozenScalarGraph::evaluate](crate::scalar::FrozenScalarGraph::evaluate)), next to the one of the witness. The
+//! canonical id of the node (see [crate::scalar::ScalarGraph::canonical_order]) stays the same across runs, unlike the
+//! node id.
+//!
+
+use std::{collections::HashMap, fmt};
+
+use ark_relations::r1cs::{ConstraintSynthesizer, ConstraintSystem, ConstraintSystemRef};
+use ark_std::Zero;
+use luminal::prelude::NodeIndex;
+
+use super::{
+  scaling_helpers::{unscaled_bigint, unscaled_f},
+  CircuitField, MLSnark, SourceType, SynthesisTrace,
+};
+use crate::{model::GraphForSnark, scalar::ScalarOp};
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Diagnosis {
+  /// The failure as the backend reported it.
+  pub error: String,
+  /// The first constraint the witness doesn't satisfy. `None` if it satisfies them all or the synthesis fails,
+  /// the rest is `None` then as well.
+  pub constraint: Option<usize>,
+  /// The scalar node that made the constraint, `None` for a constraint of the input hash.
+  pub node: Option<NodeIndex>,
+  pub canonical_id: Option<usize>,
+  pub op: Option<ScalarOp>,
+  /// The tensor of the model the node computes a part of, with the physical index of the part.
+  pub tensor: Option<(NodeIndex, usize)>,
+  pub layer: Option<String>,
+  /// The value of the node in the reference evaluation, `None` if the sources aren't all floats.
+  pub expected: Option<f32>,
+  /// The value of the node in the witness, `None` if it isn't the encoding of a float.
+  pub witnessed: Option<f32>,
+}
+
+impl fmt::Display for Diagnosis {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "{}", self.error)?;
+    let constraint = match self.constraint {
+      Some(constraint) => constraint,
+      None => return Ok(()),
+    };
+    write!(f, ", first unsatisfied constraint {}", constraint)?;
+    let x = match self.node {
+      Some(x) => x,
+      None => return write!(f, " of the input hash"),
+    };
+    write!(f, " of node {:?}", x)?;
+    if let Some(id) = self.canonical_id {
+      write!(f, " (canonical {})", id)?;
+    }
+    if let Some(op) = self.op {
+      write!(f, ", {}", op.name())?;
+    }
+    if let Some((tensor, i)) = self.tensor {
+      write!(f, ", element {} of tensor {:?}", i, tensor)?;
+    }
+    if let Some(layer) = &self.layer {
+      write!(f, " in {}", layer)?;
+    }
+    write!(
+      f,
+      ": expected {:?}, witnessed {:?}",
+      self.expected, self.witnessed
+    )
+  }
+}
+
+/// Locates the failure of a backend on the snark, see the module docs. The snark has the input of the failed attempt
+/// set, `g` is the graph it was compiled from.
+pub fn diagnose_failure<E: fmt::Debug>(
+  error: &E,
+  snark: &mut MLSnark<CircuitField>,
+  g: &GraphForSnark,
+) -> Diagnosis {
+  let mut diagnosis = Diagnosis {
+    error: format!("{:?}", error),
+    ..Default::default()
+  };
+  let cs = ConstraintSystem::<CircuitField>::new_ref();
+  if let Err(e) = (&mut *snark).generate_constraints(cs.clone()) {
+    diagnosis.error = format!("{} (synthesis fails: {:?})", diagnosis.error, e);
+    return diagnosis;
+  }
+  let constraint = match first_unsatisfied(&cs) {
+    Some(constraint) => constraint,
+    None => return diagnosis,
+  };
+  diagnosis.constraint = Some(constraint);
+  let trace: &SynthesisTrace = &snark.trace;
+  let x = match trace.node_of(constraint) {
+    Some(x) => x,
+    None => return diagnosis,
+  };
+  let sc = &snark.graph;
+  diagnosis.node = Some(x);
+  diagnosis.canonical_id = sc.canonical_order().iter().position(|y| *y == x);
+  diagnosis.op = sc.nodes.iter().find(|n| n.id == x).map(|n| n.op);
+  diagnosis.tensor = sc.inputs_tracker.origin.get(&x).copied();
+  diagnosis.layer = diagnosis
+    .tensor
+    .and_then(|(tensor, _)| g.params.layer_of(&g.graph, tensor));
+  diagnosis.expected = reference_inputs(snark).map(|inputs| sc.evaluate(&inputs)[&x]);
+  diagnosis.witnessed = trace
+    .vars
+    .get(&x)
+    .and_then(|v| cs.assigned_value(*v))
+    .and_then(|v| unscaled_f(v, &snark.scale));
+  diagnosis
+}
+
+/// The first constraint the assignment of the constraint system doesn't satisfy.
+fn first_unsatisfied(cs: &ConstraintSystemRef<CircuitField>) -> Option<usize> {
+  // the matrices need the linear combinations inlined
+  cs.finalize();
+  let matrices = cs.to_matrices()?;
+  let inner = cs.borrow()?;
+  let value = |column: usize| {
+    if column < matrices.num_instance_variables {
+      inner.instance_assignment[column]
+    } else {
+      inner.witness_assignment[column - matrices.num_instance_variables]
+    }
+  };
+  let eval = |row: &[(CircuitField, usize)]| {
+    row
+      .iter()
+      .fold(CircuitField::zero(), |acc, (coeff, column)| {
+        acc + *coeff * value(*column)
+      })
+  };
+  (0..matrices.num_constraints)
+    .find(|i| eval(&matrices.a[*i]) * eval(&matrices.b[*i]) != eval(&matrices.c[*i]))
+}
+
+/// The sources of the snark as floats, by the input of the tensor graph. `None` if one isn't set or isn't a float.
+fn reference_inputs(snark: &MLSnark<CircuitField>) -> Option<HashMap<NodeIndex, Vec<f32>>> {
+  snark
+    .graph
+    .inputs_tracker
+    .new_inputs
+    .iter()
+    .map(|(x, little)| {
+      let values = little
+        .iter()
+        .map(|y| match snark.source_map.get(y)? {
+          SourceType::Private(v) => *v,
+          SourceType::Public(v) => Some(*v),
+          SourceType::PublicEncoded(n) => unscaled_bigint(n.clone().into(), &snark.scale),
+        })
+        .collect::<Option<Vec<f32>>>()?;
+      Some((*x, values))
+    })
+    .collect()
+}
+
+#[cfg(test)]
+mod tests {
+  use ark_std::One;
+  use luminal::{graph::Graph, shape::R1};
+  use num_bigint::ToBigUint;
+
+  use super::diagnose_failure;
+  use crate::{
+    compile_graph,
+    model::{GraphForSnark, ParamRegistry},
+    scalar::ScalarOp,
+    snark::{
+      backend::{Groth16Backend, ProvingBackend},
+      scaling_helpers::f_to_bigint,
+      CircuitField, SourceType,
+    },
+  };
+
+  #[test]
+  fn test_diagnose_failure() {
+    let mut cx = Graph::new();
+    let a = cx.tensor::<R1<2>>();
+    let b = a.relu().retrieve();
+    let g = GraphForSnark {
+      graph: cx,
+      input_id: a.id,
+      weights: vec![],
+      outputs: vec![b.id],
+      params: ParamRegistry::default(),
+    };
+    let mut snark = compile_graph(&g);
+    snark.set_input(vec![0.5, -0.5]);
+    assert!(Groth16Backend.witness_outputs(&mut snark).is_ok());
+
+    // "-1" of the field, out of the range of the comparison in the relu
+    let little = snark.graph.inputs_tracker.new_inputs[&a.id][0];
+    let minus_one = f_to_bigint(-CircuitField::one()).to_biguint().unwrap();
+    snark
+      .source_map
+      .insert(little, SourceType::PublicEncoded(minus_one));
+    let error = Groth16Backend.witness_outputs(&mut snark).unwrap_err();
+    let diagnosis = diagnose_failure(&error, &mut snark, &g);
+    assert!(diagnosis.constraint.is_some());
+    let node = diagnosis.node.unwrap();
+    assert_eq!(diagnosis.op, Some(ScalarOp::Relu));
+    assert!(snark
+      .graph
+      .nodes
+      .iter()
+      .any(|n| n.id == node && n.args.contains(&little)));
+    assert!(diagnosis.canonical_id.is_some() && diagnosis.tensor.is_some());
+    assert_eq!(diagnosis.layer, None);
+    assert!(diagnosis.to_string().contains("Relu"));
+  }
+}
@@ -0,0 +1,324 @@
+//!
+//! Chunked proving (see [super::aggregate]) that survives a crash: a [ProvingJob] keeps its progress in a directory.
+//!
+//! The job first evaluates the chunks for the values at their boundaries, the partial state the chunk proofs depend on,
+//! and saves them ([BOUNDARY_FILE]) with what identifies the job ([JOB_FILE]). Then every chunk proof is saved
+//! (`chunk<i>.proof`, the proof and its public inputs) as soon as it's made. Running the job again on the same
+//! directory skips the evaluation and the chunks already proven. Files are written whole or not at all, a crash
+//! while writing one leaves a `.tmp` file behind at worst.
+//!
+//! A directory holding the job of another model, input or chunk size is refused rather than mixed in.
+//!
+
+use std::{
+  collections::HashMap,
+  error::Error,
+  fs,
+  path::{Path, PathBuf},
+};
+
+use ark_groth16::ProvingKey;
+use ark_serialize::{CanonicalDeserialize, CanonicalSerialize};
+use blake2::{Blake2s, Digest};
+use luminal::prelude::NodeIndex;
+use serde::{Deserialize, Serialize};
+use tracing::info;
+
+use super::{
+  aggregate::{
+    compile_chunked, evaluate_boundaries, prove_chunk, set_boundary_inputs, AggregatedProof,
+    ChunkProof, ChunkSnark,
+  },
+  backend::{Groth16Backend, ProvingBackend},
+  CircuitField, Curve,
+};
+use crate::model::TrainedGraph;
+
+/// What identifies the job, json.
+pub const JOB_FILE: &str = "job.json";
+/// The values at the boundaries of the chunks, by node of the scalar graph.
+pub const BOUNDARY_FILE: &str = "boundary.bin";
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct JobInfo {
+  /// Of the weights, the input and the chunk size, see [fingerprint].
+  fingerprint: String,
+  max_nodes: usize,
+  chunks: usize,
+}
+
+/// Hash of the weights (by node), the input and the chunk size, in hex.
+fn fingerprint(trained: &TrainedGraph, input: &[f32], max_nodes: usize) -> String {
+  let mut hasher = Blake2s::new();
+  hasher.update((max_nodes as u64).to_le_bytes());
+  for (x, w) in trained.graph.weights.iter() {
+    hasher.update((x.index() as u64).to_le_bytes());
+    for v in w.iter() {
+      hasher.update(v.to_le_bytes());
+    }
+  }
+  for v in input.iter() {
+    hasher.update(v.to_le_bytes());
+  }
+  hasher
+    .finalize()
+    .iter()
+    .map(|b| format!("{:02x}", b))
+    .collect()
+}
+
+/// Writes to a temporary file first and renames it, so the file is either complete or missing.
+fn write_whole(path: &Path, bytes: &[u8]) -> Result<(), Box<dyn Error>> {
+  let tmp = path.with_extension("tmp");
+  fs::write(&tmp, bytes)?;
+  fs::rename(&tmp, path)?;
+  Ok(())
+}
+
+/// A chunked proof kept in `dir`, see the module docs.
+#[derive(Debug, Clone)]
+pub struct ProvingJob {
+  pub dir: PathBuf,
+  /// The chunk size, see [crate::scalar::ScalarGraph::partition].
+  pub max_nodes: usize,
+}
+
+impl ProvingJob {
+  pub fn new(dir: &Path, max_nodes: usize) -> Self {
+    ProvingJob {
+      dir: PathBuf::from(dir),
+      max_nodes,
+    }
+  }
+
+  fn chunk_path(&self, i: usize) -> PathBuf {
+    self.dir.join(format!("chunk{}.proof", i))
+  }
+
+  fn load_info(&self) -> Result<Option<JobInfo>, Box<dyn Error>> {
+    let path = self.dir.join(JOB_FILE);
+    if !path.exists() {
+      return Ok(None);
+    }
+    Ok(Some(serde_json::from_str(&fs::read_to_string(path)?)?))
+  }
+
+  fn save_boundary(
+    &self,
+    boundary: &HashMap<NodeIndex, CircuitField>,
+  ) -> Result<(), Box<dyn Error>> {
+    let (mut nodes, mut values): (Vec<u64>, Vec<CircuitField>) = (vec![], vec![]);
+    let mut sorted: Vec<_> = boundary.iter().collect();
+    sorted.sort_by_key(|(x, _)| **x);
+    for (x, v) in sorted {
+      nodes.push(x.index() as u64);
+      values.push(*v);
+    }
+    let mut bytes = vec![];
+    nodes.serialize(&mut bytes)?;
+    values.serialize(&mut bytes)?;
+    write_whole(&self.dir.join(BOUNDARY_FILE), &bytes)
+  }
+
+  fn load_boundary(&self) -> Result<HashMap<NodeIndex, CircuitField>, Box<dyn Error>> {
+    let mut file = fs::File::open(self.dir.join(BOUNDARY_FILE))?;
+    let nodes = Vec::<u64>::deserialize(&mut file)?;
+    let values = Vec::<CircuitField>::deserialize(&mut file)?;
+    Ok(
+      nodes
+        .into_iter()
+        .map(|x| NodeIndex::new(x as usize))
+        .zip(values)
+        .collect(),
+    )
+  }
+
+  /// The chunks with their boundary inputs set, from the saved state or evaluated and saved.
+  fn prepare(
+    &self,
+    trained: &TrainedGraph,
+    input: &[f32],
+  ) -> Result<Vec<ChunkSnark>, Box<dyn Error>> {
+    fs::create_dir_all(&self.dir)?;
+    let mut chunks = compile_chunked(trained, self.max_nodes, Some(input.to_vec()));
+    let info = JobInfo {
+      fingerprint: fingerprint(trained, input, self.max_nodes),
+      max_nodes: self.max_nodes,
+      chunks: chunks.len(),
+    };
+    match self.load_info()? {
+      Some(saved) => {
+        if saved != info {
+          return Err(
+            format!(
+              "{} holds the job of another model, input or chunk size",
+              self.dir.display()
+            )
+            .into(),
+          );
+        }
+        let boundary = self.load_boundary()?;
+        for chunk in chunks.iter_mut() {
+          set_boundary_inputs(chunk, &boundary);
+        }
+      }
+      None => {
+        let boundary = evaluate_boundaries(&mut chunks)
+          .map_err(|e| format!("Failed to evaluate the chunks: {:?}", e))?;
+        self.save_boundary(&boundary)?;
+        // last, a job file means the boundary is saved
+        write_whole(
+          &self.dir.join(JOB_FILE),
+          serde_json::to_string_pretty(&info)?.as_bytes(),
+        )?;
+      }
+    }
+    Ok(chunks)
+  }
+
+  /// Indices of the chunks whose proofs are saved.
+  pub fn proven_chunks(&self) -> Result<Vec<usize>, Box<dyn Error>> {
+    Ok(match self.load_info()? {
+      Some(info) => (0..info.chunks)
+        .filter(|i| self.chunk_path(*i).exists())
+        .collect(),
+      None => vec![],
+    })
+  }
+
+  /// Proves chunks not proven yet, in order and at most `limit` of them, saving every proof as it's made.
+  /// The proving keys are those of [super::aggregate::setup_chunked_with_backend] with the chunk size of the job.
+  /// The indices of the chunks proven.
+  pub fn prove_chunks_with_backend<B: ProvingBackend>(
+    &self,
+    backend: &B,
+    trained: &TrainedGraph,
+    input: &[f32],
+    proving_keys: &[B::ProvingKey],
+    limit: Option<usize>,
+  ) -> Result<Vec<usize>, Box<dyn Error>>
+  where
+    B::Proof: CanonicalSerialize,
+  {
+    let mut chunks = self.prepare(trained, input)?;
+    if proving_keys.len() != chunks.len() {
+      return Err(
+        format!(
+          "{} proving keys for {} chunks",
+          proving_keys.len(),
+          chunks.len()
+        )
+        .into(),
+      );
+    }
+    let proven = self.proven_chunks()?;
+    let todo: Vec<usize> = (0..chunks.len())
+      .filter(|i| !proven.contains(i))
+      .take(limit.unwrap_or(usize::MAX))
+      .collect();
+    for i in todo.iter().copied() {
+      let chunk_proof = prove_chunk(backend, &mut chunks[i], &proving_keys[i])
+        .map_err(|e| format!("Failed to prove chunk {}: {:?}", i, e))?;
+      let mut bytes = vec![];
+      chunk_proof.proof.serialize(&mut bytes)?;
+      chunk_proof.public_inputs.serialize(&mut bytes)?;
+      write_whole(&self.chunk_path(i), &bytes)?;
+      info!("Proved chunk {} of {}", i + 1, chunks.len());
+    }
+    Ok(todo)
+  }
+
+  /// The proof of a finished job.
+  pub fn load_proof<P: CanonicalDeserialize>(&self) -> Result<AggregatedProof<P>, Box<dyn Error>> {
+    let info = self
+      .load_info()?
+      .ok_or_else(|| format!("No job in {}", self.dir.display()))?;
+    let chunks = (0..info.chunks)
+      .map(|i| {
+        let mut file = fs::File::open(self.chunk_path(i))
+          .map_err(|_| format!("Chunk {} is not proven yet", i))?;
+        let proof = P::deserialize(&mut file)?;
+        let public_inputs = Vec::<CircuitField>::deserialize(&mut file)?;
+        Ok(ChunkProof {
+          proof,
+          public_inputs,
+        })
+      })
+      .collect::<Result<Vec<_>, Box<dyn Error>>>()?;
+    Ok(AggregatedProof {
+      max_nodes: info.max_nodes,
+      chunks,
+    })
+  }
+
+  /// Proves what's left of the job and returns the whole proof, as [super::aggregate::prove_chunked_with_backend].
+  pub fn run_with_backend<B: ProvingBackend>(
+    &self,
+    backend: &B,
+    trained: &TrainedGraph,
+    input: &[f32],
+    proving_keys: &[B::ProvingKey],
+  ) -> Result<AggregatedProof<B::Proof>, Box<dyn Error>>
+  where
+    B::Proof: CanonicalSerialize + CanonicalDeserialize,
+  {
+    self.prove_chunks_with_backend(backend, trained, input, proving_keys, None)?;
+    self.load_proof()
+  }
+
+  pub fn run(
+    &self,
+    trained: &TrainedGraph,
+    input: &[f32],
+    proving_keys: &[ProvingKey<Curve>],
+  ) -> Result<AggregatedProof, Box<dyn Error>> {
+    self.run_with_backend(&Groth16Backend, trained, input, proving_keys)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::fs;
+
+  use super::ProvingJob;
+  use crate::snark::{
+    aggregate::{setup_chunked, verify_chunked},
+    backend::Groth16Backend,
+  };
+
+  #[test]
+  fn test_resumed_job() {
+    let trained = crate::model::fixed_weights::run_model();
+    let input = [1.0, 2.0, 3.0];
+    let dir = std::env::temp_dir().join(format!("zkml-job-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    let job = ProvingJob::new(&dir, 4);
+    let keys = setup_chunked(&trained, 4).unwrap();
+    let pks = &keys.proving_keys;
+
+    // interrupted after the first chunk
+    let proven = job
+      .prove_chunks_with_backend(&Groth16Backend, &trained, &input, pks, Some(1))
+      .unwrap();
+    assert_eq!(proven, vec![0]);
+    assert_eq!(job.proven_chunks().unwrap(), vec![0]);
+    assert!(job
+      .load_proof::<ark_groth16::Proof<crate::snark::Curve>>()
+      .is_err());
+
+    let aggregated = job.run(&trained, &input, pks).unwrap();
+    assert!(aggregated.chunks.len() > 1);
+    assert_eq!(
+      verify_chunked(&trained, &aggregated, &keys.verifying_keys),
+      Ok(true)
+    );
+    // nothing left to prove
+    let proven = job
+      .prove_chunks_with_backend(&Groth16Backend, &trained, &input, pks, None)
+      .unwrap();
+    assert_eq!(proven, vec![]);
+    assert!(job.run(&trained, &[0.0, 0.0, 0.0], pks).is_err());
+    assert!(ProvingJob::new(&dir, 5).run(&trained, &input, pks).is_err());
+    fs::remove_dir_all(&dir).unwrap();
+  }
+}
@@ -0,0 +1,292 @@
+//!
+//! Merkle commitment to the weights of a model, for weights too many to hash flat (see [super::Commitment]) and for
+//! proving chunk by chunk (see [super::aggregate]) with private weights.
+//!
+//! The weights are taken in the order of [GraphForSnark::weights], elements in the order of physical indices, each
+//! encoded as the circuit takes it (see [Note: floats as ints]). They are cut into leaves of `leaf_size` elements.
+//! A leaf is the Poseidon hash of its elements (see [super::poseidon]), an inner node the hash of its two children,
+//! and the leaves are padded with zeros to a power of two. The root commits to all the weights.
+//!
+//! A circuit opens only the leaves with the weights it uses ([WeightOpening]): it hashes every such leaf, the weights
+//! of the circuit with the rest of the leaf as witnesses, and hashes the path of the leaf up to the root, a public input.
+//! The leaves opened are fixed by the circuit, so the paths need no selection of the order of the children.
+//!
+
+use std::{collections::HashMap, ops::Range, sync::Arc};
+
+use ark_r1cs_std::fields::fp::FpVar;
+use ark_relations::r1cs::SynthesisError;
+use itertools::Itertools;
+use luminal::prelude::NodeIndex;
+
+use super::{
+  poseidon::{poseidon_hash, poseidon_hash_var},
+  scaling_helpers::{f_from_bigint_unsafe, scaled_float, ScaleT},
+  CircuitField,
+};
+use crate::{model::GraphForSnark, scalar::InputsTracker};
+
+/// The tree over encoded weights, see the module docs.
+#[derive(Debug, Clone, PartialEq)]
+pub struct WeightTree {
+  pub leaf_size: usize,
+  pub elements: Vec<CircuitField>,
+  /// The hashes of every level, from the padded leaves up to the root.
+  levels: Vec<Vec<CircuitField>>,
+}
+
+/// The siblings of a leaf from the bottom up, to recompute the root from the leaf.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MerklePath {
+  pub leaf: usize,
+  pub siblings: Vec<CircuitField>,
+}
+
+impl MerklePath {
+  /// The root of the tree with the hash of the leaf.
+  pub fn root(&self, leaf_hash: CircuitField) -> CircuitField {
+    self
+      .siblings
+      .iter()
+      .enumerate()
+      .fold(leaf_hash, |x, (d, sibling)| {
+        if (self.leaf >> d) & 1 == 0 {
+          poseidon_hash(&[x, *sibling])
+        } else {
+          poseidon_hash(&[*sibling, x])
+        }
+      })
+  }
+}
+
+impl WeightTree {
+  pub fn new(elements: Vec<CircuitField>, leaf_size: usize) -> Self {
+    assert!(leaf_size > 0, "Leaves of no elements");
+    let mut leaves: Vec<CircuitField> = elements.chunks(leaf_size).map(poseidon_hash).collect();
+    leaves.resize(
+      leaves.len().max(1).next_power_of_two(),
+      CircuitField::from(0u64),
+    );
+    let mut levels = vec![leaves];
+    while levels.last().unwrap().len() > 1 {
+      let next = levels
+        .last()
+        .unwrap()
+        .chunks(2)
+        .map(poseidon_hash)
+        .collect();
+      levels.push(next);
+    }
+    WeightTree {
+      leaf_size,
+      elements,
+      levels,
+    }
+  }
+
+  /// The tree over the weights, in their order, as the circuit encodes them with the scale.
+  pub fn of_weights(weights: &[(NodeIndex, Vec<f32>)], leaf_size: usize, scale: &ScaleT) -> Self {
+    let elements = weights
+      .iter()
+      .flat_map(|(_, w)| w.iter())
+      .map(|x| f_from_bigint_unsafe(scaled_float(*x, scale)))
+      .collect();
+    Self::new(elements, leaf_size)
+  }
+
+  pub fn root(&self) -> CircuitField {
+    self.levels.last().unwrap()[0]
+  }
+
+  /// Levels below the root.
+  pub fn depth(&self) -> usize {
+    self.levels.len() - 1
+  }
+
+  /// The leaves with elements, not counting the padding.
+  pub fn leaves(&self) -> usize {
+    leaf_count(self.elements.len(), self.leaf_size)
+  }
+
+  /// The hash of the leaf.
+  pub fn leaf(&self, leaf: usize) -> CircuitField {
+    self.levels[0][leaf]
+  }
+
+  pub fn path(&self, leaf: usize) -> MerklePath {
+    assert!(
+      leaf < self.leaves(),
+      "Leaf {} out of {}",
+      leaf,
+      self.leaves()
+    );
+    MerklePath {
+      leaf,
+      siblings: self.levels[..self.depth()]
+        .iter()
+        .enumerate()
+        .map(|(d, level)| level[(leaf >> d) ^ 1])
+        .collect(),
+    }
+  }
+}
+
+fn leaf_count(elements: usize, leaf_size: usize) -> usize {
+  elements.div_ceil(leaf_size)
+}
+
+/// [MerklePath::root] in the circuit, for the leaf at a position fixed by the circuit.
+pub fn merkle_root_var(
+  leaf_hash: &FpVar<CircuitField>,
+  leaf: usize,
+  siblings: &[FpVar<CircuitField>],
+) -> Result<FpVar<CircuitField>, SynthesisError> {
+  siblings
+    .iter()
+    .enumerate()
+    .try_fold(leaf_hash.clone(), |x, (d, sibling)| {
+      if (leaf >> d) & 1 == 0 {
+        poseidon_hash_var(&[x, sibling.clone()])
+      } else {
+        poseidon_hash_var(&[sibling.clone(), x])
+      }
+    })
+}
+
+/// The position of every little node of the weights among the elements of the [WeightTree] over them.
+pub fn weight_positions(
+  graph: &GraphForSnark,
+  tracker: &InputsTracker,
+) -> HashMap<NodeIndex, usize> {
+  graph
+    .weights
+    .iter()
+    .flat_map(|(x, _)| tracker.new_inputs[x].iter().copied())
+    .enumerate()
+    .map(|(p, x)| (x, p))
+    .collect()
+}
+
+/// The leaves of a [WeightTree] a circuit opens, those with the weights it uses. See the module docs.
+#[derive(Debug, Clone)]
+pub struct WeightOpening {
+  /// The little nodes of the circuit that are weights, with their positions among the elements of the tree.
+  pub elements: Vec<(NodeIndex, usize)>,
+  pub leaf_size: usize,
+  /// Elements of the tree, the circuit depends on it through the depth.
+  pub len: usize,
+  /// For the rest of the opened leaves and their paths, `None` for key generation and verification.
+  pub tree: Option<Arc<WeightTree>>,
+}
+
+impl WeightOpening {
+  /// In order.
+  pub fn leaves(&self) -> Vec<usize> {
+    self
+      .elements
+      .iter()
+      .map(|(_, p)| p / self.leaf_size)
+      .sorted()
+      .dedup()
+      .collect()
+  }
+
+  /// Positions of the elements of the leaf.
+  pub fn leaf_elements(&self, leaf: usize) -> Range<usize> {
+    leaf * self.leaf_size..((leaf + 1) * self.leaf_size).min(self.len)
+  }
+
+  pub fn depth(&self) -> usize {
+    leaf_count(self.len, self.leaf_size)
+      .max(1)
+      .next_power_of_two()
+      .trailing_zeros() as usize
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use ark_ff::One;
+  use ark_r1cs_std::{alloc::AllocVar, eq::EqGadget, fields::fp::FpVar, R1CSVar};
+  use ark_relations::r1cs::ConstraintSystem;
+
+  use super::{merkle_root_var, WeightTree};
+  use crate::snark::{
+    aggregate::{
+      compile_chunked_committed, evaluate_boundaries, prove_chunked_committed,
+      setup_chunked_committed, verify_chunked_committed,
+    },
+    backend::Groth16Backend,
+    poseidon::{poseidon_hash, poseidon_hash_var},
+    verifier::{PublicInputsSchema, VerifyingBackend},
+    CircuitField,
+  };
+
+  #[test]
+  fn test_weight_tree() {
+    let elements: Vec<CircuitField> = (0..5u64).map(CircuitField::from).collect();
+    let tree = WeightTree::new(elements.clone(), 2);
+    assert_eq!((tree.leaves(), tree.depth()), (3, 2));
+    for leaf in 0..tree.leaves() {
+      let path = tree.path(leaf);
+      assert_eq!(path.root(tree.leaf(leaf)), tree.root());
+    }
+    assert_eq!(tree.leaf(2), poseidon_hash(&elements[4..]));
+    let mut other = elements.clone();
+    other[3] += CircuitField::one();
+    assert_ne!(WeightTree::new(other, 2).root(), tree.root());
+    assert_eq!(WeightTree::new(elements[..2].to_vec(), 2).depth(), 0);
+
+    let cs = ConstraintSystem::<CircuitField>::new_ref();
+    let path = tree.path(1);
+    let message = elements[2..4]
+      .iter()
+      .map(|m| FpVar::new_witness(cs.clone(), || Ok(*m)).unwrap())
+      .collect::<Vec<_>>();
+    let siblings = path
+      .siblings
+      .iter()
+      .map(|s| FpVar::new_witness(cs.clone(), || Ok(*s)).unwrap())
+      .collect::<Vec<_>>();
+    let leaf = poseidon_hash_var(&message).unwrap();
+    let root = merkle_root_var(&leaf, 1, &siblings).unwrap();
+    assert_eq!(root.value().unwrap(), tree.root());
+    root
+      .enforce_equal(&FpVar::new_input(cs.clone(), || Ok(tree.root())).unwrap())
+      .unwrap();
+    assert!(cs.is_satisfied().unwrap());
+  }
+
+  #[test]
+  fn test_chunks_open_the_weight_tree() {
+    let trained = crate::model::fixed_weights::run_model();
+    let input = vec![1.0, 2.0, 3.0];
+    let keys = setup_chunked_committed(&Groth16Backend, &trained, 4, 2).unwrap();
+    let (aggregated, root) =
+      prove_chunked_committed(&Groth16Backend, &trained, input, &keys, 2).unwrap();
+    assert!(aggregated.chunks.len() > 1);
+    let vks = &keys.verifying_keys;
+    let verify =
+      |root| verify_chunked_committed(&Groth16Backend, &trained, &aggregated, vks, 2, root);
+    assert_eq!(verify(root), Ok(true));
+    assert_eq!(verify(root + CircuitField::one()), Ok(false));
+  }
+
+  #[test]
+  fn test_schema_fixes_the_weight_root() {
+    let trained = crate::model::fixed_weights::run_model();
+    let mut chunks = compile_chunked_committed(&trained, 4, Some(vec![1.0, 2.0, 3.0]), 2);
+    evaluate_boundaries(&mut chunks).unwrap();
+    let snark = &chunks
+      .iter()
+      .find(|c| c.snark.weight_opening.is_some())
+      .unwrap()
+      .snark;
+    let schema =
+      PublicInputsSchema::new(snark, &trained.graph.params, Groth16Backend::NAME).unwrap();
+    let mut public_inputs = snark.recorded_public_inputs.clone();
+    assert!(schema.check(&public_inputs).is_ok());
+    public_inputs[0] += CircuitField::one();
+    assert!(schema.check(&public_inputs).is_err());
+  }
+}
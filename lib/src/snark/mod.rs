@@ -1,3 +1,26 @@
+#[cfg(feature = "native")]
+pub mod aggregate;
+#[cfg(feature = "native")]
+pub mod backend;
+pub mod bundle;
+#[cfg(feature = "native")]
+pub mod diagnose;
+#[cfg(feature = "native")]
+pub mod job;
+#[cfg(feature = "native")]
+pub mod merkle;
+#[cfg(feature = "native")]
+pub mod pool;
+#[cfg(feature = "native")]
+pub mod poseidon;
 pub mod scaling_helpers;
+#[cfg(feature = "native")]
 mod snark;
+#[cfg(feature = "native")]
+pub mod solidity;
+pub mod verifier;
+#[cfg(feature = "native")]
 pub use snark::*;
+
+pub type Curve = ark_bls12_381::Bls12_381;
+pub type CircuitField = ark_bls12_381::Fr;
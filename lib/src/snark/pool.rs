@@ -0,0 +1,435 @@
+//!
+//! Proving the inputs of one model in parallel: a [ProverPool] of workers taking jobs from a queue.
+//!
+//! Every worker has a circuit of the model of its own, proving needs it mutably. The keys are made once, by the first
+//! worker, and the proving key is shared. The workers bound the proofs made at once, and with them the memory, the
+//! queue bounds the jobs waiting: a full queue refuses new jobs ([SubmitError::QueueFull]) rather than growing.
+//!
+//! A job is a proof as a [ProofBundle]. It keeps when it was submitted, started and finished, see [JobMetrics].
+//! Finished jobs don't stay forever: taking the bundle of a job ([ProverPool::take_bundle]) forgets it, and the others
+//! are forgotten [PoolConfig::retain] after they finish, or the oldest first past [PoolConfig::max_retained]. Their
+//! ids are unknown then, as ids never given out.
+//!
+
+use std::{
+  collections::HashMap,
+  error::Error,
+  fmt,
+  panic::{self, AssertUnwindSafe},
+  sync::{
+    atomic::{AtomicU64, Ordering},
+    mpsc, Arc, Mutex,
+  },
+  thread,
+  time::{Duration, Instant},
+};
+
+use ark_groth16::ProvingKey;
+use ark_serialize::CanonicalSerialize;
+use itertools::Itertools;
+use serde::{Deserialize, Serialize};
+use tracing::info;
+
+use super::{
+  backend::{Groth16Backend, ProvingBackend, VerifyingBackend},
+  bundle::ProofBundle,
+  verifier::PublicInputsSchema,
+  CircuitField, Curve, MLSnark,
+};
+use crate::{compile, model::TrainedGraph};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PoolConfig {
+  /// Proofs made at once.
+  pub workers: usize,
+  /// Jobs waiting for a worker, at most.
+  pub max_queued: usize,
+  /// How long a finished job is kept for its client, unless its bundle is taken sooner.
+  pub retain: Duration,
+  /// Finished jobs kept, at most. Past it the oldest are forgotten, however recent.
+  pub max_retained: usize,
+}
+
+impl Default for PoolConfig {
+  fn default() -> Self {
+    PoolConfig {
+      workers: 1,
+      max_queued: 64,
+      retain: Duration::from_secs(600),
+      max_retained: 1024,
+    }
+  }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum JobStatus {
+  Queued,
+  Proving,
+  Done,
+  Failed { error: String },
+}
+
+/// Where the time of a job went, so far for an unfinished one.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct JobMetrics {
+  /// Waiting for a worker.
+  pub queued_secs: f64,
+  /// Proving, 0 for a job still queued.
+  pub proving_secs: f64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SubmitError {
+  /// The input doesn't fit the model.
+  Length { expected: usize, got: usize },
+  /// [PoolConfig::max_queued] jobs are waiting already.
+  QueueFull,
+  /// The workers are gone, e.g. they panicked outside of proving.
+  Stopped,
+}
+
+impl fmt::Display for SubmitError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      SubmitError::Length { expected, got } => {
+        write!(f, "The model takes {} values, got {}", expected, got)
+      }
+      SubmitError::QueueFull => write!(f, "Too many jobs waiting"),
+      SubmitError::Stopped => write!(f, "The prover stopped"),
+    }
+  }
+}
+
+impl Error for SubmitError {}
+
+#[derive(Debug)]
+struct Job {
+  status: JobStatus,
+  bundle: Option<ProofBundle>,
+  submitted: Instant,
+  started: Option<Instant>,
+  finished: Option<Instant>,
+}
+
+type Jobs = Arc<Mutex<HashMap<u64, Job>>>;
+
+/// Forgets the finished jobs kept past [PoolConfig::retain], then the oldest past [PoolConfig::max_retained].
+fn evict(jobs: &mut HashMap<u64, Job>, config: &PoolConfig) {
+  let now = Instant::now();
+  jobs.retain(|_, job| job.finished.map_or(true, |f| now - f < config.retain));
+  let finished = jobs
+    .iter()
+    .filter_map(|(id, job)| job.finished.map(|f| (f, *id)))
+    .sorted()
+    .collect_vec();
+  for (_, id) in finished
+    .iter()
+    .take(finished.len().saturating_sub(config.max_retained))
+  {
+    jobs.remove(id);
+  }
+}
+type Tasks = Arc<Mutex<mpsc::Receiver<(u64, Vec<f32>)>>>;
+
+/// What the first worker reports once the keys are made.
+struct Circuit {
+  input_len: usize,
+  schema: PublicInputsSchema,
+  verifying_key: Vec<u8>,
+}
+
+/// Proves the evaluations of one model on many inputs, see the module docs.
+#[derive(Debug)]
+pub struct ProverPool {
+  jobs: Jobs,
+  config: PoolConfig,
+  queue: mpsc::SyncSender<(u64, Vec<f32>)>,
+  next_id: AtomicU64,
+  input_len: usize,
+  /// Of the circuit, for the verifiers of the bundles.
+  pub schema: PublicInputsSchema,
+  /// Compressed, see [super::verifier::VERIFYING_KEY_FILE].
+  pub verifying_key: Vec<u8>,
+}
+
+impl ProverPool {
+  /// Starts the workers, every one loads the model with `load`. Returns once the keys are made.
+  pub fn start<F>(load: F, config: PoolConfig) -> Result<Self, Box<dyn Error>>
+  where
+    F: Fn() -> TrainedGraph + Send + Sync + 'static,
+  {
+    assert!(config.workers > 0, "A pool needs a worker");
+    let load = Arc::new(load);
+    let jobs: Jobs = Arc::default();
+    let (queue, tasks) = mpsc::sync_channel(config.max_queued);
+    let tasks: Tasks = Arc::new(Mutex::new(tasks));
+
+    let (ready, circuit) = mpsc::channel();
+    {
+      let (load, jobs, tasks) = (load.clone(), jobs.clone(), tasks.clone());
+      thread::spawn(move || {
+        let trained = load();
+        let mut snark = compile(&trained);
+        match setup(&trained, &mut snark) {
+          Ok((pk, circuit)) => {
+            let pk = Arc::new(pk);
+            let _ = ready.send(Ok((pk.clone(), circuit)));
+            work(&trained, &mut snark, &pk, &tasks, &jobs, &config);
+          }
+          Err(e) => {
+            let _ = ready.send(Err(e.to_string()));
+          }
+        }
+      });
+    }
+    let (pk, circuit) = circuit
+      .recv()
+      .map_err(|_| "The prover stopped before making the keys")??;
+    for _ in 1..config.workers {
+      let (load, jobs, tasks, pk) = (load.clone(), jobs.clone(), tasks.clone(), pk.clone());
+      thread::spawn(move || {
+        let trained = load();
+        let mut snark = compile(&trained);
+        work(&trained, &mut snark, &pk, &tasks, &jobs, &config);
+      });
+    }
+
+    Ok(ProverPool {
+      jobs,
+      config,
+      queue,
+      next_id: AtomicU64::new(1),
+      input_len: circuit.input_len,
+      schema: circuit.schema,
+      verifying_key: circuit.verifying_key,
+    })
+  }
+
+  /// Queues a proof of the model on the input, the id of the job. The input is unnormalized, the workers apply the
+  /// scaler of the model.
+  pub fn submit(&self, input: Vec<f32>) -> Result<u64, SubmitError> {
+    if input.len() != self.input_len {
+      return Err(SubmitError::Length {
+        expected: self.input_len,
+        got: input.len(),
+      });
+    }
+    let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+    // registered first, a worker may take the job right away
+    let mut jobs = self.jobs.lock().unwrap();
+    evict(&mut jobs, &self.config);
+    jobs.insert(
+      id,
+      Job {
+        status: JobStatus::Queued,
+        bundle: None,
+        submitted: Instant::now(),
+        started: None,
+        finished: None,
+      },
+    );
+    drop(jobs);
+    self.queue.try_send((id, input)).map_err(|e| {
+      self.jobs.lock().unwrap().remove(&id);
+      match e {
+        mpsc::TrySendError::Full(_) => SubmitError::QueueFull,
+        mpsc::TrySendError::Disconnected(_) => SubmitError::Stopped,
+      }
+    })?;
+    Ok(id)
+  }
+
+  pub fn status(&self, id: u64) -> Option<JobStatus> {
+    self
+      .jobs
+      .lock()
+      .unwrap()
+      .get(&id)
+      .map(|job| job.status.clone())
+  }
+
+  pub fn metrics(&self, id: u64) -> Option<JobMetrics> {
+    let now = Instant::now();
+    self.jobs.lock().unwrap().get(&id).map(|job| {
+      let started = job.started.unwrap_or(now);
+      JobMetrics {
+        queued_secs: (started - job.submitted).as_secs_f64(),
+        proving_secs: job
+          .started
+          .map_or(0.0, |s| (job.finished.unwrap_or(now) - s).as_secs_f64()),
+      }
+    })
+  }
+
+  /// The bundle of a finished job, the job is forgotten then. `None` for a job not done, or not known (any more).
+  pub fn take_bundle(&self, id: u64) -> Option<ProofBundle> {
+    let mut jobs = self.jobs.lock().unwrap();
+    let bundle = jobs.get_mut(&id)?.bundle.take()?;
+    jobs.remove(&id);
+    Some(bundle)
+  }
+
+  /// Jobs known to the pool, queued, proving or finished and not forgotten yet.
+  pub fn jobs(&self) -> usize {
+    self.jobs.lock().unwrap().len()
+  }
+}
+
+/// The keys of the snark and what the pool hands out.
+fn setup(
+  trained: &TrainedGraph,
+  snark: &mut MLSnark<CircuitField>,
+) -> Result<(ProvingKey<Curve>, Circuit), Box<dyn Error>> {
+  let (pk, vk) = Groth16Backend
+    .setup(snark)
+    .map_err(|e| format!("Failed to make keys: {:?}", e))?;
+  let schema = PublicInputsSchema::new(snark, &trained.graph.params, Groth16Backend::NAME)?;
+  let mut verifying_key = vec![];
+  vk.serialize(&mut verifying_key)?;
+  let circuit = Circuit {
+    input_len: snark.graph.inputs_tracker.new_inputs[&snark.og_input_id].len(),
+    schema,
+    verifying_key,
+  };
+  Ok((pk, circuit))
+}
+
+/// Proves the jobs of the queue until the pool is dropped.
+fn work(
+  trained: &TrainedGraph,
+  snark: &mut MLSnark<CircuitField>,
+  pk: &ProvingKey<Curve>,
+  tasks: &Tasks,
+  jobs: &Jobs,
+  config: &PoolConfig,
+) {
+  loop {
+    // the lock is released once a job is taken
+    let (id, input) = match tasks.lock().unwrap().recv() {
+      Ok(task) => task,
+      Err(_) => return,
+    };
+    if let Some(job) = jobs.lock().unwrap().get_mut(&id) {
+      job.status = JobStatus::Proving;
+      job.started = Some(Instant::now());
+    }
+    let input = match &trained.scaler {
+      Some(scaler) => scaler.transform_row(&input),
+      None => input,
+    };
+    // a panic of the circuit fails the job, not the worker
+    let proved = panic::catch_unwind(AssertUnwindSafe(|| {
+      snark.set_input(input);
+      let proof = Groth16Backend
+        .prove(snark, pk)
+        .map_err(|e| format!("Failed to make proof: {:?}", e))?;
+      ProofBundle::new::<Groth16Backend>(snark, &proof).map_err(|e| e.to_string())
+    }))
+    .unwrap_or_else(|_| Err("The prover panicked".to_string()));
+
+    let mut jobs = jobs.lock().unwrap();
+    let job = jobs.get_mut(&id).unwrap();
+    job.finished = Some(Instant::now());
+    match proved {
+      Ok(bundle) => {
+        info!(
+          "Proved job {} in {:.2}s",
+          id,
+          (job.finished.unwrap() - job.started.unwrap()).as_secs_f64()
+        );
+        job.status = JobStatus::Done;
+        job.bundle = Some(bundle);
+      }
+      Err(error) => job.status = JobStatus::Failed { error },
+    }
+    evict(&mut jobs, config);
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::{thread, time::Duration};
+
+  use ark_groth16::VerifyingKey;
+  use ark_serialize::CanonicalDeserialize;
+
+  use super::{JobStatus, PoolConfig, ProverPool, SubmitError};
+  use crate::snark::{backend::Groth16Backend, bundle::verify_bundle, Curve};
+
+  #[test]
+  fn test_prover_pool() {
+    let config = PoolConfig {
+      workers: 2,
+      max_queued: 8,
+      ..PoolConfig::default()
+    };
+    let pool = ProverPool::start(crate::model::fixed_weights::run_model, config).unwrap();
+    assert_eq!(
+      pool.submit(vec![1.0]),
+      Err(SubmitError::Length {
+        expected: 3,
+        got: 1
+      })
+    );
+    let ids: Vec<u64> = (0..3)
+      .map(|i| pool.submit(vec![1.0, 2.0, i as f32]).unwrap())
+      .collect();
+    assert_eq!(pool.status(ids[2] + 1), None);
+
+    let vk = VerifyingKey::<Curve>::deserialize(&pool.verifying_key[..]).unwrap();
+    for id in ids {
+      let status = loop {
+        match pool.status(id).unwrap() {
+          JobStatus::Queued | JobStatus::Proving => thread::sleep(Duration::from_millis(50)),
+          status => break status,
+        }
+      };
+      assert_eq!(status, JobStatus::Done);
+      let metrics = pool.metrics(id).unwrap();
+      assert!(metrics.proving_secs > 0.0 && metrics.queued_secs >= 0.0);
+      let bundle = pool.take_bundle(id).unwrap();
+      assert!(verify_bundle(&Groth16Backend, &bundle, &vk, &pool.schema).unwrap());
+      // taken once, the job is forgotten
+      assert_eq!(pool.status(id), None);
+      assert_eq!(pool.take_bundle(id), None);
+    }
+    assert_eq!(pool.jobs(), 0);
+  }
+
+  #[test]
+  fn test_finished_jobs_are_evicted() {
+    let config = PoolConfig {
+      max_retained: 1,
+      ..PoolConfig::default()
+    };
+    let pool = ProverPool::start(crate::model::fixed_weights::run_model, config).unwrap();
+    let wait = |id| {
+      while matches!(
+        pool.status(id),
+        Some(JobStatus::Queued | JobStatus::Proving)
+      ) {
+        thread::sleep(Duration::from_millis(50));
+      }
+    };
+    let first = pool.submit(vec![1.0, 2.0, 3.0]).unwrap();
+    wait(first);
+    let second = pool.submit(vec![1.0, 2.0, 4.0]).unwrap();
+    wait(second);
+    // one finished job kept, the older one is gone without its bundle taken
+    assert_eq!(pool.status(first), None);
+    assert_eq!(pool.status(second), Some(JobStatus::Done));
+    assert_eq!(pool.jobs(), 1);
+
+    let config = PoolConfig {
+      retain: Duration::ZERO,
+      ..PoolConfig::default()
+    };
+    let pool = ProverPool::start(crate::model::fixed_weights::run_model, config).unwrap();
+    let id = pool.submit(vec![1.0, 2.0, 3.0]).unwrap();
+    while pool.status(id).is_some() {
+      thread::sleep(Duration::from_millis(50));
+    }
+    assert_eq!(pool.take_bundle(id), None);
+  }
+}
@@ -0,0 +1,297 @@
+//!
+//! The Poseidon hash over the scalar field, natively and in the circuit, for committing to the private input
+//! (see [super::MLSnark::input_hash]).
+//!
+//! Width 3 (rate 2, capacity 1), S-box `x^5`, 8 full and 57 partial rounds: the parameters for 128-bit security over a
+//! 255-bit field. The round constants and the MDS matrix are those of the reference generation of the Poseidon authors
+//! (`generate_parameters_grain.sage`, drawn from its Grain LFSR), so the permutation is the reference `x5_255_3` one over
+//! BLS12-381 (its test vector is in the tests). The partial rounds put the S-box on the first element, as the reference
+//! does.
+//!
+//! A message of n elements is absorbed two at a time into a state starting as `[n, 0, 0]`, the last block padded with 0,
+//! with a permutation after every block. The hash is the first rate element of the final state. This sponge is this
+//! module's own, other Poseidon hashes over the same permutation pad and absorb differently.
+//!
+
+use std::{
+  collections::VecDeque,
+  ops::{Add, Mul},
+  sync::OnceLock,
+};
+
+use ark_ff::{Field, FpParameters, PrimeField};
+use ark_r1cs_std::{fields::fp::FpVar, fields::FieldVar};
+use ark_relations::r1cs::SynthesisError;
+use num_bigint::BigUint;
+
+use super::{
+  scaling_helpers::{f_from_bigint_unsafe, scaled_float, ScaleT},
+  CircuitField,
+};
+
+const WIDTH: usize = 3;
+const FULL_ROUNDS: usize = 8;
+const PARTIAL_ROUNDS: usize = 57;
+
+struct Params {
+  round_constants: Vec<[CircuitField; WIDTH]>,
+  mds: [[CircuitField; WIDTH]; WIDTH],
+}
+
+/// The LFSR the reference generation draws the parameters from, seeded with the kind of field and S-box, the bits of
+/// the field, the width and the rounds. Its first 160 bits are dropped, then of every pair of bits the second is kept
+/// if the first is 1.
+struct Grain {
+  bits: VecDeque<bool>,
+}
+
+impl Grain {
+  fn new() -> Self {
+    let mut bits = VecDeque::new();
+    let mut push = |x: usize, n: usize| bits.extend((0..n).rev().map(|i| (x >> i) & 1 == 1));
+    // a prime field, the S-box x^alpha
+    push(1, 2);
+    push(0, 4);
+    push(Self::field_bits(), 12);
+    push(WIDTH, 12);
+    push(FULL_ROUNDS, 10);
+    push(PARTIAL_ROUNDS, 10);
+    push((1 << 30) - 1, 30);
+    let mut grain = Grain { bits };
+    for _ in 0..160 {
+      grain.step();
+    }
+    grain
+  }
+
+  fn field_bits() -> usize {
+    <CircuitField as PrimeField>::Params::MODULUS_BITS as usize
+  }
+
+  fn step(&mut self) -> bool {
+    let b = |i: usize| self.bits[i];
+    let new = b(62) ^ b(51) ^ b(38) ^ b(23) ^ b(13) ^ b(0);
+    self.bits.pop_front();
+    self.bits.push_back(new);
+    new
+  }
+
+  fn bit(&mut self) -> bool {
+    loop {
+      let (first, second) = (self.step(), self.step());
+      if first {
+        return second;
+      }
+    }
+  }
+
+  /// An integer of the bits of the field, the most significant first.
+  fn integer(&mut self) -> BigUint {
+    (0..Self::field_bits()).fold(BigUint::from(0u32), |x, _| {
+      (x << 1u32) + BigUint::from(u32::from(self.bit()))
+    })
+  }
+
+  /// A round constant: integers past the modulus are drawn again.
+  fn round_constant(&mut self) -> CircuitField {
+    let modulus: BigUint = <CircuitField as PrimeField>::Params::MODULUS.into();
+    loop {
+      let x = self.integer();
+      if x < modulus {
+        return CircuitField::from_be_bytes_mod_order(&x.to_bytes_be());
+      }
+    }
+  }
+
+  /// An element of the MDS matrix: reduced, not drawn again.
+  fn element(&mut self) -> CircuitField {
+    CircuitField::from_be_bytes_mod_order(&self.integer().to_bytes_be())
+  }
+}
+
+fn params() -> &'static Params {
+  static PARAMS: OnceLock<Params> = OnceLock::new();
+  PARAMS.get_or_init(|| {
+    let mut grain = Grain::new();
+    let round_constants = (0..FULL_ROUNDS + PARTIAL_ROUNDS)
+      .map(|_| [(); WIDTH].map(|_| grain.round_constant()))
+      .collect();
+    // the Cauchy matrix 1 / (x_i + y_j) of the next 2 * WIDTH elements. The reference draws them again when they
+    // repeat, a sum is 0 or the matrix fails its security checks, none of which happens for these parameters.
+    let xs = [(); WIDTH].map(|_| grain.element());
+    let ys = [(); WIDTH].map(|_| grain.element());
+    let mds = xs.map(|x| ys.map(|y| (x + y).inverse().unwrap()));
+    Params {
+      round_constants,
+      mds,
+    }
+  })
+}
+
+/// The permutation, on field elements or on their variables. `sbox` computes `x^5`.
+pub(crate) fn permute<T>(
+  state: &mut [T; WIDTH],
+  sbox: impl Fn(&T) -> Result<T, SynthesisError>,
+) -> Result<(), SynthesisError>
+where
+  T: Clone + Add<Output = T> + Add<CircuitField, Output = T> + Mul<CircuitField, Output = T>,
+{
+  let params = params();
+  for (r, constants) in params.round_constants.iter().enumerate() {
+    for (x, c) in state.iter_mut().zip(constants) {
+      *x = x.clone() + *c;
+    }
+    // the partial rounds are in the middle, with the S-box on the first element only
+    let full = r < FULL_ROUNDS / 2 || r >= FULL_ROUNDS / 2 + PARTIAL_ROUNDS;
+    for (i, x) in state.iter_mut().enumerate() {
+      if full || i == 0 {
+        *x = sbox(x)?;
+      }
+    }
+    let mixed: Vec<T> = params
+      .mds
+      .iter()
+      .map(|row| {
+        (1..WIDTH).fold(state[0].clone() * row[0], |acc, j| {
+          acc + state[j].clone() * row[j]
+        })
+      })
+      .collect();
+    for (x, y) in state.iter_mut().zip(mixed) {
+      *x = y;
+    }
+  }
+  Ok(())
+}
+
+/// Absorbs the message as described in the module docs, see [permute].
+pub(crate) fn sponge<T>(
+  mut state: [T; WIDTH],
+  message: &[T],
+  sbox: impl Fn(&T) -> Result<T, SynthesisError> + Copy,
+) -> Result<T, SynthesisError>
+where
+  T: Clone + Add<Output = T> + Add<CircuitField, Output = T> + Mul<CircuitField, Output = T>,
+{
+  if message.is_empty() {
+    permute(&mut state, sbox)?;
+  }
+  for block in message.chunks(WIDTH - 1) {
+    for (x, m) in state[1..].iter_mut().zip(block) {
+      *x = x.clone() + m.clone();
+    }
+    permute(&mut state, sbox)?;
+  }
+  Ok(state[1].clone())
+}
+
+pub fn poseidon_hash(message: &[CircuitField]) -> CircuitField {
+  let zero = CircuitField::from(0u64);
+  let state = [CircuitField::from(message.len() as u64), zero, zero];
+  sponge(state, message, |x| Ok(x.square() * x.square() * x)).unwrap()
+}
+
+/// [poseidon_hash] in the circuit.
+pub fn poseidon_hash_var(
+  message: &[FpVar<CircuitField>],
+) -> Result<FpVar<CircuitField>, SynthesisError> {
+  let constant = |n: u64| FpVar::Constant(CircuitField::from(n));
+  let state = [constant(message.len() as u64), constant(0), constant(0)];
+  sponge(state, message, |x| {
+    let x4 = x.square()?.square()?;
+    Ok(x4 * x)
+  })
+}
+
+/// The hash of the input as the circuit computes it, over the encodings of the floats (see [Note: floats as ints]).
+/// Publish it to commit to the input before proving, see [super::MLSnark::input_hash].
+pub fn input_hash(input: &[f32], scale: &ScaleT) -> CircuitField {
+  let message: Vec<CircuitField> = input
+    .iter()
+    .map(|x| f_from_bigint_unsafe(scaled_float(*x, scale)))
+    .collect();
+  poseidon_hash(&message)
+}
+
+#[cfg(test)]
+mod tests {
+  use ark_ff::{Field, PrimeField};
+  use ark_r1cs_std::{alloc::AllocVar, fields::fp::FpVar, R1CSVar};
+  use ark_relations::r1cs::ConstraintSystem;
+
+  use num_bigint::BigUint;
+
+  use super::{input_hash, permute, poseidon_hash, poseidon_hash_var};
+  use crate::{
+    compile,
+    snark::{
+      backend::{Groth16Backend, ProvingBackend, VerifyingBackend},
+      CircuitField,
+    },
+    SCALE,
+  };
+
+  #[test]
+  fn test_poseidon_in_circuit() {
+    let message: Vec<CircuitField> = (0..5u64).map(CircuitField::from).collect();
+    let cs = ConstraintSystem::<CircuitField>::new_ref();
+    let vars: Vec<FpVar<CircuitField>> = message
+      .iter()
+      .map(|m| FpVar::new_witness(cs.clone(), || Ok(*m)).unwrap())
+      .collect();
+    let hash = poseidon_hash_var(&vars).unwrap();
+    assert_eq!(hash.value().unwrap(), poseidon_hash(&message));
+    assert!(cs.is_satisfied().unwrap());
+    // at most three constraints per S-box (fewer on constants), three permutations
+    assert!(cs.num_constraints() <= 3 * 3 * (8 * 3 + 57));
+
+    assert_ne!(poseidon_hash(&message[..4]), poseidon_hash(&message[..5]));
+    // the length is part of the hash, padding doesn't collide
+    let padded = [&message[..3], &[CircuitField::from(0u64)]].concat();
+    assert_ne!(poseidon_hash(&message[..3]), poseidon_hash(&padded));
+  }
+
+  #[test]
+  fn test_reference_permutation() {
+    // the test vector of the reference implementation, poseidonperm_x5_255_3
+    let field = |hex: &str| {
+      let x = BigUint::parse_bytes(hex.as_bytes(), 16).unwrap();
+      CircuitField::from_be_bytes_mod_order(&x.to_bytes_be())
+    };
+    let mut state = [0u64, 1, 2].map(CircuitField::from);
+    permute(&mut state, |x| Ok(x.square() * x.square() * x)).unwrap();
+    assert_eq!(
+      state,
+      [
+        "28ce19420fc246a05553ad1e8c98f5c9d67166be2c18e9e4cb4b4e317dd2a78a",
+        "51f3e312c95343a896cfd8945ea82ba956c1118ce9b9859b6ea56637b4b1ddc4",
+        "3b2b69139b235626a0bfb56c9527ae66a7bf486ad8c11c14d1da0c69bbe0f79a",
+      ]
+      .map(field)
+    );
+  }
+
+  #[test]
+  fn test_input_hash_in_snark() {
+    let trained = crate::model::fixed_weights::run_model();
+    let mut snark = compile(&trained).with_input_hash();
+    let (pk, vk) = Groth16Backend.setup(&mut snark).unwrap();
+    let input = vec![1.0, 2.0, 3.0];
+    snark.set_input(input.clone());
+    let proof = Groth16Backend.prove(&mut snark, &pk).unwrap();
+    let mut public_inputs = snark.recorded_public_inputs.clone();
+    assert_eq!(snark.get_input_hash(), Some(input_hash(&input, &SCALE)));
+    assert_eq!(Groth16Backend.verify(&vk, &public_inputs, &proof), Ok(true));
+    assert_eq!(
+      snark.get_evaluation_results().len(),
+      trained.graph.outputs.len()
+    );
+
+    // the proof doesn't verify for a commitment to another input
+    public_inputs[0] = input_hash(&[1.0, 2.0, 3.5], &SCALE);
+    assert_eq!(
+      Groth16Backend.verify(&vk, &public_inputs, &proof),
+      Ok(false)
+    );
+  }
+}
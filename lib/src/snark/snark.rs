@@ -1,39 +1,42 @@
-use std::convert::TryInto;
-use std::{collections::HashMap, fmt::Debug};
+use std::convert::{TryFrom, TryInto};
+use std::{
+  collections::{HashMap, HashSet},
+  fmt::Debug,
+  ops::Range,
+};
 
 use ark_bls12_381::Bls12_381;
 use ark_bls12_381::Fr;
-use ark_groth16::Groth16;
+use ark_ff::{Field, Zero};
 use ark_groth16::Proof;
 use ark_groth16::ProvingKey;
 use ark_groth16::VerifyingKey;
+use ark_r1cs_std::alloc::AllocVar;
+use ark_r1cs_std::eq::EqGadget;
 use ark_r1cs_std::fields::fp::AllocatedFp;
 use ark_r1cs_std::fields::fp::FpVar;
 use ark_relations::{
   lc,
-  r1cs::{ConstraintSynthesizer, ConstraintSystem, ConstraintSystemRef, SynthesisError},
+  r1cs::{
+    ConstraintSynthesizer, ConstraintSystem, ConstraintSystemRef, LinearCombination,
+    SynthesisError, Variable,
+  },
 };
-use ark_snark::SNARK;
 use ark_std::cmp::Ordering::Less;
 use itertools::Itertools;
-use luminal::prelude::petgraph::Direction::Outgoing;
 
 ///
 /// Produce snark from the computation after scalar and integer transformations.
 ///
-use luminal::{
-  op::{Add, LessThan, Mul, Recip},
-  prelude::{
-    petgraph::{self, visit::EdgeRef, Direction::Incoming},
-    NodeIndex,
-  },
-};
+use luminal::prelude::NodeIndex;
 use num_bigint::{BigInt, BigUint};
-use tracing::{instrument, warn};
+use tracing::{field, instrument, warn, Span};
 
-use crate::scalar::ConstantOp;
-use crate::scalar::InputOp;
-use crate::scalar::{InputsTracker, ScalarGraph};
+use super::CircuitField;
+use crate::scalar::{FrozenScalarGraph, InputsTracker, ScalarOp};
+use crate::snark::backend::{Groth16Backend, ProvingBackend};
+use crate::snark::merkle::{merkle_root_var, WeightOpening};
+use crate::snark::poseidon::{poseidon_hash, poseidon_hash_var};
 use crate::snark::scaling_helpers::*;
 
 /// Tensor computation is initialized by setting input tensors data and then evaluating.
@@ -65,6 +68,9 @@ pub fn snark_input_mapping(
 pub enum SourceType<F> {
   Private(Option<F>),
   Public(F),
+  /// A public input given already encoded (see [Note: floats as integers]), not to be rescaled.
+  /// For values computed by another circuit, which have to match exactly, see [super::aggregate].
+  PublicEncoded(BigUint),
 }
 
 impl SourceType<BigUint> {
@@ -87,6 +93,18 @@ fn add_add(a: BigInt, b: BigInt, scale: &ScaleT) -> BigInt {
   // r.try_into().ok()
 }
 
+// --
+// A -- B := A-B+z
+// The subtraction on floats, as [add_add].
+fn sub_sub(a: BigInt, b: BigInt, scale: &ScaleT) -> BigInt {
+  a - b + scale.z
+}
+
+// Negation: -- A := 2z-A
+fn neg_neg(a: BigInt, scale: &ScaleT) -> BigInt {
+  BigInt::from(scale.z) * 2 - a
+}
+
 #[derive(Debug, Clone)]
 pub struct DivisionResult {
   result: BigInt,
@@ -108,9 +126,6 @@ pub fn mul_mul(a: BigInt, b: BigInt, scale: &ScaleT) -> DivisionResult {
   }
 }
 
-pub type Curve = ark_bls12_381::Bls12_381;
-pub type CircuitField = ark_bls12_381::Fr;
-
 ///
 /// NOTE on integer vs float computation:
 ///
@@ -226,13 +241,21 @@ pub type CircuitField = ark_bls12_381::Fr;
 ///
 #[derive(Debug)]
 pub struct MLSnark<F> {
-  pub graph: ScalarGraph,
+  /// The circuit, in the compact form of the scalar graph (see [FrozenScalarGraph]).
+  pub graph: FrozenScalarGraph,
   // start here
   pub scale: ScaleT,
   // pub private_inputs: HashMap<NodeIndex, Option<Vec<f32>>>,
   pub source_map: HashMap<NodeIndex, SourceType<f32>>,
   // for convenience
   pub og_input_id: NodeIndex,
+  /// Whether the circuit exposes the Poseidon hash of the input (see [super::poseidon::input_hash]) as its first public input,
+  /// before those of the recorded nodes. The input stays private, the hash commits to it. See [MLSnark::with_input_hash].
+  pub input_hash: bool,
+  /// Tensors public only as their hash, a public input for every commitment after all the others. See [Commitment].
+  pub commitments: Vec<Commitment>,
+  /// Weights committed to by the root of a Merkle tree, a public input after the input hash. See [WeightOpening].
+  pub weight_opening: Option<WeightOpening>,
   // pub inputs_tracker : InputsTracker
 
   // this is needed due to some redundancy in how public inputs need to be passed to verify.
@@ -240,16 +263,145 @@ pub struct MLSnark<F> {
   // The few last elements record the result of the circuit, last element if single output. This is due to the topo ordering and model with single output vector, record more info if for our graph toposort stops guaranteeing that.
   // In practice: save this field after calling mk_proof. Share with the verifier.
   pub recorded_public_inputs: Vec<F>,
+  // The node of every public input, in order. Unlike the above it is filled up on key generation as well.
+  pub recorded_public_nodes: Vec<NodeIndex>,
+  /// Which constraints every node made, filled up like the above. See [SynthesisTrace].
+  pub trace: SynthesisTrace,
+}
+
+/// The Poseidon hash (see [super::poseidon]) of tensors of the original graph, inputs or retrieved ones, in the circuit
+/// in place of their elements. The elements of the tensors are hashed one after another, in the order of physical
+/// indices, as [super::poseidon::input_hash] does natively. A committed result isn't a public input on its own.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Commitment {
+  pub tensors: Vec<NodeIndex>,
+}
+
+impl Commitment {
+  /// The little nodes of the committed tensors, in the order they are hashed.
+  pub fn little_nodes(&self, tracker: &InputsTracker) -> Vec<NodeIndex> {
+    self
+      .tensors
+      .iter()
+      .flat_map(|x| {
+        tracker
+          .new_inputs
+          .get(x)
+          .or_else(|| tracker.new_outputs.get(x))
+          .unwrap_or_else(|| panic!("Committed tensor {:?} is neither an input nor an output", x))
+          .iter()
+          .copied()
+      })
+      .collect()
+  }
+}
+
+/// What the synthesis made for every node, to find the node of a constraint (see [super::diagnose]).
+#[derive(Debug, Clone, Default)]
+pub struct SynthesisTrace {
+  /// The constraints of every node, in the order of synthesis. Those of the input hash come after them all.
+  pub constraints: Vec<(NodeIndex, Range<usize>)>,
+  /// The variable of the result of every node.
+  pub vars: HashMap<NodeIndex, Variable>,
+}
+
+impl SynthesisTrace {
+  /// The node that made the constraint, `None` for those of the input hash.
+  pub fn node_of(&self, constraint: usize) -> Option<NodeIndex> {
+    let i = self
+      .constraints
+      .partition_point(|(_, range)| range.end <= constraint);
+    self
+      .constraints
+      .get(i)
+      .filter(|(_, range)| range.contains(&constraint))
+      .map(|(x, _)| *x)
+  }
 }
 
 pub type SourceMap = HashMap<NodeIndex, SourceType<f32>>;
 
 impl MLSnark<CircuitField> {
   /// Watch out: this needs to be called straight after make_proof.
+  /// Assumes a single output of a single element, see [MLSnark::get_evaluation_results] otherwise.
   pub fn get_evaluation_result(&self) -> CircuitField {
     self.recorded_public_inputs.last().unwrap().clone()
   }
 
+  /// The results by the retrieved tensor of the original graph, elements in the order of physical indices. Not the
+  /// committed ones, see [MLSnark::get_commitments].
+  /// Same as above, call it straight after make_proof.
+  pub fn get_evaluation_results(&self) -> HashMap<NodeIndex, Vec<CircuitField>> {
+    let values: HashMap<NodeIndex, CircuitField> = self
+      .recorded_public_nodes
+      .iter()
+      .copied()
+      .zip(
+        self
+          .recorded_public_inputs
+          .iter()
+          .skip(self.leading_hashes())
+          .copied(),
+      )
+      .collect();
+    self
+      .graph
+      .inputs_tracker
+      .new_outputs
+      .iter()
+      .filter(|(x, _)| !self.commitments.iter().any(|c| c.tensors.contains(x)))
+      .map(|(x, little)| (*x, little.iter().map(|y| values[y]).collect()))
+      .collect()
+  }
+
+  /// The hash of the input the proof commits to, see [MLSnark::input_hash]. Same as above, call it straight after make_proof.
+  pub fn get_input_hash(&self) -> Option<CircuitField> {
+    if self.input_hash {
+      self.recorded_public_inputs.first().copied()
+    } else {
+      None
+    }
+  }
+
+  /// The root of the weights the proof opens, see [MLSnark::weight_opening]. Same as above, call it straight after make_proof.
+  pub fn get_weight_root(&self) -> Option<CircuitField> {
+    self.weight_opening.as_ref().and_then(|_| {
+      self
+        .recorded_public_inputs
+        .get(self.input_hash as usize)
+        .copied()
+    })
+  }
+
+  /// The public inputs before those of the recorded nodes: the input hash and the root of the weights.
+  pub fn leading_hashes(&self) -> usize {
+    self.input_hash as usize + self.weight_opening.is_some() as usize
+  }
+
+  /// Exposes the hash of the input as a public input, see [MLSnark::input_hash].
+  pub fn with_input_hash(mut self) -> Self {
+    self.input_hash = true;
+    self
+  }
+
+  /// Opens the weights of the circuit in a Merkle tree, see [WeightOpening].
+  pub fn with_weight_opening(mut self, opening: WeightOpening) -> Self {
+    self.weight_opening = Some(opening);
+    self
+  }
+
+  /// Commits to the tensors, see [Commitment].
+  pub fn with_commitment(mut self, tensors: Vec<NodeIndex>) -> Self {
+    self.commitments.push(Commitment { tensors });
+    self
+  }
+
+  /// The values of the [MLSnark::commitments], in order. Same as above, call it straight after make_proof.
+  pub fn get_commitments(&self) -> Vec<CircuitField> {
+    let n = self.recorded_public_inputs.len();
+    self.recorded_public_inputs[n.saturating_sub(self.commitments.len())..].to_vec()
+  }
+
   pub fn set_input(&mut self, value: Vec<f32>) {
     set_input(
       &mut self.source_map,
@@ -259,31 +411,40 @@ impl MLSnark<CircuitField> {
     )
   }
 
+  /// Sets another private input tensor than the input, e.g. the weights of a training step.
+  pub fn set_private(&mut self, tensor: NodeIndex, value: Vec<f32>) {
+    set_input(
+      &mut self.source_map,
+      &self.graph.inputs_tracker,
+      tensor,
+      value,
+    )
+  }
+
+  /// Groth16 keys, see [Groth16Backend].
+  #[instrument(level = "info", name = "setup", skip_all)]
   pub fn make_keys(
     &mut self,
   ) -> Result<(ProvingKey<Bls12_381>, VerifyingKey<Bls12_381>), SynthesisError> {
-    let rng = &mut ark_std::test_rng();
-    // generate the setup parameters
-    Groth16::<Bls12_381>::circuit_specific_setup(self, rng)
+    Groth16Backend.setup(self)
   }
 
   // first provide all inputs with the set_input method, otherwise SynthesisError
+  #[instrument(level = "info", name = "prove", skip_all)]
   pub fn make_proof(
     &mut self,
     pk: &ProvingKey<Bls12_381>,
   ) -> Result<Proof<Bls12_381>, SynthesisError> {
-    let rng = &mut ark_std::test_rng();
-    Groth16::<Bls12_381>::prove(pk, self, rng)
+    Groth16Backend.prove(self, pk)
   }
 }
 
 fn set_input(source_map: &mut SourceMap, tracker: &InputsTracker, id: NodeIndex, value: Vec<f32>) {
-  let little_ids = tracker
-    .new_inputs
-    .get(&id)
-    .unwrap_or_else(|| panic!("Wrong id"));
-  for (little_id, v) in little_ids.into_iter().zip(value) {
-    source_map.insert(*little_id, SourceType::Private(Some(v)));
+  let assigned = tracker
+    .assign(id, &value)
+    .unwrap_or_else(|e| panic!("Wrong input: {:?}", e));
+  for (little_id, v) in assigned {
+    source_map.insert(little_id, SourceType::Private(Some(v)));
   }
 }
 
@@ -292,13 +453,23 @@ impl ConstraintSynthesizer<CircuitField> for &mut MLSnark<CircuitField> {
   ///
   /// We traverse the computation DAG in toposort order, assigning variables for a result of every node and asserting its value with snark constraints.
   /// We track the variable assignments (as bigints), which to us encode float values encoded as described in [Note: floats as ints].
-  #[instrument(level = "debug", name = "generate_constraints-MLSnark")]
+  #[instrument(
+    level = "info",
+    name = "synthesize",
+    skip_all,
+    fields(nodes = self.graph.node_count(), constraints = field::Empty)
+  )]
   fn generate_constraints(
     self,
     cs: ConstraintSystemRef<CircuitField>,
   ) -> Result<(), SynthesisError> {
     type F = CircuitField;
-    let graph = &self.graph.graph;
+    let results = self.graph.results();
+    let committed: HashSet<NodeIndex> = self
+      .commitments
+      .iter()
+      .flat_map(|c| c.little_nodes(&self.graph.inputs_tracker))
+      .collect();
     let scale = self.scale;
     let source_map: HashMap<NodeIndex, SourceType<BigUint>> = self
       .source_map
@@ -309,11 +480,13 @@ impl ConstraintSynthesizer<CircuitField> for &mut MLSnark<CircuitField> {
           SourceType::Private(Some(x)) => SourceType::scaled_private(x, &scale),
           SourceType::Private(None) => SourceType::Private(None),
           SourceType::Public(x) => SourceType::scaled_public(x, &scale),
+          SourceType::PublicEncoded(n) => SourceType::Public(n),
         };
         (k, v)
       })
       .collect();
     let mut public_record: Vec<F> = vec![];
+    let mut public_nodes: Vec<NodeIndex> = vec![];
 
     // return public input variable and assignment but also record it in the map
     let mk_public_input = |n: BigInt, public_record: &mut Vec<_>| {
@@ -322,30 +495,63 @@ impl ConstraintSynthesizer<CircuitField> for &mut MLSnark<CircuitField> {
       Ok((v, Some(n.clone())))
     };
 
-    let pi = petgraph::algo::toposort(&graph.graph, None).unwrap();
-    let mut vars: HashMap<NodeIndex, ark_relations::r1cs::Variable> = HashMap::new();
+    // the hash of the input goes first, computed natively here and enforced once the input variables are made
+    let input_hash = if self.input_hash {
+      let input_ids = &self.graph.inputs_tracker.new_inputs[&self.og_input_id];
+      let value = input_ids
+        .iter()
+        .map(|x| match source_map.get(x) {
+          Some(SourceType::Private(Some(n))) | Some(SourceType::Public(n)) => {
+            F::try_from(n.clone()).ok()
+          }
+          _ => None,
+        })
+        .collect::<Option<Vec<F>>>()
+        .map(|message| poseidon_hash(&message));
+      public_record.extend(value);
+      let var = FpVar::new_input(cs.clone(), || {
+        value.ok_or(SynthesisError::AssignmentMissing)
+      })?;
+      Some((input_ids, var))
+    } else {
+      None
+    };
+    // the root of the weights goes next, enforced like the input hash
+    let weight_root = match self.weight_opening.as_ref() {
+      Some(opening) => {
+        let value = opening.tree.as_ref().map(|t| t.root());
+        public_record.extend(value);
+        Some(FpVar::new_input(cs.clone(), || {
+          value.ok_or(SynthesisError::AssignmentMissing)
+        })?)
+      }
+      None => None,
+    };
+
+    let mut vars: HashMap<NodeIndex, Variable> = HashMap::new();
     let mut assignments: HashMap<NodeIndex, Option<BigInt>> = HashMap::new();
+    let mut node_constraints: Vec<(NodeIndex, Range<usize>)> = vec![];
 
-    for x in pi {
-      let incoming: Vec<_> = graph
-        .edges_directed(x, Incoming)
-        .filter_map(|e| e.weight().as_data().map(|d| (d, e.source())))
-        .sorted_by_key(|((inp, _, _), _)| *inp)
-        .collect();
+    // the nodes are in a topological order
+    for node in self.graph.nodes.iter() {
+      let (x, op, incoming) = (node.id, node.op, &node.args);
+      let first_constraint = cs.num_constraints();
 
       let (v, ass) = {
         // SOURCE
         if incoming.is_empty() {
-          if graph.check_node_type::<ConstantOp>(x) {
-            let constant_op = graph
-              .node_weight(x)
-              .unwrap()
-              .as_any()
-              .downcast_ref::<ConstantOp>()
-              .unwrap();
-            let n = scaled_float(constant_op.val, &scale);
-            mk_public_input(n, &mut public_record)?
-          } else if graph.check_node_type::<InputOp>(x) {
+          if let ScalarOp::Constant(val) = op {
+            let n = scaled_float(val, &scale);
+            if self.graph.inputs_tracker.constants.contains_key(&x) {
+              // baked into the circuit, a combination of the one variable: no constraint, no public input
+              let one = ConstraintSystem::<CircuitField>::one();
+              let v = cs.new_lc(lc!() + (f_from_bigint(n.clone())?, one))?;
+              (v, Some(n))
+            } else {
+              public_nodes.push(x);
+              mk_public_input(n, &mut public_record)?
+            }
+          } else if op == ScalarOp::Input {
             let src_ty = source_map
               .get(&x)
               .unwrap_or_else(|| panic!("Unknown source node {:?}!", x));
@@ -359,52 +565,401 @@ impl ConstraintSynthesizer<CircuitField> for &mut MLSnark<CircuitField> {
                 })?,
                 mn.clone().map(Into::into),
               ),
-              Public(n) => mk_public_input(n.clone().into(), &mut public_record)?,
+              Public(n) => {
+                public_nodes.push(x);
+                mk_public_input(n.clone().into(), &mut public_record)?
+              }
+              PublicEncoded(_) => unreachable!("Encoded sources are converted to public above"),
             }
           } else {
-            panic!(
-              "Unknown source type: {:?}",
-              graph.node_weight(x).unwrap().type_name()
-            )
+            panic!("Unknown source type: {}", op.name())
           }
         }
         // UNOP
-        else if let Some((((_, _, _), y),)) = incoming.iter().collect_tuple() {
+        else if let Some((y,)) = incoming.iter().copied().collect_tuple() {
           let yy = vars.get(&y).unwrap().clone();
           let yy_val = assignments.get(&y).unwrap().clone();
 
-          if graph.check_node_type::<Recip>(x) {
-            todo!("Fix recip to work with the changed float scaling.");
-            // we have n = f * scale
-            // The inverse is: 1/f = scale/n
-            // so its represented by: m = scale * scale / n
-            // let ass = yy_val.map(|y| {
-            //   scale_f.square()
-            //     * y.inverse().unwrap_or_else(|| {
-            //       warn!("Tried inversing 0. Returning 0");
-            //       CircuitField::zero()
-            //     })
-            // });
-            // let v = cs.new_witness_variable(|| ass.ok_or(SynthesisError::AssignmentMissing))?;
-            // cs.enforce_constraint(
-            //   lc!() + yy,
-            //   lc!() + v,
-            //   lc!() + (scale_f * scale_f, ConstraintSystem::<CircuitField>::one()),
-            // )?; // m * n == scale * scale
-            // (v, ass)
+          if op == ScalarOp::Relu {
+            // relu in the offset encoding is max(y, F(z)), as F(z) encodes 0.0
+            //
+            // witness assignments:
+            //   lt     <- (y < z)
+            //   lo, hi <- if lt then (y, z) else (z, y)
+            //
+            // enforce:
+            //   lo - z = lt * (y - z)
+            //   hi - y = lt * (z - y)
+            //   lt = 0 or 1                         // (lt - 1) * lt == 0
+            //   lo <= hi
+            //
+            // and hi is the result. On y == z both choices of lt give the same hi.
+            let z = BigInt::from(scale.z);
+            let z_f = F::from(scale.z);
+            let one = ConstraintSystem::<CircuitField>::one();
+            let lt_ass_bool = yy_val.clone().map(|y| y < z);
+            let lt_ass = lt_ass_bool
+              .clone()
+              .map(|b| if b { BigInt::from(1) } else { BigInt::from(0) });
+            let make_lo_hi = |lo| {
+              let ass = lt_ass_bool
+                .clone()
+                .and_then(|b| yy_val.clone().map(|y| if b == lo { y } else { z.clone() }));
+              Ok((
+                cs.new_witness_variable(|| {
+                  ass
+                    .clone()
+                    .ok_or(SynthesisError::AssignmentMissing)
+                    .and_then(|x| f_from_bigint(x.clone()))
+                })?,
+                ass.clone(),
+              ))
+            };
+            let (lo, lo_val) = make_lo_hi(true)?;
+            let (hi, hi_val) = make_lo_hi(false)?;
+            let lt = cs.new_witness_variable(|| {
+              lt_ass
+                .clone()
+                .ok_or(SynthesisError::AssignmentMissing)
+                .and_then(f_from_bigint)
+            })?;
+
+            cs.enforce_constraint(lc!() + lt, lc!() + yy - (z_f, one), lc!() + lo - (z_f, one))?;
+            cs.enforce_constraint(lc!() + lt, lc!() + (z_f, one) - yy, lc!() + hi - yy)?;
+            cs.enforce_constraint(lc!() + lt, lc!() + one - lt, lc!())?;
+
+            let lo_var = FpVar::<Fr>::Var(AllocatedFp::new(
+              lo_val.map(|x| f_from_bigint(x.clone()).ok()).flatten(),
+              lo,
+              cs.clone(),
+            ));
+            let hi_var = FpVar::<Fr>::Var(AllocatedFp::new(
+              hi_val
+                .clone()
+                .map(|x| f_from_bigint(x.clone()).ok())
+                .flatten(),
+              hi,
+              cs.clone(),
+            ));
+            lo_var.enforce_cmp(&hi_var, Less, true)?;
+
+            (hi, hi_val)
+          } else if let ScalarOp::LessThanConst(c) | ScalarOp::GreaterThanConst(c) = op {
+            // x < c is y < k for k = F(c) rounded up, c < x is the negation of y < k for k = F(c) rounded down plus one,
+            // F the offset encoding. The result is the bit b in the float encoding, u = b * s + z.
+            //
+            // witness assignments:
+            //   lt     <- (y < k)
+            //   lo, hi <- if lt then (y, k) else (k - 1, y)
+            //   u      <- b * s + z, b = lt for x < c and 1 - lt for c < x
+            //
+            // enforce, with lt * s = u - z (x < c) or s + z - u (c < x):
+            //   s * (lo - (k - 1)) = lt * s * (y - (k - 1))
+            //   s * (hi - y) = lt * s * (k - y)
+            //   lt * s * (lt * s - s) = 0               // lt = 0 or 1
+            //   lo < hi
+            //
+            // The constant is in the constraints, not a public input, and lt is not a witness of its own.
+            let less_than = matches!(op, ScalarOp::LessThanConst(_));
+            let z = BigInt::from(scale.z);
+            let scaled = f64::from(c) * scale.s as f64;
+            let k = if less_than {
+              BigInt::from(scaled.ceil() as i128) + z.clone()
+            } else {
+              BigInt::from(scaled.floor() as i128) + z.clone() + 1
+            };
+            let k_f = f_from_bigint_unsafe(k.clone());
+            let k0_f = f_from_bigint_unsafe(k.clone() - 1);
+            let (s_f, z_f) = (F::from(scale.s), F::from(scale.z));
+            let one = ConstraintSystem::<CircuitField>::one();
+            let lt_ass_bool = yy_val.clone().map(|y| y < k);
+            let make_lo_hi = |lo| {
+              let ass = lt_ass_bool.and_then(|b| {
+                yy_val.clone().map(|y| match (b, lo) {
+                  (true, true) | (false, false) => y,
+                  (true, false) => k.clone(),
+                  (false, true) => k.clone() - 1,
+                })
+              });
+              Ok((
+                cs.new_witness_variable(|| {
+                  ass
+                    .clone()
+                    .ok_or(SynthesisError::AssignmentMissing)
+                    .and_then(|x| f_from_bigint(x.clone()))
+                })?,
+                ass.clone(),
+              ))
+            };
+            let (lo, lo_val) = make_lo_hi(true)?;
+            let (hi, hi_val) = make_lo_hi(false)?;
+            let u_ass = lt_ass_bool.map(|lt| {
+              let b = if lt == less_than { 1 } else { 0 };
+              BigInt::from(b) * scale.s + scale.z
+            });
+            let u = cs.new_witness_variable(|| {
+              u_ass
+                .clone()
+                .ok_or(SynthesisError::AssignmentMissing)
+                .and_then(f_from_bigint)
+            })?;
+            let lt_s = || {
+              if less_than {
+                lc!() + u - (z_f, one)
+              } else {
+                lc!() + (s_f + z_f, one) - u
+              }
+            };
+
+            cs.enforce_constraint(
+              lt_s(),
+              lc!() + yy - (k0_f, one),
+              lc!() + (s_f, lo) - (s_f * k0_f, one),
+            )?;
+            cs.enforce_constraint(
+              lt_s(),
+              lc!() + (k_f, one) - yy,
+              lc!() + (s_f, hi) - (s_f, yy),
+            )?;
+            cs.enforce_constraint(lt_s(), lt_s() - (s_f, one), lc!())?;
+
+            let lo_var = FpVar::<Fr>::Var(AllocatedFp::new(
+              lo_val.map(|x| f_from_bigint(x.clone()).ok()).flatten(),
+              lo,
+              cs.clone(),
+            ));
+            let hi_var = FpVar::<Fr>::Var(AllocatedFp::new(
+              hi_val.map(|x| f_from_bigint(x.clone()).ok()).flatten(),
+              hi,
+              cs.clone(),
+            ));
+            lo_var.enforce_cmp(&hi_var, Less, false)?;
+
+            (u, u_ass)
+          } else if op == ScalarOp::Neg {
+            // -- A := 2 * F(z) - A
+            let ass = yy_val.map(|y| neg_neg(y, &scale));
+            let v = cs.new_witness_variable(|| {
+              ass
+                .clone()
+                .map(f_from_bigint)
+                .unwrap_or(Err(SynthesisError::AssignmentMissing))
+            })?;
+            cs.enforce_constraint(
+              lc!() + v + yy,
+              lc!() + ConstraintSystem::<CircuitField>::one(),
+              lc!()
+                + (
+                  CircuitField::from(scale.z) + CircuitField::from(scale.z),
+                  ConstraintSystem::<CircuitField>::one(),
+                ),
+            )?;
+            (v, ass)
+          } else if let ScalarOp::DivConst(c) | ScalarOp::ModConst(c) = op {
+            // floor(x / c) and x - c * floor(x / c) in the offset encoding, n = y - z = x * s divided by d = c * s,
+            // a whole constant, so the quotient is floor(x / c) and the remainder (x - c * floor(x / c)) * s.
+            //
+            // witness assignments:
+            //   q, r   <- floor division of n by d, 0 <= r < d
+            //   u      <- q * s + z (DivConst) or r + z (ModConst)
+            //
+            // enforce, with qz = q + z:
+            //   (qz - z) * d = y - z - r
+            //   r < d
+            //   qz < 2 * z                          // no wrap around: |q * d| < z * d, below the modulus for d < 2^100
+            //   u = s * (qz - z) + z or r + z
+            //
+            // The constant is in the constraints, as for LessThanConst. See [crate::scalar::DivConstOp].
+            let scaled = f64::from(c) * scale.s as f64;
+            assert!(
+              scaled >= 1.0 && scaled.fract() == 0.0 && scaled < 2f64.powi(100),
+              "{} by {}: the constant times the scale {} isn't a whole number in [1, 2^100)",
+              op.name(),
+              c,
+              scale.s
+            );
+            let d = BigInt::from(scaled as u128);
+            let z = BigInt::from(scale.z);
+            let (s_f, z_f, d_f) = (F::from(scale.s), F::from(scale.z), F::from(scaled as u128));
+            let one = ConstraintSystem::<CircuitField>::one();
+            let qr_ass = yy_val.map(|y| {
+              let n = y - z.clone();
+              let (mut q, mut r) = (n.clone() / d.clone(), n % d.clone());
+              if r < BigInt::from(0) {
+                q -= 1;
+                r += d.clone();
+              }
+              (q, r)
+            });
+            let witness = |ass: Option<BigInt>| {
+              Ok((
+                cs.new_witness_variable(|| {
+                  ass
+                    .clone()
+                    .ok_or(SynthesisError::AssignmentMissing)
+                    .and_then(f_from_bigint)
+                })?,
+                ass,
+              ))
+            };
+            let (qz, qz_val) = witness(qr_ass.clone().map(|(q, _)| q + z.clone()))?;
+            let (r, r_val) = witness(qr_ass.clone().map(|(_, r)| r))?;
+            let divide = matches!(op, ScalarOp::DivConst(_));
+            let (u, u_ass) = witness(qr_ass.map(|(q, r)| {
+              if divide {
+                q * scale.s + z.clone()
+              } else {
+                r + z.clone()
+              }
+            }))?;
+
+            cs.enforce_constraint(
+              lc!() + qz - (z_f, one),
+              lc!() + (d_f, one),
+              lc!() + yy - (z_f, one) - r,
+            )?;
+            let u_lc = if divide {
+              lc!() + (s_f, qz) - (s_f * z_f - z_f, one)
+            } else {
+              lc!() + r + (z_f, one)
+            };
+            cs.enforce_constraint(u_lc, lc!() + one, lc!() + u)?;
+
+            let var = |v: Variable, val: Option<BigInt>| {
+              FpVar::<Fr>::Var(AllocatedFp::new(
+                val.map(|x| f_from_bigint(x).ok()).flatten(),
+                v,
+                cs.clone(),
+              ))
+            };
+            var(r, r_val).enforce_cmp(&FpVar::Constant(d_f), Less, false)?;
+            var(qz, qz_val).enforce_cmp(&FpVar::Constant(z_f + z_f), Less, false)?;
+
+            (u, u_ass)
+          } else if let ScalarOp::RangeCheck(bits) = op {
+            // -2^bits <= x < 2^bits for the float x, the argument is the result. That is 0 <= y - z + 2^bits * s
+            // < 2^(bits + 1) * s in the offset encoding. See [crate::quant::RangeCheck].
+            let offset = BigInt::from(scale.s) << bits;
+            assert!(
+              offset.bits() < u64::from(ENCODING_BITS),
+              "RangeCheck of {} bits, past the range of the encoding",
+              bits
+            );
+            let one = ConstraintSystem::<CircuitField>::one();
+            let shift = f_from_bigint_unsafe(offset.clone()) - F::from(scale.z);
+            let shifted = yy_val
+              .clone()
+              .map(|y| y + offset.clone() - BigInt::from(scale.z));
+            enforce_below(
+              &cs,
+              lc!() + yy + (shift, one),
+              shifted.as_ref(),
+              &(offset * 2),
+            )?;
+            (yy, yy_val)
+          } else if let ScalarOp::Recip | ScalarOp::RecipHint = op {
+            // 1 / x in the offset encoding, 0 for x == 0: for n = y - z = x * s the result is m = trunc(s^2 / n), 1 / x
+            // times s rounded towards zero. See [crate::quant::RecipHint], the zero flag is `zf` here. Recip has no
+            // zero flag, zf is the constant 0 and the constraints on it go: there's no witness for x == 0.
+            //
+            // witness assignments:
+            //   m, rem  <- s^2 = n * m + rem, 0 <= rem < |n|, and m = rem = 0 for n == 0
+            //   zf, inv <- n == 0 and the inverse of n, or 0
+            //   sg, w   <- n < 0 and sg * n
+            //   u       <- m + z
+            //
+            // enforce, with m = u - z and t = n - 2 * w:
+            //   n * inv = 1 - zf
+            //   n * zf = 0                          // zf is the flag: n * inv is 0 only for n == 0, then zf = 1
+            //   m * zf = 0
+            //   n * m = s^2 * (1 - zf) - rem
+            //   sg = 0 or 1
+            //   sg * n = w                          // t = n * (1 - 2 * sg) = +-n
+            //   0 <= rem, 0 <= t - rem - 1 + zf     // rem < |n|: t is the sum of the two and 1 - zf, small, so t = |n|
+            //   0 <= m + s^2 < 2^(bits of 2 * s^2) // |m| <= s^2, the equation can't wrap around, m is the quotient
+            let z = BigInt::from(scale.z);
+            let s2 = BigInt::from(scale.s) * scale.s;
+            let (z_f, s2_f) = (F::from(scale.z), f_from_bigint_unsafe(s2.clone()));
+            let one = ConstraintSystem::<CircuitField>::one();
+            let zero = BigInt::from(0);
+            // field elements of signed integers
+            let signed = |n: &BigInt| {
+              if *n < zero {
+                -f_from_bigint_unsafe(-n)
+              } else {
+                f_from_bigint_unsafe(n.clone())
+              }
+            };
+            let n_val = yy_val.clone().map(|y| y - z.clone());
+            let hint = n_val.as_ref().map(|n| {
+              if *n == zero {
+                (zero.clone(), zero.clone())
+              } else {
+                (&s2 / n, &s2 % n)
+              }
+            });
+            let witness = |val: Option<F>| {
+              cs.new_witness_variable(|| val.ok_or(SynthesisError::AssignmentMissing))
+            };
+            let u_ass = hint.as_ref().map(|(m, _)| m + &z);
+            let u = witness(u_ass.as_ref().map(|u| f_from_bigint_unsafe(u.clone())))?;
+            let rem_val = hint.map(|(_, rem)| rem);
+            let rem = witness(rem_val.as_ref().map(|r| f_from_bigint_unsafe(r.clone())))?;
+            let hinted = op == ScalarOp::RecipHint;
+            let zf_val = n_val.as_ref().map(|n| hinted && *n == zero);
+            let zf = if hinted {
+              lc!() + witness(zf_val.map(|b| F::from(b as u64)))?
+            } else {
+              lc!()
+            };
+            let inv = witness(
+              n_val
+                .as_ref()
+                .map(|n| signed(n).inverse().unwrap_or_else(F::zero)),
+            )?;
+            let sg = witness(n_val.as_ref().map(|n| F::from((*n < zero) as u64)))?;
+            let w = witness(n_val.as_ref().map(|n| signed(n.min(&zero))))?;
+            let n = || lc!() + yy - (z_f, one);
+            let m = || lc!() + u - (z_f, one);
+
+            cs.enforce_constraint(n(), lc!() + inv, lc!() + one - zf.clone())?;
+            if hinted {
+              cs.enforce_constraint(n(), zf.clone(), lc!())?;
+              cs.enforce_constraint(m(), zf.clone(), lc!())?;
+            }
+            cs.enforce_constraint(n(), m(), lc!() + (s2_f, one) - zf.clone() * s2_f - rem)?;
+            cs.enforce_constraint(lc!() + sg, lc!() + one - sg, lc!())?;
+            cs.enforce_constraint(lc!() + sg, n(), lc!() + w)?;
+            let gap = zip_with(n_val.as_ref(), rem_val.as_ref(), |n, r| {
+              let t = if *n < zero { -n } else { n.clone() };
+              t - r - 1 + zf_val.map_or(0, |b| b as i32)
+            });
+            enforce_bits(&cs, lc!() + rem, rem_val.as_ref(), ENCODING_BITS)?;
+            enforce_bits(
+              &cs,
+              n() - (F::from(2u64), w) - rem - one + zf,
+              gap.as_ref(),
+              ENCODING_BITS,
+            )?;
+            let m_bits = (s2.clone() * 2).bits() as u32;
+            let shifted = u_ass.as_ref().map(|u| u - &z + &s2);
+            enforce_bits(&cs, m() + (s2_f, one), shifted.as_ref(), m_bits)?;
+
+            (u, u_ass)
           } else {
-            todo!("Unsupported unop!")
+            // lookups, compile rejects them, see check_snark_supported
+            panic!("Unsupported unop {} at {:?}", op.name(), x)
           }
         }
         // BINOP
-        else if let Some(((_, l), (_, r))) = incoming.into_iter().collect_tuple() {
+        else if let Some((l, r)) = incoming.iter().copied().collect_tuple() {
           // assumes toposort order for unwraps
           let ll = vars.get(&l).unwrap().clone();
           let rr = vars.get(&r).unwrap().clone();
           let ll_val = assignments.get(&l).unwrap().clone();
           let rr_val = assignments.get(&r).unwrap().clone();
 
-          if graph.check_node_type::<Add>(x) {
+          if op == ScalarOp::Add {
             let ass = zip_with(ll_val, rr_val, |l, r| add_add(l, r, &scale));
             let v = cs.new_witness_variable(|| ass.clone().map(f_from_bigint).unwrap_or(Err(SynthesisError::AssignmentMissing)) /* would be cool to return two error types but in SyntheisError all other types are for internal use. bad design. */)?;
             // A ++ B := A+B-F(z)
@@ -419,7 +974,27 @@ impl ConstraintSynthesizer<CircuitField> for &mut MLSnark<CircuitField> {
                 ),
             )?;
             (v, ass)
-          } else if graph.check_node_type::<Mul>(x) {
+          } else if op == ScalarOp::Sub {
+            let ass = zip_with(ll_val, rr_val, |l, r| sub_sub(l, r, &scale));
+            let v = cs.new_witness_variable(|| {
+              ass
+                .clone()
+                .map(f_from_bigint)
+                .unwrap_or(Err(SynthesisError::AssignmentMissing))
+            })?;
+            // A -- B := A-B+F(z)
+            cs.enforce_constraint(
+              lc!()
+                + ll
+                + (
+                  CircuitField::from(scale.z),
+                  ConstraintSystem::<CircuitField>::one(),
+                ),
+              lc!() + ConstraintSystem::<CircuitField>::one(),
+              lc!() + v + rr,
+            )?;
+            (v, ass)
+          } else if op == ScalarOp::Mul {
             // A ** B := (A * B + F(z*s) + F(z*z) - A*F(z) - B*F(z)) / F(s)
             // that is, substituting some variables and rewriting division:
             // v * F(s) + Rem := l * r + F(z*s) + F(z*z) - l*F(z) - r*F(z)
@@ -429,6 +1004,9 @@ impl ConstraintSynthesizer<CircuitField> for &mut MLSnark<CircuitField> {
             // v * F(s) = tmp1 + F(z*s) + F(z*z) - tmp2 - Rem
             // l * r = tmp1
             // (l + r)*F(z) = tmp2
+            // 0 <= Rem < s, 0 <= v < 2^ENCODING_BITS
+            // The ranges make v * F(s) + Rem too small to wrap around, so the first equation holds over the integers and
+            // v is the quotient, not any v for some other Rem.
             let tmp1 = cs.new_witness_variable(|| {
               zip_with(ll_val.clone(), rr_val.clone(), |l, r| l * r)
                 .and_then(|b| f_from_bigint(b).ok())
@@ -441,6 +1019,7 @@ impl ConstraintSynthesizer<CircuitField> for &mut MLSnark<CircuitField> {
                 .ok_or(SynthesisError::AssignmentMissing)
             })?;
             let div_res = zip_with(ll_val.clone(), rr_val.clone(), |l, r| mul_mul(l, r, &scale));
+            let rem_val = div_res.clone().map(|d| d.remainder);
             let rem = cs.new_witness_variable(|| {
               div_res
                 .clone()
@@ -472,8 +1051,10 @@ impl ConstraintSynthesizer<CircuitField> for &mut MLSnark<CircuitField> {
               lc!() + (F::from(scale.z), ConstraintSystem::<CircuitField>::one()),
               lc!() + tmp2,
             )?;
+            enforce_below(&cs, lc!() + rem, rem_val.as_ref(), &BigInt::from(scale.s))?;
+            enforce_bits(&cs, lc!() + v, ass.as_ref(), ENCODING_BITS)?;
             (v, ass)
-          } else if graph.check_node_type::<LessThan>(x) {
+          } else if op == ScalarOp::LessThan {
             // witness assignments:
             //   x, y <- if l < r then (l, r) else (r, l)
             //   lt   <- (l < r)
@@ -554,7 +1135,7 @@ impl ConstraintSynthesizer<CircuitField> for &mut MLSnark<CircuitField> {
             // (lt_scaled, lt_scaled_ass)
             (lt_scaled, lt_scaled_ass)
           } else {
-            panic!("Unsupported binop")
+            panic!("Unsupported binop {} at {:?}", op.name(), x)
           }
         } else {
           panic!("No n-ary ops for n>2")
@@ -567,7 +1148,9 @@ impl ConstraintSynthesizer<CircuitField> for &mut MLSnark<CircuitField> {
       // if the node is a result node (a sink), assert its value against a public input.
       // we can do that only when creating the proof and having the private inputs,
       // so lets match on the Option. This all is quite a poor design but it follows from how arkworks is structured.
-      if graph.edges_directed(x, Outgoing).next().is_none() {
+      // Nodes marked for retrieval are results too, even if used further (i.e. boundary outputs of a chunk).
+      if results.contains(&x) && !committed.contains(&x) {
+        public_nodes.push(x);
         let z = cs.new_input_variable(|| {
           ass
             .clone()
@@ -584,28 +1167,245 @@ impl ConstraintSynthesizer<CircuitField> for &mut MLSnark<CircuitField> {
           None => {}
         }
       }
+      node_constraints.push((x, first_constraint..cs.num_constraints()));
+    }
+    if let Some((input_ids, hash)) = input_hash {
+      let message = input_ids
+        .iter()
+        .map(|x| {
+          let value = assignments[x].clone().and_then(|n| f_from_bigint(n).ok());
+          FpVar::Var(AllocatedFp::new(value, vars[x], cs.clone()))
+        })
+        .collect_vec();
+      poseidon_hash_var(&message)?.enforce_equal(&hash)?;
+    }
+    for commitment in self.commitments.iter() {
+      let little = commitment.little_nodes(&self.graph.inputs_tracker);
+      let values = little
+        .iter()
+        .map(|x| assignments[x].clone().and_then(|n| f_from_bigint(n).ok()))
+        .collect_vec();
+      let value = values
+        .iter()
+        .copied()
+        .collect::<Option<Vec<F>>>()
+        .map(|message| poseidon_hash(&message));
+      public_record.extend(value);
+      let hash = FpVar::new_input(cs.clone(), || {
+        value.ok_or(SynthesisError::AssignmentMissing)
+      })?;
+      let message = little
+        .iter()
+        .zip(values)
+        .map(|(x, value)| FpVar::Var(AllocatedFp::new(value, vars[x], cs.clone())))
+        .collect_vec();
+      poseidon_hash_var(&message)?.enforce_equal(&hash)?;
+    }
+    if let (Some(opening), Some(root)) = (self.weight_opening.as_ref(), weight_root) {
+      let nodes: HashMap<usize, NodeIndex> =
+        opening.elements.iter().map(|(x, p)| (*p, *x)).collect();
+      let witness = |value: Option<F>| {
+        FpVar::new_witness(cs.clone(), || {
+          value.ok_or(SynthesisError::AssignmentMissing)
+        })
+      };
+      for leaf in opening.leaves() {
+        // the weights of the circuit, the rest of the leaf from the tree
+        let message = opening
+          .leaf_elements(leaf)
+          .map(|p| match nodes.get(&p) {
+            Some(x) => {
+              let value = assignments[x].clone().and_then(|n| f_from_bigint(n).ok());
+              Ok(FpVar::Var(AllocatedFp::new(value, vars[x], cs.clone())))
+            }
+            None => witness(opening.tree.as_ref().map(|t| t.elements[p])),
+          })
+          .collect::<Result<Vec<_>, SynthesisError>>()?;
+        let path = opening.tree.as_ref().map(|t| t.path(leaf));
+        let siblings = (0..opening.depth())
+          .map(|d| witness(path.as_ref().map(|path| path.siblings[d])))
+          .collect::<Result<Vec<_>, SynthesisError>>()?;
+        merkle_root_var(&poseidon_hash_var(&message)?, leaf, &siblings)?.enforce_equal(&root)?;
+      }
     }
     self.recorded_public_inputs = public_record;
+    self.recorded_public_nodes = public_nodes;
+    self.trace = SynthesisTrace {
+      constraints: node_constraints,
+      vars,
+    };
+    Span::current().record("constraints", cs.num_constraints());
     Ok(())
   }
 }
 
+/// Bits of the values of the encoding and of their differences, `|y - z| < z < 2^130` for the floats of its range
+/// (see [Note: floats as integers]). What [MLSnark] range checks to keep equations from wrapping around the field.
+pub const ENCODING_BITS: u32 = 131;
+
+/// Constrains `0 <= n < 2^bits` for the combination `n` of the value `val`: a boolean witness per bit, summing to `n`.
+/// Sound for `bits` well below the bits of the field, the sum can't wrap around. See [bits_cost].
+fn enforce_bits(
+  cs: &ConstraintSystemRef<CircuitField>,
+  n: LinearCombination<CircuitField>,
+  val: Option<&BigInt>,
+  bits: u32,
+) -> Result<(), SynthesisError> {
+  let one = ConstraintSystem::<CircuitField>::one();
+  let mut sum = lc!();
+  for i in 0..bits {
+    let bit = val.map(|n| CircuitField::from(n.bit(u64::from(i)) as u64));
+    let b = cs.new_witness_variable(|| bit.ok_or(SynthesisError::AssignmentMissing))?;
+    cs.enforce_constraint(lc!() + b, lc!() + one - b, lc!())?;
+    sum = sum + (f_from_bigint_unsafe(BigInt::from(1) << i), b);
+  }
+  cs.enforce_constraint(n, lc!() + one, sum)
+}
+
+/// Constrains `0 <= n < bound`: `n` and `bound - 1 - n` both of the bits of `bound - 1`. See [below_cost].
+fn enforce_below(
+  cs: &ConstraintSystemRef<CircuitField>,
+  n: LinearCombination<CircuitField>,
+  val: Option<&BigInt>,
+  bound: &BigInt,
+) -> Result<(), SynthesisError> {
+  let one = ConstraintSystem::<CircuitField>::one();
+  let max = bound - BigInt::from(1);
+  let bits = max.bits() as u32;
+  let rest = lc!() + (f_from_bigint_unsafe(max.clone()), one) - n.clone();
+  enforce_bits(cs, n, val, bits)?;
+  enforce_bits(cs, rest, val.map(|n| &max - n).as_ref(), bits)
+}
+
+/// The constraints [MLSnark] makes to check a value of `bits` bits, its bit decomposition.
+pub fn bits_cost(bits: u32) -> usize {
+  bits as usize + 1
+}
+
+/// The constraints [MLSnark] makes to check a value below the bound, the decompositions of the value and of its distance
+/// to the bound.
+pub fn below_cost(bound: &BigInt) -> usize {
+  2 * bits_cost((bound - BigInt::from(1)).bits() as u32)
+}
+
 fn zip_with<A, B, C>(a: Option<A>, b : Option<B>, f : impl Fn(A, B) -> C) -> Option<C> {
   a.and_then(|a| b.map(|b| f(a, b)))
 }
 
+#[cfg(test)]
 mod tests {
   use num_bigint::BigInt;
   // use ark_ff::PrimeField;
   // use quickcheck::quickcheck;
-  use super::bigints_close_as_floats;
+  use super::{bigints_close_as_floats, neg_neg, sub_sub};
+  use crate::cost::estimate_cost;
+  use crate::quant::{RangeCheck, RecipHint};
+  use crate::scalar::{scalar, ScalarGraph};
+  use crate::snark::backend::{Groth16Backend, ProvingBackend};
   use crate::snark::scaling_helpers::*;
-  use crate::snark::{mul_mul, CircuitField};
+  use crate::snark::{mul_mul, CircuitField, MLSnark, SourceType};
   use crate::SCALE;
+  use ark_bls12_381::Bls12_381;
+  use ark_groth16::Groth16;
+  use ark_relations::r1cs::{ConstraintSynthesizer, ConstraintSystem};
+  use ark_snark::SNARK;
+  use luminal::prelude::*;
   use proptest::num::f32::{NEGATIVE, POSITIVE};
   use proptest::prelude::*;
+  use std::collections::HashMap;
   use std::ops::Div;
 
+  /// The snark of the scalar graph with the input as its one private tensor, as [crate::compile] makes them.
+  /// Checks the cost model of the backend on it.
+  fn snark_of(sc: ScalarGraph, input: NodeIndex) -> MLSnark<CircuitField> {
+    let cost = estimate_cost(&sc, &Groth16Backend);
+    assert!(cost.unsupported.is_empty());
+    let source_map = sc.inputs_tracker.new_inputs[&input]
+      .iter()
+      .map(|x| (*x, SourceType::Private(None)))
+      .collect();
+    let mut snark = MLSnark {
+      graph: sc.freeze_ops(),
+      scale: SCALE,
+      source_map,
+      og_input_id: input,
+      input_hash: false,
+      commitments: vec![],
+      weight_opening: None,
+      recorded_public_inputs: vec![],
+      recorded_public_nodes: vec![],
+      trace: Default::default(),
+    };
+    assert_eq!(
+      Groth16Backend.estimate_constraints(&mut snark).unwrap(),
+      cost.total
+    );
+    snark
+  }
+
+  /// Proves the snark on the input and verifies the proof. The results, as floats.
+  fn prove(snark: &mut MLSnark<CircuitField>, input: Vec<f32>) -> HashMap<NodeIndex, Vec<f32>> {
+    let (pk, vk) = snark.make_keys().unwrap();
+    snark.set_input(input);
+    let proof = snark.make_proof(&pk).unwrap();
+    assert_eq!(
+      Groth16::<Bls12_381>::verify(&vk, &snark.recorded_public_inputs, &proof),
+      Ok(true)
+    );
+    snark
+      .get_evaluation_results()
+      .into_iter()
+      .map(|(x, v)| {
+        (
+          x,
+          v.iter().map(|f| unscaled_f(*f, &SCALE).unwrap()).collect(),
+        )
+      })
+      .collect()
+  }
+
+  /// Whether the witness of the snark on the input satisfies its constraints.
+  fn satisfied(snark: &mut MLSnark<CircuitField>, input: Vec<f32>) -> bool {
+    snark.set_input(input);
+    let cs = ConstraintSystem::<CircuitField>::new_ref();
+    (&mut *snark).generate_constraints(cs.clone()).unwrap();
+    cs.is_satisfied().unwrap()
+  }
+
+  #[test]
+  fn test_range_check() {
+    let mut cx = Graph::new();
+    let a = cx.tensor::<R1<3>>();
+    let b = (a * a).retrieve();
+    let (mut sc, _) = scalar(&cx);
+    for x in sc.inputs_tracker.new_outputs[&b.id].clone() {
+      sc.insert_after(x, RangeCheck { bits: 4 });
+    }
+    let mut snark = snark_of(sc, a.id);
+    assert_eq!(
+      prove(&mut snark, vec![-3.0, 0.5, 3.5])[&b.id],
+      vec![9.0, 0.25, 12.25]
+    );
+    assert!(satisfied(&mut snark, vec![0.0, -3.9, 3.9]));
+    assert!(!satisfied(&mut snark, vec![0.0, 4.5, 0.0]));
+  }
+
+  #[test]
+  fn test_recip_hint() {
+    let mut cx = Graph::new();
+    let a = cx.tensor::<R1<4>>();
+    let b = a.recip().retrieve();
+    let (mut sc, _) = scalar(&cx);
+    for x in sc.graph.node_indices().collect::<Vec<_>>() {
+      if sc.graph.check_node_type::<luminal::op::Recip>(x) {
+        *sc.graph.node_weight_mut(x).unwrap() = Box::new(RecipHint {});
+      }
+    }
+    let results = prove(&mut snark_of(sc, a.id), vec![2.0, -4.0, 0.0, 0.3]);
+    assert_eq!(results[&b.id][..3], [0.5, -0.25, 0.0]);
+    assert!((results[&b.id][3] - 1.0 / 0.3).abs() < 1e-4);
+  }
+
   proptest! {
 
     #[test]
@@ -624,5 +1424,15 @@ mod tests {
       assert!(bigints_close_as_floats(af_m_bf, a_m_b_f, &SCALE), "scaled(a * b) == scaled(a) ** scaled(b)");
       drop(scope);
     }
+
+    #[test]
+    fn test_scaling_is_sub_homo(a in -10e15..10e15f64, b in -10e15..10e15f64) {
+      let a: f32 = a as f32;
+      let b: f32 = b as f32;
+      let af_s_bf = sub_sub(scaled_float(a, &SCALE), scaled_float(b, &SCALE), &SCALE);
+      assert!(bigints_close_as_floats(af_s_bf, scaled_float(a - b, &SCALE), &SCALE), "scaled(a - b) == scaled(a) -- scaled(b)");
+      let neg_af = neg_neg(scaled_float(a, &SCALE), &SCALE);
+      assert!(bigints_close_as_floats(neg_af, scaled_float(-a, &SCALE), &SCALE), "scaled(-a) == -- scaled(a)");
+    }
   }
 }
@@ -0,0 +1,292 @@
+//!
+//! A Solidity contract verifying the Groth16 proofs of one model on chain, with the BLS12-381 precompiles of EIP-2537
+//! (Ethereum since Pectra). Written by [super::backend::ProvingBackend::export_solidity_verifier].
+//!
+//! The constants and weights (or the root of private ones) are fixed by the [PublicInputsSchema], so they're folded into the first point of the key:
+//! the contract takes only the values the prover claims (the outputs, after the input hash if any),
+//! and a proof made with other weights doesn't verify.
+//! The contract checks `e(A, B) * e(alpha, -beta) * e(vk_x, -gamma) * e(C, -delta) == 1`, the G2 points negated here,
+//! and `vk_x` computed by one multi-scalar multiplication.
+//!
+//! Call `verifyProof(bytes proof, uint256[] outputs)` with the proof from [encode_proof] and the outputs from
+//! [solidity_outputs], or send the whole [solidity_calldata]. Points are in the EIP-2537 encoding:
+//! every base field element in 64 big-endian bytes, a G1 point `x | y`, a G2 point `x.c0 | x.c1 | y.c0 | y.c1`.
+//!
+
+use std::{error::Error, fs, iter, path::Path};
+
+use ark_bls12_381::{Fq, G1Affine, G2Affine};
+use ark_ec::{AffineCurve, ProjectiveCurve};
+use ark_ff::{BigInteger, PrimeField};
+use ark_groth16::{Proof, VerifyingKey};
+use num_bigint::BigInt;
+
+use super::{
+  scaling_helpers::f_from_bigint,
+  verifier::{PublicInput, PublicInputsSchema},
+  CircuitField, Curve,
+};
+
+/// Selector of `verifyProof(bytes,uint256[])`, the first 4 bytes of its keccak256.
+pub const VERIFY_SELECTOR: [u8; 4] = [0x1e, 0x8e, 0x1e, 0x13];
+
+fn fq_bytes(x: &Fq) -> Vec<u8> {
+  let mut bytes = vec![0; 16];
+  bytes.extend(x.into_repr().to_bytes_be());
+  bytes
+}
+
+/// A G1 point in 128 bytes, zeros for the point at infinity.
+pub fn g1_bytes(p: &G1Affine) -> Vec<u8> {
+  if p.infinity {
+    return vec![0; 128];
+  }
+  [fq_bytes(&p.x), fq_bytes(&p.y)].concat()
+}
+
+/// A G2 point in 256 bytes, zeros for the point at infinity.
+pub fn g2_bytes(p: &G2Affine) -> Vec<u8> {
+  if p.infinity {
+    return vec![0; 256];
+  }
+  [
+    fq_bytes(&p.x.c0),
+    fq_bytes(&p.x.c1),
+    fq_bytes(&p.y.c0),
+    fq_bytes(&p.y.c1),
+  ]
+  .concat()
+}
+
+/// A field element as a big-endian uint256.
+fn fr_bytes(x: &CircuitField) -> Vec<u8> {
+  x.into_repr().to_bytes_be()
+}
+
+/// The proof as the contract takes it: `A | B | C`, 512 bytes.
+pub fn encode_proof(proof: &Proof<Curve>) -> Vec<u8> {
+  [g1_bytes(&proof.a), g2_bytes(&proof.b), g1_bytes(&proof.c)].concat()
+}
+
+/// The points of the key for the claimed values: the first with the constants and weights of the schema folded in,
+/// then one per output (or input hash) of the schema, in order.
+pub fn fold_fixed_inputs(
+  vk: &VerifyingKey<Curve>,
+  schema: &PublicInputsSchema,
+) -> Result<Vec<G1Affine>, Box<dyn Error>> {
+  if vk.gamma_abc_g1.len() != schema.inputs.len() + 1 {
+    return Err(
+      format!(
+        "The key has {} public inputs, the schema {}",
+        vk.gamma_abc_g1.len() - 1,
+        schema.inputs.len()
+      )
+      .into(),
+    );
+  }
+  let mut first = vk.gamma_abc_g1[0].into_projective();
+  let mut outputs = vec![];
+  for (input, point) in schema.inputs.iter().zip(vk.gamma_abc_g1[1..].iter()) {
+    match input {
+      PublicInput::Constant { value }
+      | PublicInput::Weight { value, .. }
+      | PublicInput::WeightRoot { value, .. } => {
+        let v = f_from_bigint(value.parse::<BigInt>()?)
+          .map_err(|_| format!("Value {} out of the field", value))?;
+        first += point.mul(v.into_repr());
+      }
+      PublicInput::Output { .. }
+      | PublicInput::Argmax { .. }
+      | PublicInput::InputHash
+      | PublicInput::Commitment { .. } => outputs.push(*point),
+    }
+  }
+  Ok(iter::once(first.into_affine()).chain(outputs).collect())
+}
+
+/// The claimed values among the public inputs of a proof (the outputs, argmaxes and the hashes), as the contract takes
+/// them.
+/// Fails for public inputs with other constants or weights than the schema, see [PublicInputsSchema::check].
+pub fn solidity_outputs(
+  schema: &PublicInputsSchema,
+  public_inputs: &[CircuitField],
+) -> Result<Vec<CircuitField>, Box<dyn Error>> {
+  schema.check(public_inputs)?;
+  Ok(
+    schema
+      .inputs
+      .iter()
+      .zip(public_inputs)
+      .filter(|(input, _)| {
+        matches!(
+          input,
+          PublicInput::Output { .. }
+            | PublicInput::Argmax { .. }
+            | PublicInput::InputHash
+            | PublicInput::Commitment { .. }
+        )
+      })
+      .map(|(_, v)| *v)
+      .collect(),
+  )
+}
+
+/// The ABI encoded call of `verifyProof` on the proof and its public inputs.
+pub fn solidity_calldata(
+  proof: &Proof<Curve>,
+  schema: &PublicInputsSchema,
+  public_inputs: &[CircuitField],
+) -> Result<Vec<u8>, Box<dyn Error>> {
+  let outputs = solidity_outputs(schema, public_inputs)?;
+  let proof = encode_proof(proof);
+  let word = |n: usize| {
+    let mut w = vec![0; 24];
+    w.extend((n as u64).to_be_bytes());
+    w
+  };
+  let mut calldata = VERIFY_SELECTOR.to_vec();
+  // offsets of the two dynamic arguments, then the bytes and the array, each prefixed with its length
+  calldata.extend(word(64));
+  calldata.extend(word(64 + 32 + proof.len()));
+  calldata.extend(word(proof.len()));
+  calldata.extend(proof);
+  calldata.extend(word(outputs.len()));
+  calldata.extend(outputs.iter().flat_map(fr_bytes));
+  Ok(calldata)
+}
+
+/// The verifier contract of the key, for the public inputs of the schema. See the module docs.
+pub fn solidity_verifier(
+  vk: &VerifyingKey<Curve>,
+  schema: &PublicInputsSchema,
+) -> Result<String, Box<dyn Error>> {
+  let points = fold_fixed_inputs(vk, schema)?;
+  let one = CircuitField::from(1u64);
+  // the input of the multi-scalar multiplication: (point, scalar) pairs, the scalars of the outputs set by the contract
+  let msm: Vec<u8> = points
+    .iter()
+    .enumerate()
+    .flat_map(|(i, p)| {
+      let scalar = if i == 0 { fr_bytes(&one) } else { vec![0; 32] };
+      [g1_bytes(p), scalar].concat()
+    })
+    .collect();
+  let hex = |bytes: Vec<u8>| -> String { bytes.iter().map(|b| format!("{:02x}", b)).collect() };
+  Ok(format!(
+    r#"// SPDX-License-Identifier: MIT
+// Generated by zkml: verifies the Groth16 proofs (BLS12-381) of one model, with the EIP-2537 precompiles.
+pragma solidity ^0.8.24;
+
+contract ModelVerifier {{
+    address constant G1_MSM = address(0x0c);
+    address constant PAIRING_CHECK = address(0x0f);
+    // order of the scalar field
+    uint256 constant R = 0x73eda753299d7d483339d80809a1d80553bda402fffe5bfeffffffff00000001;
+
+    uint256 constant OUTPUTS = {outputs};
+
+    bytes constant ALPHA = hex"{alpha}";
+    bytes constant NEG_BETA = hex"{beta}";
+    bytes constant NEG_GAMMA = hex"{gamma}";
+    bytes constant NEG_DELTA = hex"{delta}";
+    // (point, scalar) pairs of vk_x: the first with the model's constants and weights folded in and scalar 1,
+    // then one per output, the scalars filled in with the outputs
+    bytes constant MSM = hex"{msm}";
+
+    /// @param proof A (G1), B (G2), C (G1) in the EIP-2537 encoding, 512 bytes
+    /// @param outputs the claimed results of the model, as field elements
+    function verifyProof(bytes calldata proof, uint256[] calldata outputs) external view returns (bool) {{
+        require(proof.length == 512, "Wrong proof length");
+        require(outputs.length == OUTPUTS, "Wrong number of outputs");
+        bytes memory msm = MSM;
+        for (uint256 i = 0; i < OUTPUTS; i++) {{
+            uint256 s = outputs[i];
+            require(s < R, "Output out of the field");
+            assembly {{
+                mstore(add(msm, add(32, add(mul(add(i, 1), 160), 128))), s)
+            }}
+        }}
+        (bool ok, bytes memory vkx) = G1_MSM.staticcall(msm);
+        if (!ok || vkx.length != 128) {{
+            return false;
+        }}
+        bytes memory pairs = abi.encodePacked(proof[0:384], ALPHA, NEG_BETA, vkx, NEG_GAMMA, proof[384:512], NEG_DELTA);
+        bytes memory result;
+        (ok, result) = PAIRING_CHECK.staticcall(pairs);
+        return ok && result.length == 32 && abi.decode(result, (uint256)) == 1;
+    }}
+}}
+"#,
+    outputs = points.len() - 1,
+    alpha = hex(g1_bytes(&vk.alpha_g1)),
+    beta = hex(g2_bytes(&-vk.beta_g2)),
+    gamma = hex(g2_bytes(&-vk.gamma_g2)),
+    delta = hex(g2_bytes(&-vk.delta_g2)),
+    msm = hex(msm),
+  ))
+}
+
+/// Writes the contract of [solidity_verifier] to the file.
+pub fn write_solidity_verifier(
+  vk: &VerifyingKey<Curve>,
+  schema: &PublicInputsSchema,
+  path: &Path,
+) -> Result<(), Box<dyn Error>> {
+  fs::write(path, solidity_verifier(vk, schema)?)?;
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use ark_ec::AffineCurve;
+  use ark_ff::PrimeField;
+  use ark_groth16::{prepare_inputs, prepare_verifying_key};
+
+  use super::{fold_fixed_inputs, solidity_calldata, solidity_outputs, solidity_verifier};
+  use crate::{
+    compile,
+    snark::{
+      backend::{Groth16Backend, ProvingBackend, VerifyingBackend},
+      verifier::{PublicInput, PublicInputsSchema},
+      CircuitField,
+    },
+  };
+
+  #[test]
+  fn test_solidity_verifier() {
+    let trained = crate::model::fixed_weights::run_model();
+    let mut snark = compile(&trained);
+    let (pk, vk) = Groth16Backend.setup(&mut snark).unwrap();
+    let schema =
+      PublicInputsSchema::new(&snark, &trained.graph.params, Groth16Backend::NAME).unwrap();
+    snark.set_input(vec![1.0, 2.0, 3.0]);
+    let proof = Groth16Backend.prove(&mut snark, &pk).unwrap();
+    let public_inputs = snark.recorded_public_inputs.clone();
+
+    // the folded key computes the same vk_x from the outputs alone
+    let points = fold_fixed_inputs(&vk, &schema).unwrap();
+    let outputs = solidity_outputs(&schema, &public_inputs).unwrap();
+    assert_eq!(points.len(), outputs.len() + 1);
+    let mut vkx = points[0].into_projective();
+    for (p, v) in points[1..].iter().zip(outputs.iter()) {
+      vkx += p.mul(v.into_repr());
+    }
+    let expected = prepare_inputs(&prepare_verifying_key(&vk), &public_inputs).unwrap();
+    assert_eq!(vkx, expected);
+
+    let calldata = solidity_calldata(&proof, &schema, &public_inputs).unwrap();
+    assert_eq!(calldata.len(), 4 + 3 * 32 + 512 + 32 * (1 + outputs.len()));
+    let contract = solidity_verifier(&vk, &schema).unwrap();
+    assert!(contract.contains(&format!("uint256 constant OUTPUTS = {};", outputs.len())));
+
+    // a proof with other weights is refused before the call
+    let i = schema
+      .inputs
+      .iter()
+      .position(|i| matches!(i, PublicInput::Weight { .. }))
+      .unwrap();
+    let mut other = public_inputs.clone();
+    other[i] += CircuitField::from(1u64);
+    assert!(solidity_calldata(&proof, &schema, &other).is_err());
+  }
+}
@@ -0,0 +1,431 @@
+//!
+//! What a third party needs to verify our proofs without this crate, written by
+//! [ProvingBackend::export_verifier](super::backend::ProvingBackend::export_verifier) into a directory:
+//!  - `verifying_key.bin`: the verifying key, for Groth16 the compressed arkworks serialization of a BLS12-381 key.
+//!  - `public_inputs.json`: the [PublicInputsSchema], the hash of the circuit and what every public input of it stands
+//!    for, in order.
+//!
+//! A proof file (see [crate::subcommands::save_proof]) is the compressed proof followed by the public inputs:
+//! a little-endian u64 count, then the field elements of the BLS12-381 scalar field, 32 little-endian bytes each.
+//!
+//! Floats are field elements `round(x * scale) + zero`, see [Note: floats as ints]. The constants, the public weights
+//! and the root of the private ones are fixed by the schema, a verifier has to check the proof's public inputs against them: a proof made with other
+//! weights verifies against the same key just as well. Weights baked into the circuit (see [crate::WeightsMode]) are
+//! fixed by the key instead, they're no public inputs. The outputs are claimed by the prover.
+//! The circuit doesn't commit to the weights, they're public inputs one by one, unless they're private and opened in a
+//! Merkle tree (see [MLSnark::weight_opening]) whose root the schema fixes. The input is private,
+//! optionally with its hash public (see [MLSnark::input_hash]), which the prover claims as well.
+//!
+//! The checks of the verifier and the verification of the backends ([VerifyingBackend]) build without the `native`
+//! feature too, e.g. for `wasm32-unknown-unknown` (see [super::bundle]). Making the schema and reading or writing the
+//! files need it.
+//!
+
+#[cfg(feature = "native")]
+use std::{collections::HashMap, fs, path::Path};
+use std::{error::Error, fmt::Debug};
+
+use ark_ec::{AffineCurve, PairingEngine, ProjectiveCurve};
+use ark_ff::{One, PrimeField};
+use ark_groth16::{prepare_inputs, prepare_verifying_key, Groth16, Proof, VerifyingKey};
+#[cfg(feature = "native")]
+use ark_serialize::CanonicalDeserialize;
+use ark_serialize::CanonicalSerialize;
+use ark_snark::SNARK;
+use blake2::{Blake2s, Digest};
+#[cfg(feature = "native")]
+use luminal::prelude::NodeIndex;
+use serde::{Deserialize, Serialize};
+
+#[cfg(feature = "native")]
+use crate::{
+  model::ParamRegistry,
+  scalar::{FrozenNode, ScalarOp},
+  subcommands::load_proof,
+};
+
+#[cfg(feature = "native")]
+use super::{bundle::to_hex, scaling_helpers::scaled_float, MLSnark, SourceType};
+use super::{scaling_helpers::f_to_bigint, CircuitField, Curve};
+
+/// The verification of a proof system, the half of a [ProvingBackend](super::backend::ProvingBackend) a verifier needs.
+pub trait VerifyingBackend {
+  type VerifyingKey;
+  type Proof;
+  type Error: Debug;
+
+  /// Names the proof system in the exported verifiers, see the module docs.
+  const NAME: &'static str;
+
+  fn verify(
+    &self,
+    vk: &Self::VerifyingKey,
+    public_inputs: &[CircuitField],
+    proof: &Self::Proof,
+  ) -> Result<bool, Self::Error>;
+
+  /// Whether every proof of the batch verifies, each with its key and public inputs. Backends that can check them
+  /// together override it, by default they're verified one by one.
+  fn verify_batch(
+    &self,
+    batch: &[(&Self::VerifyingKey, &[CircuitField], &Self::Proof)],
+  ) -> Result<bool, Self::Error> {
+    for (vk, public_inputs, proof) in batch.iter() {
+      if !self.verify(vk, public_inputs, proof)? {
+        return Ok(false);
+      }
+    }
+    Ok(true)
+  }
+}
+
+/// Groth16 on BLS12-381, with the R1CS made by the [ConstraintSynthesizer](ark_relations::r1cs::ConstraintSynthesizer)
+/// of [MLSnark](super::MLSnark).
+///
+/// Setup and proving randomness comes from the OS. The verifier checks proofs with the verifying key published by
+/// whoever ran the setup (see [ProvingBackend::export_verifier](super::backend::ProvingBackend::export_verifier)),
+/// and trusts them to have thrown away its randomness: with it proofs can be forged.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Groth16Backend;
+
+impl VerifyingBackend for Groth16Backend {
+  type VerifyingKey = VerifyingKey<Curve>;
+  type Proof = Proof<Curve>;
+  type Error = ark_relations::r1cs::SynthesisError;
+
+  const NAME: &'static str = "groth16-bls12-381";
+
+  fn verify(
+    &self,
+    vk: &Self::VerifyingKey,
+    public_inputs: &[CircuitField],
+    proof: &Self::Proof,
+  ) -> Result<bool, Self::Error> {
+    Groth16::<Curve>::verify(vk, public_inputs, proof)
+  }
+
+  /// The pairing equations of the proofs, `e(A, B) = e(alpha, beta) e(IC, gamma) e(C, delta)` with `IC` the
+  /// combination of the public inputs, multiplied together each raised to a random coefficient: one Miller loop over
+  /// all the pairs and one final exponentiation, instead of one each. A failing proof makes the product 1 with
+  /// probability about 2^-128 over the coefficients, which are hashed from the whole batch (Fiat-Shamir), so the
+  /// prover can't pick the proofs knowing them.
+  fn verify_batch(
+    &self,
+    batch: &[(&Self::VerifyingKey, &[CircuitField], &Self::Proof)],
+  ) -> Result<bool, Self::Error> {
+    let seed = batch_seed(batch);
+    let mut pairs: Vec<(
+      <Curve as PairingEngine>::G1Prepared,
+      <Curve as PairingEngine>::G2Prepared,
+    )> = vec![];
+    for (i, (vk, public_inputs, proof)) in batch.iter().enumerate() {
+      let pvk = prepare_verifying_key(vk);
+      let ic = prepare_inputs(&pvk, public_inputs)?.into_affine();
+      let mut hasher = Blake2s::new();
+      hasher.update(&seed);
+      hasher.update((i as u64).to_le_bytes());
+      let r = CircuitField::from_le_bytes_mod_order(&hasher.finalize()[..16]).into_repr();
+      pairs.push((proof.a.mul(r).into_affine().into(), proof.b.into()));
+      pairs.push((ic.mul(r).into_affine().into(), pvk.gamma_g2_neg_pc.clone()));
+      pairs.push((
+        proof.c.mul(r).into_affine().into(),
+        pvk.delta_g2_neg_pc.clone(),
+      ));
+      pairs.push((
+        (-vk.alpha_g1.mul(r)).into_affine().into(),
+        vk.beta_g2.into(),
+      ));
+    }
+    let product = Curve::final_exponentiation(&Curve::miller_loop(pairs.iter()));
+    Ok(product == Some(<Curve as PairingEngine>::Fqk::one()))
+  }
+}
+
+/// The hash of the keys, the public inputs and the proofs of a batch, the coefficients of
+/// [Groth16Backend::verify_batch] are drawn from it.
+fn batch_seed(batch: &[(&VerifyingKey<Curve>, &[CircuitField], &Proof<Curve>)]) -> Vec<u8> {
+  let mut bytes = vec![];
+  for (vk, public_inputs, proof) in batch.iter() {
+    vk.serialize(&mut bytes).expect("Serializing into memory");
+    bytes.extend((public_inputs.len() as u64).to_le_bytes());
+    for x in public_inputs.iter() {
+      x.serialize(&mut bytes).expect("Serializing into memory");
+    }
+    proof
+      .serialize(&mut bytes)
+      .expect("Serializing into memory");
+  }
+  Blake2s::digest(&bytes).to_vec()
+}
+
+pub const VERIFYING_KEY_FILE: &str = "verifying_key.bin";
+pub const SCHEMA_FILE: &str = "public_inputs.json";
+
+/// A public input of the circuit. Field elements are decimal strings.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum PublicInput {
+  /// A constant of the model, e.g. the 0 of a comparison.
+  Constant { value: String },
+  /// An element (physical index) of a public weight. Named if registered, see [ParamRegistry].
+  Weight {
+    name: Option<String>,
+    tensor: usize,
+    element: usize,
+    value: String,
+  },
+  /// An element (physical index) of a retrieved tensor, a result of the model.
+  Output { tensor: usize, element: usize },
+  /// The index of the max of an element of a max reduction, see [crate::scalar::InputsTracker::argmax].
+  Argmax { tensor: usize, element: usize },
+  /// The hash of the private input, see [super::poseidon::input_hash].
+  InputHash,
+  /// The hash of the tensors of a [Commitment](super::Commitment), in their order.
+  Commitment { tensors: Vec<usize> },
+  /// The root of the Merkle tree of the private weights, with the leaves the circuit opens, see
+  /// [WeightOpening](super::merkle::WeightOpening).
+  WeightRoot {
+    leaf_size: usize,
+    leaves: Vec<usize>,
+    value: String,
+  },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PublicInputsSchema {
+  /// The proof system, e.g. `groth16-bls12-381`.
+  pub backend: String,
+  /// Hex of the [structural hash](crate::scalar::ScalarGraph::structural_hash) of the circuit.
+  pub circuit_hash: String,
+  pub scale: String,
+  pub zero: String,
+  /// In the order of the public inputs of the proofs.
+  pub inputs: Vec<PublicInput>,
+}
+
+impl PublicInputsSchema {
+  /// The schema of the circuit of the snark, which has to be set up (or proven) already, see [MLSnark::recorded_public_nodes].
+  /// Tensors are the nodes of the graph the snark was compiled from, which the registry names.
+  /// `backend` is the [VerifyingBackend::NAME] of the backend proving the circuit.
+  #[cfg(feature = "native")]
+  pub fn new(
+    snark: &MLSnark<CircuitField>,
+    params: &ParamRegistry,
+    backend: &str,
+  ) -> Result<Self, Box<dyn Error>> {
+    if snark.recorded_public_nodes.is_empty() {
+      return Err("The snark has no public inputs recorded, make the keys first".into());
+    }
+    let tracker = &snark.graph.inputs_tracker;
+    let output_of: HashMap<NodeIndex, (usize, usize)> = tracker
+      .new_outputs
+      .iter()
+      .flat_map(|(x, little)| {
+        little
+          .iter()
+          .enumerate()
+          .map(move |(i, y)| (*y, (x.index(), i)))
+      })
+      .collect();
+    let argmax_of: HashMap<NodeIndex, (usize, usize)> = tracker
+      .argmax
+      .iter()
+      .flat_map(|(x, little)| {
+        little
+          .iter()
+          .enumerate()
+          .map(move |(i, y)| (*y, (x.index(), i)))
+      })
+      .collect();
+    let nodes: HashMap<NodeIndex, &FrozenNode> =
+      snark.graph.nodes.iter().map(|n| (n.id, n)).collect();
+    let mut sources_seen = vec![];
+    let mut inputs = vec![];
+    if snark.input_hash {
+      inputs.push(PublicInput::InputHash);
+    }
+    if let Some(opening) = snark.weight_opening.as_ref() {
+      let tree = opening
+        .tree
+        .as_ref()
+        .ok_or("The weight tree is unknown, make the schema from the snark of the prover")?;
+      inputs.push(PublicInput::WeightRoot {
+        leaf_size: opening.leaf_size,
+        leaves: opening.leaves(),
+        value: f_to_bigint(tree.root()).to_string(),
+      });
+    }
+    for x in snark.recorded_public_nodes.iter().copied() {
+      // a source that is a result as well is recorded twice, the source first
+      let is_source = nodes[&x].args.is_empty();
+      if is_source && !sources_seen.contains(&x) {
+        sources_seen.push(x);
+        if let ScalarOp::Constant(val) = nodes[&x].op {
+          inputs.push(PublicInput::Constant {
+            value: scaled_float(val, &snark.scale).to_string(),
+          });
+          continue;
+        }
+        let (tensor, element) = tracker
+          .origin
+          .get(&x)
+          .ok_or_else(|| format!("Public source {:?} without an origin", x))?;
+        let value = match snark.source_map.get(&x) {
+          Some(SourceType::Public(v)) => scaled_float(*v, &snark.scale).to_string(),
+          Some(SourceType::PublicEncoded(n)) => n.to_string(),
+          _ => return Err(format!("Public source {:?} without a public value", x).into()),
+        };
+        inputs.push(PublicInput::Weight {
+          name: params.by_id(*tensor).map(|p| p.name.clone()),
+          tensor: tensor.index(),
+          element: *element,
+          value,
+        });
+      } else if let Some((tensor, element)) = output_of.get(&x) {
+        inputs.push(PublicInput::Output {
+          tensor: *tensor,
+          element: *element,
+        });
+      } else {
+        let (tensor, element) = argmax_of
+          .get(&x)
+          .ok_or_else(|| format!("Public input {:?} is neither a source nor an output", x))?;
+        inputs.push(PublicInput::Argmax {
+          tensor: *tensor,
+          element: *element,
+        });
+      }
+    }
+    for commitment in snark.commitments.iter() {
+      inputs.push(PublicInput::Commitment {
+        tensors: commitment.tensors.iter().map(|x| x.index()).collect(),
+      });
+    }
+    Ok(PublicInputsSchema {
+      backend: backend.to_string(),
+      circuit_hash: to_hex(&snark.graph.structural_hash()),
+      scale: snark.scale.s.to_string(),
+      zero: snark.scale.z.to_string(),
+      inputs,
+    })
+  }
+
+  /// Checks the public inputs of a proof against the values fixed by the schema.
+  pub fn check(&self, public_inputs: &[CircuitField]) -> Result<(), Box<dyn Error>> {
+    if public_inputs.len() != self.inputs.len() {
+      return Err(
+        format!(
+          "The proof has {} public inputs, the circuit {}",
+          public_inputs.len(),
+          self.inputs.len()
+        )
+        .into(),
+      );
+    }
+    for (i, (input, got)) in self.inputs.iter().zip(public_inputs).enumerate() {
+      let expected = match input {
+        PublicInput::Constant { value }
+        | PublicInput::Weight { value, .. }
+        | PublicInput::WeightRoot { value, .. } => value,
+        PublicInput::Output { .. }
+        | PublicInput::Argmax { .. }
+        | PublicInput::InputHash
+        | PublicInput::Commitment { .. } => continue,
+      };
+      if f_to_bigint(*got).to_string() != *expected {
+        return Err(format!("Public input {} ({:?}) differs from the schema", i, input).into());
+      }
+    }
+    Ok(())
+  }
+
+  #[cfg(feature = "native")]
+  pub fn save(&self, path: &Path) -> Result<(), Box<dyn Error>> {
+    fs::write(path, serde_json::to_string_pretty(self)?)?;
+    Ok(())
+  }
+
+  #[cfg(feature = "native")]
+  pub fn load(path: &Path) -> Result<Self, Box<dyn Error>> {
+    Ok(serde_json::from_str(&fs::read_to_string(path)?)?)
+  }
+}
+
+/// Verifies the proof file against the verifier exported into `verifier_dir`, see the module docs.
+/// Fails on a proof with other constants or weights than the schema.
+#[cfg(feature = "native")]
+pub fn verify_from_files(verifier_dir: &Path, proof_path: &Path) -> Result<bool, Box<dyn Error>> {
+  let schema = PublicInputsSchema::load(&verifier_dir.join(SCHEMA_FILE))?;
+  if schema.backend != Groth16Backend::NAME {
+    return Err(format!("Unsupported backend {}", schema.backend).into());
+  }
+  let vk =
+    VerifyingKey::<Curve>::deserialize(fs::File::open(verifier_dir.join(VERIFYING_KEY_FILE))?)?;
+  let (proof, public_inputs) = load_proof(proof_path)?;
+  schema.check(&public_inputs)?;
+  Groth16Backend
+    .verify(&vk, &public_inputs, &proof)
+    .map_err(|e| format!("Failed to verify: {:?}", e).into())
+}
+
+#[cfg(all(test, feature = "native"))]
+mod tests {
+  use ark_std::One;
+
+  use super::{verify_from_files, PublicInput, PublicInputsSchema, SCHEMA_FILE};
+  use crate::{
+    compile,
+    snark::{
+      backend::{Groth16Backend, ProvingBackend},
+      CircuitField,
+    },
+    subcommands::save_proof,
+  };
+
+  #[test]
+  fn test_verify_from_files() {
+    let trained = crate::model::fixed_weights::run_model();
+    let mut snark = compile(&trained);
+    let (pk, vk) = Groth16Backend.setup(&mut snark).unwrap();
+    let dir = std::env::temp_dir().join(format!("zkml-verifier-{}", std::process::id()));
+    Groth16Backend
+      .export_verifier(&snark, &trained.graph.params, &vk, &dir)
+      .unwrap();
+
+    snark.set_input(vec![1.0, 2.0, 3.0]);
+    let proof = Groth16Backend.prove(&mut snark, &pk).unwrap();
+    let mut public_inputs = snark.recorded_public_inputs.clone();
+    let proof_path = dir.join("proof.bin");
+    save_proof(&proof_path, &proof, &public_inputs).unwrap();
+    assert!(verify_from_files(&dir, &proof_path).unwrap());
+
+    let schema = PublicInputsSchema::load(&dir.join(SCHEMA_FILE)).unwrap();
+    let weights: usize = trained.graph.weights.iter().map(|(_, w)| w.len()).sum();
+    let count = |f: fn(&PublicInput) -> bool| schema.inputs.iter().filter(|i| f(i)).count();
+    assert_eq!(
+      count(|i| matches!(i, PublicInput::Weight { name: Some(_), .. })),
+      weights
+    );
+    assert_eq!(
+      count(|i| matches!(i, PublicInput::Output { .. })),
+      snark
+        .graph
+        .inputs_tracker
+        .new_outputs
+        .values()
+        .map(Vec::len)
+        .sum::<usize>()
+    );
+
+    // same proof, other weights
+    let i = schema
+      .inputs
+      .iter()
+      .position(|i| matches!(i, PublicInput::Weight { .. }))
+      .unwrap();
+    public_inputs[i] += CircuitField::one();
+    save_proof(&proof_path, &proof, &public_inputs).unwrap();
+    assert!(verify_from_files(&dir, &proof_path).is_err());
+    std::fs::remove_dir_all(dir).unwrap();
+  }
+}
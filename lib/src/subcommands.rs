@@ -1,7 +1,29 @@
 pub use client::*;
+pub use eval::*;
+pub use prove::*;
+pub use scalarize::*;
 pub use server::*;
 pub use setup::*;
+pub use train::*;
+pub use verify::*;
 
 pub mod client;
+pub mod eval;
+pub mod prove;
+pub mod scalarize;
 pub mod server;
 pub mod setup;
+pub mod train;
+pub mod verify;
+
+use std::{error::Error, fs, path::Path};
+
+/// Reads a whitespace separated vector of floats, the format of a single row of the dataset (without the label).
+pub fn read_input(path: &Path) -> Result<Vec<f32>, Box<dyn Error>> {
+  let content = fs::read_to_string(path)?;
+  let input = content
+    .split_whitespace()
+    .map(|a| a.parse::<f32>())
+    .collect::<Result<Vec<_>, _>>()?;
+  Ok(input)
+}
@@ -0,0 +1,36 @@
+use std::path::{Path, PathBuf};
+
+use crate::model::{load_model, SavedModel};
+
+use super::read_input;
+
+/// Evaluates a saved model on an input, without any snark.
+pub struct Eval {
+  model_path: PathBuf,
+  input_path: PathBuf,
+}
+
+impl Eval {
+  pub fn new(model_path: &Path, input_path: &Path) -> Self {
+    Self {
+      model_path: PathBuf::from(model_path),
+      input_path: PathBuf::from(input_path),
+    }
+  }
+
+  pub fn run(self) {
+    let saved = SavedModel::load(self.model_path.as_path())
+      .unwrap_or_else(|e| panic!("Failed to load the model: {}", e));
+    let trained = load_model(saved);
+    let input = read_input(self.input_path.as_path())
+      .unwrap_or_else(|e| panic!("Failed to read the input: {}", e));
+    let input = match &trained.scaler {
+      Some(scaler) => scaler.transform_row(&input),
+      None => input,
+    };
+    let results = trained.evaluate(input);
+    for output in trained.graph.outputs.iter() {
+      println!("{:?}", results[output]);
+    }
+  }
+}
@@ -0,0 +1,105 @@
+use std::{
+  error::Error,
+  fs::File,
+  path::{Path, PathBuf},
+};
+
+use ark_groth16::Proof;
+use ark_serialize::{CanonicalDeserialize, CanonicalSerialize};
+
+use crate::{
+  compile,
+  model::{load_model, SavedModel},
+  snark::{
+    backend::{Groth16Backend, ProvingBackend},
+    verifier::VerifyingBackend,
+    CircuitField, Curve,
+  },
+};
+
+use super::{load_proving_key, read_input};
+
+/// Proves the evaluation of a saved model on a private input, with the proving key [super::Setup] made for the model.
+/// Writes the proof together with the public inputs, which the verifier needs as well.
+pub struct Prove {
+  model_path: PathBuf,
+  input_path: PathBuf,
+  proving_key_path: PathBuf,
+  proof_output_path: PathBuf,
+}
+
+impl Prove {
+  pub fn new(
+    model_path: &Path,
+    input_path: &Path,
+    proving_key_path: &Path,
+    proof_output_path: &Path,
+  ) -> Self {
+    Self {
+      model_path: PathBuf::from(model_path),
+      input_path: PathBuf::from(input_path),
+      proving_key_path: PathBuf::from(proving_key_path),
+      proof_output_path: PathBuf::from(proof_output_path),
+    }
+  }
+
+  pub fn run(self) {
+    let saved = SavedModel::load(self.model_path.as_path())
+      .unwrap_or_else(|e| panic!("Failed to load the model: {}", e));
+    let trained = load_model(saved);
+    let input = read_input(self.input_path.as_path())
+      .unwrap_or_else(|e| panic!("Failed to read the input: {}", e));
+    let input = match &trained.scaler {
+      Some(scaler) => scaler.transform_row(&input),
+      None => input,
+    };
+
+    let pk = load_proving_key(self.proving_key_path.as_path())
+      .unwrap_or_else(|e| panic!("Failed to load the proving key: {}", e));
+
+    let backend = Groth16Backend;
+    let mut snark = compile(&trained);
+    snark.set_input(input);
+    let proof = backend
+      .prove(&mut snark, &pk)
+      .unwrap_or_else(|e| panic!("Failed to make proof: {:?}", e));
+    // a key of another circuit makes proofs that don't verify, better to say so here than at the verifier
+    let verified = backend.verify(&pk.vk, &snark.recorded_public_inputs, &proof);
+    assert!(
+      verified == Ok(true),
+      "The proving key at {} is not of the model's circuit",
+      self.proving_key_path.display()
+    );
+    save_proof(
+      self.proof_output_path.as_path(),
+      &proof,
+      &snark.recorded_public_inputs,
+    )
+    .unwrap_or_else(|e| panic!("Failed to save the proof: {}", e));
+    let results = snark.get_evaluation_results();
+    let results: Vec<_> = trained.graph.outputs.iter().map(|x| &results[x]).collect();
+    println!(
+      "Proved evaluation to {:?}, saved proof to {}",
+      results,
+      self.proof_output_path.display()
+    );
+  }
+}
+
+pub fn save_proof(
+  path: &Path,
+  proof: &Proof<Curve>,
+  public_inputs: &Vec<CircuitField>,
+) -> Result<(), Box<dyn Error>> {
+  let mut file = File::create(path)?;
+  proof.serialize(&mut file)?;
+  public_inputs.serialize(&mut file)?;
+  Ok(())
+}
+
+pub fn load_proof(path: &Path) -> Result<(Proof<Curve>, Vec<CircuitField>), Box<dyn Error>> {
+  let mut file = File::open(path)?;
+  let proof = Proof::<Curve>::deserialize(&mut file)?;
+  let public_inputs = Vec::<CircuitField>::deserialize(&mut file)?;
+  Ok((proof, public_inputs))
+}
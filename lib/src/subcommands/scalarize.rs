@@ -0,0 +1,93 @@
+use std::{
+  collections::HashMap,
+  error::Error,
+  fs,
+  path::{Path, PathBuf},
+};
+
+use luminal::prelude::NodeIndex;
+
+use crate::{
+  model::{load_model, SavedModel},
+  scalar::{integer_bits, range_analysis, save_scalar_graphviz, scalar, GraphvizOptions},
+};
+
+/// Scalarizes a saved model and dumps the scalar graph in graphviz format, or as JSON (see [crate::scalar::ir]) to a
+/// `.json` output.
+pub struct Scalarize {
+  model_path: PathBuf,
+  output_path: PathBuf,
+  graphviz: GraphvizOptions,
+  input_bound: f64,
+}
+
+impl Scalarize {
+  /// `neighborhood` is the id of a scalar node and a radius, to render only the part of the graph around it.
+  /// The reported value bounds are for inputs within `[-input_bound, input_bound]`.
+  pub fn new(
+    model_path: &Path,
+    output_path: &Path,
+    cluster: bool,
+    neighborhood: Option<(usize, usize)>,
+    input_bound: f64,
+  ) -> Self {
+    Self {
+      model_path: PathBuf::from(model_path),
+      output_path: PathBuf::from(output_path),
+      graphviz: GraphvizOptions {
+        neighborhood: neighborhood.map(|(x, r)| (NodeIndex::new(x), r)),
+        cluster,
+      },
+      input_bound,
+    }
+  }
+
+  pub fn run(self) {
+    let saved = SavedModel::load(self.model_path.as_path())
+      .unwrap_or_else(|e| panic!("Failed to load the model: {}", e));
+    let trained = load_model(saved);
+    let (sc, _) = scalar(&trained.graph.graph);
+    println!(
+      "Scalar graph has {} nodes and {} edges",
+      sc.graph.node_count(),
+      sc.graph.edge_count()
+    );
+    // the weights are known exactly, bounded by their own extremes
+    let mut input_bounds: HashMap<_, _> = trained
+      .graph
+      .weights
+      .iter()
+      .map(|(x, w)| {
+        let (lo, hi) = w
+          .iter()
+          .fold((f64::INFINITY, f64::NEG_INFINITY), |(lo, hi), v| {
+            (lo.min(f64::from(*v)), hi.max(f64::from(*v)))
+          });
+        (*x, (lo, hi))
+      })
+      .collect();
+    input_bounds.insert(
+      trained.graph.input_id,
+      (-self.input_bound, self.input_bound),
+    );
+    let ranges = range_analysis(&sc, &input_bounds);
+    if let Some((x, r)) = ranges
+      .iter()
+      .max_by(|(_, a), (_, b)| integer_bits(**a).cmp(&integer_bits(**b)))
+    {
+      println!(
+        "For inputs within ±{} values need {} integer bits, the widest range is {:?} at node {}",
+        self.input_bound,
+        integer_bits(*r),
+        r,
+        x.index()
+      );
+    }
+    let written: Result<(), Box<dyn Error>> =
+      match self.output_path.extension().is_some_and(|e| e == "json") {
+        true => fs::write(&self.output_path, sc.to_json()).map_err(|e| e.into()),
+        false => save_scalar_graphviz(self.output_path.as_path(), &sc, &self.graphviz),
+      };
+    written.unwrap_or_else(|e| panic!("Failed to save the scalar graph: {}", e));
+  }
+}
@@ -1,67 +1,76 @@
 use std::{
-  collections::HashMap,
+  error::Error,
   fs::File,
   path::{Path, PathBuf},
 };
 
-use ark_groth16::Groth16;
-use ark_serialize::CanonicalSerialize;
-use ark_snark::SNARK;
-use luminal::compiler_utils::ToId;
-use rand::{rngs::StdRng, SeedableRng};
+use ark_groth16::ProvingKey;
+use ark_serialize::{CanonicalDeserialize, CanonicalSerialize};
 
-use crate::{model::TrainParams, snark::Curve};
+use crate::{
+  compile,
+  model::{load_model, SavedModel},
+  snark::{
+    backend::{Groth16Backend, ProvingBackend},
+    Curve,
+  },
+};
 
+/// Makes the keys of the circuit of a saved model, run by the verifier: the setup randomness can forge proofs, so it
+/// stays with whoever checks them (and is thrown away, see [crate::snark::verifier::Groth16Backend]).
+/// Publishes the verifier of the keys (see [crate::snark::verifier]) and writes the proving key for [super::Prove].
 pub struct Setup {
-  dataset_path: PathBuf,
-  prover_output_path: PathBuf,
+  model_path: PathBuf,
   verifier_output_path: PathBuf,
-  weights_output_path: PathBuf,
+  proving_key_output_path: PathBuf,
 }
 
 impl Setup {
   pub fn new(
-    dataset_path: &Path,
-    prover_output_path: &Path,
+    model_path: &Path,
     verifier_output_path: &Path,
-    weights_output_path: &Path,
-    epichs: usize,
+    proving_key_output_path: &Path,
   ) -> Self {
     Self {
-      dataset_path: PathBuf::from(dataset_path),
-      prover_output_path: PathBuf::from(prover_output_path),
+      model_path: PathBuf::from(model_path),
       verifier_output_path: PathBuf::from(verifier_output_path),
-      weights_output_path: PathBuf::from(weights_output_path),
+      proving_key_output_path: PathBuf::from(proving_key_output_path),
     }
   }
 
   pub fn run(self) {
-    let rng = StdRng::seed_from_u64(1);
-
-    let dataset = crate::model::read_dataset(self.dataset_path.as_path()).unwrap();
-    let graph = crate::model::run_model(TrainParams {
-      data: dataset,
-      epochs: 20,
-    });
-    // todo: implement serialization for TrainedGraph, then recreate test_trained_into_snark.
-
-    // let weights = crate::model::get_weights(&graph, &model);
-
-    // we need to construct input to compile function
-    // let circuit = crate::lib::compile(...);
-
-    // let (pk, vk) = Groth16::<Curve>::circuit_specific_setup(circuit, &mut rng).unwrap();
+    let saved = SavedModel::load(self.model_path.as_path())
+      .unwrap_or_else(|e| panic!("Failed to load the model: {}", e));
+    let trained = load_model(saved);
 
-    // let mut pk_file =
-    //   File::create(self.prover_output_path).expect("Failed to create prover setup file");
-    // pk.serialize_uncompressed(pk_file);
+    let backend = Groth16Backend;
+    let mut snark = compile(&trained);
+    let (pk, vk) = backend
+      .setup(&mut snark)
+      .unwrap_or_else(|e| panic!("Failed to make keys: {:?}", e));
+    backend
+      .export_verifier(
+        &snark,
+        &trained.graph.params,
+        &vk,
+        self.verifier_output_path.as_path(),
+      )
+      .unwrap_or_else(|e| panic!("Failed to save the verifier: {}", e));
+    save_proving_key(self.proving_key_output_path.as_path(), &pk)
+      .unwrap_or_else(|e| panic!("Failed to save the proving key: {}", e));
+    println!(
+      "Saved the verifier to {} and the proving key to {}",
+      self.verifier_output_path.display(),
+      self.proving_key_output_path.display()
+    );
+  }
+}
 
-    // let mut vk_file =
-    //   File::create(self.verifier_output_path).expect("Failed to create verifier setup file");
-    // vk.serialize_uncompressed(vk_file);
+pub fn save_proving_key(path: &Path, pk: &ProvingKey<Curve>) -> Result<(), Box<dyn Error>> {
+  pk.serialize(File::create(path)?)?;
+  Ok(())
+}
 
-    // let mut weights_file =
-    //   File::create(self.weights_output_path).expect("Failed to create weights file");
-    // vk.serialize_uncompressed(weights_file);
-  }
+pub fn load_proving_key(path: &Path) -> Result<ProvingKey<Curve>, Box<dyn Error>> {
+  Ok(ProvingKey::<Curve>::deserialize(File::open(path)?)?)
 }
@@ -0,0 +1,60 @@
+use std::path::{Path, PathBuf};
+
+use crate::{
+  model::{read_dataset, run_model, ClassWeights, OutputHead, SavedModel, TrainParams},
+  quant::QuantConfig,
+};
+
+/// Trains the medium model and saves its weights.
+pub struct Train {
+  dataset_path: PathBuf,
+  model_output_path: PathBuf,
+  epochs: usize,
+  head: OutputHead,
+  seed: u64,
+  quantization: Option<QuantConfig>,
+  class_weights: Option<ClassWeights>,
+}
+
+impl Train {
+  pub fn new(
+    dataset_path: &Path,
+    model_output_path: &Path,
+    epochs: usize,
+    head: OutputHead,
+    seed: u64,
+    quantization: Option<QuantConfig>,
+    class_weights: Option<ClassWeights>,
+  ) -> Self {
+    Self {
+      dataset_path: PathBuf::from(dataset_path),
+      model_output_path: PathBuf::from(model_output_path),
+      epochs,
+      head,
+      seed,
+      quantization,
+      class_weights,
+    }
+  }
+
+  pub fn run(self) {
+    let data = read_dataset(self.dataset_path.as_path())
+      .unwrap_or_else(|e| panic!("Failed to read the dataset: {}", e));
+    let trained = run_model(TrainParams {
+      data,
+      epochs: self.epochs,
+      head: self.head,
+      seed: self.seed,
+      quantization: self.quantization,
+      class_weights: self.class_weights,
+      ..Default::default()
+    });
+    SavedModel::from_trained(&trained)
+      .save(self.model_output_path.as_path())
+      .unwrap_or_else(|e| panic!("Failed to save the model: {}", e));
+    if let Some(threshold) = trained.threshold {
+      println!("Calibrated decision threshold: {}", threshold);
+    }
+    println!("Saved model to {}", self.model_output_path.display());
+  }
+}
@@ -0,0 +1,36 @@
+use std::path::{Path, PathBuf};
+
+use crate::{
+  snark::{scaling_helpers::unscaled_f, verifier::verify_from_files},
+  SCALE,
+};
+
+use super::load_proof;
+
+/// Verifies a proof made by [super::Prove] with the verifier it published, see [crate::snark::verifier].
+pub struct Verify {
+  verifier_path: PathBuf,
+  proof_path: PathBuf,
+}
+
+impl Verify {
+  pub fn new(verifier_path: &Path, proof_path: &Path) -> Self {
+    Self {
+      verifier_path: PathBuf::from(verifier_path),
+      proof_path: PathBuf::from(proof_path),
+    }
+  }
+
+  pub fn run(self) -> bool {
+    let verified = verify_from_files(self.verifier_path.as_path(), self.proof_path.as_path())
+      .unwrap_or_else(|e| {
+        println!("Rejected: {}", e);
+        false
+      });
+    let (_proof, public_inputs) = load_proof(self.proof_path.as_path())
+      .unwrap_or_else(|e| panic!("Failed to load the proof: {}", e));
+    let result = public_inputs.last().and_then(|r| unscaled_f(*r, &SCALE));
+    println!("Verified: {}, claimed result: {:?}", verified, result);
+    verified
+  }
+}
@@ -5,8 +5,10 @@ use human_panic::setup_panic;
 #[cfg(debug_assertions)]
 extern crate better_panic;
 
+use std::str::FromStr;
+
 use tracing::subscriber::{DefaultGuard, SetGlobalDefaultError};
-use tracing_subscriber::{self};
+use tracing_subscriber::{self, EnvFilter};
 
 // [NOTE] tracing
 //
@@ -21,24 +23,59 @@ use tracing_subscriber::{self};
 // #[tracing::myfn]
 // pub fn myfn\
 
-pub fn install_logger() -> Result<(), SetGlobalDefaultError> {
-  // let subscriber = tracing_subscriber::registry()
-  //   .with(fmt::layer())
-  //   .with(tracing_subscriber::filter::EnvFilter::from_default_env())
-  //   .init();
-  let subscriber = tracing_subscriber::fmt().compact();
-  // .with
-  // .with(EnvFilter::from_default_env());
-  // let s = tracing_subscriber::registry().with(fmt::layer());
+// [NOTE] log volume
+//
+// The pipeline runs in phases, each in an info span with the sizes as fields:
+// scalarize, quantize (exporters), setup, synthesize, prove.
+// Anything per node is at trace level and sampled with [sampled], scalar graphs of real models have millions of nodes.
+// Filter with RUST_LOG, i.e. RUST_LOG=lib::scalar=trace.
 
-  // #[cfg(debug_assertions)]
-  // let subscriber = subscriber.with_max_level(tracing::Level::DEBUG);
+/// Per-node logs are emitted for every `LOG_SAMPLE_EVERY`th node only.
+pub const LOG_SAMPLE_EVERY: usize = 1000;
 
-  let subscriber = subscriber.finish();
-  return tracing::subscriber::set_global_default(subscriber);
+pub fn sampled(i: usize) -> bool {
+  i % LOG_SAMPLE_EVERY == 0
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum LogFormat {
+  #[default]
+  Compact,
+  /// One json object per event, with the fields of the enclosing spans.
+  Json,
+}
+
+impl FromStr for LogFormat {
+  type Err = String;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    match s {
+      "compact" => Ok(LogFormat::Compact),
+      "json" => Ok(LogFormat::Json),
+      _ => Err(format!(
+        "Unknown log format {}, expected compact or json",
+        s
+      )),
+    }
+  }
+}
+
+pub fn install_logger(format: LogFormat) -> Result<(), SetGlobalDefaultError> {
+  let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
+  let subscriber = tracing_subscriber::fmt().with_env_filter(filter);
+  match format {
+    LogFormat::Compact => tracing::subscriber::set_global_default(subscriber.compact().finish()),
+    LogFormat::Json => {
+      tracing::subscriber::set_global_default(subscriber.json().with_current_span(true).finish())
+    }
+  }
 }
 
 pub fn init_logging() -> Result<(), SetGlobalDefaultError> {
+  init_logging_with(LogFormat::default())
+}
+
+pub fn init_logging_with(format: LogFormat) -> Result<(), SetGlobalDefaultError> {
   // Human Panic. Only enabled when *not* debugging.
   #[cfg(not(debug_assertions))]
   {
@@ -56,7 +93,7 @@ pub fn init_logging() -> Result<(), SetGlobalDefaultError> {
   }
 
   // Setup Logging
-  install_logger()?;
+  install_logger(format)?;
 
   Ok(())
 }
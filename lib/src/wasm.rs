@@ -0,0 +1,34 @@
+//!
+//! Verifying proof bundles in the browser, the `wasm` feature.
+//!
+//! Built without `native` for `wasm32-unknown-unknown` (see the justfile), the crate is the verifier of
+//! [crate::snark::bundle] and nothing else. The bindings take json and bytes and report errors as strings.
+//!
+
+use ark_groth16::VerifyingKey;
+use ark_serialize::CanonicalDeserialize;
+use wasm_bindgen::prelude::*;
+
+use crate::snark::{
+  bundle::{verify_bundle, ProofBundle},
+  verifier::{Groth16Backend, PublicInputsSchema},
+  Curve,
+};
+
+/// [verify_bundle] with [Groth16Backend]: the bundle and the schema as json, the verifying key in its compressed
+/// serialization (the [VERIFYING_KEY_FILE](crate::snark::verifier::VERIFYING_KEY_FILE) of an exported verifier).
+#[wasm_bindgen(js_name = verifyBundle)]
+pub fn verify_bundle_json(
+  bundle: &str,
+  verifying_key: &[u8],
+  schema: &str,
+) -> Result<bool, JsValue> {
+  let err = |e: String| JsValue::from_str(&e);
+  let bundle: ProofBundle =
+    serde_json::from_str(bundle).map_err(|e| err(format!("Bad bundle: {}", e)))?;
+  let schema: PublicInputsSchema =
+    serde_json::from_str(schema).map_err(|e| err(format!("Bad schema: {}", e)))?;
+  let vk = VerifyingKey::<Curve>::deserialize(verifying_key)
+    .map_err(|e| err(format!("Bad verifying key: {:?}", e)))?;
+  verify_bundle(&Groth16Backend, &bundle, &vk, &schema).map_err(|e| err(e.to_string()))
+}